@@ -0,0 +1,78 @@
+//! Checks the toolchain against the crate's minimum supported Rust version.
+//!
+//! Cargo already refuses toolchains older than `rust-version`, but not with
+//! `--ignore-rust-version` or when the crate is vendored into another build
+//! system; this check fails those builds with a clear message instead of an
+//! obscure compile error. It also enables a `has_*` cfg for each newer std
+//! API the toolchain provides, see `src/compat.rs`.
+//!
+//! This file must itself compile on old toolchains, so it sticks to syntax
+//! and APIs from well before the MSRV.
+
+use std::env;
+use std::process::{self, Command};
+
+/// Newer std APIs used when available, with the Rust minor version stabilizing them
+const NEWER_APIS: &[(&str, u32)] = &[("has_path_absolute", 79), ("has_crosses_devices", 85)];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=RUSTC");
+
+    let minor = match rustc_minor() {
+        Some(minor) => minor,
+        None => {
+            println!("cargo:warning=could not determine the rustc version, assuming a recent one");
+            u32::MAX
+        }
+    };
+
+    // Cargo only accepts check-cfg instructions from 1.80 on and warns before
+    if minor >= 80 {
+        for &(cfg, _) in NEWER_APIS {
+            println!("cargo:rustc-check-cfg=cfg({})", cfg);
+        }
+    }
+
+    let msrv = env::var("CARGO_PKG_RUST_VERSION").unwrap_or_default();
+    if let Some(msrv_minor) = parse_minor(&msrv) {
+        if minor < msrv_minor {
+            eprintln!(
+                "error: icloud-album-rs requires Rust {} or newer, but rustc is 1.{}. \
+                 Update the toolchain, e.g. with `rustup update`, or use an older release of the crate.",
+                msrv, minor
+            );
+            process::exit(1);
+        }
+    }
+
+    for &(cfg, since) in NEWER_APIS {
+        if minor >= since {
+            println!("cargo:rustc-cfg={}", cfg);
+        }
+    }
+
+    // Linker flags for loading the Node.js addon, e.g. on macOS
+    #[cfg(feature = "napi")]
+    napi_build::setup();
+}
+
+/// Returns the minor version of the compiler Cargo builds with
+fn rustc_minor() -> Option<u32> {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let output = Command::new(rustc).arg("--version").output().ok()?;
+    let version = String::from_utf8(output.stdout).ok()?;
+    // e.g. "rustc 1.75.0 (82e1608df 2023-12-21)"
+    parse_minor(version.split_whitespace().nth(1)?)
+}
+
+/// Returns the minor version of a `1.x[.y][-suffix]` version string
+fn parse_minor(version: &str) -> Option<u32> {
+    let mut parts = version.split('.');
+    if parts.next()? != "1" {
+        return None;
+    }
+    let minor = parts.next()?;
+    let digits: String = minor.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
@@ -1,4 +1,4 @@
-use icloud_album_rs::api::{get_api_response, get_asset_urls};
+use icloud_album_rs::api::{get_api_response, get_asset_urls, WebstreamResponse};
 use reqwest::Client;
 use serde_json::json;
 
@@ -127,7 +127,9 @@ async fn test_get_api_response() -> bool {
 
     // Call the function and check the result
     match get_api_response(&client, &base_url).await {
-        Ok((photos, metadata)) => {
+        Ok(WebstreamResponse {
+            photos, metadata, ..
+        }) => {
             // Verify metadata
             let metadata_correct = metadata.stream_name == "Test Album"
                 && metadata.user_first_name == "John"
@@ -0,0 +1,37 @@
+//! Example of diagnosing why an album doesn't fetch
+//!
+//! Runs each step of a fetch once and prints a health report: which partition
+//! answered, how long each request took, schema compatibility, how many asset
+//! URLs resolved and whether a few assets are actually served. Assets are
+//! probed with single-byte range requests, so nothing is downloaded.
+//!
+//! Run with:
+//! ```
+//! cargo run --example diagnose_album -- "your_shared_album_token"
+//! ```
+//!
+//! Exits with status 1 if the album is broken.
+
+use icloud_album_rs::diagnose::{diagnose, Health};
+use icloud_album_rs::fetch::FetchOptions;
+use std::env;
+use std::process;
+
+const USAGE: &str = "Usage: cargo run --example diagnose_album -- TOKEN";
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [token] = args.as_slice() else {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    };
+
+    let report = diagnose(token, &FetchOptions::new()).await;
+    print!("{}", report);
+    if report.health() == Health::Broken {
+        process::exit(1);
+    }
+}
@@ -2,71 +2,44 @@
 //!
 //! Run with:
 //! ```
-//! cargo run --example download_photos -- "your_shared_album_token" "./download_dir"
+//! cargo run --example download_photos -- "your_shared_album_token_or_url" "./download_dir"
 //! ```
+//!
+//! To download only the photos of a saved selection (see
+//! `icloud_album_rs::selection`), pass its name after the directory:
+//! ```
+//! cargo run --example download_photos -- "your_shared_album_token_or_url" "./download_dir" --selection mylist
+//! ```
+//! Selections are read from `./download_dir/.selections`.
 
+use icloud_album_rs::base_url::normalize_token;
+use icloud_album_rs::selection::Selection;
+use icloud_album_rs::state_store::JsonFileStore;
+use icloud_album_rs::utils::sanitize_filename;
 use icloud_album_rs::{download_photo, get_icloud_photos};
-use std::collections::HashSet;
 use std::env;
 use std::fs;
 use std::path::Path;
 
-/// Sanitizes a filename to ensure it's valid across different operating systems
-///
-/// Replaces invalid characters with underscores and trims the filename if it's too long.
-/// Common invalid characters are replaced, including:
-/// - Control characters
-/// - Characters that are illegal on various file systems (Windows, macOS, Linux)
-/// - Characters that have special meaning in shell commands
-fn sanitize_filename(input: &str) -> String {
-    // Define invalid characters for filenames across different OS
-    let mut invalid_chars = HashSet::new();
-
-    // Control characters (0-31) and special characters
-    for c in (0..32).map(|i| char::from_u32(i).unwrap_or(' ')) {
-        invalid_chars.insert(c);
-    }
-
-    // Characters illegal in Windows filenames
-    for c in &['<', '>', ':', '"', '/', '\\', '|', '?', '*'] {
-        invalid_chars.insert(*c);
-    }
-
-    // Other potentially problematic characters
-    for c in &[
-        '!', '@', '#', '$', '%', '^', '&', '\'', ';', '=', '+', ',', '`', '~',
-    ] {
-        invalid_chars.insert(*c);
-    }
-
-    // Replace all invalid characters with underscores
-    let sanitized = input
-        .chars()
-        .map(|c| if invalid_chars.contains(&c) { '_' } else { c })
-        .collect::<String>();
-
-    // Remove leading/trailing dots and whitespace
-    let sanitized = sanitized.trim().trim_matches('.').to_string();
-
-    // Limit the filename length to a reasonable size (255 is often the max)
-    // Leave room for the extension and potential path components
-    if sanitized.len() > 200 {
-        format!("{}_truncated", &sanitized[0..195])
-    } else {
-        sanitized
-    }
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Get the token and download directory from the command line arguments
     let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: cargo run --example download_photos -- \"your_shared_album_token\" \"./download_dir\"");
-        std::process::exit(1);
-    }
+    let selection_name = match &args[..] {
+        [_, _, _] => None,
+        [_, _, _, flag, name] if flag == "--selection" => Some(name.clone()),
+        _ => {
+            eprintln!("Usage: cargo run --example download_photos -- \"your_shared_album_token\" \"./download_dir\" [--selection name]");
+            std::process::exit(1);
+        }
+    };
 
-    let token = &args[1];
+    // Accept share URLs and tokens pasted with stray punctuation
+    let normalized = normalize_token(&args[1])?;
+    for warning in &normalized.warnings {
+        eprintln!("Note: {}", warning);
+    }
+    let token = &normalized.token;
     let download_dir = &args[2];
 
     // Create the download directory if it doesn't exist
@@ -78,7 +51,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Downloading photos to: {}", download_dir);
 
     // Fetch photos and metadata
-    let response = get_icloud_photos(token).await?;
+    let mut response = get_icloud_photos(token).await?;
+
+    // Keep only the selected photos, if a selection was named
+    if let Some(name) = selection_name {
+        let store = JsonFileStore::new(Path::new(download_dir).join(".selections"));
+        let Some(selection) = Selection::load(&store, &name)? else {
+            eprintln!("No selection named {}", name);
+            std::process::exit(1);
+        };
+        for guid in selection.missing(&response.photos) {
+            eprintln!("Note: selected photo {} is no longer in the album", guid);
+        }
+        response.photos = selection.filter(&response.photos);
+    }
 
     println!("\nAlbum: {}", response.metadata.stream_name);
     println!(
@@ -2,9 +2,10 @@
 //!
 //! Run with:
 //! ```
-//! cargo run --example fetch_album -- "your_shared_album_token"
+//! cargo run --example fetch_album -- "your_shared_album_token_or_url"
 //! ```
 
+use icloud_album_rs::base_url::normalize_token;
 use icloud_album_rs::get_icloud_photos;
 use std::env;
 
@@ -17,7 +18,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         std::process::exit(1);
     }
 
-    let token = &args[1];
+    // Accept share URLs and tokens pasted with stray punctuation
+    let normalized = normalize_token(&args[1])?;
+    for warning in &normalized.warnings {
+        eprintln!("Note: {}", warning);
+    }
+    let token = &normalized.token;
 
     println!("Fetching shared album with token: {}", token);
 
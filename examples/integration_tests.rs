@@ -199,8 +199,11 @@ async fn mock_get_icloud_photos(
     let redirected_url = format!("{}/sharedstreams/", mock_url);
 
     // Fetch the metadata and photos using our mock URL
-    let (mut photos, metadata) =
-        icloud_album_rs::api::get_api_response(&client, &redirected_url).await?;
+    let icloud_album_rs::api::WebstreamResponse {
+        mut photos,
+        metadata,
+        ..
+    } = icloud_album_rs::api::get_api_response(&client, &redirected_url).await?;
 
     // Extract all photo GUIDs
     let photo_guids: Vec<String> = photos.iter().map(|p| p.photo_guid.clone()).collect();
@@ -213,5 +216,9 @@ async fn mock_get_icloud_photos(
     icloud_album_rs::enrich::enrich_photos_with_urls(&mut photos, &all_urls);
 
     // Return the final response
-    Ok(icloud_album_rs::models::ICloudResponse { metadata, photos })
+    Ok(icloud_album_rs::models::ICloudResponse {
+        metadata,
+        photos,
+        unparsed: Vec::new(),
+    })
 }
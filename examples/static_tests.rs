@@ -24,14 +24,9 @@ fn test_parse_api_response() {
     println!("\nTesting API response manual parsing...");
 
     // Create metadata and images manually
-    let metadata = Metadata {
-        stream_name: "Test Album".to_string(),
-        user_first_name: "John".to_string(),
-        user_last_name: "Doe".to_string(),
-        stream_ctag: "12345".to_string(),
-        items_returned: 2,
-        locations: serde_json::json!({}),
-    };
+    let mut metadata = Metadata::new("Test Album", "John", "Doe");
+    metadata.stream_ctag = "12345".into();
+    metadata.items_returned = 2;
 
     // Create first image with derivatives
     let mut derivatives1 = HashMap::new();
@@ -43,6 +38,7 @@ fn test_parse_api_response() {
             width: Some(800),
             height: Some(600),
             url: None,
+            ..Default::default()
         },
     );
     derivatives1.insert(
@@ -53,6 +49,7 @@ fn test_parse_api_response() {
             width: Some(1600),
             height: Some(1200),
             url: None,
+            ..Default::default()
         },
     );
 
@@ -62,8 +59,12 @@ fn test_parse_api_response() {
         caption: Some("Test image 1".to_string()),
         date_created: Some("2023-01-01".to_string()),
         batch_date_created: Some("2023-01-01".to_string()),
+        contributor_full_name: None,
         width: Some(1600),
         height: Some(1200),
+        location: None,
+        place_name: None,
+        placeholder: None,
     };
 
     // Create second image with derivatives
@@ -76,6 +77,7 @@ fn test_parse_api_response() {
             width: Some(800),
             height: Some(600),
             url: None,
+            ..Default::default()
         },
     );
 
@@ -85,11 +87,15 @@ fn test_parse_api_response() {
         caption: Some("Test image 2".to_string()),
         date_created: Some("2023-01-02".to_string()),
         batch_date_created: Some("2023-01-02".to_string()),
+        contributor_full_name: None,
         width: Some(800),
         height: Some(600),
+        location: None,
+        place_name: None,
+        placeholder: None,
     };
 
-    let photos = vec![image1, image2];
+    let photos = [image1, image2];
 
     // Verify the manually created objects
     assert_eq!(metadata.stream_name, "Test Album", "Stream name mismatch");
@@ -131,6 +137,7 @@ fn test_parse_asset_urls() {
             width: Some(800),
             height: Some(600),
             url: None,
+            ..Default::default()
         },
     );
 
@@ -140,8 +147,12 @@ fn test_parse_asset_urls() {
         caption: Some("Test image 1".to_string()),
         date_created: Some("2023-01-01".to_string()),
         batch_date_created: Some("2023-01-01".to_string()),
+        contributor_full_name: None,
         width: Some(1600),
         height: Some(1200),
+        location: None,
+        place_name: None,
+        placeholder: None,
     };
 
     let mut derivatives2 = HashMap::new();
@@ -153,6 +164,7 @@ fn test_parse_asset_urls() {
             width: Some(800),
             height: Some(600),
             url: None,
+            ..Default::default()
         },
     );
 
@@ -162,8 +174,12 @@ fn test_parse_asset_urls() {
         caption: Some("Test image 2".to_string()),
         date_created: Some("2023-01-02".to_string()),
         batch_date_created: Some("2023-01-02".to_string()),
+        contributor_full_name: None,
         width: Some(800),
         height: Some(600),
+        location: None,
+        place_name: None,
+        placeholder: None,
     };
 
     let mut photos = vec![image1, image2];
@@ -0,0 +1,14 @@
+//! Checks that sanitized names are safe to use as filenames, whatever the caption
+
+#![no_main]
+
+use icloud_album_rs::utils::{sanitize_filename, MAX_BASE_FILENAME_BYTES};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    let name = sanitize_filename(input);
+    assert!(!name.contains(['/', '\\', '\0']), "{:?}", name);
+    assert!(!name.chars().any(char::is_control), "{:?}", name);
+    assert!(name.len() <= MAX_BASE_FILENAME_BYTES, "{:?}", name);
+    assert!(!name.starts_with('.') && !name.ends_with(' '), "{:?}", name);
+});
@@ -0,0 +1,10 @@
+//! Feeds arbitrary bodies to the webasseturls parser, which must never panic
+
+#![no_main]
+
+use icloud_album_rs::api::parse_webasseturls_bytes;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_webasseturls_bytes(data);
+});
@@ -0,0 +1,10 @@
+//! Feeds arbitrary bodies to the webstream parser, which must never panic
+
+#![no_main]
+
+use icloud_album_rs::api::parse_webstream_bytes;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_webstream_bytes(data);
+});
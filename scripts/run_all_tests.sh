@@ -1,22 +0,0 @@
-#!/bin/bash
-# Script to run all tests, including those marked with #[ignore]
-# This can be used in CI environments
-
-set -e
-
-# First run the regular test suite, excluding the test runner itself
-echo "Running regular test suite..."
-cargo test -- --skip run_all_tests
-
-# Then run the special test that runs the ignored tests
-echo "Running test wrapper for ignored tests..."
-cargo test --test run_all_tests
-
-# Note: The test runner now handles tokio runtime conflicts by skipping those tests
-# and suggesting manual verification, rather than failing the build
-
-echo "Tests completed successfully!"
-
-# If you want to manually run the ignored tests (which may include tokio runtime tests)
-# you can run:
-# echo "To manually run all ignored tests: cargo test -- --ignored"
\ No newline at end of file
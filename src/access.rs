@@ -0,0 +1,245 @@
+//! Checking the credentials of requests to self-hosted viewers.
+//!
+//! A viewer or asset proxy exposed beyond localhost needs access control. An
+//! [`AccessPolicy`] holds the credentials that open everything, bearer tokens
+//! and a basic-auth user, and album tokens that open a single album, e.g. to
+//! share one album with family. [`AccessPolicy::authorize`] checks a request's
+//! `Authorization` header, or an `access_token` query parameter for links
+//! opened in a browser, which can't send headers with `<img>` requests.
+//!
+//! Applications serving albums themselves call the policy before answering,
+//! and send [`AccessPolicy::challenge`] with a 401. The JSON-RPC server of
+//! the `server` feature takes a policy in its
+//! [`crate::server::ServerConfig`].
+
+use crate::utils;
+use std::fmt;
+
+/// Name of the query parameter carrying an access token
+pub const ACCESS_TOKEN_PARAM: &str = "access_token";
+
+/// What a request may access
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+    /// Every album
+    Full,
+    /// Only the album with this ID
+    Album(String),
+    /// Nothing: credentials are missing or wrong; answer with 401 and the challenge
+    Unauthorized,
+    /// Nothing: the credentials are valid but for another album; answer with 403
+    Forbidden,
+}
+
+impl Access {
+    /// Whether the album with ID `album` may be accessed
+    pub fn allows(&self, album: &str) -> bool {
+        match self {
+            Access::Full => true,
+            Access::Album(allowed) => allowed == album,
+            Access::Unauthorized | Access::Forbidden => false,
+        }
+    }
+
+    /// The status code to answer with, or `None` if access is granted
+    pub fn denial_status(&self) -> Option<u16> {
+        match self {
+            Access::Full | Access::Album(_) => None,
+            Access::Unauthorized => Some(401),
+            Access::Forbidden => Some(403),
+        }
+    }
+}
+
+/// Credentials accepted by a self-hosted viewer
+///
+/// A policy without any credentials grants full access to every request, so
+/// protection stays optional for viewers bound to localhost.
+#[derive(Clone)]
+pub struct AccessPolicy {
+    realm: String,
+    bearer_tokens: Vec<String>,
+    basic: Option<(String, String)>,
+    album_tokens: Vec<(String, String)>,
+}
+
+impl fmt::Debug for AccessPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the credentials themselves
+        f.debug_struct("AccessPolicy")
+            .field("realm", &self.realm)
+            .field("bearer_tokens", &self.bearer_tokens.len())
+            .field("basic", &self.basic.as_ref().map(|(user, _)| user))
+            .field(
+                "albums",
+                &self
+                    .album_tokens
+                    .iter()
+                    .map(|(album, _)| album)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Default for AccessPolicy {
+    fn default() -> Self {
+        Self {
+            realm: "icloud-album".to_string(),
+            bearer_tokens: Vec::new(),
+            basic: None,
+            album_tokens: Vec::new(),
+        }
+    }
+}
+
+impl AccessPolicy {
+    /// Create a policy without credentials, which grants full access
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name the protected area in the challenge, e.g. after the application
+    #[must_use]
+    pub fn with_realm(mut self, realm: impl Into<String>) -> Self {
+        self.realm = realm.into();
+        self
+    }
+
+    /// Grant full access to requests carrying `token`
+    #[must_use]
+    pub fn with_bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.bearer_tokens.push(token.into());
+        self
+    }
+
+    /// Grant full access to requests authenticating as `user` with HTTP basic auth
+    #[must_use]
+    pub fn with_basic_auth(mut self, user: impl Into<String>, password: impl Into<String>) -> Self {
+        self.basic = Some((user.into(), password.into()));
+        self
+    }
+
+    /// Grant access to the album with ID `album` to requests carrying `token`
+    #[must_use]
+    pub fn with_album_token(mut self, album: impl Into<String>, token: impl Into<String>) -> Self {
+        self.album_tokens.push((album.into(), token.into()));
+        self
+    }
+
+    /// Whether any credentials are configured
+    pub fn is_protected(&self) -> bool {
+        !self.bearer_tokens.is_empty() || self.basic.is_some() || !self.album_tokens.is_empty()
+    }
+
+    /// The `WWW-Authenticate` header value to send with a 401
+    ///
+    /// Browsers prompt for a user and password when basic auth is configured.
+    pub fn challenge(&self) -> String {
+        let scheme = if self.basic.is_some() {
+            "Basic"
+        } else {
+            "Bearer"
+        };
+        format!("{} realm=\"{}\", charset=\"UTF-8\"", scheme, self.realm)
+    }
+
+    /// Decides what a request may access
+    ///
+    /// Tokens are compared in constant time. An album token grants its album
+    /// for requests that name no album, e.g. an album list, which the caller
+    /// then filters with [`Access::allows`].
+    ///
+    /// # Arguments
+    ///
+    /// * `authorization` - The request's `Authorization` header
+    /// * `query` - The request's query string, searched for [`ACCESS_TOKEN_PARAM`]
+    /// * `album` - ID of the album the request is for, if any
+    pub fn authorize(
+        &self,
+        authorization: Option<&str>,
+        query: Option<&str>,
+        album: Option<&str>,
+    ) -> Access {
+        if !self.is_protected() {
+            return Access::Full;
+        }
+        let credentials = match authorization.map(str::trim) {
+            Some(header) => parse_authorization(header),
+            None => query.and_then(query_token).map(Credentials::Token),
+        };
+        let Some(credentials) = credentials else {
+            return Access::Unauthorized;
+        };
+
+        match credentials {
+            Credentials::Basic(user, password) => match &self.basic {
+                Some((expected_user, expected_password))
+                    if constant_time_eq(&user, expected_user)
+                        & constant_time_eq(&password, expected_password) =>
+                {
+                    Access::Full
+                }
+                _ => Access::Unauthorized,
+            },
+            Credentials::Token(token) => {
+                if self.bearer_tokens.iter().fold(false, |found, expected| {
+                    found | constant_time_eq(&token, expected)
+                }) {
+                    return Access::Full;
+                }
+                let granted = self
+                    .album_tokens
+                    .iter()
+                    .filter(|(_, expected)| constant_time_eq(&token, expected))
+                    .map(|(granted, _)| granted)
+                    .collect::<Vec<_>>();
+                match (granted.first(), album) {
+                    (None, _) => Access::Unauthorized,
+                    (Some(_), Some(album)) if granted.iter().any(|granted| *granted == album) => {
+                        Access::Album(album.to_string())
+                    }
+                    (Some(_), Some(_)) => Access::Forbidden,
+                    (Some(granted), None) => Access::Album(granted.to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// Credentials presented by a request
+enum Credentials {
+    Token(String),
+    Basic(String, String),
+}
+
+/// Parses a `Bearer` or `Basic` authorization header
+fn parse_authorization(header: &str) -> Option<Credentials> {
+    let (scheme, value) = header.split_once(' ')?;
+    let value = value.trim();
+    if scheme.eq_ignore_ascii_case("bearer") {
+        Some(Credentials::Token(value.to_string()))
+    } else if scheme.eq_ignore_ascii_case("basic") {
+        let decoded = String::from_utf8(utils::decode_base64(value)?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        Some(Credentials::Basic(user.to_string(), password.to_string()))
+    } else {
+        None
+    }
+}
+
+/// Returns the [`ACCESS_TOKEN_PARAM`] of a query string, percent-decoded
+fn query_token(query: &str) -> Option<String> {
+    url::form_urlencoded::parse(query.trim_start_matches('?').as_bytes())
+        .find(|(name, _)| name == ACCESS_TOKEN_PARAM)
+        .map(|(_, value)| value.into_owned())
+}
+
+/// Compares two strings in time depending only on their lengths
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
@@ -0,0 +1,168 @@
+//! Identity of a shared album.
+//!
+//! An [`AlbumRef`] bundles an album's token with what is learned about it
+//! while talking to the API: the partition it lives on and the host its
+//! requests were redirected to. Passing it to [`crate::get_album_photos`]
+//! reuses the resolved host instead of probing partitions on every fetch, and
+//! its display helpers show the token redacted with [`redact_token`].
+
+use crate::base_url::{self, BaseUrlError};
+use crate::redact::redact_token;
+use std::fmt;
+use std::io;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// A shared album: its token, partition, resolved host and an optional alias
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlbumRef {
+    token: String,
+    partition: u32,
+    resolved_base_url: Option<String>,
+    alias: Option<String>,
+}
+
+impl AlbumRef {
+    /// Create a reference from a token
+    ///
+    /// The partition is computed under the installed
+    /// [`base_url::PartitionScheme`], or taken from the partition that last
+    /// answered for the token.
+    pub fn new(token: impl Into<String>) -> Result<Self, BaseUrlError> {
+        let token = token.into();
+        let partition = match base_url::cached_partition(&token) {
+            Some(partition) => partition,
+            None => base_url::calculate_partition(&token)?,
+        };
+        Ok(Self {
+            token,
+            partition,
+            resolved_base_url: None,
+            alias: None,
+        })
+    }
+
+    /// Create a reference from a pasted token or share URL
+    ///
+    /// The input is cleaned up with [`base_url::normalize_token`]; its
+    /// warnings are dropped.
+    pub fn parse(input: &str) -> Result<Self, BaseUrlError> {
+        Self::new(base_url::normalize_token(input)?.token)
+    }
+
+    /// Name the album, e.g. for logs and notifications
+    #[must_use]
+    pub fn with_alias(mut self, alias: impl Into<String>) -> Self {
+        self.alias = Some(alias.into());
+        self
+    }
+
+    /// The album token
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// The partition the album lives on, e.g. `12` for `p12-sharedstreams.icloud.com`
+    pub fn partition(&self) -> u32 {
+        self.partition
+    }
+
+    /// The alias, if one was set
+    pub fn alias(&self) -> Option<&str> {
+        self.alias.as_deref()
+    }
+
+    /// The base URL API requests were redirected to, once known
+    pub fn resolved_base_url(&self) -> Option<&str> {
+        self.resolved_base_url.as_deref()
+    }
+
+    /// The host of [`AlbumRef::resolved_base_url`], once known
+    pub fn resolved_host(&self) -> Option<&str> {
+        let url = self.resolved_base_url.as_deref()?;
+        let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+        rest.split('/').next()
+    }
+
+    /// The base URL to send API requests to
+    ///
+    /// This is the resolved base URL once known, and otherwise the one built
+    /// from the partition (see [`base_url::get_base_url`]).
+    pub fn base_url(&self) -> String {
+        match &self.resolved_base_url {
+            Some(url) => url.clone(),
+            None => base_url::partition_base_url(self.partition, &self.token),
+        }
+    }
+
+    /// Records the base URL requests were redirected to
+    ///
+    /// The partition is updated if one answered for the token since the
+    /// reference was created.
+    pub fn set_resolved(&mut self, base_url: impl Into<String>) {
+        self.resolved_base_url = Some(base_url.into());
+        if let Some(partition) = base_url::cached_partition(&self.token) {
+            self.partition = partition;
+        }
+    }
+
+    /// Forgets the resolved base URL, e.g. after the host stopped answering
+    pub fn clear_resolved(&mut self) {
+        self.resolved_base_url = None;
+    }
+
+    /// The alias, or the redacted token, for display
+    pub fn display_name(&self) -> String {
+        match &self.alias {
+            Some(alias) => alias.clone(),
+            None => redact_token(&self.token),
+        }
+    }
+
+    /// The public web address of the album, see [`base_url::share_url`]
+    pub fn share_url(&self) -> String {
+        format!("{}{}", base_url::SHARE_URL_PREFIX, self.token)
+    }
+
+    /// Opens the album's public web address in the default browser
+    ///
+    /// This runs `open` on macOS, `start` on Windows and `xdg-open`
+    /// elsewhere, without waiting for the browser.
+    pub fn open_in_browser(&self) -> io::Result<()> {
+        let url = self.share_url();
+        let mut command = if cfg!(target_os = "macos") {
+            Command::new("open")
+        } else if cfg!(windows) {
+            // The empty argument is the window title `start` expects first
+            let mut command = Command::new("cmd");
+            command.args(["/C", "start", ""]);
+            command
+        } else {
+            Command::new("xdg-open")
+        };
+        command
+            .arg(url)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(drop)
+    }
+}
+
+impl fmt::Display for AlbumRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.alias {
+            Some(alias) => write!(f, "{} ({})", alias, redact_token(&self.token)),
+            None => f.write_str(&redact_token(&self.token)),
+        }
+    }
+}
+
+impl FromStr for AlbumRef {
+    type Err = BaseUrlError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::parse(input)
+    }
+}
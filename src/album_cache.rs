@@ -0,0 +1,294 @@
+//! In-process caching of album fetches for services.
+//!
+//! A web service showing a shared album to many visitors would otherwise run
+//! the whole fetch pipeline for every page view. An [`AlbumCache`] keeps the
+//! result of each fetch, keyed by album token and a caller-chosen filter name,
+//! and hands out shared copies of it:
+//!
+//! 1. Within the time-to-live, the cached album is returned without a request
+//! 2. After it, the album's `streamCtag` is checked with a single request; if it
+//!    has not changed, the cached album is kept for another time-to-live
+//! 3. Once the album has changed, or the entry is older than the maximum age
+//!    (asset URLs expire eventually), the album is fetched again
+//!
+//! Requests for the same key are serialized, so when an entry goes stale only
+//! the first caller refreshes it; everyone who arrived meanwhile waits and
+//! then shares the fresh result.
+//!
+//! Share one cache between the request handlers of a service, e.g. in an
+//! [`Arc`], and call [`AlbumCache::get`] where they would otherwise call
+//! [`crate::get_icloud_photos`].
+
+use crate::fetch::FetchOptions;
+use crate::logging;
+use crate::models::{ICloudResponse, Image, StreamCtag};
+use crate::observer::NoopObserver;
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Default for [`AlbumCacheConfig::ttl`]
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+/// Default for [`AlbumCacheConfig::max_age`]
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(30 * 60);
+
+/// How long cached albums are served
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlbumCacheConfig {
+    /// How long an album is served before its `streamCtag` is checked again
+    pub ttl: Duration,
+    /// How long an album is served at most, however often it is revalidated
+    pub max_age: Duration,
+}
+
+impl Default for AlbumCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: DEFAULT_TTL,
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+}
+
+/// Counters of how requests to an [`AlbumCache`] were served
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AlbumCacheStats {
+    /// Requests served from a fresh entry
+    pub hits: u64,
+    /// Requests served from an entry whose `streamCtag` was still current
+    pub revalidations: u64,
+    /// Requests that fetched the album
+    pub fetches: u64,
+}
+
+/// Key of a cached album
+type CacheKey = (String, String);
+
+/// A cached album and its bookkeeping
+struct CachedAlbum {
+    response: Arc<ICloudResponse>,
+    fetched_at: Instant,
+    validated_at: Instant,
+}
+
+/// Holds one entry; locked while it is being refreshed
+type Slot = Arc<tokio::sync::Mutex<Option<CachedAlbum>>>;
+
+/// Concurrent cache of album fetches keyed by (token, filter)
+///
+/// The cache is internally synchronized and can be shared between tasks by
+/// reference or in an [`Arc`].
+pub struct AlbumCache {
+    config: AlbumCacheConfig,
+    slots: Mutex<HashMap<CacheKey, Slot>>,
+    hits: AtomicU64,
+    revalidations: AtomicU64,
+    fetches: AtomicU64,
+}
+
+impl AlbumCache {
+    /// Create an empty cache
+    pub fn new(config: AlbumCacheConfig) -> Self {
+        Self {
+            config,
+            slots: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            revalidations: AtomicU64::new(0),
+            fetches: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the configuration of the cache
+    pub fn config(&self) -> &AlbumCacheConfig {
+        &self.config
+    }
+
+    /// Returns an album with all its photos, fetching it if needed
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The iCloud shared album token
+    ///
+    /// # Returns
+    ///
+    /// The cached or freshly fetched album, or the error of the fetch
+    pub async fn get(&self, token: &str) -> Result<Arc<ICloudResponse>, Box<dyn Error>> {
+        self.get_selected(token, "", |photos| photos).await
+    }
+
+    /// Returns a selection of the photos of an album, fetching it if needed
+    ///
+    /// The selection is done as with [`crate::fetch::FetchOptions::with_selection`].
+    /// `filter` names the selection and is part of the cache key, so different
+    /// selections of the same album are cached separately; `select` is only
+    /// called when the album is fetched.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The iCloud shared album token
+    /// * `filter` - Name of the selection, e.g. `"latest-20"`
+    /// * `select` - Function choosing the photos to return
+    ///
+    /// # Returns
+    ///
+    /// The cached or freshly fetched album, or the error of the fetch
+    pub async fn get_selected<F>(
+        &self,
+        token: &str,
+        filter: &str,
+        select: F,
+    ) -> Result<Arc<ICloudResponse>, Box<dyn Error>>
+    where
+        F: FnOnce(Vec<Image>) -> Vec<Image>,
+    {
+        let options = FetchOptions::new();
+        self.get_with(
+            token,
+            filter,
+            || crate::fetch_selected(token, &options, &NoopObserver, select),
+            || crate::get_stream_ctag(token),
+        )
+        .await
+    }
+
+    /// Returns an album, fetching and revalidating it with the given functions
+    ///
+    /// This is the building block of [`AlbumCache::get_selected`] for albums
+    /// fetched by other means, e.g. with a custom client.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The iCloud shared album token
+    /// * `filter` - Name of the selection, part of the cache key
+    /// * `fetch` - Fetches the album
+    /// * `ctag` - Fetches the current `streamCtag` of the album
+    ///
+    /// # Returns
+    ///
+    /// The cached or freshly fetched album, or the error of the fetch
+    pub async fn get_with<Fetch, FetchFut, Ctag, CtagFut>(
+        &self,
+        token: &str,
+        filter: &str,
+        fetch: Fetch,
+        ctag: Ctag,
+    ) -> Result<Arc<ICloudResponse>, Box<dyn Error>>
+    where
+        Fetch: FnOnce() -> FetchFut,
+        FetchFut: Future<Output = Result<ICloudResponse, Box<dyn Error>>>,
+        Ctag: FnOnce() -> CtagFut,
+        CtagFut: Future<Output = Result<StreamCtag, Box<dyn Error>>>,
+    {
+        let slot = self.slot(token, filter);
+        // Waiters queue here while the first caller refreshes the entry
+        let mut entry = slot.lock().await;
+        let now = Instant::now();
+
+        if let Some(cached) = entry.as_mut() {
+            if now.duration_since(cached.validated_at) < self.config.ttl {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(Arc::clone(&cached.response));
+            }
+            if now.duration_since(cached.fetched_at) < self.config.max_age {
+                match ctag().await {
+                    Ok(current)
+                        if current.unchanged_since(Some(&cached.response.metadata.stream_ctag)) =>
+                    {
+                        cached.validated_at = Instant::now();
+                        self.revalidations.fetch_add(1, Ordering::Relaxed);
+                        return Ok(Arc::clone(&cached.response));
+                    }
+                    Ok(_) => {
+                        logging::log_debug!(
+                            logging::API,
+                            "Cached album changed, fetching it again"
+                        );
+                    }
+                    Err(e) => {
+                        logging::log_warn!(
+                            logging::API,
+                            "Could not revalidate cached album, fetching it again: {}",
+                            e
+                        );
+                    }
+                }
+            }
+        }
+
+        self.fetches.fetch_add(1, Ordering::Relaxed);
+        let response = Arc::new(fetch().await?);
+        let fetched_at = Instant::now();
+        *entry = Some(CachedAlbum {
+            response: Arc::clone(&response),
+            fetched_at,
+            validated_at: fetched_at,
+        });
+        Ok(response)
+    }
+
+    /// Returns the slot for a key, creating it if needed
+    fn slot(&self, token: &str, filter: &str) -> Slot {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        Arc::clone(
+            slots
+                .entry((token.to_string(), filter.to_string()))
+                .or_default(),
+        )
+    }
+
+    /// Drops every cached selection of an album
+    pub fn invalidate(&self, token: &str) {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        slots.retain(|(t, _), _| t != token);
+    }
+
+    /// Drops all cached albums
+    pub fn clear(&self) {
+        self.slots.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    /// Drops entries older than the maximum age, returning how many were dropped
+    ///
+    /// Entries that are being refreshed are kept. Long-running services serving
+    /// many albums can call this periodically to bound memory use.
+    pub fn purge_expired(&self) -> usize {
+        let max_age = self.config.max_age;
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        let before = slots.len();
+        slots.retain(|_, slot| match slot.try_lock() {
+            Ok(entry) => entry
+                .as_ref()
+                .is_some_and(|cached| cached.fetched_at.elapsed() < max_age),
+            Err(_) => true,
+        });
+        before - slots.len()
+    }
+
+    /// Returns the number of cached (token, filter) keys
+    pub fn len(&self) -> usize {
+        self.slots.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Returns true if nothing is cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns how requests have been served so far
+    pub fn stats(&self) -> AlbumCacheStats {
+        AlbumCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            revalidations: self.revalidations.load(Ordering::Relaxed),
+            fetches: self.fetches.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for AlbumCache {
+    fn default() -> Self {
+        Self::new(AlbumCacheConfig::default())
+    }
+}
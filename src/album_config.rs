@@ -0,0 +1,393 @@
+//! Reloading the album configuration of long-running applications.
+//!
+//! Applications following several albums, e.g. one [`crate::watch::AlbumWatcher`]
+//! and mirror per album, usually read the albums from a config file. A
+//! [`ConfigWatcher`] watches that file and reports what changed when it is
+//! edited, as [`ConfigChange`]s: albums added or removed, poll intervals and
+//! output directories changed. The application applies the changes to its
+//! running watchers, so none of them has to be restarted.
+//!
+//! The config file is JSON:
+//!
+//! ```json
+//! {
+//!   "albums": [
+//!     { "token": "B0z5qAGN1JIFd3y", "name": "Family", "interval_secs": 600, "output_dir": "photos/family" }
+//!   ]
+//! }
+//! ```
+//!
+//! `name` is optional and `interval_secs` defaults to
+//! [`DEFAULT_INTERVAL_SECS`]. A file that fails to load is reported and the
+//! previous configuration stays in effect. [`AlbumsConfig::load`] reads the
+//! file synchronously.
+//!
+//! The albums of an [`crate::app_config::AppConfig`] file, in any of its
+//! formats, are watched with [`crate::app_config::AppConfig::watch`].
+
+use crate::app_config::AppConfigError;
+use crate::logging;
+use crate::shutdown::Shutdown;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Default for [`FollowedAlbum::interval_secs`]
+pub const DEFAULT_INTERVAL_SECS: u64 = 300;
+
+/// Default time between checks of the config file
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+fn default_interval_secs() -> u64 {
+    DEFAULT_INTERVAL_SECS
+}
+
+/// An album followed by the application
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FollowedAlbum {
+    /// Token of the shared album, which identifies it in the config
+    pub token: String,
+    /// Name shown for the album, if different from the album's own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Seconds between polls of the album
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Directory the album is mirrored to
+    pub output_dir: PathBuf,
+}
+
+impl FollowedAlbum {
+    /// Time between polls of the album
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+}
+
+/// The albums followed by the application
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlbumsConfig {
+    /// The albums, in config file order
+    pub albums: Vec<FollowedAlbum>,
+}
+
+/// Error returned when a config file could not be loaded
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// The file could not be read
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The file is not valid config JSON
+    #[error("Invalid config file: {0}")]
+    Parse(#[from] serde_json::Error),
+    /// The file is valid JSON but describes an unusable configuration
+    #[error("Invalid config: {0}")]
+    Invalid(String),
+    /// The file is not a valid [`crate::app_config::AppConfig`]
+    #[error(transparent)]
+    App(#[from] AppConfigError),
+}
+
+impl AlbumsConfig {
+    /// Parses and checks a config file's contents
+    ///
+    /// Tokens must be unique and non-empty, and intervals non-zero.
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let config: AlbumsConfig = serde_json::from_str(contents)?;
+        let mut tokens = HashSet::new();
+        for (index, album) in config.albums.iter().enumerate() {
+            if album.token.trim().is_empty() {
+                return Err(ConfigError::Invalid(format!(
+                    "album {} has an empty token",
+                    index
+                )));
+            }
+            if !tokens.insert(album.token.as_str()) {
+                return Err(ConfigError::Invalid(format!(
+                    "album {} repeats the token of an earlier album",
+                    index
+                )));
+            }
+            if album.interval_secs == 0 {
+                return Err(ConfigError::Invalid(format!(
+                    "album {} has an interval of zero",
+                    index
+                )));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Reads and parses a config file
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Returns the album with the given token
+    pub fn album(&self, token: &str) -> Option<&FollowedAlbum> {
+        self.albums.iter().find(|album| album.token == token)
+    }
+}
+
+/// A change between two versions of the configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ConfigChange {
+    /// An album is now followed
+    AlbumAdded {
+        /// The new album
+        album: FollowedAlbum,
+    },
+    /// An album is no longer followed
+    AlbumRemoved {
+        /// Token of the album
+        token: String,
+    },
+    /// The time between polls of an album changed
+    IntervalChanged {
+        /// Token of the album
+        token: String,
+        /// Seconds between polls before the change
+        old_secs: u64,
+        /// Seconds between polls after the change
+        new_secs: u64,
+    },
+    /// The directory an album is mirrored to changed
+    ///
+    /// Moving the files already mirrored is up to the application.
+    OutputDirChanged {
+        /// Token of the album
+        token: String,
+        /// Directory before the change
+        old_dir: PathBuf,
+        /// Directory after the change
+        new_dir: PathBuf,
+    },
+    /// The name shown for an album changed
+    NameChanged {
+        /// Token of the album
+        token: String,
+        /// Name before the change
+        old_name: Option<String>,
+        /// Name after the change
+        new_name: Option<String>,
+    },
+}
+
+impl ConfigChange {
+    /// Returns the token of the album the change relates to
+    pub fn token(&self) -> &str {
+        match self {
+            ConfigChange::AlbumAdded { album } => &album.token,
+            ConfigChange::AlbumRemoved { token }
+            | ConfigChange::IntervalChanged { token, .. }
+            | ConfigChange::OutputDirChanged { token, .. }
+            | ConfigChange::NameChanged { token, .. } => token,
+        }
+    }
+}
+
+/// Compares two versions of the configuration
+///
+/// Removals come first, then changes to albums followed by both, then
+/// additions, each in config file order.
+///
+/// # Arguments
+///
+/// * `previous` - The configuration in effect
+/// * `current` - The newly loaded configuration
+///
+/// # Returns
+///
+/// * `Vec<ConfigChange>` - The changes, empty if the albums are the same
+pub fn diff_configs(previous: &AlbumsConfig, current: &AlbumsConfig) -> Vec<ConfigChange> {
+    let mut changes = Vec::new();
+    for album in &previous.albums {
+        if current.album(&album.token).is_none() {
+            changes.push(ConfigChange::AlbumRemoved {
+                token: album.token.clone(),
+            });
+        }
+    }
+    for album in &current.albums {
+        let Some(old) = previous.album(&album.token) else {
+            continue;
+        };
+        if old.interval_secs != album.interval_secs {
+            changes.push(ConfigChange::IntervalChanged {
+                token: album.token.clone(),
+                old_secs: old.interval_secs,
+                new_secs: album.interval_secs,
+            });
+        }
+        if old.output_dir != album.output_dir {
+            changes.push(ConfigChange::OutputDirChanged {
+                token: album.token.clone(),
+                old_dir: old.output_dir.clone(),
+                new_dir: album.output_dir.clone(),
+            });
+        }
+        if old.name != album.name {
+            changes.push(ConfigChange::NameChanged {
+                token: album.token.clone(),
+                old_name: old.name.clone(),
+                new_name: album.name.clone(),
+            });
+        }
+    }
+    for album in &current.albums {
+        if previous.album(&album.token).is_none() {
+            changes.push(ConfigChange::AlbumAdded {
+                album: album.clone(),
+            });
+        }
+    }
+    changes
+}
+
+/// Parses the contents of a watched config file
+type Parser = Box<dyn Fn(&str) -> Result<AlbumsConfig, ConfigError> + Send + Sync>;
+
+/// Watches a config file and reports the changes made to it
+///
+/// The file is read again every poll interval and compared with the contents
+/// last loaded, so edits are noticed regardless of timestamp granularity and
+/// saves that change nothing report nothing.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    parse: Parser,
+    config: AlbumsConfig,
+    contents: String,
+    poll_interval: Duration,
+    shutdown: Shutdown,
+}
+
+impl ConfigWatcher {
+    /// Loads the config file at `path` and creates a watcher for it
+    ///
+    /// Fails if the initial configuration can't be loaded, since there is
+    /// nothing to fall back to yet.
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self, ConfigError> {
+        Self::open_with(path, AlbumsConfig::parse).await
+    }
+
+    /// Loads a config file of another format with `parse` and creates a watcher for it
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The config file
+    /// * `parse` - Returns the albums described by the file's contents
+    pub async fn open_with(
+        path: impl Into<PathBuf>,
+        parse: impl Fn(&str) -> Result<AlbumsConfig, ConfigError> + Send + Sync + 'static,
+    ) -> Result<Self, ConfigError> {
+        let path = path.into();
+        let contents = tokio::fs::read_to_string(&path).await?;
+        let config = parse(&contents)?;
+        Ok(Self {
+            path,
+            parse: Box::new(parse),
+            config,
+            contents,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            shutdown: Shutdown::new(),
+        })
+    }
+
+    /// Set the time between checks of the file used by [`ConfigWatcher::run`]
+    #[must_use]
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Stop when `shutdown` is triggered, e.g. a signal shared with other components
+    #[must_use]
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Returns the path of the watched file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the configuration in effect
+    pub fn config(&self) -> &AlbumsConfig {
+        &self.config
+    }
+
+    /// Returns the watcher's shutdown signal, for stopping it from another task
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Reads the file again and applies it if it changed
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Vec<ConfigChange>, ConfigError>` - The changes applied, or why
+    ///   the file could not be loaded, in which case the configuration in
+    ///   effect is kept
+    pub async fn reload(&mut self) -> Result<Vec<ConfigChange>, ConfigError> {
+        let contents = tokio::fs::read_to_string(&self.path).await?;
+        if contents == self.contents {
+            return Ok(Vec::new());
+        }
+        let config = (self.parse)(&contents)?;
+        let changes = diff_configs(&self.config, &config);
+        self.config = config;
+        self.contents = contents;
+        Ok(changes)
+    }
+
+    /// Checks the file until shut down, calling `on_change` with every change applied
+    ///
+    /// `on_change` receives the new configuration and the changes, never
+    /// empty. Files that fail to load are logged once per distinct error and
+    /// the previous configuration stays in effect until the file is fixed.
+    pub async fn run(&mut self, mut on_change: impl FnMut(&AlbumsConfig, &[ConfigChange])) {
+        let shutdown = self.shutdown.clone();
+        let mut last_error = None;
+        while !shutdown.is_triggered() {
+            shutdown
+                .run_until(tokio::time::sleep(self.poll_interval))
+                .await;
+            if shutdown.is_triggered() {
+                break;
+            }
+            match self.reload().await {
+                Ok(changes) => {
+                    last_error = None;
+                    if !changes.is_empty() {
+                        logging::log_debug!(
+                            logging::WATCH,
+                            "Applied {} change(s) from {}",
+                            changes.len(),
+                            self.path.display()
+                        );
+                        on_change(&self.config, &changes);
+                    }
+                }
+                Err(e) => {
+                    let message = e.to_string();
+                    if last_error.as_ref() != Some(&message) {
+                        logging::log_warn!(
+                            logging::WATCH,
+                            "Keeping the previous configuration, {} failed to load: {}",
+                            self.path.display(),
+                            message
+                        );
+                        last_error = Some(message);
+                    }
+                }
+            }
+        }
+        logging::log_debug!(logging::WATCH, "Config watcher stopped");
+    }
+}
@@ -0,0 +1,228 @@
+//! Storage analysis helpers for fetched albums.
+//!
+//! This module computes byte-size statistics from an [`ICloudResponse`], such as
+//! the total size per derivative class, the largest items, and a size histogram,
+//! so storage-planning tools don't need to re-derive them from raw derivative maps.
+//! [`date_summary`] does the same for the dates photos were taken.
+
+use crate::derivatives::{classify_derivative, SizeClass};
+use crate::models::{CalendarDate, ICloudResponse, Image};
+use std::collections::{BTreeMap, HashMap};
+
+/// Aggregate storage figures for an album
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StorageSummary {
+    /// Number of photos in the album
+    pub photo_count: usize,
+    /// Total size of every derivative with a known file size
+    pub total_bytes: u64,
+    /// Total size per derivative size class
+    pub bytes_by_class: HashMap<SizeClass, u64>,
+    /// Total size of derivatives whose key could not be classified
+    pub unclassified_bytes: u64,
+    /// Average size of the largest derivative of each photo
+    ///
+    /// This approximates the per-photo cost of downloading the best version of
+    /// every photo. `None` if no photo has a derivative with a known size.
+    pub average_photo_bytes: Option<u64>,
+}
+
+/// A single derivative identified by photo and key, with its size
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizedItem {
+    /// GUID of the photo the derivative belongs to
+    pub photo_guid: String,
+    /// Key of the derivative within the photo
+    pub derivative_key: String,
+    /// Size of the derivative in bytes
+    pub file_size: u64,
+}
+
+/// A bucket of a size histogram covering `min_bytes..max_bytes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistogramBucket {
+    /// Inclusive lower bound of the bucket in bytes
+    pub min_bytes: u64,
+    /// Exclusive upper bound of the bucket in bytes
+    pub max_bytes: u64,
+    /// Number of photos whose largest derivative falls into the bucket
+    pub count: usize,
+}
+
+/// Dates covered by an album, with per-day photo counts
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DateSummary {
+    /// Earliest day a photo was taken
+    pub first: Option<CalendarDate>,
+    /// Latest day a photo was taken
+    pub last: Option<CalendarDate>,
+    /// Number of photos per day, for days with at least one photo
+    pub per_day: BTreeMap<CalendarDate, usize>,
+    /// Number of photos without a parseable date
+    pub undated: usize,
+}
+
+impl DateSummary {
+    /// Returns the photo count of every day from `first` to `last`, including empty days
+    ///
+    /// This is the shape calendar heatmaps expect.
+    pub fn daily_counts(&self) -> Vec<(CalendarDate, usize)> {
+        let (Some(first), Some(last)) = (self.first, self.last) else {
+            return Vec::new();
+        };
+        let mut counts = Vec::new();
+        let mut day = first;
+        loop {
+            counts.push((day, self.per_day.get(&day).copied().unwrap_or(0)));
+            if day >= last {
+                break;
+            }
+            day = day.next_day();
+        }
+        counts
+    }
+}
+
+/// Returns the size of the largest derivative of a photo, if any size is known
+fn largest_derivative_size(photo: &Image) -> Option<u64> {
+    photo.derivatives.values().filter_map(|d| d.file_size).max()
+}
+
+/// Computes aggregate storage figures for an album
+///
+/// # Arguments
+///
+/// * `response` - The album to analyze
+///
+/// # Returns
+///
+/// A [`StorageSummary`] with totals per size class and the average photo size
+pub fn storage_summary(response: &ICloudResponse) -> StorageSummary {
+    let mut summary = StorageSummary {
+        photo_count: response.photos.len(),
+        ..Default::default()
+    };
+
+    let mut photo_sizes_total: u64 = 0;
+    let mut photos_with_size: u64 = 0;
+
+    for photo in &response.photos {
+        for (key, derivative) in &photo.derivatives {
+            let Some(size) = derivative.file_size else {
+                continue;
+            };
+
+            summary.total_bytes += size;
+            match classify_derivative(key, derivative) {
+                Some(class) => *summary.bytes_by_class.entry(class).or_insert(0) += size,
+                None => summary.unclassified_bytes += size,
+            }
+        }
+
+        if let Some(size) = largest_derivative_size(photo) {
+            photo_sizes_total += size;
+            photos_with_size += 1;
+        }
+    }
+
+    summary.average_photo_bytes = photo_sizes_total.checked_div(photos_with_size);
+
+    summary
+}
+
+/// Returns the `n` largest derivatives in the album, largest first
+///
+/// # Arguments
+///
+/// * `response` - The album to analyze
+/// * `n` - Maximum number of items to return
+///
+/// # Returns
+///
+/// Up to `n` derivatives sorted by descending size
+pub fn largest_items(response: &ICloudResponse, n: usize) -> Vec<SizedItem> {
+    let mut items: Vec<SizedItem> = response
+        .photos
+        .iter()
+        .flat_map(|photo| {
+            photo
+                .derivatives
+                .iter()
+                .filter_map(move |(key, derivative)| {
+                    derivative.file_size.map(|file_size| SizedItem {
+                        photo_guid: photo.photo_guid.clone(),
+                        derivative_key: key.clone(),
+                        file_size,
+                    })
+                })
+        })
+        .collect();
+
+    // Sort by size, breaking ties deterministically
+    items.sort_by(|a, b| {
+        b.file_size
+            .cmp(&a.file_size)
+            .then_with(|| a.photo_guid.cmp(&b.photo_guid))
+            .then_with(|| a.derivative_key.cmp(&b.derivative_key))
+    });
+    items.truncate(n);
+    items
+}
+
+/// Builds a histogram of photo sizes using power-of-two buckets
+///
+/// Each photo is counted once, by the size of its largest derivative. Buckets
+/// span `[2^k, 2^(k+1))` bytes and only non-empty buckets are returned, in
+/// ascending order. Photos without any known size are not counted.
+///
+/// # Arguments
+///
+/// * `response` - The album to analyze
+///
+/// # Returns
+///
+/// The non-empty histogram buckets in ascending order
+pub fn size_histogram(response: &ICloudResponse) -> Vec<HistogramBucket> {
+    let mut counts: HashMap<u32, usize> = HashMap::new();
+
+    for size in response.photos.iter().filter_map(largest_derivative_size) {
+        // Zero-byte entries share the lowest bucket with one-byte entries
+        let exponent = size.max(1).ilog2();
+        *counts.entry(exponent).or_insert(0) += 1;
+    }
+
+    let mut buckets: Vec<HistogramBucket> = counts
+        .into_iter()
+        .map(|(exponent, count)| HistogramBucket {
+            min_bytes: if exponent == 0 { 0 } else { 1u64 << exponent },
+            max_bytes: 1u64.checked_shl(exponent + 1).unwrap_or(u64::MAX),
+            count,
+        })
+        .collect();
+    buckets.sort_by_key(|bucket| bucket.min_bytes);
+    buckets
+}
+
+/// Counts photos per day and finds the album's date range
+///
+/// Photos are dated with [`Image::date_taken`].
+///
+/// # Arguments
+///
+/// * `response` - The album to analyze
+///
+/// # Returns
+///
+/// A [`DateSummary`] with the first and last day and the per-day counts
+pub fn date_summary(response: &ICloudResponse) -> DateSummary {
+    let mut summary = DateSummary::default();
+    for photo in &response.photos {
+        match photo.date_taken() {
+            Some(date) => *summary.per_day.entry(date).or_insert(0) += 1,
+            None => summary.undated += 1,
+        }
+    }
+    summary.first = summary.per_day.keys().next().copied();
+    summary.last = summary.per_day.keys().next_back().copied();
+    summary
+}
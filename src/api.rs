@@ -3,8 +3,11 @@
 //! This module provides functions to fetch album metadata, photo information,
 //! and asset URLs from the iCloud shared album API endpoints.
 
-use crate::models::{self, Image, Metadata};
-use log::warn;
+use crate::diagnostics;
+use crate::logging;
+use crate::models::{self, Image, Metadata, StreamCtag, WebstreamRequest};
+use crate::redact::Redacted;
+use crate::url_policy::{UrlPolicy, UrlPolicyError};
 use reqwest::Client;
 use serde_json::json;
 use std::collections::HashMap;
@@ -12,7 +15,10 @@ use std::error::Error;
 use std::fmt;
 
 /// Custom error type for API-related errors
-#[derive(Debug)]
+///
+/// `Display` and `Debug` output is passed through [`Redacted`], so request URLs
+/// embedded in errors do not leak album tokens or URL signatures.
+#[non_exhaustive]
 pub enum ApiError {
     /// Error from a network request
     NetworkError(reqwest::Error),
@@ -27,32 +33,351 @@ pub enum ApiError {
         /// Error message
         message: String,
     },
+    /// Error when an asset URL in the response fails validation
+    UrlRejected {
+        /// The GUID or checksum the URL belongs to
+        guid: String,
+        /// Why the URL was rejected
+        reason: UrlPolicyError,
+    },
+    /// Error when Apple reports that the album doesn't exist, with its explanation
+    AlbumNotFound(String),
     /// Error during retries
     RetryError(String),
     /// Other errors
     Other(String),
+    /// Another error, annotated with what was being done when it occurred
+    Context {
+        /// The operation, endpoint and photo involved
+        context: ErrorContext,
+        /// The underlying error
+        source: Box<ApiError>,
+    },
+}
+
+/// Describes the operation an error occurred in
+///
+/// Attached to errors with [`ApiError::with_context`], so that a single log line
+/// says which request (and which photo, where applicable) failed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// ID of the operation the error occurred in, see [`logging::operation_id`]
+    pub operation_id: Option<String>,
+    /// Short description of the operation (e.g. `"fetch webstream"`)
+    pub operation: String,
+    /// URL of the endpoint that was requested
+    pub endpoint: Option<String>,
+    /// GUID of the photo involved
+    pub guid: Option<String>,
+    /// Diagnostic headers of the last response received, if any
+    pub response_headers: Option<Box<ResponseHeaders>>,
+}
+
+impl ErrorContext {
+    /// Create a context for the named operation
+    ///
+    /// The ID of the current operation, if any, is recorded with it.
+    pub fn new(operation: impl Into<String>) -> Self {
+        Self {
+            operation_id: logging::operation_id(),
+            operation: operation.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Set the endpoint URL
+    #[must_use]
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Set the photo GUID
+    #[must_use]
+    pub fn with_guid(mut self, guid: impl Into<String>) -> Self {
+        self.guid = Some(guid.into());
+        self
+    }
+
+    /// Set the diagnostic headers of the last response, if any were captured
+    #[must_use]
+    pub fn with_response_headers(mut self, headers: Option<ResponseHeaders>) -> Self {
+        self.response_headers = headers.filter(|headers| !headers.is_empty()).map(Box::new);
+        self
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.operation)?;
+        if let Some(guid) = &self.guid {
+            write!(f, " for photo {}", guid)?;
+        }
+        if let Some(endpoint) = &self.endpoint {
+            write!(f, " ({})", Redacted(endpoint))?;
+        }
+        if let Some(id) = &self.operation_id {
+            write!(f, " [op {}]", id)?;
+        }
+        if let Some(id) = self.response_headers.as_ref().and_then(|h| h.request_id()) {
+            write!(f, " [request {}]", id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Response headers that help diagnose failures on Apple's side
+///
+/// Only headers identifying the request or the responding server, and those
+/// describing rate limits, are kept; see [`ResponseHeaders::is_captured`].
+/// Include them when reporting failures that can't be explained locally.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResponseHeaders {
+    /// HTTP status of the response
+    pub status: u16,
+    /// The captured headers, as (lowercase name, value)
+    pub headers: Vec<(String, String)>,
+}
+
+/// Headers naming the request or the server that answered it
+const CAPTURED_HEADERS: &[&str] = &[
+    "x-apple-request-uuid",
+    "x-request-id",
+    "x-apple-jingle-correlation-key",
+    "x-responding-instance",
+    "x-apple-user-partition",
+    "apple-seq",
+    "apple-tk",
+    "apple-originating-system",
+    "server",
+    "via",
+    "retry-after",
+];
+
+/// Headers checked, in order, for an identifier of the request
+const REQUEST_ID_HEADERS: &[&str] = &[
+    "x-apple-request-uuid",
+    "x-request-id",
+    "x-apple-jingle-correlation-key",
+];
+
+impl ResponseHeaders {
+    /// Keeps the diagnostic headers of a response
+    pub fn capture(status: u16, headers: &reqwest::header::HeaderMap) -> Self {
+        Self {
+            status,
+            headers: headers
+                .iter()
+                .filter(|(name, _)| Self::is_captured(name.as_str()))
+                .map(|(name, value)| {
+                    (
+                        name.as_str().to_string(),
+                        String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Keeps the diagnostic headers of a response
+    pub fn from_response(response: &reqwest::Response) -> Self {
+        Self::capture(response.status().as_u16(), response.headers())
+    }
+
+    /// Whether a header, given by its lowercase name, is kept
+    pub fn is_captured(name: &str) -> bool {
+        CAPTURED_HEADERS.contains(&name)
+            || name.starts_with("x-ratelimit-")
+            || name.starts_with("ratelimit")
+    }
+
+    /// Whether no header was kept
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    /// Returns the first value of a header, by case-insensitive name
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the identifier Apple assigned to the request, if any
+    pub fn request_id(&self) -> Option<&str> {
+        REQUEST_ID_HEADERS.iter().find_map(|name| self.get(name))
+    }
+
+    /// Returns the rate-limit headers
+    pub fn rate_limits(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers
+            .iter()
+            .filter(|(name, _)| name.starts_with("x-ratelimit-") || name.starts_with("ratelimit"))
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+impl ApiError {
+    /// Wraps the error with a description of the operation it occurred in
+    #[must_use]
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        ApiError::Context {
+            context,
+            source: Box::new(self),
+        }
+    }
+
+    /// Returns the innermost context attached to the error, if any
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            ApiError::Context { context, source } => source.context().or(Some(context)),
+            _ => None,
+        }
+    }
+
+    /// Whether Apple reported that the album doesn't exist, e.g. because it
+    /// was deleted or its sharing was turned off
+    pub fn is_not_found(&self) -> bool {
+        matches!(self.root(), ApiError::AlbumNotFound(_))
+    }
+
+    /// Returns the underlying error with all context removed
+    pub fn root(&self) -> &ApiError {
+        match self {
+            ApiError::Context { source, .. } => source.root(),
+            other => other,
+        }
+    }
+
+    /// Broad cause of the error, see [`FailureClass`]
+    ///
+    /// Missing albums and `401`, `403`, `404` and `410` responses are
+    /// [`FailureClass::Token`]; failed requests, other statuses and exhausted
+    /// retries are [`FailureClass::Network`].
+    pub fn failure_class(&self) -> FailureClass {
+        match self.root() {
+            ApiError::AlbumNotFound(_) => FailureClass::Token,
+            ApiError::RequestError {
+                status: Some(401 | 403 | 404 | 410),
+                ..
+            } => FailureClass::Token,
+            ApiError::NetworkError(_) | ApiError::RequestError { .. } | ApiError::RetryError(_) => {
+                FailureClass::Network
+            }
+            _ => FailureClass::Other,
+        }
+    }
+}
+
+/// Broad cause of a failed fetch or download, e.g. to choose an exit code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FailureClass {
+    /// The token is invalid, or the album was deleted or is no longer shared
+    Token,
+    /// Apple's servers could not be reached or failed to answer
+    Network,
+    /// Anything else, e.g. unexpected responses or local I/O errors
+    Other,
+}
+
+impl FailureClass {
+    /// Classifies an error returned by this crate
+    ///
+    /// [`ApiError`]s are classified with [`ApiError::failure_class`], and a
+    /// [`crate::download::DownloadError`] by its source. Invalid tokens,
+    /// failed downloads and transfers that stalled or were refused are
+    /// classified as well; other errors are [`FailureClass::Other`].
+    pub fn of(error: &(dyn Error + 'static)) -> Self {
+        if let Some(error) = error.downcast_ref::<ApiError>() {
+            error.failure_class()
+        } else if let Some(error) = error.downcast_ref::<crate::download::DownloadError>() {
+            Self::of(error.source.as_ref())
+        } else if error.is::<crate::base_url::BaseUrlError>() {
+            FailureClass::Token
+        } else if error.is::<reqwest::Error>() || error.is::<crate::download::TransferError>() {
+            FailureClass::Network
+        } else {
+            FailureClass::Other
+        }
+    }
 }
 
 impl fmt::Display for ApiError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ApiError::NetworkError(e) => write!(f, "Network error: {}", e),
+            ApiError::NetworkError(e) => write!(f, "Network error: {}", Redacted(e)),
             ApiError::JsonParseError(msg) => write!(f, "JSON parse error: {}", msg),
             ApiError::MissingFieldError(field) => write!(f, "Missing field in response: {}", field),
             ApiError::RequestError { status, message } => {
                 if let Some(status_code) = status {
-                    write!(f, "Request error (status {}): {}", status_code, message)
+                    write!(
+                        f,
+                        "Request error (status {}): {}",
+                        status_code,
+                        Redacted(message)
+                    )
                 } else {
-                    write!(f, "Request error: {}", message)
+                    write!(f, "Request error: {}", Redacted(message))
                 }
             }
-            ApiError::RetryError(msg) => write!(f, "Retry error: {}", msg),
-            ApiError::Other(msg) => write!(f, "Error: {}", msg),
+            ApiError::UrlRejected { guid, reason } => {
+                write!(f, "Rejected asset URL for {}: {}", guid, Redacted(reason))
+            }
+            ApiError::AlbumNotFound(msg) => write!(f, "Album not found: {}", Redacted(msg)),
+            ApiError::RetryError(msg) => write!(f, "Retry error: {}", Redacted(msg)),
+            ApiError::Other(msg) => write!(f, "Error: {}", Redacted(msg)),
+            ApiError::Context { context, source } => {
+                write!(f, "{}: {}", Redacted(context), source)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::NetworkError(e) => f.debug_tuple("NetworkError").field(&Redacted(e)).finish(),
+            ApiError::JsonParseError(msg) => f.debug_tuple("JsonParseError").field(msg).finish(),
+            ApiError::MissingFieldError(field) => {
+                f.debug_tuple("MissingFieldError").field(field).finish()
+            }
+            ApiError::RequestError { status, message } => f
+                .debug_struct("RequestError")
+                .field("status", status)
+                .field("message", &Redacted(message))
+                .finish(),
+            ApiError::UrlRejected { guid, reason } => f
+                .debug_struct("UrlRejected")
+                .field("guid", guid)
+                .field("reason", &Redacted(reason))
+                .finish(),
+            ApiError::AlbumNotFound(msg) => f
+                .debug_tuple("AlbumNotFound")
+                .field(&Redacted(msg))
+                .finish(),
+            ApiError::RetryError(msg) => f.debug_tuple("RetryError").field(&Redacted(msg)).finish(),
+            ApiError::Other(msg) => f.debug_tuple("Other").field(&Redacted(msg)).finish(),
+            ApiError::Context { context, source } => f
+                .debug_struct("Context")
+                .field("context", &Redacted(context))
+                .field("source", source)
+                .finish(),
         }
     }
 }
 
-impl Error for ApiError {}
+impl Error for ApiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ApiError::NetworkError(e) => Some(e),
+            ApiError::UrlRejected { reason, .. } => Some(reason),
+            ApiError::Context { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl From<reqwest::Error> for ApiError {
     fn from(err: reqwest::Error) -> Self {
@@ -81,6 +406,34 @@ impl From<&str> for ApiError {
 // Don't need an explicit conversion from ApiError to Box<dyn Error>
 // since this is provided by the standard library for any type that implements Error
 
+/// Prepares a request to an API endpoint
+///
+/// With the `compression` feature, this asks for a compressed response as
+/// configured by `compression::set_accept_encoding`. Every prepared request
+/// counts as an API call in the installed [`crate::metrics::Metrics`].
+pub(crate) fn api_request(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    crate::metrics::record(|metrics| metrics.on_api_call());
+    #[cfg(feature = "compression")]
+    let request = crate::compression::negotiate(request);
+    request
+}
+
+/// Reads the body of an API response, decompressing it if needed
+pub(crate) async fn api_body(resp: reqwest::Response) -> Result<Vec<u8>, ApiError> {
+    #[cfg(feature = "compression")]
+    return crate::compression::read_body(resp).await;
+    #[cfg(not(feature = "compression"))]
+    Ok(Vec::from(resp.bytes().await?))
+}
+
+/// Reads the JSON body of an API response, decompressing it if needed
+pub(crate) async fn api_json(resp: reqwest::Response) -> Result<serde_json::Value, ApiError> {
+    #[cfg(feature = "compression")]
+    return Ok(serde_json::from_slice(&api_body(resp).await?)?);
+    #[cfg(not(feature = "compression"))]
+    Ok(resp.json().await?)
+}
+
 /// Fetches metadata and photos from the iCloud API
 ///
 /// This function makes a POST request to the webstream endpoint and extracts
@@ -93,77 +446,338 @@ impl From<&str> for ApiError {
 ///
 /// # Returns
 ///
-/// A tuple containing a vector of Images and Metadata information
+/// The photos and metadata of the album, see [`WebstreamResponse`]
 pub async fn get_api_response(
     client: &Client,
     base_url: &str,
-) -> Result<(Vec<Image>, Metadata), ApiError> {
+) -> Result<WebstreamResponse, ApiError> {
+    get_api_response_with_request(client, base_url, &WebstreamRequest::default()).await
+}
+
+/// Fetches metadata and photos from the iCloud API using a custom request payload
+///
+/// This behaves like [`get_api_response`], but sends the given [`WebstreamRequest`]
+/// instead of the default `{"streamCtag": null}` payload.
+///
+/// # Arguments
+///
+/// * `client` - A reqwest HTTP client
+/// * `base_url` - The base URL for API requests
+/// * `request` - The payload to send to the webstream endpoint
+///
+/// # Returns
+///
+/// The photos and metadata of the album, see [`WebstreamResponse`]
+pub async fn get_api_response_with_request(
+    client: &Client,
+    base_url: &str,
+    request: &WebstreamRequest,
+) -> Result<WebstreamResponse, ApiError> {
+    // A single attempt
+    let retry_config = RetryConfig {
+        max_retries: 1,
+        ..Default::default()
+    };
+    get_api_response_with_retry(client, base_url, request, &retry_config).await
+}
+
+/// Fetches metadata and photos from the iCloud API, retrying failed requests
+///
+/// This behaves like [`get_api_response_with_request`], but retries failures
+/// as configured by `retry_config`, e.g. the [`Endpoint::Webstream`] entry of
+/// a [`RetryPolicies`].
+///
+/// # Arguments
+///
+/// * `client` - A reqwest HTTP client
+/// * `base_url` - The base URL for API requests
+/// * `request` - The payload to send to the webstream endpoint
+/// * `retry_config` - Configuration for retry behavior
+///
+/// # Returns
+///
+/// The photos and metadata of the album, with the [`ParseReport`] of the
+/// response, including the items that failed to parse
+pub async fn get_api_response_with_retry(
+    client: &Client,
+    base_url: &str,
+    request: &WebstreamRequest,
+    retry_config: &RetryConfig,
+) -> Result<WebstreamResponse, ApiError> {
     // Build the URL for the webstream endpoint
     let url = format!("{}webstream", base_url);
 
-    // Create the payload with a null streamCtag
-    let payload = json!({ "streamCtag": null });
+    // Build the payload from the request
+    let payload = request.to_payload();
+
+    let last_headers = LastHeaders::default();
+    let result = execute_with_retry(
+        || async {
+            // Make the POST request
+            let resp = crate::har::send(api_request(client.post(&url).json(&payload))).await?;
+            let headers = last_headers.record(&resp);
+
+            // Check if the request was successful
+            if !resp.status().is_success() {
+                return Err(ApiError::RequestError {
+                    status: Some(resp.status().as_u16()),
+                    message: "webstream request failed".to_string(),
+                });
+            }
+
+            // Parse the response as JSON
+            let data = api_json(resp).await?;
+
+            let mut response = parse_webstream_response(&data)?;
+            response.report.response_headers = Some(headers);
+            Ok(response)
+        },
+        retry_config,
+        None,
+    )
+    .await;
+
+    result.map_err(|e| {
+        e.with_context(
+            ErrorContext::new("fetch webstream")
+                .with_endpoint(&url)
+                .with_response_headers(last_headers.take()),
+        )
+    })
+}
 
-    // Make the POST request
-    let resp = client.post(&url).json(&payload).send().await?;
+/// The diagnostic headers of the last response to a (retried) request
+#[derive(Default)]
+struct LastHeaders(std::sync::Mutex<Option<ResponseHeaders>>);
 
-    // Check if the request was successful
-    if !resp.status().is_success() {
-        return Err(ApiError::RequestError {
-            status: Some(resp.status().as_u16()),
-            message: "webstream request failed".to_string(),
-        });
+impl LastHeaders {
+    /// Keeps the headers of `response`, returning them
+    fn record(&self, response: &reqwest::Response) -> ResponseHeaders {
+        let headers = ResponseHeaders::from_response(response);
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Some(headers.clone());
+        headers
     }
 
-    // Parse the response as JSON
-    let data: serde_json::Value = resp.json().await?;
+    /// Returns the headers of the last response, if there was one
+    fn take(&self) -> Option<ResponseHeaders> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+/// Fetches only the `streamCtag` of an album
+///
+/// The webstream endpoint has no lighter variant, so this sends the same
+/// request as [`get_api_response`], but the body is only scanned for the
+/// `streamCtag` field: photos are neither validated nor parsed. Pollers can
+/// compare the result with a stored tag before deciding to do a full fetch.
+///
+/// # Arguments
+///
+/// * `client` - A reqwest HTTP client
+/// * `base_url` - The (redirected) base URL for API requests
+///
+/// # Returns
+///
+/// The album's current `streamCtag`
+pub async fn get_stream_ctag_at(client: &Client, base_url: &str) -> Result<StreamCtag, ApiError> {
+    let url = format!("{}webstream", base_url);
+    let payload = WebstreamRequest::default().to_payload();
+
+    let last_headers = LastHeaders::default();
+    let result: Result<_, ApiError> = async {
+        let resp = crate::har::send(api_request(client.post(&url).json(&payload))).await?;
+        last_headers.record(&resp);
+        if !resp.status().is_success() {
+            return Err(ApiError::RequestError {
+                status: Some(resp.status().as_u16()),
+                message: "webstream request failed".to_string(),
+            });
+        }
+        let bytes = api_body(resp).await?;
+        parse_stream_ctag_bytes(&bytes)
+    }
+    .await;
+
+    result.map_err(|e| {
+        e.with_context(
+            ErrorContext::new("fetch stream ctag")
+                .with_endpoint(&url)
+                .with_response_headers(last_headers.take()),
+        )
+    })
+}
+
+/// Extracts the `streamCtag` from a raw webstream response body
+///
+/// Other fields, including the photos, are skipped without being parsed.
+///
+/// # Arguments
+///
+/// * `bytes` - The raw body of a webstream response
+///
+/// # Returns
+///
+/// The album's `streamCtag`
+pub fn parse_stream_ctag_bytes(bytes: &[u8]) -> Result<StreamCtag, ApiError> {
+    #[derive(serde::Deserialize)]
+    struct CtagOnly {
+        #[serde(rename = "streamCtag")]
+        stream_ctag: Option<StreamCtag>,
+    }
+
+    let parsed: CtagOnly = serde_json::from_slice(bytes)?;
+    parsed
+        .stream_ctag
+        .ok_or_else(|| ApiError::MissingFieldError("streamCtag".to_string()))
+}
+
+/// Parses a raw webstream response body
+///
+/// This is a pure function that performs no I/O, so it can be used with bodies
+/// obtained from other HTTP stacks or recordings, and fuzzed directly. Malformed
+/// input never panics: invalid JSON is reported as [`ApiError::JsonParseError`]
+/// and individual photos that fail to parse are skipped with a warning.
+///
+/// # Arguments
+///
+/// * `bytes` - The raw body of a webstream response
+///
+/// # Returns
+///
+/// The photos and metadata of the album, see [`WebstreamResponse`]
+pub fn parse_webstream_bytes(bytes: &[u8]) -> Result<WebstreamResponse, ApiError> {
+    let data: serde_json::Value = serde_json::from_slice(bytes)?;
+    parse_webstream_response(&data)
+}
+
+/// Parses a raw webasseturls response body
+///
+/// Like [`parse_webstream_bytes`], this performs no I/O and never panics on
+/// malformed input.
+///
+/// # Arguments
+///
+/// * `bytes` - The raw body of a webasseturls response
+///
+/// # Returns
+///
+/// A HashMap mapping from checksum to full URL
+pub fn parse_webasseturls_bytes(bytes: &[u8]) -> Result<HashMap<String, String>, ApiError> {
+    let data: serde_json::Value = serde_json::from_slice(bytes)?;
+    parse_webasseturls_response(&data)
+}
+
+/// Photos and metadata of an album, as returned by the webstream endpoint
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct WebstreamResponse {
+    /// The photos that parsed, without asset URLs
+    pub photos: Vec<Image>,
+    /// Metadata of the album
+    pub metadata: Metadata,
+    /// What was tolerated while parsing the response
+    pub report: ParseReport,
+}
+
+/// Asset URLs, as returned by the webasseturls endpoint
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct AssetUrlsResponse {
+    /// Full URLs by derivative checksum
+    pub urls: HashMap<String, String>,
+    /// The schema issues found and the assets left out
+    pub report: ParseReport,
+}
+
+/// Diagnostics collected while parsing a response
+///
+/// Parsing is lenient: schema drift and individual unparseable photos are
+/// tolerated rather than failing the whole response. This report records what
+/// was tolerated so that callers can inspect it programmatically.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParseReport {
+    /// Schema validation issues found in the response
+    pub schema_issues: Vec<SchemaIssue>,
+    /// Photos that failed to parse, as (index in the `photos` array, error message)
+    pub skipped_photos: Vec<(usize, String)>,
+    /// The raw items of the photos that failed to parse
+    pub unparsed_items: Vec<models::UnparsedItem>,
+    /// Fixtures written for skipped photos (see [`crate::diagnostics::set_capture_dir`])
+    pub captured_fixtures: Vec<std::path::PathBuf>,
+    /// Assets left out of a webasseturls result, as (checksum, reason)
+    pub skipped_assets: Vec<(String, String)>,
+    /// Diagnostic headers of the response, when it was fetched over HTTP
+    pub response_headers: Option<ResponseHeaders>,
+}
+
+impl ParseReport {
+    /// Returns true if the response parsed without any issues
+    pub fn is_clean(&self) -> bool {
+        self.schema_issues.is_empty()
+            && self.skipped_photos.is_empty()
+            && self.skipped_assets.is_empty()
+    }
+
+    /// Returns the highest severity among the schema issues, if there are any
+    pub fn max_severity(&self) -> Option<IssueSeverity> {
+        self.schema_issues.iter().map(|issue| issue.severity).max()
+    }
+
+    /// Returns the schema issues at or above `severity`
+    pub fn issues_at_least(&self, severity: IssueSeverity) -> impl Iterator<Item = &SchemaIssue> {
+        self.schema_issues
+            .iter()
+            .filter(move |issue| issue.severity >= severity)
+    }
+}
+
+/// Extracts photos and metadata from an already-fetched webstream response body
+///
+/// This is the pure parsing half of [`get_api_response_with_request`]. It
+/// performs schema validation, photo parsing, and metadata extraction without
+/// making any requests, so it can be used with bodies obtained elsewhere.
+///
+/// # Arguments
+///
+/// * `data` - The JSON body of a webstream response
+///
+/// # Returns
+///
+/// The photos, metadata and [`ParseReport`] of the album, see [`WebstreamResponse`]
+pub fn parse_webstream_response(data: &serde_json::Value) -> Result<WebstreamResponse, ApiError> {
+    let _repeats = logging::limit_repeats();
+    let mut report = ParseReport::default();
 
     // Validate the API response against expected schema
-    let issues = validate_api_schema(&data, "webstream");
+    let issues = check_api_schema(data, "webstream");
     if !issues.is_empty() {
-        // Log all validation issues as warnings
-        for (field, failure) in &issues {
-            match failure {
-                ValidationFailure::Missing => {
-                    log_warning(&format!("Schema validation: Missing field '{}'", field));
-                }
-                ValidationFailure::WrongType => {
-                    log_warning(&format!(
-                        "Schema validation: Field '{}' has wrong type",
-                        field
-                    ));
-                }
-                ValidationFailure::InvalidValue(msg) => {
-                    log_warning(&format!(
-                        "Schema validation: Field '{}' has invalid value: {}",
-                        field, msg
-                    ));
-                }
-            }
-        }
+        // Log validation issues by severity, one line per distinct issue
+        log_schema_issues(&issues);
 
         // If there are critical issues, we might want to fail the request
         // For now, we log but continue with the processing
-        log_warning(&format!(
+        log_schema_warning(&format!(
             "API response has {} schema validation issues",
             issues.len()
         ));
     }
+    report.schema_issues = issues;
 
     // Extract the photos array from the JSON
+    let no_photos = Vec::new();
     let photos_raw = match data.get("photos") {
         Some(photos) => match photos.as_array() {
             Some(photos_array) => photos_array,
             None => {
                 // Log warning but don't fail - photos field exists but is not an array
                 log_warning("'photos' field is not an array");
-                &Vec::new()
+                &no_photos
             }
         },
         None => {
             // Log warning but don't fail - missing photos field
             log_warning("Missing 'photos' field in API response");
-            &Vec::new()
+            &no_photos
         }
     };
 
@@ -175,24 +789,44 @@ pub async fn get_api_response(
             Ok(parsed) => photos.push(parsed),
             Err(e) => {
                 // Log warning with more context but don't fail the entire request
-                log_warning(&format!("Failed to parse photo at index {}: {}", index, e));
+                logging::log_warn_repeated!(
+                    logging::API,
+                    "Photos that failed to parse",
+                    "Failed to parse photo at index {}: {}",
+                    index,
+                    e
+                );
+                report.skipped_photos.push((index, e.to_string()));
+                report.unparsed_items.push(models::UnparsedItem {
+                    index,
+                    raw: photo.clone(),
+                    error: e.to_string(),
+                });
+                if report.captured_fixtures.len() < diagnostics::MAX_CAPTURES_PER_RESPONSE {
+                    if let Some(path) =
+                        diagnostics::capture_parse_failure("photo", index, &e.to_string(), photo)
+                    {
+                        report.captured_fixtures.push(path);
+                    }
+                }
             }
         }
     }
 
     // Extract the metadata fields from the JSON with better error handling
     // streamName is considered required for a valid album
-    let stream_name = get_string_field(
-        &data,
-        "streamName",
-        "Unknown Album",
-        FieldSeverity::Required,
-    )?;
+    let stream_name =
+        get_string_field(data, "streamName", "Unknown Album", FieldSeverity::Required)?;
     // User info is helpful but not critical
-    let user_first_name = get_string_field(&data, "userFirstName", "", FieldSeverity::Optional)?;
-    let user_last_name = get_string_field(&data, "userLastName", "", FieldSeverity::Optional)?;
+    let user_first_name = get_string_field(data, "userFirstName", "", FieldSeverity::Optional)?;
+    let user_last_name = get_string_field(data, "userLastName", "", FieldSeverity::Optional)?;
     // streamCtag is important for API contract but we can continue without it
-    let stream_ctag = get_string_field(&data, "streamCtag", "", FieldSeverity::Optional)?;
+    let stream_ctag = StreamCtag::new(get_string_field(
+        data,
+        "streamCtag",
+        "",
+        FieldSeverity::Optional,
+    )?);
     // Instead of manually extracting itemsReturned, we'll rely on serde's type conversion
     // in models.rs which handles both string and number formats safely
     let api_response: models::ApiResponse = match serde_json::from_value(data.clone()) {
@@ -220,7 +854,7 @@ pub async fn get_api_response(
     let locations = match data.get("locations") {
         Some(value) => value.clone(),
         None => {
-            log_warning("Missing 'locations' field");
+            log_schema_warning("Missing 'locations' field");
             serde_json::Value::Null
         }
     };
@@ -232,9 +866,79 @@ pub async fn get_api_response(
         stream_ctag,
         items_returned,
         locations,
+        sharing: models::SharingInfo::from_response(data),
     };
 
-    Ok((photos, metadata))
+    Ok(WebstreamResponse {
+        photos,
+        metadata,
+        report,
+    })
+}
+
+/// Extracts asset URLs from an already-fetched webasseturls response body
+///
+/// This is the pure parsing half of [`get_asset_urls`]. It validates the
+/// response and builds full URLs without making any requests.
+///
+/// # Arguments
+///
+/// * `data` - The JSON body of a webasseturls response
+///
+/// # Returns
+///
+/// A HashMap mapping from checksum to full URL
+pub fn parse_webasseturls_response(
+    data: &serde_json::Value,
+) -> Result<HashMap<String, String>, ApiError> {
+    parse_webasseturls_response_with_policy(data, &UrlPolicy::default())
+}
+
+/// Extracts asset URLs from a webasseturls response body, validating them against a policy
+///
+/// Every URL is parsed and checked with [`UrlPolicy::asset_url`]; a single
+/// rejected URL fails the whole response with [`ApiError::UrlRejected`], since
+/// it indicates a malformed or untrustworthy response.
+///
+/// # Arguments
+///
+/// * `data` - The JSON body of a webasseturls response
+/// * `policy` - Rules the asset URLs must satisfy
+///
+/// # Returns
+///
+/// A HashMap mapping from checksum to full URL
+pub fn parse_webasseturls_response_with_policy(
+    data: &serde_json::Value,
+    policy: &UrlPolicy,
+) -> Result<HashMap<String, String>, ApiError> {
+    parse_webasseturls_response_with_report(data, policy).map(|response| response.urls)
+}
+
+/// Extracts asset URLs from a webasseturls response body, together with a [`ParseReport`]
+///
+/// This behaves like [`parse_webasseturls_response_with_policy`], but also
+/// returns the schema issues found and the assets that were left out.
+///
+/// # Arguments
+///
+/// * `data` - The JSON body of a webasseturls response
+/// * `policy` - Rules the asset URLs must satisfy
+///
+/// # Returns
+///
+/// The URLs by checksum and the report, see [`AssetUrlsResponse`]
+pub fn parse_webasseturls_response_with_report(
+    data: &serde_json::Value,
+    policy: &UrlPolicy,
+) -> Result<AssetUrlsResponse, ApiError> {
+    let _repeats = logging::limit_repeats();
+    let mut report = ParseReport {
+        schema_issues: validate_webasseturls_response(data)?,
+        ..Default::default()
+    };
+    let urls = process_webasseturls_response(data, policy, &mut report)?;
+    Ok(AssetUrlsResponse { urls, report })
 }
 
 /// Severity level for field validation
@@ -316,7 +1020,7 @@ impl JsonFieldExtractor<String> for StringFieldExtractor {
                 field_name
             ))),
             FieldSeverity::Optional | FieldSeverity::Lenient => {
-                log_warning(&err_msg);
+                log_schema_warning(&err_msg);
                 Ok(self.default_value())
             }
         }
@@ -331,7 +1035,7 @@ impl JsonFieldExtractor<String> for StringFieldExtractor {
         match severity {
             FieldSeverity::Required => Err(ApiError::MissingFieldError(field_name.to_string())),
             FieldSeverity::Optional | FieldSeverity::Lenient => {
-                log_warning(&err_msg);
+                log_schema_warning(&err_msg);
                 Ok(self.default_value())
             }
         }
@@ -370,10 +1074,191 @@ fn get_string_field(
 
 /// Helper function for logging warnings
 ///
-/// Uses the `log` crate to log warnings which can be captured by any logger
-/// implementation the user configures.
+/// Uses the `log` crate to log warnings under the [`logging::API`] target,
+/// which can be captured by any logger implementation the user configures.
 fn log_warning(message: &str) {
-    warn!("{}", message);
+    logging::log_warn!(logging::API, "{}", message);
+}
+
+/// Helper function for logging schema drift warnings under [`logging::SCHEMA`]
+fn log_schema_warning(message: &str) {
+    logging::log_warn!(logging::SCHEMA, "{}", message);
+}
+
+/// How serious a schema validation issue is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IssueSeverity {
+    /// Informational: the field is expected but nothing depends on it
+    Info,
+    /// Some data is lost, e.g. an individual photo or asset is skipped
+    Warning,
+    /// The response cannot be used as a whole
+    Critical,
+}
+
+/// A schema validation issue with its severity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaIssue {
+    /// Path of the field, e.g. `photos[3].derivatives`
+    pub field: String,
+    /// How the field failed validation
+    pub failure: ValidationFailure,
+    /// How serious the issue is
+    pub severity: IssueSeverity,
+}
+
+/// Returns the severity of an issue with a field of the given schema
+fn issue_severity(schema_name: &str, field: &str) -> IssueSeverity {
+    match (schema_name, field) {
+        ("webstream", "streamName") | ("webstream", "photos") | ("webasseturls", "items") => {
+            IssueSeverity::Critical
+        }
+        ("webstream", "streamCtag") => IssueSeverity::Info,
+        _ => IssueSeverity::Warning,
+    }
+}
+
+/// Validates an API response against the expected schema, classifying each issue
+///
+/// This is the structured counterpart of [`validate_api_schema`]: every issue
+/// carries an [`IssueSeverity`] so callers can act on specific drift.
+///
+/// # Arguments
+///
+/// * `data` - The JSON data to validate
+/// * `schema_name` - The name of the schema to validate against (`"webstream"` or `"webasseturls"`)
+///
+/// # Returns
+///
+/// The issues found (empty if valid)
+pub fn check_api_schema(data: &serde_json::Value, schema_name: &str) -> Vec<SchemaIssue> {
+    validate_api_schema(data, schema_name)
+        .into_iter()
+        .map(|(field, failure)| SchemaIssue {
+            severity: issue_severity(schema_name, &field),
+            field,
+            failure,
+        })
+        .collect()
+}
+
+/// Schema validation issues that share a field pattern and failure
+///
+/// Per-item paths are generalized so that one systematic quirk across a large
+/// album is reported once: `photos[17].derivatives` becomes
+/// `photos[].derivatives` and `items.<guid>.url_path` becomes
+/// `items.*.url_path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaIssueGroup {
+    /// The generalized field path
+    pub field_pattern: String,
+    /// How the field failed validation
+    pub failure: ValidationFailure,
+    /// Severity of the issues in the group
+    pub severity: IssueSeverity,
+    /// Number of issues in the group
+    pub count: usize,
+    /// The concrete path of the first issue in the group
+    pub example: String,
+}
+
+/// Generalizes per-item indices and keys in a field path
+fn field_pattern(path: &str) -> String {
+    let mut pattern = String::with_capacity(path.len());
+    let mut in_index = false;
+    for c in path.chars() {
+        match c {
+            '[' => {
+                in_index = true;
+                pattern.push_str("[]");
+            }
+            ']' => in_index = false,
+            _ if in_index => {}
+            _ => pattern.push(c),
+        }
+    }
+    // webasseturls items are keyed by checksum
+    if let Some(rest) = pattern.strip_prefix("items.") {
+        if let Some((_, field)) = rest.split_once('.') {
+            return format!("items.*.{}", field);
+        }
+    }
+    pattern
+}
+
+/// Groups schema validation issues by field pattern and failure
+///
+/// # Arguments
+///
+/// * `issues` - Issues as returned by [`check_api_schema`]
+///
+/// # Returns
+///
+/// One group per distinct (pattern, failure) pair, in order of first occurrence
+pub fn group_schema_issues(issues: &[SchemaIssue]) -> Vec<SchemaIssueGroup> {
+    let mut groups: Vec<SchemaIssueGroup> = Vec::new();
+    for issue in issues {
+        let pattern = field_pattern(&issue.field);
+        match groups
+            .iter_mut()
+            .find(|g| g.field_pattern == pattern && g.failure == issue.failure)
+        {
+            Some(group) => {
+                group.count += 1;
+                group.severity = group.severity.max(issue.severity);
+            }
+            None => groups.push(SchemaIssueGroup {
+                field_pattern: pattern,
+                failure: issue.failure.clone(),
+                severity: issue.severity,
+                count: 1,
+                example: issue.field.clone(),
+            }),
+        }
+    }
+    groups
+}
+
+/// Logs schema validation issues, one line per group of identical issues
+///
+/// Critical issues are logged as errors, warnings as warnings and
+/// informational issues at info level.
+fn log_schema_issues(issues: &[SchemaIssue]) {
+    for group in group_schema_issues(issues) {
+        let field = if group.count == 1 {
+            &group.example
+        } else {
+            &group.field_pattern
+        };
+        let problem = match &group.failure {
+            ValidationFailure::Missing => "is missing".to_string(),
+            ValidationFailure::WrongType => "has wrong type".to_string(),
+            ValidationFailure::InvalidValue(msg) => format!("has invalid value: {}", msg),
+        };
+        let level = match group.severity {
+            IssueSeverity::Info => log::Level::Info,
+            IssueSeverity::Warning => log::Level::Warn,
+            IssueSeverity::Critical => log::Level::Error,
+        };
+        if group.count == 1 {
+            logging::log_at!(
+                logging::SCHEMA,
+                level,
+                "Schema validation: Field '{}' {}",
+                field,
+                problem
+            );
+        } else {
+            logging::log_at!(
+                logging::SCHEMA,
+                level,
+                "Schema validation: Field '{}' {} in {} items",
+                field,
+                problem,
+                group.count
+            );
+        }
+    }
 }
 
 /// Validates the API response against expected schema
@@ -508,7 +1393,11 @@ fn check_field_exists_with_prefix(
 }
 
 /// Backoff strategy for retries
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// The jittered strategies follow the AWS architecture blog post
+/// "Exponential Backoff And Jitter". All delays are capped at
+/// [`RetryConfig::max_delay_ms`].
+#[derive(Debug, Clone, Copy)]
 pub enum BackoffStrategy {
     /// No backoff - constant delay between retries
     Constant,
@@ -518,8 +1407,31 @@ pub enum BackoffStrategy {
     Exponential,
     /// Exponential backoff with full jitter - random delay between 0 and exponential value
     ExponentialWithJitter,
+    /// Exponential backoff with equal jitter - half the exponential value plus a random part up to the other half
+    ///
+    /// Keeps a minimum delay while still spreading out retries of concurrent clients.
+    EqualJitter,
+    /// Decorrelated jitter - random delay between the base delay and three times the previous delay
+    ///
+    /// Delays grow with each retry but, unlike the other strategies, don't depend on the attempt number.
+    DecorrelatedJitter,
+    /// Delay computed by a custom function of the retry attempt (starting at 1) and the previous delay in milliseconds
+    ///
+    /// The previous delay is 0 before the first retry.
+    Custom(fn(u64, u64) -> u64),
 }
 
+impl PartialEq for BackoffStrategy {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (BackoffStrategy::Custom(a), BackoffStrategy::Custom(b)) => *a as usize == *b as usize,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+impl Eq for BackoffStrategy {}
+
 /// Statistics about retry attempts
 #[derive(Debug, Clone, Default)]
 pub struct RetryStats {
@@ -579,6 +1491,80 @@ pub struct RetryConfig {
     pub retryable_status_codes: Vec<u16>,
     /// Status codes that should be treated as permanent failures
     pub permanent_failure_status_codes: Vec<u16>,
+    /// Callback overriding the built-in decision whether an error is retried
+    ///
+    /// Called with the error and the number of attempts made so far (1 after
+    /// the first failure). It is not consulted once `max_retries` is reached.
+    pub retry_predicate: Option<fn(&ApiError, u64) -> RetryDecision>,
+}
+
+/// Requests that can be retried with their own [`RetryConfig`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    /// The webstream endpoint returning the album's metadata and photos
+    Webstream,
+    /// The webasseturls endpoint resolving asset URLs
+    WebAssetUrls,
+    /// Downloads of assets from the CDN
+    AssetDownload,
+}
+
+/// Retry configurations per endpoint, with a default for the others
+///
+/// ```
+/// use icloud_album_rs::api::{Endpoint, RetryConfig, RetryPolicies};
+///
+/// // Few fast retries for the album, more patience for asset downloads
+/// let policies = RetryPolicies::default()
+///     .with_endpoint(
+///         Endpoint::Webstream,
+///         RetryConfig { max_retries: 2, base_delay_ms: 100, ..Default::default() },
+///     )
+///     .with_endpoint(
+///         Endpoint::AssetDownload,
+///         RetryConfig { max_retries: 6, base_delay_ms: 1000, ..Default::default() },
+///     );
+/// assert_eq!(policies.for_endpoint(Endpoint::WebAssetUrls).max_retries, 3);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicies {
+    /// Configuration for endpoints without their own
+    pub default: RetryConfig,
+    /// Configurations of individual endpoints
+    pub endpoints: HashMap<Endpoint, RetryConfig>,
+}
+
+impl RetryPolicies {
+    /// Create policies using `default` for every endpoint
+    pub fn new(default: RetryConfig) -> Self {
+        Self {
+            default,
+            endpoints: HashMap::new(),
+        }
+    }
+
+    /// Use `config` for `endpoint`
+    #[must_use]
+    pub fn with_endpoint(mut self, endpoint: Endpoint, config: RetryConfig) -> Self {
+        self.endpoints.insert(endpoint, config);
+        self
+    }
+
+    /// Returns the configuration for `endpoint`, falling back to the default
+    pub fn for_endpoint(&self, endpoint: Endpoint) -> &RetryConfig {
+        self.endpoints.get(&endpoint).unwrap_or(&self.default)
+    }
+}
+
+/// Decision of a [`RetryConfig::retry_predicate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryDecision {
+    /// Retry the operation
+    Retry,
+    /// Fail with the error without retrying
+    Stop,
+    /// Use the built-in classification of the error
+    Default,
 }
 
 impl Default for RetryConfig {
@@ -591,33 +1577,90 @@ impl Default for RetryConfig {
             track_stats: false,
             retryable_status_codes: vec![408, 429, 500, 502, 503, 504], // Common transient errors
             permanent_failure_status_codes: vec![400, 401, 403, 404],   // Common permanent errors
+            retry_predicate: None,
         }
     }
 }
 
-/// Calculate delay for next retry based on retry configuration
-fn calculate_retry_delay(config: &RetryConfig, attempt: u64) -> u64 {
-    match config.backoff_strategy {
-        BackoffStrategy::Constant => config.base_delay_ms,
-
-        BackoffStrategy::Linear => {
-            let delay = config.base_delay_ms * attempt;
-            std::cmp::min(delay, config.max_delay_ms)
-        }
-
-        BackoffStrategy::Exponential => {
-            let delay = config.base_delay_ms * (1 << attempt.min(30)); // Prevent overflow with min(30)
-            std::cmp::min(delay, config.max_delay_ms)
-        }
-
-        BackoffStrategy::ExponentialWithJitter => {
-            let max_delay = config.base_delay_ms * (1 << attempt.min(30)); // Prevent overflow
-            let capped_delay = std::cmp::min(max_delay, config.max_delay_ms);
+impl RetryConfig {
+    /// Calculates the delay before a retry
+    ///
+    /// Every retried operation of the crate waits this long before retry
+    /// `attempt`, so the configured [`BackoffStrategy`] applies uniformly.
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - The retry attempt, starting at 1
+    /// * `previous_delay_ms` - The delay before the previous retry, or 0 before the first one
+    ///
+    /// # Returns
+    ///
+    /// The delay in milliseconds, at most [`RetryConfig::max_delay_ms`]
+    pub fn retry_delay_ms(&self, attempt: u64, previous_delay_ms: u64) -> u64 {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        // Prevent overflow with min(30)
+        let exponential = self
+            .base_delay_ms
+            .saturating_mul(1 << attempt.min(30))
+            .min(self.max_delay_ms);
+
+        let delay = match self.backoff_strategy {
+            BackoffStrategy::Constant => self.base_delay_ms,
+            BackoffStrategy::Linear => self.base_delay_ms.saturating_mul(attempt),
+            BackoffStrategy::Exponential => exponential,
+            // Random delay between 0 and the exponential delay
+            BackoffStrategy::ExponentialWithJitter => rng.gen_range(0..=exponential),
+            BackoffStrategy::EqualJitter => {
+                let half = exponential / 2;
+                half + rng.gen_range(0..=exponential - half)
+            }
+            BackoffStrategy::DecorrelatedJitter => {
+                let previous = previous_delay_ms.max(self.base_delay_ms);
+                rng.gen_range(self.base_delay_ms..=previous.saturating_mul(3))
+            }
+            BackoffStrategy::Custom(delay) => delay(attempt, previous_delay_ms),
+        };
+        delay.min(self.max_delay_ms)
+    }
 
-            // Generate random delay between 0 and capped_delay
-            use rand::Rng;
-            let mut rng = rand::thread_rng();
-            rng.gen_range(0..=capped_delay)
+    /// Decides whether an operation that failed with `error` is retried
+    ///
+    /// The [`RetryConfig::retry_predicate`] decides if set; otherwise, or if
+    /// it returns [`RetryDecision::Default`], network errors and retryable
+    /// status codes are retried, while parse errors, missing fields and
+    /// rejected URLs are not.
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - The error the operation failed with
+    /// * `attempt` - The number of attempts made so far, starting at 1
+    ///
+    /// # Returns
+    ///
+    /// True if the operation should be retried
+    pub fn should_retry(&self, error: &ApiError, attempt: u64) -> bool {
+        match self
+            .retry_predicate
+            .map(|predicate| predicate(error, attempt))
+        {
+            Some(RetryDecision::Retry) => true,
+            Some(RetryDecision::Stop) => false,
+            Some(RetryDecision::Default) | None => match error {
+                ApiError::NetworkError(_) => true, // Network errors are generally transient
+                ApiError::RequestError {
+                    status: Some(status_code),
+                    ..
+                } => should_retry_status(self, *status_code),
+                ApiError::RequestError { status: None, .. } => {
+                    true // If no status code available, retry by default
+                }
+                ApiError::JsonParseError(_) => false, // JSON parse errors are unlikely to be resolved by retry
+                ApiError::MissingFieldError(_) => false, // Missing fields won't appear on retry
+                ApiError::UrlRejected { .. } => false, // The same URLs would be returned again
+                ApiError::AlbumNotFound(_) => false,  // Deleted albums don't come back
+                _ => true,                            // Default to retry for other error types
+            },
         }
     }
 }
@@ -673,6 +1716,39 @@ pub async fn get_asset_urls_with_config(
     base_url: &str,
     photo_guids: &[String],
     retry_config: RetryConfig,
+) -> Result<HashMap<String, String>, ApiError> {
+    get_asset_urls_with_policy(
+        client,
+        base_url,
+        photo_guids,
+        retry_config,
+        &UrlPolicy::default(),
+    )
+    .await
+}
+
+/// Fetches URLs for photo assets, validating them against a custom [`UrlPolicy`]
+///
+/// This behaves like [`get_asset_urls_with_config`], but checks the returned
+/// URLs with `policy` instead of the default Apple-hosts-over-https policy.
+///
+/// # Arguments
+///
+/// * `client` - A reqwest HTTP client
+/// * `base_url` - The base URL for API requests
+/// * `photo_guids` - A slice of photo GUIDs to fetch URLs for
+/// * `retry_config` - Configuration for retry behavior
+/// * `policy` - Rules the returned asset URLs must satisfy
+///
+/// # Returns
+///
+/// A HashMap mapping from photo GUID to its full URL
+pub async fn get_asset_urls_with_policy(
+    client: &Client,
+    base_url: &str,
+    photo_guids: &[String],
+    retry_config: RetryConfig,
+    policy: &UrlPolicy,
 ) -> Result<HashMap<String, String>, ApiError> {
     // Early exit if there are no photo GUIDs
     if photo_guids.is_empty() {
@@ -694,10 +1770,12 @@ pub async fn get_asset_urls_with_config(
     };
 
     // Execute the HTTP request with retries
+    let last_headers = LastHeaders::default();
     let result = execute_with_retry(
         || async {
             // Make the POST request
-            let resp = client.post(&url).json(&payload).send().await?;
+            let resp = crate::har::send(api_request(client.post(&url).json(&payload))).await?;
+            last_headers.record(&resp);
 
             // Special case: handle 400 Bad Request differently for this endpoint
             if resp.status().as_u16() == 400 {
@@ -712,11 +1790,9 @@ pub async fn get_asset_urls_with_config(
                 });
             }
             // Parse the response as JSON
-            let data: serde_json::Value = resp.json().await?;
-            // Validate the API response against expected schema
-            validate_webasseturls_response(&data)?;
-            // Process the response and extract URLs
-            process_webasseturls_response(&data)
+            let data = api_json(resp).await?;
+            // Validate the response and extract URLs
+            parse_webasseturls_response_with_policy(&data, policy)
         },
         &retry_config,
         stats.as_mut(),
@@ -727,7 +1803,7 @@ pub async fn get_asset_urls_with_config(
         if stats.attempts > 0 {
             log_warning(&format!(
                 "Request to {} required {} retries over {}ms{}",
-                url,
+                Redacted(&url),
                 stats.attempts,
                 stats.total_delay_ms,
                 if stats.succeeded {
@@ -739,37 +1815,27 @@ pub async fn get_asset_urls_with_config(
         }
     }
 
-    result
+    result.map_err(|e| {
+        e.with_context(
+            ErrorContext::new("fetch asset URLs")
+                .with_endpoint(&url)
+                .with_response_headers(last_headers.take()),
+        )
+    })
 }
 
 /// Validate the API response for webasseturls endpoint
-fn validate_webasseturls_response(data: &serde_json::Value) -> Result<(), ApiError> {
+fn validate_webasseturls_response(data: &serde_json::Value) -> Result<Vec<SchemaIssue>, ApiError> {
     // Validate the API response against expected schema
-    let issues = validate_api_schema(data, "webasseturls");
+    let issues = check_api_schema(data, "webasseturls");
     if !issues.is_empty() {
-        // Log all validation issues as warnings
-        for (field, failure) in &issues {
-            match failure {
-                ValidationFailure::Missing => {
-                    log_warning(&format!("Schema validation: Missing field '{}'", field));
-                }
-                ValidationFailure::WrongType => {
-                    log_warning(&format!(
-                        "Schema validation: Field '{}' has wrong type",
-                        field
-                    ));
-                }
-                ValidationFailure::InvalidValue(msg) => {
-                    log_warning(&format!(
-                        "Schema validation: Field '{}' has invalid value: {}",
-                        field, msg
-                    ));
-                }
-            }
-        }
+        // Log validation issues by severity, one line per distinct issue
+        log_schema_issues(&issues);
 
         // Critical schema issues should be treated as errors
-        let critical_issues = issues.iter().any(|(field, _)| field == "items");
+        let critical_issues = issues
+            .iter()
+            .any(|issue| issue.severity == IssueSeverity::Critical);
         if critical_issues {
             return Err(ApiError::JsonParseError(format!(
                 "Critical schema validation issues: {}",
@@ -778,18 +1844,20 @@ fn validate_webasseturls_response(data: &serde_json::Value) -> Result<(), ApiErr
         }
 
         // Non-critical issues are logged but processing continues
-        log_warning(&format!(
+        log_schema_warning(&format!(
             "API response has {} schema validation issues",
             issues.len()
         ));
     }
 
-    Ok(())
+    Ok(issues)
 }
 
 /// Process the webasseturls response to extract URLs
 fn process_webasseturls_response(
     data: &serde_json::Value,
+    policy: &UrlPolicy,
+    report: &mut ParseReport,
 ) -> Result<HashMap<String, String>, ApiError> {
     let mut results = HashMap::new();
 
@@ -825,16 +1893,40 @@ fn process_webasseturls_response(
             Some(loc) => match loc.as_str() {
                 Some(s) if !s.is_empty() => s,
                 Some(_) => {
-                    log_warning(&format!("Empty url_location for guid {}", guid));
+                    logging::log_warn_repeated!(
+                        logging::SCHEMA,
+                        "Assets with an empty url_location",
+                        "Empty url_location for guid {}",
+                        guid
+                    );
+                    report
+                        .skipped_assets
+                        .push((guid.to_string(), "empty url_location".to_string()));
                     continue;
                 }
                 None => {
-                    log_warning(&format!("url_location is not a string for guid {}", guid));
+                    logging::log_warn_repeated!(
+                        logging::SCHEMA,
+                        "Assets whose url_location is not a string",
+                        "url_location is not a string for guid {}",
+                        guid
+                    );
+                    report
+                        .skipped_assets
+                        .push((guid.to_string(), "url_location is not a string".to_string()));
                     continue;
                 }
             },
             None => {
-                log_warning(&format!("Missing url_location for guid {}", guid));
+                logging::log_warn_repeated!(
+                    logging::SCHEMA,
+                    "Assets without a url_location",
+                    "Missing url_location for guid {}",
+                    guid
+                );
+                report
+                    .skipped_assets
+                    .push((guid.to_string(), "missing url_location".to_string()));
                 continue;
             }
         };
@@ -844,23 +1936,53 @@ fn process_webasseturls_response(
             Some(path) => match path.as_str() {
                 Some(s) if !s.is_empty() => s,
                 Some(_) => {
-                    log_warning(&format!("Empty url_path for guid {}", guid));
+                    logging::log_warn_repeated!(
+                        logging::SCHEMA,
+                        "Assets with an empty url_path",
+                        "Empty url_path for guid {}",
+                        guid
+                    );
+                    report
+                        .skipped_assets
+                        .push((guid.to_string(), "empty url_path".to_string()));
                     continue;
                 }
                 None => {
-                    log_warning(&format!("url_path is not a string for guid {}", guid));
+                    logging::log_warn_repeated!(
+                        logging::SCHEMA,
+                        "Assets whose url_path is not a string",
+                        "url_path is not a string for guid {}",
+                        guid
+                    );
+                    report
+                        .skipped_assets
+                        .push((guid.to_string(), "url_path is not a string".to_string()));
                     continue;
                 }
             },
             None => {
-                log_warning(&format!("Missing url_path for guid {}", guid));
+                logging::log_warn_repeated!(
+                    logging::SCHEMA,
+                    "Assets without a url_path",
+                    "Missing url_path for guid {}",
+                    guid
+                );
+                report
+                    .skipped_assets
+                    .push((guid.to_string(), "missing url_path".to_string()));
                 continue;
             }
         };
 
-        // Build the full URL and add to results
-        let full_url = format!("https://{}{}", url_location, url_path);
-        results.insert(guid.to_string(), full_url);
+        // Build and validate the full URL, then add it to results
+        let full_url =
+            policy
+                .asset_url(url_location, url_path)
+                .map_err(|reason| ApiError::UrlRejected {
+                    guid: guid.to_string(),
+                    reason,
+                })?;
+        results.insert(guid.to_string(), full_url.to_string());
     }
 
     Ok(results)
@@ -887,6 +2009,7 @@ where
     Fut: std::future::Future<Output = Result<T, ApiError>>,
 {
     let mut attempt: u64 = 0;
+    let mut delay_ms: u64 = 0;
     let mut last_error = None;
 
     loop {
@@ -898,12 +2021,13 @@ where
         // Only sleep before retries (not before first attempt)
         if attempt > 0 {
             // Calculate delay for this retry attempt
-            let delay_ms = calculate_retry_delay(config, attempt);
+            delay_ms = config.retry_delay_ms(attempt, delay_ms);
 
             // Record the attempt if tracking stats
             if let Some(stats_ref) = stats.as_mut() {
                 stats_ref.record_attempt(delay_ms);
             }
+            crate::metrics::record(|metrics| metrics.on_retry());
 
             // Sleep before retry
             tokio::time::sleep(tokio::time::Duration::from_millis(delay_ms)).await;
@@ -921,19 +2045,7 @@ where
             }
             Err(err) => {
                 // Determine if we should retry based on the error
-                let should_retry = match &err {
-                    ApiError::NetworkError(_) => true, // Network errors are generally transient
-                    ApiError::RequestError {
-                        status: Some(status_code),
-                        ..
-                    } => should_retry_status(config, *status_code),
-                    ApiError::RequestError { status: None, .. } => {
-                        true // If no status code available, retry by default
-                    }
-                    ApiError::JsonParseError(_) => false, // JSON parse errors are unlikely to be resolved by retry
-                    ApiError::MissingFieldError(_) => false, // Missing fields won't appear on retry
-                    _ => true,                            // Default to retry for other error types
-                };
+                let should_retry = config.should_retry(&err, attempt + 1);
 
                 if should_retry {
                     // Save the error and increment attempt counter
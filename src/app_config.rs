@@ -0,0 +1,512 @@
+//! Configuration files shared by applications built on the crate.
+//!
+//! [`AppConfig`] describes everything a mirroring tool or daemon needs: the
+//! albums it follows, how files are named, retries, rate limits and the sinks
+//! album changes are reported to. Applications embed it in their own config,
+//! or load it on its own:
+//!
+//! ```toml
+//! [[albums]]
+//! token = "B0z5qAGN1JIFd3y"
+//! name = "Family"
+//! output_dir = "family"
+//!
+//! [output]
+//! dir = "/srv/photos"
+//! markdown_captions = true
+//! naming = { thumb = "{base}-small.{ext}", original = "{base}.{ext}" }
+//!
+//! [retry]
+//! max_retries = 5
+//!
+//! [rate_limits]
+//! concurrency = 4
+//!
+//! [[sinks]]
+//! type = "jsonl"
+//! path = "/var/log/albums.jsonl"
+//! ```
+//!
+//! JSON is always supported; TOML needs the `toml-config` feature and YAML the
+//! `yaml-config` feature. Unknown keys are rejected, and every error names the
+//! offending key, e.g. `albums[1].interval_secs`.
+//!
+//! The file is read synchronously, typically once at startup.
+//! [`AppConfig::watch`] follows later edits to its albums.
+
+use crate::album_config::{AlbumsConfig, ConfigError, ConfigWatcher, FollowedAlbum};
+use crate::api::RetryConfig;
+use crate::derivatives::SizeClass;
+use crate::download::DownloadOptions;
+use crate::sinks::SmtpTls;
+use crate::throttle::{AimdConfig, Concurrency};
+use crate::watch::EventSink;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Format of a config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    /// JSON
+    Json,
+    /// TOML, with the `toml-config` feature
+    Toml,
+    /// YAML, with the `yaml-config` feature
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Returns the format of a file from its extension
+    ///
+    /// # Returns
+    ///
+    /// The format, or `None` for an unknown extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(ConfigFormat::Json),
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            _ => None,
+        }
+    }
+}
+
+/// Error returned when a config file could not be loaded
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum AppConfigError {
+    /// The file could not be read
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The file's extension is unknown or its format not compiled in
+    #[error("Unsupported config format: {0}")]
+    UnsupportedFormat(String),
+    /// The file could not be parsed
+    #[error("Invalid config at `{key}`: {message}")]
+    Parse {
+        /// Path of the offending key, e.g. `albums[0].token`, empty for the whole file
+        key: String,
+        /// What is wrong
+        message: String,
+    },
+    /// The file parsed but a value is unusable
+    #[error("Invalid config at `{key}`: {message}")]
+    Invalid {
+        /// Path of the offending key, e.g. `albums[0].token`
+        key: String,
+        /// What is wrong
+        message: String,
+    },
+}
+
+impl AppConfigError {
+    /// Returns the path of the offending key, if the error concerns one
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            AppConfigError::Parse { key, .. } | AppConfigError::Invalid { key, .. } => Some(key),
+            _ => None,
+        }
+    }
+}
+
+/// Creates an [`AppConfigError::Invalid`]
+fn invalid(key: impl Into<String>, message: impl Into<String>) -> AppConfigError {
+    AppConfigError::Invalid {
+        key: key.into(),
+        message: message.into(),
+    }
+}
+
+/// Configuration of an application following shared albums
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    /// The albums followed, see [`FollowedAlbum`]
+    pub albums: Vec<FollowedAlbum>,
+    /// Where and how files are written
+    pub output: OutputSettings,
+    /// How failed requests and downloads are retried
+    pub retry: RetrySettings,
+    /// How many requests run at once
+    pub rate_limits: RateLimitSettings,
+    /// Where album changes are reported
+    pub sinks: Vec<SinkSettings>,
+}
+
+/// Where and how files are written
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputSettings {
+    /// Directory relative album output directories are resolved against
+    pub dir: Option<PathBuf>,
+    /// File name templates by size class suffix, e.g. `thumb` or `original`
+    ///
+    /// See [`crate::download::DEFAULT_FILE_NAME_TEMPLATE`] for the placeholders.
+    pub naming: BTreeMap<String, String>,
+    /// Treat captions as Markdown, see [`crate::markdown`]
+    pub markdown_captions: bool,
+}
+
+/// How failed requests and downloads are retried; unset values keep their defaults
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetrySettings {
+    /// Maximum number of retries
+    pub max_retries: Option<u64>,
+    /// Base delay between retries in milliseconds
+    pub base_delay_ms: Option<u64>,
+    /// Maximum delay between retries in milliseconds
+    pub max_delay_ms: Option<u64>,
+}
+
+/// How many requests run at once
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitSettings {
+    /// Concurrent downloads per asset host, or the starting point with `adaptive`
+    pub concurrency: Option<usize>,
+    /// Adapt the concurrency to the server's responses, see [`crate::throttle`]
+    pub adaptive: bool,
+    /// Highest concurrency `adaptive` may reach
+    pub max_concurrency: Option<usize>,
+}
+
+/// A sink album changes are reported to, see [`crate::sinks`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SinkSettings {
+    /// Appends events to a JSON Lines file
+    Jsonl {
+        /// The file
+        path: PathBuf,
+    },
+    /// Emails events through an SMTP relay, with the `smtp` feature
+    Smtp {
+        /// Host of the relay
+        host: String,
+        /// Port of the relay
+        #[serde(default = "default_smtp_port")]
+        port: u16,
+        /// Sender address
+        from: String,
+        /// Recipient addresses
+        to: Vec<String>,
+        /// Send one email per event instead of one per poll
+        #[serde(default)]
+        per_event: bool,
+        /// How the connection is secured, STARTTLS when offered by default
+        #[serde(default)]
+        tls: SmtpTls,
+        /// User to authenticate as, together with `password`
+        #[serde(default)]
+        username: Option<String>,
+        /// Password of `username`
+        #[serde(default)]
+        password: Option<String>,
+    },
+    /// Posts new photos to a Telegram chat, with the `webhooks` feature
+    Telegram {
+        /// Token of the bot
+        bot_token: String,
+        /// Chat to post to
+        chat_id: String,
+    },
+    /// Posts new photos to a Discord channel, with the `webhooks` feature
+    Discord {
+        /// URL of the channel's webhook
+        webhook_url: String,
+    },
+}
+
+fn default_smtp_port() -> u16 {
+    25
+}
+
+impl SinkSettings {
+    /// Creates the sink
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Path of the sink in the config, used in errors
+    ///
+    /// # Returns
+    ///
+    /// The sink, or an error if its feature is not compiled in
+    pub fn build(&self, key: &str) -> Result<Box<dyn EventSink>, AppConfigError> {
+        match self {
+            SinkSettings::Jsonl { path } => Ok(Box::new(crate::sinks::JsonlSink::new(path))),
+            #[cfg(feature = "smtp")]
+            SinkSettings::Smtp {
+                host,
+                port,
+                from,
+                to,
+                per_event,
+                tls,
+                username,
+                password,
+            } => {
+                let to: Vec<&str> = to.iter().map(String::as_str).collect();
+                let mode = if *per_event {
+                    crate::sinks::EmailMode::PerEvent
+                } else {
+                    crate::sinks::EmailMode::Digest
+                };
+                let mut sink = crate::sinks::SmtpSink::new(host, *port, from, &to)
+                    .with_mode(mode)
+                    .with_tls(*tls);
+                if let (Some(username), Some(password)) = (username, password) {
+                    sink = sink.with_credentials(username, password);
+                }
+                Ok(Box::new(sink))
+            }
+            #[cfg(feature = "webhooks")]
+            SinkSettings::Telegram { bot_token, chat_id } => Ok(Box::new(
+                crate::sinks::TelegramSink::new(bot_token, chat_id),
+            )),
+            #[cfg(feature = "webhooks")]
+            SinkSettings::Discord { webhook_url } => {
+                Ok(Box::new(crate::sinks::DiscordSink::new(webhook_url)))
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(invalid(
+                key,
+                "this sink type needs a feature that is not compiled in",
+            )),
+        }
+    }
+}
+
+impl AppConfig {
+    /// Parses and checks a config file's contents
+    ///
+    /// # Arguments
+    ///
+    /// * `contents` - The file's contents
+    /// * `format` - The file's format
+    pub fn parse(contents: &str, format: ConfigFormat) -> Result<Self, AppConfigError> {
+        let config: AppConfig = match format {
+            ConfigFormat::Json => deserialize(&mut serde_json::Deserializer::from_str(contents))?,
+            #[cfg(feature = "toml-config")]
+            ConfigFormat::Toml => deserialize(toml::Deserializer::new(contents))?,
+            #[cfg(feature = "yaml-config")]
+            ConfigFormat::Yaml => deserialize(serde_yaml::Deserializer::from_str(contents))?,
+            #[allow(unreachable_patterns)]
+            _ => {
+                return Err(AppConfigError::UnsupportedFormat(format!(
+                    "{:?} support is not compiled in",
+                    format
+                )))
+            }
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads and parses a config file, choosing the format by extension
+    pub fn load(path: &Path) -> Result<Self, AppConfigError> {
+        let format = ConfigFormat::from_path(path)
+            .ok_or_else(|| AppConfigError::UnsupportedFormat(path.display().to_string()))?;
+        Self::parse(&std::fs::read_to_string(path)?, format)
+    }
+
+    /// Watches a config file for changes to its albums
+    ///
+    /// The file is parsed as by [`AppConfig::load`], and the albums compared
+    /// are those of [`AppConfig::albums_config`]. Edits to the other settings
+    /// are checked but not reported.
+    pub async fn watch(path: impl Into<PathBuf>) -> Result<ConfigWatcher, ConfigError> {
+        let path = path.into();
+        let format = ConfigFormat::from_path(&path)
+            .ok_or_else(|| AppConfigError::UnsupportedFormat(path.display().to_string()))?;
+        ConfigWatcher::open_with(path, move |contents| {
+            Ok(AppConfig::parse(contents, format)?.albums_config())
+        })
+        .await
+    }
+
+    /// Checks the values that parsed but can't be used
+    pub fn validate(&self) -> Result<(), AppConfigError> {
+        let mut tokens = HashSet::new();
+        for (index, album) in self.albums.iter().enumerate() {
+            if album.token.trim().is_empty() {
+                return Err(invalid(format!("albums[{}].token", index), "is empty"));
+            }
+            if !tokens.insert(album.token.as_str()) {
+                return Err(invalid(
+                    format!("albums[{}].token", index),
+                    "repeats the token of an earlier album",
+                ));
+            }
+            if album.interval_secs == 0 {
+                return Err(invalid(
+                    format!("albums[{}].interval_secs", index),
+                    "must not be zero",
+                ));
+            }
+        }
+
+        for (suffix, template) in &self.output.naming {
+            let key = format!("output.naming.{}", suffix);
+            if SizeClass::from_file_suffix(suffix).is_none() {
+                return Err(invalid(
+                    key,
+                    "unknown size class, expected thumb, medium, large, original, poster or video",
+                ));
+            }
+            if !template.contains("{base}") || template.contains(['/', '\\']) {
+                return Err(invalid(key, "must contain {base} and no path separator"));
+            }
+        }
+        let options = self.download_options();
+        let classes: Vec<SizeClass> = options.file_name_templates.keys().copied().collect();
+        crate::download::check_file_name_templates(&options, &classes)
+            .map_err(|message| invalid("output.naming", message))?;
+
+        if let (Some(base), Some(max)) = (self.retry.base_delay_ms, self.retry.max_delay_ms) {
+            if base > max {
+                return Err(invalid(
+                    "retry.base_delay_ms",
+                    "must not exceed retry.max_delay_ms",
+                ));
+            }
+        }
+
+        let limits = &self.rate_limits;
+        if limits.concurrency == Some(0) {
+            return Err(invalid("rate_limits.concurrency", "must not be zero"));
+        }
+        if limits.max_concurrency.is_some() && !limits.adaptive {
+            return Err(invalid(
+                "rate_limits.max_concurrency",
+                "only applies with rate_limits.adaptive",
+            ));
+        }
+        if let (Some(initial), Some(max)) = (limits.concurrency, limits.max_concurrency) {
+            if initial > max {
+                return Err(invalid(
+                    "rate_limits.concurrency",
+                    "must not exceed rate_limits.max_concurrency",
+                ));
+            }
+        }
+
+        for (index, sink) in self.sinks.iter().enumerate() {
+            match sink {
+                SinkSettings::Smtp { to, .. } if to.is_empty() => {
+                    return Err(invalid(format!("sinks[{}].to", index), "is empty"));
+                }
+                SinkSettings::Smtp {
+                    username, password, ..
+                } if username.is_some() != password.is_some() => {
+                    let (missing, given) = if username.is_some() {
+                        ("password", "username")
+                    } else {
+                        ("username", "password")
+                    };
+                    return Err(invalid(
+                        format!("sinks[{}].{}", index, missing),
+                        format!("is required with {}", given),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the albums, with output directories resolved against `output.dir`
+    pub fn albums_config(&self) -> AlbumsConfig {
+        let albums = self
+            .albums
+            .iter()
+            .map(|album| FollowedAlbum {
+                output_dir: match &self.output.dir {
+                    Some(dir) => dir.join(&album.output_dir),
+                    None => album.output_dir.clone(),
+                },
+                ..album.clone()
+            })
+            .collect();
+        AlbumsConfig { albums }
+    }
+
+    /// Returns the retry configuration, or `None` if no retry setting is configured
+    pub fn retry_config(&self) -> Option<RetryConfig> {
+        let retry = &self.retry;
+        if *retry == RetrySettings::default() {
+            return None;
+        }
+        let mut config = RetryConfig::default();
+        if let Some(max_retries) = retry.max_retries {
+            config.max_retries = max_retries;
+        }
+        if let Some(base_delay_ms) = retry.base_delay_ms {
+            config.base_delay_ms = base_delay_ms;
+        }
+        if let Some(max_delay_ms) = retry.max_delay_ms {
+            config.max_delay_ms = max_delay_ms;
+        }
+        Some(config)
+    }
+
+    /// Returns the download options described by the config
+    ///
+    /// Settings the config leaves out keep the defaults of
+    /// [`DownloadOptions::new`], including those from the environment.
+    pub fn download_options(&self) -> DownloadOptions {
+        let mut options =
+            DownloadOptions::new().with_markdown_captions(self.output.markdown_captions);
+        for (suffix, template) in &self.output.naming {
+            if let Some(size_class) = SizeClass::from_file_suffix(suffix) {
+                options = options.with_file_name_template(size_class, template.clone());
+            }
+        }
+        if let Some(retry) = self.retry_config() {
+            options = options.with_retry(retry);
+        }
+        let limits = &self.rate_limits;
+        if limits.adaptive {
+            let mut aimd = AimdConfig::default();
+            if let Some(max) = limits.max_concurrency {
+                aimd.max = max;
+            }
+            if let Some(initial) = limits.concurrency {
+                aimd.initial = initial;
+            }
+            aimd.initial = aimd.initial.min(aimd.max);
+            options = options.with_concurrency(Concurrency::Adaptive(aimd));
+        } else if let Some(concurrency) = limits.concurrency {
+            options = options.with_concurrency(Concurrency::Fixed(concurrency));
+        }
+        options
+    }
+
+    /// Creates the configured sinks, in config order
+    pub fn build_sinks(&self) -> Result<Vec<Box<dyn EventSink>>, AppConfigError> {
+        self.sinks
+            .iter()
+            .enumerate()
+            .map(|(index, sink)| sink.build(&format!("sinks[{}]", index)))
+            .collect()
+    }
+}
+
+/// Deserializes a config, reporting the path of the key that failed
+fn deserialize<'de, D>(deserializer: D) -> Result<AppConfig, AppConfigError>
+where
+    D: serde::Deserializer<'de>,
+    D::Error: std::fmt::Display,
+{
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let key = e.path().to_string();
+        AppConfigError::Parse {
+            key: if key == "." { String::new() } else { key },
+            message: e.into_inner().to_string(),
+        }
+    })
+}
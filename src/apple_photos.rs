@@ -0,0 +1,293 @@
+//! Export layout for re-importing albums into Apple Photos on macOS.
+//!
+//! Apple Photos takes dates from embedded EXIF data and falls back to the file
+//! modification time, and it has no sidecar format for captions. Captions of
+//! shared albums only exist in the API, so [`export_apple_photos`] writes one
+//! folder per album with:
+//!
+//! * the photos, with their modification time set to when they were taken
+//! * `captions.csv` with the caption, date, location and contributor of each file
+//! * `album.plist`, the same data as a property list for scripts and Shortcuts
+//! * `import.applescript`, which imports the folder into an album of the same
+//!   name and restores captions and dates; run it with `osascript`
+//!
+//! No `.AAE` files are written: those carry edits made in Photos, and shared
+//! albums only hold the rendered images.
+//!
+//! ```text
+//! <root>/<album>/<file>
+//! <root>/<album>/captions.csv
+//! <root>/<album>/album.plist
+//! <root>/<album>/import.applescript
+//! ```
+
+use crate::export::escape_xml;
+use crate::logging;
+use crate::models::{format_timestamp, CalendarDate, Image, Metadata};
+use crate::sync::SyncState;
+use crate::views::sanitize_component;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// File name of the caption list written by [`export_apple_photos`]
+pub const CAPTIONS_FILE: &str = "captions.csv";
+/// File name of the property list written by [`export_apple_photos`]
+pub const PLIST_FILE: &str = "album.plist";
+/// File name of the import script written by [`export_apple_photos`]
+pub const SCRIPT_FILE: &str = "import.applescript";
+
+/// A photo placed in the export folder
+struct ExportedPhoto<'a> {
+    photo: &'a Image,
+    path: PathBuf,
+    file_name: String,
+}
+
+impl ExportedPhoto<'_> {
+    fn caption(&self) -> Option<&str> {
+        self.photo
+            .caption
+            .as_deref()
+            .map(str::trim)
+            .filter(|caption| !caption.is_empty())
+    }
+}
+
+/// Quotes a CSV field if it contains separators, quotes or line breaks
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Builds `captions.csv`
+fn captions_csv(photos: &[ExportedPhoto]) -> String {
+    let mut csv = String::from("file,caption,date_taken,latitude,longitude,contributor\n");
+    for exported in photos {
+        let photo = exported.photo;
+        let (latitude, longitude) = match &photo.location {
+            Some(location) => (
+                location.latitude.to_string(),
+                location.longitude.to_string(),
+            ),
+            None => (String::new(), String::new()),
+        };
+        let fields = [
+            exported.file_name.clone(),
+            exported.caption().unwrap_or_default().to_string(),
+            photo
+                .timestamp_taken()
+                .map(format_timestamp)
+                .unwrap_or_default(),
+            latitude,
+            longitude,
+            photo.contributor_full_name.clone().unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Builds `album.plist`
+fn album_plist(album: &Metadata, photos: &[ExportedPhoto]) -> String {
+    let mut plist = String::new();
+    plist.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    plist.push_str("<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n");
+    plist.push_str("<plist version=\"1.0\">\n<dict>\n");
+    plist.push_str(&format!(
+        "  <key>AlbumName</key>\n  <string>{}</string>\n",
+        escape_xml(&album.stream_name)
+    ));
+    plist.push_str("  <key>Photos</key>\n  <array>\n");
+    for exported in photos {
+        let photo = exported.photo;
+        plist.push_str("    <dict>\n");
+        plist.push_str(&format!(
+            "      <key>FileName</key><string>{}</string>\n",
+            escape_xml(&exported.file_name)
+        ));
+        plist.push_str(&format!(
+            "      <key>PhotoGUID</key><string>{}</string>\n",
+            escape_xml(&photo.photo_guid)
+        ));
+        if let Some(caption) = exported.caption() {
+            plist.push_str(&format!(
+                "      <key>Caption</key><string>{}</string>\n",
+                escape_xml(caption)
+            ));
+        }
+        if let Some(taken) = photo.timestamp_taken() {
+            plist.push_str(&format!(
+                "      <key>DateTaken</key><date>{}</date>\n",
+                format_timestamp(taken)
+            ));
+        }
+        if let Some(location) = &photo.location {
+            plist.push_str(&format!(
+                "      <key>Latitude</key><real>{}</real>\n      <key>Longitude</key><real>{}</real>\n",
+                location.latitude, location.longitude
+            ));
+        }
+        if let Some(contributor) = &photo.contributor_full_name {
+            plist.push_str(&format!(
+                "      <key>Contributor</key><string>{}</string>\n",
+                escape_xml(contributor)
+            ));
+        }
+        plist.push_str("    </dict>\n");
+    }
+    plist.push_str("  </array>\n</dict>\n</plist>\n");
+    plist
+}
+
+/// Quotes a string for AppleScript
+fn applescript_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Returns the date and seconds since midnight a photo was taken, as recorded
+///
+/// AppleScript dates are in local time, so the wall-clock time of the camera
+/// is used rather than converting to UTC.
+fn recorded_time(photo: &Image) -> Option<(CalendarDate, u32)> {
+    photo.timestamp_taken()?;
+    let date_created = photo.date_created.as_deref()?;
+    let date = CalendarDate::parse(date_created)?;
+    let number = |range: std::ops::Range<usize>| date_created.get(range)?.parse::<u32>().ok();
+    Some((
+        date,
+        number(11..13)? * 3600 + number(14..16)? * 60 + number(17..19)?,
+    ))
+}
+
+/// Builds `import.applescript`
+fn import_script(album: &Metadata, photos: &[ExportedPhoto]) -> String {
+    let mut script = String::new();
+    script.push_str("tell application \"Photos\"\n");
+    script.push_str(&format!(
+        "  set targetAlbum to make new album named {}\n",
+        applescript_string(&album.stream_name)
+    ));
+    for exported in photos {
+        script.push_str(&format!(
+            "  set imported to import {{POSIX file {}}} into targetAlbum with skip check duplicates\n",
+            applescript_string(&exported.path.to_string_lossy())
+        ));
+        let caption = exported.caption();
+        let time = recorded_time(exported.photo);
+        if caption.is_none() && time.is_none() {
+            continue;
+        }
+        script.push_str("  if (count of imported) > 0 then\n");
+        script.push_str("    set importedItem to item 1 of imported\n");
+        if let Some(caption) = caption {
+            script.push_str(&format!(
+                "    set description of importedItem to {}\n",
+                applescript_string(caption)
+            ));
+        }
+        if let Some((date, seconds)) = time {
+            // Set the fields one by one, since parsing date strings depends on the locale
+            script.push_str("    set takenDate to current date\n");
+            script.push_str("    set day of takenDate to 1\n");
+            script.push_str(&format!("    set year of takenDate to {}\n", date.year));
+            script.push_str(&format!("    set month of takenDate to {}\n", date.month));
+            script.push_str(&format!("    set day of takenDate to {}\n", date.day));
+            script.push_str(&format!("    set time of takenDate to {}\n", seconds));
+            script.push_str("    set date of importedItem to takenDate\n");
+        }
+        script.push_str("  end if\n");
+    }
+    script.push_str("end tell\n");
+    script
+}
+
+/// Sets the modification time of an exported file to when the photo was taken
+async fn set_taken_time(path: &Path, photo: &Image) -> io::Result<()> {
+    let Some(taken) = photo.timestamp_taken() else {
+        return Ok(());
+    };
+    let time = if taken >= 0 {
+        UNIX_EPOCH + Duration::from_secs(taken as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(taken.unsigned_abs())
+    };
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_modified(time)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Exports the downloaded files of an album in a layout for Apple Photos
+///
+/// Each photo with a recorded, existing file in `state` is copied into
+/// `<root>/<album>` under its downloaded file name; photos without a file are
+/// skipped. Existing files are replaced, so the export can be re-run.
+///
+/// # Arguments
+///
+/// * `root` - Directory to create the album folder in
+/// * `album` - Metadata of the album, naming its folder
+/// * `photos` - The album's photos
+/// * `state` - Sync state mapping photo GUIDs to downloaded files
+///
+/// # Returns
+///
+/// The paths of the exported photos, in album order
+pub async fn export_apple_photos(
+    root: impl AsRef<Path>,
+    album: &Metadata,
+    photos: &[Image],
+    state: &SyncState,
+) -> io::Result<Vec<PathBuf>> {
+    let album_dir = root.as_ref().join(sanitize_component(&album.stream_name));
+    tokio::fs::create_dir_all(&album_dir).await?;
+
+    let mut exported = Vec::new();
+    for photo in photos {
+        let Some(file) = state.file(&photo.photo_guid) else {
+            continue;
+        };
+        let Some(file_name) = file.path.file_name() else {
+            continue;
+        };
+        if tokio::fs::metadata(&file.path).await.is_err() {
+            logging::log_warn!(
+                logging::SYNC,
+                "Skipping {} in Apple Photos export: {} is missing",
+                photo.photo_guid,
+                file.path.display()
+            );
+            continue;
+        }
+
+        let target = album_dir.join(file_name);
+        tokio::fs::copy(&file.path, &target).await?;
+        set_taken_time(&target, photo).await?;
+        exported.push(ExportedPhoto {
+            photo,
+            // Absolute, so the import script works from any directory
+            path: crate::compat::absolute(&target)?,
+            file_name: file_name.to_string_lossy().into_owned(),
+        });
+    }
+
+    tokio::fs::write(album_dir.join(CAPTIONS_FILE), captions_csv(&exported)).await?;
+    tokio::fs::write(album_dir.join(PLIST_FILE), album_plist(album, &exported)).await?;
+    tokio::fs::write(album_dir.join(SCRIPT_FILE), import_script(album, &exported)).await?;
+
+    Ok(exported
+        .into_iter()
+        .map(|exported| album_dir.join(exported.file_name))
+        .collect())
+}
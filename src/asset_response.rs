@@ -0,0 +1,272 @@
+//! Answering browser requests for proxied assets.
+//!
+//! Applications that serve albums usually proxy the assets, since iCloud's
+//! URLs expire. Browsers then need cache validators to avoid downloading
+//! thumbnails again, and byte ranges to seek in videos. [`respond`] works out
+//! the status and headers of the response to an [`AssetRequest`]: the ETag is
+//! the derivative's checksum, which changes whenever the content does, and a
+//! single `Range` is answered with `206 Partial Content`.
+//!
+//! The response only says which bytes of the content to send;
+//! [`AssetResponse::body`] cuts them out of content held in memory. With the
+//! `server` feature, the serve mode proxies assets at
+//! `/albums/{token}/assets/{guid}/{class}` this way.
+
+use crate::models::Derivative;
+use std::ops::Range;
+use std::time::Duration;
+
+/// How long browsers may cache assets, see [`respond`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetCachePolicy {
+    /// How long a response may be used without revalidating it
+    pub max_age: Duration,
+    /// Whether shared caches such as CDNs may store responses
+    pub public: bool,
+}
+
+impl Default for AssetCachePolicy {
+    fn default() -> Self {
+        Self {
+            max_age: Duration::from_secs(24 * 60 * 60),
+            public: true,
+        }
+    }
+}
+
+impl AssetCachePolicy {
+    /// Let responses be used for `max_age` without revalidating them
+    #[must_use]
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Keep responses out of shared caches, e.g. for private albums
+    #[must_use]
+    pub fn private(mut self) -> Self {
+        self.public = false;
+        self
+    }
+
+    /// The `Cache-Control` header value of the policy
+    pub fn header_value(&self) -> String {
+        format!(
+            "{}, max-age={}",
+            if self.public { "public" } else { "private" },
+            self.max_age.as_secs()
+        )
+    }
+}
+
+/// The headers of a browser's request for an asset that [`respond`] looks at
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AssetRequest {
+    /// The `Range` header
+    pub range: Option<String>,
+    /// The `If-None-Match` header
+    pub if_none_match: Option<String>,
+    /// The `If-Range` header
+    pub if_range: Option<String>,
+}
+
+impl AssetRequest {
+    /// Picks the relevant headers out of a request's (name, value) pairs
+    pub fn from_headers<K, V>(headers: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: AsRef<str>,
+        V: Into<String>,
+    {
+        let mut request = Self::default();
+        for (name, value) in headers {
+            let slot = match name.as_ref().to_ascii_lowercase().as_str() {
+                "range" => &mut request.range,
+                "if-none-match" => &mut request.if_none_match,
+                "if-range" => &mut request.if_range,
+                _ => continue,
+            };
+            *slot = Some(value.into());
+        }
+        request
+    }
+}
+
+/// What a `Range` header asks for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// The whole content, also for headers that aren't a single byte range
+    Full,
+    /// The bytes in the range
+    Partial(Range<u64>),
+    /// A range that starts past the end of the content
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header for content of `len` bytes
+///
+/// Only single ranges in bytes are supported, e.g. `bytes=0-499`,
+/// `bytes=500-` and `bytes=-500`; anything else asks for the whole content,
+/// as servers may ignore ranges they don't support.
+pub fn parse_range(header: &str, len: u64) -> RangeRequest {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+    let parse = |value: &str| value.parse::<u64>().ok();
+    match (start.is_empty(), end.is_empty()) {
+        // The last `end` bytes
+        (true, false) => match parse(end) {
+            Some(0) => RangeRequest::Unsatisfiable,
+            Some(suffix) if len > 0 => RangeRequest::Partial(len.saturating_sub(suffix)..len),
+            Some(_) => RangeRequest::Unsatisfiable,
+            None => RangeRequest::Full,
+        },
+        (false, _) => {
+            let Some(start) = parse(start) else {
+                return RangeRequest::Full;
+            };
+            let end = if end.is_empty() {
+                len.saturating_sub(1)
+            } else {
+                match parse(end) {
+                    Some(end) if end >= start => end.min(len.saturating_sub(1)),
+                    _ => return RangeRequest::Full,
+                }
+            };
+            if start >= len {
+                RangeRequest::Unsatisfiable
+            } else {
+                RangeRequest::Partial(start..end + 1)
+            }
+        }
+        (true, true) => RangeRequest::Full,
+    }
+}
+
+/// The status and headers to answer an asset request with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetResponse {
+    /// The HTTP status code: 200, 206, 304 or 416
+    pub status: u16,
+    /// Response headers as (name, value)
+    pub headers: Vec<(String, String)>,
+    /// The bytes of the content to send, empty for 304 and 416
+    pub body: Range<u64>,
+}
+
+impl AssetResponse {
+    /// Returns the value of a header, matching its name case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Cuts the bytes to send out of the content
+    ///
+    /// Returns nothing if `content` is shorter than the response was built for.
+    pub fn body<'a>(&self, content: &'a [u8]) -> &'a [u8] {
+        usize::try_from(self.body.start)
+            .ok()
+            .zip(usize::try_from(self.body.end).ok())
+            .and_then(|(start, end)| content.get(start..end))
+            .unwrap_or_default()
+    }
+}
+
+/// Returns the ETag of a derivative, or `None` if it has no checksum
+pub fn asset_etag(derivative: &Derivative) -> Option<String> {
+    (!derivative.checksum.is_empty()).then(|| format!("\"{}\"", derivative.checksum))
+}
+
+/// Works out how to answer a request for a derivative's content
+///
+/// A request whose `If-None-Match` lists the ETag is answered with
+/// `304 Not Modified`. A `Range` is honored unless an `If-Range` names
+/// another version of the content, in which case the whole content is sent.
+///
+/// # Arguments
+///
+/// * `request` - The relevant headers of the request
+/// * `derivative` - The derivative being served
+/// * `len` - Size of the content in bytes
+/// * `content_type` - The content's type, if known, e.g. `video/mp4`
+/// * `policy` - How long browsers may cache the response
+///
+/// # Returns
+///
+/// The status, headers and the bytes of the content to send
+pub fn respond(
+    request: &AssetRequest,
+    derivative: &Derivative,
+    len: u64,
+    content_type: Option<&str>,
+    policy: &AssetCachePolicy,
+) -> AssetResponse {
+    let etag = asset_etag(derivative);
+    let mut headers = vec![
+        ("Accept-Ranges".to_string(), "bytes".to_string()),
+        ("Cache-Control".to_string(), policy.header_value()),
+    ];
+    if let Some(etag) = &etag {
+        headers.push(("ETag".to_string(), etag.clone()));
+    }
+
+    let not_modified = match (&request.if_none_match, &etag) {
+        (Some(candidates), Some(etag)) => etag_listed(candidates, etag),
+        _ => false,
+    };
+    if not_modified {
+        return AssetResponse {
+            status: 304,
+            headers,
+            body: 0..0,
+        };
+    }
+
+    let range_applies = match &request.if_range {
+        Some(if_range) => etag.as_deref() == Some(if_range.trim()),
+        None => true,
+    };
+    let range = match &request.range {
+        Some(range) if range_applies => parse_range(range, len),
+        _ => RangeRequest::Full,
+    };
+    if let Some(content_type) = content_type {
+        headers.push(("Content-Type".to_string(), content_type.to_string()));
+    }
+    let (status, body) = match range {
+        RangeRequest::Full => (200, 0..len),
+        RangeRequest::Partial(body) => {
+            headers.push((
+                "Content-Range".to_string(),
+                format!("bytes {}-{}/{}", body.start, body.end - 1, len),
+            ));
+            (206, body)
+        }
+        RangeRequest::Unsatisfiable => {
+            headers.push(("Content-Range".to_string(), format!("bytes */{}", len)));
+            (416, 0..0)
+        }
+    };
+    headers.push((
+        "Content-Length".to_string(),
+        (body.end - body.start).to_string(),
+    ));
+    AssetResponse {
+        status,
+        headers,
+        body,
+    }
+}
+
+/// Whether an `If-None-Match` value lists an ETag, comparing weakly
+fn etag_listed(candidates: &str, etag: &str) -> bool {
+    candidates.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
@@ -1,13 +1,81 @@
 //! URL generation for iCloud album API endpoints.
 //!
 //! This module handles base URL construction and token parsing to determine
-//! the correct server partition for API requests.
+//! the correct server partition for API requests. The partition formula is
+//! configurable with [`PartitionScheme`].
+//!
+//! The scheme and host of every album URL, including those followed from 330
+//! redirects and asset URLs, can be overridden to point the whole pipeline at a mock or
+//! staging server: for one future with [`with_api_origin`], or for the process
+//! with the [`API_ORIGIN_ENV`] environment variable.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+
+/// Environment variable overriding the origin of album API requests
+///
+/// Set to e.g. `http://127.0.0.1:8080`; [`with_api_origin`] takes precedence.
+pub const API_ORIGIN_ENV: &str = "ICLOUD_ALBUM_API_ORIGIN";
+
+/// Start of an album's public web address, followed by its token
+pub const SHARE_URL_PREFIX: &str = "https://www.icloud.com/sharedalbum/#";
+
+tokio::task_local! {
+    /// Origin override installed for the current task
+    static API_ORIGIN: String;
+}
+
+/// Runs `future` with album API requests sent to `origin`
+///
+/// Every album URL built inside `future` uses `origin` (e.g.
+/// `http://127.0.0.1:8080`) instead of the iCloud partition host, and 330
+/// redirects and asset downloads stay on it. Tasks spawned by `future` don't inherit the override.
+///
+/// # Arguments
+///
+/// * `origin` - The scheme and host (and optional port) to send requests to
+/// * `future` - The work to run
+///
+/// # Returns
+///
+/// The output of `future`
+pub async fn with_api_origin<F: Future>(origin: &str, future: F) -> F::Output {
+    API_ORIGIN
+        .scope(origin.trim_end_matches('/').to_string(), future)
+        .await
+}
+
+/// Returns the origin overriding the iCloud hosts, if any
+///
+/// The override installed with [`with_api_origin`] is used first, then the
+/// [`API_ORIGIN_ENV`] environment variable.
+pub fn api_origin() -> Option<String> {
+    API_ORIGIN.try_with(Clone::clone).ok().or_else(|| {
+        std::env::var(API_ORIGIN_ENV)
+            .ok()
+            .map(|origin| origin.trim_end_matches('/').to_string())
+            .filter(|origin| !origin.is_empty())
+    })
+}
+
+/// Builds the album base URL on `host`, honouring any [`api_origin`] override
+pub(crate) fn album_base_url(host: &str, token: &str) -> String {
+    match api_origin() {
+        Some(origin) => format!("{}/{}/sharedstreams/", origin, token),
+        None => format!("https://{}/{}/sharedstreams/", host, token),
+    }
+}
 
 /// Error type for base URL generation
-#[derive(Debug, thiserror::Error)]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
 pub enum BaseUrlError {
+    /// The token starts with a character outside `[0-9A-Za-z]`
     #[error("Invalid base62 character: {0}")]
     InvalidBase62Char(char),
+    /// The token is empty
     #[error("Empty token provided")]
     EmptyToken,
 }
@@ -22,8 +90,350 @@ fn char_to_base62(c: char) -> Result<u32, BaseUrlError> {
     }
 }
 
-/// Calculate server partition based on the token's first character
-fn calculate_partition(token: &str) -> Result<u32, BaseUrlError> {
+/// Characters stripped from the end of pasted tokens
+const TRAILING_PUNCTUATION: &[char] = &[
+    '.', ',', ';', ':', '!', '?', ')', ']', '}', '>', '"', '\'', '\u{201D}', '\u{2019}',
+];
+
+/// Something [`normalize_token`] changed or noticed in a pasted token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenWarning {
+    /// Whitespace or invisible characters were removed
+    TrimmedWhitespace,
+    /// The token was taken from the fragment of a share URL
+    StrippedUrl,
+    /// Punctuation was removed from around the token
+    StrippedPunctuation(String),
+    /// The token contains characters that are easily confused (`0`/`O`),
+    /// listed with their zero-based positions
+    AmbiguousCharacters(Vec<(usize, char)>),
+}
+
+impl std::fmt::Display for TokenWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenWarning::TrimmedWhitespace => write!(f, "removed whitespace around the token"),
+            TokenWarning::StrippedUrl => write!(f, "took the token from the share URL"),
+            TokenWarning::StrippedPunctuation(removed) => {
+                write!(f, "removed \"{}\" around the token", removed)
+            }
+            TokenWarning::AmbiguousCharacters(chars) => {
+                let positions: Vec<String> = chars
+                    .iter()
+                    .map(|(index, c)| format!("'{}' at {}", c, index + 1))
+                    .collect();
+                write!(
+                    f,
+                    "check for zero/O mix-ups: {} (if the album isn't found)",
+                    positions.join(", ")
+                )
+            }
+        }
+    }
+}
+
+/// A token cleaned up by [`normalize_token`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NormalizedToken {
+    /// The token to use
+    pub token: String,
+    /// What was changed or should be double-checked
+    pub warnings: Vec<TokenWarning>,
+}
+
+/// Cleans up a token pasted by a user and validates it
+///
+/// Surrounding whitespace and invisible characters are trimmed, a share URL
+/// (`https://www.icloud.com/sharedalbum/#B0z5qAGN1JIFd3y`) is reduced to its
+/// fragment, and quotes or trailing punctuation picked up when copying from a
+/// message are removed. Zero/O characters can't be corrected, so they are only
+/// flagged. Every change is reported in [`NormalizedToken::warnings`].
+///
+/// # Arguments
+///
+/// * `input` - The token or share URL as pasted
+///
+/// # Returns
+///
+/// The normalized token, or an error if it is empty or not base62
+pub fn normalize_token(input: &str) -> Result<NormalizedToken, BaseUrlError> {
+    let mut warnings = Vec::new();
+    let is_invisible = |c: char| {
+        c.is_whitespace() || matches!(c, '\u{200B}' | '\u{200C}' | '\u{200D}' | '\u{FEFF}')
+    };
+
+    let mut token = input.trim_matches(is_invisible);
+    if token.len() != input.len() {
+        warnings.push(TokenWarning::TrimmedWhitespace);
+    }
+
+    // Share URLs carry the token in the fragment
+    if let Some((_, fragment)) = token.rsplit_once('#') {
+        // Parameters may follow the token, e.g. `#B0z5qAGN1JIFd3y;Trip`
+        token = fragment.split(';').next().unwrap_or_default();
+        token = token.trim_matches(is_invisible);
+        warnings.push(TokenWarning::StrippedUrl);
+    }
+
+    let unquoted = token
+        .trim_start_matches(['"', '\'', '(', '[', '<', '\u{201C}', '\u{2018}'])
+        .trim_end_matches(TRAILING_PUNCTUATION);
+    if unquoted.len() != token.len() {
+        let start = token.find(unquoted).unwrap_or_default();
+        let removed = format!("{}{}", &token[..start], &token[start + unquoted.len()..]);
+        warnings.push(TokenWarning::StrippedPunctuation(removed));
+        token = unquoted;
+    }
+
+    if token.is_empty() {
+        return Err(BaseUrlError::EmptyToken);
+    }
+    for c in token.chars() {
+        char_to_base62(c)?;
+    }
+
+    let ambiguous: Vec<(usize, char)> = token
+        .chars()
+        .enumerate()
+        .filter(|(_, c)| matches!(c, '0' | 'O'))
+        .collect();
+    if !ambiguous.is_empty() {
+        warnings.push(TokenWarning::AmbiguousCharacters(ambiguous));
+    }
+
+    Ok(NormalizedToken {
+        token: token.to_string(),
+        warnings,
+    })
+}
+
+/// Builds the public web address of an album from its token
+///
+/// The token is cleaned up and validated with [`normalize_token`], so a
+/// pasted share URL with parameters yields the canonical one.
+///
+/// # Arguments
+///
+/// * `token` - The token or share URL, e.g. as stored in a config file
+///
+/// # Returns
+///
+/// The share URL, e.g. `https://www.icloud.com/sharedalbum/#B0z5qAGN1JIFd3y`
+pub fn share_url(token: &str) -> Result<String, BaseUrlError> {
+    Ok(format!(
+        "{}{}",
+        SHARE_URL_PREFIX,
+        normalize_token(token)?.token
+    ))
+}
+
+/// Number of partitions Apple's formula distributes albums over
+pub const DEFAULT_PARTITION_COUNT: u32 = 40;
+
+/// Largest radius of a [`NeighborScan`]
+pub const MAX_SCAN_RADIUS: u32 = 5;
+
+/// Probing of the partitions next to the computed one
+///
+/// When the computed partition host doesn't resolve or answers 404, the
+/// partitions up to `radius` away are probed, nearest first, waiting `delay`
+/// before each probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NeighborScan {
+    /// How far from the computed partition to probe, at most [`MAX_SCAN_RADIUS`]
+    pub radius: u32,
+    /// Delay before each probe
+    pub delay: Duration,
+}
+
+impl NeighborScan {
+    /// Create a scan of the partitions up to `radius` away, `delay` apart
+    pub fn new(radius: u32, delay: Duration) -> Self {
+        Self {
+            radius: radius.min(MAX_SCAN_RADIUS),
+            delay,
+        }
+    }
+
+    /// Returns the partitions to probe around `center`, nearest first
+    ///
+    /// Partitions wrap around within `1..=count`.
+    pub fn partitions(&self, center: u32, count: u32) -> Vec<u32> {
+        let count = count.max(1);
+        let index = center.saturating_sub(1) % count;
+        let mut partitions = Vec::new();
+        for distance in 1..=self.radius.min(MAX_SCAN_RADIUS) {
+            for neighbor in [
+                (index + count - distance % count) % count,
+                (index + distance) % count,
+            ] {
+                let partition = neighbor + 1;
+                if partition != center && !partitions.contains(&partition) {
+                    partitions.push(partition);
+                }
+            }
+        }
+        partitions
+    }
+}
+
+/// Computes the partition of a token given the partition count
+pub type PartitionFormula = Arc<dyn Fn(&str, u32) -> Result<u32, BaseUrlError> + Send + Sync>;
+
+/// How tokens map to `pXX-sharedstreams` partitions
+///
+/// By default, the partition is `1 + base62(first character) % 40`. If Apple
+/// changes its partitioning, the count or the whole formula can be replaced
+/// and extra candidate partitions can be listed; the album pipeline tries the
+/// candidates when the computed partition fails, optionally followed by a
+/// [`NeighborScan`], and remembers the partition that answered for each
+/// token, optionally hedging a slow remembered partition with the computed one.
+/// Install a scheme with [`set_partition_scheme`].
+#[derive(Clone)]
+pub struct PartitionScheme {
+    /// Number of partitions passed to the formula
+    pub count: u32,
+    /// Formula computing a token's partition, or `None` for Apple's
+    pub formula: Option<PartitionFormula>,
+    /// Partitions tried, in order, when the computed one fails
+    pub candidates: Vec<u32>,
+    /// Scan of neighboring partitions when the computed host is missing
+    pub neighbor_scan: Option<NeighborScan>,
+    /// Delay after which the computed partition is raced against a slow remembered one
+    pub hedge_delay: Option<Duration>,
+}
+
+impl Default for PartitionScheme {
+    fn default() -> Self {
+        Self {
+            count: DEFAULT_PARTITION_COUNT,
+            formula: None,
+            candidates: Vec::new(),
+            neighbor_scan: None,
+            hedge_delay: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for PartitionScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PartitionScheme")
+            .field("count", &self.count)
+            .field("formula", &self.formula.as_ref().map(|_| "custom"))
+            .field("candidates", &self.candidates)
+            .field("neighbor_scan", &self.neighbor_scan)
+            .field("hedge_delay", &self.hedge_delay)
+            .finish()
+    }
+}
+
+impl PartitionScheme {
+    /// Create Apple's scheme of 40 partitions without candidates
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Distribute tokens over `count` partitions
+    #[must_use]
+    pub fn with_count(mut self, count: u32) -> Self {
+        self.count = count.max(1);
+        self
+    }
+
+    /// Compute partitions with `formula` instead of Apple's
+    #[must_use]
+    pub fn with_formula<F>(mut self, formula: F) -> Self
+    where
+        F: Fn(&str, u32) -> Result<u32, BaseUrlError> + Send + Sync + 'static,
+    {
+        self.formula = Some(Arc::new(formula));
+        self
+    }
+
+    /// Try `partitions`, in order, when the computed partition fails
+    #[must_use]
+    pub fn with_candidates(mut self, partitions: impl IntoIterator<Item = u32>) -> Self {
+        self.candidates = partitions.into_iter().collect();
+        self
+    }
+
+    /// Probe neighboring partitions when the computed host is missing
+    ///
+    /// See [`NeighborScan`]; the radius is capped at [`MAX_SCAN_RADIUS`].
+    #[must_use]
+    pub fn with_neighbor_scan(mut self, radius: u32, delay: Duration) -> Self {
+        self.neighbor_scan = Some(NeighborScan::new(radius, delay));
+        self
+    }
+
+    /// Race the computed partition against a remembered one that hasn't answered after `delay`
+    ///
+    /// The partition that last answered for a token is normally probed first
+    /// and on its own. With hedging, if it hasn't answered after `delay`, the
+    /// computed partition is probed as well and whichever answers first is
+    /// used. This bounds the latency of interactive apps when a remembered
+    /// host turns slow, at the cost of an extra request in that case.
+    #[must_use]
+    pub fn with_hedging(mut self, delay: Duration) -> Self {
+        self.hedge_delay = Some(delay);
+        self
+    }
+
+    /// Computes the partition of `token` under this scheme
+    pub fn partition(&self, token: &str) -> Result<u32, BaseUrlError> {
+        match &self.formula {
+            Some(formula) => formula(token, self.count),
+            None => apple_partition(token, self.count),
+        }
+    }
+}
+
+/// The installed scheme and the partitions that answered, by token
+#[derive(Default)]
+struct Partitions {
+    scheme: PartitionScheme,
+    answered: HashMap<String, u32>,
+}
+
+fn partitions() -> MutexGuard<'static, Partitions> {
+    static PARTITIONS: OnceLock<Mutex<Partitions>> = OnceLock::new();
+    PARTITIONS
+        .get_or_init(|| Mutex::new(Partitions::default()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Installs the partition scheme for all album requests of the process
+///
+/// Forgets the partitions remembered under the previous scheme.
+pub fn set_partition_scheme(scheme: PartitionScheme) {
+    let mut partitions = partitions();
+    partitions.scheme = scheme;
+    partitions.answered.clear();
+}
+
+/// Returns the installed partition scheme
+pub fn partition_scheme() -> PartitionScheme {
+    partitions().scheme.clone()
+}
+
+/// Returns the partition remembered for `token`, if it answered before
+pub fn cached_partition(token: &str) -> Option<u32> {
+    partitions().answered.get(token).copied()
+}
+
+/// Forgets the partitions remembered for all tokens
+pub fn clear_partition_cache() {
+    partitions().answered.clear();
+}
+
+/// Remembers that `partition` answered for `token`
+pub(crate) fn remember_partition(token: &str, partition: u32) {
+    partitions().answered.insert(token.to_string(), partition);
+}
+
+/// Apple's formula: `1 + base62(first character) % count`
+fn apple_partition(token: &str, count: u32) -> Result<u32, BaseUrlError> {
     if token.is_empty() {
         return Err(BaseUrlError::EmptyToken);
     }
@@ -31,16 +441,82 @@ fn calculate_partition(token: &str) -> Result<u32, BaseUrlError> {
     // Get the first character of the token
     let first_char = token.chars().next().ok_or(BaseUrlError::EmptyToken)?;
 
-    // Convert to base62 value and use modulo to get a server partition between 1-40
+    // Convert to base62 value and use modulo to get a server partition between 1 and count
     let base62_value = char_to_base62(first_char)?;
-    Ok(1 + (base62_value % 40))
+    Ok(1 + (base62_value % count.max(1)))
+}
+
+/// Calculates the server partition of a token
+///
+/// The partition is computed from the token's first character under the
+/// installed [`PartitionScheme`]; partitions remembered from earlier fetches
+/// are not consulted.
+///
+/// # Arguments
+///
+/// * `token` - The iCloud shared album token
+///
+/// # Returns
+///
+/// The partition number, e.g. `12` for `p12-sharedstreams.icloud.com`
+pub fn calculate_partition(token: &str) -> Result<u32, BaseUrlError> {
+    partition_scheme().partition(token)
+}
+
+/// Returns the partition host album requests for a token are sent to
+///
+/// This is the host of [`get_base_url`], e.g. `p12-sharedstreams.icloud.com`,
+/// without the scheme, path or any [`api_origin`] override: the partition that
+/// last answered for the token, or else the computed one.
+///
+/// # Arguments
+///
+/// * `token` - The iCloud shared album token
+///
+/// # Returns
+///
+/// The partition host name
+pub fn partition_host(token: &str) -> Result<String, BaseUrlError> {
+    let partition = calculate_partition(token)?;
+    Ok(host_of_partition(
+        cached_partition(token).unwrap_or(partition),
+    ))
+}
+
+/// Returns the host name of `partition`
+fn host_of_partition(partition: u32) -> String {
+    format!("p{:02}-sharedstreams.icloud.com", partition)
+}
+
+/// Returns the partitions to try for `token`, in order
+///
+/// The remembered partition comes first, then the computed one, then the
+/// scheme's candidates, without duplicates.
+pub(crate) fn candidate_partitions(token: &str) -> Result<Vec<u32>, BaseUrlError> {
+    let scheme = partition_scheme();
+    let computed = scheme.partition(token)?;
+    let mut candidates: Vec<u32> = cached_partition(token).into_iter().collect();
+    for partition in std::iter::once(computed).chain(scheme.candidates) {
+        if !candidates.contains(&partition) {
+            candidates.push(partition);
+        }
+    }
+    Ok(candidates)
+}
+
+/// Builds the base URL of `token` on `partition`
+pub(crate) fn partition_base_url(partition: u32, token: &str) -> String {
+    album_base_url(&host_of_partition(partition), token)
 }
 
 /// Generates the base URL for the iCloud API using the token
 ///
 /// The URL is constructed in the format:
 /// `https://pXX-sharedstreams.icloud.com/{token}/sharedstreams/`
-/// where XX is the server partition determined by the first character of the token.
+/// where XX is the server partition determined by the first character of the
+/// token under the installed [`PartitionScheme`], or the partition that last
+/// answered for the token. With an [`api_origin`] override, the URL is built on
+/// that origin instead.
 ///
 /// # Arguments
 ///
@@ -50,11 +526,7 @@ fn calculate_partition(token: &str) -> Result<u32, BaseUrlError> {
 ///
 /// The generated base URL as a Result containing either the URL string or an error
 pub fn get_base_url(token: &str) -> Result<String, BaseUrlError> {
-    let server_partition = calculate_partition(token)?;
-    Ok(format!(
-        "https://p{:02}-sharedstreams.icloud.com/{}/sharedstreams/",
-        server_partition, token
-    ))
+    Ok(album_base_url(&partition_host(token)?, token))
 }
 
 #[cfg(test)]
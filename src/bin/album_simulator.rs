@@ -0,0 +1,89 @@
+//! Serves a simulated shared album API for local development
+//!
+//! Run with:
+//! ```
+//! cargo run --features simulator --bin album-simulator -- --photos 200 --fail-every 5
+//! ```
+//!
+//! Then point the crate at it, e.g.
+//! `ICLOUD_ALBUM_API_ORIGIN=http://127.0.0.1:8080 cargo run --example fetch_album -- AnyToken`
+
+use icloud_album_rs::simulator::{FailureMode, Simulator, SimulatorConfig};
+use std::env;
+use std::process;
+use std::time::Duration;
+
+const USAGE: &str = "Usage: album-simulator [--addr ADDR] [--photos N] [--name NAME] \
+[--latency-ms MS] [--fail-every N [--fail-status STATUS]] [--malformed-every N] \
+[--missing-urls] [--redirect]";
+
+/// Parses the value following a flag
+fn value<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> T {
+    match args.next().and_then(|value| value.parse().ok()) {
+        Some(value) => value,
+        None => {
+            eprintln!("Missing or invalid value for {}\n{}", flag, USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let mut addr = "127.0.0.1:8080".to_string();
+    let mut config = SimulatorConfig::new();
+    let mut fail_every = None;
+    let mut fail_status = 503;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => addr = value(&mut args, &arg),
+            "--photos" => config = config.with_photos(value(&mut args, &arg)),
+            "--name" => config = config.with_album_name(&value::<String>(&mut args, &arg)),
+            "--latency-ms" => {
+                config = config.with_latency(Duration::from_millis(value(&mut args, &arg)))
+            }
+            "--fail-every" => fail_every = Some(value(&mut args, &arg)),
+            "--fail-status" => fail_status = value(&mut args, &arg),
+            "--malformed-every" => {
+                config = config.with_failure(FailureMode::Malformed {
+                    every: value(&mut args, &arg),
+                })
+            }
+            "--missing-urls" => config = config.with_failure(FailureMode::MissingUrls),
+            "--redirect" => config = config.with_failure(FailureMode::Redirect),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => {
+                eprintln!("Unknown argument: {}\n{}", arg, USAGE);
+                process::exit(2);
+            }
+        }
+    }
+    if let Some(every) = fail_every {
+        config = config.with_failure(FailureMode::Status {
+            every,
+            status: fail_status,
+        });
+    }
+
+    let simulator = match Simulator::bind(&addr, config).await {
+        Ok(simulator) => simulator,
+        Err(e) => {
+            eprintln!("Failed to listen on {}: {}", addr, e);
+            process::exit(1);
+        }
+    };
+    println!("Simulating the album API at {}", simulator.origin());
+    println!(
+        "Point the crate at it with: export ICLOUD_ALBUM_API_ORIGIN={}",
+        simulator.origin()
+    );
+    if let Err(e) = simulator.run().await {
+        eprintln!("Simulator stopped: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,34 @@
+//! `icloud-album check`: checks an album for changes in Apple's API
+//!
+//! Fetches the album's webstream response and the asset URLs of a sample of
+//! its photos, without downloading any of them, and prints a compatibility
+//! report: the fields seen, fields this crate doesn't know and values of
+//! unexpected types. Run this first when an album stops fetching.
+
+use super::{album_token, EXIT_FAILURE};
+use icloud_album_rs::check;
+use icloud_album_rs::fetch::FetchOptions;
+use std::process;
+
+/// Runs `icloud-album check TOKEN`, exiting with 1 if the album is incompatible
+pub async fn run(mut args: impl Iterator<Item = String>) {
+    let token = match (args.next(), args.next()) {
+        (Some(token), None) if !token.starts_with("--") => album_token(&token),
+        _ => {
+            eprintln!("{}", super::USAGE);
+            process::exit(EXIT_FAILURE);
+        }
+    };
+
+    let report = match check::check_album(&token, &FetchOptions::new()).await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Failed to reach the album: {}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    };
+    print!("{}", report);
+    if !report.is_compatible() {
+        process::exit(EXIT_FAILURE);
+    }
+}
@@ -0,0 +1,229 @@
+//! `icloud-album frame`: a digital photo frame, e.g. on a Raspberry Pi
+//!
+//! Mirrors an album as a slideshow sized for the display, rebuilds it when the
+//! album's `streamCtag` changes, and serves a fullscreen frame page on a local
+//! HTTP endpoint. Point the frame's kiosk browser at it.
+//!
+//! The endpoint serves:
+//! - `/` - the frame page, which plays the slideshow and reloads the manifest
+//! - `/slideshow.json` - the manifest, see `icloud_album_rs::slideshow`
+//! - `/slides/{file}` - the slides
+
+use super::{album_token, value, EXIT_FAILURE};
+use icloud_album_rs::get_icloud_photos;
+use icloud_album_rs::get_stream_ctag;
+use icloud_album_rs::models::StreamCtag;
+use icloud_album_rs::slideshow::{build_slideshow, SlideOrder, SlideshowOptions, MANIFEST_FILE};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+/// The page shown by the frame's browser
+const FRAME_HTML: &str = r#"<!doctype html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Photo frame</title>
+<style>
+  html, body { margin: 0; height: 100%; background: #000; overflow: hidden; cursor: none; }
+  img { position: absolute; inset: 0; width: 100%; height: 100%; object-fit: contain;
+        opacity: 0; transition: opacity 1s; }
+  img.shown { opacity: 1; }
+  p { position: absolute; left: 0; right: 0; bottom: 24px; margin: 0; text-align: center;
+      font: 28px system-ui, sans-serif; color: #fff; text-shadow: 0 0 6px #000; }
+</style>
+</head>
+<body>
+<img id="a" alt=""><img id="b" alt="">
+<p id="caption"></p>
+<script>
+"use strict";
+const images = [document.getElementById("a"), document.getElementById("b")];
+const caption = document.getElementById("caption");
+let slides = [];
+let position = 0;
+let front = 0;
+
+async function loadManifest() {
+  try {
+    const response = await fetch("slideshow.json", { cache: "no-store" });
+    if (response.ok) {
+      slides = (await response.json()).slides;
+    }
+  } catch (error) {
+    // Keep playing the slides already known
+  }
+}
+
+function showNext() {
+  if (slides.length === 0) {
+    setTimeout(showNext, 5000);
+    return;
+  }
+  const slide = slides[position % slides.length];
+  position += 1;
+  const back = images[1 - front];
+  back.onload = () => {
+    back.classList.add("shown");
+    images[front].classList.remove("shown");
+    front = 1 - front;
+    caption.textContent = slide.caption || "";
+  };
+  back.src = "slides/" + encodeURIComponent(slide.file);
+  setTimeout(showNext, slide.duration_secs * 1000);
+}
+
+loadManifest().then(showNext);
+setInterval(loadManifest, 60000);
+</script>
+</body>
+</html>
+"#;
+
+/// Downloads the album as a slideshow and swaps it in once complete
+///
+/// The frame keeps showing the previous slideshow while the new one is built.
+async fn rebuild(
+    token: &str,
+    output_dir: &Path,
+    options: &SlideshowOptions,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let album = get_icloud_photos(token).await?;
+    let next = output_dir.join("next");
+    let current = output_dir.join("current");
+    let previous = output_dir.join("previous");
+    let _ = tokio::fs::remove_dir_all(&next).await;
+    let manifest = build_slideshow(&album, &next, options, None).await?;
+
+    let _ = tokio::fs::remove_dir_all(&previous).await;
+    if tokio::fs::metadata(&current).await.is_ok() {
+        tokio::fs::rename(&current, &previous).await?;
+    }
+    tokio::fs::rename(&next, &current).await?;
+    let _ = tokio::fs::remove_dir_all(&previous).await;
+    Ok(manifest.slides.len())
+}
+
+/// Answers one request of the frame's browser
+fn answer(mut stream: TcpStream, slides_dir: &Path) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or("/"));
+    let path = path.split('?').next().unwrap_or("/");
+    let (status, content_type, body): (u16, String, Vec<u8>) = if method != "GET" {
+        (405, "text/plain".into(), b"Method not allowed".to_vec())
+    } else if path == "/" {
+        (
+            200,
+            "text/html; charset=utf-8".into(),
+            FRAME_HTML.as_bytes().to_vec(),
+        )
+    } else if path == "/slideshow.json" {
+        match std::fs::read(slides_dir.join(MANIFEST_FILE)) {
+            Ok(body) => (200, "application/json".into(), body),
+            // Not built yet: an empty slideshow makes the page retry
+            Err(_) => (200, "application/json".into(), b"{\"slides\": []}".to_vec()),
+        }
+    } else if let Some(file) = path.strip_prefix("/slides/") {
+        let file = url::form_urlencoded::parse(format!("f={}", file).as_bytes())
+            .next()
+            .map(|(_, file)| file.into_owned())
+            .unwrap_or_default();
+        if file.is_empty() || file.contains('/') || file.contains('\\') || file.starts_with('.') {
+            (404, "text/plain".into(), b"Not found".to_vec())
+        } else {
+            match std::fs::read(slides_dir.join(&file)) {
+                Ok(body) => {
+                    let content_type = mime_guess::from_path(&file).first_or_octet_stream();
+                    (200, content_type.essence_str().to_string(), body)
+                }
+                Err(_) => (404, "text/plain".into(), b"Not found".to_vec()),
+            }
+        }
+    } else {
+        (404, "text/plain".into(), b"Not found".to_vec())
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status,
+        if status == 200 { "OK" } else { "Error" },
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(&body)
+}
+
+/// Runs `icloud-album frame TOKEN OUTPUT_DIR [flags]` until it is stopped
+pub async fn run(mut args: impl Iterator<Item = String>) {
+    let (Some(token), Some(output_dir)) = (args.next(), args.next()) else {
+        eprintln!("{}", super::USAGE);
+        process::exit(EXIT_FAILURE);
+    };
+    let token = album_token(&token);
+    let output_dir = PathBuf::from(output_dir);
+    let mut options = SlideshowOptions::default();
+    let mut addr = "127.0.0.1:8090".to_string();
+    let mut interval = Duration::from_secs(300);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--width" => options.width = value(&mut args, &arg),
+            "--height" => options.height = value(&mut args, &arg),
+            "--duration" => options.duration_secs = value(&mut args, &arg),
+            "--addr" => addr = value(&mut args, &arg),
+            "--interval" => interval = Duration::from_secs(value(&mut args, &arg)),
+            "--shuffle" => options.order = SlideOrder::Shuffled,
+            _ => {
+                eprintln!("Unknown argument: {}\n{}", arg, super::USAGE);
+                process::exit(EXIT_FAILURE);
+            }
+        }
+    }
+
+    let listener = match TcpListener::bind(&addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Could not listen on {}: {}", addr, e);
+            process::exit(EXIT_FAILURE);
+        }
+    };
+    match listener.local_addr() {
+        Ok(local) => println!("Serving the frame on http://{}/", local),
+        Err(_) => println!("Serving the frame on http://{}/", addr),
+    }
+    let slides_dir = output_dir.join("current");
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = answer(stream, &slides_dir) {
+                eprintln!("Request failed: {}", e);
+            }
+        }
+    });
+
+    let mut last_ctag: Option<StreamCtag> = None;
+    loop {
+        match get_stream_ctag(&token).await {
+            Ok(ctag) if ctag.unchanged_since(last_ctag.as_ref()) => {}
+            Ok(ctag) => match rebuild(&token, &output_dir, &options).await {
+                Ok(count) => {
+                    println!("Slideshow updated with {} slides", count);
+                    last_ctag = Some(ctag);
+                }
+                Err(e) => eprintln!("Failed to update the slideshow: {}", e),
+            },
+            Err(e) => eprintln!("Failed to check the album: {}", e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,56 @@
+//! `icloud-album history`: shows how an album grew over the runs of a sync
+//!
+//! Reads the history recorded in a sync state file (see
+//! `SyncState::record_run`) and prints one line per run.
+
+use super::EXIT_FAILURE;
+use icloud_album_rs::server::SYNC_STATE_FILE;
+use icloud_album_rs::sync::SyncState;
+use std::path::PathBuf;
+use std::process;
+
+/// Runs `icloud-album history PATH`, where `PATH` is a state file or an album directory
+pub async fn run(mut args: impl Iterator<Item = String>) {
+    let path = match (args.next(), args.next()) {
+        (Some(path), None) if !path.starts_with("--") => PathBuf::from(path),
+        _ => {
+            eprintln!("{}", super::USAGE);
+            process::exit(EXIT_FAILURE);
+        }
+    };
+    // Mirrored albums keep their state file at the root of their directory
+    let path = if path.is_dir() {
+        path.join(SYNC_STATE_FILE)
+    } else {
+        path
+    };
+
+    let state = match SyncState::load(&path).await {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path.display(), e);
+            process::exit(EXIT_FAILURE);
+        }
+    };
+    if state.history.is_empty() {
+        println!("No runs recorded yet");
+        return;
+    }
+
+    println!(
+        "{:<12} {:>8} {:>8} {:>8} {:>12}",
+        "Finished", "Photos", "Change", "Files", "Bytes"
+    );
+    let mut previous = None;
+    for snapshot in &state.history {
+        let change = match previous {
+            Some(count) => format!("{:+}", snapshot.photo_count as i64 - count as i64),
+            None => String::new(),
+        };
+        println!(
+            "{:<12} {:>8} {:>8} {:>8} {:>12}",
+            snapshot.finished_at, snapshot.photo_count, change, snapshot.file_count, snapshot.bytes
+        );
+        previous = Some(snapshot.photo_count);
+    }
+}
@@ -0,0 +1,697 @@
+//! Serves a JSON-RPC API driving album fetches, syncs and downloads, mirrors albums,
+//! or runs tools built on them
+//!
+//! Run with:
+//! ```
+//! cargo run --features server --bin icloud-album -- serve --port 8787 --root ./albums
+//! ```
+//!
+//! Requests need `Authorization: Bearer <token>` when `--bearer-token` or the
+//! `ICLOUD_ALBUM_SERVER_TOKEN` environment variable is set. `--rate-limit N`
+//! allows each client IP N requests per minute, in bursts of up to `--burst`,
+//! and `--album-quota MB` serves at most that many megabytes of each album
+//! per day. Limited requests are answered with `429 Too Many Requests`.
+//! `--prefetch N` downloads the next N photos a client is predicted to view
+//! ahead of its requests.
+//!
+//! With `ICLOUD_ALBUM_TOKENS` set, `serve` also mirrors those albums in the
+//! background, below `--root` unless `ICLOUD_ALBUM_OUTPUT_DIR` is set, every
+//! `ICLOUD_ALBUM_INTERVAL_SECS` (300 by default). `GET /health` then reports
+//! when a sync last succeeded, the last error and how many albums are waiting
+//! for a free slot, and answers 503 while an album keeps failing.
+//!
+//! `icloud-album mirror` reads the albums, output directory, interval and
+//! concurrency from the environment (see `icloud_album_rs::config`) and syncs
+//! every album into its own directory.
+//! With an interval, it keeps syncing the albums until it is stopped, on a
+//! `PollScheduler` that staggers the albums, polls those that change often
+//! sooner and backs off those that fail. This suits containers that are
+//! restarted by their runtime:
+//! ```text
+//! ICLOUD_ALBUM_TOKENS=B0z5qAGN1JIFd3y ICLOUD_ALBUM_OUTPUT_DIR=/photos \
+//!     ICLOUD_ALBUM_INTERVAL_SECS=3600 icloud-album mirror
+//! ```
+//!
+//! `--config FILE`, for `serve` and `mirror`, takes the albums from a config
+//! file instead, each with its own interval and output directory, along with
+//! file naming, retries, concurrency and the sinks new photos are reported
+//! to (see `icloud_album_rs::app_config`). The file is JSON, or TOML and YAML
+//! with the `toml-config` and `yaml-config` features. Edits to its albums
+//! apply while the command runs: albums are added and removed, and their
+//! intervals and directories change, with each change reported on stderr. `mirror --config`
+//! keeps syncing until it is stopped, or syncs every album once with `--once`.
+//!
+//! A single pass prints one JSON summary line on stdout, with progress on
+//! stderr; with an interval, every sync of an album prints its own. A single
+//! pass exits with a code schedulers can alert on: 0 when everything was
+//! mirrored, 2 when some photos failed to download, 3 when an
+//! album token is invalid or the album is gone, 4 when Apple's servers could
+//! not be reached, and 1 for other errors, including invalid arguments. The
+//! first of 3, 4, 1 and 2 that applies to any album wins.
+//!
+//! `icloud-album frame TOKEN DIR` runs a digital photo frame: it keeps a
+//! slideshow of the album sized for the display (`--width`, `--height`) in
+//! `DIR`, rebuilds it when the album changes (checked every `--interval`
+//! seconds, 300 by default) and serves it to a kiosk browser on `--addr`
+//! (`127.0.0.1:8090` by default). `--duration` sets the seconds per slide and
+//! `--shuffle` shuffles them.
+//!
+//! `icloud-album publish TOKEN DIR` publishes the album as a static website
+//! in `DIR`: `index.html` pages of 200 photos (`--page-size N`, 0 for a single
+//! page) with thumbnails and screen-sized renditions. `--title`,
+//! `--no-captions` and `--markdown` shape the pages, and `--css FILE` and
+//! `--templates DIR` (with `page.html` and `photo.html`, see
+//! `icloud_album_rs::gallery::DEFAULT_PAGE_TEMPLATE`) theme them. `--rsync
+//! DEST` or `--s3 URL` then upload the site. Running it again only downloads
+//! new and edited photos.
+//!
+//! `icloud-album open TOKEN` prints the album's share URL and opens it in the
+//! browser, or only prints it with `--print`. With `--config FILE`, the album
+//! can be named by its `name` in the config file instead.
+//!
+//! `icloud-album check TOKEN` checks the album's API responses against the
+//! schema the crate expects, without downloading any photo, and prints the
+//! fields seen, unknown fields and type drifts. It exits with 1 if the album
+//! is incompatible.
+//!
+//! `icloud-album history DIR` prints how a mirrored album grew, one line per
+//! sync run recorded in its state file; `DIR` may also name the file itself.
+//!
+//! `icloud-album search INDEX QUERY`, with the `sqlite` feature, searches the
+//! captions in a metadata index kept up to date by mirror runs (see
+//! `icloud_album_rs::metadata_index`) and prints the matching photos, best
+//! first. `--since` and `--until` take `YYYY-MM-DD` days.
+//!
+//! `--check-update`, before any command or on its own, first asks crates.io
+//! whether a newer release of the crate is out and prints upgrade instructions
+//! on stderr, as fixes for API changes only help once they are installed.
+//! Nothing else contacts crates.io.
+
+mod check;
+mod frame;
+mod history;
+mod open;
+mod publish;
+#[cfg(feature = "sqlite")]
+mod search;
+
+use icloud_album_rs::access::AccessPolicy;
+use icloud_album_rs::album_config::{
+    AlbumsConfig, ConfigChange, ConfigWatcher, FollowedAlbum, DEFAULT_INTERVAL_SECS,
+};
+use icloud_album_rs::api::{ApiError, FailureClass, RetryPolicies};
+use icloud_album_rs::app_config::AppConfig;
+use icloud_album_rs::base_url::normalize_token;
+use icloud_album_rs::config::{Config, MirrorConfig, OUTPUT_DIR_ENV, TOKENS_ENV};
+use icloud_album_rs::download::DownloadOptions;
+use icloud_album_rs::fetch::FetchOptions;
+use icloud_album_rs::metrics::{self, PrometheusMetrics};
+use icloud_album_rs::prefetch::{PrefetchConfig, PrefetchPredictor};
+use icloud_album_rs::quota::{BandwidthQuota, RateLimit, ServeLimiter};
+use icloud_album_rs::redact::{redact_token, Redacted};
+use icloud_album_rs::scheduler::{PollError, PollScheduler};
+use icloud_album_rs::server::{self, Server, ServerConfig, SyncRun, DEFAULT_PORT};
+use icloud_album_rs::update;
+use icloud_album_rs::watch::{self, EventSink};
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+const USAGE: &str = "Usage: icloud-album serve [--port PORT] [--addr IP] [--root DIR] \
+[--bearer-token TOKEN] [--rate-limit PER_MINUTE] [--burst N] [--album-quota MB_PER_DAY] [--prefetch N] \
+[--config FILE]
+       icloud-album mirror [--config FILE [--once]]   (otherwise configured through ICLOUD_ALBUM_* \
+environment variables)
+       icloud-album frame TOKEN DIR [--width PX] [--height PX] [--addr ADDR] [--interval SECS] \
+[--duration SECS] [--shuffle]
+       icloud-album publish TOKEN DIR [--title TITLE] [--no-captions] [--markdown] [--page-size N] \
+[--css FILE] [--templates DIR] [--rsync DEST] [--s3 URL]
+       icloud-album open TOKEN_OR_NAME [--config FILE] [--print]
+       icloud-album check TOKEN
+       icloud-album history STATE_FILE_OR_ALBUM_DIR
+       icloud-album search INDEX QUERY [--since YYYY-MM-DD] [--until YYYY-MM-DD]   (with the sqlite feature)
+       icloud-album --check-update [COMMAND ...]";
+
+/// Exit code of other failures, including invalid arguments
+const EXIT_FAILURE: i32 = 1;
+/// Exit code of a mirror pass where some photos failed to download
+const EXIT_PARTIAL: i32 = 2;
+/// Exit code of an invalid album token or an album that is gone
+const EXIT_TOKEN: i32 = 3;
+/// Exit code of a pass that could not reach Apple's servers
+const EXIT_NETWORK: i32 = 4;
+
+/// Environment variable holding the bearer token, kept out of process listings
+const TOKEN_VAR: &str = "ICLOUD_ALBUM_SERVER_TOKEN";
+
+/// Parses the value following a flag
+fn value<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> T {
+    match args.next().and_then(|value| value.parse().ok()) {
+        Some(value) => value,
+        None => {
+            eprintln!("Missing or invalid value for {}\n{}", flag, USAGE);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Normalizes an album token or share URL given on the command line
+fn album_token(token: &str) -> String {
+    match normalize_token(token) {
+        Ok(normalized) => {
+            for warning in &normalized.warnings {
+                eprintln!("Note: {}", warning);
+            }
+            normalized.token
+        }
+        Err(e) => {
+            eprintln!("Invalid album token: {}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+/// Asks crates.io whether a newer release is out, printing the answer on stderr
+async fn check_update() {
+    match update::check_for_update(&reqwest::Client::new()).await {
+        Ok(Some(update)) => eprintln!("{}", update.instructions()),
+        Ok(None) => eprintln!(
+            "{} {} is up to date",
+            update::CRATE_NAME,
+            update::CURRENT_VERSION
+        ),
+        Err(e) => eprintln!("Failed to check for updates: {}", e),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = env::args().skip(1).peekable();
+    // Only contact crates.io when asked to
+    if args.next_if(|arg| arg == "--check-update").is_some() {
+        check_update().await;
+        if args.peek().is_none() {
+            return;
+        }
+    }
+    match args.next().as_deref() {
+        Some("serve") => {}
+        Some("mirror") => return mirror(args).await,
+        Some("frame") => return frame::run(args).await,
+        Some("publish") => return publish::run(args).await,
+        Some("open") => return open::run(args),
+        Some("check") => return check::run(args).await,
+        Some("history") => return history::run(args).await,
+        #[cfg(feature = "sqlite")]
+        Some("search") => return search::run(args),
+        #[cfg(not(feature = "sqlite"))]
+        Some("search") => {
+            eprintln!("search needs icloud-album built with the sqlite feature");
+            process::exit(EXIT_FAILURE);
+        }
+        Some("--help" | "-h") => {
+            println!("{}", USAGE);
+            return;
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+
+    let mut ip = "127.0.0.1".to_string();
+    let mut port = DEFAULT_PORT;
+    let mut root = PathBuf::from(".");
+    let mut bearer_token = env::var(TOKEN_VAR).ok().filter(|token| !token.is_empty());
+    let mut rate_limit: Option<u32> = None;
+    let mut burst: Option<u32> = None;
+    let mut album_quota: Option<u64> = None;
+    let mut prefetch: Option<usize> = None;
+    let mut config_file: Option<PathBuf> = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => port = value(&mut args, &arg),
+            "--addr" => ip = value(&mut args, &arg),
+            "--root" => root = value(&mut args, &arg),
+            "--bearer-token" => bearer_token = Some(value(&mut args, &arg)),
+            "--rate-limit" => rate_limit = Some(value(&mut args, &arg)),
+            "--burst" => burst = Some(value(&mut args, &arg)),
+            "--album-quota" => album_quota = Some(value(&mut args, &arg)),
+            "--prefetch" => prefetch = Some(value(&mut args, &arg)),
+            "--config" => config_file = Some(value(&mut args, &arg)),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => {
+                eprintln!("Unknown argument: {}\n{}", arg, USAGE);
+                process::exit(EXIT_FAILURE);
+            }
+        }
+    }
+
+    let mut access = AccessPolicy::new();
+    if let Some(token) = bearer_token {
+        access = access.with_bearer_token(token);
+    }
+    let protected = access.is_protected();
+    let prometheus = Arc::new(PrometheusMetrics::new());
+    metrics::install(prometheus.clone());
+    let mut config = ServerConfig::new(&root)
+        .with_access(access)
+        .with_metrics(prometheus);
+    if rate_limit.is_some() || album_quota.is_some() {
+        let mut limiter = ServeLimiter::new();
+        if let Some(requests) = rate_limit {
+            let limit = RateLimit::new(requests, Duration::from_secs(60));
+            limiter = limiter.with_rate_limit(limit.with_burst(burst.unwrap_or(requests)));
+        }
+        if let Some(megabytes) = album_quota {
+            let quota = BandwidthQuota::new(megabytes * 1024 * 1024, Duration::from_secs(86400));
+            limiter = limiter.with_bandwidth_quota(quota);
+        }
+        config = config.with_limiter(Arc::new(limiter));
+    }
+    if let Some(depth) = prefetch.filter(|&depth| depth > 0) {
+        let predictor = PrefetchPredictor::new(PrefetchConfig::default().with_depth(depth));
+        config = config.with_prefetch(Arc::new(predictor));
+    }
+
+    // Albums to mirror in the background, from the config file or below the root
+    let following = env::var(TOKENS_ENV).is_ok_and(|tokens| !tokens.trim().is_empty());
+    if let Some(path) = config_file {
+        let (app, watcher) = open_config(path).await;
+        let mirror = Arc::new(Mirror::from_app_config(&app));
+        let scheduler = schedule(watcher.config());
+        println!(
+            "Mirroring {} albums from {}",
+            watcher.config().albums.len(),
+            watcher.path().display()
+        );
+        config = config.with_scheduler(Arc::clone(&scheduler));
+        watch(watcher, Arc::clone(&scheduler), Arc::clone(&mirror));
+        tokio::spawn(follow(scheduler, mirror, false));
+    } else if following {
+        let root_dir = root.display().to_string();
+        let lookup = |name: &str| match env::var(name).ok().filter(|value| !value.is_empty()) {
+            None if name == OUTPUT_DIR_ENV => Some(root_dir.clone()),
+            value => value,
+        };
+        let mirror = match MirrorConfig::from_lookup(lookup) {
+            Ok(mirror) => mirror,
+            Err(e) => {
+                eprintln!("{}\n{}", e, USAGE);
+                process::exit(EXIT_FAILURE);
+            }
+        };
+        let albums = albums_from_env(&mirror);
+        let scheduler = schedule(&albums);
+        println!(
+            "Mirroring {} albums below {} every {}s",
+            mirror.tokens.len(),
+            mirror.output_dir.display(),
+            env_interval(&mirror).as_secs()
+        );
+        config = config.with_scheduler(Arc::clone(&scheduler));
+        let mirror = Mirror::from_env(&mirror.config, &albums);
+        tokio::spawn(follow(scheduler, Arc::new(mirror), false));
+    }
+
+    let addr = format!("{}:{}", ip, port);
+    let server = match Server::bind(&addr, config).await {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Failed to listen on {}: {}", addr, e);
+            process::exit(EXIT_FAILURE);
+        }
+    };
+    println!(
+        "Serving albums below {} at {}/rpc",
+        root.display(),
+        server.origin()
+    );
+    if !protected {
+        println!(
+            "Requests are not authenticated; set {} to require a bearer token",
+            TOKEN_VAR
+        );
+    }
+    if let Err(e) = server.run().await {
+        eprintln!("Server stopped: {}", e);
+        process::exit(EXIT_FAILURE);
+    }
+}
+
+/// Albums to mirror, with the options they are fetched and downloaded with
+struct Mirror {
+    /// Directory of each album, by token
+    dirs: Mutex<HashMap<String, PathBuf>>,
+    fetch: FetchOptions,
+    download: DownloadOptions,
+    /// Where the photos each sync downloads are reported
+    sinks: Vec<Box<dyn EventSink>>,
+}
+
+impl Mirror {
+    /// Mirrors `albums` with the settings of the environment
+    fn from_env(settings: &Config, albums: &AlbumsConfig) -> Self {
+        let mirror = Self {
+            dirs: Mutex::default(),
+            fetch: settings.apply_to_fetch(FetchOptions::default()),
+            download: settings.apply_to_download(DownloadOptions::default()),
+            sinks: Vec::new(),
+        };
+        mirror.set_albums(albums);
+        mirror
+    }
+
+    /// Mirrors the albums of a config file with its settings, or exits if its sinks can't be built
+    ///
+    /// Settings the file leaves out come from the environment.
+    fn from_app_config(app: &AppConfig) -> Self {
+        let mut fetch = settings_from_env().apply_to_fetch(FetchOptions::default());
+        if let Some(retry) = app.retry_config() {
+            fetch = fetch.with_retry(RetryPolicies::new(retry));
+        }
+        let sinks = app.build_sinks().unwrap_or_else(|e| {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(EXIT_FAILURE);
+        });
+        let mirror = Self {
+            dirs: Mutex::default(),
+            fetch,
+            download: app.download_options(),
+            sinks,
+        };
+        mirror.set_albums(&app.albums_config());
+        mirror
+    }
+
+    /// Mirrors the albums of `albums` from now on, e.g. after the config file changed
+    fn set_albums(&self, albums: &AlbumsConfig) {
+        let dirs = albums
+            .albums
+            .iter()
+            .map(|album| (album.token.clone(), album.output_dir.clone()))
+            .collect();
+        *self.dirs.lock().unwrap_or_else(|e| e.into_inner()) = dirs;
+    }
+
+    /// Syncs an album into its directory, reporting the outcome on stderr
+    async fn sync(&self, token: &str) -> Result<SyncRun, Box<dyn Error>> {
+        let dir = self
+            .dirs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(token)
+            .cloned()
+            .ok_or("the album is no longer followed")?;
+        let result = server::sync_album(token, &dir, &self.fetch, &self.download).await;
+        match &result {
+            Ok(run) => eprintln!(
+                "Mirrored {} into {}: {} downloaded, {} failed, {} already present",
+                redact_token(token),
+                dir.display(),
+                run.report.downloaded.len(),
+                run.report.failures.len(),
+                run.skipped
+            ),
+            Err(e) => eprintln!("Failed to mirror {}: {}", redact_token(token), Redacted(e)),
+        }
+        if let Ok(run) = &result {
+            self.report(token, run).await;
+        }
+        result
+    }
+
+    /// Reports the photos a sync downloaded to the sinks
+    async fn report(&self, token: &str, run: &SyncRun) {
+        let events = watch::diff_albums(&[], &run.new_photos);
+        if events.is_empty() {
+            return;
+        }
+        for sink in &self.sinks {
+            if let Err(e) = sink.deliver(&run.metadata, &events).await {
+                eprintln!(
+                    "Failed to report new photos of {}: {}",
+                    redact_token(token),
+                    Redacted(e)
+                );
+            }
+        }
+    }
+}
+
+/// Loads a config file and watches its albums, or exits if it can't be loaded
+async fn open_config(path: PathBuf) -> (AppConfig, ConfigWatcher) {
+    let loaded = match AppConfig::load(&path) {
+        Ok(app) => AppConfig::watch(&path).await.map(|watcher| (app, watcher)),
+        Err(e) => Err(e.into()),
+    };
+    loaded.unwrap_or_else(|e| {
+        eprintln!("Failed to load {}: {}\n{}", path.display(), e, USAGE);
+        process::exit(EXIT_FAILURE);
+    })
+}
+
+/// Reads the download settings from the environment, or exits if they are invalid
+fn settings_from_env() -> Config {
+    Config::from_env().unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(EXIT_FAILURE);
+    })
+}
+
+/// The albums configured in the environment, each mirrored below the output directory
+fn albums_from_env(config: &MirrorConfig) -> AlbumsConfig {
+    let interval = env_interval(config);
+    let albums = config
+        .tokens
+        .iter()
+        .map(|token| FollowedAlbum {
+            token: token.clone(),
+            name: None,
+            interval_secs: interval.as_secs(),
+            output_dir: config.album_dir(token),
+        })
+        .collect();
+    AlbumsConfig { albums }
+}
+
+/// The interval of the albums configured in the environment
+fn env_interval(config: &MirrorConfig) -> Duration {
+    config
+        .interval
+        .unwrap_or(Duration::from_secs(DEFAULT_INTERVAL_SECS))
+}
+
+/// A scheduler following every album of `albums` at its interval
+fn schedule(albums: &AlbumsConfig) -> Arc<PollScheduler> {
+    let scheduler = Arc::new(PollScheduler::new());
+    for album in &albums.albums {
+        scheduler.add(&album.token, album.interval());
+    }
+    scheduler
+}
+
+/// Applies the edits of the watched config file to `scheduler` and `mirror` in the background
+fn watch(mut watcher: ConfigWatcher, scheduler: Arc<PollScheduler>, mirror: Arc<Mirror>) {
+    tokio::spawn(async move {
+        watcher
+            .run(|config, changes| {
+                // Directories first, so added albums have one when they are polled
+                mirror.set_albums(config);
+                scheduler.apply(changes);
+                for change in changes {
+                    eprintln!("{}", describe(change));
+                }
+            })
+            .await
+    });
+}
+
+/// Describes a config change for the log, naming the album by its redacted token
+fn describe(change: &ConfigChange) -> String {
+    let album = redact_token(change.token());
+    match change {
+        ConfigChange::AlbumAdded { album: added } => {
+            format!("Following {} into {}", album, added.output_dir.display())
+        }
+        ConfigChange::AlbumRemoved { .. } => format!("No longer following {}", album),
+        ConfigChange::IntervalChanged { new_secs, .. } => {
+            format!("Polling {} every {}s", album, new_secs)
+        }
+        ConfigChange::OutputDirChanged { new_dir, .. } => {
+            format!("Mirroring {} into {}", album, new_dir.display())
+        }
+        ConfigChange::NameChanged { .. } => format!("Renamed {}", album),
+    }
+}
+
+/// Mirrors the albums of `mirror` whenever `scheduler` says they are due, until it shuts down
+///
+/// With `summaries`, every sync prints its summary line on stdout.
+async fn follow(scheduler: Arc<PollScheduler>, mirror: Arc<Mirror>, summaries: bool) {
+    let runtime = Handle::current();
+    scheduler
+        .run(move |token| {
+            let mirror = Arc::clone(&mirror);
+            let runtime = runtime.clone();
+            // Syncs hold errors that aren't `Send` across awaits, while the
+            // scheduler runs polls as tasks, so each runs on the blocking pool
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    runtime.block_on(poll(&mirror, &token, summaries))
+                })
+                .await
+                .map_err(PollError::from)?
+            }
+        })
+        .await
+}
+
+/// Syncs an album for the scheduler, returning whether photos were downloaded
+async fn poll(mirror: &Mirror, token: &str, summaries: bool) -> Result<bool, PollError> {
+    let result = mirror.sync(token).await;
+    if summaries {
+        let mut summary = Summary::default();
+        summary.add(token, &result);
+        summary.print();
+    }
+    match result {
+        Ok(run) => Ok(!run.report.downloaded.is_empty()),
+        // API errors are kept, so the scheduler recognizes rejected tokens
+        Err(e) => Err(match e.downcast::<ApiError>() {
+            Ok(e) => PollError::from(*e),
+            Err(e) => Redacted(e).to_string().into(),
+        }),
+    }
+}
+
+/// Mirrors the albums of the config file or the environment, once or every interval
+async fn mirror(mut args: impl Iterator<Item = String>) {
+    let mut config_file: Option<PathBuf> = None;
+    let mut once = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config_file = Some(value(&mut args, &arg)),
+            "--once" => once = true,
+            _ => {
+                eprintln!("Unknown argument: {}\n{}", arg, USAGE);
+                process::exit(EXIT_FAILURE);
+            }
+        }
+    }
+    if once && config_file.is_none() {
+        eprintln!(
+            "--once needs --config; without it, leave the interval unset\n{}",
+            USAGE
+        );
+        process::exit(EXIT_FAILURE);
+    }
+
+    let (albums, watcher, mirror) = match config_file {
+        Some(path) => {
+            let (app, watcher) = open_config(path).await;
+            let albums = watcher.config().clone();
+            let mirror = Mirror::from_app_config(&app);
+            (albums, Some(watcher).filter(|_| !once), mirror)
+        }
+        None => {
+            let config = match MirrorConfig::from_env() {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("{}\n{}", e, USAGE);
+                    process::exit(EXIT_FAILURE);
+                }
+            };
+            once = config.interval.is_none();
+            let albums = albums_from_env(&config);
+            let mirror = Mirror::from_env(&config.config, &albums);
+            (albums, None, mirror)
+        }
+    };
+    if once {
+        let mut summary = Summary::default();
+        for album in &albums.albums {
+            summary.add(&album.token, &mirror.sync(&album.token).await);
+        }
+        process::exit(summary.print());
+    }
+
+    let scheduler = schedule(&albums);
+    let mirror = Arc::new(mirror);
+    if let Some(watcher) = watcher {
+        watch(watcher, Arc::clone(&scheduler), Arc::clone(&mirror));
+    }
+    follow(scheduler, mirror, true).await;
+}
+
+/// Outcome of syncing one or more albums, printed as a JSON summary line
+#[derive(Default)]
+struct Summary {
+    albums: usize,
+    downloaded: usize,
+    failed: usize,
+    skipped: usize,
+    errors: Vec<serde_json::Value>,
+    classes: Vec<FailureClass>,
+}
+
+impl Summary {
+    /// Counts the outcome of syncing the album with `token`
+    fn add(&mut self, token: &str, result: &Result<SyncRun, Box<dyn Error>>) {
+        self.albums += 1;
+        match result {
+            Ok(run) => {
+                self.downloaded += run.report.downloaded.len();
+                self.failed += run.report.failures.len();
+                self.skipped += run.skipped;
+            }
+            Err(e) => {
+                let class = FailureClass::of(e.as_ref());
+                self.errors.push(json!({
+                    "album": redact_token(token),
+                    "class": format!("{:?}", class).to_lowercase(),
+                    "error": Redacted(e).to_string(),
+                }));
+                self.classes.push(class);
+            }
+        }
+    }
+
+    /// Prints the summary line and returns the exit code of the syncs
+    fn print(&self) -> i32 {
+        let classes = &self.classes;
+        let (code, status) = if classes.contains(&FailureClass::Token) {
+            (EXIT_TOKEN, "token_error")
+        } else if classes.contains(&FailureClass::Network) {
+            (EXIT_NETWORK, "network_error")
+        } else if !classes.is_empty() {
+            (EXIT_FAILURE, "failed")
+        } else if self.failed > 0 {
+            (EXIT_PARTIAL, "partial")
+        } else {
+            (0, "ok")
+        };
+        println!(
+            "{}",
+            json!({
+                "status": status,
+                "exit_code": code,
+                "albums": self.albums,
+                "downloaded": self.downloaded,
+                "failed": self.failed,
+                "skipped": self.skipped,
+                "errors": self.errors,
+            })
+        );
+        code
+    }
+}
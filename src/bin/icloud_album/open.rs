@@ -0,0 +1,65 @@
+//! `icloud-album open`: opens a shared album in the browser from its token
+//!
+//! Config files and scripts usually only store an album's token; this prints
+//! the album's canonical share URL and opens it in the default browser.
+
+use super::{value, EXIT_FAILURE};
+use icloud_album_rs::album::AlbumRef;
+use icloud_album_rs::app_config::AppConfig;
+use std::path::PathBuf;
+use std::process;
+
+/// Runs `icloud-album open TOKEN_OR_NAME [--config FILE] [--print]`
+pub fn run(mut args: impl Iterator<Item = String>) {
+    let Some(album) = args.next() else {
+        eprintln!("{}", super::USAGE);
+        process::exit(EXIT_FAILURE);
+    };
+    let mut config: Option<PathBuf> = None;
+    let mut print_only = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config = Some(value(&mut args, &arg)),
+            "--print" => print_only = true,
+            _ => {
+                eprintln!("Unknown argument: {}\n{}", arg, super::USAGE);
+                process::exit(EXIT_FAILURE);
+            }
+        }
+    }
+
+    // A name from the config takes precedence over reading the argument as a token
+    let mut token = album.clone();
+    if let Some(path) = config {
+        let config = match AppConfig::load(&path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Failed to load {}: {}", path.display(), e);
+                process::exit(EXIT_FAILURE);
+            }
+        };
+        if let Some(followed) = config
+            .albums
+            .iter()
+            .find(|followed| followed.name.as_deref() == Some(album.as_str()))
+        {
+            token = followed.token.clone();
+        }
+    }
+
+    let album = match AlbumRef::parse(&token) {
+        Ok(album) => album,
+        Err(e) => {
+            eprintln!("Invalid album token: {}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    };
+    println!("{}", album.share_url());
+    if print_only {
+        return;
+    }
+    if let Err(e) = album.open_in_browser() {
+        eprintln!("Failed to open a browser: {}", e);
+        process::exit(EXIT_FAILURE);
+    }
+}
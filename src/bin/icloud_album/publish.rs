@@ -0,0 +1,182 @@
+//! `icloud-album publish`: publishes a shared album as a static website
+//!
+//! Fetches the album, downloads a thumbnail and a screen-sized rendition of
+//! every photo (and the videos with their posters), writes an `index.html`
+//! gallery next to them and optionally uploads the directory with `rsync` or
+//! the AWS CLI. Uploads mirror the directory, deleting remote files no longer
+//! in the album.
+//!
+//! Running it again updates the site incrementally: only new and edited
+//! photos are downloaded, and the files of removed ones are deleted.
+//!
+//! iCloud already provides the renditions at fixed sizes, so nothing is
+//! resized locally.
+
+use super::{album_token, value, EXIT_FAILURE};
+use icloud_album_rs::derivatives::SizeClass;
+use icloud_album_rs::download::{download_derivative_classes, DownloadOptions};
+use icloud_album_rs::gallery::{
+    write_gallery_incremental, GalleryManifest, GalleryOptions, TemplateTheme,
+};
+use icloud_album_rs::get_icloud_photos;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+/// Size classes published for every photo
+const CLASSES: &[SizeClass] = &[
+    SizeClass::Thumbnail,
+    SizeClass::Large,
+    SizeClass::VideoPoster,
+    SizeClass::Video,
+];
+
+/// Where the site is uploaded to
+enum Upload {
+    Rsync(String),
+    S3(String),
+}
+
+/// Uploads the site directory, replacing what was published before
+fn upload(site: &Path, upload: &Upload) -> std::io::Result<()> {
+    let mut command = match upload {
+        Upload::Rsync(dest) => {
+            let mut command = Command::new("rsync");
+            // The trailing slash uploads the directory's contents rather than the directory
+            command
+                .arg("-a")
+                .arg("--delete")
+                .arg(format!("{}/", site.display()))
+                .arg(dest);
+            command
+        }
+        Upload::S3(url) => {
+            let mut command = Command::new("aws");
+            command.args(["s3", "sync", "--delete"]).arg(site).arg(url);
+            command
+        }
+    };
+    let status = command.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "upload exited with {}",
+            status
+        )))
+    }
+}
+
+/// Runs `icloud-album publish TOKEN OUTPUT_DIR [flags]`
+pub async fn run(mut args: impl Iterator<Item = String>) {
+    let (Some(token), Some(output_dir)) = (args.next(), args.next()) else {
+        eprintln!("{}", super::USAGE);
+        process::exit(EXIT_FAILURE);
+    };
+    let token = album_token(&token);
+    let mut options = GalleryOptions::new();
+    let mut destination = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--title" => options = options.with_title(value::<String>(&mut args, &arg)),
+            "--no-captions" => options = options.with_captions(false),
+            "--markdown" => options = options.with_markdown_captions(true),
+            "--page-size" => options = options.with_page_size(value(&mut args, &arg)),
+            "--css" => {
+                let path: String = value(&mut args, &arg);
+                match std::fs::read_to_string(&path) {
+                    Ok(css) => options = options.with_css(css),
+                    Err(e) => {
+                        eprintln!("Failed to read {}: {}", path, e);
+                        process::exit(EXIT_FAILURE);
+                    }
+                }
+            }
+            "--templates" => {
+                let dir: PathBuf = value(&mut args, &arg);
+                match TemplateTheme::load(&dir.join("page.html"), &dir.join("photo.html")) {
+                    Ok(theme) => options = options.with_theme(theme),
+                    Err(e) => {
+                        eprintln!("Failed to read the templates in {}: {}", dir.display(), e);
+                        process::exit(EXIT_FAILURE);
+                    }
+                }
+            }
+            "--rsync" => destination = Some(Upload::Rsync(value(&mut args, &arg))),
+            "--s3" => destination = Some(Upload::S3(value(&mut args, &arg))),
+            _ => {
+                eprintln!("Unknown argument: {}\n{}", arg, super::USAGE);
+                process::exit(EXIT_FAILURE);
+            }
+        }
+    }
+
+    println!("Fetching album...");
+    let album = match get_icloud_photos(&token).await {
+        Ok(album) => album,
+        Err(e) => {
+            eprintln!("Failed to fetch the album: {}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    };
+    let site = Path::new(&output_dir);
+    if let Err(e) = std::fs::create_dir_all(site) {
+        eprintln!("Failed to create {}: {}", output_dir, e);
+        process::exit(EXIT_FAILURE);
+    }
+    let manifest = match GalleryManifest::load(site) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("Failed to read the previous build: {}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    };
+    let plan = manifest.plan(&album, site);
+    println!(
+        "Downloading {} new or changed photos of \"{}\" to {} ({} unchanged)",
+        plan.changed.len(),
+        album.metadata.stream_name,
+        output_dir,
+        plan.unchanged.len()
+    );
+    let report = match download_derivative_classes(
+        &plan.changed_photos(&album),
+        &output_dir,
+        CLASSES,
+        &DownloadOptions::new().with_markdown_captions(options.markdown_captions),
+    )
+    .await
+    {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Failed to download the album: {}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    };
+    for (guid, size_class, error) in &report.failures {
+        eprintln!("Failed to download {:?} of {}: {}", size_class, guid, error);
+    }
+
+    match write_gallery_incremental(&album, &report, site, &options, &manifest) {
+        Ok(build) => println!(
+            "Published {} photos on {} pages ({} photos updated, {} pages rewritten, {} files removed)",
+            build.manifest.photos.len(),
+            build.manifest.pages.len(),
+            build.published,
+            build.pages_written,
+            build.removed_files.len()
+        ),
+        Err(e) => {
+            eprintln!("Failed to write the gallery: {}", e);
+            process::exit(EXIT_FAILURE);
+        }
+    }
+
+    if let Some(destination) = &destination {
+        println!("Uploading...");
+        if let Err(e) = upload(site, destination) {
+            eprintln!("Failed to upload the site: {}", e);
+            process::exit(EXIT_FAILURE);
+        }
+        println!("Published");
+    }
+}
@@ -0,0 +1,62 @@
+//! `icloud-album search`: searches the captions of archived albums
+//!
+//! Searches a metadata index kept up to date by mirror runs (see
+//! `MetadataIndex::update_album`) and prints the matching photos, best first.
+
+use super::EXIT_FAILURE;
+use icloud_album_rs::metadata_index::MetadataIndex;
+use icloud_album_rs::models::{format_timestamp, parse_timestamp};
+use icloud_album_rs::search::CaptionQuery;
+use std::process;
+
+/// Parses a `YYYY-MM-DD` day into the Unix timestamp of its start
+fn parse_day(day: &str) -> i64 {
+    parse_timestamp(&format!("{}T00:00:00Z", day)).unwrap_or_else(|| {
+        eprintln!("Invalid date: {}\n{}", day, super::USAGE);
+        process::exit(EXIT_FAILURE);
+    })
+}
+
+/// Runs `icloud-album search INDEX QUERY [--since DAY] [--until DAY]`
+pub fn run(mut args: impl Iterator<Item = String>) {
+    let (Some(path), Some(text)) = (args.next(), args.next()) else {
+        eprintln!("{}", super::USAGE);
+        process::exit(EXIT_FAILURE);
+    };
+    let mut query = CaptionQuery::new(&text);
+    while let Some(arg) = args.next() {
+        query = match (arg.as_str(), args.next()) {
+            ("--since", Some(day)) => query.with_since(parse_day(&day)),
+            ("--until", Some(day)) => query.with_until(parse_day(&day)),
+            _ => {
+                eprintln!("Unknown or incomplete argument: {}\n{}", arg, super::USAGE);
+                process::exit(EXIT_FAILURE);
+            }
+        };
+    }
+
+    let hits = match MetadataIndex::open(&path).and_then(|index| index.search_captions(&query)) {
+        Ok(hits) => hits,
+        Err(e) => {
+            eprintln!("Failed to search {}: {}", path, e);
+            process::exit(EXIT_FAILURE);
+        }
+    };
+    if hits.is_empty() {
+        println!("No photos match \"{}\"", text);
+        return;
+    }
+    for hit in hits {
+        let photo = hit.item;
+        println!(
+            "{:<20} {:<16} {}",
+            photo
+                .taken_at
+                .map(format_timestamp)
+                .unwrap_or_else(|| "undated".to_string()),
+            photo.album,
+            photo.path.display()
+        );
+        println!("    {}", photo.caption.unwrap_or_default());
+    }
+}
@@ -0,0 +1,13 @@
+//! Generates Swift and Kotlin sources for the bindings of the `uniffi` feature
+//!
+//! Run with:
+//! ```text
+//! cargo run --features uniffi-bindgen --bin uniffi-bindgen -- generate \
+//!     --library target/release/libicloud_album_rs.so --language swift --out-dir bindings
+//! ```
+//!
+//! The library is built first, see the `mobile` module.
+
+fn main() {
+    uniffi::uniffi_bindgen_main()
+}
@@ -0,0 +1,211 @@
+//! Short-lived caching of asset URL lookups.
+//!
+//! Asset URLs returned by the webasseturls endpoint stay valid for a while, so
+//! callers that resolve the same photos repeatedly (e.g. refreshing a view or
+//! retrying downloads) can reuse them instead of hitting the API again. This
+//! module provides a small LRU cache keyed by photo GUID and derivative key,
+//! with a time-to-live so expired URLs are never handed out.
+
+use crate::api::{self, ApiError};
+use crate::enrich;
+use crate::models::Image;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A cached URL and its bookkeeping
+#[derive(Debug)]
+struct CacheEntry {
+    url: String,
+    inserted_at: Instant,
+    last_used: u64,
+}
+
+/// Mutable state of the cache, guarded by a mutex
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<(String, String), CacheEntry>,
+    // Monotonic counter used to order entries by recency
+    clock: u64,
+}
+
+/// LRU cache mapping (photo GUID, derivative key) to an asset URL
+///
+/// Entries expire after the configured time-to-live. When the cache is full,
+/// the least recently used entry is evicted. The cache is internally
+/// synchronized and can be shared between tasks by reference.
+#[derive(Debug)]
+pub struct AssetUrlCache {
+    state: Mutex<CacheState>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl Default for AssetUrlCache {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY, Self::DEFAULT_TTL)
+    }
+}
+
+impl AssetUrlCache {
+    /// Default maximum number of cached URLs
+    pub const DEFAULT_CAPACITY: usize = 1024;
+
+    /// Default lifetime of a cached URL
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(10 * 60);
+
+    /// Create a cache holding at most `capacity` URLs for `ttl` each
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            state: Mutex::new(CacheState::default()),
+            capacity,
+            ttl,
+        }
+    }
+
+    /// Returns the cached URL of a derivative, if present and not expired
+    pub fn get(&self, photo_guid: &str, derivative_key: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.clock += 1;
+        let now = state.clock;
+
+        let key = (photo_guid.to_string(), derivative_key.to_string());
+        let expired = match state.entries.get_mut(&key) {
+            Some(entry) if entry.inserted_at.elapsed() < self.ttl => {
+                entry.last_used = now;
+                return Some(entry.url.clone());
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            state.entries.remove(&key);
+        }
+        None
+    }
+
+    /// Stores the URL of a derivative, evicting the least recently used entry if full
+    pub fn insert(&self, photo_guid: &str, derivative_key: &str, url: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.clock += 1;
+        let now = state.clock;
+
+        let key = (photo_guid.to_string(), derivative_key.to_string());
+        if !state.entries.contains_key(&key) && state.entries.len() >= self.capacity {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+            }
+        }
+
+        state.entries.insert(
+            key,
+            CacheEntry {
+                url: url.to_string(),
+                inserted_at: Instant::now(),
+                last_used: now,
+            },
+        );
+    }
+
+    /// Returns the number of entries, including expired ones not yet evicted
+    pub fn len(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entries
+            .len()
+    }
+
+    /// Returns true if the cache holds no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every entry
+    pub fn clear(&self) {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entries
+            .clear();
+    }
+}
+
+/// Fills in derivative URLs, consulting the cache before the API
+///
+/// Photos whose derivatives are all cached are enriched without a request.
+/// The remaining photos are resolved with a single webasseturls request, and
+/// the resulting URLs are added to the cache.
+///
+/// # Arguments
+///
+/// * `client` - A reqwest HTTP client
+/// * `base_url` - The base URL for API requests
+/// * `photos` - The photos to enrich
+/// * `cache` - The cache to consult and update
+///
+/// # Returns
+///
+/// The number of photos that had to be resolved through the API
+pub async fn enrich_photos_cached(
+    client: &reqwest::Client,
+    base_url: &str,
+    photos: &mut [Image],
+    cache: &AssetUrlCache,
+) -> Result<usize, ApiError> {
+    let mut missing: Vec<usize> = Vec::new();
+
+    for (index, photo) in photos.iter_mut().enumerate() {
+        let cached: Option<Vec<(String, String)>> = photo
+            .derivatives
+            .keys()
+            .map(|key| {
+                cache
+                    .get(&photo.photo_guid, key)
+                    .map(|url| (key.clone(), url))
+            })
+            .collect();
+
+        match cached {
+            Some(urls) if !urls.is_empty() => {
+                for (key, url) in urls {
+                    if let Some(derivative) = photo.derivatives.get_mut(&key) {
+                        derivative.url = Some(url);
+                    }
+                }
+            }
+            _ => missing.push(index),
+        }
+    }
+
+    if missing.is_empty() {
+        return Ok(0);
+    }
+
+    let guids: Vec<String> = missing
+        .iter()
+        .map(|&index| photos[index].photo_guid.clone())
+        .collect();
+    let all_urls = api::get_asset_urls(client, base_url, &guids).await?;
+
+    for &index in &missing {
+        let photo = &mut photos[index];
+        enrich::enrich_photos_with_urls(std::slice::from_mut(photo), &all_urls);
+        for (key, derivative) in &photo.derivatives {
+            if let Some(url) = &derivative.url {
+                cache.insert(&photo.photo_guid, key, url);
+            }
+        }
+    }
+
+    Ok(missing.len())
+}
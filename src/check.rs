@@ -0,0 +1,519 @@
+//! Dry-run compatibility check of an album against the expected API schema.
+//!
+//! When Apple changes the shared album API, users see downloads fail or photos
+//! go missing long before anyone knows which field changed. [`check_album`]
+//! fetches an album's webstream response and the asset URLs of a sample of
+//! its photos, without downloading any asset, and compares them with the
+//! [JSON Schemas](crate::schema) this crate expects. The resulting
+//! [`CompatibilityReport`] lists every field pattern seen, fields the schema
+//! doesn't know, values of unexpected types, the strict schema validation
+//! issues and whatever the parsers had to skip. It is the first thing to run
+//! when a breakage is reported:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use icloud_album_rs::check;
+//! use icloud_album_rs::fetch::FetchOptions;
+//!
+//! let report = check::check_album("B0z5qAGN1JIFd3y", &FetchOptions::new()).await?;
+//! print!("{}", report);
+//! if !report.is_compatible() {
+//!     std::process::exit(1);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`check_responses`] produces the same report for recorded responses.
+
+use crate::api::{self, ApiError, IssueSeverity, SchemaIssueGroup, ValidationFailure};
+use crate::fetch::FetchOptions;
+use crate::models::WebstreamRequest;
+use crate::redirect::{self, WebstreamProbe};
+use crate::schema;
+use crate::url_policy::UrlPolicy;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Most photos whose asset URLs [`check_album`] requests
+pub const SAMPLE_SIZE: usize = 25;
+
+/// A field pattern seen in a response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSummary {
+    /// The response the field was seen in, `"webstream"` or `"webasseturls"`
+    pub response: &'static str,
+    /// Path of the field with per-item indices and keys generalized, e.g.
+    /// `photos[].derivatives.*.width`
+    pub pattern: String,
+    /// JSON types the field had, e.g. `["integer", "string"]`
+    pub types: Vec<&'static str>,
+    /// Number of times the field was seen
+    pub count: usize,
+    /// Whether the schema describes the field
+    pub known: bool,
+}
+
+/// A field whose values had a type the schema doesn't allow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeDrift {
+    /// The response the field was seen in, `"webstream"` or `"webasseturls"`
+    pub response: &'static str,
+    /// Generalized path of the field
+    pub pattern: String,
+    /// Types the schema allows, e.g. `["integer", "string"]`
+    pub expected: Vec<&'static str>,
+    /// The type that was found instead
+    pub found: &'static str,
+    /// Number of values of that type
+    pub count: usize,
+    /// The concrete path of the first such value
+    pub example: String,
+}
+
+/// What a compatibility check found
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct CompatibilityReport {
+    /// Every field pattern seen, in order of response and pattern
+    pub fields: Vec<FieldSummary>,
+    /// Fields with values of unexpected types
+    pub type_drifts: Vec<TypeDrift>,
+    /// Grouped issues of the strict schema validation of both responses
+    pub schema_issues: Vec<SchemaIssueGroup>,
+    /// Number of photos in the webstream response
+    pub photos: usize,
+    /// Photos the parser skipped, as (index in the `photos` array, error message)
+    pub skipped_photos: Vec<(usize, String)>,
+    /// Number of asset URLs resolved, or `None` if no webasseturls response was checked
+    pub asset_urls: Option<usize>,
+    /// Assets the parser left out, as (checksum, reason)
+    pub skipped_assets: Vec<(String, String)>,
+    /// Why a response could not be parsed at all, if it couldn't
+    pub parse_errors: Vec<String>,
+}
+
+impl CompatibilityReport {
+    /// Returns the fields the schema doesn't describe
+    ///
+    /// New fields are usually harmless, but are the first sign of a change.
+    pub fn unknown_fields(&self) -> impl Iterator<Item = &FieldSummary> {
+        self.fields.iter().filter(|field| !field.known)
+    }
+
+    /// Returns true if the album can be fetched without losing data
+    ///
+    /// That is, both responses parsed, nothing was skipped, no value had an
+    /// unexpected type and no schema issue is critical. Unknown fields don't
+    /// affect compatibility.
+    pub fn is_compatible(&self) -> bool {
+        self.parse_errors.is_empty()
+            && self.skipped_photos.is_empty()
+            && self.skipped_assets.is_empty()
+            && self.type_drifts.is_empty()
+            && self
+                .schema_issues
+                .iter()
+                .all(|group| group.severity < IssueSeverity::Critical)
+    }
+}
+
+impl fmt::Display for CompatibilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = if self.is_compatible() {
+            "compatible"
+        } else {
+            "INCOMPATIBLE"
+        };
+        writeln!(f, "Compatibility: {}", verdict)?;
+        write!(f, "Photos: {}", self.photos)?;
+        if !self.skipped_photos.is_empty() {
+            write!(f, " ({} skipped)", self.skipped_photos.len())?;
+        }
+        writeln!(f)?;
+        match self.asset_urls {
+            Some(count) if self.skipped_assets.is_empty() => writeln!(f, "Asset URLs: {}", count)?,
+            Some(count) => writeln!(
+                f,
+                "Asset URLs: {} ({} skipped)",
+                count,
+                self.skipped_assets.len()
+            )?,
+            None => writeln!(f, "Asset URLs: not checked")?,
+        }
+
+        for error in &self.parse_errors {
+            writeln!(f, "Parse error: {}", error)?;
+        }
+        section(
+            f,
+            "Skipped photos",
+            &self.skipped_photos,
+            |f, (index, error)| writeln!(f, "  photos[{}]: {}", index, error),
+        )?;
+        section(
+            f,
+            "Skipped assets",
+            &self.skipped_assets,
+            |f, (checksum, reason)| writeln!(f, "  {}: {}", checksum, reason),
+        )?;
+        section(f, "Type drifts", &self.type_drifts, |f, drift| {
+            writeln!(
+                f,
+                "  {} {}: expected {}, found {} {} time(s), e.g. {}",
+                drift.response,
+                drift.pattern,
+                drift.expected.join(" or "),
+                drift.found,
+                drift.count,
+                drift.example
+            )
+        })?;
+        section(f, "Schema issues", &self.schema_issues, |f, group| {
+            let problem = match &group.failure {
+                ValidationFailure::Missing => "missing".to_string(),
+                ValidationFailure::WrongType => "wrong type".to_string(),
+                ValidationFailure::InvalidValue(message) => format!("invalid: {}", message),
+            };
+            writeln!(
+                f,
+                "  [{:?}] {}: {} in {} item(s), e.g. {}",
+                group.severity, group.field_pattern, problem, group.count, group.example
+            )
+        })?;
+        let unknown: Vec<&FieldSummary> = self.unknown_fields().collect();
+        section(f, "Unknown fields", &unknown, |f, field| {
+            writeln!(
+                f,
+                "  {} {} ({}, {} time(s))",
+                field.response,
+                field.pattern,
+                field.types.join(", "),
+                field.count
+            )
+        })?;
+        section(f, "Fields seen", &self.fields, |f, field| {
+            writeln!(
+                f,
+                "  {} {} ({}, {} time(s))",
+                field.response,
+                field.pattern,
+                field.types.join(", "),
+                field.count
+            )
+        })
+    }
+}
+
+/// Writes a titled list, or nothing if it is empty
+fn section<T>(
+    f: &mut fmt::Formatter<'_>,
+    title: &str,
+    items: &[T],
+    mut line: impl FnMut(&mut fmt::Formatter<'_>, &T) -> fmt::Result,
+) -> fmt::Result {
+    if items.is_empty() {
+        return Ok(());
+    }
+    writeln!(f, "{} ({}):", title, items.len())?;
+    items.iter().try_for_each(|item| line(f, item))
+}
+
+/// Checks an album's API responses without downloading any asset
+///
+/// The webstream response is fetched like [`crate::get_icloud_photos_with`]
+/// does, following a redirect to another partition, and the asset URLs of up
+/// to [`SAMPLE_SIZE`] of its photos are requested. Only `client` and
+/// `base_url` of `options` are used; requests are not retried, so that the
+/// report shows what the API answered.
+///
+/// # Arguments
+///
+/// * `token` - The iCloud shared album token
+/// * `options` - How to reach the album
+///
+/// # Returns
+///
+/// The report, or an error if the album could not be reached at all
+pub async fn check_album(
+    token: &str,
+    options: &FetchOptions,
+) -> Result<CompatibilityReport, Box<dyn std::error::Error>> {
+    let client = options.client.clone().unwrap_or_default();
+    let request = WebstreamRequest::default();
+    let mut probe = match options.base_url.as_deref() {
+        Some(base_url) => redirect::probe_webstream(&client, base_url, token, &request).await?,
+        None => redirect::probe_album(&client, token, &request).await?,
+    };
+    if let WebstreamProbe::Redirected(base_url) = &probe {
+        probe = redirect::probe_webstream(&client, base_url, token, &request).await?;
+    }
+    let (base_url, webstream) = into_webstream(probe)?;
+    let photo_guids = sample_guids(&webstream);
+    let webasseturls = if photo_guids.is_empty() {
+        None
+    } else {
+        Some(request_asset_urls(&client, &base_url, &photo_guids).await?)
+    };
+
+    Ok(check_responses(&webstream, webasseturls.as_ref()))
+}
+
+/// Returns the base URL and webstream body of a probe that wasn't redirected
+pub(crate) fn into_webstream(probe: WebstreamProbe) -> Result<(String, Value), ApiError> {
+    let base_url = probe.base_url().to_string();
+    match probe.into_body() {
+        Some(body) => Ok((base_url, body)),
+        None => Err(ApiError::Other(format!(
+            "no webstream response from {}",
+            crate::redact::Redacted(&base_url)
+        ))),
+    }
+}
+
+/// Returns the GUIDs of up to [`SAMPLE_SIZE`] photos of a webstream response
+pub(crate) fn sample_guids(webstream: &Value) -> Vec<&str> {
+    webstream
+        .get("photos")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|photo| photo.get("photoGuid")?.as_str())
+        .take(SAMPLE_SIZE)
+        .collect()
+}
+
+/// Requests the raw webasseturls response for some photos, without retries
+pub(crate) async fn request_asset_urls(
+    client: &reqwest::Client,
+    base_url: &str,
+    photo_guids: &[&str],
+) -> Result<Value, ApiError> {
+    let url = format!("{}webasseturls", base_url);
+    let payload = serde_json::json!({ "photoGuids": photo_guids });
+    let resp = crate::har::send(api::api_request(client.post(&url).json(&payload))).await?;
+    if !resp.status().is_success() {
+        return Err(ApiError::RequestError {
+            status: Some(resp.status().as_u16()),
+            message: "webasseturls request failed".to_string(),
+        });
+    }
+    api::api_json(resp).await
+}
+
+/// Checks recorded API responses
+///
+/// # Arguments
+///
+/// * `webstream` - The JSON body of a webstream response
+/// * `webasseturls` - The JSON body of a webasseturls response, if there is one
+///
+/// # Returns
+///
+/// What [`check_album`] would report for these responses
+pub fn check_responses(webstream: &Value, webasseturls: Option<&Value>) -> CompatibilityReport {
+    let mut report = CompatibilityReport::default();
+    let mut walk = Walk::default();
+    walk.value(
+        "webstream",
+        webstream,
+        Some(&schema::webstream_schema()),
+        "",
+    );
+
+    let mut issues = api::check_api_schema(webstream, "webstream");
+    report.photos = webstream
+        .get("photos")
+        .and_then(Value::as_array)
+        .map_or(0, Vec::len);
+    match api::parse_webstream_response(webstream) {
+        Ok(response) => report.skipped_photos = response.report.skipped_photos,
+        Err(e) => report.parse_errors.push(format!("webstream: {}", e)),
+    }
+
+    if let Some(webasseturls) = webasseturls {
+        let schema = schema::webasseturls_schema();
+        walk.value("webasseturls", webasseturls, Some(&schema), "");
+        issues.extend(api::check_api_schema(webasseturls, "webasseturls"));
+        match api::parse_webasseturls_response_with_report(webasseturls, &UrlPolicy::default()) {
+            Ok(response) => {
+                report.asset_urls = Some(response.urls.len());
+                report.skipped_assets = response.report.skipped_assets;
+            }
+            Err(e) => report.parse_errors.push(format!("webasseturls: {}", e)),
+        }
+    }
+
+    report.schema_issues = api::group_schema_issues(&issues);
+    report.fields = walk
+        .fields
+        .into_iter()
+        .map(|((response, pattern), field)| FieldSummary {
+            response,
+            pattern,
+            types: field.types,
+            count: field.count,
+            known: field.known,
+        })
+        .collect();
+    report.type_drifts = walk.drifts;
+    report
+}
+
+/// A field seen while walking a response
+#[derive(Default)]
+struct SeenField {
+    types: Vec<&'static str>,
+    count: usize,
+    known: bool,
+}
+
+/// Walks a response alongside its schema
+#[derive(Default)]
+struct Walk {
+    fields: BTreeMap<(&'static str, String), SeenField>,
+    drifts: Vec<TypeDrift>,
+}
+
+impl Walk {
+    /// Records `value` at `path`, then the fields inside it
+    ///
+    /// `pattern` is the generalized path, and `schema` is `None` for fields
+    /// the schema doesn't describe, which are recorded but not descended into.
+    fn value(&mut self, response: &'static str, value: &Value, schema: Option<&Value>, path: &str) {
+        self.walk(response, value, schema, path, path);
+    }
+
+    fn walk(
+        &mut self,
+        response: &'static str,
+        value: &Value,
+        schema: Option<&Value>,
+        path: &str,
+        pattern: &str,
+    ) {
+        let found = json_type(value);
+        if !pattern.is_empty() {
+            let field = self
+                .fields
+                .entry((response, pattern.to_string()))
+                .or_default();
+            field.count += 1;
+            field.known = schema.is_some();
+            if !field.types.contains(&found) {
+                field.types.push(found);
+                field.types.sort_unstable();
+            }
+        }
+        let Some(schema) = schema else {
+            return;
+        };
+
+        let expected = schema_types(schema);
+        let allowed = expected.is_empty()
+            || expected.contains(&found)
+            || (found == "integer" && expected.contains(&"number"));
+        if !allowed {
+            match self
+                .drifts
+                .iter_mut()
+                .find(|d| d.response == response && d.pattern == pattern && d.found == found)
+            {
+                Some(drift) => drift.count += 1,
+                None => self.drifts.push(TypeDrift {
+                    response,
+                    pattern: pattern.to_string(),
+                    expected,
+                    found,
+                    count: 1,
+                    example: path.to_string(),
+                }),
+            }
+            return;
+        }
+
+        match value {
+            Value::Object(entries) => {
+                let properties = schema.get("properties").and_then(Value::as_object);
+                let additional = schema.get("additionalProperties");
+                for (key, value) in entries {
+                    let (schema, segment) = match properties.and_then(|p| p.get(key)) {
+                        Some(schema) => (Some(schema), key.as_str()),
+                        None => match additional {
+                            Some(schema) if schema.is_object() => (Some(schema), "*"),
+                            _ => (None, key.as_str()),
+                        },
+                    };
+                    self.walk(
+                        response,
+                        value,
+                        schema,
+                        &join(path, key),
+                        &join(pattern, segment),
+                    );
+                }
+            }
+            Value::Array(items) => {
+                let schema = schema.get("items");
+                for (index, item) in items.iter().enumerate() {
+                    self.walk(
+                        response,
+                        item,
+                        schema,
+                        &format!("{}[{}]", path, index),
+                        &format!("{}[]", pattern),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Appends a key to a field path
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Name of a value's JSON type, as used by JSON Schema
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Types a schema allows, or none if it allows any
+fn schema_types(schema: &Value) -> Vec<&'static str> {
+    const TYPES: &[&str] = &[
+        "array", "boolean", "integer", "null", "number", "object", "string",
+    ];
+    let names: Vec<&str> = match schema.get("type") {
+        Some(Value::String(name)) => vec![name.as_str()],
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+        _ => schema
+            .get("anyOf")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .flat_map(schema_types)
+            .collect(),
+    };
+    let mut types: Vec<&'static str> = names
+        .into_iter()
+        .filter_map(|name| TYPES.iter().find(|t| **t == name).copied())
+        .collect();
+    types.sort_unstable();
+    types.dedup();
+    types
+}
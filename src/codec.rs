@@ -0,0 +1,61 @@
+//! Decoding and encoding common image formats with the `image` crate.
+//!
+//! The image features take their decoders and encoders as traits, so
+//! applications can plug in the library they already use. [`StandardDecoder`]
+//! implements them for JPEG, PNG and binary PGM/PPM, which covers the JPEG
+//! derivatives iCloud serves for most photos. HEIC originals aren't
+//! supported; ask for a JPEG size class such as `thumb` or `medium` instead.
+//!
+//! Perceptual hashes are computed from the luminance of the decoded images,
+//! and placeholders and resized images from their RGB pixels.
+//!
+//! ```no_run
+//! # #[cfg(feature = "resize")]
+//! # fn example() {
+//! use icloud_album_rs::codec::StandardDecoder;
+//! use icloud_album_rs::resize::{JpegEncoder, Resizer};
+//! use std::sync::Arc;
+//!
+//! let resizer = Resizer::new(Arc::new(StandardDecoder), Arc::new(JpegEncoder::default()));
+//! # }
+//! ```
+
+#[cfg(feature = "phash")]
+use crate::phash::{GrayImage, ImageDecoder};
+#[cfg(feature = "placeholders")]
+use crate::placeholder::{RgbDecoder, RgbImage};
+
+/// Error returned by [`StandardDecoder`]
+type DecodeError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Decoder for JPEG, PNG and binary PGM/PPM images
+///
+/// The format is detected from the content, so the file name doesn't matter.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StandardDecoder;
+
+impl StandardDecoder {
+    /// Decodes the contents of an image file of a supported format
+    fn decode_image(&self, bytes: &[u8]) -> Result<image::DynamicImage, DecodeError> {
+        Ok(image::load_from_memory(bytes)?)
+    }
+}
+
+#[cfg(feature = "phash")]
+impl ImageDecoder for StandardDecoder {
+    fn decode(&self, bytes: &[u8]) -> Result<GrayImage, DecodeError> {
+        let image = self.decode_image(bytes)?.into_luma8();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        GrayImage::new(width, height, image.into_raw()).ok_or_else(|| "Image is empty".into())
+    }
+}
+
+#[cfg(feature = "placeholders")]
+impl RgbDecoder for StandardDecoder {
+    fn decode(&self, bytes: &[u8]) -> Result<RgbImage, DecodeError> {
+        let image = self.decode_image(bytes)?.into_rgb8();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let pixels = image.pixels().map(|pixel| pixel.0).collect();
+        RgbImage::new(width, height, pixels).ok_or_else(|| "Image is empty".into())
+    }
+}
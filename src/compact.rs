@@ -0,0 +1,296 @@
+//! A compact, byte-budgeted album model for memory-constrained devices.
+//!
+//! An [`ICloudResponse`] keeps every derivative, caption and location of every
+//! photo, in many small heap allocations. Devices that only list an album and
+//! show thumbnails, such as e-ink frames or small single-board computers,
+//! need a fraction of that. [`CompactAlbum`] keeps, per photo, the GUID, the
+//! URL, dimensions and time of the smallest still derivative, with all strings
+//! packed into one buffer and URL origins interned. Photos beyond an optional
+//! byte budget are left out rather than exceeding it.
+//!
+//! Select it with [`FetchOptions::with_compact`], which also trims photos
+//! while they are fetched, and build it with [`CompactAlbum::fetch`]:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use icloud_album_rs::compact::{CompactAlbum, CompactOptions};
+//! use icloud_album_rs::fetch::FetchOptions;
+//!
+//! let options = FetchOptions::new().with_compact(CompactOptions::new().with_max_bytes(64 * 1024));
+//! let album = CompactAlbum::fetch("B0z5qAGN1JIFd3y", &options).await?;
+//! for photo in album.photos() {
+//!     println!("{} {:?}", photo.guid, photo.url());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`FetchOptions::with_compact`]: crate::fetch::FetchOptions::with_compact
+
+use crate::fetch::FetchOptions;
+use crate::models::{parse_timestamp, Derivative, ICloudResponse, Image};
+use crate::utils;
+use std::collections::HashMap;
+use std::mem;
+
+/// Options for [`CompactAlbum`], see [`FetchOptions::with_compact`]
+///
+/// [`FetchOptions::with_compact`]: crate::fetch::FetchOptions::with_compact
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CompactOptions {
+    /// Most bytes the album may take, or `None` for no limit
+    pub max_bytes: Option<usize>,
+}
+
+impl CompactOptions {
+    /// Create options without a byte budget
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Leave out photos that would make the album exceed `max_bytes`
+    #[must_use]
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+}
+
+/// Reduces a photo to what a [`CompactAlbum`] keeps
+///
+/// Only the smallest still derivative is left, without the fields used to
+/// classify it, and all optional fields except the creation date are dropped.
+pub(crate) fn trim_photo(photo: Image) -> Image {
+    let derivatives = utils::select_thumbnail_derivative(&photo.derivatives)
+        .map(|(key, derivative)| {
+            let derivative = Derivative {
+                checksum: derivative.checksum.clone(),
+                width: derivative.width,
+                height: derivative.height,
+                url: derivative.url.clone(),
+                ..Default::default()
+            };
+            HashMap::from([(key, derivative)])
+        })
+        .unwrap_or_default();
+    Image {
+        photo_guid: photo.photo_guid,
+        derivatives,
+        date_created: photo.date_created,
+        ..Default::default()
+    }
+}
+
+/// Position of a string in a [`CompactAlbum`]'s buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Span {
+    start: u32,
+    end: u32,
+}
+
+/// A photo as stored in a [`CompactAlbum`]
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    guid: Span,
+    /// Index of the URL's origin in `origins`, and the rest of the URL
+    url: Option<(u32, Span)>,
+    width: Option<u32>,
+    height: Option<u32>,
+    taken_at: Option<i64>,
+}
+
+/// A photo of a [`CompactAlbum`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactPhoto<'a> {
+    /// The photo's GUID
+    pub guid: &'a str,
+    /// Width of the kept derivative in pixels, if known
+    pub width: Option<u32>,
+    /// Height of the kept derivative in pixels, if known
+    pub height: Option<u32>,
+    /// When the photo was taken, in seconds since the Unix epoch, if known
+    pub taken_at: Option<i64>,
+    url: Option<(&'a str, &'a str)>,
+}
+
+impl CompactPhoto<'_> {
+    /// Returns the URL of the kept derivative, if it was resolved
+    pub fn url(&self) -> Option<String> {
+        self.url.map(|(origin, rest)| format!("{}{}", origin, rest))
+    }
+}
+
+/// An album reduced to what listing it and fetching thumbnails needs
+#[derive(Debug, Clone, Default)]
+pub struct CompactAlbum {
+    name: Box<str>,
+    strings: String,
+    origins: Vec<Span>,
+    entries: Vec<Entry>,
+    omitted: usize,
+}
+
+impl CompactAlbum {
+    /// Fetches an album straight into its compact form
+    ///
+    /// Photos are trimmed while they are fetched, as if `options` had
+    /// [`FetchOptions::with_compact`] set; its budget, if any, applies.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The iCloud shared album token
+    /// * `options` - How to fetch the album
+    pub async fn fetch(
+        token: &str,
+        options: &FetchOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let compact = options.compact.unwrap_or_default();
+        let options = options.clone().with_compact(compact);
+        let response = crate::get_icloud_photos_with(token, &options).await?;
+        Ok(Self::from_response(&response, &compact))
+    }
+
+    /// Builds the compact form of a fetched album
+    ///
+    /// Photos are kept in album order until the next one would exceed the
+    /// budget of `options`; the number left out is [`CompactAlbum::omitted`].
+    pub fn from_response(response: &ICloudResponse, options: &CompactOptions) -> Self {
+        let budget = options.max_bytes.unwrap_or(usize::MAX);
+        let mut album = Self {
+            name: response.metadata.stream_name.as_str().into(),
+            ..Default::default()
+        };
+        let mut origins: HashMap<&str, u32> = HashMap::new();
+
+        for (index, photo) in response.photos.iter().enumerate() {
+            let derivative = utils::select_thumbnail_derivative(&photo.derivatives)
+                .map(|(_, derivative)| derivative);
+            let url = derivative.and_then(|d| d.url.as_deref()).map(split_origin);
+
+            // Count what the photo adds before storing anything
+            let new_origin = url
+                .map(|(origin, _)| !origins.contains_key(origin))
+                .unwrap_or(false);
+            let added = photo.photo_guid.len()
+                + url.map_or(0, |(origin, rest)| {
+                    rest.len()
+                        + if new_origin {
+                            origin.len() + mem::size_of::<Span>()
+                        } else {
+                            0
+                        }
+                })
+                + mem::size_of::<Entry>();
+            if album.bytes().saturating_add(added) > budget {
+                album.omitted = response.photos.len() - index;
+                break;
+            }
+
+            let guid = album.push(&photo.photo_guid);
+            let url = url.map(|(origin, rest)| {
+                let origin = match origins.get(origin) {
+                    Some(&id) => id,
+                    None => {
+                        let span = album.push(origin);
+                        album.origins.push(span);
+                        let id = (album.origins.len() - 1) as u32;
+                        origins.insert(origin, id);
+                        id
+                    }
+                };
+                (origin, album.push(rest))
+            });
+            album.entries.push(Entry {
+                guid,
+                url,
+                width: derivative.and_then(|d| d.width),
+                height: derivative.and_then(|d| d.height),
+                taken_at: photo.date_created.as_deref().and_then(parse_timestamp),
+            });
+        }
+
+        album.strings.shrink_to_fit();
+        album.origins.shrink_to_fit();
+        album.entries.shrink_to_fit();
+        album
+    }
+
+    /// Name of the album
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Number of photos kept
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no photo was kept
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of photos left out to stay within the byte budget
+    pub fn omitted(&self) -> usize {
+        self.omitted
+    }
+
+    /// Returns the photo at `index`, in album order
+    pub fn get(&self, index: usize) -> Option<CompactPhoto<'_>> {
+        self.entries.get(index).map(|entry| self.photo(entry))
+    }
+
+    /// Returns the photos, in album order
+    pub fn photos(&self) -> impl Iterator<Item = CompactPhoto<'_>> + '_ {
+        self.entries.iter().map(|entry| self.photo(entry))
+    }
+
+    /// Bytes the album takes, counting its heap allocations and itself
+    pub fn bytes(&self) -> usize {
+        mem::size_of::<Self>()
+            + self.name.len()
+            + self.strings.len()
+            + self.origins.len() * mem::size_of::<Span>()
+            + self.entries.len() * mem::size_of::<Entry>()
+    }
+
+    /// Appends a string to the buffer
+    fn push(&mut self, text: &str) -> Span {
+        let start = self.strings.len() as u32;
+        self.strings.push_str(text);
+        Span {
+            start,
+            end: self.strings.len() as u32,
+        }
+    }
+
+    /// Returns a string of the buffer
+    fn text(&self, span: Span) -> &str {
+        self.strings
+            .get(span.start as usize..span.end as usize)
+            .unwrap_or_default()
+    }
+
+    fn photo(&self, entry: &Entry) -> CompactPhoto<'_> {
+        CompactPhoto {
+            guid: self.text(entry.guid),
+            width: entry.width,
+            height: entry.height,
+            taken_at: entry.taken_at,
+            url: entry.url.map(|(origin, rest)| {
+                let origin = self.origins.get(origin as usize).copied();
+                (origin.map_or("", |span| self.text(span)), self.text(rest))
+            }),
+        }
+    }
+}
+
+/// Splits a URL into its origin, e.g. `https://host:443`, and the rest
+fn split_origin(url: &str) -> (&str, &str) {
+    let authority = url.find("://").map_or(0, |index| index + 3);
+    let end = url[authority..]
+        .find(['/', '?', '#'])
+        .map_or(url.len(), |index| authority + index);
+    url.split_at(end)
+}
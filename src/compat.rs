@@ -0,0 +1,44 @@
+//! Shims for std APIs newer than the crate's minimum supported Rust version.
+//!
+//! `build.rs` enables a `has_*` cfg for each API the compiling toolchain
+//! provides; older toolchains get an equivalent fallback. Code elsewhere in
+//! the crate uses these shims instead of the APIs directly, which
+//! `clippy::incompatible_msrv` enforces.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Makes `path` absolute without touching the filesystem, like [`std::path::absolute`]
+#[cfg(has_path_absolute)]
+#[allow(clippy::incompatible_msrv)]
+pub(crate) fn absolute(path: &Path) -> io::Result<PathBuf> {
+    std::path::absolute(path)
+}
+
+/// Makes `path` absolute without touching the filesystem, like `std::path::absolute`
+#[cfg(not(has_path_absolute))]
+pub(crate) fn absolute(path: &Path) -> io::Result<PathBuf> {
+    if path.is_absolute() {
+        Ok(path.to_path_buf())
+    } else {
+        Ok(std::env::current_dir()?.join(path))
+    }
+}
+
+/// Whether an operation failed because it would have crossed filesystems
+#[cfg(has_crosses_devices)]
+#[allow(clippy::incompatible_msrv)]
+pub(crate) fn is_crosses_devices(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::CrossesDevices
+}
+
+/// Whether an operation failed because it would have crossed filesystems
+#[cfg(not(has_crosses_devices))]
+pub(crate) fn is_crosses_devices(error: &io::Error) -> bool {
+    // EXDEV on Unix, ERROR_NOT_SAME_DEVICE on Windows
+    match error.raw_os_error() {
+        Some(18) => cfg!(unix),
+        Some(17) => cfg!(windows),
+        _ => false,
+    }
+}
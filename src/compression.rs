@@ -0,0 +1,195 @@
+//! Compressed API responses.
+//!
+//! Webstream responses of large albums are several megabytes of JSON, which
+//! gzip and brotli shrink to a fraction of that. With this module, requests to
+//! the API endpoints advertise the encodings enabled by
+//! [`set_accept_encoding`] (both by default) and compressed responses are
+//! decoded before parsing. Downloads are not affected: photos and videos are
+//! already compressed.
+//!
+//! [`compression_stats`] reports how many bytes API responses took on the
+//! wire and after decoding, so the savings can be measured.
+
+use crate::logging::{self, log_debug};
+use reqwest::header::{HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// The encodings API responses may be compressed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AcceptEncoding {
+    /// Accept gzip-compressed responses
+    pub gzip: bool,
+    /// Accept brotli-compressed responses
+    pub brotli: bool,
+}
+
+impl Default for AcceptEncoding {
+    fn default() -> Self {
+        Self {
+            gzip: true,
+            brotli: true,
+        }
+    }
+}
+
+impl AcceptEncoding {
+    /// Accept no compression, requesting responses as they are
+    pub fn identity() -> Self {
+        Self {
+            gzip: false,
+            brotli: false,
+        }
+    }
+
+    /// The `Accept-Encoding` header value, or `None` when nothing is accepted
+    pub fn header_value(&self) -> Option<&'static str> {
+        match (self.brotli, self.gzip) {
+            (true, true) => Some("br, gzip"),
+            (true, false) => Some("br"),
+            (false, true) => Some("gzip"),
+            (false, false) => None,
+        }
+    }
+}
+
+static ACCEPT_GZIP: AtomicBool = AtomicBool::new(true);
+static ACCEPT_BROTLI: AtomicBool = AtomicBool::new(true);
+
+/// Sets the encodings accepted for API responses, for the whole process
+pub fn set_accept_encoding(encoding: AcceptEncoding) {
+    ACCEPT_GZIP.store(encoding.gzip, Ordering::Relaxed);
+    ACCEPT_BROTLI.store(encoding.brotli, Ordering::Relaxed);
+}
+
+/// Returns the encodings accepted for API responses
+pub fn accept_encoding() -> AcceptEncoding {
+    AcceptEncoding {
+        gzip: ACCEPT_GZIP.load(Ordering::Relaxed),
+        brotli: ACCEPT_BROTLI.load(Ordering::Relaxed),
+    }
+}
+
+/// Sizes of the API responses read since the start or the last reset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// Number of responses read
+    pub responses: u64,
+    /// Number of those that were compressed
+    pub compressed_responses: u64,
+    /// Bytes received on the wire
+    pub wire_bytes: u64,
+    /// Bytes after decoding
+    pub decoded_bytes: u64,
+}
+
+impl CompressionStats {
+    /// Bytes that compression saved
+    pub fn saved_bytes(&self) -> u64 {
+        self.decoded_bytes.saturating_sub(self.wire_bytes)
+    }
+
+    /// Wire size as a fraction of the decoded size, or `None` before any body was read
+    pub fn ratio(&self) -> Option<f64> {
+        (self.decoded_bytes > 0).then(|| self.wire_bytes as f64 / self.decoded_bytes as f64)
+    }
+}
+
+static RESPONSES: AtomicU64 = AtomicU64::new(0);
+static COMPRESSED_RESPONSES: AtomicU64 = AtomicU64::new(0);
+static WIRE_BYTES: AtomicU64 = AtomicU64::new(0);
+static DECODED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the sizes of the API responses read so far
+pub fn compression_stats() -> CompressionStats {
+    CompressionStats {
+        responses: RESPONSES.load(Ordering::Relaxed),
+        compressed_responses: COMPRESSED_RESPONSES.load(Ordering::Relaxed),
+        wire_bytes: WIRE_BYTES.load(Ordering::Relaxed),
+        decoded_bytes: DECODED_BYTES.load(Ordering::Relaxed),
+    }
+}
+
+/// Resets the sizes reported by [`compression_stats`]
+pub fn reset_compression_stats() {
+    for counter in [
+        &RESPONSES,
+        &COMPRESSED_RESPONSES,
+        &WIRE_BYTES,
+        &DECODED_BYTES,
+    ] {
+        counter.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Decodes a response body sent with the given `Content-Encoding`
+///
+/// # Arguments
+///
+/// * `encoding` - The content encoding, e.g. `gzip`, `br` or `identity`
+/// * `body` - The body as received
+///
+/// # Returns
+///
+/// The decoded body, or an error for unknown encodings and corrupt data
+pub fn decode(encoding: &str, body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(body.len().saturating_mul(4));
+    match encoding.trim().to_ascii_lowercase().as_str() {
+        "" | "identity" => decoded.extend_from_slice(body),
+        "gzip" | "x-gzip" => {
+            flate2::read::MultiGzDecoder::new(body).read_to_end(&mut decoded)?;
+        }
+        "br" => {
+            brotli_decompressor::Decompressor::new(body, 4096).read_to_end(&mut decoded)?;
+        }
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported content encoding: {}", other),
+            ))
+        }
+    }
+    Ok(decoded)
+}
+
+/// Asks for the accepted encodings on an API request
+pub(crate) fn negotiate(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match accept_encoding().header_value() {
+        Some(value) => request.header(ACCEPT_ENCODING, HeaderValue::from_static(value)),
+        None => request,
+    }
+}
+
+/// Reads an API response body, decoding it and recording its sizes
+pub(crate) async fn read_body(
+    response: reqwest::Response,
+) -> Result<Vec<u8>, crate::api::ApiError> {
+    let encoding = response
+        .headers()
+        .get(CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let body = response.bytes().await?;
+    let wire_len = body.len();
+    let decoded = match encoding.as_deref() {
+        Some(encoding) => decode(encoding, &body).map_err(|e| {
+            crate::api::ApiError::Other(format!("failed to decode response body: {}", e))
+        })?,
+        None => Vec::from(body),
+    };
+
+    RESPONSES.fetch_add(1, Ordering::Relaxed);
+    WIRE_BYTES.fetch_add(wire_len as u64, Ordering::Relaxed);
+    DECODED_BYTES.fetch_add(decoded.len() as u64, Ordering::Relaxed);
+    if let Some(encoding) = encoding.filter(|e| !e.eq_ignore_ascii_case("identity")) {
+        COMPRESSED_RESPONSES.fetch_add(1, Ordering::Relaxed);
+        log_debug!(
+            logging::API,
+            "Decoded {} response: {} bytes on the wire, {} bytes decoded",
+            encoding,
+            wire_len,
+            decoded.len()
+        );
+    }
+    Ok(decoded)
+}
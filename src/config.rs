@@ -0,0 +1,280 @@
+//! Process-wide configuration from environment variables.
+//!
+//! Containerized deployments often can't change the code or flags of the
+//! application embedding this crate, but can set its environment. [`Config`]
+//! reads the following variables:
+//!
+//! | Variable | Meaning |
+//! |----------|---------|
+//! | `ICLOUD_ALBUM_CONCURRENCY` | Concurrent downloads per asset host |
+//! | `ICLOUD_ALBUM_RETRY_MAX` | Retries of failed requests and downloads |
+//! | `ICLOUD_ALBUM_RETRY_DELAY_MS` | Base delay between retries, in milliseconds |
+//! | `ICLOUD_ALBUM_TIMEOUT_SECS` | Connect timeout, and the timeout of API requests |
+//!
+//! The `icloud-album mirror` command is configured entirely through the
+//! environment: besides the variables above, [`MirrorConfig`] reads
+//!
+//! | Variable | Meaning |
+//! |----------|---------|
+//! | `ICLOUD_ALBUM_TOKENS` | Album tokens or share URLs, separated by commas or whitespace |
+//! | `ICLOUD_ALBUM_OUTPUT_DIR` | Directory the albums are mirrored below, one subdirectory per token |
+//! | `ICLOUD_ALBUM_INTERVAL_SECS` | Seconds between mirror passes; unset to mirror once and exit |
+//!
+//! [`FetchOptions::new`] and [`DownloadOptions::new`] start from the
+//! process-wide [`Config::global`], so the variables apply without code
+//! changes; options set in code afterwards take precedence. The `Default`
+//! implementations of the options ignore the environment.
+//!
+//! [`FetchOptions::new`]: crate::fetch::FetchOptions::new
+//! [`DownloadOptions::new`]: crate::download::DownloadOptions::new
+
+use crate::api::{RetryConfig, RetryPolicies};
+use crate::download::DownloadOptions;
+use crate::fetch::FetchOptions;
+use crate::logging;
+use crate::throttle::Concurrency;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Variable setting [`Config::concurrency`]
+pub const CONCURRENCY_ENV: &str = "ICLOUD_ALBUM_CONCURRENCY";
+/// Variable setting [`Config::retry_max`]
+pub const RETRY_MAX_ENV: &str = "ICLOUD_ALBUM_RETRY_MAX";
+/// Variable setting [`Config::retry_delay_ms`]
+pub const RETRY_DELAY_MS_ENV: &str = "ICLOUD_ALBUM_RETRY_DELAY_MS";
+/// Variable setting [`Config::timeout`]
+pub const TIMEOUT_SECS_ENV: &str = "ICLOUD_ALBUM_TIMEOUT_SECS";
+
+/// Variable setting [`MirrorConfig::tokens`]
+pub const TOKENS_ENV: &str = "ICLOUD_ALBUM_TOKENS";
+/// Variable setting [`MirrorConfig::output_dir`]
+pub const OUTPUT_DIR_ENV: &str = "ICLOUD_ALBUM_OUTPUT_DIR";
+/// Variable setting [`MirrorConfig::interval`]
+pub const INTERVAL_SECS_ENV: &str = "ICLOUD_ALBUM_INTERVAL_SECS";
+
+/// Error returned when a variable has an invalid value
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+#[error("Invalid value {value:?} for {name}: expected {expected}")]
+pub struct EnvConfigError {
+    /// Name of the variable
+    pub name: &'static str,
+    /// Its value
+    pub value: String,
+    /// What the value should look like
+    pub expected: &'static str,
+}
+
+/// Settings read from the environment; `None` leaves the built-in default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Concurrent downloads per asset host
+    pub concurrency: Option<usize>,
+    /// Maximum number of retries
+    pub retry_max: Option<u64>,
+    /// Base delay between retries in milliseconds
+    pub retry_delay_ms: Option<u64>,
+    /// Connect timeout of all clients and overall timeout of API requests
+    pub timeout: Option<Duration>,
+}
+
+impl Config {
+    /// Reads the configuration from the process environment
+    ///
+    /// Unset and empty variables leave the corresponding setting unset.
+    pub fn from_env() -> Result<Self, EnvConfigError> {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    /// Reads the configuration from the variables `lookup` returns
+    ///
+    /// # Arguments
+    ///
+    /// * `lookup` - Returns the value of a variable, or `None` if unset
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, EnvConfigError> {
+        let concurrency = parse(&lookup, CONCURRENCY_ENV, "a positive number")?;
+        if concurrency == Some(0) {
+            return Err(EnvConfigError {
+                name: CONCURRENCY_ENV,
+                value: "0".to_string(),
+                expected: "a positive number",
+            });
+        }
+        Ok(Self {
+            concurrency,
+            retry_max: parse(&lookup, RETRY_MAX_ENV, "a number")?,
+            retry_delay_ms: parse(&lookup, RETRY_DELAY_MS_ENV, "a number of milliseconds")?,
+            timeout: parse(&lookup, TIMEOUT_SECS_ENV, "a number of seconds")?
+                .map(Duration::from_secs),
+        })
+    }
+
+    /// Returns the configuration of the process, read from the environment once
+    ///
+    /// An invalid variable is logged and the whole environment ignored, so a
+    /// typo never half-applies.
+    pub fn global() -> &'static Config {
+        static GLOBAL: OnceLock<Config> = OnceLock::new();
+        GLOBAL.get_or_init(|| {
+            Self::from_env().unwrap_or_else(|e| {
+                logging::log_warn!(logging::ROOT, "Ignoring the environment: {}", e);
+                Self::default()
+            })
+        })
+    }
+
+    /// Whether no setting is configured
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns the retry configuration, or `None` if no retry setting is configured
+    pub fn retry_config(&self) -> Option<RetryConfig> {
+        if self.retry_max.is_none() && self.retry_delay_ms.is_none() {
+            return None;
+        }
+        let mut retry = RetryConfig::default();
+        if let Some(max_retries) = self.retry_max {
+            retry.max_retries = max_retries;
+        }
+        if let Some(base_delay_ms) = self.retry_delay_ms {
+            retry.base_delay_ms = base_delay_ms;
+        }
+        Some(retry)
+    }
+
+    /// Applies the configured settings to download options
+    pub fn apply_to_download(&self, mut options: DownloadOptions) -> DownloadOptions {
+        if let Some(concurrency) = self.concurrency {
+            options.concurrency = Concurrency::Fixed(concurrency);
+        }
+        if let Some(retry) = self.retry_config() {
+            options.retry = Some(retry);
+        }
+        if let Some(timeout) = self.timeout {
+            options.network.connect_timeout = Some(timeout);
+        }
+        options
+    }
+
+    /// Applies the configured settings to fetch options
+    ///
+    /// A configured timeout replaces the client of `options` with one
+    /// using it.
+    pub fn apply_to_fetch(&self, mut options: FetchOptions) -> FetchOptions {
+        if let Some(retry) = self.retry_config() {
+            options.retry = Some(RetryPolicies::new(retry));
+        }
+        if let Some(timeout) = self.timeout {
+            match reqwest::Client::builder()
+                .connect_timeout(timeout)
+                .timeout(timeout)
+                .build()
+            {
+                Ok(client) => options.client = Some(client),
+                Err(e) => logging::log_warn!(
+                    logging::API,
+                    "Could not build a client with the configured timeout: {}",
+                    e
+                ),
+            }
+        }
+        options
+    }
+}
+
+/// Parses a variable, treating unset and empty variables as unset
+fn parse<T: FromStr>(
+    lookup: &impl Fn(&str) -> Option<String>,
+    name: &'static str,
+    expected: &'static str,
+) -> Result<Option<T>, EnvConfigError> {
+    let Some(value) = lookup(name).filter(|value| !value.trim().is_empty()) else {
+        return Ok(None);
+    };
+    value.trim().parse().map(Some).map_err(|_| EnvConfigError {
+        name,
+        value,
+        expected,
+    })
+}
+
+/// Settings of a mirroring process configured entirely from the environment
+///
+/// Used by the `icloud-album mirror` command, e.g. in a container restarted
+/// by its runtime. Concurrency, retries and timeouts come from [`Config`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MirrorConfig {
+    /// Tokens of the albums to mirror, normalized from tokens or share URLs
+    pub tokens: Vec<String>,
+    /// Directory the albums are mirrored below, each in a subdirectory named after its token
+    pub output_dir: PathBuf,
+    /// Time between the starts of mirror passes, or `None` to mirror once
+    pub interval: Option<Duration>,
+    /// Download and fetch settings
+    pub config: Config,
+}
+
+impl MirrorConfig {
+    /// Reads the configuration from the process environment
+    pub fn from_env() -> Result<Self, EnvConfigError> {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    /// Reads the configuration from the variables `lookup` returns
+    ///
+    /// [`TOKENS_ENV`] and [`OUTPUT_DIR_ENV`] are required.
+    ///
+    /// # Arguments
+    ///
+    /// * `lookup` - Returns the value of a variable, or `None` if unset
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, EnvConfigError> {
+        let required = |name, expected| {
+            lookup(name)
+                .filter(|value| !value.trim().is_empty())
+                .ok_or(EnvConfigError {
+                    name,
+                    value: String::new(),
+                    expected,
+                })
+        };
+        let expected = "album tokens or share URLs separated by commas or whitespace";
+        let raw_tokens = required(TOKENS_ENV, expected)?;
+        let tokens = raw_tokens
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                crate::base_url::normalize_token(token)
+                    .map(|normalized| normalized.token)
+                    .map_err(|_| EnvConfigError {
+                        name: TOKENS_ENV,
+                        value: token.to_string(),
+                        expected,
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let output_dir = required(OUTPUT_DIR_ENV, "a directory")?;
+        let interval = parse(&lookup, INTERVAL_SECS_ENV, "a positive number of seconds")?;
+        if interval == Some(0) {
+            return Err(EnvConfigError {
+                name: INTERVAL_SECS_ENV,
+                value: "0".to_string(),
+                expected: "a positive number of seconds",
+            });
+        }
+        Ok(Self {
+            tokens,
+            output_dir: PathBuf::from(output_dir.trim()),
+            interval: interval.map(Duration::from_secs),
+            config: Config::from_lookup(lookup)?,
+        })
+    }
+
+    /// Directory the album with `token` is mirrored to
+    pub fn album_dir(&self, token: &str) -> PathBuf {
+        self.output_dir.join(token)
+    }
+}
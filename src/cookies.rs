@@ -0,0 +1,352 @@
+//! A cookie jar for the API and download clients.
+//!
+//! The shared-stream endpoints don't set cookies today. [`CookieJar`] is a
+//! small, persistable store for when they start to, and for experimenting with
+//! whether responses differ with and without cookies. Install it with
+//! [`crate::net::NetworkConfig::with_cookies`]; clients built from that
+//! configuration then send and store cookies. [`CookieJar::load`] and
+//! [`CookieJar::save`] keep the cookies in a JSON file between runs, and
+//! [`CookieJar::load_from_store`] and [`CookieJar::save_to_store`] in a
+//! [`StateStore`]. Loading and saving are synchronous, like the store.
+//!
+//! Only the attributes that decide where a cookie is sent are honored:
+//! `Domain`, `Path`, `Secure`, `Max-Age` and `Expires` (in the IMF-fixdate
+//! form, e.g. `Wed, 21 Oct 2015 07:28:00 GMT`).
+
+use crate::state_store::{get_json, put_json, StateStore};
+use reqwest::header::HeaderValue;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A cookie kept by a [`CookieJar`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredCookie {
+    /// Name of the cookie
+    pub name: String,
+    /// Value of the cookie
+    pub value: String,
+    /// Host or domain the cookie is sent to, lowercase
+    pub domain: String,
+    /// Whether the cookie is only sent to `domain` itself, not its subdomains
+    pub host_only: bool,
+    /// Path prefix of the URLs the cookie is sent to
+    pub path: String,
+    /// Whether the cookie is only sent over https
+    pub secure: bool,
+    /// Expiry in seconds since the Unix epoch, or `None` for a session cookie
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl StoredCookie {
+    /// Whether the cookie has expired at `now` (seconds since the Unix epoch)
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Whether the cookie should be sent with a request to `url`
+    fn matches(&self, url: &url::Url) -> bool {
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let domain_matches = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(&host, &self.domain)
+        };
+        domain_matches
+            && path_matches(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+    }
+}
+
+/// A thread-safe cookie store, optionally backed by a file
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    cookies: Mutex<Vec<StoredCookie>>,
+    path: Option<PathBuf>,
+}
+
+impl CookieJar {
+    /// Create an empty jar that is not backed by a file
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a jar from `path`, remembering the path for [`CookieJar::save`]
+    ///
+    /// A missing file gives an empty jar. Session and expired cookies in the
+    /// file are kept out.
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let cookies: Vec<StoredCookie> = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let now = now_secs();
+        Ok(Self {
+            cookies: Mutex::new(
+                cookies
+                    .into_iter()
+                    .filter(|cookie| cookie.expires_at.is_some() && !cookie.is_expired(now))
+                    .collect(),
+            ),
+            path: Some(path),
+        })
+    }
+
+    /// Writes the persistent cookies to the file the jar was loaded from
+    ///
+    /// Does nothing for jars created with [`CookieJar::new`].
+    pub fn save(&self) -> io::Result<()> {
+        match &self.path {
+            Some(path) => self.save_to(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Loads a jar from the cookies kept under `key` of a [`StateStore`]
+    ///
+    /// A missing key gives an empty jar. The jar doesn't remember the store;
+    /// save it with [`CookieJar::save_to_store`].
+    pub fn load_from_store(store: &(impl StateStore + ?Sized), key: &str) -> io::Result<Self> {
+        let cookies: Vec<StoredCookie> = get_json(store, key)?.unwrap_or_default();
+        let now = now_secs();
+        Ok(Self {
+            cookies: Mutex::new(
+                cookies
+                    .into_iter()
+                    .filter(|cookie| cookie.expires_at.is_some() && !cookie.is_expired(now))
+                    .collect(),
+            ),
+            path: None,
+        })
+    }
+
+    /// Writes the persistent cookies under `key` of a [`StateStore`]
+    pub fn save_to_store(&self, store: &(impl StateStore + ?Sized), key: &str) -> io::Result<()> {
+        put_json(store, key, &self.persistent_cookies())
+    }
+
+    /// Writes the persistent cookies to `path`
+    ///
+    /// Session cookies, which have no expiry, are not written.
+    pub fn save_to(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(&self.persistent_cookies())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let partial = path.with_extension("part");
+        std::fs::write(&partial, json)?;
+        std::fs::rename(&partial, path)
+    }
+
+    /// Returns the unexpired cookies that have an expiry
+    fn persistent_cookies(&self) -> Vec<StoredCookie> {
+        let now = now_secs();
+        self.cookies()
+            .into_iter()
+            .filter(|cookie| cookie.expires_at.is_some() && !cookie.is_expired(now))
+            .collect()
+    }
+
+    /// Returns the cookies currently in the jar
+    pub fn cookies(&self) -> Vec<StoredCookie> {
+        self.lock().clone()
+    }
+
+    /// Removes every cookie
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Stores the cookies of `Set-Cookie` header values received from `url`
+    ///
+    /// Cookies that can't be parsed or name a domain `url` doesn't belong to
+    /// are ignored. A cookie replaces one with the same name, domain and path,
+    /// and an expired cookie removes it.
+    pub fn store_response_cookies<'a>(
+        &self,
+        set_cookie: impl IntoIterator<Item = &'a str>,
+        url: &url::Url,
+    ) {
+        let now = now_secs();
+        let mut cookies = self.lock();
+        for header in set_cookie {
+            let Some(cookie) = parse_set_cookie(header, url, now) else {
+                continue;
+            };
+            cookies.retain(|c| {
+                (c.name.as_str(), c.domain.as_str(), c.path.as_str())
+                    != (
+                        cookie.name.as_str(),
+                        cookie.domain.as_str(),
+                        cookie.path.as_str(),
+                    )
+            });
+            if !cookie.is_expired(now) {
+                cookies.push(cookie);
+            }
+        }
+    }
+
+    /// Returns the `Cookie` header value to send to `url`, if any cookie applies
+    ///
+    /// Cookies with longer paths come first.
+    pub fn cookie_header(&self, url: &url::Url) -> Option<String> {
+        let now = now_secs();
+        let mut cookies = self.lock();
+        cookies.retain(|cookie| !cookie.is_expired(now));
+        let mut matching: Vec<&StoredCookie> = cookies
+            .iter()
+            .filter(|cookie| cookie.matches(url))
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+        matching.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+        Some(
+            matching
+                .iter()
+                .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    }
+
+    /// Locks the cookie list, recovering it if a holder panicked
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<StoredCookie>> {
+        self.cookies.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl reqwest::cookie::CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &url::Url) {
+        let headers: Vec<&str> = cookie_headers
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        self.store_response_cookies(headers, url);
+    }
+
+    fn cookies(&self, url: &url::Url) -> Option<HeaderValue> {
+        HeaderValue::from_str(&self.cookie_header(url)?).ok()
+    }
+}
+
+/// Parses a `Set-Cookie` header value received from `url`
+fn parse_set_cookie(header: &str, url: &url::Url, now: u64) -> Option<StoredCookie> {
+    let mut parts = header.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    let host = url.host_str()?.to_ascii_lowercase();
+
+    let mut cookie = StoredCookie {
+        name: name.to_string(),
+        value: value.trim().trim_matches('"').to_string(),
+        domain: host.clone(),
+        host_only: true,
+        path: default_path(url.path()),
+        secure: false,
+        expires_at: None,
+    };
+    let mut max_age = None;
+    let mut expires = None;
+    for attribute in parts {
+        let (key, value) = match attribute.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (attribute.trim(), ""),
+        };
+        match key.to_ascii_lowercase().as_str() {
+            "domain" if !value.is_empty() => {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                if !domain_matches(&host, &domain) {
+                    return None;
+                }
+                cookie.domain = domain;
+                cookie.host_only = false;
+            }
+            "path" if value.starts_with('/') => cookie.path = value.to_string(),
+            "secure" => cookie.secure = true,
+            "max-age" => max_age = value.parse::<i64>().ok(),
+            "expires" => expires = parse_http_date(value),
+            _ => {}
+        }
+    }
+    // Max-Age takes precedence over Expires
+    cookie.expires_at = match max_age {
+        Some(seconds) if seconds <= 0 => Some(0),
+        Some(seconds) => Some(now.saturating_add(seconds as u64)),
+        None => expires,
+    };
+    Some(cookie)
+}
+
+/// Whether `host` is `domain` or one of its subdomains
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<std::net::IpAddr>().is_err())
+}
+
+/// Whether a request path falls under a cookie path
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+/// The path a cookie without a `Path` attribute applies to: the request's directory
+fn default_path(request_path: &str) -> String {
+    match request_path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(index) => request_path[..index].to_string(),
+    }
+}
+
+/// Parses an IMF-fixdate such as `Wed, 21 Oct 2015 07:28:00 GMT`
+fn parse_http_date(value: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    let [_weekday, day, month, year, time, "GMT"] = fields[..] else {
+        return None;
+    };
+    let day: u64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))? as u64 + 1;
+    let year: u64 = year.parse().ok()?;
+    let mut clock = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (clock.next()??, clock.next()??, clock.next()??);
+    if year < 1970 || day == 0 || day > 31 || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    // Days from the epoch to the civil date (proleptic Gregorian calendar)
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y / 400;
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).checked_sub(719_468)?;
+    Some(days * 86_400 + hours * 3600 + minutes * 60 + seconds)
+}
+
+/// Current time in seconds since the Unix epoch
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
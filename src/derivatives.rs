@@ -0,0 +1,186 @@
+//! Known derivative keys and their meanings.
+//!
+//! iCloud identifies the variants of each photo ("derivatives") by opaque keys.
+//! This module collects the keys that have been observed in shared album
+//! responses together with their meaning and typical size class, so that the
+//! selection logic in [`crate::utils`] and downstream consumers share one source
+//! of truth that can be updated as Apple changes its keys.
+
+use crate::models::Derivative;
+
+/// Broad size class of a derivative
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SizeClass {
+    /// Small preview suitable for grid thumbnails
+    Thumbnail,
+    /// Medium-sized rendition suitable for on-screen viewing
+    Medium,
+    /// Large rendition, smaller than the original
+    Large,
+    /// The original (full resolution) asset
+    Original,
+    /// Still image representing a video
+    VideoPoster,
+    /// Video rendition
+    Video,
+}
+
+impl SizeClass {
+    /// Short name used to tell apart files of different size classes, e.g. `thumb`
+    pub fn file_suffix(&self) -> &'static str {
+        match self {
+            SizeClass::Thumbnail => "thumb",
+            SizeClass::Medium => "medium",
+            SizeClass::Large => "large",
+            SizeClass::Original => "original",
+            SizeClass::VideoPoster => "poster",
+            SizeClass::Video => "video",
+        }
+    }
+
+    /// Returns the size class with the given [`SizeClass::file_suffix`]
+    pub fn from_file_suffix(suffix: &str) -> Option<Self> {
+        [
+            SizeClass::Thumbnail,
+            SizeClass::Medium,
+            SizeClass::Large,
+            SizeClass::Original,
+            SizeClass::VideoPoster,
+            SizeClass::Video,
+        ]
+        .into_iter()
+        .find(|size_class| size_class.file_suffix() == suffix)
+    }
+}
+
+/// Description of a known derivative key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DerivativeKeyInfo {
+    /// The key as it appears in the `derivatives` map
+    pub key: &'static str,
+    /// Human-readable meaning of the key
+    pub meaning: &'static str,
+    /// Typical size class of derivatives with this key
+    pub size_class: SizeClass,
+}
+
+/// Derivative keys observed in iCloud shared album responses
+pub const KNOWN_DERIVATIVE_KEYS: &[DerivativeKeyInfo] = &[
+    DerivativeKeyInfo {
+        key: "3",
+        meaning: "Original asset (commonly used for originals)",
+        size_class: SizeClass::Original,
+    },
+    DerivativeKeyInfo {
+        key: "4",
+        meaning: "Highest quality rendition (sometimes used for originals)",
+        size_class: SizeClass::Original,
+    },
+    DerivativeKeyInfo {
+        key: "PosterFrame",
+        meaning: "Still frame shown in place of a video",
+        size_class: SizeClass::VideoPoster,
+    },
+    DerivativeKeyInfo {
+        key: "360p",
+        meaning: "Low resolution video rendition",
+        size_class: SizeClass::Video,
+    },
+    DerivativeKeyInfo {
+        key: "720p",
+        meaning: "HD video rendition",
+        size_class: SizeClass::Video,
+    },
+    DerivativeKeyInfo {
+        key: "1080p",
+        meaning: "Full HD video rendition",
+        size_class: SizeClass::Video,
+    },
+];
+
+/// Largest pixel dimension of a numeric key still considered a thumbnail
+const THUMBNAIL_MAX_DIMENSION: u32 = 400;
+/// Largest pixel dimension of a numeric key still considered medium-sized
+const MEDIUM_MAX_DIMENSION: u32 = 1280;
+/// Smallest numeric key interpreted as a pixel dimension rather than an index
+const MIN_DIMENSION_KEY: u32 = 100;
+
+/// Looks up a key in [`KNOWN_DERIVATIVE_KEYS`]
+///
+/// # Arguments
+///
+/// * `key` - The derivative key to look up
+///
+/// # Returns
+///
+/// The table entry for the key, if it is known
+pub fn lookup_derivative_key(key: &str) -> Option<&'static DerivativeKeyInfo> {
+    KNOWN_DERIVATIVE_KEYS.iter().find(|info| info.key == key)
+}
+
+/// Classifies a derivative key into a size class
+///
+/// Known keys are resolved through [`KNOWN_DERIVATIVE_KEYS`]. Unknown keys are
+/// classified heuristically: names containing "original" or "full" are treated
+/// as originals, and large numeric keys (e.g. "342", "2049") are interpreted as
+/// the rendition's longest pixel dimension.
+///
+/// # Arguments
+///
+/// * `key` - The derivative key to classify
+///
+/// # Returns
+///
+/// The size class, or `None` if the key cannot be classified
+pub fn classify_derivative_key(key: &str) -> Option<SizeClass> {
+    if let Some(info) = lookup_derivative_key(key) {
+        return Some(info.size_class);
+    }
+
+    let lower = key.to_lowercase();
+    if lower.contains("original") || lower.contains("full") {
+        return Some(SizeClass::Original);
+    }
+
+    match key.parse::<u32>() {
+        Ok(dimension) if dimension >= MIN_DIMENSION_KEY => {
+            Some(if dimension <= THUMBNAIL_MAX_DIMENSION {
+                SizeClass::Thumbnail
+            } else if dimension <= MEDIUM_MAX_DIMENSION {
+                SizeClass::Medium
+            } else {
+                SizeClass::Large
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Classifies a derivative into a size class, preferring its declared type
+///
+/// The key is classified with [`classify_derivative_key`], then corrected by
+/// what the derivative declares (see [`Derivative::declares_video`]): a
+/// declared video is a [`SizeClass::Video`] whatever its key, and a declared
+/// still image under a video key is taken to be its poster frame.
+///
+/// # Arguments
+///
+/// * `key` - The derivative key
+/// * `derivative` - The derivative stored under the key
+///
+/// # Returns
+///
+/// The size class, or `None` if the derivative cannot be classified
+pub fn classify_derivative(key: &str, derivative: &Derivative) -> Option<SizeClass> {
+    let by_key = classify_derivative_key(key);
+    match (derivative.declares_video(), by_key) {
+        (Some(true), _) => Some(SizeClass::Video),
+        (Some(false), Some(SizeClass::Video)) => Some(SizeClass::VideoPoster),
+        _ => by_key,
+    }
+}
+
+/// Returns true if the key is likely to identify the original asset
+pub fn is_original_key(key: &str) -> bool {
+    classify_derivative_key(key) == Some(SizeClass::Original)
+}
@@ -0,0 +1,402 @@
+//! One-call health diagnosis of a shared album.
+//!
+//! When an album "doesn't work", the cause can be a partition redirect, a slow
+//! or failing endpoint, a change in Apple's response schema, asset URLs that
+//! no longer resolve, or a CDN that refuses the resolved URLs. [`diagnose`]
+//! runs each step of a fetch once, timing every request, and gathers the
+//! results in a [`DiagnosisReport`] with an overall [`Health`] and score:
+//!
+//! - the redirect behavior, i.e. which partition answered;
+//! - the latency of every request made;
+//! - the [compatibility report](crate::check) of the responses;
+//! - how many derivatives of a sample of photos got a URL;
+//! - whether a few of those URLs actually serve the asset.
+//!
+//! Assets are probed with single-byte range requests, so nothing is
+//! downloaded. Later steps are skipped when an earlier one fails, and the
+//! failure is recorded in the report rather than returned as an error.
+//!
+//! ```no_run
+//! # async fn example() {
+//! use icloud_album_rs::diagnose::{diagnose, Health};
+//! use icloud_album_rs::fetch::FetchOptions;
+//!
+//! let report = diagnose("B0z5qAGN1JIFd3y", &FetchOptions::new()).await;
+//! print!("{}", report);
+//! if report.health() == Health::Broken {
+//!     std::process::exit(1);
+//! }
+//! # }
+//! ```
+
+use crate::api;
+use crate::check::{self, CompatibilityReport};
+use crate::fetch::FetchOptions;
+use crate::models::WebstreamRequest;
+use crate::redact::Redacted;
+use crate::redirect::{self, WebstreamProbe};
+use crate::url_policy::UrlPolicy;
+use crate::{enrich, utils};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Most resolved asset URLs [`diagnose`] probes
+pub const ASSET_SAMPLE_SIZE: usize = 5;
+
+/// Overall state of an album
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Health {
+    /// Every step succeeded
+    Healthy,
+    /// The album can be fetched, but some data or assets are lost
+    Degraded,
+    /// The album cannot be fetched
+    Broken,
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Health::Healthy => "healthy",
+            Health::Degraded => "degraded",
+            Health::Broken => "broken",
+        })
+    }
+}
+
+/// Where the album's API requests ended up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedirectBehavior {
+    /// The base URL computed from the token, or the one configured
+    pub initial_base_url: String,
+    /// The base URL that answered the webstream request
+    pub base_url: String,
+    /// Whether the API redirected to another partition
+    pub redirected: bool,
+}
+
+/// How long a request took
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Latency {
+    /// The request, e.g. `"webstream"`, `"webstream (redirected)"`,
+    /// `"webasseturls"` or `"asset"`
+    pub request: &'static str,
+    /// Time until the response was received, or the request failed
+    pub elapsed: Duration,
+}
+
+/// How many derivatives of the sampled photos got a URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UrlResolution {
+    /// Derivatives of the sampled photos
+    pub requested: usize,
+    /// Derivatives that got a URL
+    pub resolved: usize,
+}
+
+impl UrlResolution {
+    /// Fraction of derivatives that got a URL, 1.0 if there were none
+    pub fn rate(&self) -> f64 {
+        if self.requested == 0 {
+            1.0
+        } else {
+            self.resolved as f64 / self.requested as f64
+        }
+    }
+}
+
+/// The result of requesting the first byte of an asset
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetProbe {
+    /// GUID of the photo
+    pub photo_guid: String,
+    /// Checksum of the derivative
+    pub checksum: String,
+    /// HTTP status of the response, if there was one
+    pub status: Option<u16>,
+    /// Why the request failed, if it did
+    pub error: Option<String>,
+}
+
+impl AssetProbe {
+    /// Whether the asset was served
+    pub fn is_available(&self) -> bool {
+        self.status
+            .is_some_and(|status| (200..300).contains(&status))
+    }
+}
+
+/// What [`diagnose`] found
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct DiagnosisReport {
+    /// Where the requests went, or `None` if the album couldn't be reached
+    pub redirect: Option<RedirectBehavior>,
+    /// Every request made, in order
+    pub latencies: Vec<Latency>,
+    /// Schema compatibility of the responses, if the webstream was received
+    pub compatibility: Option<CompatibilityReport>,
+    /// URL resolution of the sampled photos, if asset URLs were requested
+    pub url_resolution: Option<UrlResolution>,
+    /// The probed assets
+    pub assets: Vec<AssetProbe>,
+    /// Steps that failed, with why
+    pub errors: Vec<String>,
+}
+
+impl DiagnosisReport {
+    /// Returns the overall state of the album
+    ///
+    /// The album is broken if its photos cannot be listed, none of the sampled
+    /// URLs resolved or none of the probed assets was served, and degraded if
+    /// anything less was lost.
+    pub fn health(&self) -> Health {
+        let compatibility = match &self.compatibility {
+            Some(report) if report.parse_errors.is_empty() => report,
+            _ => return Health::Broken,
+        };
+        let no_urls = self
+            .url_resolution
+            .is_some_and(|urls| urls.requested > 0 && urls.resolved == 0);
+        let no_assets =
+            !self.assets.is_empty() && !self.assets.iter().any(AssetProbe::is_available);
+        if no_urls || no_assets {
+            return Health::Broken;
+        }
+        if !self.errors.is_empty()
+            || !compatibility.is_compatible()
+            || self
+                .url_resolution
+                .is_some_and(|urls| urls.resolved < urls.requested)
+            || !self.assets.iter().all(AssetProbe::is_available)
+        {
+            return Health::Degraded;
+        }
+        Health::Healthy
+    }
+
+    /// Returns a score from 0 to 100 summarizing the album's health
+    ///
+    /// A compatible schema counts 40 points, and the URL resolution rate and
+    /// the share of available assets 30 points each. An album that couldn't
+    /// be reached scores 0.
+    pub fn score(&self) -> u8 {
+        let Some(compatibility) = &self.compatibility else {
+            return 0;
+        };
+        let schema = if compatibility.is_compatible() {
+            1.0
+        } else {
+            0.0
+        };
+        // An empty album has nothing to resolve
+        let urls = match self.url_resolution {
+            Some(urls) => urls.rate(),
+            None if compatibility.photos == 0 => 1.0,
+            None => 0.0,
+        };
+        let assets = if self.assets.is_empty() {
+            urls
+        } else {
+            self.assets.iter().filter(|a| a.is_available()).count() as f64
+                / self.assets.len() as f64
+        };
+        (40.0 * schema + 30.0 * urls + 30.0 * assets).round() as u8
+    }
+
+    /// Returns the slowest request, if any was made
+    pub fn slowest(&self) -> Option<&Latency> {
+        self.latencies.iter().max_by_key(|latency| latency.elapsed)
+    }
+
+    /// Records the time since `started` for a request
+    fn time(&mut self, request: &'static str, started: Instant) {
+        self.latencies.push(Latency {
+            request,
+            elapsed: started.elapsed(),
+        });
+    }
+}
+
+impl fmt::Display for DiagnosisReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Health: {} ({}/100)", self.health(), self.score())?;
+        match &self.redirect {
+            Some(redirect) if redirect.redirected => writeln!(
+                f,
+                "Redirect: {} -> {}",
+                Redacted(&redirect.initial_base_url),
+                Redacted(&redirect.base_url)
+            )?,
+            Some(redirect) => writeln!(f, "Redirect: none ({})", Redacted(&redirect.base_url))?,
+            None => writeln!(f, "Redirect: album not reached")?,
+        }
+        for latency in &self.latencies {
+            writeln!(
+                f,
+                "Latency {}: {} ms",
+                latency.request,
+                latency.elapsed.as_millis()
+            )?;
+        }
+        if let Some(urls) = self.url_resolution {
+            writeln!(
+                f,
+                "URL resolution: {}/{} derivatives ({:.0}%)",
+                urls.resolved,
+                urls.requested,
+                urls.rate() * 100.0
+            )?;
+        }
+        for asset in &self.assets {
+            match (&asset.status, &asset.error) {
+                (_, Some(error)) => writeln!(f, "Asset {}: {}", asset.checksum, error)?,
+                (Some(status), None) => writeln!(f, "Asset {}: HTTP {}", asset.checksum, status)?,
+                (None, None) => writeln!(f, "Asset {}: no response", asset.checksum)?,
+            }
+        }
+        for error in &self.errors {
+            writeln!(f, "Error: {}", error)?;
+        }
+        if let Some(compatibility) = &self.compatibility {
+            write!(f, "{}", compatibility)?;
+        }
+        Ok(())
+    }
+}
+
+/// Diagnoses an album, see the [module documentation](self)
+///
+/// Only `client` and `base_url` of `options` are used, and requests are not
+/// retried so that the report shows what the API answered. Asset URLs are
+/// requested for up to [`check::SAMPLE_SIZE`] photos, and up to
+/// [`ASSET_SAMPLE_SIZE`] of them are probed.
+///
+/// # Arguments
+///
+/// * `token` - The iCloud shared album token
+/// * `options` - How to reach the album
+///
+/// # Returns
+///
+/// The report, which records failed steps instead of returning an error
+pub async fn diagnose(token: &str, options: &FetchOptions) -> DiagnosisReport {
+    let mut report = DiagnosisReport::default();
+    let client = options.client.clone().unwrap_or_default();
+
+    // 1. Find the partition and fetch the webstream
+    let initial_base_url = match &options.base_url {
+        Some(base_url) => base_url.clone(),
+        None => match crate::base_url::get_base_url(token) {
+            Ok(base_url) => base_url,
+            Err(e) => {
+                report.errors.push(format!("invalid token: {}", e));
+                return report;
+            }
+        },
+    };
+    let request = WebstreamRequest::default();
+    let started = Instant::now();
+    let probe = match &options.base_url {
+        Some(base_url) => redirect::probe_webstream(&client, base_url, token, &request).await,
+        None => redirect::probe_album(&client, token, &request).await,
+    };
+    report.time("webstream", started);
+    let mut probe = match probe {
+        Ok(probe) => probe,
+        Err(e) => {
+            report.errors.push(format!("webstream: {}", e));
+            return report;
+        }
+    };
+    let redirected =
+        matches!(probe, WebstreamProbe::Redirected(_)) || probe.base_url() != initial_base_url;
+    if let WebstreamProbe::Redirected(base_url) = &probe {
+        let started = Instant::now();
+        let redirected = redirect::probe_webstream(&client, base_url, token, &request).await;
+        report.time("webstream (redirected)", started);
+        probe = match redirected {
+            Ok(probe) => probe,
+            Err(e) => {
+                report.errors.push(format!("redirected webstream: {}", e));
+                return report;
+            }
+        };
+    }
+    report.redirect = Some(RedirectBehavior {
+        initial_base_url,
+        base_url: probe.base_url().to_string(),
+        redirected,
+    });
+    let (base_url, webstream) = match check::into_webstream(probe) {
+        Ok(response) => response,
+        Err(e) => {
+            report.errors.push(format!("webstream: {}", e));
+            return report;
+        }
+    };
+
+    // 2. Resolve the URLs of a sample of photos
+    let photo_guids = check::sample_guids(&webstream);
+    let webasseturls = if photo_guids.is_empty() {
+        None
+    } else {
+        let started = Instant::now();
+        let response = check::request_asset_urls(&client, &base_url, &photo_guids).await;
+        report.time("webasseturls", started);
+        match response {
+            Ok(response) => Some(response),
+            Err(e) => {
+                report.errors.push(format!("webasseturls: {}", e));
+                None
+            }
+        }
+    };
+    report.compatibility = Some(check::check_responses(&webstream, webasseturls.as_ref()));
+
+    let (Some(webasseturls), Ok(parsed)) =
+        (webasseturls, api::parse_webstream_response(&webstream))
+    else {
+        return report;
+    };
+    let urls = api::parse_webasseturls_response_with_report(&webasseturls, &UrlPolicy::default())
+        .map(|response| response.urls)
+        .unwrap_or_default();
+    let mut photos: Vec<_> = parsed
+        .photos
+        .into_iter()
+        .filter(|photo| photo_guids.contains(&photo.photo_guid.as_str()))
+        .collect();
+    enrich::enrich_photos_with_urls(&mut photos, &urls);
+    let derivatives = photos.iter().flat_map(|photo| photo.derivatives.values());
+    report.url_resolution = Some(UrlResolution {
+        requested: derivatives.clone().count(),
+        resolved: derivatives.filter(|d| d.url.is_some()).count(),
+    });
+
+    // 3. Probe the smallest derivatives of a few photos
+    let samples = photos
+        .iter()
+        .filter_map(|photo| {
+            let (_, derivative) = utils::select_thumbnail_derivative(&photo.derivatives)?;
+            Some((photo, derivative, derivative.url.as_deref()?))
+        })
+        .take(ASSET_SAMPLE_SIZE);
+    for (photo, derivative, url) in samples {
+        let started = Instant::now();
+        let response =
+            crate::har::send(client.get(url).header(reqwest::header::RANGE, "bytes=0-0")).await;
+        report.time("asset", started);
+        let (status, error) = match response {
+            Ok(response) => (Some(response.status().as_u16()), None),
+            Err(e) => (None, Some(Redacted(e).to_string())),
+        };
+        report.assets.push(AssetProbe {
+            photo_guid: photo.photo_guid.clone(),
+            checksum: derivative.checksum.clone(),
+            status,
+            error,
+        });
+    }
+    report
+}
@@ -0,0 +1,127 @@
+//! Capture of anonymized fixtures when parts of an API response fail to parse.
+//!
+//! A warning like "photo at index 37 failed to parse" is hard to act on without
+//! the JSON that caused it. When a capture directory is configured with
+//! [`set_capture_dir`], every photo that fails to deserialize is written there,
+//! passed through [`crate::utils::anonymize_response`] and wrapped with the
+//! error and some context, ready to be attached to an issue.
+//!
+//! ```
+//! use icloud_album_rs::diagnostics;
+//!
+//! diagnostics::set_capture_dir(Some(std::env::temp_dir().join("icloud-fixtures")));
+//! # diagnostics::set_capture_dir(None);
+//! ```
+
+use crate::logging;
+use crate::utils;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Maximum number of fixtures captured while parsing a single response
+pub const MAX_CAPTURES_PER_RESPONSE: usize = 5;
+
+/// Directory configured with [`set_capture_dir`]
+fn capture_dir_lock() -> &'static RwLock<Option<PathBuf>> {
+    static CAPTURE_DIR: OnceLock<RwLock<Option<PathBuf>>> = OnceLock::new();
+    CAPTURE_DIR.get_or_init(|| RwLock::new(None))
+}
+
+/// Sets the directory fixtures are written to, or disables capture with `None`
+///
+/// Capture is disabled by default. The directory is created when the first
+/// fixture is written.
+pub fn set_capture_dir(dir: Option<PathBuf>) {
+    *capture_dir_lock()
+        .write()
+        .unwrap_or_else(|e| e.into_inner()) = dir;
+}
+
+/// Returns the directory set with [`set_capture_dir`]
+pub fn capture_dir() -> Option<PathBuf> {
+    capture_dir_lock()
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// Writes an anonymized fixture for a fragment that failed to parse
+///
+/// Failures to write are logged and otherwise ignored, so capture never
+/// affects parsing.
+///
+/// # Arguments
+///
+/// * `kind` - What the fragment is, e.g. `"photo"`
+/// * `index` - Position of the fragment in its array
+/// * `error` - The deserialization error
+/// * `fragment` - The JSON that failed to parse
+///
+/// # Returns
+///
+/// The path of the written fixture, or `None` if capture is disabled or failed
+pub(crate) fn capture_parse_failure(
+    kind: &str,
+    index: usize,
+    error: &str,
+    fragment: &serde_json::Value,
+) -> Option<PathBuf> {
+    let dir = capture_dir()?;
+    match write_fixture(&dir, kind, index, error, fragment) {
+        Ok(path) => {
+            logging::log_debug!(
+                logging::API,
+                "Captured {} at index {} to {}",
+                kind,
+                index,
+                path.display()
+            );
+            Some(path)
+        }
+        Err(e) => {
+            logging::log_warn!(
+                logging::API,
+                "Failed to capture {} at index {} to {}: {}",
+                kind,
+                index,
+                dir.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Serializes a fixture and writes it to a new file in `dir`
+fn write_fixture(
+    dir: &Path,
+    kind: &str,
+    index: usize,
+    error: &str,
+    fragment: &serde_json::Value,
+) -> std::io::Result<PathBuf> {
+    let captured_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let fixture = serde_json::json!({
+        "kind": kind,
+        "index": index,
+        "error": error,
+        "crate_version": env!("CARGO_PKG_VERSION"),
+        "captured_at": captured_at.as_secs(),
+        "fragment": utils::anonymize_response(fragment.clone()),
+    });
+
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!(
+        "{}-{}-{}-{}.json",
+        kind,
+        captured_at.as_nanos(),
+        std::process::id(),
+        index
+    ));
+    let contents = serde_json::to_vec_pretty(&fixture).map_err(std::io::Error::from)?;
+    std::fs::write(&path, contents)?;
+    Ok(path)
+}
@@ -0,0 +1,2136 @@
+//! Download options and the file-writing path shared by all downloads.
+//!
+//! Downloaded content is first written to a temporary file and then moved into
+//! place, so an interrupted download never leaves a partial file under its final
+//! name. [`DownloadOptions`] controls where those temporary files are staged.
+//! Bodies are streamed into them as they arrive, so memory use doesn't grow
+//! with the size of the assets, see [`MemoryBudget`].
+//!
+//! [`download_derivative_classes`] downloads several sizes of every photo in
+//! one pass, e.g. thumbnails and originals for a gallery. Its requests run
+//! concurrently as configured by [`DownloadOptions::concurrency`]. Slow small
+//! assets can be requested a second time, see [`HedgeConfig`], and transfers
+//! that stop receiving data can be resumed where they stalled, see
+//! [`StallConfig`] and [`SpeedFloor`].
+//!
+//! Asset servers occasionally answer with an empty body or an HTML error page
+//! and a 200 status. Such bodies are never saved (see [`check_asset_body`]);
+//! they count as failed downloads that are retried, and with
+//! [`DownloadOptions::url_refresh`] the photo's URL is resolved again first.
+
+use crate::api::{ApiError, ErrorContext, RetryConfig};
+use crate::derivatives::SizeClass;
+use crate::journal::{DownloadJournal, JournaledWrite};
+use crate::logging;
+use crate::markdown::strip_markdown;
+use crate::metrics;
+use crate::models::{DownloadOutcome, HttpValidators, Image};
+use crate::net::NetworkConfig;
+use crate::packfile::Packfile;
+use crate::postprocess::{PostProcessFailure, PostProcessing, PostProcessor};
+use crate::predownload::{PreDownloadHook, PreDownloadHooks, Verdict};
+use crate::redact::Redacted;
+use crate::shutdown::Shutdown;
+use crate::throttle::{self, CircuitBreakerConfig, Concurrency, HostLimits, Outcome};
+use crate::url_policy::UrlPolicy;
+use crate::utils;
+use crate::zip_stream::Crc32;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+
+/// Error returned when downloading a photo fails
+///
+/// Carries the photo GUID and URL involved, so a single log line identifies
+/// the failed download. The URL is redacted when formatted.
+pub struct DownloadError {
+    /// The operation, URL and photo involved
+    pub context: ErrorContext,
+    /// The underlying error
+    pub source: Box<dyn Error>,
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.context, Redacted(&self.source))
+    }
+}
+
+impl fmt::Debug for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DownloadError")
+            .field("context", &Redacted(&self.context))
+            .field("source", &Redacted(&self.source))
+            .finish()
+    }
+}
+
+impl Error for DownloadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+/// When [`download_derivative_classes`] requests a slow small asset a second time
+///
+/// If an asset no larger than `max_size` hasn't arrived after `delay`, a
+/// second request for it is sent and whichever completes first is used. This
+/// cuts the tail latency of thumbnail-heavy passes, e.g. in gallery servers,
+/// at the cost of extra requests, which are capped at `max_extra` of the
+/// downloads of the pass. Second requests wait for a slot of their host's
+/// limit like any other, so hedging only helps with a
+/// [`DownloadOptions::concurrency`] above one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HedgeConfig {
+    /// Time without a response after which the second request is sent
+    pub delay: Duration,
+    /// Largest asset hedged, in bytes; assets of unknown size are hedged only if they are thumbnails
+    pub max_size: u64,
+    /// Most extra requests, as a fraction of the downloads of the pass
+    pub max_extra: f64,
+}
+
+impl HedgeConfig {
+    /// Hedge assets up to 256 KiB after `delay`, with at most 10% extra requests
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            max_size: 256 * 1024,
+            max_extra: 0.1,
+        }
+    }
+
+    /// Only hedge assets up to `max_size` bytes
+    #[must_use]
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Send at most `max_extra` extra requests per download of the pass
+    #[must_use]
+    pub fn with_max_extra(mut self, max_extra: f64) -> Self {
+        self.max_extra = max_extra.max(0.0);
+        self
+    }
+
+    /// Whether a derivative of `size_class` and `file_size` bytes is hedged
+    fn applies(&self, size_class: SizeClass, file_size: Option<u64>) -> bool {
+        file_size.map_or(size_class == SizeClass::Thumbnail, |size| {
+            size <= self.max_size
+        })
+    }
+}
+
+/// When a transfer that stopped receiving data is aborted and resumed
+///
+/// Long videos over mobile connections sometimes stop receiving data while
+/// the connection stays open. With stall detection, a body that receives no
+/// bytes for `timeout` is abandoned and requested again from where it
+/// stopped with a `Range` request (guarded by `If-Range` when the server sent
+/// an `ETag`), up to `max_resumes` times per download. Servers that answer
+/// with the whole file are read from the start again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StallConfig {
+    /// Time without receiving any bytes after which the transfer is resumed
+    pub timeout: Duration,
+    /// Most resumes of one download before it fails
+    pub max_resumes: u32,
+}
+
+impl StallConfig {
+    /// Resume transfers that receive nothing for `timeout`, at most 5 times each
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            max_resumes: 5,
+        }
+    }
+
+    /// Fail a download after `max_resumes` resumes
+    #[must_use]
+    pub fn with_max_resumes(mut self, max_resumes: u32) -> Self {
+        self.max_resumes = max_resumes;
+        self
+    }
+}
+
+/// Slowest transfer rate a download may keep up
+///
+/// A transfer that receives less than `min_bytes_per_second` on average over
+/// `window` is abandoned, so one pathological connection doesn't hold up the
+/// end of a large pass. With a [`StallConfig`], the transfer is resumed from
+/// where it was over a new connection, counting towards
+/// [`StallConfig::max_resumes`]; without one, the download fails and is
+/// retried from the start as set by [`DownloadOptions::retry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpeedFloor {
+    /// Least average rate, in bytes per second
+    pub min_bytes_per_second: u64,
+    /// Period the rate is averaged over
+    pub window: Duration,
+}
+
+impl SpeedFloor {
+    /// Abandon transfers slower than `min_bytes_per_second` over `window`
+    pub fn new(min_bytes_per_second: u64, window: Duration) -> Self {
+        Self {
+            min_bytes_per_second,
+            window,
+        }
+    }
+}
+
+/// Ceiling on the response bytes downloads hold in memory at once
+///
+/// Downloads stream each body to a partial file as it arrives, holding only
+/// the chunk being written in memory. With a budget, every transfer reserves
+/// a buffer of [`TRANSFER_BUFFER_BYTES`] (less for smaller assets) before
+/// reading its body and releases it once the body is on disk; when the budget
+/// is used up, further transfers wait. Assets of any size, with or without a
+/// `Content-Length`, fit within the buffer, so the ceiling holds whatever is
+/// downloaded and bounds how many transfers run at once.
+///
+/// One budget can be shared by several passes through their
+/// [`DownloadOptions::memory_budget`], bounding them together.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: usize,
+    kib: Arc<Semaphore>,
+}
+
+impl MemoryBudget {
+    /// Create a budget of `limit` bytes, rounded up to whole KiB
+    pub fn new(limit: usize) -> Self {
+        let kib = limit.div_ceil(1024).clamp(1, u32::MAX as usize);
+        Self {
+            limit: kib * 1024,
+            kib: Arc::new(Semaphore::new(kib)),
+        }
+    }
+
+    /// The ceiling, in bytes
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Bytes currently reserved by downloads
+    pub fn in_use(&self) -> usize {
+        self.limit - self.kib.available_permits() * 1024
+    }
+
+    /// Reserves the buffer of a transfer whose body is `length` bytes, if known
+    ///
+    /// Waits until enough memory has been released.
+    async fn reserve(&self, length: Option<u64>) -> Option<OwnedSemaphorePermit> {
+        let buffer = length.map_or(TRANSFER_BUFFER_BYTES as u64, |length| {
+            length.min(TRANSFER_BUFFER_BYTES as u64)
+        });
+        let kib = (buffer.div_ceil(1024) as usize).clamp(1, self.limit / 1024);
+        // The semaphore is never closed, so this doesn't fail
+        Arc::clone(&self.kib)
+            .acquire_many_owned(kib as u32)
+            .await
+            .ok()
+    }
+}
+
+/// Memory a transfer reserves from a [`MemoryBudget`] while streaming its body
+pub const TRANSFER_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Leading bytes of a body kept to detect its type, see [`check_asset_body`]
+const HEAD_BYTES: usize = 64;
+
+/// How the bodies of a pass's downloads are read
+#[derive(Debug, Clone)]
+struct TransferLimits {
+    stall: Option<StallConfig>,
+    speed_floor: Option<SpeedFloor>,
+    memory: Option<Arc<MemoryBudget>>,
+    staging_dir: PathBuf,
+}
+
+impl TransferLimits {
+    fn new(options: &DownloadOptions, staging_dir: PathBuf) -> Self {
+        Self {
+            stall: options.stall.clone(),
+            speed_floor: options.speed_floor.clone(),
+            memory: options.memory_budget.clone(),
+            staging_dir,
+        }
+    }
+}
+
+/// A downloaded body in a partial file, which is deleted when this is dropped
+///
+/// Saving the body moves the file into place instead, see [`Staged::into_path`].
+pub(crate) struct Staged {
+    path: Option<PathBuf>,
+    len: u64,
+    head: Vec<u8>,
+    crc: Crc32,
+}
+
+impl Staged {
+    /// Creates an empty partial file with a unique name in `dir`
+    async fn create(dir: &Path) -> io::Result<(Self, tokio::fs::File)> {
+        static STAGED: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            ".download-{}-{}.part",
+            std::process::id(),
+            STAGED.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = dir.join(name);
+        let file = tokio::fs::File::create(&path).await?;
+        let staged = Self {
+            path: Some(path),
+            len: 0,
+            head: Vec::new(),
+            crc: Crc32::default(),
+        };
+        Ok((staged, file))
+    }
+
+    /// Appends a chunk of the body to the file
+    async fn write(&mut self, file: &mut tokio::fs::File, chunk: &[u8]) -> io::Result<()> {
+        file.write_all(chunk).await?;
+        let missing = HEAD_BYTES.saturating_sub(self.head.len()).min(chunk.len());
+        self.head.extend_from_slice(&chunk[..missing]);
+        self.crc.update(chunk);
+        self.len += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Empties the file, for a server that sends the whole body again
+    async fn restart(&mut self, file: &mut tokio::fs::File) -> io::Result<()> {
+        file.flush().await?;
+        file.set_len(0).await?;
+        file.seek(io::SeekFrom::Start(0)).await?;
+        self.len = 0;
+        self.head.clear();
+        self.crc = Crc32::default();
+        Ok(())
+    }
+
+    /// Path of the partial file
+    pub(crate) fn path(&self) -> &Path {
+        self.path.as_deref().unwrap_or(Path::new(""))
+    }
+
+    /// Size of the body in bytes
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// The first bytes of the body, enough to detect its type
+    pub(crate) fn head(&self) -> &[u8] {
+        &self.head
+    }
+
+    /// CRC-32 of the body, computed as it arrived
+    pub(crate) fn crc32(&self) -> u32 {
+        self.crc.finish()
+    }
+
+    /// Reads the whole body back into memory
+    async fn read(&self) -> io::Result<Vec<u8>> {
+        tokio::fs::read(self.path()).await
+    }
+
+    /// Deletes the partial file now rather than in the background
+    pub(crate) async fn discard(mut self) {
+        if let Some(path) = self.path.take() {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+
+    /// Gives up ownership of the partial file, which is no longer deleted on drop
+    fn into_path(mut self) -> PathBuf {
+        self.path.take().unwrap_or_default()
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        let Some(path) = self.path.take() else {
+            return;
+        };
+        // Bodies that lost a hedge, failed midway or were cancelled; removal is best effort
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(move || std::fs::remove_file(path))),
+            Err(_) => {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// The extra requests sent by a pass, capped by [`HedgeConfig::max_extra`]
+#[derive(Default)]
+struct HedgeBudget {
+    downloads: AtomicUsize,
+    hedged: AtomicUsize,
+}
+
+impl HedgeBudget {
+    /// Takes one extra request from the budget, if any is left
+    fn try_hedge(&self, max_extra: f64) -> bool {
+        let allowed = (self.downloads.load(Ordering::Relaxed) as f64 * max_extra).floor() as usize;
+        self.hedged
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |hedged| {
+                (hedged < allowed).then_some(hedged + 1)
+            })
+            .is_ok()
+    }
+}
+
+/// File name template of [`download_derivative_classes`], e.g. `ABC123_thumb.jpg`
+pub const DEFAULT_FILE_NAME_TEMPLATE: &str = "{base}_{suffix}.{ext}";
+
+/// Fills in a file name template of [`download_derivative_classes`]
+///
+/// # Arguments
+///
+/// * `template` - The template, with these placeholders:
+///   `{base}` for the photo's base filename (see [`utils::photo_base_filename`]),
+///   `{suffix}` for [`SizeClass::file_suffix`] and
+///   `{ext}` for the extension detected from the content, without the dot
+/// * `base` - The base filename of the photo
+/// * `size_class` - The size class of the file
+/// * `extension` - The extension, with or without its leading dot
+///
+/// # Returns
+///
+/// The file name; the extension is appended if the template has no `{ext}`
+pub fn render_file_name(
+    template: &str,
+    base: &str,
+    size_class: SizeClass,
+    extension: &str,
+) -> String {
+    let extension = extension.trim_start_matches('.');
+    let name = template
+        .replace("{base}", base)
+        .replace("{suffix}", size_class.file_suffix());
+    if template.contains("{ext}") {
+        name.replace("{ext}", extension)
+    } else if extension.is_empty() {
+        name
+    } else {
+        format!("{}.{}", name, extension)
+    }
+}
+
+/// Checks that the templates of the requested classes name every file apart
+///
+/// A template must contain `{base}` and no path separator, and templates of
+/// different classes must differ by more than the extension, so that a video
+/// and its poster frame, or two JPEG renditions, never share a name.
+pub(crate) fn check_file_name_templates(
+    options: &DownloadOptions,
+    classes: &[SizeClass],
+) -> Result<(), String> {
+    let mut rendered: HashMap<String, SizeClass> = HashMap::new();
+    for &size_class in classes {
+        let template = options.file_name_template(size_class);
+        if !template.contains("{base}") || template.contains(['/', '\\']) {
+            return Err(format!(
+                "file name template {:?} of {:?} files must contain {{base}} and no path separator",
+                template, size_class
+            ));
+        }
+        let name = render_file_name(template, "base", size_class, "ext");
+        match rendered.insert(name, size_class) {
+            Some(other) if other != size_class => {
+                return Err(format!(
+                    "file name templates of {:?} and {:?} files give the same names",
+                    other, size_class
+                ))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Options controlling how downloaded files are written
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct DownloadOptions {
+    /// Directory for partial files, or `None` to stage them in the output directory
+    ///
+    /// Staging on a fast local disk and moving into a network mount is
+    /// supported; moves across filesystems fall back to copy and remove.
+    pub temp_dir: Option<PathBuf>,
+    /// Flush files and their parent directory to stable storage
+    ///
+    /// When enabled, the file contents are synced before the file is moved
+    /// into place and the destination directory is synced afterwards, so a
+    /// power loss cannot leave a truncated file under its final name.
+    pub fsync: bool,
+    /// Unix permission bits for written files (e.g. `0o644`), or `None` to keep the umask default
+    pub file_mode: Option<u32>,
+    /// Unix permission bits for output directories created by the download
+    pub dir_mode: Option<u32>,
+    /// Owner user ID for written files and created directories (requires privileges)
+    pub uid: Option<u32>,
+    /// Owner group ID for written files and created directories (requires privileges)
+    pub gid: Option<u32>,
+    /// Record provenance as extended attributes on written files
+    ///
+    /// Requires the `xattr` feature on Linux or macOS; ignored otherwise.
+    /// Filesystems without extended attribute support are logged and skipped.
+    pub xattrs: bool,
+    /// Album name recorded in the provenance attributes
+    pub album_name: Option<String>,
+    /// Write a Google Takeout compatible `<file>.json` next to every file
+    ///
+    /// See [`crate::takeout`] for the format.
+    pub takeout_metadata: bool,
+    /// Policy the download URL and any redirects must satisfy, or `None` to allow any URL
+    pub url_policy: Option<UrlPolicy>,
+    /// How many requests [`download_derivative_classes`] runs at once per host
+    pub concurrency: Concurrency,
+    /// When [`download_derivative_classes`] pauses requests to a failing host
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Address family, connect timeout and resolver settings of the download client
+    pub network: NetworkConfig,
+    /// How [`download_derivative_classes`] retries failed downloads, or `None` for a single attempt
+    pub retry: Option<RetryConfig>,
+    /// How [`download_derivative_classes`] transcodes downloaded videos, or `None` to keep them as is
+    #[cfg(feature = "transcode")]
+    pub transcode: Option<crate::transcode::TranscodeConfig>,
+    /// Signal stopping [`download_derivative_classes`], or `None` to always finish the pass
+    pub shutdown: Option<Shutdown>,
+    /// When [`download_derivative_classes`] requests slow small assets twice, or `None` never to
+    pub hedge: Option<HedgeConfig>,
+    /// Journal recording file writes for crash recovery, see [`crate::journal`]
+    pub journal: Option<Arc<DownloadJournal>>,
+    /// File name templates of [`download_derivative_classes`] by size class
+    ///
+    /// Classes without a template use [`DEFAULT_FILE_NAME_TEMPLATE`].
+    pub file_name_templates: HashMap<SizeClass, String>,
+    /// Treat captions as Markdown, naming files after their plain text
+    ///
+    /// See [`crate::markdown::strip_markdown`].
+    pub markdown_captions: bool,
+    /// API base URL used to resolve a photo's URLs again when its asset URL
+    /// returns something other than media, or `None` not to
+    pub url_refresh: Option<String>,
+    /// When stalled transfers are resumed, or `None` to wait for them
+    pub stall: Option<StallConfig>,
+    /// Slowest transfer rate kept up, or `None` to accept any rate
+    pub speed_floor: Option<SpeedFloor>,
+    /// Ceiling on the response bytes held in memory, or `None` for no ceiling
+    pub memory_budget: Option<Arc<MemoryBudget>>,
+    /// Pack receiving the files of [`DownloadOptions::packed_classes`], see [`crate::packfile`]
+    pub packfile: Option<Arc<Packfile>>,
+    /// Size classes [`download_derivative_classes`] appends to the pack instead of writing files
+    pub packed_classes: Vec<SizeClass>,
+    /// Steps [`download_derivative_classes`] runs on the written files, see [`crate::postprocess`]
+    pub post_processing: PostProcessing,
+    /// Hooks [`download_derivative_classes`] consults before each request, see [`crate::predownload`]
+    pub pre_download: PreDownloadHooks,
+}
+
+impl DownloadOptions {
+    /// Create options with the default behavior, adjusted by the environment
+    ///
+    /// See [`crate::config`] for the variables; [`DownloadOptions::default`]
+    /// ignores them.
+    pub fn new() -> Self {
+        crate::config::Config::global().apply_to_download(Self::default())
+    }
+
+    /// Stop [`download_derivative_classes`] passes when `shutdown` is triggered
+    #[must_use]
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Stage partial files in the given directory
+    #[must_use]
+    pub fn with_temp_dir(mut self, temp_dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = Some(temp_dir.into());
+        self
+    }
+
+    /// Enable or disable syncing files and directories to stable storage
+    #[must_use]
+    pub fn with_fsync(mut self, fsync: bool) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    /// Set the permission bits of written files and created directories
+    ///
+    /// Permissions are only applied on Unix platforms.
+    #[must_use]
+    pub fn with_modes(mut self, file_mode: u32, dir_mode: u32) -> Self {
+        self.file_mode = Some(file_mode);
+        self.dir_mode = Some(dir_mode);
+        self
+    }
+
+    /// Record the photo GUID and, if given, the album name as extended attributes
+    #[must_use]
+    pub fn with_xattrs(mut self, album_name: Option<&str>) -> Self {
+        self.xattrs = true;
+        self.album_name = album_name.map(|name| name.to_string());
+        self
+    }
+
+    /// Write Google Takeout compatible metadata next to every downloaded file
+    #[must_use]
+    pub fn with_takeout_metadata(mut self, takeout_metadata: bool) -> Self {
+        self.takeout_metadata = takeout_metadata;
+        self
+    }
+
+    /// Only download from URLs allowed by `policy`, refusing redirects elsewhere
+    #[must_use]
+    pub fn with_url_policy(mut self, policy: UrlPolicy) -> Self {
+        self.url_policy = Some(policy);
+        self
+    }
+
+    /// Set how many downloads run at once per host, or let the limit adapt to the server
+    ///
+    /// See [`crate::throttle`] for how adaptive concurrency reacts to
+    /// throttling and latency.
+    #[must_use]
+    pub fn with_concurrency(mut self, concurrency: Concurrency) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Set how the download client connects, e.g. to force IPv4
+    #[must_use]
+    pub fn with_network(mut self, network: NetworkConfig) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Retry failed downloads as configured by `retry`
+    ///
+    /// Usually the [`crate::api::Endpoint::AssetDownload`] entry of a
+    /// [`crate::api::RetryPolicies`]. Downloads refused by the URL policy or
+    /// skipped while their host is paused are not retried.
+    #[must_use]
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Transcode downloaded videos to H.264 MP4 with ffmpeg
+    ///
+    /// See [`crate::transcode`] for which videos are transcoded.
+    #[cfg(feature = "transcode")]
+    #[must_use]
+    pub fn with_transcode(mut self, transcode: crate::transcode::TranscodeConfig) -> Self {
+        self.transcode = Some(transcode);
+        self
+    }
+
+    /// Record file writes in `journal`, so a crashed run can be cleaned up
+    #[must_use]
+    pub fn with_journal(mut self, journal: Arc<DownloadJournal>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Request slow small assets a second time, see [`HedgeConfig`]
+    #[must_use]
+    pub fn with_hedging(mut self, hedge: HedgeConfig) -> Self {
+        self.hedge = Some(hedge);
+        self
+    }
+
+    /// Name the files of a size class after `template`, see [`render_file_name`]
+    ///
+    /// For example, `{base}.{ext}` for [`SizeClass::Video`] and
+    /// `{base}.poster.{ext}` for [`SizeClass::VideoPoster`] keep a video and
+    /// its poster frame side by side.
+    #[must_use]
+    pub fn with_file_name_template(
+        mut self,
+        size_class: SizeClass,
+        template: impl Into<String>,
+    ) -> Self {
+        self.file_name_templates.insert(size_class, template.into());
+        self
+    }
+
+    /// Name files after the plain text of Markdown captions
+    #[must_use]
+    pub fn with_markdown_captions(mut self, markdown_captions: bool) -> Self {
+        self.markdown_captions = markdown_captions;
+        self
+    }
+
+    /// The file name template of a size class
+    pub fn file_name_template(&self, size_class: SizeClass) -> &str {
+        self.file_name_templates
+            .get(&size_class)
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_FILE_NAME_TEMPLATE)
+    }
+
+    /// Resolve a photo's URLs again through `base_url` when its asset URL returns no media
+    ///
+    /// `base_url` is the album's (redirected) API base URL, as used by
+    /// [`crate::enrich::resolve_photo_urls`]. Only [`download_derivative_classes`]
+    /// refreshes URLs, once per download and after any retries.
+    #[must_use]
+    pub fn with_url_refresh(mut self, base_url: impl Into<String>) -> Self {
+        self.url_refresh = Some(base_url.into());
+        self
+    }
+
+    /// Resume transfers that stop receiving data, see [`StallConfig`]
+    #[must_use]
+    pub fn with_stall_detection(mut self, stall: StallConfig) -> Self {
+        self.stall = Some(stall);
+        self
+    }
+
+    /// Abandon transfers that are too slow, see [`SpeedFloor`]
+    #[must_use]
+    pub fn with_speed_floor(mut self, speed_floor: SpeedFloor) -> Self {
+        self.speed_floor = Some(speed_floor);
+        self
+    }
+
+    /// Hold at most `bytes` of downloaded content in memory at once, see [`MemoryBudget`]
+    ///
+    /// For example, `with_memory_limit(256 * 1024 * 1024)` for 256 MiB.
+    #[must_use]
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(Arc::new(MemoryBudget::new(bytes)));
+        self
+    }
+
+    /// Share `budget` with other downloaders, e.g. to bound several albums together
+    #[must_use]
+    pub fn with_memory_budget(mut self, budget: Arc<MemoryBudget>) -> Self {
+        self.memory_budget = Some(budget);
+        self
+    }
+
+    /// Append the files of `classes` to `packfile` instead of writing them one by one
+    ///
+    /// Meant for classes with many small files, e.g. [`SizeClass::Thumbnail`].
+    /// Only [`download_derivative_classes`] packs files; it flushes the pack's
+    /// index at the end of every pass.
+    #[must_use]
+    pub fn with_packfile(mut self, packfile: Arc<Packfile>, classes: &[SizeClass]) -> Self {
+        self.packfile = Some(packfile);
+        self.packed_classes = classes.to_vec();
+        self
+    }
+
+    /// The pack receiving the files of a size class, if that class is packed
+    pub fn packfile_for(&self, size_class: SizeClass) -> Option<&Arc<Packfile>> {
+        self.packfile
+            .as_ref()
+            .filter(|_| self.packed_classes.contains(&size_class))
+    }
+
+    /// Adds a step run on every file [`download_derivative_classes`] writes
+    ///
+    /// Steps run in the order they are added, see [`crate::postprocess`].
+    #[must_use]
+    pub fn with_post_processor(mut self, processor: impl PostProcessor + 'static) -> Self {
+        self.post_processing.processors.push(Arc::new(processor));
+        self
+    }
+
+    /// Adds a hook consulted before [`download_derivative_classes`] requests a file
+    ///
+    /// Hooks are consulted in the order they are added, see [`crate::predownload`].
+    #[must_use]
+    pub fn with_pre_download_hook(mut self, hook: impl PreDownloadHook + 'static) -> Self {
+        self.pre_download.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Sets how many files are post-processed at once
+    #[must_use]
+    pub fn with_post_process_concurrency(mut self, concurrency: usize) -> Self {
+        self.post_processing.concurrency = concurrency;
+        self
+    }
+
+    /// Set when requests to a failing host are paused
+    #[must_use]
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = circuit_breaker;
+        self
+    }
+
+    /// Builds the download client from the URL policy and network settings
+    pub(crate) fn build_client(&self) -> reqwest::Result<reqwest::Client> {
+        let builder = match &self.url_policy {
+            Some(policy) => policy.client_builder(),
+            None => reqwest::Client::builder(),
+        };
+        self.network.apply(builder).build()
+    }
+
+    /// Set the owner of written files and created directories
+    ///
+    /// `None` leaves the corresponding ID unchanged. Changing ownership usually
+    /// requires running as root (e.g. in a container) and is only applied on
+    /// Unix platforms.
+    #[must_use]
+    pub fn with_owner(mut self, uid: Option<u32>, gid: Option<u32>) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+}
+
+/// Downloads a single photo or video, writing it according to `options`
+///
+/// This behaves like [`crate::download_photo`], which uses the default options.
+///
+/// # Arguments
+///
+/// * `photo` - The photo to download
+/// * `index` - Optional index for numbering purposes (useful in loops)
+/// * `output_dir` - Directory where the file should be saved
+/// * `custom_filename` - Optional custom filename to use (without extension)
+/// * `options` - Options controlling how the file is written
+///
+/// # Returns
+///
+/// A Result containing the filepath where the content was saved
+pub async fn download_photo_with_options(
+    photo: &Image,
+    index: Option<usize>,
+    output_dir: &str,
+    custom_filename: Option<String>,
+    options: &DownloadOptions,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut context = ErrorContext::new("download").with_guid(&photo.photo_guid);
+    let result: Result<String, Box<dyn Error>> = async {
+        let (_key, _derivative, url) = utils::select_best_derivative(&photo.derivatives)
+            .ok_or_else(|| "No suitable derivative found for download".to_string())?;
+        context.endpoint = Some(url.clone());
+
+        if let Some(policy) = &options.url_policy {
+            policy.validate(&url)?;
+        }
+        let client = options.build_client()?;
+        let response = crate::har::send(client.get(&url)).await?;
+        let target = SaveTarget {
+            photo,
+            index,
+            output_dir,
+            custom_filename: custom_filename.as_deref(),
+        };
+        save_response(&client, &url, response, &target, options).await
+    }
+    .await;
+
+    result.map_err(|source| DownloadError { context, source }.into())
+}
+
+/// Downloads a photo unless the server reports it unchanged, writing it according to `options`
+///
+/// This behaves like [`crate::download_photo_if_changed`], which uses the
+/// default options. The body is streamed to disk like in
+/// [`download_photo_with_options`], within [`DownloadOptions::memory_budget`].
+///
+/// # Arguments
+///
+/// * `photo` - The photo to download
+/// * `index` - Optional index for numbering purposes (useful in loops)
+/// * `output_dir` - Directory where the file should be saved
+/// * `custom_filename` - Optional custom filename to use (without extension)
+/// * `validators` - Validators from a previous download, or empty for an unconditional download
+/// * `options` - Options controlling how the file is written
+///
+/// # Returns
+///
+/// A Result containing whether the file was downloaded or confirmed unchanged
+pub async fn download_photo_if_changed_with_options(
+    photo: &Image,
+    index: Option<usize>,
+    output_dir: &str,
+    custom_filename: Option<String>,
+    validators: &HttpValidators,
+    options: &DownloadOptions,
+) -> Result<DownloadOutcome, Box<dyn std::error::Error>> {
+    let mut context = ErrorContext::new("download").with_guid(&photo.photo_guid);
+    let result: Result<DownloadOutcome, Box<dyn Error>> = async {
+        let (_key, _derivative, url) = utils::select_best_derivative(&photo.derivatives)
+            .ok_or_else(|| "No suitable derivative found for download".to_string())?;
+        context.endpoint = Some(url.clone());
+
+        if let Some(policy) = &options.url_policy {
+            policy.validate(&url)?;
+        }
+        let client = options.build_client()?;
+        let mut request = client.get(&url);
+        if let Some(etag) = &validators.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+
+        let response = crate::har::send(request).await?;
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(DownloadOutcome::NotModified);
+        }
+        let response = response.error_for_status()?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let validators = HttpValidators {
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+        };
+
+        let target = SaveTarget {
+            photo,
+            index,
+            output_dir,
+            custom_filename: custom_filename.as_deref(),
+        };
+        let path = save_response(&client, &url, response, &target, options).await?;
+        Ok(DownloadOutcome::Downloaded { path, validators })
+    }
+    .await;
+
+    result.map_err(|source| DownloadError { context, source }.into())
+}
+
+/// Where a single downloaded photo is saved
+struct SaveTarget<'a> {
+    photo: &'a Image,
+    index: Option<usize>,
+    output_dir: &'a str,
+    custom_filename: Option<&'a str>,
+}
+
+/// Streams the body of an asset response to a partial file and moves it into place
+async fn save_response(
+    client: &reqwest::Client,
+    url: &str,
+    response: reqwest::Response,
+    target: &SaveTarget<'_>,
+    options: &DownloadOptions,
+) -> Result<String, Box<dyn Error>> {
+    let staging_dir = prepare_output_dir(target.output_dir, options).await?;
+    let content_type = content_type_of(&response);
+    let transfer = TransferLimits::new(options, staging_dir);
+    let staged = read_body(client, url, response, &transfer).await?;
+    check_asset_head(content_type.as_deref(), staged.head(), staged.len())
+        .map_err(TransferError)?;
+    metrics::record(|metrics| metrics.on_bytes_downloaded(staged.len()));
+
+    let base_filename = base_filename(target.photo, target.index, target.custom_filename, options);
+    let extension = utils::get_extension_for_content(staged.head(), None);
+    let filename = format!("{}{}", base_filename, extension);
+    save_staged_named(staged, target.photo, target.output_dir, &filename, options).await
+}
+
+/// A derivative written by [`download_derivative_classes`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivativeDownload {
+    /// GUID of the photo
+    pub photo_guid: String,
+    /// Size class the derivative was selected for
+    pub size_class: SizeClass,
+    /// Key of the downloaded derivative
+    pub key: String,
+    /// Path of the written file
+    ///
+    /// For a class packed by [`DownloadOptions::with_packfile`], this is the
+    /// pack's path and the file's name in the pack joined by `#`, e.g.
+    /// `thumbs.pack#ABC123_thumb.jpg`.
+    pub path: String,
+}
+
+/// Result of [`download_derivative_classes`]
+#[derive(Debug, Default)]
+pub struct MultiDownloadReport {
+    /// Files written, in photo order and then in the order of the requested classes
+    pub downloaded: Vec<DerivativeDownload>,
+    /// Photos without a derivative of a requested class, as (photo GUID, size class)
+    pub missing: Vec<(String, SizeClass)>,
+    /// Downloads that failed, as (photo GUID, size class, error)
+    pub failures: Vec<(String, SizeClass, DownloadError)>,
+    /// Concurrency limit of each asset host at the end of the pass
+    ///
+    /// With adaptive concurrency, these are a good starting point for the next pass.
+    pub concurrency: HashMap<String, usize>,
+    /// Downloads cancelled by [`DownloadOptions::shutdown`], as (photo GUID, size class)
+    pub cancelled: Vec<(String, SizeClass)>,
+    /// Extra requests sent for slow assets, see [`DownloadOptions::hedge`]
+    pub hedged: usize,
+    /// Outcome of transcoding each downloaded video, if [`DownloadOptions::transcode`] is set
+    #[cfg(feature = "transcode")]
+    pub transcodes: Vec<crate::transcode::VideoTranscode>,
+    /// Downloads denied by a pre-download hook, as (photo GUID, size class, reason)
+    pub denied: Vec<(String, SizeClass, String)>,
+    /// Files a post-processor failed on, see [`DownloadOptions::post_processing`]
+    pub post_process_failures: Vec<PostProcessFailure>,
+}
+
+impl MultiDownloadReport {
+    /// Returns the videos written together with their poster frames
+    ///
+    /// # Returns
+    ///
+    /// The photo GUID, video path and poster path of every photo for which
+    /// both a [`SizeClass::Video`] and a [`SizeClass::VideoPoster`] file were
+    /// written, in photo order
+    pub fn video_pairs(&self) -> Vec<(String, String, String)> {
+        let posters: HashMap<&str, &str> = self
+            .downloaded
+            .iter()
+            .filter(|d| d.size_class == SizeClass::VideoPoster)
+            .map(|d| (d.photo_guid.as_str(), d.path.as_str()))
+            .collect();
+        self.downloaded
+            .iter()
+            .filter(|d| d.size_class == SizeClass::Video)
+            .filter_map(|d| {
+                let poster = posters.get(d.photo_guid.as_str())?;
+                Some((d.photo_guid.clone(), d.path.clone(), poster.to_string()))
+            })
+            .collect()
+    }
+}
+
+/// Downloads several size classes of every photo in one pass
+///
+/// For each photo and class, the largest derivative of that class is
+/// downloaded (see [`utils::select_derivative_of_class`]) and saved as
+/// `<base>_<suffix>.<ext>`, where the suffix comes from
+/// [`SizeClass::file_suffix`], e.g. `ABC123_thumb.jpg` and
+/// `ABC123_original.jpg`, unless [`DownloadOptions::file_name_templates`]
+/// names the class differently. Classes packed by
+/// [`DownloadOptions::with_packfile`] are appended to the pack instead. All
+/// requests share one client, so connections to the asset servers are reused.
+/// A failed download is recorded in the report and does not stop the pass.
+///
+/// Requests run concurrently as set by [`DownloadOptions::concurrency`], with
+/// a separate limit and circuit breaker for each asset host (see
+/// [`HostLimits`]); responses are written to disk one at a time as they arrive.
+///
+/// When [`DownloadOptions::shutdown`] is triggered, requests in flight are
+/// cancelled and the pass returns with them listed in
+/// [`MultiDownloadReport::cancelled`]. Responses that already arrived are
+/// still written, and no partial files are left behind.
+///
+/// Downloads run as tasks owned by the pass. A download whose task panics is
+/// recorded in [`MultiDownloadReport::failures`] with the panic message while
+/// the others carry on, and dropping the pass's future aborts every task it
+/// spawned, so none outlive it.
+///
+/// # Arguments
+///
+/// * `photos` - The photos to download, enriched with URLs
+/// * `output_dir` - Directory where the files should be saved
+/// * `classes` - The size classes to download for every photo
+/// * `options` - Options controlling how the files are written
+///
+/// # Returns
+///
+/// A report of the written files, missing classes and failures, or an error if
+/// the HTTP client could not be built, the file name templates of two
+/// classes could give the same name or the pack's index could not be written
+pub async fn download_derivative_classes(
+    photos: &[Image],
+    output_dir: &str,
+    classes: &[SizeClass],
+    options: &DownloadOptions,
+) -> Result<MultiDownloadReport, Box<dyn Error>> {
+    logging::in_operation(download_classes(photos, output_dir, classes, options)).await
+}
+
+/// Runs a [`download_derivative_classes`] pass within the current operation
+async fn download_classes(
+    photos: &[Image],
+    output_dir: &str,
+    classes: &[SizeClass],
+    options: &DownloadOptions,
+) -> Result<MultiDownloadReport, Box<dyn Error>> {
+    check_file_name_templates(options, classes)?;
+    let client = options.build_client()?;
+    let operation = logging::operation_id().unwrap_or_default();
+    let transport = crate::transport::current();
+    let hosts = Arc::new(HostLimits::new(
+        options.concurrency.clone(),
+        options.circuit_breaker.clone(),
+    ));
+
+    let budget = Arc::new(HedgeBudget::default());
+
+    let mut report = MultiDownloadReport::default();
+    let mut jobs = Vec::new();
+    let mut tasks = JoinSet::new();
+    let mut task_jobs = HashMap::new();
+    let mut downloaded = Vec::new();
+    let mut failures = Vec::new();
+    let mut cancelled = Vec::new();
+    let transfer = TransferLimits::new(options, prepare_output_dir(output_dir, options).await?);
+    for photo in photos {
+        for &size_class in classes {
+            let Some((key, derivative)) =
+                utils::select_derivative_of_class(&photo.derivatives, size_class)
+            else {
+                report.missing.push((photo.photo_guid.clone(), size_class));
+                continue;
+            };
+            // Selected derivatives always have a URL
+            let url = derivative.url.clone().unwrap_or_default();
+
+            let mut context = ErrorContext::new("download").with_guid(&photo.photo_guid);
+            context.endpoint = Some(url.clone());
+            let index = jobs.len();
+            let url = if options.pre_download.hooks.is_empty() {
+                url
+            } else {
+                let check = options
+                    .pre_download
+                    .check(photo, size_class, &key, derivative, url);
+                let verdict = match &options.shutdown {
+                    Some(shutdown) => shutdown.run_until(check).await,
+                    None => Some(check.await),
+                };
+                match verdict {
+                    Some(Verdict::Download(url)) => url,
+                    Some(Verdict::Denied(reason)) => {
+                        logging::log_debug!(
+                            logging::DOWNLOAD,
+                            "Download of {:?} of photo {} denied: {}",
+                            size_class,
+                            photo.photo_guid,
+                            reason
+                        );
+                        report
+                            .denied
+                            .push((photo.photo_guid.clone(), size_class, reason));
+                        continue;
+                    }
+                    Some(Verdict::Failed(hook, e)) => {
+                        let source = format!("pre-download hook {} failed: {}", hook, e).into();
+                        failures.push((
+                            index,
+                            (
+                                photo.photo_guid.clone(),
+                                size_class,
+                                DownloadError { context, source },
+                            ),
+                        ));
+                        continue;
+                    }
+                    None => {
+                        cancelled.push((index, (photo.photo_guid.clone(), size_class)));
+                        continue;
+                    }
+                }
+            };
+            context.endpoint = Some(url.clone());
+            jobs.push((photo, size_class, key, context));
+
+            let client = client.clone();
+            let hosts = Arc::clone(&hosts);
+            let policy = options.url_policy.clone();
+            let retry = options.retry.clone();
+            let transfer = transfer.clone();
+            let shutdown = options.shutdown.clone().unwrap_or_default();
+            let hedge = options
+                .hedge
+                .clone()
+                .filter(|hedge| hedge.applies(size_class, derivative.file_size))
+                .map(|hedge| (hedge, Arc::clone(&budget)));
+            budget.downloads.fetch_add(1, Ordering::Relaxed);
+            // Spawned tasks don't inherit the operation ID or transport
+            let fetch = async move {
+                let fetch = fetch_hedged(&client, &url, &hosts, retry.as_ref(), &transfer, hedge);
+                let fetched = match policy.map(|policy| policy.validate(&url)) {
+                    Some(Err(e)) => Err(DownloadFailure::Policy(e)),
+                    _ => shutdown
+                        .run_until(fetch)
+                        .await
+                        .unwrap_or(Err(DownloadFailure::Cancelled)),
+                };
+                (index, fetched)
+            };
+            let task = tasks.spawn(logging::with_operation_id(
+                operation.clone(),
+                crate::transport::scoped(transport.clone(), fetch),
+            ));
+            task_jobs.insert(task.id(), index);
+        }
+    }
+
+    while let Some(joined) = tasks.join_next().await {
+        let (index, fetched) = match joined {
+            Ok(joined) => joined,
+            // Every task the set owns was spawned above
+            Err(e) => (task_jobs[&e.id()], Err(DownloadFailure::from_join_error(e))),
+        };
+        let (photo, size_class, key, context) = &jobs[index];
+        // Refreshed here rather than in the task, so the request runs in the caller's scope
+        let fetched = match (fetched, &options.url_refresh) {
+            (Err(DownloadFailure::InvalidBody(reason)), Some(base_url)) => {
+                let refetch =
+                    refetch_refreshed(&client, base_url, photo, key, &hosts, &transfer, options);
+                let shutdown = options.shutdown.clone().unwrap_or_default();
+                match shutdown.run_until(refetch).await {
+                    Some(Some(refetched)) => refetched,
+                    Some(None) => Err(DownloadFailure::InvalidBody(reason)),
+                    None => Err(DownloadFailure::Cancelled),
+                }
+            }
+            (fetched, _) => fetched,
+        };
+        let result: Result<String, Box<dyn Error>> = match fetched {
+            Err(DownloadFailure::Cancelled) => {
+                cancelled.push((index, (photo.photo_guid.clone(), *size_class)));
+                continue;
+            }
+            Ok(staged) => {
+                let filename = render_file_name(
+                    options.file_name_template(*size_class),
+                    &base_filename(photo, None, None, options),
+                    *size_class,
+                    &match photo.derivatives.get(key) {
+                        Some(derivative) => {
+                            utils::get_extension_for_derivative(staged.head(), derivative)
+                        }
+                        None => utils::get_extension_for_content(staged.head(), None),
+                    },
+                );
+                match options.packfile_for(*size_class) {
+                    Some(packfile) => pack_content(packfile, staged, photo, filename).await,
+                    None => save_staged_named(staged, photo, output_dir, &filename, options).await,
+                }
+            }
+            Err(failure) => Err(failure.into()),
+        };
+        match result {
+            Ok(path) => downloaded.push((
+                index,
+                DerivativeDownload {
+                    photo_guid: photo.photo_guid.clone(),
+                    size_class: *size_class,
+                    key: key.clone(),
+                    path,
+                },
+            )),
+            Err(source) => failures.push((
+                index,
+                (
+                    photo.photo_guid.clone(),
+                    *size_class,
+                    DownloadError {
+                        context: context.clone(),
+                        source,
+                    },
+                ),
+            )),
+        }
+    }
+
+    if let Some(packfile) = options
+        .packfile
+        .as_ref()
+        .filter(|_| classes.iter().any(|c| options.packed_classes.contains(c)))
+    {
+        let packfile = Arc::clone(packfile);
+        tokio::task::spawn_blocking(move || packfile.flush()).await??;
+    }
+
+    // Report in photo order, whatever order the responses arrived in
+    downloaded.sort_by_key(|(index, _)| *index);
+    failures.sort_by_key(|(index, _)| *index);
+    cancelled.sort_by_key(|(index, _)| *index);
+    report.downloaded = downloaded.into_iter().map(|(_, d)| d).collect();
+    report.failures = failures.into_iter().map(|(_, f)| f).collect();
+    report.cancelled = cancelled.into_iter().map(|(_, c)| c).collect();
+    if !report.cancelled.is_empty() {
+        logging::log_debug!(
+            logging::DOWNLOAD,
+            "Shutdown cancelled {} downloads",
+            report.cancelled.len()
+        );
+    }
+    report.concurrency = hosts.concurrency();
+    report.hedged = budget.hedged.load(Ordering::Relaxed);
+    // Transcoding is skipped entirely on shutdown
+    #[cfg(feature = "transcode")]
+    if let Some(config) = options.transcode.as_ref().filter(|_| {
+        !options
+            .shutdown
+            .as_ref()
+            .is_some_and(Shutdown::is_triggered)
+    }) {
+        report.transcodes =
+            crate::transcode::transcode_downloads(&mut report.downloaded, config, options).await;
+    }
+    if !options.post_processing.processors.is_empty() {
+        report.post_process_failures =
+            crate::postprocess::post_process_downloads(&mut report.downloaded, photos, options)
+                .await;
+    }
+    Ok(report)
+}
+
+/// Appends a downloaded file to a pack, returning its `<pack>#<name>` path
+///
+/// Packed classes hold small files, so the file is read back into memory.
+async fn pack_content(
+    packfile: &Arc<Packfile>,
+    staged: Staged,
+    photo: &Image,
+    name: String,
+) -> Result<String, Box<dyn Error>> {
+    let content = staged.read().await?;
+    staged.discard().await;
+    let path = format!("{}#{}", packfile.path().display(), name);
+    let packfile = Arc::clone(packfile);
+    let photo_guid = photo.photo_guid.clone();
+    tokio::task::spawn_blocking(move || packfile.append(&name, &photo_guid, &content)).await??;
+    Ok(path)
+}
+
+/// Why a concurrent download did not produce content
+#[derive(Debug)]
+enum DownloadFailure {
+    Policy(crate::url_policy::UrlPolicyError),
+    Paused(String),
+    Http(reqwest::Error),
+    InvalidBody(String),
+    Stalled(String),
+    Io(io::Error),
+    Panicked(String),
+    Cancelled,
+}
+
+impl DownloadFailure {
+    /// The failure of a download task that did not return
+    fn from_join_error(e: tokio::task::JoinError) -> Self {
+        if !e.is_panic() {
+            return DownloadFailure::Cancelled;
+        }
+        let payload = e.into_panic();
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        logging::log_warn!(logging::DOWNLOAD, "Download task panicked: {}", message);
+        DownloadFailure::Panicked(message)
+    }
+
+    /// What was wrong with a received body, for failures worth another request
+    fn body_problem(&self) -> Option<&str> {
+        match self {
+            DownloadFailure::InvalidBody(reason) | DownloadFailure::Stalled(reason) => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+/// A transfer the asset host refused, stopped or answered with something other than media
+#[derive(Debug)]
+pub(crate) struct TransferError(String);
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for TransferError {}
+
+impl From<DownloadFailure> for Box<dyn Error> {
+    fn from(failure: DownloadFailure) -> Self {
+        match failure {
+            DownloadFailure::Policy(e) => e.into(),
+            DownloadFailure::Paused(host) => Box::new(TransferError(format!(
+                "downloads from {} are paused after repeated failures",
+                host
+            ))),
+            DownloadFailure::Http(e) => e.into(),
+            DownloadFailure::InvalidBody(reason) | DownloadFailure::Stalled(reason) => {
+                Box::new(TransferError(reason))
+            }
+            DownloadFailure::Io(e) => e.into(),
+            DownloadFailure::Panicked(message) => {
+                format!("download task panicked: {}", message).into()
+            }
+            DownloadFailure::Cancelled => "download was cancelled by shutdown".into(),
+        }
+    }
+}
+
+/// Downloads a URL with [`fetch_with_retry`], requesting it again if it is slow to arrive
+///
+/// With a `hedge`, a second request is sent once the first has taken longer
+/// than [`HedgeConfig::delay`], if the budget allows. The first successful
+/// response wins; the other request is dropped.
+async fn fetch_hedged(
+    client: &reqwest::Client,
+    url: &str,
+    hosts: &HostLimits,
+    retry: Option<&RetryConfig>,
+    transfer: &TransferLimits,
+    hedge: Option<(HedgeConfig, Arc<HedgeBudget>)>,
+) -> Result<Staged, DownloadFailure> {
+    let first = fetch_with_retry(client, url, hosts, retry, transfer);
+    let Some((config, budget)) = hedge else {
+        return first.await;
+    };
+    tokio::pin!(first);
+    tokio::select! {
+        fetched = &mut first => return fetched,
+        _ = tokio::time::sleep(config.delay) => {}
+    }
+    if !budget.try_hedge(config.max_extra) {
+        return first.await;
+    }
+
+    logging::log_debug!(
+        logging::DOWNLOAD,
+        "Hedging slow download of {}",
+        Redacted(url)
+    );
+    let second = fetch_with_retry(client, url, hosts, retry, transfer);
+    tokio::pin!(second);
+    tokio::select! {
+        fetched = &mut first => match fetched {
+            Ok(content) => Ok(content),
+            Err(_) => second.await,
+        },
+        fetched = &mut second => match fetched {
+            Ok(content) => Ok(content),
+            Err(_) => first.await,
+        },
+    }
+}
+
+/// Downloads a URL with [`fetch_limited`], retrying HTTP failures as configured
+async fn fetch_with_retry(
+    client: &reqwest::Client,
+    url: &str,
+    hosts: &HostLimits,
+    retry: Option<&RetryConfig>,
+    transfer: &TransferLimits,
+) -> Result<Staged, DownloadFailure> {
+    let Some(config) = retry else {
+        return fetch_limited(client, url, hosts, transfer).await;
+    };
+    let mut attempt = 1;
+    let mut delay_ms = 0;
+    loop {
+        match fetch_limited(client, url, hosts, transfer).await {
+            Err(DownloadFailure::Http(e)) if attempt < config.max_retries => {
+                let error = ApiError::RequestError {
+                    status: e.status().map(|status| status.as_u16()),
+                    message: e.to_string(),
+                };
+                if !config.should_retry(&error, attempt) {
+                    return Err(DownloadFailure::Http(e));
+                }
+                delay_ms = config.retry_delay_ms(attempt, delay_ms);
+                logging::log_debug!(
+                    logging::DOWNLOAD,
+                    "Retrying download of {} in {}ms: {}",
+                    Redacted(url),
+                    delay_ms,
+                    Redacted(&e)
+                );
+                metrics::record(|metrics| metrics.on_retry());
+                tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
+                attempt += 1;
+            }
+            Err(failure) if attempt < config.max_retries && failure.body_problem().is_some() => {
+                let reason = failure.body_problem().unwrap_or_default().to_string();
+                if !config.should_retry(&ApiError::Other(reason.clone()), attempt) {
+                    return Err(failure);
+                }
+                delay_ms = config.retry_delay_ms(attempt, delay_ms);
+                logging::log_debug!(
+                    logging::DOWNLOAD,
+                    "Retrying download of {} in {}ms: {}",
+                    Redacted(url),
+                    delay_ms,
+                    reason
+                );
+                metrics::record(|metrics| metrics.on_retry());
+                tokio::time::sleep(std::time::Duration::from_millis(delay_ms)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Downloads a URL while holding a permit of its host's limit, reporting the outcome
+async fn fetch_limited(
+    client: &reqwest::Client,
+    url: &str,
+    hosts: &HostLimits,
+    transfer: &TransferLimits,
+) -> Result<Staged, DownloadFailure> {
+    let host = throttle::host_key(url);
+    let permit = hosts.limit(&host).acquire().await;
+    // Checked after waiting for the permit, since the host may have failed meanwhile
+    if hosts.is_paused(&host) {
+        return Err(DownloadFailure::Paused(host));
+    }
+
+    let sent = Instant::now();
+    let response = match crate::har::send(client.get(url)).await {
+        Ok(response) => response,
+        Err(e) => {
+            hosts.record(&host, Outcome::Unavailable);
+            permit.finish(Outcome::Unavailable);
+            return Err(DownloadFailure::Http(e));
+        }
+    };
+    // Latency is measured up to the response headers, so large files don't count as slow
+    let outcome = Outcome::from_status(response.status(), sent.elapsed());
+
+    let content_type = content_type_of(&response);
+    let content = match response.error_for_status() {
+        Ok(response) => read_body(client, url, response, transfer).await,
+        Err(e) => Err(DownloadFailure::Http(e)),
+    };
+    let content = match content {
+        Ok(fetched) => {
+            match check_asset_head(content_type.as_deref(), fetched.head(), fetched.len()) {
+                Ok(()) => Ok(fetched),
+                Err(reason) => {
+                    fetched.discard().await;
+                    Err(DownloadFailure::InvalidBody(reason))
+                }
+            }
+        }
+        Err(failure) => Err(failure),
+    };
+    if let Ok(fetched) = &content {
+        metrics::record(|metrics| metrics.on_bytes_downloaded(fetched.len()));
+    }
+    let outcome = match &content {
+        // Error pages, stalls and local write errors say nothing about the host's capacity
+        Err(
+            DownloadFailure::InvalidBody(_) | DownloadFailure::Stalled(_) | DownloadFailure::Io(_),
+        ) => Outcome::Failed,
+        Err(_) if matches!(outcome, Outcome::Success(_)) => Outcome::Unavailable,
+        _ => outcome,
+    };
+    hosts.record(&host, outcome);
+    permit.finish(outcome);
+    content
+}
+
+/// Streams a response body to a partial file, resuming it with range requests when it stalls
+///
+/// Every chunk is written to a new file in [`TransferLimits::staging_dir`]
+/// as it arrives, so memory use doesn't grow with the asset; with
+/// [`TransferLimits::memory`], a buffer is reserved for the transfer first.
+/// A transfer below the speed floor is resumed like a stalled one when stall
+/// detection is enabled, and fails otherwise.
+async fn read_body(
+    client: &reqwest::Client,
+    url: &str,
+    response: reqwest::Response,
+    transfer: &TransferLimits,
+) -> Result<Staged, DownloadFailure> {
+    let _reservation = match &transfer.memory {
+        Some(memory) => memory.reserve(response.content_length()).await,
+        None => None,
+    };
+    let (mut body, mut file) = Staged::create(&transfer.staging_dir)
+        .await
+        .map_err(DownloadFailure::Io)?;
+    match stream_body(client, url, response, transfer, &mut body, &mut file).await {
+        Ok(()) => Ok(body),
+        Err(failure) => {
+            drop(file);
+            body.discard().await;
+            Err(failure)
+        }
+    }
+}
+
+/// Writes a response body into `body`, for [`read_body`]
+async fn stream_body(
+    client: &reqwest::Client,
+    url: &str,
+    mut response: reqwest::Response,
+    transfer: &TransferLimits,
+    body: &mut Staged,
+    file: &mut tokio::fs::File,
+) -> Result<(), DownloadFailure> {
+    let (stall, floor) = (transfer.stall.as_ref(), transfer.speed_floor.as_ref());
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string);
+
+    let mut resumes = 0;
+    let mut last_data = tokio::time::Instant::now();
+    // Start and bytes of the current speed floor window
+    let mut window = (last_data, 0);
+    loop {
+        let deadline = [
+            stall.map(|stall| last_data + stall.timeout),
+            floor.map(|floor| window.0 + floor.window),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let next = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, response.chunk())
+                .await
+                .ok(),
+            None => Some(response.chunk().await),
+        };
+        match next {
+            Some(Ok(Some(chunk))) => {
+                body.write(file, &chunk)
+                    .await
+                    .map_err(DownloadFailure::Io)?;
+                last_data = tokio::time::Instant::now();
+                window.1 += chunk.len() as u64;
+            }
+            Some(Ok(None)) => return file.flush().await.map_err(DownloadFailure::Io),
+            Some(Err(e)) => return Err(DownloadFailure::Http(e)),
+            None => {}
+        }
+
+        let now = tokio::time::Instant::now();
+        let problem = if stall.is_some_and(|stall| now >= last_data + stall.timeout) {
+            "stalled".to_string()
+        } else if let Some(floor) = floor.filter(|floor| now >= window.0 + floor.window) {
+            let elapsed = now.duration_since(window.0).as_secs_f64();
+            if (window.1 as f64) >= floor.min_bytes_per_second as f64 * elapsed {
+                window = (now, 0);
+                continue;
+            }
+            format!("fell below {} bytes/s", floor.min_bytes_per_second)
+        } else {
+            continue;
+        };
+
+        // Request the rest, until a response arrives in time
+        response = loop {
+            if resumes >= stall.map_or(0, |stall| stall.max_resumes) {
+                return Err(DownloadFailure::Stalled(format!(
+                    "transfer {} after {} bytes and {} resumes",
+                    problem,
+                    body.len(),
+                    resumes
+                )));
+            }
+            resumes += 1;
+            logging::log_debug!(
+                logging::DOWNLOAD,
+                "Transfer of {} {} after {} bytes, resuming",
+                Redacted(url),
+                problem,
+                body.len()
+            );
+            let mut request = client
+                .get(url)
+                .header(reqwest::header::RANGE, format!("bytes={}-", body.len()));
+            if let Some(etag) = &etag {
+                request = request.header(reqwest::header::IF_RANGE, etag);
+            }
+            let timeout = stall.map_or(Duration::MAX, |stall| stall.timeout);
+            match tokio::time::timeout(timeout, crate::har::send(request)).await {
+                Ok(Ok(response)) => break response,
+                Ok(Err(e)) => return Err(DownloadFailure::Http(e)),
+                Err(_) => continue,
+            }
+        };
+        last_data = tokio::time::Instant::now();
+        window = (last_data, 0);
+
+        let status = response.status();
+        if status == reqwest::StatusCode::PARTIAL_CONTENT {
+            let expected = format!("bytes {}-", body.len());
+            let resumed_at_end = response
+                .headers()
+                .get(reqwest::header::CONTENT_RANGE)
+                .and_then(|range| range.to_str().ok())
+                .is_some_and(|range| range.starts_with(&expected));
+            if !resumed_at_end {
+                return Err(DownloadFailure::Stalled(format!(
+                    "transfer resumed at the wrong offset after {} bytes",
+                    body.len()
+                )));
+            }
+        } else if status.is_success() {
+            // The server sent the whole file again
+            body.restart(file).await.map_err(DownloadFailure::Io)?;
+        } else {
+            return Err(match response.error_for_status() {
+                Err(e) => DownloadFailure::Http(e),
+                Ok(_) => DownloadFailure::Stalled(format!("transfer resumed with {}", status)),
+            });
+        }
+    }
+}
+
+/// The `Content-Type` header of a response
+fn content_type_of(response: &reqwest::Response) -> Option<String> {
+    let value = response.headers().get(reqwest::header::CONTENT_TYPE)?;
+    value.to_str().ok().map(str::to_string)
+}
+
+/// Checks that a downloaded body is media rather than an error page
+///
+/// Bodies whose signature is a known image or video format (see
+/// [`utils::sniff_mime_type`]) always pass. Otherwise, empty bodies, bodies
+/// served as text (e.g. `text/html`) and bodies that start like HTML or XML
+/// are rejected; other unrecognized binary content passes, since Apple may
+/// serve formats this crate doesn't know.
+///
+/// # Arguments
+///
+/// * `content_type` - The `Content-Type` header of the response, if it had one
+/// * `body` - The downloaded body
+///
+/// # Returns
+///
+/// `Ok(())` for media, or a description of what was received instead
+pub fn check_asset_body(content_type: Option<&str>, body: &[u8]) -> Result<(), String> {
+    check_asset_head(content_type, body, body.len() as u64)
+}
+
+/// [`check_asset_body`] for a body of `len` bytes starting with `head`
+fn check_asset_head(content_type: Option<&str>, head: &[u8], len: u64) -> Result<(), String> {
+    if len == 0 {
+        return Err("asset URL returned an empty body".to_string());
+    }
+    if utils::sniff_mime_type(head).is_some() {
+        return Ok(());
+    }
+    let start = String::from_utf8_lossy(&head[..head.len().min(HEAD_BYTES)])
+        .trim_start()
+        .to_ascii_lowercase();
+    let markup = ["<!doctype", "<html", "<head", "<body", "<?xml", "<error"]
+        .iter()
+        .any(|tag| start.starts_with(tag));
+    let media_type = content_type.map(|content_type| {
+        let media_type = content_type.split(';').next().unwrap_or_default();
+        media_type.trim().to_ascii_lowercase()
+    });
+    let textual = media_type
+        .as_deref()
+        .is_some_and(|media_type| media_type.starts_with("text/") || media_type.ends_with("+xml"));
+    if markup || textual {
+        return Err(format!(
+            "asset URL returned {} ({} bytes) instead of media",
+            media_type.as_deref().unwrap_or("an error page"),
+            len
+        ));
+    }
+    Ok(())
+}
+
+/// Resolves a derivative's URL again and downloads it, for [`DownloadOptions::url_refresh`]
+///
+/// # Returns
+///
+/// The outcome of the new download, or `None` if no new URL was resolved
+async fn refetch_refreshed(
+    client: &reqwest::Client,
+    base_url: &str,
+    photo: &Image,
+    key: &str,
+    hosts: &HostLimits,
+    transfer: &TransferLimits,
+    options: &DownloadOptions,
+) -> Option<Result<Staged, DownloadFailure>> {
+    let mut photo = photo.clone();
+    if let Err(e) =
+        crate::enrich::resolve_photo_urls(client, base_url, std::slice::from_mut(&mut photo)).await
+    {
+        logging::log_debug!(
+            logging::DOWNLOAD,
+            "Could not refresh the URLs of {}: {}",
+            photo.photo_guid,
+            e
+        );
+        return None;
+    }
+    let url = photo.derivatives.get(key)?.url.clone()?;
+    logging::log_debug!(
+        logging::DOWNLOAD,
+        "Downloading {} again from a refreshed URL",
+        photo.photo_guid
+    );
+    if let Some(Err(e)) = options
+        .url_policy
+        .as_ref()
+        .map(|policy| policy.validate(&url))
+    {
+        return Some(Err(DownloadFailure::Policy(e)));
+    }
+    Some(fetch_with_retry(client, &url, hosts, options.retry.as_ref(), transfer).await)
+}
+
+/// Downloads one derivative of a photo outside a pass, e.g. for [`crate::zip_stream`]
+///
+/// The URL policy, retries, transfer limits and URL refresh of `options`
+/// apply as in a [`download_derivative_classes`] pass; requests are not hedged.
+/// The body is staged in [`DownloadOptions::temp_dir`], or the system's
+/// temporary directory, until the returned file is dropped.
+pub(crate) async fn fetch_derivative(
+    client: &reqwest::Client,
+    hosts: &HostLimits,
+    photo: &Image,
+    key: &str,
+    options: &DownloadOptions,
+) -> Result<Staged, Box<dyn Error>> {
+    let url = photo
+        .derivatives
+        .get(key)
+        .and_then(|derivative| derivative.url.clone())
+        .ok_or("derivative has no URL")?;
+    if let Some(policy) = &options.url_policy {
+        policy.validate(&url).map_err(DownloadFailure::Policy)?;
+    }
+    let staging_dir = options.temp_dir.clone().unwrap_or_else(std::env::temp_dir);
+    tokio::fs::create_dir_all(&staging_dir).await?;
+    let transfer = TransferLimits::new(options, staging_dir);
+    let fetched = fetch_with_retry(client, &url, hosts, options.retry.as_ref(), &transfer).await;
+    let fetched = match (fetched, &options.url_refresh) {
+        (Err(DownloadFailure::InvalidBody(reason)), Some(base_url)) => {
+            refetch_refreshed(client, base_url, photo, key, hosts, &transfer, options)
+                .await
+                .unwrap_or(Err(DownloadFailure::InvalidBody(reason)))
+        }
+        (fetched, _) => fetched,
+    };
+    Ok(fetched?)
+}
+
+/// Writes downloaded photo content to disk, choosing the filename and extension
+pub(crate) async fn save_photo_content(
+    content: &[u8],
+    photo: &Image,
+    index: Option<usize>,
+    output_dir: &str,
+    custom_filename: Option<String>,
+    options: &DownloadOptions,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let base_filename = base_filename(photo, index, custom_filename.as_deref(), options);
+    save_content_as(content, photo, output_dir, &base_filename, options).await
+}
+
+/// The base filename of a photo, see [`utils::photo_base_filename`]
+///
+/// With [`DownloadOptions::markdown_captions`], the caption's Markdown is
+/// stripped and its lines joined first.
+fn base_filename(
+    photo: &Image,
+    index: Option<usize>,
+    custom_filename: Option<&str>,
+    options: &DownloadOptions,
+) -> String {
+    match &photo.caption {
+        Some(caption) if options.markdown_captions => {
+            let plain = strip_markdown(caption)
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            let named = Image {
+                caption: Some(plain).filter(|plain| !plain.is_empty()),
+                ..photo.clone()
+            };
+            utils::photo_base_filename(&named, index, custom_filename)
+        }
+        _ => utils::photo_base_filename(photo, index, custom_filename),
+    }
+}
+
+/// Writes downloaded photo content to disk under a base filename, adding the extension
+async fn save_content_as(
+    content: &[u8],
+    photo: &Image,
+    output_dir: &str,
+    base_filename: &str,
+    options: &DownloadOptions,
+) -> Result<String, Box<dyn std::error::Error>> {
+    // Get content type and appropriate extension
+    let extension = utils::get_extension_for_content(content, None);
+    let filename = format!("{}{}", base_filename, extension);
+    save_content_named(content, photo, output_dir, &filename, options).await
+}
+
+/// Creates `output_dir` if needed and returns the directory partial files are staged in
+async fn prepare_output_dir(output_dir: &str, options: &DownloadOptions) -> io::Result<PathBuf> {
+    // Create the directory if it doesn't exist (using async tokio fs)
+    if tokio::fs::metadata(output_dir).await.is_err() {
+        tokio::fs::create_dir_all(output_dir).await?;
+        apply_permissions(Path::new(output_dir), options.dir_mode, options).await?;
+    }
+
+    // Content is staged in a partial file next to the target or in the temp dir
+    match &options.temp_dir {
+        Some(dir) => {
+            tokio::fs::create_dir_all(dir).await?;
+            Ok(dir.clone())
+        }
+        None => Ok(PathBuf::from(output_dir)),
+    }
+}
+
+/// Saves content under `filename` in `output_dir`, according to `options`
+async fn save_content_named(
+    content: &[u8],
+    photo: &Image,
+    output_dir: &str,
+    filename: &str,
+    options: &DownloadOptions,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let staging_dir = prepare_output_dir(output_dir, options).await?;
+    let filepath = format!("{}/{}", output_dir, filename);
+    let temp_path = staging_dir.join(format!(".{}.part", filename));
+    begin_write(&temp_path, &filepath, photo, content.len() as u64, options).await?;
+
+    let result: io::Result<()> = async {
+        let mut file = tokio::fs::File::create(&temp_path).await?;
+        tokio::io::copy(&mut &content[..], &mut file).await?;
+        if options.fsync {
+            file.sync_all().await?;
+        }
+        drop(file);
+        install_file(&temp_path, Path::new(&filepath), photo, options).await
+    }
+    .await;
+
+    end_write(&temp_path, &filepath, result, options).await?;
+    Ok(filepath)
+}
+
+/// Moves a body streamed to a partial file to `filename` in `output_dir`, according to `options`
+///
+/// The partial file is journaled once the body is complete, as the final
+/// name depends on its content.
+async fn save_staged_named(
+    staged: Staged,
+    photo: &Image,
+    output_dir: &str,
+    filename: &str,
+    options: &DownloadOptions,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let filepath = format!("{}/{}", output_dir, filename);
+    begin_write(staged.path(), &filepath, photo, staged.len(), options).await?;
+    let temp_path = staged.into_path();
+
+    let result: io::Result<()> = async {
+        if options.fsync {
+            tokio::fs::File::open(&temp_path).await?.sync_all().await?;
+        }
+        install_file(&temp_path, Path::new(&filepath), photo, options).await
+    }
+    .await;
+
+    end_write(&temp_path, &filepath, result, options).await?;
+    Ok(filepath)
+}
+
+/// Records in the journal, if any, that `partial` is about to be moved to `filepath`
+async fn begin_write(
+    partial: &Path,
+    filepath: &str,
+    photo: &Image,
+    expected_size: u64,
+    options: &DownloadOptions,
+) -> io::Result<()> {
+    let Some(journal) = &options.journal else {
+        return Ok(());
+    };
+    let write = JournaledWrite {
+        photo_guid: photo.photo_guid.clone(),
+        path: PathBuf::from(filepath),
+        partial: partial.to_path_buf(),
+        expected_size,
+    };
+    let journal = Arc::clone(journal);
+    tokio::task::spawn_blocking(move || journal.begin(&write))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Cleans up after a write to `filepath` and records in the journal, if any, that it ended
+async fn end_write(
+    partial: &Path,
+    filepath: &str,
+    result: io::Result<()>,
+    options: &DownloadOptions,
+) -> io::Result<()> {
+    if result.is_err() {
+        // Best effort: don't leave partial files behind
+        let _ = tokio::fs::remove_file(partial).await;
+    }
+    if let Some(journal) = &options.journal {
+        let journal = Arc::clone(journal);
+        let path = PathBuf::from(filepath);
+        tokio::task::spawn_blocking(move || journal.end(&path))
+            .await
+            .map_err(io::Error::other)??;
+    }
+    result
+}
+
+/// Moves a complete partial file into place and applies the file options to it
+async fn install_file(
+    partial: &Path,
+    target: &Path,
+    photo: &Image,
+    options: &DownloadOptions,
+) -> io::Result<()> {
+    move_file(partial, target).await?;
+    apply_permissions(target, options.file_mode, options).await?;
+    if options.xattrs {
+        write_provenance(target, photo, options);
+    }
+    if options.takeout_metadata {
+        crate::takeout::write_photo_metadata(target, photo).await?;
+    }
+    if options.fsync {
+        sync_parent_dir(target).await?;
+    }
+    Ok(())
+}
+
+/// Applies the configured mode and ownership to a written file or created directory
+#[cfg(unix)]
+pub(crate) async fn apply_permissions(
+    path: &Path,
+    mode: Option<u32>,
+    options: &DownloadOptions,
+) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(mode) = mode {
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await?;
+    }
+    if options.uid.is_some() || options.gid.is_some() {
+        let path = path.to_path_buf();
+        let (uid, gid) = (options.uid, options.gid);
+        tokio::task::spawn_blocking(move || std::os::unix::fs::chown(path, uid, gid))
+            .await
+            .map_err(io::Error::other)??;
+    }
+    Ok(())
+}
+
+/// Applies the configured mode and ownership to a written file or created directory
+#[cfg(not(unix))]
+pub(crate) async fn apply_permissions(
+    _path: &Path,
+    _mode: Option<u32>,
+    _options: &DownloadOptions,
+) -> io::Result<()> {
+    Ok(())
+}
+
+/// Records provenance attributes on a written file, logging failures
+#[cfg(all(feature = "xattr", any(target_os = "linux", target_os = "macos")))]
+fn write_provenance(path: &Path, photo: &Image, options: &DownloadOptions) {
+    let provenance = crate::xattr::Provenance {
+        photo_guid: Some(photo.photo_guid.clone()),
+        album: options.album_name.clone(),
+    };
+    if let Err(e) = crate::xattr::write_provenance(path, &provenance) {
+        logging::log_warn!(
+            logging::DOWNLOAD,
+            "Could not write provenance attributes to {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+/// Records provenance attributes on a written file, logging failures
+#[cfg(not(all(feature = "xattr", any(target_os = "linux", target_os = "macos"))))]
+fn write_provenance(_path: &Path, _photo: &Image, _options: &DownloadOptions) {
+    logging::log_debug!(
+        logging::DOWNLOAD,
+        "Extended attributes are not supported in this build"
+    );
+}
+
+/// Moves a file, falling back to copy and remove across filesystems
+///
+/// A plain rename is attempted first. If it fails because source and target
+/// are on different devices (`EXDEV`), the file is copied to a partial file
+/// next to the target, synced, renamed into place, and the source is removed,
+/// so the target never appears half-written.
+///
+/// # Arguments
+///
+/// * `from` - Path of the file to move
+/// * `to` - Destination path
+pub async fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match tokio::fs::rename(from, to).await {
+        Err(e) if crate::compat::is_crosses_devices(&e) => {
+            let mut partial_name = to.file_name().unwrap_or_default().to_os_string();
+            partial_name.push(".part");
+            let partial = to.with_file_name(partial_name);
+
+            tokio::fs::copy(from, &partial).await?;
+            // The copy is a new file whose contents have not been flushed yet
+            tokio::fs::File::open(&partial).await?.sync_all().await?;
+            if let Err(e) = tokio::fs::rename(&partial, to).await {
+                let _ = tokio::fs::remove_file(&partial).await;
+                return Err(e);
+            }
+            tokio::fs::remove_file(from).await
+        }
+        result => result,
+    }
+}
+
+/// Flushes a file's directory entry to stable storage
+///
+/// After a rename, the new name is only durable once the containing directory
+/// has been synced. This is a no-op on platforms where directories cannot be
+/// opened for syncing (e.g. Windows).
+///
+/// # Arguments
+///
+/// * `path` - Path of a file whose parent directory should be synced
+pub async fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    if cfg!(unix) {
+        tokio::fs::File::open(parent).await?.sync_all().await
+    } else {
+        Ok(())
+    }
+}
@@ -3,9 +3,17 @@
 //! This module provides utilities to enrich photo data with additional information,
 //! particularly combining photo metadata with their corresponding asset URLs
 //! after they've been fetched from separate API endpoints.
+//!
+//! Photo locations can additionally be turned into place names through a
+//! [`Geocoder`]. The crate ships no geocoding service; applications implement the
+//! trait on top of whichever service they use and call [`geocode_photos`].
 
-use crate::models::Image;
+use crate::api::{self, ApiError};
+use crate::logging;
+use crate::models::{Image, Location};
 use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
 
 /// Enriches photos by adding URLs to their derivatives
 ///
@@ -30,3 +38,162 @@ pub fn enrich_photos_with_urls(photos: &mut [Image], all_urls: &HashMap<String,
         }
     }
 }
+
+/// Resolves the URLs of the given photos and enriches them
+///
+/// Only the GUIDs of `photos` are sent to the webasseturls endpoint, so callers
+/// that only need some photos of an album (e.g. the latest 20) avoid resolving
+/// the rest.
+///
+/// # Arguments
+///
+/// * `client` - A reqwest HTTP client
+/// * `base_url` - The (redirected) base URL for API requests
+/// * `photos` - The photos to resolve URLs for
+///
+/// # Returns
+///
+/// The number of asset URLs returned by the API
+pub async fn resolve_photo_urls(
+    client: &reqwest::Client,
+    base_url: &str,
+    photos: &mut [Image],
+) -> Result<usize, ApiError> {
+    if photos.is_empty() {
+        return Ok(0);
+    }
+    let photo_guids: Vec<String> = photos.iter().map(|p| p.photo_guid.clone()).collect();
+    let urls = api::get_asset_urls(client, base_url, &photo_guids).await?;
+    enrich_photos_with_urls(photos, &urls);
+    Ok(urls.len())
+}
+
+/// Number of photos whose URLs are requested at once by [`resolve_thumbnail_urls`]
+pub const THUMBNAIL_BATCH_SIZE: usize = 100;
+
+/// Resolves the URL of the smallest derivative of each photo, for grid thumbnails
+///
+/// URLs are requested in batches of [`THUMBNAIL_BATCH_SIZE`] photos, so large
+/// albums don't produce one oversized webasseturls request. The smallest still
+/// image is chosen with [`crate::utils::select_thumbnail_derivative`].
+///
+/// # Arguments
+///
+/// * `client` - A reqwest HTTP client
+/// * `base_url` - The (redirected) base URL for API requests
+/// * `photos` - The photos to resolve thumbnails for
+///
+/// # Returns
+///
+/// The thumbnail URL keyed by photo GUID; photos without a resolved URL are left out
+pub async fn resolve_thumbnail_urls(
+    client: &reqwest::Client,
+    base_url: &str,
+    photos: &[Image],
+) -> Result<HashMap<String, String>, ApiError> {
+    let mut thumbnails = HashMap::new();
+    for batch in photos.chunks(THUMBNAIL_BATCH_SIZE) {
+        let mut batch = batch.to_vec();
+        resolve_photo_urls(client, base_url, &mut batch).await?;
+        for photo in batch {
+            let url = crate::utils::select_thumbnail_derivative(&photo.derivatives)
+                .and_then(|(_, derivative)| derivative.url.clone());
+            if let Some(url) = url {
+                thumbnails.insert(photo.photo_guid, url);
+            }
+        }
+    }
+    Ok(thumbnails)
+}
+
+/// Attaches coordinates to photos
+///
+/// # Arguments
+///
+/// * `photos` - A mutable slice of Images to be enriched
+/// * `locations` - Coordinates keyed by photo GUID, see [`crate::models::Metadata::photo_locations`]
+pub fn enrich_photos_with_locations(photos: &mut [Image], locations: &HashMap<String, Location>) {
+    for photo in photos.iter_mut() {
+        if let Some(location) = locations.get(&photo.photo_guid) {
+            photo.location = Some(*location);
+        }
+    }
+}
+
+/// Error returned by a [`Geocoder`] that failed to resolve a location
+pub type GeocodeError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Future returned by [`Geocoder::reverse_geocode`]
+pub type GeocodeFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Option<String>, GeocodeError>> + Send + 'a>>;
+
+/// Reverse geocoding service turning coordinates into place names
+pub trait Geocoder: Send + Sync {
+    /// Resolves a location to a human-readable place name
+    ///
+    /// # Arguments
+    ///
+    /// * `location` - The coordinates to resolve
+    ///
+    /// # Returns
+    ///
+    /// The place name, or `None` if the service knows no name for the location
+    fn reverse_geocode(&self, location: Location) -> GeocodeFuture<'_>;
+}
+
+/// Attaches place names to photos that have a location
+///
+/// Photos that already have a place name are skipped, and each distinct
+/// location is resolved only once. Geocoding failures are logged and leave the
+/// photo without a place name.
+///
+/// # Arguments
+///
+/// * `photos` - A mutable slice of Images, usually enriched with [`enrich_photos_with_locations`]
+/// * `geocoder` - The service resolving locations
+///
+/// # Returns
+///
+/// The number of photos that received a place name
+pub async fn geocode_photos(photos: &mut [Image], geocoder: &dyn Geocoder) -> usize {
+    // f64 is not hashable, so identical locations are keyed by their bit patterns
+    let mut resolved: HashMap<(u64, u64), Option<String>> = HashMap::new();
+    let mut named = 0;
+
+    for photo in photos.iter_mut() {
+        let Some(location) = photo.location else {
+            continue;
+        };
+        if photo.place_name.is_some() {
+            continue;
+        }
+
+        let key = (location.latitude.to_bits(), location.longitude.to_bits());
+        let place_name = match resolved.get(&key) {
+            Some(place_name) => place_name.clone(),
+            None => {
+                let place_name = match geocoder.reverse_geocode(location).await {
+                    Ok(place_name) => place_name,
+                    Err(e) => {
+                        logging::log_warn!(
+                            logging::API,
+                            "Failed to geocode photo {}: {}",
+                            photo.photo_guid,
+                            e
+                        );
+                        None
+                    }
+                };
+                resolved.insert(key, place_name.clone());
+                place_name
+            }
+        };
+
+        if place_name.is_some() {
+            photo.place_name = place_name;
+            named += 1;
+        }
+    }
+
+    named
+}
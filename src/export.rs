@@ -0,0 +1,319 @@
+//! Exporting fetched albums to formats understood by other tools.
+//!
+//! [`to_kml`] writes the located photos of an album as a KML document for
+//! Google Earth: one placemark per photo and, for photos with a resolved
+//! thumbnail URL, a photo overlay showing the image at its location.
+//! [`to_kml_with_options`] can treat captions as Markdown, showing them
+//! formatted in the placemark balloons.
+
+use crate::markdown::{render_markdown, strip_markdown};
+use crate::models::{ICloudResponse, Image, Location};
+use crate::utils;
+
+/// Horizontal field of view of photo overlays, in degrees on each side
+const OVERLAY_FOV_HORIZONTAL: f64 = 30.0;
+/// Vertical field of view of photo overlays, in degrees on each side
+const OVERLAY_FOV_VERTICAL: f64 = 20.0;
+/// Distance in meters from the camera at which photo overlays are shown
+const OVERLAY_NEAR: f64 = 10.0;
+
+/// Options for [`to_kml_with_options`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct KmlOptions {
+    /// Treat captions as Markdown, see [`crate::markdown`]
+    ///
+    /// Placemark names get the caption's plain text and balloons the
+    /// rendered caption.
+    pub markdown_captions: bool,
+    /// Catalog translating the balloon labels and dates, or `None` for English
+    #[cfg(feature = "l10n")]
+    pub catalog: Option<crate::l10n::Catalog>,
+}
+
+impl KmlOptions {
+    /// Create options exporting captions as plain text
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether captions are rendered from Markdown
+    #[must_use]
+    pub fn with_markdown_captions(mut self, markdown_captions: bool) -> Self {
+        self.markdown_captions = markdown_captions;
+        self
+    }
+
+    /// Translates the balloon labels and formats dates with `catalog`
+    #[cfg(feature = "l10n")]
+    #[must_use]
+    pub fn with_catalog(mut self, catalog: crate::l10n::Catalog) -> Self {
+        self.catalog = Some(catalog);
+        self
+    }
+
+    /// Returns a balloon label, translated if there is a catalog
+    fn label(&self, id: &str, english: &str) -> String {
+        #[cfg(feature = "l10n")]
+        if let Some(catalog) = &self.catalog {
+            return catalog.message(id, &[]);
+        }
+        #[cfg(not(feature = "l10n"))]
+        let _ = id;
+        english.to_string()
+    }
+
+    /// Formats a date as written in the catalog's locale, or returns it as is
+    fn date(&self, date: &str) -> String {
+        #[cfg(feature = "l10n")]
+        if let Some(formatted) = self
+            .catalog
+            .as_ref()
+            .and_then(|catalog| catalog.format_date(date))
+        {
+            return formatted;
+        }
+        date.to_string()
+    }
+}
+
+/// Escapes text for use in XML content and attribute values
+pub(crate) fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Formats a location as KML coordinates (longitude first)
+fn kml_coordinates(location: &Location) -> String {
+    format!(
+        "{},{},{}",
+        location.longitude,
+        location.latitude,
+        location.altitude.unwrap_or(0.0)
+    )
+}
+
+/// Returns the display name of a photo
+fn photo_title(photo: &Image, options: &KmlOptions) -> String {
+    let caption = photo
+        .caption
+        .as_deref()
+        .filter(|caption| !caption.trim().is_empty());
+    match caption {
+        Some(caption) if options.markdown_captions => {
+            let plain = strip_markdown(caption).replace('\n', " ");
+            if plain.trim().is_empty() {
+                photo.photo_guid.clone()
+            } else {
+                plain
+            }
+        }
+        Some(caption) => caption.to_string(),
+        None => photo.photo_guid.clone(),
+    }
+}
+
+/// Returns the alt text of a photo's balloon image: its caption, or its kind and date
+fn alt_text(photo: &Image, options: &KmlOptions) -> String {
+    let has_caption = photo.caption.as_deref().is_some_and(|caption| {
+        let text = if options.markdown_captions {
+            strip_markdown(caption)
+        } else {
+            caption.to_string()
+        };
+        !text.trim().is_empty()
+    });
+    if has_caption {
+        return photo_title(photo, options);
+    }
+    let kind = options.label("photo", "Photo");
+    match &photo.date_created {
+        Some(date) => format!("{}, {}", kind, options.date(date)),
+        None => kind,
+    }
+}
+
+/// Returns the URL of the smallest still image of a photo, if resolved
+fn thumbnail_url(photo: &Image) -> Option<&str> {
+    utils::select_thumbnail_derivative(&photo.derivatives)
+        .and_then(|(_key, derivative)| derivative.url.as_deref())
+}
+
+/// Writes the placemark of a located photo
+fn write_placemark(kml: &mut String, photo: &Image, location: &Location, options: &KmlOptions) {
+    kml.push_str("      <Placemark>\n");
+    kml.push_str(&format!(
+        "        <name>{}</name>\n",
+        escape_xml(&photo_title(photo, options))
+    ));
+
+    let mut description = Vec::new();
+    if let Some(caption) = photo
+        .caption
+        .as_deref()
+        .filter(|_| options.markdown_captions)
+    {
+        let caption = render_markdown(caption);
+        if !caption.is_empty() {
+            description.push(escape_xml(&caption));
+        }
+    }
+    if let Some(date) = &photo.date_created {
+        description.push(format!(
+            "{}: {}",
+            escape_xml(&options.label("taken", "Taken")),
+            escape_xml(&options.date(date))
+        ));
+    }
+    if let Some(place_name) = &photo.place_name {
+        description.push(format!(
+            "{}: {}",
+            escape_xml(&options.label("place", "Place")),
+            escape_xml(place_name)
+        ));
+    }
+    if let Some(contributor) = &photo.contributor_full_name {
+        description.push(format!(
+            "{}: {}",
+            escape_xml(&options.label("added-by", "Added by")),
+            escape_xml(contributor)
+        ));
+    }
+    if let Some(url) = thumbnail_url(photo) {
+        // The balloon is HTML within XML, so the alt text is escaped for both
+        description.push(format!(
+            "&lt;img src=\"{}\" alt=\"{}\"/&gt;",
+            escape_xml(url),
+            escape_xml(&escape_xml(&alt_text(photo, options)))
+        ));
+    }
+    if !description.is_empty() {
+        kml.push_str(&format!(
+            "        <description>{}</description>\n",
+            description.join("&lt;br/&gt;")
+        ));
+    }
+    if let Some(date) = &photo.date_created {
+        kml.push_str(&format!(
+            "        <TimeStamp><when>{}</when></TimeStamp>\n",
+            escape_xml(date)
+        ));
+    }
+
+    kml.push_str(&format!(
+        "        <Point><coordinates>{}</coordinates></Point>\n",
+        kml_coordinates(location)
+    ));
+    kml.push_str("      </Placemark>\n");
+}
+
+/// Writes the photo overlay of a located photo with a thumbnail URL
+fn write_photo_overlay(
+    kml: &mut String,
+    photo: &Image,
+    location: &Location,
+    url: &str,
+    options: &KmlOptions,
+) {
+    kml.push_str("      <PhotoOverlay>\n");
+    kml.push_str(&format!(
+        "        <name>{}</name>\n",
+        escape_xml(&photo_title(photo, options))
+    ));
+    kml.push_str(&format!(
+        "        <Camera><longitude>{}</longitude><latitude>{}</latitude><altitude>{}</altitude><heading>0</heading><tilt>90</tilt><roll>0</roll></Camera>\n",
+        location.longitude,
+        location.latitude,
+        location.altitude.unwrap_or(0.0)
+    ));
+    kml.push_str(&format!(
+        "        <Icon><href>{}</href></Icon>\n",
+        escape_xml(url)
+    ));
+    kml.push_str(&format!(
+        "        <ViewVolume><leftFov>-{h}</leftFov><rightFov>{h}</rightFov><bottomFov>-{v}</bottomFov><topFov>{v}</topFov><near>{near}</near></ViewVolume>\n",
+        h = OVERLAY_FOV_HORIZONTAL,
+        v = OVERLAY_FOV_VERTICAL,
+        near = OVERLAY_NEAR
+    ));
+    kml.push_str(&format!(
+        "        <Point><coordinates>{}</coordinates></Point>\n",
+        kml_coordinates(location)
+    ));
+    kml.push_str("        <shape>rectangle</shape>\n");
+    kml.push_str("      </PhotoOverlay>\n");
+}
+
+/// Exports the located photos of an album as a KML document
+///
+/// Photos without a location (see [`crate::enrich::enrich_photos_with_locations`])
+/// are left out. Photo overlays are only written for photos whose derivatives
+/// have been enriched with URLs; note that asset URLs expire after a while.
+///
+/// # Arguments
+///
+/// * `response` - The album to export
+///
+/// # Returns
+///
+/// The KML document
+pub fn to_kml(response: &ICloudResponse) -> String {
+    to_kml_with_options(response, &KmlOptions::default())
+}
+
+/// Exports the located photos of an album as a KML document, as set by `options`
+///
+/// This behaves like [`to_kml`], which uses the default options.
+///
+/// # Arguments
+///
+/// * `response` - The album to export
+/// * `options` - How captions are exported
+///
+/// # Returns
+///
+/// The KML document
+pub fn to_kml_with_options(response: &ICloudResponse, options: &KmlOptions) -> String {
+    let located: Vec<(&Image, &Location)> = response
+        .photos
+        .iter()
+        .filter_map(|photo| Some((photo, photo.location.as_ref()?)))
+        .collect();
+
+    let mut kml = String::new();
+    kml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    kml.push_str("<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n");
+    kml.push_str("  <Document>\n");
+    kml.push_str(&format!(
+        "    <name>{}</name>\n",
+        escape_xml(&response.metadata.stream_name)
+    ));
+
+    kml.push_str("    <Folder>\n      <name>Photos</name>\n");
+    for (photo, location) in &located {
+        write_placemark(&mut kml, photo, location, options);
+    }
+    kml.push_str("    </Folder>\n");
+
+    kml.push_str("    <Folder>\n      <name>Photo overlays</name>\n");
+    for (photo, location) in &located {
+        if let Some(url) = thumbnail_url(photo) {
+            write_photo_overlay(&mut kml, photo, location, url, options);
+        }
+    }
+    kml.push_str("    </Folder>\n");
+
+    kml.push_str("  </Document>\n");
+    kml.push_str("</kml>\n");
+    kml
+}
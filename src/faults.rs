@@ -0,0 +1,163 @@
+//! Synthetic failures for resilience testing.
+//!
+//! Applications built on this crate need to know that their retries, alerts
+//! and fallbacks work when iCloud misbehaves, which is hard to provoke on
+//! demand. While a [`FaultConfig`] is installed with [`set_faults`], every
+//! request the crate sends (webstream, webasseturls and downloads) passes
+//! through it first: it can be delayed by a random latency, and every Nth
+//! request can be answered with a chosen status code without reaching the
+//! network.
+//!
+//! For example, `FaultConfig::new().fail_every(3, 503)` answers every third
+//! request with 503, and [`FaultConfig::with_latency`] adds a random delay to
+//! each one. Call [`clear_faults`] when the scenario is over.
+//!
+//! The configuration is process-wide; this module is only compiled with the
+//! `fault-injection` feature, so it can't be enabled by accident in
+//! production builds. Injected failures are not recorded by [`crate::har`].
+
+use rand::Rng;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Which requests fail and how much latency is added
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FaultConfig {
+    /// Fail every Nth matching request, or `None` to fail none
+    pub fail_every: Option<u64>,
+    /// Status code of injected failures
+    pub status: u16,
+    /// Range of latency added to every matching request, or `None` for none
+    pub latency: Option<(Duration, Duration)>,
+    /// Only affect requests to this host, or `None` for all hosts
+    pub host: Option<String>,
+}
+
+impl FaultConfig {
+    /// Create a configuration that injects nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer every `n`th request with `status` instead of sending it
+    #[must_use]
+    pub fn fail_every(mut self, n: u64, status: u16) -> Self {
+        self.fail_every = Some(n.max(1));
+        self.status = status;
+        self
+    }
+
+    /// Delay every request by a random duration between `min` and `max`
+    #[must_use]
+    pub fn with_latency(mut self, min: Duration, max: Duration) -> Self {
+        self.latency = Some((min, max.max(min)));
+        self
+    }
+
+    /// Only affect requests to `host`
+    #[must_use]
+    pub fn for_host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_string());
+        self
+    }
+}
+
+/// Counters of the requests seen while faults were installed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    /// Requests the configuration applied to
+    pub requests: u64,
+    /// Requests answered with an injected failure
+    pub failures: u64,
+}
+
+/// The installed configuration and its counters
+struct Injector {
+    config: FaultConfig,
+    stats: FaultStats,
+}
+
+fn injector() -> std::sync::MutexGuard<'static, Option<Injector>> {
+    static INJECTOR: OnceLock<Mutex<Option<Injector>>> = OnceLock::new();
+    INJECTOR
+        .get_or_init(|| Mutex::new(None))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Installs a fault configuration for all requests of the process
+///
+/// Replaces any previous configuration and resets the counters.
+pub fn set_faults(config: FaultConfig) {
+    *injector() = Some(Injector {
+        config,
+        stats: FaultStats::default(),
+    });
+}
+
+/// Removes the fault configuration, returning the counters it collected
+pub fn clear_faults() -> Option<FaultStats> {
+    injector().take().map(|injector| injector.stats)
+}
+
+/// Returns the counters of the installed configuration, if any
+pub fn stats() -> Option<FaultStats> {
+    injector().as_ref().map(|injector| injector.stats)
+}
+
+/// Applies the installed faults to a request before it is sent
+///
+/// # Returns
+///
+/// The request to send, or the injected failure response to return instead
+pub(crate) async fn intercept(
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::RequestBuilder, reqwest::Response> {
+    let host = request
+        .try_clone()
+        .and_then(|clone| clone.build().ok())
+        .and_then(|built| built.url().host_str().map(str::to_string));
+
+    let (latency, status) = {
+        let mut guard = injector();
+        let Some(injector) = guard.as_mut() else {
+            return Ok(request);
+        };
+        let config = &injector.config;
+        if config.host.is_some() && config.host != host {
+            return Ok(request);
+        }
+        injector.stats.requests += 1;
+        let latency = config
+            .latency
+            .map(|(min, max)| rand::thread_rng().gen_range(min..=max));
+        let fails = config
+            .fail_every
+            .is_some_and(|n| injector.stats.requests % n == 0);
+        if fails {
+            injector.stats.failures += 1;
+        }
+        (latency, fails.then_some(config.status))
+    };
+
+    if let Some(latency) = latency {
+        tokio::time::sleep(latency).await;
+    }
+    match status {
+        Some(status) => Err(failure_response(status)),
+        None => Ok(request),
+    }
+}
+
+/// Builds the response of an injected failure
+fn failure_response(status: u16) -> reqwest::Response {
+    let response = http::Response::builder()
+        .status(status)
+        .body("injected failure")
+        .unwrap_or_else(|_| {
+            let mut response = http::Response::new("injected failure");
+            *response.status_mut() = http::StatusCode::SERVICE_UNAVAILABLE;
+            response
+        });
+    reqwest::Response::from(response)
+}
@@ -0,0 +1,213 @@
+//! Options for fetching albums with [`crate::get_icloud_photos_with`].
+//!
+//! [`FetchOptions`] gathers the knobs of the fetch pipeline in one place: the
+//! HTTP client and starting URL, retries per endpoint, how strictly photos are
+//! parsed, whether asset URLs are resolved, which photos are kept and in what
+//! order, whether they are trimmed for a compact album, the hosts requests may
+//! reach, and an observer for progress reporting.
+
+use crate::api::RetryPolicies;
+use crate::compact::CompactOptions;
+use crate::models::Image;
+use crate::observer::PipelineObserver;
+use crate::url_policy::UrlPolicy;
+use std::fmt;
+use std::sync::Arc;
+
+/// Predicate choosing the photos kept by [`FetchOptions::filter`]
+pub type PhotoFilter = Arc<dyn Fn(&Image) -> bool + Send + Sync>;
+
+/// Function choosing the photos returned by [`FetchOptions::selection`]
+pub type PhotoSelection = Arc<dyn Fn(Vec<Image>) -> Vec<Image> + Send + Sync>;
+
+/// How photos that fail to parse are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// Skip them and return them in [`crate::models::ICloudResponse::unparsed`]
+    #[default]
+    Lenient,
+    /// Fail the whole fetch
+    Strict,
+}
+
+/// Options for [`crate::get_icloud_photos_with`]
+#[derive(Clone)]
+#[non_exhaustive]
+pub struct FetchOptions {
+    /// Client to send requests with, or `None` for a default client
+    pub client: Option<reqwest::Client>,
+    /// Base URL to start from instead of the token's partitions, ending with a slash
+    pub base_url: Option<String>,
+    /// Retry configuration for the webstream and webasseturls endpoints
+    ///
+    /// `None` makes a single attempt at each endpoint, like
+    /// [`crate::get_icloud_photos`].
+    pub retry: Option<RetryPolicies>,
+    /// How photos that fail to parse are handled
+    pub parse_mode: ParseMode,
+    /// Whether to fetch the asset URLs of the photos (default `true`)
+    pub resolve_urls: bool,
+    /// Photos to keep, applied before any URLs are resolved
+    pub filter: Option<PhotoFilter>,
+    /// Selection of the photos kept by the filter, which may also reorder or
+    /// truncate them, applied before any URLs are resolved
+    pub selection: Option<PhotoSelection>,
+    /// Trim photos for a [`crate::compact::CompactAlbum`], keeping only the
+    /// smallest still derivative and no optional fields but the date
+    pub compact: Option<CompactOptions>,
+    /// Hosts and schemes requests may reach, for fetching albums on behalf of
+    /// untrusted users, see [`FetchOptions::with_url_policy`]
+    pub url_policy: Option<UrlPolicy>,
+    /// Observer notified as the pipeline progresses
+    pub observer: Option<Arc<dyn PipelineObserver>>,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            client: None,
+            base_url: None,
+            retry: None,
+            parse_mode: ParseMode::default(),
+            resolve_urls: true,
+            filter: None,
+            selection: None,
+            compact: None,
+            url_policy: None,
+            observer: None,
+        }
+    }
+}
+
+impl fmt::Debug for FetchOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FetchOptions")
+            .field("client", &self.client)
+            .field("base_url", &self.base_url)
+            .field("retry", &self.retry)
+            .field("parse_mode", &self.parse_mode)
+            .field("resolve_urls", &self.resolve_urls)
+            .field("filter", &self.filter.as_ref().map(|_| ".."))
+            .field("selection", &self.selection.as_ref().map(|_| ".."))
+            .field("compact", &self.compact)
+            .field("url_policy", &self.url_policy)
+            .field("observer", &self.observer.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl FetchOptions {
+    /// Create options with the default behavior, adjusted by the environment
+    ///
+    /// See [`crate::config`] for the variables; [`FetchOptions::default`]
+    /// ignores them.
+    pub fn new() -> Self {
+        crate::config::Config::global().apply_to_fetch(Self::default())
+    }
+
+    /// Send requests with the given client
+    #[must_use]
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Start from the given base URL, e.g. a local mock server in tests
+    #[must_use]
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    /// Retry the webstream and webasseturls endpoints as configured
+    #[must_use]
+    pub fn with_retry(mut self, policies: RetryPolicies) -> Self {
+        self.retry = Some(policies);
+        self
+    }
+
+    /// Handle photos that fail to parse as given
+    #[must_use]
+    pub fn with_parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.parse_mode = parse_mode;
+        self
+    }
+
+    /// Enable or disable fetching the asset URLs of the photos
+    #[must_use]
+    pub fn with_resolve_urls(mut self, resolve_urls: bool) -> Self {
+        self.resolve_urls = resolve_urls;
+        self
+    }
+
+    /// Keep only the photos for which `filter` returns true
+    #[must_use]
+    pub fn with_filter(mut self, filter: impl Fn(&Image) -> bool + Send + Sync + 'static) -> Self {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Pass the photos kept by the filter through `selection`
+    ///
+    /// The selection may filter, reorder or truncate the photos. It runs before
+    /// any URLs are resolved, so the webasseturls endpoint is only asked about
+    /// the photos that are kept.
+    ///
+    /// ```no_run
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// use icloud_album_rs::fetch::FetchOptions;
+    ///
+    /// // Only resolve URLs for the 20 most recently taken photos
+    /// let options = FetchOptions::new().with_selection(|mut photos| {
+    ///     photos.sort_by_key(|photo| std::cmp::Reverse(photo.timestamp_taken()));
+    ///     photos.truncate(20);
+    ///     photos
+    /// });
+    /// let latest = icloud_album_rs::get_icloud_photos_with("B0z5qAGN1JIFd3y", &options).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn with_selection(
+        mut self,
+        selection: impl Fn(Vec<Image>) -> Vec<Image> + Send + Sync + 'static,
+    ) -> Self {
+        self.selection = Some(Arc::new(selection));
+        self
+    }
+
+    /// Trim photos for a [`crate::compact::CompactAlbum`] built with `compact`
+    ///
+    /// Trimming happens before any URLs are resolved, so only the kept
+    /// derivatives are looked up.
+    #[must_use]
+    pub fn with_compact(mut self, compact: CompactOptions) -> Self {
+        self.compact = Some(compact);
+        self
+    }
+
+    /// Only reach the hosts allowed by `policy`
+    ///
+    /// This is the SSRF-safe mode for services that fetch albums on behalf of
+    /// untrusted users. The token must be a plain base62 string, and the base
+    /// URL, any 330 redirect target and every asset URL are checked against the
+    /// policy. Requests are sent with a client built by
+    /// [`UrlPolicy::build_client`], replacing any client set with
+    /// [`FetchOptions::with_client`], and only the token's own partition is
+    /// tried. To keep downloads within the same hosts, pass the policy to
+    /// [`crate::download::DownloadOptions::with_url_policy`].
+    #[must_use]
+    pub fn with_url_policy(mut self, policy: UrlPolicy) -> Self {
+        self.url_policy = Some(policy);
+        self
+    }
+
+    /// Notify `observer` as the pipeline progresses
+    ///
+    /// Pass an `Arc` of the observer to keep a handle to it.
+    #[must_use]
+    pub fn with_observer(mut self, observer: impl PipelineObserver + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+}
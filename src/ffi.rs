@@ -0,0 +1,170 @@
+//! C-compatible FFI layer.
+//!
+//! This module is only compiled with the `ffi` feature. It exposes the fetch and
+//! download entry points as `extern "C"` functions so that non-Rust applications
+//! (Swift, Python via ctypes, etc.) can load the crate as a shared library.
+//!
+//! All strings crossing the boundary are NUL-terminated UTF-8. Strings returned
+//! by this module are owned by the caller and must be released with
+//! [`icloud_string_free`]. On failure, functions return a null pointer and the
+//! error message can be retrieved with [`icloud_last_error_message`].
+//! A panic inside the crate is reported the same way instead of unwinding into
+//! the caller, which would abort the host process.
+//!
+//! The crate builds as an `rlib` only. Build the shared library with
+//! `cargo rustc --lib --release --features ffi --crate-type cdylib`.
+
+use crate::models::Image;
+use crate::redact::Redacted;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::AssertUnwindSafe;
+
+thread_local! {
+    // Error message of the last failed call on this thread
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Records an error message for retrieval by `icloud_last_error_message`
+fn set_last_error(message: String) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Converts a Rust string into a caller-owned C string
+fn into_c_string(value: String) -> *mut c_char {
+    match CString::new(value) {
+        Ok(s) => s.into_raw(),
+        Err(e) => {
+            set_last_error(format!("Result contained an interior NUL byte: {}", e));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Reads a borrowed C string argument as UTF-8
+///
+/// # Safety
+///
+/// `ptr` must be null or point to a valid NUL-terminated string.
+unsafe fn read_c_str<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, String> {
+    if ptr.is_null() {
+        return Err(format!("Argument '{}' is null", name));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|e| format!("Argument '{}' is not valid UTF-8: {}", name, e))
+}
+
+/// Runs `call`, turning a panic into an error message
+fn catch_panic<T>(call: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    std::panic::catch_unwind(AssertUnwindSafe(call)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(format!("Internal error (panic): {}", message))
+    })
+}
+
+/// Runs a future to completion on a dedicated runtime
+fn block_on<F: std::future::Future>(future: F) -> Result<F::Output, String> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map(|runtime| runtime.block_on(future))
+        .map_err(|e| format!("Failed to start async runtime: {}", e))
+}
+
+/// Fetches a shared album and returns it serialized as JSON
+///
+/// The JSON has the shape of [`crate::models::ICloudResponse`]: an object with
+/// `metadata` and `photos` fields.
+///
+/// # Safety
+///
+/// `token` must be null or point to a valid NUL-terminated string. The returned
+/// pointer must be released with [`icloud_string_free`].
+#[no_mangle]
+pub unsafe extern "C" fn icloud_fetch_album_json(token: *const c_char) -> *mut c_char {
+    let result = catch_panic(|| {
+        let token = read_c_str(token, "token")?;
+        block_on(async {
+            crate::get_icloud_photos(token)
+                .await
+                .map_err(|e| Redacted(e).to_string())
+        })?
+        .and_then(|response| serde_json::to_string(&response).map_err(|e| e.to_string()))
+    });
+
+    match result {
+        Ok(json) => into_c_string(json),
+        Err(message) => {
+            set_last_error(message);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Downloads a single photo described as JSON and returns the saved file path
+///
+/// `photo_json` must be one element of the `photos` array returned by
+/// [`icloud_fetch_album_json`], so that its derivatives carry download URLs.
+///
+/// # Safety
+///
+/// `photo_json` and `output_dir` must be null or point to valid NUL-terminated
+/// strings. The returned pointer must be released with [`icloud_string_free`].
+#[no_mangle]
+pub unsafe extern "C" fn icloud_download_photo(
+    photo_json: *const c_char,
+    output_dir: *const c_char,
+) -> *mut c_char {
+    let result = catch_panic(|| {
+        let photo_json = read_c_str(photo_json, "photo_json")?;
+        let output_dir = read_c_str(output_dir, "output_dir")?;
+        let photo: Image =
+            serde_json::from_str(photo_json).map_err(|e| format!("Invalid photo JSON: {}", e))?;
+        block_on(async {
+            crate::download_photo(&photo, None, output_dir, None)
+                .await
+                .map_err(|e| Redacted(e).to_string())
+        })?
+    });
+
+    match result {
+        Ok(path) => into_c_string(path),
+        Err(message) => {
+            set_last_error(message);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Returns the error message of the last failed call on the current thread
+///
+/// Returns null if no call has failed yet. The returned pointer must be
+/// released with [`icloud_string_free`].
+#[no_mangle]
+pub extern "C" fn icloud_last_error_message() -> *mut c_char {
+    std::panic::catch_unwind(|| {
+        LAST_ERROR
+            .with(|last| last.borrow().clone())
+            .map(into_c_string)
+            .unwrap_or(std::ptr::null_mut())
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+/// Releases a string returned by any function in this module
+///
+/// # Safety
+///
+/// `ptr` must be null or a pointer previously returned by this module that has
+/// not already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn icloud_string_free(ptr: *mut c_char) {
+    if !ptr.is_null() {
+        let _ = std::panic::catch_unwind(AssertUnwindSafe(|| drop(CString::from_raw(ptr))));
+    }
+}
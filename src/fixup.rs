@@ -0,0 +1,209 @@
+//! Extension repair for existing download directories.
+//!
+//! Older versions of this crate saved any file whose type could not be
+//! determined with a `.jpg` extension. This module scans a directory, sniffs
+//! each file's signature in parallel, and renames files whose extension does
+//! not match their content. A dry-run mode reports the planned renames without
+//! touching the filesystem.
+
+use crate::logging;
+use crate::utils;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// Number of leading bytes read from each file for signature detection
+const SNIFF_LEN: usize = 16;
+
+/// Options controlling [`fix_extensions`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct FixupOptions {
+    /// Report planned renames without performing them
+    pub dry_run: bool,
+    /// Descend into subdirectories
+    pub recursive: bool,
+    /// Maximum number of files sniffed concurrently
+    pub concurrency: usize,
+}
+
+impl Default for FixupOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            recursive: true,
+            concurrency: 16,
+        }
+    }
+}
+
+impl FixupOptions {
+    /// Create options renaming files recursively
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether renames are only reported
+    #[must_use]
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Sets whether subdirectories are descended into
+    #[must_use]
+    pub fn with_recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Sets how many files are sniffed concurrently
+    #[must_use]
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+}
+
+/// A file whose extension did not match its content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionFix {
+    /// Current path of the file
+    pub from: PathBuf,
+    /// Path the file was (or, in dry-run mode, would be) renamed to
+    pub to: PathBuf,
+    /// MIME type detected from the file's signature
+    pub mime_type: String,
+}
+
+/// Returns true if the file's current extension is valid for `mime_type`
+fn extension_matches(path: &Path, mime_type: &str) -> bool {
+    mime_guess::from_path(path)
+        .iter()
+        .any(|guess| guess.essence_str() == mime_type)
+}
+
+/// Lists the regular files in `dir`, optionally recursing into subdirectories
+async fn list_files(dir: &Path, recursive: bool) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(current) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(&current).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            if file_type.is_file() {
+                files.push(entry.path());
+            } else if file_type.is_dir() && recursive {
+                pending.push(entry.path());
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Reads the leading bytes of a file and returns its sniffed MIME type
+async fn sniff_file(path: &Path) -> io::Result<Option<&'static str>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buffer = [0u8; SNIFF_LEN];
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let read = file.read(&mut buffer[filled..]).await?;
+        if read == 0 {
+            break;
+        }
+        filled += read;
+    }
+    Ok(utils::sniff_mime_type(&buffer[..filled]))
+}
+
+/// Returns a path with the new extension that does not collide with an existing file
+fn unused_target(from: &Path, extension: &str, taken: &[PathBuf]) -> PathBuf {
+    let stem = from
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut candidate = from.with_file_name(format!("{}{}", stem, extension));
+    let mut counter = 1;
+    while candidate.exists() || taken.contains(&candidate) {
+        candidate = from.with_file_name(format!("{}_{}{}", stem, counter, extension));
+        counter += 1;
+    }
+    candidate
+}
+
+/// Finds files whose extension does not match their content and renames them
+///
+/// Files whose signature is not recognized are left alone, as are files whose
+/// extension is already valid for the detected type (e.g. `.jpeg` for JPEG).
+/// If the corrected name is already taken, a numeric suffix is appended.
+/// Unreadable files are logged and skipped.
+///
+/// # Arguments
+///
+/// * `dir` - The download directory to scan
+/// * `options` - Dry-run, recursion and concurrency settings
+///
+/// # Returns
+///
+/// The renames performed (or planned, in dry-run mode), ordered by original path
+pub async fn fix_extensions(
+    dir: impl AsRef<Path>,
+    options: &FixupOptions,
+) -> io::Result<Vec<ExtensionFix>> {
+    let files = list_files(dir.as_ref(), options.recursive).await?;
+
+    // Sniff files concurrently, bounded by the configured concurrency
+    let semaphore = Arc::new(Semaphore::new(options.concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    for path in files {
+        let semaphore = Arc::clone(&semaphore);
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let result = sniff_file(&path).await;
+            (path, result)
+        });
+    }
+
+    let mut mismatched = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        let (path, result) = joined.map_err(io::Error::other)?;
+        match result {
+            Ok(Some(mime_type)) if !extension_matches(&path, mime_type) => {
+                mismatched.push((path, mime_type));
+            }
+            Ok(_) => {}
+            Err(e) => logging::log_warn!(
+                logging::FILES,
+                "Skipping unreadable file {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+    mismatched.sort();
+
+    // Rename sequentially so that collision handling is deterministic
+    let mut fixes: Vec<ExtensionFix> = Vec::new();
+    for (from, mime_type) in mismatched {
+        let extension = utils::extension_from_mime_type(mime_type);
+        let taken: Vec<PathBuf> = fixes.iter().map(|fix| fix.to.clone()).collect();
+        let to = unused_target(&from, &extension, &taken);
+
+        if !options.dry_run {
+            tokio::fs::rename(&from, &to).await?;
+        }
+        fixes.push(ExtensionFix {
+            from,
+            to,
+            mime_type: mime_type.to_string(),
+        });
+    }
+
+    Ok(fixes)
+}
@@ -0,0 +1,1108 @@
+//! Static HTML galleries of downloaded albums.
+//!
+//! After an album has been downloaded with
+//! [`crate::download::download_derivative_classes`], [`write_gallery`] writes
+//! an `index.html` next to the files: a grid of thumbnails, each linking to
+//! the largest downloaded version of the photo or video. The pages reference
+//! the files by relative paths and need no scripts, so the directory can be
+//! published as is on any static host.
+//!
+//! Large albums are split into pages of [`GalleryOptions::page_size`] photos,
+//! `index.html`, `page-2.html` and so on, linked to each other. Thumbnails
+//! are loaded lazily and carry their dimensions, so the grid doesn't shift
+//! while they load.
+//!
+//! Republishing a large album after every sync doesn't have to start over:
+//! [`write_gallery_incremental`] records what it published in a
+//! [`GalleryManifest`], from which [`GalleryManifest::plan`] tells the next
+//! build which photos changed. Only those are downloaded again, the files of
+//! removed photos are deleted, and pages are only rewritten when their
+//! contents change, so unchanged files keep their timestamps for `rsync` and
+//! caches.
+//!
+//! The page is rendered by a [`GalleryTheme`]. The default [`TemplateTheme`]
+//! fills in [`DEFAULT_PAGE_TEMPLATE`] and [`DEFAULT_PHOTO_TEMPLATE`], which
+//! can be replaced with custom layouts, and [`GalleryOptions::with_css`]
+//! replaces its stylesheet. Applications using a template engine such as
+//! Tera or askama implement [`GalleryTheme`] instead, passing the
+//! serializable [`GalleryPage`] to their templates.
+//!
+//! Thumbnails get their caption as alt text. Photos without one are
+//! described by an [`AltTextGenerator`] if set, e.g. an image captioning
+//! model, and otherwise by their kind and date.
+//!
+//! [`GalleryOptions::with_share_url`] links each page back to the source
+//! album; with the `qr-code` feature, the link carries a QR code of it, for
+//! galleries shown on frames or printed.
+//!
+//! Labels such as the photo count are in English; with the `l10n` feature,
+//! [`GalleryOptions::with_catalog`] translates them and formats dates for a
+//! locale, see `crate::l10n`.
+//!
+//! Galleries are written with synchronous file I/O. A build touches every
+//! file of the album, so async applications run it on the blocking pool.
+
+use crate::derivatives::SizeClass;
+use crate::download::MultiDownloadReport;
+use crate::export::escape_xml;
+use crate::markdown::{render_markdown, strip_markdown};
+use crate::models::{ICloudResponse, Image, Location};
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// File name of the first page written by [`write_gallery`]
+pub const GALLERY_FILE: &str = "index.html";
+
+/// Default for [`GalleryOptions::page_size`]
+pub const DEFAULT_PAGE_SIZE: usize = 200;
+
+/// File name of the manifest written by [`write_gallery_incremental`]
+pub const MANIFEST_FILE: &str = "gallery.json";
+
+/// Page template of the default theme
+///
+/// Placeholders: `{lang}`, the language of the labels, `{title}`, `{count}` (number of photos in the album),
+/// `{count_label}`, e.g. `12 photos`, `{page}`, `{page_count}`, `{css}`,
+/// `{photos}`, the photo template filled in for every photo of the page,
+/// `{pagination}`, links to the previous and next pages if there is more than
+/// one, and `{share}`, the link to the source album if set.
+pub const DEFAULT_PAGE_TEMPLATE: &str = include_str!("gallery/page.html");
+
+/// Photo template of the default theme
+///
+/// Placeholders: `{guid}`, `{url}`, `{thumbnail_url}`, `{kind}` (`photo` or
+/// `video`), `{alt}`, the thumbnail's alt text, `{caption}` as plain text,
+/// `{caption_html}`, `{date}`,
+/// `{date_label}`, the date as written in the locale, `{place}`,
+/// `{latitude}`, `{longitude}`, `{width}` and `{height}` of the thumbnail,
+/// `{size_attributes}`, its `width` and `height` attributes if known, and
+/// `{figcaption}`, a `<figcaption>` with the caption if captions are shown.
+/// Unknown values are empty.
+pub const DEFAULT_PHOTO_TEMPLATE: &str = include_str!("gallery/photo.html");
+
+/// Stylesheet of the default theme
+pub const DEFAULT_CSS: &str = include_str!("gallery/gallery.css");
+
+/// Size classes shown in the grid, most preferred first
+const THUMBNAIL_CLASSES: &[SizeClass] = &[
+    SizeClass::Thumbnail,
+    SizeClass::VideoPoster,
+    SizeClass::Medium,
+    SizeClass::Large,
+    SizeClass::Original,
+];
+
+/// Size classes linked from the grid, most preferred first
+const FULL_CLASSES: &[SizeClass] = &[
+    SizeClass::Video,
+    SizeClass::Large,
+    SizeClass::Original,
+    SizeClass::Medium,
+];
+
+/// Options for [`render_gallery`]
+#[derive(Clone)]
+#[non_exhaustive]
+pub struct GalleryOptions {
+    /// Title of the page, or `None` for the album's name
+    pub title: Option<String>,
+    /// Show photo captions under the thumbnails
+    pub captions: bool,
+    /// Treat captions as Markdown, see [`crate::markdown`]
+    pub markdown_captions: bool,
+    /// Stylesheet of the page, or `None` for [`DEFAULT_CSS`]
+    pub css: Option<String>,
+    /// Photos per page, or 0 to show all photos on one page
+    pub page_size: usize,
+    /// Catalog translating the labels, or `None` for English
+    #[cfg(feature = "l10n")]
+    pub catalog: Option<crate::l10n::Catalog>,
+    /// Describes photos without a caption, or `None` for their kind and date
+    pub alt_text: Option<Arc<dyn AltTextGenerator>>,
+    /// Renders the page
+    pub theme: Arc<dyn GalleryTheme>,
+    /// URL of the source album to link to, or `None` for no link
+    pub share_url: Option<String>,
+}
+
+impl Default for GalleryOptions {
+    fn default() -> Self {
+        Self {
+            title: None,
+            captions: true,
+            markdown_captions: false,
+            css: None,
+            page_size: DEFAULT_PAGE_SIZE,
+            #[cfg(feature = "l10n")]
+            catalog: None,
+            alt_text: None,
+            theme: Arc::new(TemplateTheme::default()),
+            share_url: None,
+        }
+    }
+}
+
+impl fmt::Debug for GalleryOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("GalleryOptions");
+        debug
+            .field("title", &self.title)
+            .field("captions", &self.captions)
+            .field("markdown_captions", &self.markdown_captions)
+            .field("css", &self.css)
+            .field("page_size", &self.page_size)
+            .field("alt_text", &self.alt_text.is_some())
+            .field("share_url", &self.share_url);
+        #[cfg(feature = "l10n")]
+        debug.field("catalog", &self.catalog.as_ref().map(|c| c.locale()));
+        debug.finish_non_exhaustive()
+    }
+}
+
+impl GalleryOptions {
+    /// Create options showing captions under the album's name
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the title of the page
+    #[must_use]
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Sets whether captions are shown
+    #[must_use]
+    pub fn with_captions(mut self, captions: bool) -> Self {
+        self.captions = captions;
+        self
+    }
+
+    /// Sets whether captions are rendered from Markdown
+    ///
+    /// Shown captions are rendered to HTML; `alt` texts get their plain text.
+    #[must_use]
+    pub fn with_markdown_captions(mut self, markdown_captions: bool) -> Self {
+        self.markdown_captions = markdown_captions;
+        self
+    }
+
+    /// Replaces the stylesheet of the page
+    #[must_use]
+    pub fn with_css(mut self, css: impl Into<String>) -> Self {
+        self.css = Some(css.into());
+        self
+    }
+
+    /// Sets the number of photos per page, 0 for a single page
+    #[must_use]
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Renders the page with a custom theme
+    #[must_use]
+    pub fn with_theme(mut self, theme: impl GalleryTheme + 'static) -> Self {
+        self.theme = Arc::new(theme);
+        self
+    }
+
+    /// Describes photos without a caption with `generator`
+    #[must_use]
+    pub fn with_alt_text(mut self, generator: impl AltTextGenerator + 'static) -> Self {
+        self.alt_text = Some(Arc::new(generator));
+        self
+    }
+
+    /// Links the pages to the source album at `url`
+    ///
+    /// Pass [`crate::album::AlbumRef::share_url`]. With the `qr-code`
+    /// feature, the link shows a QR code of the URL.
+    #[must_use]
+    pub fn with_share_url(mut self, url: impl Into<String>) -> Self {
+        self.share_url = Some(url.into());
+        self
+    }
+
+    /// Translates the labels and formats dates with `catalog`
+    #[cfg(feature = "l10n")]
+    #[must_use]
+    pub fn with_catalog(mut self, catalog: crate::l10n::Catalog) -> Self {
+        self.catalog = Some(catalog);
+        self
+    }
+
+    /// Formats a label with the catalog, or returns `english` without one
+    fn label(&self, id: &str, args: &[(&str, &dyn fmt::Display)], english: String) -> String {
+        #[cfg(feature = "l10n")]
+        if let Some(catalog) = &self.catalog {
+            return catalog.message(id, args);
+        }
+        #[cfg(not(feature = "l10n"))]
+        let _ = (id, args);
+        english
+    }
+
+    /// Formats a date as written in the catalog's locale, or returns it as is
+    fn date_label(&self, date: &str) -> String {
+        #[cfg(feature = "l10n")]
+        if let Some(formatted) = self
+            .catalog
+            .as_ref()
+            .and_then(|catalog| catalog.format_date(date))
+        {
+            return formatted;
+        }
+        date.to_string()
+    }
+
+    /// Returns the language of the labels, e.g. `en`
+    fn lang(&self) -> String {
+        #[cfg(feature = "l10n")]
+        if let Some(catalog) = &self.catalog {
+            return catalog.locale().to_string();
+        }
+        "en".to_string()
+    }
+
+    /// Returns the alt text of a photo's thumbnail
+    ///
+    /// The caption comes first, then the generator's description, then the
+    /// photo's kind and date. Videos are announced as such.
+    fn alt_text(&self, item: &GalleryItem, date_label: Option<&str>) -> String {
+        let caption = item.caption.as_deref().map(|caption| {
+            if self.markdown_captions {
+                strip_markdown(caption)
+            } else {
+                caption.to_string()
+            }
+        });
+        let tidy = |text: String| {
+            Some(text.split_whitespace().collect::<Vec<_>>().join(" ")).filter(|t| !t.is_empty())
+        };
+        let description = caption.and_then(tidy).or_else(|| {
+            self.alt_text
+                .as_ref()
+                .and_then(|generator| generator.alt_text(item))
+                .and_then(tidy)
+        });
+        let kind = if item.is_video {
+            self.label("video", &[], "Video".to_string())
+        } else {
+            self.label("photo", &[], "Photo".to_string())
+        };
+        match (description, date_label) {
+            (Some(description), _) if item.is_video => format!("{}: {}", kind, description),
+            (Some(description), _) => description,
+            (None, Some(date)) => format!("{}, {}", kind, date),
+            (None, None) => kind,
+        }
+    }
+}
+
+/// Describes photos that have no caption
+///
+/// This is the integration point for image captioning models: the gallery
+/// asks for a description of every caption-less photo it renders.
+pub trait AltTextGenerator: Send + Sync {
+    /// Describes the photo of `item`
+    ///
+    /// `item.thumbnail_url` and `item.url` are relative to the gallery's
+    /// output directory.
+    ///
+    /// # Returns
+    ///
+    /// A short description, or `None` to describe the photo by its kind and date
+    fn alt_text(&self, item: &GalleryItem) -> Option<String>;
+}
+
+/// A photo in the gallery
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GalleryItem {
+    /// GUID of the photo
+    pub photo_guid: String,
+    /// Caption of the photo, if any
+    pub caption: Option<String>,
+    /// When the photo was taken, as reported by iCloud
+    pub date_created: Option<String>,
+    /// When the photo was taken, as written in the locale of the page
+    pub date_label: Option<String>,
+    /// Alt text of the thumbnail, set by [`render_gallery`]
+    pub alt_text: Option<String>,
+    /// Name of the place the photo was taken, if known
+    pub place_name: Option<String>,
+    /// Where the photo was taken, if known
+    pub location: Option<Location>,
+    /// URL of the thumbnail, relative to the page
+    pub thumbnail_url: String,
+    /// Width of the thumbnail in pixels, if known
+    pub width: Option<u32>,
+    /// Height of the thumbnail in pixels, if known
+    pub height: Option<u32>,
+    /// URL of the file the thumbnail links to, relative to the page
+    pub url: String,
+    /// Whether `url` is a video
+    pub is_video: bool,
+}
+
+/// Everything a [`GalleryTheme`] renders for one page
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GalleryPage {
+    /// Language of the labels, e.g. `en`
+    pub lang: String,
+    /// Title of the gallery
+    pub title: String,
+    /// The photos of the page, in album order
+    pub photos: Vec<GalleryItem>,
+    /// Number of photos in the gallery
+    pub photo_count: usize,
+    /// Label of the number of photos, e.g. `12 photos`
+    pub count_label: String,
+    /// Number of the page, starting at 1
+    pub page: usize,
+    /// Number of pages
+    pub page_count: usize,
+    /// URL of the previous page, relative to this one
+    pub previous_url: Option<String>,
+    /// URL of the next page, relative to this one
+    pub next_url: Option<String>,
+    /// Label of the page's position, e.g. `Page 2 of 5`
+    pub page_label: String,
+    /// Label of the link to the previous page
+    pub previous_label: String,
+    /// Label of the link to the next page
+    pub next_label: String,
+    /// Accessible name of the pagination, e.g. `Pages`
+    pub pages_label: String,
+    /// Whether captions are shown
+    pub captions: bool,
+    /// Whether captions are Markdown, see [`crate::markdown`]
+    pub markdown_captions: bool,
+    /// Stylesheet of the page
+    pub css: String,
+    /// URL of the source album, if linked
+    pub share_url: Option<String>,
+    /// Label of the link to the source album
+    pub share_label: String,
+    /// SVG document of a QR code of `share_url`, with the `qr-code` feature
+    pub qr_code_svg: Option<String>,
+}
+
+/// A page rendered by [`render_gallery`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedPage {
+    /// File name of the page, see [`page_file_name`]
+    pub file_name: String,
+    /// The HTML document
+    pub html: String,
+}
+
+/// Renders the page of a gallery
+///
+/// Implement this to render galleries with a template engine; values must
+/// be escaped by the implementation.
+pub trait GalleryTheme: Send + Sync {
+    /// Returns the HTML document of `page`
+    fn render(&self, page: &GalleryPage) -> String;
+}
+
+/// Theme filling in `{placeholder}` templates, see [`DEFAULT_PAGE_TEMPLATE`]
+///
+/// Values are HTML-escaped; placeholders it doesn't know are left as they are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateTheme {
+    page: String,
+    photo: String,
+}
+
+impl Default for TemplateTheme {
+    fn default() -> Self {
+        Self::new(DEFAULT_PAGE_TEMPLATE, DEFAULT_PHOTO_TEMPLATE)
+    }
+}
+
+impl TemplateTheme {
+    /// Create a theme from a page and a photo template
+    pub fn new(page: impl Into<String>, photo: impl Into<String>) -> Self {
+        Self {
+            page: page.into(),
+            photo: photo.into(),
+        }
+    }
+
+    /// Reads the page and photo templates from files
+    pub fn load(page: &Path, photo: &Path) -> io::Result<Self> {
+        Ok(Self::new(
+            std::fs::read_to_string(page)?,
+            std::fs::read_to_string(photo)?,
+        ))
+    }
+}
+
+impl GalleryTheme for TemplateTheme {
+    fn render(&self, page: &GalleryPage) -> String {
+        let photos: Vec<String> = page
+            .photos
+            .iter()
+            .map(|item| {
+                let caption = item.caption.as_deref().unwrap_or("");
+                let (caption, caption_html) = if page.markdown_captions {
+                    (strip_markdown(caption), render_markdown(caption))
+                } else {
+                    (caption.to_string(), escape_xml(caption))
+                };
+                fill(&self.photo, |name| match name {
+                    "guid" => Some(escape_xml(&item.photo_guid)),
+                    "url" => Some(escape_xml(&item.url)),
+                    "thumbnail_url" => Some(escape_xml(&item.thumbnail_url)),
+                    "kind" => Some(if item.is_video { "video" } else { "photo" }.to_string()),
+                    "alt" => Some(escape_xml(item.alt_text.as_deref().unwrap_or(&caption))),
+                    "caption" => Some(escape_xml(&caption)),
+                    "caption_html" => Some(caption_html.clone()),
+                    "date" => Some(escape_xml(item.date_created.as_deref().unwrap_or(""))),
+                    "date_label" => Some(escape_xml(item.date_label.as_deref().unwrap_or(""))),
+                    "place" => Some(escape_xml(item.place_name.as_deref().unwrap_or(""))),
+                    "latitude" => Some(
+                        item.location
+                            .map(|location| location.latitude.to_string())
+                            .unwrap_or_default(),
+                    ),
+                    "longitude" => Some(
+                        item.location
+                            .map(|location| location.longitude.to_string())
+                            .unwrap_or_default(),
+                    ),
+                    "width" => Some(item.width.map(|w| w.to_string()).unwrap_or_default()),
+                    "height" => Some(item.height.map(|h| h.to_string()).unwrap_or_default()),
+                    "size_attributes" => Some(match (item.width, item.height) {
+                        (Some(width), Some(height)) => {
+                            format!(" width=\"{}\" height=\"{}\"", width, height)
+                        }
+                        _ => String::new(),
+                    }),
+                    "figcaption" if page.captions && !caption.is_empty() => {
+                        Some(format!("<figcaption>{}</figcaption>", caption_html))
+                    }
+                    "figcaption" => Some(String::new()),
+                    _ => None,
+                })
+            })
+            .collect();
+        fill(&self.page, |name| match name {
+            "lang" => Some(escape_xml(&page.lang)),
+            "title" => Some(escape_xml(&page.title)),
+            "count" => Some(page.photo_count.to_string()),
+            "count_label" => Some(escape_xml(&page.count_label)),
+            "page" => Some(page.page.to_string()),
+            "page_count" => Some(page.page_count.to_string()),
+            "css" => Some(page.css.trim_end().to_string()),
+            "photos" => Some(photos.join("\n")),
+            "pagination" if page.page_count > 1 => Some(pagination(page)),
+            "pagination" => Some(String::new()),
+            "share" => Some(share(page)),
+            _ => None,
+        })
+    }
+}
+
+/// A photo published by a gallery build
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublishedPhoto {
+    /// GUID of the photo
+    pub photo_guid: String,
+    /// Fingerprint of the photo's derivatives when published, see [`photo_fingerprint`]
+    pub fingerprint: String,
+    /// URL of the thumbnail, relative to the page
+    pub thumbnail_url: String,
+    /// Width of the thumbnail in pixels, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    /// Height of the thumbnail in pixels, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// URL of the file the thumbnail links to, relative to the page
+    pub url: String,
+    /// Whether `url` is a video
+    pub is_video: bool,
+    /// Paths of the photo's files, relative to the output directory
+    pub files: Vec<String>,
+}
+
+/// What a gallery build published, see [`write_gallery_incremental`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GalleryManifest {
+    /// The photos, in album order
+    pub photos: Vec<PublishedPhoto>,
+    /// File names of the pages
+    #[serde(default)]
+    pub pages: Vec<String>,
+}
+
+impl GalleryManifest {
+    /// Reads the manifest of an output directory
+    ///
+    /// A directory without a manifest, e.g. before the first build, yields an
+    /// empty one.
+    pub fn load(output_dir: &Path) -> io::Result<Self> {
+        match std::fs::read_to_string(output_dir.join(MANIFEST_FILE)) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the published photo with the given GUID
+    pub fn photo(&self, photo_guid: &str) -> Option<&PublishedPhoto> {
+        self.photos
+            .iter()
+            .find(|photo| photo.photo_guid == photo_guid)
+    }
+
+    /// Compares the published photos with the album's current ones
+    ///
+    /// A photo is unchanged if its derivatives have the same checksums as
+    /// when it was published and its files are still in `output_dir`.
+    ///
+    /// # Arguments
+    ///
+    /// * `album` - The freshly fetched album
+    /// * `output_dir` - Directory the gallery is built in
+    ///
+    /// # Returns
+    ///
+    /// Which photos need to be downloaded for the next build
+    pub fn plan(&self, album: &ICloudResponse, output_dir: &Path) -> GalleryPlan {
+        let mut plan = GalleryPlan::default();
+        for photo in &album.photos {
+            let unchanged = self.photo(&photo.photo_guid).is_some_and(|published| {
+                published.fingerprint == photo_fingerprint(photo)
+                    && published
+                        .files
+                        .iter()
+                        .all(|file| output_dir.join(file).is_file())
+            });
+            if unchanged {
+                plan.unchanged.push(photo.photo_guid.clone());
+            } else {
+                plan.changed.push(photo.photo_guid.clone());
+            }
+        }
+        let current: HashSet<&str> = album
+            .photos
+            .iter()
+            .map(|photo| photo.photo_guid.as_str())
+            .collect();
+        plan.removed = self
+            .photos
+            .iter()
+            .filter(|photo| !current.contains(photo.photo_guid.as_str()))
+            .map(|photo| photo.photo_guid.clone())
+            .collect();
+        plan
+    }
+}
+
+/// Result of [`GalleryManifest::plan`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GalleryPlan {
+    /// Photos that are new or changed, or whose files went missing
+    pub changed: Vec<String>,
+    /// Photos that can be published from the files of the previous build
+    pub unchanged: Vec<String>,
+    /// Photos no longer in the album
+    pub removed: Vec<String>,
+}
+
+impl GalleryPlan {
+    /// Returns the photos to download, in album order
+    pub fn changed_photos(&self, album: &ICloudResponse) -> Vec<Image> {
+        let changed: HashSet<&str> = self.changed.iter().map(String::as_str).collect();
+        album
+            .photos
+            .iter()
+            .filter(|photo| changed.contains(photo.photo_guid.as_str()))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Result of [`write_gallery_incremental`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GalleryBuild {
+    /// What the build published, also written to [`MANIFEST_FILE`]
+    pub manifest: GalleryManifest,
+    /// Photos published from the download report
+    pub published: usize,
+    /// Photos published from the files of the previous build
+    pub reused: usize,
+    /// Files and pages of the previous build that were deleted
+    pub removed_files: Vec<PathBuf>,
+    /// Pages that changed and were written
+    pub pages_written: usize,
+}
+
+/// Fingerprints a photo's derivatives, to tell whether it changed since it was published
+///
+/// The fingerprint covers the keys and checksums of all derivatives, so it
+/// changes when the photo is edited but not when its URLs are refreshed.
+pub fn photo_fingerprint(photo: &Image) -> String {
+    let mut derivatives: Vec<_> = photo
+        .derivatives
+        .iter()
+        .map(|(key, derivative)| format!("{}={};", key, derivative.checksum))
+        .collect();
+    derivatives.sort();
+    let hash = utils::stable_hash(derivatives.iter().flat_map(|entry| entry.bytes()));
+    format!("{:016x}", hash)
+}
+
+/// Collects the gallery items of a downloaded album
+///
+/// Photos without a downloaded file to show are left out; packed files are
+/// skipped, since a page can't reference them.
+///
+/// # Arguments
+///
+/// * `album` - The downloaded album
+/// * `report` - Report of the download pass
+/// * `output_dir` - Directory the files were downloaded to, where the page goes
+///
+/// # Returns
+///
+/// The items, in album order
+pub fn gallery_items(
+    album: &ICloudResponse,
+    report: &MultiDownloadReport,
+    output_dir: &Path,
+) -> Vec<GalleryItem> {
+    let published = publish(album, report, output_dir);
+    items(album, published.values())
+}
+
+/// Returns the file name of a gallery page: `index.html`, then `page-2.html` and so on
+pub fn page_file_name(page: usize) -> String {
+    if page <= 1 {
+        GALLERY_FILE.to_string()
+    } else {
+        format!("page-{}.html", page)
+    }
+}
+
+/// Renders the pages of a gallery with the theme of `options`
+///
+/// # Arguments
+///
+/// * `title` - Title of the gallery
+/// * `items` - The photos, see [`gallery_items`]
+/// * `options` - What the pages show
+///
+/// # Returns
+///
+/// The pages, at least one even for an empty gallery
+pub fn render_gallery(
+    title: &str,
+    items: &[GalleryItem],
+    options: &GalleryOptions,
+) -> Vec<RenderedPage> {
+    let page_size = if options.page_size == 0 {
+        items.len().max(1)
+    } else {
+        options.page_size
+    };
+    let page_count = items.len().div_ceil(page_size).max(1);
+    let count = items.len();
+    let count_label = if count == 1 {
+        options.label("photo-count-one", &[], "1 photo".to_string())
+    } else {
+        options.label(
+            "photo-count",
+            &[("count", &count)],
+            format!("{} photos", count),
+        )
+    };
+    #[cfg(feature = "qr-code")]
+    let qr_code_svg = options.share_url.as_ref().and_then(|url| {
+        crate::qr::QrCode::encode(url.as_bytes(), crate::qr::EcLevel::default())
+            .ok()
+            .map(|code| code.to_svg())
+    });
+    #[cfg(not(feature = "qr-code"))]
+    let qr_code_svg: Option<String> = None;
+    (1..=page_count)
+        .map(|page| {
+            let start = (page - 1) * page_size;
+            let photos = items[start.min(count)..(start + page_size).min(count)]
+                .iter()
+                .map(|item| {
+                    let date_label = item
+                        .date_created
+                        .as_deref()
+                        .map(|date| options.date_label(date));
+                    GalleryItem {
+                        alt_text: Some(options.alt_text(item, date_label.as_deref())),
+                        date_label,
+                        ..item.clone()
+                    }
+                })
+                .collect();
+            let html = options.theme.render(&GalleryPage {
+                lang: options.lang(),
+                title: title.to_string(),
+                photos,
+                photo_count: count,
+                count_label: count_label.clone(),
+                page,
+                page_count,
+                previous_url: (page > 1).then(|| page_file_name(page - 1)),
+                next_url: (page < page_count).then(|| page_file_name(page + 1)),
+                page_label: options.label(
+                    "page-of",
+                    &[("page", &page), ("count", &page_count)],
+                    format!("Page {} of {}", page, page_count),
+                ),
+                previous_label: options.label("previous-page", &[], "Previous".to_string()),
+                next_label: options.label("next-page", &[], "Next".to_string()),
+                pages_label: options.label("pages", &[], "Pages".to_string()),
+                captions: options.captions,
+                markdown_captions: options.markdown_captions,
+                css: options.css.as_deref().unwrap_or(DEFAULT_CSS).to_string(),
+                share_url: options.share_url.clone(),
+                share_label: options.label("view-album", &[], "View the album".to_string()),
+                qr_code_svg: qr_code_svg.clone(),
+            });
+            RenderedPage {
+                file_name: page_file_name(page),
+                html,
+            }
+        })
+        .collect()
+}
+
+/// Writes the gallery pages of a downloaded album into its output directory
+///
+/// # Arguments
+///
+/// * `album` - The downloaded album
+/// * `report` - Report of the download pass
+/// * `output_dir` - Directory the files were downloaded to
+/// * `options` - What the pages show
+///
+/// # Returns
+///
+/// The path of the first page
+pub fn write_gallery(
+    album: &ICloudResponse,
+    report: &MultiDownloadReport,
+    output_dir: &Path,
+    options: &GalleryOptions,
+) -> io::Result<PathBuf> {
+    let items = gallery_items(album, report, output_dir);
+    for page in render_gallery(page_title(album, options), &items, options) {
+        std::fs::write(output_dir.join(&page.file_name), page.html)?;
+    }
+    Ok(output_dir.join(GALLERY_FILE))
+}
+
+/// Writes the gallery pages and manifest of a build, reusing the previous build's files
+///
+/// `report` only needs to cover the photos of [`GalleryPlan::changed`];
+/// unchanged photos are published from `previous`. Files and pages of the
+/// previous build that are no longer referenced are deleted, and pages are
+/// only written if their contents changed.
+///
+/// # Arguments
+///
+/// * `album` - The freshly fetched album
+/// * `report` - Report of the download pass over the changed photos
+/// * `output_dir` - Directory the gallery is built in
+/// * `options` - What the pages show
+/// * `previous` - Manifest of the previous build, see [`GalleryManifest::load`]
+///
+/// # Returns
+///
+/// What the build published and changed
+pub fn write_gallery_incremental(
+    album: &ICloudResponse,
+    report: &MultiDownloadReport,
+    output_dir: &Path,
+    options: &GalleryOptions,
+    previous: &GalleryManifest,
+) -> io::Result<GalleryBuild> {
+    let mut fresh = publish(album, report, output_dir);
+    let mut build = GalleryBuild::default();
+    for photo in &album.photos {
+        if let Some(published) = fresh.remove(photo.photo_guid.as_str()) {
+            build.published += 1;
+            build.manifest.photos.push(published);
+        } else if let Some(published) = previous
+            .photo(&photo.photo_guid)
+            .filter(|published| published.fingerprint == photo_fingerprint(photo))
+        {
+            build.reused += 1;
+            build.manifest.photos.push(published.clone());
+        }
+    }
+
+    let kept: HashSet<&str> = build
+        .manifest
+        .photos
+        .iter()
+        .flat_map(|photo| photo.files.iter().map(String::as_str))
+        .collect();
+    for file in previous.photos.iter().flat_map(|photo| &photo.files) {
+        // Never follow a manifest out of the output directory
+        let relative = Path::new(file);
+        if kept.contains(file.as_str())
+            || !relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            continue;
+        }
+        let path = output_dir.join(relative);
+        match std::fs::remove_file(&path) {
+            Ok(()) => build.removed_files.push(path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let pages = render_gallery(
+        page_title(album, options),
+        &items(album, build.manifest.photos.iter()),
+        options,
+    );
+    for page in pages {
+        let path = output_dir.join(&page.file_name);
+        if std::fs::read_to_string(&path).ok().as_deref() != Some(page.html.as_str()) {
+            std::fs::write(&path, page.html)?;
+            build.pages_written += 1;
+        }
+        build.manifest.pages.push(page.file_name);
+    }
+    for file_name in &previous.pages {
+        let stale = !build.manifest.pages.contains(file_name)
+            && Path::new(file_name)
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        if stale {
+            let path = output_dir.join(file_name);
+            match std::fs::remove_file(&path) {
+                Ok(()) => build.removed_files.push(path),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    let manifest = serde_json::to_string_pretty(&build.manifest)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    std::fs::write(output_dir.join(MANIFEST_FILE), manifest)?;
+    Ok(build)
+}
+
+/// Title of the page, the album's name unless set in the options
+fn page_title<'a>(album: &'a ICloudResponse, options: &'a GalleryOptions) -> &'a str {
+    options
+        .title
+        .as_deref()
+        .unwrap_or(&album.metadata.stream_name)
+}
+
+/// Publishes the photos of a download report, keyed by photo GUID
+fn publish(
+    album: &ICloudResponse,
+    report: &MultiDownloadReport,
+    output_dir: &Path,
+) -> HashMap<String, PublishedPhoto> {
+    let mut files: HashMap<&str, Vec<(SizeClass, &str, &Path)>> = HashMap::new();
+    for download in &report.downloaded {
+        if download.path.contains('#') {
+            continue;
+        }
+        let path = Path::new(&download.path);
+        files
+            .entry(download.photo_guid.as_str())
+            .or_default()
+            .push((
+                download.size_class,
+                download.key.as_str(),
+                path.strip_prefix(output_dir).unwrap_or(path),
+            ));
+    }
+
+    album
+        .photos
+        .iter()
+        .filter_map(|photo| {
+            let files = files.get(photo.photo_guid.as_str())?;
+            let file = |classes: &[SizeClass]| {
+                classes.iter().find_map(|size_class| {
+                    files
+                        .iter()
+                        .find(|(class, _, _)| class == size_class)
+                        .copied()
+                })
+            };
+            let (thumbnail_class, thumbnail_key, thumbnail) = file(THUMBNAIL_CLASSES)?;
+            let (full_class, _, full) =
+                file(FULL_CLASSES).unwrap_or((thumbnail_class, thumbnail_key, thumbnail));
+            let dimensions = photo.derivatives.get(thumbnail_key);
+            Some((
+                photo.photo_guid.clone(),
+                PublishedPhoto {
+                    photo_guid: photo.photo_guid.clone(),
+                    fingerprint: photo_fingerprint(photo),
+                    thumbnail_url: relative_url(thumbnail),
+                    width: dimensions.and_then(|derivative| derivative.width),
+                    height: dimensions.and_then(|derivative| derivative.height),
+                    url: relative_url(full),
+                    is_video: full_class == SizeClass::Video,
+                    files: files
+                        .iter()
+                        .map(|(_, _, path)| relative_path(path))
+                        .collect(),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// The gallery items of the published photos, in album order with current captions
+fn items<'a>(
+    album: &ICloudResponse,
+    published: impl Iterator<Item = &'a PublishedPhoto>,
+) -> Vec<GalleryItem> {
+    let published: HashMap<&str, &PublishedPhoto> = published
+        .map(|photo| (photo.photo_guid.as_str(), photo))
+        .collect();
+    album
+        .photos
+        .iter()
+        .filter_map(|photo| {
+            let published = published.get(photo.photo_guid.as_str())?;
+            Some(GalleryItem {
+                photo_guid: photo.photo_guid.clone(),
+                caption: photo
+                    .caption
+                    .clone()
+                    .filter(|caption| !caption.trim().is_empty()),
+                date_created: photo.date_created.clone(),
+                date_label: None,
+                alt_text: None,
+                place_name: photo.place_name.clone(),
+                location: photo.location,
+                thumbnail_url: published.thumbnail_url.clone(),
+                width: published.width,
+                height: published.height,
+                url: published.url.clone(),
+                is_video: published.is_video,
+            })
+        })
+        .collect()
+}
+
+/// Links to the previous and next pages of a gallery page
+/// Renders the link to the source album, with its QR code if there is one
+fn share(page: &GalleryPage) -> String {
+    let Some(url) = &page.share_url else {
+        return String::new();
+    };
+    format!(
+        "<p class=\"share\"><a href=\"{}\">{}{}</a></p>",
+        escape_xml(url),
+        page.qr_code_svg.as_deref().unwrap_or(""),
+        escape_xml(&page.share_label)
+    )
+}
+
+fn pagination(page: &GalleryPage) -> String {
+    let mut html = format!(
+        "<nav class=\"pagination\" aria-label=\"{}\">",
+        escape_xml(&page.pages_label)
+    );
+    if let Some(url) = &page.previous_url {
+        html.push_str(&format!(
+            "<a href=\"{}\" rel=\"prev\">&larr; {}</a> ",
+            escape_xml(url),
+            escape_xml(&page.previous_label)
+        ));
+    }
+    html.push_str(&format!(
+        "<span aria-current=\"page\">{}</span>",
+        escape_xml(&page.page_label)
+    ));
+    if let Some(url) = &page.next_url {
+        html.push_str(&format!(
+            " <a href=\"{}\" rel=\"next\">{} &rarr;</a>",
+            escape_xml(url),
+            escape_xml(&page.next_label)
+        ));
+    }
+    html.push_str("</nav>");
+    html
+}
+
+/// Replaces the `{name}` placeholders of a template in one pass
+///
+/// Placeholders `value` returns `None` for are kept, so literal braces, e.g.
+/// in inline CSS, pass through.
+fn fill(template: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let replaced = placeholder.find('}').and_then(|end| {
+            let name = &placeholder[1..end];
+            value(name).map(|value| (value, end + 1))
+        });
+        match replaced {
+            Some((value, length)) => {
+                output.push_str(&value);
+                rest = &placeholder[length..];
+            }
+            None => {
+                output.push('{');
+                rest = &placeholder[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Joins the components of a relative path with `/`, as stored in a [`GalleryManifest`]
+fn relative_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Turns a relative file path into a URL path, percent-encoding each segment
+fn relative_url(path: &Path) -> String {
+    path.components()
+        .map(|component| {
+            let segment = component.as_os_str().to_string_lossy();
+            let mut encoded = String::with_capacity(segment.len());
+            for byte in segment.bytes() {
+                match byte {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                        encoded.push(byte as char)
+                    }
+                    _ => encoded.push_str(&format!("%{:02X}", byte)),
+                }
+            }
+            encoded
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,586 @@
+//! Recording of HTTP interactions as HAR files.
+//!
+//! When an album fails for one user only, the exact requests and responses
+//! are the quickest way to see why. While recording is enabled with
+//! [`start_recording`], every request the crate sends (webstream,
+//! webasseturls and downloads) is kept in memory together with its response.
+//! [`stop_recording`] returns the interactions as a [`HarLog`] in the HTTP
+//! Archive 1.2 format, which browser developer tools and HAR viewers open
+//! directly.
+//!
+//! By default, the log is safe to attach to an issue: URLs and headers are
+//! passed through [`crate::redact::redact_text`], JSON bodies through one
+//! shared [`crate::utils::ResponseAnonymizer`], and bodies are truncated.
+//! Binary bodies, such as photos, are never included; only their size and
+//! type are recorded.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use icloud_album_rs::har;
+//!
+//! har::start_recording(har::HarOptions::default());
+//! let _result = icloud_album_rs::get_icloud_photos("B0z5qAGN1JIFd3y").await;
+//! if let Some(log) = har::stop_recording() {
+//!     log.write("album.har").await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::models::format_timestamp;
+use crate::redact;
+use crate::utils::ResponseAnonymizer;
+use serde::Serialize;
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Default maximum number of body bytes kept per request or response
+pub const DEFAULT_MAX_BODY_LEN: usize = 64 * 1024;
+
+/// Options controlling what is recorded
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HarOptions {
+    /// Maximum number of bytes kept of each text body; longer bodies are truncated
+    pub max_body_len: usize,
+    /// Redact URLs and headers and anonymize JSON bodies
+    ///
+    /// URL redaction follows [`crate::redact::set_redaction`], so it is skipped
+    /// while redaction is disabled process-wide.
+    pub anonymize: bool,
+}
+
+impl Default for HarOptions {
+    fn default() -> Self {
+        Self {
+            max_body_len: DEFAULT_MAX_BODY_LEN,
+            anonymize: true,
+        }
+    }
+}
+
+impl HarOptions {
+    /// Create options anonymizing the log and truncating long bodies
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of bytes kept of each text body
+    #[must_use]
+    pub fn with_max_body_len(mut self, max_body_len: usize) -> Self {
+        self.max_body_len = max_body_len;
+        self
+    }
+
+    /// Sets whether URLs, headers and bodies are anonymized
+    #[must_use]
+    pub fn with_anonymize(mut self, anonymize: bool) -> Self {
+        self.anonymize = anonymize;
+        self
+    }
+}
+
+/// A recorded session in the HTTP Archive format
+#[derive(Debug, Clone, Serialize)]
+pub struct HarLog {
+    /// The archive, under the top-level `log` key required by the format
+    pub log: HarArchive,
+}
+
+/// Contents of a [`HarLog`]
+#[derive(Debug, Clone, Serialize)]
+pub struct HarArchive {
+    /// Version of the HAR format
+    pub version: String,
+    /// The application that recorded the archive
+    pub creator: HarCreator,
+    /// The recorded interactions, in the order their requests were sent
+    pub entries: Vec<HarEntry>,
+}
+
+/// Name and version of the recording application
+#[derive(Debug, Clone, Serialize)]
+pub struct HarCreator {
+    /// Application name
+    pub name: String,
+    /// Application version
+    pub version: String,
+}
+
+/// One request and its response
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarEntry {
+    /// When the request was sent (ISO 8601)
+    pub started_date_time: String,
+    /// Total time of the interaction in milliseconds
+    pub time: f64,
+    /// The request
+    pub request: HarRequest,
+    /// The response, with status 0 if none was received
+    pub response: HarResponse,
+    /// Cache information (always empty)
+    pub cache: serde_json::Map<String, serde_json::Value>,
+    /// Time spent in each phase
+    pub timings: HarTimings,
+    /// The error that ended the interaction, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// A header or query parameter
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HarNameValue {
+    /// Name
+    pub name: String,
+    /// Value
+    pub value: String,
+}
+
+/// A recorded request
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+    /// HTTP method
+    pub method: String,
+    /// Request URL
+    pub url: String,
+    /// HTTP version
+    pub http_version: String,
+    /// Cookies (always empty)
+    pub cookies: Vec<HarNameValue>,
+    /// Request headers
+    pub headers: Vec<HarNameValue>,
+    /// Query parameters
+    pub query_string: Vec<HarNameValue>,
+    /// The request body, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<HarPostData>,
+    /// Size of the headers (unknown, always -1)
+    pub headers_size: i64,
+    /// Size of the body in bytes
+    pub body_size: i64,
+}
+
+/// A recorded request body
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarPostData {
+    /// MIME type of the body
+    pub mime_type: String,
+    /// The body, possibly anonymized and truncated
+    pub text: String,
+}
+
+/// A recorded response
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarResponse {
+    /// HTTP status, or 0 if no response was received
+    pub status: u16,
+    /// Reason phrase of the status
+    pub status_text: String,
+    /// HTTP version
+    pub http_version: String,
+    /// Cookies (always empty)
+    pub cookies: Vec<HarNameValue>,
+    /// Response headers
+    pub headers: Vec<HarNameValue>,
+    /// The response body
+    pub content: HarContent,
+    /// Target of a redirect response, or empty
+    #[serde(rename = "redirectURL")]
+    pub redirect_url: String,
+    /// Size of the headers (unknown, always -1)
+    pub headers_size: i64,
+    /// Size of the body in bytes, or -1 if unknown
+    pub body_size: i64,
+}
+
+/// A recorded response body
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarContent {
+    /// Size of the full body in bytes
+    pub size: i64,
+    /// MIME type of the body
+    pub mime_type: String,
+    /// The body for text types, possibly anonymized and truncated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Why the body is missing or incomplete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// Time spent in each phase of an interaction, in milliseconds
+#[derive(Debug, Clone, Serialize)]
+pub struct HarTimings {
+    /// Sending the request (not measured separately, always 0)
+    pub send: f64,
+    /// Waiting for the response headers, including connecting
+    pub wait: f64,
+    /// Reading the response body
+    pub receive: f64,
+}
+
+impl HarLog {
+    fn new(entries: Vec<HarEntry>) -> Self {
+        Self {
+            log: HarArchive {
+                version: "1.2".to_string(),
+                creator: HarCreator {
+                    name: env!("CARGO_PKG_NAME").to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                },
+                entries,
+            },
+        }
+    }
+
+    /// Serializes the log as pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    /// Writes the log to a `.har` file
+    pub async fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        tokio::fs::write(path, self.to_json()).await
+    }
+}
+
+/// State of an active recording
+struct Recorder {
+    options: HarOptions,
+    anonymizer: ResponseAnonymizer,
+    entries: Vec<HarEntry>,
+}
+
+fn recorder_lock() -> &'static Mutex<Option<Recorder>> {
+    static RECORDER: OnceLock<Mutex<Option<Recorder>>> = OnceLock::new();
+    RECORDER.get_or_init(|| Mutex::new(None))
+}
+
+fn recorder() -> std::sync::MutexGuard<'static, Option<Recorder>> {
+    recorder_lock().lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Starts recording HTTP interactions, discarding any earlier recording
+///
+/// Recording is process-wide: interactions of all concurrent fetches and
+/// downloads end up in the same log.
+pub fn start_recording(options: HarOptions) {
+    *recorder() = Some(Recorder {
+        options,
+        anonymizer: ResponseAnonymizer::new(),
+        entries: Vec::new(),
+    });
+}
+
+/// Stops recording and returns what was recorded
+///
+/// # Returns
+///
+/// The recorded log, or `None` if recording was not started
+pub fn stop_recording() -> Option<HarLog> {
+    recorder()
+        .take()
+        .map(|recorder| HarLog::new(recorder.entries))
+}
+
+/// Returns true if HTTP interactions are being recorded
+pub fn is_recording() -> bool {
+    recorder().is_some()
+}
+
+/// A request and response as seen on the wire, before redaction
+struct Interaction<'a> {
+    started: SystemTime,
+    wait_ms: f64,
+    receive_ms: f64,
+    method: &'a str,
+    url: &'a reqwest::Url,
+    request_headers: &'a reqwest::header::HeaderMap,
+    request_body: Option<&'a [u8]>,
+    status: Option<reqwest::StatusCode>,
+    version: Option<reqwest::Version>,
+    response_headers: Option<&'a reqwest::header::HeaderMap>,
+    response_body: Option<&'a [u8]>,
+    error: Option<String>,
+}
+
+fn version_name(version: Option<reqwest::Version>) -> String {
+    match version {
+        Some(reqwest::Version::HTTP_09) => "HTTP/0.9",
+        Some(reqwest::Version::HTTP_10) => "HTTP/1.0",
+        Some(reqwest::Version::HTTP_2) => "HTTP/2.0",
+        Some(reqwest::Version::HTTP_3) => "HTTP/3.0",
+        _ => "HTTP/1.1",
+    }
+    .to_string()
+}
+
+fn content_type(headers: Option<&reqwest::header::HeaderMap>) -> String {
+    headers
+        .and_then(|headers| headers.get(reqwest::header::CONTENT_TYPE))
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Returns true for bodies that are kept as text
+fn is_text(mime_type: &str, body: &[u8]) -> bool {
+    let essence = mime_type.split(';').next().unwrap_or_default().trim();
+    let text_type = essence.starts_with("text/") || essence.ends_with("json");
+    // Servers often omit the type of JSON API responses
+    let looks_like_json = essence.is_empty() && matches!(body.first(), Some(b'{' | b'['));
+    (text_type || looks_like_json) && std::str::from_utf8(body).is_ok()
+}
+
+impl Recorder {
+    fn text(&self, value: &str) -> String {
+        if self.options.anonymize {
+            redact::redact_text(value)
+        } else {
+            value.to_string()
+        }
+    }
+
+    fn headers(&self, headers: &reqwest::header::HeaderMap) -> Vec<HarNameValue> {
+        headers
+            .iter()
+            .map(|(name, value)| HarNameValue {
+                name: name.to_string(),
+                value: self.text(&String::from_utf8_lossy(value.as_bytes())),
+            })
+            .collect()
+    }
+
+    /// Returns the body as recorded text and why it is incomplete, if it is
+    fn body(&mut self, mime_type: &str, body: &[u8]) -> (Option<String>, Option<String>) {
+        if body.is_empty() {
+            return (None, None);
+        }
+        if !is_text(mime_type, body) {
+            return (None, Some("binary body not recorded".to_string()));
+        }
+        let mut text = String::from_utf8_lossy(body).into_owned();
+        if self.options.anonymize {
+            text = match serde_json::from_str::<serde_json::Value>(&text) {
+                Ok(value) => self.anonymizer.anonymize(value).to_string(),
+                Err(_) => redact::redact_text(&text),
+            };
+        }
+        if text.len() > self.options.max_body_len {
+            let mut end = self.options.max_body_len;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+            return (
+                Some(text),
+                Some(format!("truncated to {} bytes", self.options.max_body_len)),
+            );
+        }
+        (Some(text), None)
+    }
+
+    fn record(&mut self, interaction: Interaction) {
+        let url = self.text(interaction.url.as_str());
+        let query_string = if self.options.anonymize {
+            // Query strings hold URL signatures, which redaction removes
+            Vec::new()
+        } else {
+            interaction
+                .url
+                .query_pairs()
+                .map(|(name, value)| HarNameValue {
+                    name: name.into_owned(),
+                    value: value.into_owned(),
+                })
+                .collect()
+        };
+
+        let request_type = content_type(Some(interaction.request_headers));
+        let post_data = match interaction.request_body {
+            Some(body) => {
+                let (text, _) = self.body(&request_type, body);
+                text.map(|text| HarPostData {
+                    mime_type: request_type.clone(),
+                    text,
+                })
+            }
+            None => None,
+        };
+
+        let response_type = content_type(interaction.response_headers);
+        let (text, comment) = match interaction.response_body {
+            Some(body) => self.body(&response_type, body),
+            None => (None, None),
+        };
+        let response_headers = interaction
+            .response_headers
+            .map(|headers| self.headers(headers))
+            .unwrap_or_default();
+        let redirect_url = response_headers
+            .iter()
+            .find(|header| header.name == "location")
+            .map(|header| header.value.clone())
+            .unwrap_or_default();
+        let body_size = interaction
+            .response_body
+            .map_or(-1, |body| body.len() as i64);
+
+        let started_secs = interaction
+            .started
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        let entry = HarEntry {
+            started_date_time: format_timestamp(started_secs),
+            time: interaction.wait_ms + interaction.receive_ms,
+            request: HarRequest {
+                method: interaction.method.to_string(),
+                url,
+                http_version: version_name(interaction.version),
+                cookies: Vec::new(),
+                headers: self.headers(interaction.request_headers),
+                query_string,
+                post_data,
+                headers_size: -1,
+                body_size: interaction.request_body.map_or(0, |body| body.len() as i64),
+            },
+            response: HarResponse {
+                status: interaction.status.map_or(0, |status| status.as_u16()),
+                status_text: interaction
+                    .status
+                    .and_then(|status| status.canonical_reason())
+                    .unwrap_or_default()
+                    .to_string(),
+                http_version: version_name(interaction.version),
+                cookies: Vec::new(),
+                headers: response_headers,
+                content: HarContent {
+                    size: body_size.max(0),
+                    mime_type: response_type,
+                    text,
+                    comment,
+                },
+                redirect_url,
+                headers_size: -1,
+                body_size,
+            },
+            cache: serde_json::Map::new(),
+            timings: HarTimings {
+                send: 0.0,
+                wait: interaction.wait_ms,
+                receive: interaction.receive_ms,
+            },
+            comment: interaction.error.map(|e| self.text(&e)),
+        };
+        self.entries.push(entry);
+    }
+}
+
+fn record(interaction: Interaction) {
+    if let Some(recorder) = recorder().as_mut() {
+        recorder.record(interaction);
+    }
+}
+
+fn millis(since: Instant) -> f64 {
+    since.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Sends a request, recording it and its response while recording is enabled
+///
+/// When recording, the response body is read completely before this returns
+/// and the returned response is rebuilt from it; its `url()` is then not
+/// meaningful. Without recording, the request is simply sent. With the
+/// `fault-injection` feature, installed faults are applied before either.
+/// Requests answered by a [`crate::transport::Transport`] are not sent.
+pub(crate) async fn send(request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+    #[cfg(feature = "fault-injection")]
+    let request = match crate::faults::intercept(request).await {
+        Ok(request) => request,
+        Err(injected) => return Ok(injected),
+    };
+    let request = match crate::transport::intercept(request) {
+        Ok(request) => request,
+        Err(answered) => return Ok(*answered),
+    };
+
+    if !is_recording() {
+        return request.send().await;
+    }
+
+    let (client, request) = request.build_split();
+    let request = request?;
+    let method = request.method().to_string();
+    let url = request.url().clone();
+    let request_headers = request.headers().clone();
+    let request_body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .map(<[u8]>::to_vec);
+
+    let started = SystemTime::now();
+    let timer = Instant::now();
+    let mut interaction = Interaction {
+        started,
+        wait_ms: 0.0,
+        receive_ms: 0.0,
+        method: &method,
+        url: &url,
+        request_headers: &request_headers,
+        request_body: request_body.as_deref(),
+        status: None,
+        version: None,
+        response_headers: None,
+        response_body: None,
+        error: None,
+    };
+
+    let response = match client.execute(request).await {
+        Ok(response) => response,
+        Err(e) => {
+            interaction.wait_ms = millis(timer);
+            interaction.error = Some(e.to_string());
+            record(interaction);
+            return Err(e);
+        }
+    };
+    interaction.wait_ms = millis(timer);
+    let status = response.status();
+    let version = response.version();
+    let headers = response.headers().clone();
+    interaction.status = Some(status);
+    interaction.version = Some(version);
+    interaction.response_headers = Some(&headers);
+
+    let receive_timer = Instant::now();
+    let body = match response.bytes().await {
+        Ok(body) => body,
+        Err(e) => {
+            interaction.receive_ms = millis(receive_timer);
+            interaction.error = Some(e.to_string());
+            record(interaction);
+            return Err(e);
+        }
+    };
+    interaction.receive_ms = millis(receive_timer);
+    interaction.response_body = Some(&body);
+    record(interaction);
+
+    let mut rebuilt = http::Response::builder().status(status).version(version);
+    if let Some(rebuilt_headers) = rebuilt.headers_mut() {
+        *rebuilt_headers = headers;
+    }
+    // Status, version and headers come from a valid response
+    let rebuilt = rebuilt
+        .body(body)
+        .expect("recorded response parts are valid");
+    Ok(reqwest::Response::from(rebuilt))
+}
@@ -0,0 +1,426 @@
+//! Pushing downloaded photos to self-hosted photo servers.
+//!
+//! [`import_album`] uploads the files of an album (tracked by [`SyncState`])
+//! through a [`PhotoImporter`]. Two importers are provided:
+//!
+//! * [`ImmichImporter`] uploads through the Immich API and sets each asset's
+//!   description, date and location, optionally adding it to an album
+//! * [`PhotoPrismImporter`] uploads through the PhotoPrism API together with an
+//!   XMP sidecar carrying caption, date and location, then starts the import
+//!   into an album named after the shared album
+
+use crate::export::escape_xml;
+use crate::models::{Image, Location, Metadata};
+use crate::redact::Redacted;
+use crate::sync::SyncState;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use reqwest::multipart::{Form, Part};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+
+/// Device identifier reported to servers that track the uploading device
+pub const DEVICE_ID: &str = "icloud-album-rs";
+
+/// Date used when a photo has no date at all
+const FALLBACK_DATE: &str = "1970-01-01T00:00:00Z";
+
+/// Error returned by a [`PhotoImporter`]
+pub type ImportError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Result of uploading a single photo
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportOutcome {
+    /// The server created a new asset with this identifier
+    Created(String),
+    /// The server already had the photo, under this identifier
+    Duplicate(String),
+    /// The upload was accepted and will be processed later
+    Queued,
+}
+
+/// Future returned by [`PhotoImporter::upload`]
+pub type ImportFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<ImportOutcome, ImportError>> + Send + 'a>>;
+
+/// Future returned by [`PhotoImporter::finish`]
+pub type FinishFuture<'a> = Pin<Box<dyn Future<Output = Result<(), ImportError>> + Send + 'a>>;
+
+/// A photo server that downloaded photos can be pushed to
+pub trait PhotoImporter: Send + Sync {
+    /// Uploads one downloaded photo with its metadata
+    ///
+    /// # Arguments
+    ///
+    /// * `album` - Metadata of the album the photo belongs to
+    /// * `photo` - The photo's metadata
+    /// * `path` - The downloaded file
+    fn upload<'a>(
+        &'a self,
+        album: &'a Metadata,
+        photo: &'a Image,
+        path: &'a Path,
+    ) -> ImportFuture<'a>;
+
+    /// Called once after all photos of an album have been uploaded
+    fn finish<'a>(&'a self, _album: &'a Metadata) -> FinishFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Summary of an [`import_album`] run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Number of photos the server created new assets for
+    pub created: usize,
+    /// Number of photos the server already had
+    pub duplicates: usize,
+    /// Number of photos queued for processing by the server
+    pub queued: usize,
+    /// Photos that failed to upload, as (photo GUID, error message)
+    pub failures: Vec<(String, String)>,
+}
+
+/// Uploads the downloaded photos of an album to a photo server
+///
+/// Photos without a recorded file in `state` are skipped. A failed upload is
+/// recorded in the report and does not stop the import.
+///
+/// # Arguments
+///
+/// * `importer` - The server to upload to
+/// * `album` - Metadata of the album
+/// * `photos` - The album's photos
+/// * `state` - Sync state mapping photo GUIDs to downloaded files
+///
+/// # Returns
+///
+/// The report of the import, or an error if the importer failed to finish
+pub async fn import_album(
+    importer: &dyn PhotoImporter,
+    album: &Metadata,
+    photos: &[Image],
+    state: &SyncState,
+) -> Result<ImportReport, ImportError> {
+    let mut report = ImportReport::default();
+    for photo in photos {
+        let Some(file) = state.file(&photo.photo_guid) else {
+            continue;
+        };
+        match importer.upload(album, photo, &file.path).await {
+            Ok(ImportOutcome::Created(_)) => report.created += 1,
+            Ok(ImportOutcome::Duplicate(_)) => report.duplicates += 1,
+            Ok(ImportOutcome::Queued) => report.queued += 1,
+            Err(e) => report
+                .failures
+                .push((photo.photo_guid.clone(), Redacted(e).to_string())),
+        }
+    }
+    importer.finish(album).await?;
+    Ok(report)
+}
+
+/// Returns the caption of a photo, if it has a non-blank one
+fn caption(photo: &Image) -> Option<&str> {
+    photo
+        .caption
+        .as_deref()
+        .map(str::trim)
+        .filter(|caption| !caption.is_empty())
+}
+
+/// Returns the best available date of a photo
+fn photo_date(photo: &Image) -> &str {
+    photo
+        .date_created
+        .as_deref()
+        .or(photo.batch_date_created.as_deref())
+        .unwrap_or(FALLBACK_DATE)
+}
+
+/// Reads a downloaded file into a multipart part named after the file
+async fn file_part(path: &Path) -> Result<Part, ImportError> {
+    let content = tokio::fs::read(path).await?;
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "photo".to_string());
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    Ok(Part::bytes(content)
+        .file_name(file_name)
+        .mime_str(mime.as_ref())?)
+}
+
+/// Importer for an Immich server
+#[derive(Debug, Clone)]
+pub struct ImmichImporter {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: String,
+    album_id: Option<String>,
+}
+
+impl ImmichImporter {
+    /// Create an importer for the server at `base_url` (e.g. `https://immich.example.com`)
+    pub fn new(base_url: &str, api_key: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            album_id: None,
+        }
+    }
+
+    /// Adds every uploaded asset to an existing Immich album
+    #[must_use]
+    pub fn with_album(mut self, album_id: &str) -> Self {
+        self.album_id = Some(album_id.to_string());
+        self
+    }
+
+    /// Sends an authenticated JSON request and fails on non-success responses
+    async fn send_json(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Value,
+    ) -> Result<(), ImportError> {
+        self.client
+            .request(method, format!("{}{}", self.base_url, path))
+            .header("x-api-key", &self.api_key)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn upload_photo(&self, photo: &Image, path: &Path) -> Result<ImportOutcome, ImportError> {
+        let date = photo_date(photo);
+        let form = Form::new()
+            .text("deviceAssetId", photo.photo_guid.clone())
+            .text("deviceId", DEVICE_ID)
+            .text("fileCreatedAt", date.to_string())
+            .text("fileModifiedAt", date.to_string())
+            .part("assetData", file_part(path).await?);
+
+        let response: Value = self
+            .client
+            .post(format!("{}/api/assets", self.base_url))
+            .header("x-api-key", &self.api_key)
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let id = response
+            .get("id")
+            .and_then(Value::as_str)
+            .ok_or("Immich response has no asset id")?
+            .to_string();
+        let duplicate = response.get("status").and_then(Value::as_str) == Some("duplicate");
+
+        if !duplicate {
+            let mut metadata = json!({ "dateTimeOriginal": date });
+            if let Some(caption) = caption(photo) {
+                metadata["description"] = json!(caption);
+            }
+            if let Some(Location {
+                latitude,
+                longitude,
+                ..
+            }) = photo.location
+            {
+                metadata["latitude"] = json!(latitude);
+                metadata["longitude"] = json!(longitude);
+            }
+            self.send_json(
+                reqwest::Method::PUT,
+                &format!("/api/assets/{}", id),
+                metadata,
+            )
+            .await?;
+        }
+        if let Some(album_id) = &self.album_id {
+            self.send_json(
+                reqwest::Method::PUT,
+                &format!("/api/albums/{}/assets", album_id),
+                json!({ "ids": [id] }),
+            )
+            .await?;
+        }
+
+        Ok(if duplicate {
+            ImportOutcome::Duplicate(id)
+        } else {
+            ImportOutcome::Created(id)
+        })
+    }
+}
+
+impl PhotoImporter for ImmichImporter {
+    fn upload<'a>(
+        &'a self,
+        _album: &'a Metadata,
+        photo: &'a Image,
+        path: &'a Path,
+    ) -> ImportFuture<'a> {
+        Box::pin(self.upload_photo(photo, path))
+    }
+}
+
+/// Formats a coordinate as an XMP GPS value, e.g. `41,52.686N`
+fn xmp_coordinate(value: f64, positive: char, negative: char) -> String {
+    let degrees = value.abs().trunc();
+    let minutes = (value.abs() - degrees) * 60.0;
+    format!(
+        "{},{:.6}{}",
+        degrees as u32,
+        minutes,
+        if value < 0.0 { negative } else { positive }
+    )
+}
+
+/// Builds an XMP sidecar with the caption, date and location of a photo
+pub fn xmp_sidecar(photo: &Image) -> String {
+    let mut fields = String::new();
+    if let Some(caption) = caption(photo) {
+        let caption = escape_xml(caption);
+        for element in ["dc:title", "dc:description"] {
+            fields.push_str(&format!(
+                "   <{e}><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></{e}>\n",
+                caption,
+                e = element
+            ));
+        }
+    }
+    if let Some(date) = &photo.date_created {
+        fields.push_str(&format!(
+            "   <photoshop:DateCreated>{}</photoshop:DateCreated>\n",
+            escape_xml(date)
+        ));
+    }
+    if let Some(location) = &photo.location {
+        fields.push_str(&format!(
+            "   <exif:GPSLatitude>{}</exif:GPSLatitude>\n",
+            xmp_coordinate(location.latitude, 'N', 'S')
+        ));
+        fields.push_str(&format!(
+            "   <exif:GPSLongitude>{}</exif:GPSLongitude>\n",
+            xmp_coordinate(location.longitude, 'E', 'W')
+        ));
+    }
+
+    format!(
+        concat!(
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+            " <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+            "  <rdf:Description rdf:about=\"\"",
+            " xmlns:dc=\"http://purl.org/dc/elements/1.1/\"",
+            " xmlns:photoshop=\"http://ns.adobe.com/photoshop/1.0/\"",
+            " xmlns:exif=\"http://ns.adobe.com/exif/1.0/\">\n",
+            "{}",
+            "  </rdf:Description>\n",
+            " </rdf:RDF>\n",
+            "</x:xmpmeta>\n"
+        ),
+        fields
+    )
+}
+
+/// Importer for a PhotoPrism server
+///
+/// Files are uploaded into one upload session per importer; [`PhotoImporter::finish`]
+/// then asks PhotoPrism to import the session into an album named after the
+/// shared album, creating it if needed.
+#[derive(Debug, Clone)]
+pub struct PhotoPrismImporter {
+    client: reqwest::Client,
+    base_url: String,
+    auth_token: String,
+    user_uid: String,
+    upload_token: String,
+}
+
+impl PhotoPrismImporter {
+    /// Create an importer for the server at `base_url`
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - The server URL, e.g. `https://photos.example.com`
+    /// * `auth_token` - A session ID or app password, sent as `X-Auth-Token`
+    /// * `user_uid` - UID of the user the photos are imported for
+    pub fn new(base_url: &str, auth_token: &str, user_uid: &str) -> Self {
+        let upload_token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            auth_token: auth_token.to_string(),
+            user_uid: user_uid.to_string(),
+            upload_token: upload_token.to_lowercase(),
+        }
+    }
+
+    /// URL of this importer's upload session
+    fn upload_url(&self) -> String {
+        format!(
+            "{}/api/v1/users/{}/upload/{}",
+            self.base_url, self.user_uid, self.upload_token
+        )
+    }
+
+    async fn upload_photo(&self, photo: &Image, path: &Path) -> Result<ImportOutcome, ImportError> {
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| photo.photo_guid.clone());
+        let sidecar = Part::text(xmp_sidecar(photo))
+            .file_name(format!("{}.xmp", stem))
+            .mime_str("application/rdf+xml")?;
+        let form = Form::new()
+            .part("files", file_part(path).await?)
+            .part("files", sidecar);
+
+        self.client
+            .post(self.upload_url())
+            .header("X-Auth-Token", &self.auth_token)
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(ImportOutcome::Queued)
+    }
+
+    async fn process_uploads(&self, album: &Metadata) -> Result<(), ImportError> {
+        self.client
+            .put(self.upload_url())
+            .header("X-Auth-Token", &self.auth_token)
+            .json(&json!({ "albums": [album.stream_name] }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+impl PhotoImporter for PhotoPrismImporter {
+    fn upload<'a>(
+        &'a self,
+        _album: &'a Metadata,
+        photo: &'a Image,
+        path: &'a Path,
+    ) -> ImportFuture<'a> {
+        Box::pin(self.upload_photo(photo, path))
+    }
+
+    fn finish<'a>(&'a self, album: &'a Metadata) -> FinishFuture<'a> {
+        Box::pin(self.process_uploads(album))
+    }
+}
@@ -0,0 +1,450 @@
+//! Local integrity checks for downloaded files.
+//!
+//! Apple's derivative `checksum` strings are mostly opaque identifiers, not
+//! hashes that can be recomputed from a file. This module provides the crate's
+//! own integrity layer: a pluggable [`IntegrityHasher`] (SHA-256 by default,
+//! or BLAKE3 with the `blake3` feature), digests that record which algorithm
+//! produced them, and parallel hashing of many files for verify and audit jobs
+//! over large archives. With the
+//! `parallel-hashing` feature, [`ParallelHasher`] runs those jobs on a rayon
+//! thread pool of configurable size.
+//!
+//! Hashing reads files with `std::fs`. Jobs over many files belong on a
+//! dedicated thread or tokio's blocking pool, not on an async worker.
+//!
+//! # Apple's checksum format
+//!
+//! Observed checksums are hex strings made of a one-byte type prefix followed
+//! by a 20-byte value (42 characters, e.g. `01a1b2...`). The value is a chunked
+//! storage signature computed over Apple's internal chunking of the file, not a
+//! SHA-1 of its bytes, so it cannot be derived locally. [`parse_checksum`]
+//! recognizes the rare checksums that are plain SHA-256 digests (64 hex
+//! characters, optionally behind a one-byte prefix), and [`verify_derivative`]
+//! checks downloads against those, falling back to the advertised file size for
+//! everything else. The [`VerifyConfidence`] in each report says which check
+//! was applied.
+
+use crate::models::Derivative;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Size of the buffer used when hashing files
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Incremental state of a hash computation
+pub trait Digest: Send {
+    /// Feeds more data into the hash
+    fn update(&mut self, data: &[u8]);
+    /// Completes the hash and returns its raw bytes
+    fn finalize(self: Box<Self>) -> Vec<u8>;
+}
+
+/// A hash algorithm usable for integrity checks
+pub trait IntegrityHasher: Send + Sync {
+    /// Name of the algorithm as recorded in digests (e.g. `"sha256"`)
+    fn algorithm(&self) -> &'static str;
+    /// Starts a new hash computation
+    fn new_digest(&self) -> Box<dyn Digest>;
+}
+
+/// A hash of some content, tagged with the algorithm that produced it
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct IntegrityDigest {
+    /// Name of the algorithm, as returned by [`IntegrityHasher::algorithm`]
+    pub algorithm: String,
+    /// Lowercase hexadecimal hash value
+    pub hex: String,
+}
+
+impl std::fmt::Display for IntegrityDigest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.algorithm, self.hex)
+    }
+}
+
+/// Builds a digest from raw hash bytes
+fn to_digest(hasher: &dyn IntegrityHasher, bytes: &[u8]) -> IntegrityDigest {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    IntegrityDigest {
+        algorithm: hasher.algorithm().to_string(),
+        hex,
+    }
+}
+
+/// Hashes an in-memory buffer
+pub fn hash_bytes(hasher: &dyn IntegrityHasher, data: &[u8]) -> IntegrityDigest {
+    let mut digest = hasher.new_digest();
+    digest.update(data);
+    to_digest(hasher, &digest.finalize())
+}
+
+/// Hashes everything readable from `reader`
+pub fn hash_reader(
+    hasher: &dyn IntegrityHasher,
+    reader: &mut dyn Read,
+) -> io::Result<IntegrityDigest> {
+    let mut digest = hasher.new_digest();
+    let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => digest.update(&buffer[..read]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(to_digest(hasher, &digest.finalize()))
+}
+
+/// Hashes a file
+pub fn hash_file(hasher: &dyn IntegrityHasher, path: &Path) -> io::Result<IntegrityDigest> {
+    let mut file = std::fs::File::open(path)?;
+    hash_reader(hasher, &mut file)
+}
+
+/// Checks a file against a previously recorded digest
+///
+/// # Returns
+///
+/// `Ok(true)` if the file matches, `Ok(false)` if it differs, and an error if
+/// the file cannot be read or `expected` was produced by a different algorithm
+pub fn verify_file(
+    hasher: &dyn IntegrityHasher,
+    path: &Path,
+    expected: &IntegrityDigest,
+) -> io::Result<bool> {
+    if expected.algorithm != hasher.algorithm() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Digest uses {}, but the hasher implements {}",
+                expected.algorithm,
+                hasher.algorithm()
+            ),
+        ));
+    }
+    Ok(hash_file(hasher, path)?.hex == expected.hex)
+}
+
+/// Hashes many files in parallel
+///
+/// Files are distributed over `threads` worker threads (at least one), which
+/// keeps both disks and CPUs busy when verifying large archives. Results are
+/// returned in the same order as `paths`.
+///
+/// # Arguments
+///
+/// * `hasher` - The hash algorithm to use
+/// * `paths` - The files to hash
+/// * `threads` - Number of worker threads
+///
+/// # Returns
+///
+/// One result per input path, in input order
+pub fn hash_files(
+    hasher: &dyn IntegrityHasher,
+    paths: &[PathBuf],
+    threads: usize,
+) -> Vec<io::Result<IntegrityDigest>> {
+    let results: Vec<Mutex<Option<io::Result<IntegrityDigest>>>> =
+        paths.iter().map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, paths.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = paths.get(index) else {
+                    break;
+                };
+                let result = hash_file(hasher, path);
+                *results[index].lock().unwrap_or_else(|e| e.into_inner()) = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|slot| {
+            slot.into_inner()
+                .unwrap_or_else(|e| e.into_inner())
+                .unwrap_or_else(|| Err(io::Error::other("File was not hashed")))
+        })
+        .collect()
+}
+
+/// A rayon thread pool for hashing and verifying large archives
+///
+/// Unlike [`hash_files`], which spawns its threads on every call, the pool is
+/// kept for the lifetime of the value, so verify and audit jobs that walk an
+/// archive in batches reuse the same workers. Files are read in blocks, so a
+/// pool larger than the number of CPUs can help on slow disks.
+#[cfg(feature = "parallel-hashing")]
+#[derive(Debug)]
+pub struct ParallelHasher {
+    pool: rayon::ThreadPool,
+}
+
+#[cfg(feature = "parallel-hashing")]
+impl ParallelHasher {
+    /// Create a pool with `threads` workers
+    ///
+    /// With `0`, rayon picks the number of threads: `RAYON_NUM_THREADS` if
+    /// set, otherwise one per CPU.
+    pub fn new(threads: usize) -> Result<Self, rayon::ThreadPoolBuildError> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|index| format!("integrity-hash-{}", index))
+            .build()?;
+        Ok(Self { pool })
+    }
+
+    /// Number of worker threads in the pool
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Hashes many files on the pool
+    ///
+    /// # Returns
+    ///
+    /// One result per input path, in input order
+    pub fn hash_files(
+        &self,
+        hasher: &dyn IntegrityHasher,
+        paths: &[PathBuf],
+    ) -> Vec<io::Result<IntegrityDigest>> {
+        use rayon::prelude::*;
+
+        self.pool.install(|| {
+            paths
+                .par_iter()
+                .map(|path| hash_file(hasher, path))
+                .collect()
+        })
+    }
+
+    /// Checks many files against previously recorded digests on the pool
+    ///
+    /// Each file is checked like [`verify_file`].
+    ///
+    /// # Returns
+    ///
+    /// One result per input file, in input order
+    pub fn verify_files(
+        &self,
+        hasher: &dyn IntegrityHasher,
+        files: &[(PathBuf, IntegrityDigest)],
+    ) -> Vec<io::Result<bool>> {
+        use rayon::prelude::*;
+
+        self.pool.install(|| {
+            files
+                .par_iter()
+                .map(|(path, expected)| verify_file(hasher, path, expected))
+                .collect()
+        })
+    }
+}
+
+/// What a derivative `checksum` string was recognized as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChecksumFormat {
+    /// A hash of the file's bytes that can be recomputed locally
+    Digest {
+        /// The one-byte type prefix, if the checksum has one
+        prefix: Option<u8>,
+        /// The hash the file must match
+        digest: IntegrityDigest,
+    },
+    /// An identifier that cannot be derived from the file
+    Opaque {
+        /// The one-byte type prefix, if the checksum is hex with one
+        prefix: Option<u8>,
+    },
+}
+
+/// Length in hex characters of a SHA-256 digest
+const SHA256_HEX_LEN: usize = 64;
+
+/// Length in hex characters of Apple's prefixed 20-byte signatures
+const SIGNATURE_HEX_LEN: usize = 2 + 40;
+
+/// Classifies a derivative `checksum` string
+///
+/// Only SHA-256 digests, bare or behind a one-byte type prefix, are
+/// recognized as derivable; Apple's usual prefixed 20-byte signatures and
+/// anything else are [`ChecksumFormat::Opaque`].
+pub fn parse_checksum(checksum: &str) -> ChecksumFormat {
+    let hex = checksum.trim().to_ascii_lowercase();
+    if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return ChecksumFormat::Opaque { prefix: None };
+    }
+    let prefix_of = |hex: &str| u8::from_str_radix(&hex[..2], 16).ok();
+    let digest = |hex: &str| IntegrityDigest {
+        algorithm: Sha256Hasher.algorithm().to_string(),
+        hex: hex.to_string(),
+    };
+    match hex.len() {
+        SHA256_HEX_LEN => ChecksumFormat::Digest {
+            prefix: None,
+            digest: digest(&hex),
+        },
+        len if len == SHA256_HEX_LEN + 2 => ChecksumFormat::Digest {
+            prefix: prefix_of(&hex),
+            digest: digest(&hex[2..]),
+        },
+        SIGNATURE_HEX_LEN => ChecksumFormat::Opaque {
+            prefix: prefix_of(&hex),
+        },
+        _ => ChecksumFormat::Opaque { prefix: None },
+    }
+}
+
+/// How strongly a verification result vouches for a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyConfidence {
+    /// Nothing could be checked: the checksum is opaque and no size is known
+    Unverified,
+    /// Only the file size was compared with the advertised size
+    Size,
+    /// The file's hash was compared with the checksum
+    Checksum,
+}
+
+/// Result of checking a downloaded file against its derivative
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DerivativeVerification {
+    /// Which check was applied
+    pub confidence: VerifyConfidence,
+    /// Whether the file passed it; always true when nothing was checked
+    pub ok: bool,
+    /// Why the file failed, if it did
+    pub mismatch: Option<String>,
+}
+
+/// Compares a computed digest and size with what the derivative advertises
+fn verification(
+    derivative: &Derivative,
+    hasher: &dyn IntegrityHasher,
+    size: u64,
+    digest: impl FnOnce() -> io::Result<IntegrityDigest>,
+) -> io::Result<DerivativeVerification> {
+    let mut result = DerivativeVerification {
+        confidence: VerifyConfidence::Unverified,
+        ok: true,
+        mismatch: None,
+    };
+    if let ChecksumFormat::Digest {
+        digest: expected, ..
+    } = parse_checksum(&derivative.checksum)
+    {
+        if expected.algorithm == hasher.algorithm() {
+            let actual = digest()?;
+            result.confidence = VerifyConfidence::Checksum;
+            if actual.hex != expected.hex {
+                result.ok = false;
+                result.mismatch = Some(format!("Expected {}, got {}", expected, actual));
+            }
+            return Ok(result);
+        }
+    }
+    if let Some(expected) = derivative.file_size {
+        result.confidence = VerifyConfidence::Size;
+        if size != expected {
+            result.ok = false;
+            result.mismatch = Some(format!("Expected {} bytes, got {}", expected, size));
+        }
+    }
+    Ok(result)
+}
+
+/// Checks downloaded bytes against a derivative's checksum or size
+///
+/// The checksum is used when [`parse_checksum`] finds a digest made by
+/// `hasher`'s algorithm; otherwise the advertised file size is compared.
+pub fn verify_derivative_bytes(
+    hasher: &dyn IntegrityHasher,
+    data: &[u8],
+    derivative: &Derivative,
+) -> DerivativeVerification {
+    verification(derivative, hasher, data.len() as u64, || {
+        Ok(hash_bytes(hasher, data))
+    })
+    .expect("hashing bytes cannot fail")
+}
+
+/// Checks a downloaded file against a derivative's checksum or size
+///
+/// See [`verify_derivative_bytes`]. The file is only hashed when the checksum
+/// is derivable.
+pub fn verify_derivative(
+    hasher: &dyn IntegrityHasher,
+    path: &Path,
+    derivative: &Derivative,
+) -> io::Result<DerivativeVerification> {
+    let size = std::fs::metadata(path)?.len();
+    verification(derivative, hasher, size, || hash_file(hasher, path))
+}
+
+/// SHA-256, the default integrity hash
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256Hasher;
+
+impl IntegrityHasher for Sha256Hasher {
+    fn algorithm(&self) -> &'static str {
+        "sha256"
+    }
+
+    fn new_digest(&self) -> Box<dyn Digest> {
+        Box::new(<sha2::Sha256 as sha2::Digest>::new())
+    }
+}
+
+impl Digest for sha2::Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        sha2::Digest::finalize(*self).to_vec()
+    }
+}
+
+/// BLAKE3, a faster hash for large archives
+///
+/// Digests are recorded as `blake3`, so files hashed with it are verified
+/// with it too. Only available with the `blake3` feature.
+#[cfg(feature = "blake3")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blake3Hasher;
+
+#[cfg(feature = "blake3")]
+impl IntegrityHasher for Blake3Hasher {
+    fn algorithm(&self) -> &'static str {
+        "blake3"
+    }
+
+    fn new_digest(&self) -> Box<dyn Digest> {
+        Box::new(blake3::Hasher::new())
+    }
+}
+
+#[cfg(feature = "blake3")]
+impl Digest for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        blake3::Hasher::finalize(&self).as_bytes().to_vec()
+    }
+}
@@ -0,0 +1,172 @@
+//! Write-ahead journal of in-progress downloads.
+//!
+//! A download is staged in a partial file and then renamed into place (see
+//! [`crate::download`]). A crash in between leaves a partial file behind, and
+//! one right after the rename leaves a complete file nobody recorded. With a
+//! [`DownloadJournal`] installed through
+//! [`crate::download::DownloadOptions::with_journal`], every file write is
+//! recorded before it starts and marked done once it is in place. On the next
+//! start, [`DownloadJournal::recover`] goes through the writes that never
+//! finished: it deletes their partial files, keeps final files of the expected
+//! size, and lists the photos to download again, without scanning directories.
+//!
+//! The journal is a JSON Lines file, appended to and flushed for every entry.
+//! Its methods are synchronous; the download functions call them on tokio's
+//! blocking pool, and recovery usually runs once before the first download.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A file write recorded in the journal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournaledWrite {
+    /// GUID of the photo being written
+    pub photo_guid: String,
+    /// Final path of the file
+    pub path: PathBuf,
+    /// Path of the partial file the content is staged in
+    pub partial: PathBuf,
+    /// Size of the complete file in bytes
+    pub expected_size: u64,
+}
+
+/// A line of the journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Entry {
+    /// A write is about to start
+    Begin(JournaledWrite),
+    /// The write to `path` finished, or was cleaned up after failing
+    End { path: PathBuf },
+}
+
+/// What [`DownloadJournal::recover`] found and did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JournalRecovery {
+    /// Writes whose final file is in place with the expected size
+    pub completed: Vec<JournaledWrite>,
+    /// Writes that didn't finish; their photos need to be downloaded again
+    pub incomplete: Vec<JournaledWrite>,
+    /// Partial files that were deleted
+    pub removed_partials: Vec<PathBuf>,
+}
+
+/// An append-only journal of the file writes of downloads
+#[derive(Debug)]
+pub struct DownloadJournal {
+    path: PathBuf,
+    file: Mutex<std::fs::File>,
+}
+
+impl DownloadJournal {
+    /// Opens the journal at `path`, creating it if needed
+    ///
+    /// Entries of a previous run are kept until [`DownloadJournal::recover`]
+    /// is called.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Path of the journal file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records that a write is about to start
+    ///
+    /// The entry is flushed to stable storage before this returns.
+    pub fn begin(&self, write: &JournaledWrite) -> io::Result<()> {
+        self.append(&Entry::Begin(write.clone()), true)
+    }
+
+    /// Records that the write to `path` finished or was cleaned up
+    pub fn end(&self, path: &Path) -> io::Result<()> {
+        self.append(
+            &Entry::End {
+                path: path.to_path_buf(),
+            },
+            false,
+        )
+    }
+
+    /// Returns the writes that were started and never ended
+    ///
+    /// A line cut short by a crash is ignored.
+    pub fn pending(&self) -> io::Result<Vec<JournaledWrite>> {
+        let _guard = self.lock();
+        let reader = io::BufReader::new(std::fs::File::open(&self.path)?);
+        let mut pending = BTreeMap::new();
+        for line in reader.lines() {
+            match serde_json::from_str(&line?) {
+                Ok(Entry::Begin(write)) => {
+                    pending.insert(write.path.clone(), write);
+                }
+                Ok(Entry::End { path }) => {
+                    pending.remove(&path);
+                }
+                Err(_) => continue,
+            }
+        }
+        Ok(pending.into_values().collect())
+    }
+
+    /// Cleans up after the writes that never finished, then empties the journal
+    ///
+    /// For each pending write, the partial file is deleted if it exists. A
+    /// final file of the expected size was renamed into place before the
+    /// crash and counts as completed; anything else is listed as incomplete.
+    /// Final files are never deleted.
+    pub fn recover(&self) -> io::Result<JournalRecovery> {
+        let mut recovery = JournalRecovery::default();
+        for write in self.pending()? {
+            match std::fs::remove_file(&write.partial) {
+                Ok(()) => recovery.removed_partials.push(write.partial.clone()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            let complete = std::fs::metadata(&write.path)
+                .is_ok_and(|metadata| metadata.is_file() && metadata.len() == write.expected_size);
+            if complete {
+                recovery.completed.push(write);
+            } else {
+                recovery.incomplete.push(write);
+            }
+        }
+
+        let file = self.lock();
+        file.set_len(0)?;
+        file.sync_all()?;
+        Ok(recovery)
+    }
+
+    /// Appends an entry, flushing it to stable storage if `sync` is set
+    fn append(&self, entry: &Entry, sync: bool) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry).map_err(io::Error::other)?;
+        line.push(b'\n');
+        let mut file = self.lock();
+        file.write_all(&line)?;
+        if sync {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Locks the journal file, recovering it if a holder panicked
+    fn lock(&self) -> std::sync::MutexGuard<'_, std::fs::File> {
+        self.file.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
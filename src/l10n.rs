@@ -0,0 +1,204 @@
+//! Localized strings and dates for generated output.
+//!
+//! Galleries and exports label their output in English by default. A
+//! [`Catalog`] translates those labels and formats dates for a locale, e.g.
+//! for family albums shared with relatives who don't read English:
+//!
+//! ```
+//! use icloud_album_rs::gallery::GalleryOptions;
+//! use icloud_album_rs::l10n::Catalog;
+//!
+//! let options = GalleryOptions::new().with_catalog(Catalog::builtin("de").unwrap());
+//! ```
+//!
+//! Catalogs are written in a subset of Fluent syntax: one `id = text` message
+//! per line, with `{ $name }` standing for an argument and `#` starting a
+//! comment. Catalogs for English, German, French, Spanish and Dutch are
+//! built in; [`Catalog::parse`] loads others, falling back to English for
+//! messages they leave out.
+//!
+//! Messages used by the crate:
+//!
+//! | Id | Arguments | English |
+//! |----|-----------|---------|
+//! | `photo-count`, `photo-count-one` | `count` | `{ $count } photos`, `1 photo` |
+//! | `page-of` | `page`, `count` | `Page { $page } of { $count }` |
+//! | `previous-page`, `next-page` | | `Previous`, `Next` |
+//! | `pages` | | `Pages` |
+//! | `photo`, `video` | | `Photo`, `Video` |
+//! | `taken`, `place`, `added-by` | | `Taken`, `Place`, `Added by` |
+//! | `view-album` | | `View the album` |
+//! | `date` | `day`, `month`, `year` | `{ $month } { $day }, { $year }` |
+//! | `month-1` to `month-12` | | `January` to `December` |
+
+use std::collections::HashMap;
+use std::fmt;
+use thiserror::Error;
+
+/// Built-in catalogs by language
+const BUILTIN: &[(&str, &str)] = &[
+    ("en", include_str!("l10n/en.ftl")),
+    ("de", include_str!("l10n/de.ftl")),
+    ("fr", include_str!("l10n/fr.ftl")),
+    ("es", include_str!("l10n/es.ftl")),
+    ("nl", include_str!("l10n/nl.ftl")),
+];
+
+/// Error returned when a catalog can't be parsed
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Invalid catalog line {line}: {message}")]
+pub struct CatalogError {
+    /// Line number, starting at 1
+    pub line: usize,
+    /// What is wrong with the line
+    pub message: String,
+}
+
+/// Translated messages of a locale
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Catalog {
+    locale: String,
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// Returns the built-in English catalog
+    pub fn english() -> Self {
+        Self {
+            locale: "en".to_string(),
+            messages: parse_messages(BUILTIN[0].1).expect("the English catalog is valid"),
+        }
+    }
+
+    /// Returns the built-in catalog of a locale, e.g. `de` or `fr-CA`
+    ///
+    /// Only the language of the locale is considered.
+    ///
+    /// # Returns
+    ///
+    /// The catalog, or `None` if the language has no built-in catalog
+    pub fn builtin(locale: &str) -> Option<Self> {
+        let language = locale
+            .split(['-', '_'])
+            .next()
+            .unwrap_or(locale)
+            .to_ascii_lowercase();
+        let (_, source) = BUILTIN.iter().find(|(code, _)| *code == language)?;
+        Self::parse(locale, source).ok()
+    }
+
+    /// Parses a catalog, falling back to English for missing messages
+    ///
+    /// # Arguments
+    ///
+    /// * `locale` - The locale the catalog is for, e.g. `pt-BR`
+    /// * `source` - The catalog, in the format described in the module documentation
+    pub fn parse(locale: &str, source: &str) -> Result<Self, CatalogError> {
+        let mut catalog = Self::english();
+        catalog.locale = locale.to_string();
+        catalog.messages.extend(parse_messages(source)?);
+        Ok(catalog)
+    }
+
+    /// Returns the locale of the catalog
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    /// Formats a message
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The message's id
+    /// * `args` - Values of the message's arguments, by name
+    ///
+    /// # Returns
+    ///
+    /// The message, or `id` itself if the catalog has no such message
+    pub fn message(&self, id: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+        let Some(message) = self.messages.get(id) else {
+            return id.to_string();
+        };
+        let mut output = String::with_capacity(message.len());
+        let mut rest = message.as_str();
+        while let Some(start) = rest.find('{') {
+            output.push_str(&rest[..start]);
+            let Some(end) = rest[start..].find('}') else {
+                rest = &rest[start..];
+                break;
+            };
+            let name = rest[start + 1..start + end].trim().trim_start_matches('$');
+            match args.iter().find(|(arg, _)| *arg == name) {
+                Some((_, value)) => output.push_str(&value.to_string()),
+                None => output.push_str(&rest[start..start + end + 1]),
+            }
+            rest = &rest[start + end + 1..];
+        }
+        output.push_str(rest);
+        output
+    }
+
+    /// Formats a message about `count` things, using `{id}-one` for a single one if present
+    pub fn count_message(&self, id: &str, count: usize) -> String {
+        let one = format!("{}-one", id);
+        let id = if count == 1 && self.messages.contains_key(&one) {
+            one.as_str()
+        } else {
+            id
+        };
+        self.message(id, &[("count", &count)])
+    }
+
+    /// Formats a date as written in the locale, e.g. `1. Juni 2023`
+    ///
+    /// # Arguments
+    ///
+    /// * `date` - A date as reported by iCloud, e.g. `2023-06-01T12:00:00Z`
+    ///
+    /// # Returns
+    ///
+    /// The formatted date, or `None` if `date` doesn't start with a valid
+    /// `YYYY-MM-DD` date
+    pub fn format_date(&self, date: &str) -> Option<String> {
+        let mut parts = date.get(..10)?.split('-');
+        let year: u32 = parts.next()?.parse().ok()?;
+        let month: u32 = parts.next()?.parse().ok()?;
+        let day: u32 = parts.next()?.parse().ok()?;
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+        let month_name = self.message(&format!("month-{}", month), &[]);
+        Some(self.message(
+            "date",
+            &[("day", &day), ("month", &month_name), ("year", &year)],
+        ))
+    }
+}
+
+/// Parses the `id = text` lines of a catalog
+fn parse_messages(source: &str) -> Result<HashMap<String, String>, CatalogError> {
+    let mut messages = HashMap::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| CatalogError {
+            line: index + 1,
+            message: message.to_string(),
+        };
+        let (id, text) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected `id = text`"))?;
+        let id = id.trim();
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(error("invalid message id"));
+        }
+        messages.insert(id.to_string(), text.trim().to_string());
+    }
+    Ok(messages)
+}
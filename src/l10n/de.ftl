@@ -0,0 +1,26 @@
+# Deutsch
+photo-count = { $count } Fotos
+photo-count-one = 1 Foto
+page-of = Seite { $page } von { $count }
+previous-page = Zurück
+next-page = Weiter
+pages = Seiten
+photo = Foto
+video = Video
+taken = Aufgenommen
+place = Ort
+added-by = Hinzugefügt von
+view-album = Album ansehen
+date = { $day }. { $month } { $year }
+month-1 = Januar
+month-2 = Februar
+month-3 = März
+month-4 = April
+month-5 = Mai
+month-6 = Juni
+month-7 = Juli
+month-8 = August
+month-9 = September
+month-10 = Oktober
+month-11 = November
+month-12 = Dezember
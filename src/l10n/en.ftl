@@ -0,0 +1,26 @@
+# English
+photo-count = { $count } photos
+photo-count-one = 1 photo
+page-of = Page { $page } of { $count }
+previous-page = Previous
+next-page = Next
+pages = Pages
+photo = Photo
+video = Video
+taken = Taken
+place = Place
+added-by = Added by
+view-album = View the album
+date = { $month } { $day }, { $year }
+month-1 = January
+month-2 = February
+month-3 = March
+month-4 = April
+month-5 = May
+month-6 = June
+month-7 = July
+month-8 = August
+month-9 = September
+month-10 = October
+month-11 = November
+month-12 = December
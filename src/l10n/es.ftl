@@ -0,0 +1,26 @@
+# Español
+photo-count = { $count } fotos
+photo-count-one = 1 foto
+page-of = Página { $page } de { $count }
+previous-page = Anterior
+next-page = Siguiente
+pages = Páginas
+photo = Foto
+video = Vídeo
+taken = Tomada
+place = Lugar
+added-by = Añadida por
+view-album = Ver el álbum
+date = { $day } de { $month } de { $year }
+month-1 = enero
+month-2 = febrero
+month-3 = marzo
+month-4 = abril
+month-5 = mayo
+month-6 = junio
+month-7 = julio
+month-8 = agosto
+month-9 = septiembre
+month-10 = octubre
+month-11 = noviembre
+month-12 = diciembre
@@ -0,0 +1,26 @@
+# Français
+photo-count = { $count } photos
+photo-count-one = 1 photo
+page-of = Page { $page } sur { $count }
+previous-page = Précédente
+next-page = Suivante
+pages = Pages
+photo = Photo
+video = Vidéo
+taken = Prise le
+place = Lieu
+added-by = Ajoutée par
+view-album = Voir l'album
+date = { $day } { $month } { $year }
+month-1 = janvier
+month-2 = février
+month-3 = mars
+month-4 = avril
+month-5 = mai
+month-6 = juin
+month-7 = juillet
+month-8 = août
+month-9 = septembre
+month-10 = octobre
+month-11 = novembre
+month-12 = décembre
@@ -0,0 +1,26 @@
+# Nederlands
+photo-count = { $count } foto's
+photo-count-one = 1 foto
+page-of = Pagina { $page } van { $count }
+previous-page = Vorige
+next-page = Volgende
+pages = Pagina's
+photo = Foto
+video = Video
+taken = Gemaakt
+place = Plaats
+added-by = Toegevoegd door
+view-album = Album bekijken
+date = { $day } { $month } { $year }
+month-1 = januari
+month-2 = februari
+month-3 = maart
+month-4 = april
+month-5 = mei
+month-6 = juni
+month-7 = juli
+month-8 = augustus
+month-9 = september
+month-10 = oktober
+month-11 = november
+month-12 = december
@@ -0,0 +1,173 @@
+//! Albums whose photo URLs are resolved on first access.
+//!
+//! [`crate::get_icloud_photos`] resolves the URLs of every photo up front,
+//! which costs webasseturls requests even when a user only browses captions
+//! and dates. A [`LazyAlbum`] fetches the metadata once and resolves URLs only
+//! when a photo, or a page of photos, is requested. Resolved URLs are kept in
+//! an [`AssetUrlCache`], so repeated access is free until the URLs expire.
+
+use crate::api::ApiError;
+use crate::cache::{self, AssetUrlCache};
+use crate::enrich;
+use crate::models::{Image, Metadata};
+use crate::observer::NoopObserver;
+use std::ops::Range;
+
+/// A fetched album that resolves derivative URLs on demand
+///
+/// The album holds its own HTTP client and the album's API base URL. Access
+/// methods take `&self`, so the album can be shared between tasks by
+/// reference.
+#[derive(Debug)]
+pub struct LazyAlbum {
+    client: reqwest::Client,
+    base_url: String,
+    metadata: Metadata,
+    photos: Vec<Image>,
+    cache: AssetUrlCache,
+}
+
+impl LazyAlbum {
+    /// Fetches the metadata and photos of an album, without resolving URLs
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The iCloud shared album token
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the album on success, or an error on failure
+    pub async fn fetch(token: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::fetch_with_client(token, reqwest::Client::new()).await
+    }
+
+    /// Fetches an album like [`LazyAlbum::fetch`], sending requests through `client`
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The iCloud shared album token
+    /// * `client` - The client used for the fetch and later URL resolution
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the album on success, or an error on failure
+    pub async fn fetch_with_client(
+        token: &str,
+        client: reqwest::Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (base_url, photos, metadata) =
+            crate::fetch_webstream(&client, token, &NoopObserver).await?;
+        Ok(Self::new(client, &base_url, metadata, photos))
+    }
+
+    /// Create an album from already fetched data
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The client used to resolve URLs
+    /// * `base_url` - The (redirected) base URL for API requests
+    /// * `metadata` - The album's metadata
+    /// * `photos` - The album's photos
+    pub fn new(
+        client: reqwest::Client,
+        base_url: &str,
+        metadata: Metadata,
+        mut photos: Vec<Image>,
+    ) -> Self {
+        enrich::enrich_photos_with_locations(&mut photos, &metadata.photo_locations());
+        Self {
+            client,
+            base_url: base_url.to_string(),
+            metadata,
+            photos,
+            cache: AssetUrlCache::default(),
+        }
+    }
+
+    /// Use `cache` for resolved URLs instead of a default one
+    #[must_use]
+    pub fn with_cache(mut self, cache: AssetUrlCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Returns the album's metadata
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Returns the album's photos, without derivative URLs
+    pub fn photos(&self) -> &[Image] {
+        &self.photos
+    }
+
+    /// Returns the number of photos in the album
+    pub fn len(&self) -> usize {
+        self.photos.len()
+    }
+
+    /// Returns true if the album has no photos
+    pub fn is_empty(&self) -> bool {
+        self.photos.is_empty()
+    }
+
+    /// Returns copies of the given photos with their derivative URLs resolved
+    async fn resolved(&self, photos: impl Iterator<Item = &Image>) -> Result<Vec<Image>, ApiError> {
+        let mut photos: Vec<Image> = photos.cloned().collect();
+        cache::enrich_photos_cached(&self.client, &self.base_url, &mut photos, &self.cache).await?;
+        Ok(photos)
+    }
+
+    /// Returns a photo with its derivative URLs resolved
+    ///
+    /// # Arguments
+    ///
+    /// * `photo_guid` - GUID of the photo
+    ///
+    /// # Returns
+    ///
+    /// The photo, or `None` if the album has no photo with this GUID
+    pub async fn photo(&self, photo_guid: &str) -> Result<Option<Image>, ApiError> {
+        let found = self
+            .photos
+            .iter()
+            .filter(|photo| photo.photo_guid == photo_guid);
+        Ok(self.resolved(found).await?.pop())
+    }
+
+    /// Returns several photos with their URLs resolved in one batch
+    ///
+    /// # Arguments
+    ///
+    /// * `photo_guids` - GUIDs of the photos
+    ///
+    /// # Returns
+    ///
+    /// The photos found, in album order
+    pub async fn photos_by_guid(&self, photo_guids: &[&str]) -> Result<Vec<Image>, ApiError> {
+        self.resolved(
+            self.photos
+                .iter()
+                .filter(|photo| photo_guids.contains(&photo.photo_guid.as_str())),
+        )
+        .await
+    }
+
+    /// Returns a page of photos with their URLs resolved in one batch
+    ///
+    /// The range is clamped to the album, so paging past the end returns fewer
+    /// (or no) photos.
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - Indices of the photos, in album order
+    ///
+    /// # Returns
+    ///
+    /// The photos in the range
+    pub async fn page(&self, range: Range<usize>) -> Result<Vec<Image>, ApiError> {
+        let end = range.end.min(self.photos.len());
+        let start = range.start.min(end);
+        self.resolved(self.photos[start..end].iter()).await
+    }
+}
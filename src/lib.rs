@@ -17,26 +17,221 @@
 //! ```
 //!
 //! The library logs warnings for non-critical issues, such as type inconsistencies
-//! in API responses, and errors for more serious problems.
+//! in API responses, and errors for more serious problems. Records of an album
+//! fetch or download pass are prefixed with the ID of that operation, see
+//! [`logging::with_operation_id`].
+
+#![warn(clippy::return_self_not_must_use, clippy::unwrap_used)]
+
+// Modules whose own docs link to their items have no outer doc comment here,
+// as rustdoc would then resolve those links from the crate root.
+
+pub mod logging;
 
 /// Module containing data model structures
 pub mod models;
 
-/// Module handling the base URL generation for API calls
 pub mod base_url;
 
-/// Module handling redirects from the iCloud API
 pub mod redirect;
 
 /// Module for API calls to fetch metadata and photos
 pub mod api;
 
-/// Module for enriching photos with their URLs
+pub mod schema;
+
+pub mod check;
+
+pub mod diagnose;
+
+pub mod diagnostics;
+
+pub mod redact;
+
+pub mod har;
+
+pub mod transport;
+
+pub mod url_policy;
+
+pub mod net;
+
 pub mod enrich;
 
+pub mod download;
+
+pub mod predownload;
+
+pub mod postprocess;
+
+pub mod throttle;
+
+pub mod journal;
+
+pub mod packfile;
+
+pub mod zip_stream;
+
+pub mod asset_response;
+
+pub mod access;
+
+pub mod quota;
+
+#[cfg(feature = "web-ui")]
+pub mod web_ui;
+
+pub mod sync;
+
+pub mod state_store;
+
+pub mod selection;
+
+pub mod session;
+
+pub mod integrity;
+
+pub mod snapshot;
+
+pub mod views;
+
+/// Module for repairing file extensions in existing download directories
+pub mod fixup;
+
+/// Module providing a short-lived cache for asset URL lookups
+pub mod cache;
+
+pub mod lazy;
+
+pub mod refresh;
+
+pub mod prefetch;
+
 /// Module containing utility functions for file handling
 pub mod utils;
 
+pub mod observer;
+
+pub mod metrics;
+
+pub mod fetch;
+
+pub mod config;
+
+pub mod album;
+
+pub mod shutdown;
+
+/// Module describing known derivative keys and their size classes
+pub mod derivatives;
+
+pub mod compact;
+
+pub mod search;
+
+pub mod merge;
+
+pub mod analysis;
+
+pub mod export;
+
+pub mod gallery;
+
+pub mod markdown;
+
+/// Module writing Google Takeout compatible metadata files
+pub mod takeout;
+
+pub mod media_library;
+
+pub mod apple_photos;
+
+pub mod slideshow;
+
+pub mod watch;
+
+pub mod album_config;
+
+pub mod app_config;
+
+pub mod scheduler;
+
+pub mod sinks;
+
+pub mod update;
+
+/// Module writing provenance extended attributes on downloaded files
+#[cfg(all(feature = "xattr", any(target_os = "linux", target_os = "macos")))]
+pub mod xattr;
+
+#[cfg(feature = "import")]
+pub mod import;
+
+#[cfg(feature = "phash")]
+pub mod phash;
+
+#[cfg(feature = "placeholders")]
+pub mod placeholder;
+
+#[cfg(feature = "resize")]
+pub mod resize;
+
+/// Module shimming std APIs newer than the minimum supported Rust version
+mod compat;
+
+/// Module parsing binary PGM and PPM images
+#[cfg(any(feature = "phash", feature = "placeholders"))]
+mod pnm;
+
+#[cfg(any(feature = "phash", feature = "placeholders"))]
+pub mod codec;
+
+#[cfg(feature = "transcode")]
+pub mod transcode;
+
+#[cfg(feature = "album-cache")]
+pub mod album_cache;
+
+#[cfg(feature = "fault-injection")]
+pub mod faults;
+
+#[cfg(feature = "simulator")]
+pub mod simulator;
+
+#[cfg(feature = "server")]
+pub mod server;
+
+#[cfg(any(feature = "simulator", feature = "server"))]
+mod local_http;
+
+#[cfg(feature = "cookies")]
+pub mod cookies;
+
+#[cfg(feature = "compression")]
+pub mod compression;
+
+#[cfg(feature = "sqlite")]
+pub mod metadata_index;
+
+#[cfg(feature = "l10n")]
+pub mod l10n;
+
+#[cfg(feature = "qr-code")]
+pub mod qr;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+/// Module exposing the fetch and download surface to Node.js
+#[cfg(feature = "napi")]
+pub mod node;
+
+#[cfg(feature = "uniffi")]
+pub mod mobile;
+
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
+
 /// Main entry point for fetching photos from an iCloud shared album
 ///
 /// This function orchestrates the entire process of:
@@ -44,7 +239,14 @@ pub mod utils;
 /// 2. Handling any redirects
 /// 3. Fetching the album metadata and photos
 /// 4. Fetching the URLs for all photos
-/// 5. Enriching the photos with their URLs
+/// 5. Enriching the photos with their URLs and locations
+///
+/// Place names can be added afterwards with [`enrich::geocode_photos`]. To
+/// configure retries, parsing, URL resolution or filtering, use
+/// [`get_icloud_photos_with`].
+///
+/// When the token's host does not redirect, the body of the redirect check is
+/// used as the metadata response, so only one webstream request is made.
 ///
 /// # Arguments
 ///
@@ -56,29 +258,463 @@ pub mod utils;
 pub async fn get_icloud_photos(
     token: &str,
 ) -> Result<models::ICloudResponse, Box<dyn std::error::Error>> {
-    // Create a reqwest client
+    get_icloud_photos_with(token, &fetch::FetchOptions::new()).await
+}
+
+/// Fetches photos from an iCloud shared album with the given options
+///
+/// With default options, this behaves exactly like [`get_icloud_photos`]. The
+/// options choose the client and starting URL, retries of the webstream and
+/// webasseturls endpoints, whether photos that fail to parse fail the fetch,
+/// whether asset URLs are resolved, which photos are kept (before any URLs are
+/// resolved) and the observer to notify.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use icloud_album_rs::fetch::{FetchOptions, ParseMode};
+///
+/// // One contributor's photos without URLs, failing on photos that cannot be parsed
+/// let options = FetchOptions::new()
+///     .with_parse_mode(ParseMode::Strict)
+///     .with_resolve_urls(false)
+///     .with_filter(|photo| photo.contributor_full_name.as_deref() == Some("Jane Doe"));
+/// let response = icloud_album_rs::get_icloud_photos_with("B0z5qAGN1JIFd3y", &options).await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Arguments
+///
+/// * `token` - The iCloud shared album token
+/// * `options` - How to fetch the album
+///
+/// # Returns
+///
+/// A Result containing an ICloudResponse with metadata and photos on success, or an error on failure
+pub async fn get_icloud_photos_with(
+    token: &str,
+    options: &fetch::FetchOptions,
+) -> Result<models::ICloudResponse, Box<dyn std::error::Error>> {
+    let observer = options
+        .observer
+        .as_deref()
+        .unwrap_or(&observer::NoopObserver);
+    fetch_selected(token, options, observer, |photos| photos).await
+}
+
+/// Fetches an album as [`get_icloud_photos_with`] does, notifying `observer`
+/// and passing the photos kept by `options` through `select`
+pub(crate) async fn fetch_selected<F>(
+    token: &str,
+    options: &fetch::FetchOptions,
+    observer: &dyn observer::PipelineObserver,
+    select: F,
+) -> Result<models::ICloudResponse, Box<dyn std::error::Error>>
+where
+    F: FnOnce(Vec<models::Image>) -> Vec<models::Image>,
+{
+    let client = client_for(options)?;
+    fetch_album(
+        &client,
+        options.base_url.as_deref(),
+        token,
+        observer,
+        |photos| select(select_with(options, photos)),
+        options,
+    )
+    .await
+}
+
+/// Fetches the metadata and photos of an iCloud shared album without their URLs
+///
+/// This skips the webasseturls request, which is the largest part of a fetch,
+/// for consumers that only need counts, captions, dates or dimensions. The
+/// derivatives of the returned photos have no `url`. It is a shorthand for
+/// [`get_icloud_photos_with`] with [`fetch::FetchOptions::with_resolve_urls`]
+/// set to `false`.
+///
+/// # Arguments
+///
+/// * `token` - The iCloud shared album token
+///
+/// # Returns
+///
+/// A Result containing an ICloudResponse with metadata and photos on success, or an error on failure
+pub async fn get_album_metadata(
+    token: &str,
+) -> Result<models::ICloudResponse, Box<dyn std::error::Error>> {
+    get_icloud_photos_with(token, &fetch::FetchOptions::new().with_resolve_urls(false)).await
+}
+
+/// Fetches photos from an iCloud shared album identified by an [`album::AlbumRef`]
+///
+/// This behaves like [`get_icloud_photos_with`], but starts from the album's
+/// resolved base URL when it is known, skipping the partition probe, and
+/// records the base URL the fetch was redirected to in `album` for the next
+/// call. A `base_url` in `options` takes precedence over the resolved one.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// use icloud_album_rs::album::AlbumRef;
+/// use icloud_album_rs::fetch::FetchOptions;
+///
+/// let mut album = AlbumRef::parse("https://www.icloud.com/sharedalbum/#B0z5qAGN1JIFd3y")?
+///     .with_alias("Family");
+/// let options = FetchOptions::new();
+/// let first = icloud_album_rs::get_album_photos(&mut album, &options).await?;
+/// // Goes straight to the host the first fetch was redirected to
+/// let second = icloud_album_rs::get_album_photos(&mut album, &options).await?;
+/// println!("{}: {} photos", album, second.photos.len());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Arguments
+///
+/// * `album` - The album to fetch, updated with its resolved base URL
+/// * `options` - How to fetch the album
+///
+/// # Returns
+///
+/// A Result containing an ICloudResponse with metadata and photos on success, or an error on failure
+pub async fn get_album_photos(
+    album: &mut album::AlbumRef,
+    options: &fetch::FetchOptions,
+) -> Result<models::ICloudResponse, Box<dyn std::error::Error>> {
+    let client = client_for(options)?;
+    let observer = options
+        .observer
+        .as_deref()
+        .unwrap_or(&observer::NoopObserver);
+    let base_url = options
+        .base_url
+        .as_deref()
+        .or_else(|| album.resolved_base_url());
+    let (redirected_url, response) = fetch_album_from(
+        &client,
+        base_url,
+        album.token(),
+        observer,
+        |photos| select_with(options, photos),
+        options,
+    )
+    .await?;
+    album.set_resolved(redirected_url);
+    Ok(response)
+}
+
+/// Returns the client to fetch with: one enforcing the URL policy of `options`,
+/// the client of `options`, or a default one
+fn client_for(
+    options: &fetch::FetchOptions,
+) -> Result<reqwest::Client, Box<dyn std::error::Error>> {
+    Ok(match (&options.url_policy, &options.client) {
+        (Some(policy), _) => policy.build_client()?,
+        (None, Some(client)) => client.clone(),
+        (None, None) => reqwest::Client::new(),
+    })
+}
+
+/// Keeps the photos chosen by the filter and selection of `options`, trimmed
+/// if it asks for compact photos
+fn select_with(options: &fetch::FetchOptions, photos: Vec<models::Image>) -> Vec<models::Image> {
+    let photos = match &options.filter {
+        Some(filter) => photos.into_iter().filter(|photo| filter(photo)).collect(),
+        None => photos,
+    };
+    let photos = match &options.selection {
+        Some(selection) => selection(photos),
+        None => photos,
+    };
+    match options.compact {
+        Some(_) => photos.into_iter().map(compact::trim_photo).collect(),
+        None => photos,
+    }
+}
+
+/// Fetches the thumbnail URL of every photo in an iCloud shared album
+///
+/// For each photo, the URL of its smallest still derivative is resolved, which
+/// is what gallery grids need. URLs are resolved in batches, see
+/// [`enrich::resolve_thumbnail_urls`].
+///
+/// # Arguments
+///
+/// * `token` - The iCloud shared album token
+///
+/// # Returns
+///
+/// A Result containing the thumbnail URLs keyed by photo GUID, or an error on failure
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let thumbnails = icloud_album_rs::get_photo_thumbnails("B0z5qAGN1JIFd3y").await?;
+/// for (guid, url) in &thumbnails {
+///     println!("{}: {}", guid, url);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn get_photo_thumbnails(
+    token: &str,
+) -> Result<std::collections::HashMap<String, String>, Box<dyn std::error::Error>> {
+    let client = reqwest::Client::new();
+    let (redirected_url, photos, _) =
+        fetch_webstream(&client, token, &observer::NoopObserver).await?;
+    Ok(enrich::resolve_thumbnail_urls(&client, &redirected_url, &photos).await?)
+}
+
+/// Fetches the current `streamCtag` of an iCloud shared album
+///
+/// The tag changes whenever photos are added, removed or edited, so pollers can
+/// call this and only run [`get_icloud_photos`] when it differs from the tag of
+/// their last fetch. Photos in the response are not parsed, and when the album
+/// host does not redirect, a single request is made.
+///
+/// # Arguments
+///
+/// * `token` - The iCloud shared album token
+///
+/// # Returns
+///
+/// A Result containing the album's `streamCtag`, or an error on failure
+pub async fn get_stream_ctag(
+    token: &str,
+) -> Result<models::StreamCtag, Box<dyn std::error::Error>> {
     let client = reqwest::Client::new();
+    let probe = redirect::probe_album(&client, token, &models::WebstreamRequest::default()).await?;
+    let redirected_url = probe.base_url().to_string();
+    match probe.into_body() {
+        Some(body) => match body.get("streamCtag").and_then(|ctag| ctag.as_str()) {
+            Some(ctag) => Ok(models::StreamCtag::new(ctag)),
+            None => Err(api::ApiError::MissingFieldError("streamCtag".to_string()).into()),
+        },
+        None => Ok(api::get_stream_ctag_at(&client, &redirected_url).await?),
+    }
+}
+
+/// Runs the fetch pipeline, resolving URLs only for the photos chosen by `select`
+///
+/// Starts from `base_url`, or from the token's partitions when `None`. The
+/// retry, parse mode and URL resolution settings are taken from `options`.
+async fn fetch_album<F>(
+    client: &reqwest::Client,
+    base_url: Option<&str>,
+    token: &str,
+    observer: &dyn observer::PipelineObserver,
+    select: F,
+    options: &fetch::FetchOptions,
+) -> Result<models::ICloudResponse, Box<dyn std::error::Error>>
+where
+    F: FnOnce(Vec<models::Image>) -> Vec<models::Image>,
+{
+    let (_, response) =
+        fetch_album_from(client, base_url, token, observer, select, options).await?;
+    Ok(response)
+}
+
+/// Runs the fetch pipeline like [`fetch_album`]
+///
+/// # Returns
+///
+/// The redirected base URL the album was fetched from, and the response
+async fn fetch_album_from<F>(
+    client: &reqwest::Client,
+    base_url: Option<&str>,
+    token: &str,
+    observer: &dyn observer::PipelineObserver,
+    select: F,
+    options: &fetch::FetchOptions,
+) -> Result<(String, models::ICloudResponse), Box<dyn std::error::Error>>
+where
+    F: FnOnce(Vec<models::Image>) -> Vec<models::Image>,
+{
+    logging::in_operation(async move {
+        // 1-3. Resolve the album host and fetch the metadata and photos
+        let webstream_retry = match &options.retry {
+            Some(policies) => policies.for_endpoint(api::Endpoint::Webstream).clone(),
+            None => single_attempt(),
+        };
+        // Hardened fetches start from the token's own partition, checked against the policy
+        let policy_base_url = match &options.url_policy {
+            Some(policy) => {
+                url_policy::validate_token(token)?;
+                let base_url = match base_url {
+                    Some(base_url) => base_url.to_string(),
+                    None => base_url::get_base_url(token)?,
+                };
+                policy.validate(&base_url)?;
+                Some(base_url)
+            }
+            None => None,
+        };
+        let base_url = policy_base_url.as_deref().or(base_url);
+        let (redirected_url, photos, metadata, report) =
+            fetch_webstream_at(client, base_url, token, observer, &webstream_retry).await?;
+        if let (Some(policy), Some(base_url)) = (&options.url_policy, base_url) {
+            if redirected_url != base_url {
+                // The redirect host comes from the response body, so it must stay a bare host
+                let parsed = policy.validate(&redirected_url)?;
+                if parsed.path() != format!("/{}/sharedstreams/", token) {
+                    return Err(url_policy::UrlPolicyError::Malformed(redirected_url).into());
+                }
+            }
+        }
+        if options.parse_mode == fetch::ParseMode::Strict {
+            if let Some((index, message)) = report.skipped_photos.first() {
+                return Err(api::ApiError::JsonParseError(format!(
+                    "{} photo(s) failed to parse, first photos[{}]: {}",
+                    report.skipped_photos.len(),
+                    index,
+                    message
+                ))
+                .into());
+            }
+        }
+
+        // 4. Select the photos to resolve
+        let mut photos = select(photos);
+
+        // 5. Fetch the URLs of the selected photos and enrich them
+        if options.resolve_urls {
+            let resolved = if photos.is_empty() {
+                0
+            } else {
+                let photo_guids: Vec<String> =
+                    photos.iter().map(|p| p.photo_guid.clone()).collect();
+                let retry_config = match &options.retry {
+                    Some(policies) => policies.for_endpoint(api::Endpoint::WebAssetUrls).clone(),
+                    None => single_attempt(),
+                };
+                let urls = api::get_asset_urls_with_policy(
+                    client,
+                    &redirected_url,
+                    &photo_guids,
+                    retry_config,
+                    &options.url_policy.clone().unwrap_or_default(),
+                )
+                .await?;
+                enrich::enrich_photos_with_urls(&mut photos, &urls);
+                urls.len()
+            };
+            observer.on_urls_resolved(resolved);
+        }
+
+        // 6. Enrich the photos with their locations
+        enrich::enrich_photos_with_locations(&mut photos, &metadata.photo_locations());
+
+        // 7. Return the final response, keeping the items that failed to parse
+        Ok((
+            redirected_url,
+            models::ICloudResponse {
+                metadata,
+                photos,
+                unparsed: report.unparsed_items,
+            },
+        ))
+    })
+    .await
+}
+
+/// Fetches the metadata and photos of an album without resolving any URLs
+///
+/// # Returns
+///
+/// The redirected base URL for further API requests, the photos and the metadata
+pub(crate) async fn fetch_webstream(
+    client: &reqwest::Client,
+    token: &str,
+    observer: &dyn observer::PipelineObserver,
+) -> Result<(String, Vec<models::Image>, models::Metadata), Box<dyn std::error::Error>> {
+    let (redirected_url, photos, metadata, _report) =
+        fetch_webstream_at(client, None, token, observer, &single_attempt()).await?;
+    Ok((redirected_url, photos, metadata))
+}
 
-    // 1. Compute the base URL from the token
-    let base_url = base_url::get_base_url(token)?;
+/// Fetches the metadata and photos of an album starting from `base_url`
+///
+/// Without a base URL, the partitions of the token are tried, see
+/// [`redirect::probe_album`]. A webstream request made after a redirect is
+/// retried according to `retry_config`.
+///
+/// # Returns
+///
+/// The redirected base URL for further API requests, the photos, the metadata
+/// and the parse report
+async fn fetch_webstream_at(
+    client: &reqwest::Client,
+    base_url: Option<&str>,
+    token: &str,
+    observer: &dyn observer::PipelineObserver,
+    retry_config: &api::RetryConfig,
+) -> Result<
+    (
+        String,
+        Vec<models::Image>,
+        models::Metadata,
+        api::ParseReport,
+    ),
+    Box<dyn std::error::Error>,
+> {
+    let request = models::WebstreamRequest::default();
+    let probe = match base_url {
+        Some(base_url) => {
+            observer.on_base_url(base_url);
 
-    // 2. Handle any redirects
-    let redirected_url = redirect::get_redirected_base_url(&client, &base_url, token).await?;
+            // 2. Handle any redirects
+            redirect::probe_webstream(client, base_url, token, &request).await?
+        }
+        None => {
+            // 1. Compute the base URL from the token
+            observer.on_base_url(&base_url::get_base_url(token)?);
 
-    // 3. Fetch the metadata and photos
-    let (mut photos, metadata) = api::get_api_response(&client, &redirected_url).await?;
+            // 2. Handle any redirects, trying candidate partitions
+            redirect::probe_album(client, token, &request).await?
+        }
+    };
+    let redirected_url = probe.base_url().to_string();
+    observer.on_redirect(&redirected_url);
 
-    // 4. Extract all photo GUIDs
-    let photo_guids: Vec<String> = photos.iter().map(|p| p.photo_guid.clone()).collect();
+    // 3. Fetch the metadata and photos, reusing the probe's body when it was not redirected
+    let api::WebstreamResponse {
+        photos,
+        metadata,
+        report,
+    } = webstream_from_probe(client, probe, &request, retry_config).await?;
+    if let Some(headers) = &report.response_headers {
+        observer.on_response_headers(headers);
+    }
+    observer.on_metadata(&metadata);
+    observer.on_photos_parsed(photos.len());
 
-    // 5. Fetch the URLs for all photos
-    let all_urls = api::get_asset_urls(&client, &redirected_url, &photo_guids).await?;
+    Ok((redirected_url, photos, metadata, report))
+}
 
-    // 6. Enrich the photos with their URLs
-    enrich::enrich_photos_with_urls(&mut photos, &all_urls);
+/// Parses the webstream body kept by `probe`, or fetches it from the probe's base URL
+async fn webstream_from_probe(
+    client: &reqwest::Client,
+    probe: redirect::WebstreamProbe,
+    request: &models::WebstreamRequest,
+    retry_config: &api::RetryConfig,
+) -> Result<api::WebstreamResponse, Box<dyn std::error::Error>> {
+    let base_url = probe.base_url().to_string();
+    let headers = probe.response_headers().cloned();
+    Ok(match probe.into_body() {
+        Some(body) => {
+            let mut response = api::parse_webstream_response(&body)?;
+            response.report.response_headers = headers;
+            response
+        }
+        None => api::get_api_response_with_retry(client, &base_url, request, retry_config).await?,
+    })
+}
 
-    // 7. Return the final response
-    Ok(models::ICloudResponse { metadata, photos })
+/// Retry configuration making a single attempt
+fn single_attempt() -> api::RetryConfig {
+    api::RetryConfig {
+        max_retries: 1,
+        ..Default::default()
+    }
 }
 
 /// Downloads a single photo or video from a shared album
@@ -105,62 +741,53 @@ pub async fn download_photo(
     output_dir: &str,
     custom_filename: Option<String>,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    // Create a client for downloading
-    let client = reqwest::Client::new();
-
-    // Select the best derivative
-    let best_derivative = utils::select_best_derivative(&photo.derivatives)
-        .ok_or_else(|| "No suitable derivative found for download".to_string())?;
-
-    // Extract components - we only need the URL
-    let (_key, _derivative, url) = best_derivative;
-
-    // Download the file content
-    let response = client.get(&url).send().await?;
-    let content = response.bytes().await?;
-
-    // Get content type and appropriate extension
-    let extension = utils::get_extension_for_content(&content, None);
-
-    // Create the directory if it doesn't exist (using async tokio fs)
-    if tokio::fs::metadata(output_dir).await.is_err() {
-        tokio::fs::create_dir_all(output_dir).await?;
-    }
-
-    // Determine base filename
-    let base_filename = if let Some(custom_name) = custom_filename {
-        // Always include the photo_guid for uniqueness even with custom filenames
-        format!("{}_{}", photo.photo_guid, custom_name)
-    } else if let Some(caption) = &photo.caption {
-        // Sanitize the caption for use as a filename - simplified version
-        let sanitized = caption
-            .chars()
-            .map(|c| match c {
-                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
-                _ => c,
-            })
-            .collect::<String>();
-
-        if let Some(idx) = index {
-            format!("{}_{}_{}", idx + 1, photo.photo_guid, sanitized)
-        } else {
-            format!("{}_{}", photo.photo_guid, sanitized)
-        }
-    } else if let Some(idx) = index {
-        format!("{}_{}", idx + 1, photo.photo_guid)
-    } else {
-        photo.photo_guid.clone()
-    };
-
-    // Combine with extension
-    let filename = format!("{}{}", base_filename, extension);
-    let filepath = format!("{}/{}", output_dir, filename);
-
-    // Write the file using async I/O
-    let mut file = tokio::fs::File::create(&filepath).await?;
-    tokio::io::copy(&mut content.as_ref(), &mut file).await?;
+    download::download_photo_with_options(
+        photo,
+        index,
+        output_dir,
+        custom_filename,
+        &download::DownloadOptions::new(),
+    )
+    .await
+}
 
-    Ok(filepath)
+/// Downloads a photo unless the server reports it unchanged
+///
+/// This behaves like [`download_photo`], but sends `If-None-Match` and
+/// `If-Modified-Since` headers built from `validators`. If the server answers
+/// `304 Not Modified`, nothing is transferred or written. Otherwise the file is
+/// saved and the validators of the new response are returned, to be stored and
+/// passed in on the next call (e.g. in a periodic verify-and-repair job).
+/// The body is streamed to disk; to bound memory or change how the file is
+/// written, use [`download::download_photo_if_changed_with_options`].
+///
+/// # Arguments
+///
+/// * `photo` - The photo to download
+/// * `index` - Optional index for numbering purposes (useful in loops)
+/// * `output_dir` - Directory where the file should be saved
+/// * `custom_filename` - Optional custom filename to use (without extension)
+/// * `validators` - Validators from a previous download, or empty for an unconditional download
+///
+/// # Returns
+///
+/// A Result containing whether the file was downloaded or confirmed unchanged
+pub async fn download_photo_if_changed(
+    photo: &models::Image,
+    index: Option<usize>,
+    output_dir: &str,
+    custom_filename: Option<String>,
+    validators: &models::HttpValidators,
+) -> Result<models::DownloadOutcome, Box<dyn std::error::Error>> {
+    download::download_photo_if_changed_with_options(
+        photo,
+        index,
+        output_dir,
+        custom_filename,
+        validators,
+        &download::DownloadOptions::new(),
+    )
+    .await
 }
 
 #[cfg(test)]
@@ -0,0 +1,243 @@
+//! Minimal HTTP/1.1 handling for the crate's local servers.
+//!
+//! The simulator and the JSON-RPC server answer a single request per
+//! connection and then close it, which is all their clients need and keeps
+//! the crate free of an HTTP server dependency. Bodies too large to hold in
+//! memory, such as album archives, are streamed after [`write_head`].
+//! Requests are read within line, header count and time limits, since the
+//! server may be reachable from other hosts.
+
+// The simulator ignores headers and query strings
+#![cfg_attr(not(feature = "server"), allow(dead_code))]
+
+use serde_json::Value;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// Largest request body read, in bytes
+const MAX_BODY_BYTES: usize = 8 * 1024 * 1024;
+
+/// Longest request line or header line read, in bytes
+const MAX_LINE_BYTES: u64 = 8 * 1024;
+
+/// Most headers read from one request
+const MAX_HEADERS: usize = 100;
+
+/// Time a client has to send its whole request
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A parsed HTTP request
+pub(crate) struct Request {
+    pub method: String,
+    /// Path without the query string
+    pub path: String,
+    pub query: Option<String>,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Returns the value of the first header named `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the decoded value of the first query parameter named `name`
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        url::form_urlencoded::parse(self.query.as_deref()?.as_bytes())
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.into_owned())
+    }
+}
+
+/// A response to write back
+pub(crate) struct Response {
+    pub status: u16,
+    pub content_type: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: impl Into<String>, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type: content_type.into(),
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    pub fn json(status: u16, body: &Value) -> Self {
+        Self::new(status, "application/json", body.to_string().into_bytes())
+    }
+
+    pub fn text(status: u16, body: &str) -> Self {
+        Self::new(status, "text/plain", body.as_bytes().to_vec())
+    }
+
+    /// Adds a header to the response
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+/// Reads the request line, headers and body of a request
+///
+/// A request that breaks the line, header or body limits, or that isn't
+/// received within [`READ_TIMEOUT`], is answered with an error status and
+/// the connection is closed.
+pub(crate) async fn read_request(stream: &mut TcpStream) -> io::Result<Request> {
+    let read = match tokio::time::timeout(READ_TIMEOUT, read_request_within_limits(stream)).await {
+        Ok(read) => read,
+        Err(_) => Err(ReadError::Rejected(408, "Request was not received in time")),
+    };
+    match read {
+        Ok(request) => Ok(request),
+        Err(ReadError::Io(e)) => Err(e),
+        Err(ReadError::Rejected(status, message)) => {
+            // The client may already be gone, so only its request is reported
+            let _ = write_response(stream, &Response::text(status, message)).await;
+            Err(io::Error::new(io::ErrorKind::InvalidData, message))
+        }
+    }
+}
+
+/// Why a request couldn't be read
+enum ReadError {
+    Io(io::Error),
+    /// The request is answered with this status and message
+    Rejected(u16, &'static str),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        ReadError::Io(e)
+    }
+}
+
+/// Reads a request, holding at most [`MAX_LINE_BYTES`] of any line
+async fn read_request_within_limits(stream: &mut TcpStream) -> Result<Request, ReadError> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    if !read_limited_line(&mut reader, &mut line).await? {
+        return Err(ReadError::Rejected(400, "Request line is too long"));
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default();
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target.to_string(), None),
+    };
+
+    let mut headers = Vec::new();
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if !read_limited_line(&mut reader, &mut line).await? {
+            return Err(ReadError::Rejected(431, "Request header is too long"));
+        }
+        if line.trim().is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(ReadError::Rejected(431, "Request has too many headers"));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let (name, value) = (name.trim(), value.trim());
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().unwrap_or(0);
+            }
+            headers.push((name.to_string(), value.to_string()));
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(ReadError::Rejected(413, "Request body is too large"));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+    Ok(Request {
+        method,
+        path,
+        query,
+        headers,
+        body,
+    })
+}
+
+/// Reads one line into `line`, returning `false` if it is longer than [`MAX_LINE_BYTES`]
+///
+/// A connection closed before the line ends leaves what was read in `line`.
+async fn read_limited_line<R>(reader: &mut R, line: &mut String) -> io::Result<bool>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let read = reader.take(MAX_LINE_BYTES).read_line(line).await?;
+    Ok(read < MAX_LINE_BYTES as usize || line.ends_with('\n'))
+}
+
+/// Writes `response` to `stream` and closes the connection
+pub(crate) async fn write_response(stream: &mut TcpStream, response: &Response) -> io::Result<()> {
+    write_head(stream, response, Some(response.body.len() as u64)).await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await
+}
+
+/// Writes the status line and headers of `response`, ignoring its body
+///
+/// The caller streams the body and closes the connection. Without a
+/// `content_length`, the body ends when the connection is closed.
+pub(crate) async fn write_head(
+    stream: &mut TcpStream,
+    response: &Response,
+    content_length: Option<u64>,
+) -> io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\ncontent-type: {}\r\nconnection: close\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+    );
+    if let Some(length) = content_length {
+        head.push_str(&format!("content-length: {}\r\n", length));
+    }
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await
+}
+
+/// Returns the reason phrase of the status codes the servers send
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        206 => "Partial Content",
+        304 => "Not Modified",
+        308 => "Permanent Redirect",
+        330 => "Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Content Too Large",
+        416 => "Range Not Satisfiable",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,280 @@
+//! Log targets and programmatic verbosity control.
+//!
+//! Every log record emitted by this crate uses one of the target names below
+//! instead of the Rust module path, so loggers can filter by area, e.g.
+//! `RUST_LOG=icloud_album::schema=error` with `env_logger`.
+//!
+//! Independently of the installed logger, [`set_level`] caps the verbosity of
+//! a target (or of every target starting with a prefix) from code, so an
+//! application can silence the schema-drift warnings while keeping the rest:
+//!
+//! ```
+//! use icloud_album_rs::logging;
+//! use log::LevelFilter;
+//!
+//! logging::set_level(logging::SCHEMA, LevelFilter::Error);
+//! # logging::reset_levels();
+//! ```
+//!
+//! Warnings that repeat for every photo of an album (e.g. a systematic type
+//! quirk) are limited while a response is parsed: each kind of warning is
+//! logged at most [`repeat_limit`] times, followed by one summary line with
+//! the number of suppressed occurrences.
+//!
+//! Each album fetch and download pass runs as an operation with a short
+//! random ID. Records logged while it runs are prefixed with `[op <id>]`, and
+//! the [`crate::api::ErrorContext`] of its errors carries the same ID, so the
+//! retries, warnings and failure of one run can be told apart from those of
+//! concurrent runs. Callers can pick the ID themselves with
+//! [`with_operation_id`], e.g. to reuse the request ID of a web service:
+//!
+//! ```
+//! use icloud_album_rs::logging;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let response = logging::with_operation_id(
+//!     "req-42",
+//!     icloud_album_rs::get_icloud_photos("B0z5qAGN1JIFd3y"),
+//! )
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use log::{Level, LevelFilter};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{OnceLock, RwLock};
+
+/// Prefix shared by all targets of this crate
+pub const ROOT: &str = "icloud_album";
+/// API requests and response handling
+pub const API: &str = "icloud_album::api";
+/// Schema validation and type-coercion warnings about API responses
+pub const SCHEMA: &str = "icloud_album::schema";
+/// Downloading and writing files
+pub const DOWNLOAD: &str = "icloud_album::download";
+/// Incremental sync state and symlink views
+pub const SYNC: &str = "icloud_album::sync";
+/// Album watchers and event sinks
+pub const WATCH: &str = "icloud_album::watch";
+/// File type detection and repair
+pub const FILES: &str = "icloud_album::files";
+
+/// Verbosity caps configured with [`set_level`], keyed by target prefix
+fn levels() -> &'static RwLock<HashMap<String, LevelFilter>> {
+    static LEVELS: OnceLock<RwLock<HashMap<String, LevelFilter>>> = OnceLock::new();
+    LEVELS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Caps the verbosity of a target and every target below it
+///
+/// `target` is matched as a `::`-separated prefix, so [`ROOT`] applies to the
+/// whole crate. The most specific configured prefix wins. Records still have
+/// to pass the installed logger's own filter.
+pub fn set_level(target: &str, level: LevelFilter) {
+    let mut levels = levels().write().unwrap_or_else(|e| e.into_inner());
+    levels.insert(target.to_string(), level);
+}
+
+/// Removes all verbosity caps set with [`set_level`]
+pub fn reset_levels() {
+    levels().write().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Returns the verbosity cap in effect for `target`
+///
+/// Targets without a configured cap allow every level.
+pub fn level(target: &str) -> LevelFilter {
+    let levels = levels().read().unwrap_or_else(|e| e.into_inner());
+    levels
+        .iter()
+        .filter(|(prefix, _)| {
+            target == prefix.as_str()
+                || (target.starts_with(prefix.as_str()) && target[prefix.len()..].starts_with("::"))
+        })
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, level)| *level)
+        .unwrap_or(LevelFilter::Trace)
+}
+
+/// Returns true if a record at `level` for `target` passes the configured cap
+pub fn enabled(target: &str, level: Level) -> bool {
+    level <= self::level(target)
+}
+
+/// Default for [`set_repeat_limit`]
+pub const DEFAULT_REPEAT_LIMIT: usize = 3;
+
+/// Maximum number of times each repeated warning is logged per response
+static REPEAT_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_REPEAT_LIMIT);
+
+thread_local! {
+    /// Occurrences of repeated warnings in the active scope, keyed by (target, kind)
+    static REPEATS: RefCell<Option<BTreeMap<(&'static str, &'static str), usize>>> =
+        const { RefCell::new(None) };
+}
+
+/// Sets how often each kind of repeated warning is logged while parsing a response
+///
+/// Further occurrences are counted and reported in a single summary line. A
+/// limit of zero logs only the summaries.
+pub fn set_repeat_limit(limit: usize) {
+    REPEAT_LIMIT.store(limit, Ordering::Relaxed);
+}
+
+/// Returns the limit set with [`set_repeat_limit`]
+pub fn repeat_limit() -> usize {
+    REPEAT_LIMIT.load(Ordering::Relaxed)
+}
+
+/// Scope in which repeated warnings are limited, summarizing them when dropped
+///
+/// Scopes nest; only the outermost one logs summaries.
+pub(crate) struct RepeatScope {
+    outermost: bool,
+}
+
+/// Starts limiting repeated warnings on the current thread
+pub(crate) fn limit_repeats() -> RepeatScope {
+    let outermost = REPEATS.with(|repeats| {
+        let mut repeats = repeats.borrow_mut();
+        if repeats.is_none() {
+            *repeats = Some(BTreeMap::new());
+            true
+        } else {
+            false
+        }
+    });
+    RepeatScope { outermost }
+}
+
+impl Drop for RepeatScope {
+    fn drop(&mut self) {
+        if !self.outermost {
+            return;
+        }
+        let counts = REPEATS.with(|repeats| repeats.borrow_mut().take());
+        let limit = repeat_limit();
+        for ((target, kind), count) in counts.unwrap_or_default() {
+            if count > limit {
+                log_warn!(
+                    target,
+                    "{}: {} occurrences in total, {} not shown",
+                    kind,
+                    count,
+                    count - limit
+                );
+            }
+        }
+    }
+}
+
+/// Counts an occurrence of a repeated warning, returning true if it should be logged
+pub(crate) fn record_repeat(target: &'static str, kind: &'static str) -> bool {
+    REPEATS.with(|repeats| match repeats.borrow_mut().as_mut() {
+        Some(counts) => {
+            let count = counts.entry((target, kind)).or_insert(0);
+            *count += 1;
+            *count <= repeat_limit()
+        }
+        None => true,
+    })
+}
+
+tokio::task_local! {
+    /// ID of the operation the current task belongs to
+    static OPERATION_ID: String;
+}
+
+/// Returns the ID of the operation the current task runs in, if any
+pub fn operation_id() -> Option<String> {
+    OPERATION_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Generates a new random operation ID of 8 hex digits
+pub fn new_operation_id() -> String {
+    format!("{:08x}", rand::random::<u32>())
+}
+
+/// Runs `future` as an operation with the given ID
+///
+/// Operations started inside `future` by this crate keep the ID instead of
+/// generating their own.
+///
+/// # Arguments
+///
+/// * `id` - The operation ID to attach to logs and errors
+/// * `future` - The work to run
+///
+/// # Returns
+///
+/// The output of `future`
+pub async fn with_operation_id<F: Future>(id: impl Into<String>, future: F) -> F::Output {
+    OPERATION_ID.scope(id.into(), future).await
+}
+
+/// Runs `future` in the current operation, or in a new one if there is none
+pub(crate) async fn in_operation<F: Future>(future: F) -> F::Output {
+    match operation_id() {
+        Some(_) => future.await,
+        None => with_operation_id(new_operation_id(), future).await,
+    }
+}
+
+/// Logs through the `log` crate if the target's verbosity cap allows it
+///
+/// Records logged within an operation are prefixed with its ID.
+macro_rules! log_at {
+    ($target:expr, $level:expr, $($arg:tt)+) => {{
+        let level = $level;
+        if $crate::logging::enabled($target, level) {
+            match $crate::logging::operation_id() {
+                Some(id) => ::log::log!(
+                    target: $target,
+                    level,
+                    "[op {}] {}",
+                    id,
+                    format_args!($($arg)+)
+                ),
+                None => ::log::log!(target: $target, level, $($arg)+),
+            }
+        }
+    }};
+}
+
+/// Logs a warning to a crate target
+macro_rules! log_warn {
+    ($target:expr, $($arg:tt)+) => {
+        $crate::logging::log_at!($target, ::log::Level::Warn, $($arg)+)
+    };
+}
+
+/// Logs a debug message to a crate target
+macro_rules! log_debug {
+    ($target:expr, $($arg:tt)+) => {
+        $crate::logging::log_at!($target, ::log::Level::Debug, $($arg)+)
+    };
+}
+
+/// Logs a trace message to a crate target
+macro_rules! log_trace {
+    ($target:expr, $($arg:tt)+) => {
+        $crate::logging::log_at!($target, ::log::Level::Trace, $($arg)+)
+    };
+}
+
+/// Logs a warning that may repeat for many items, subject to [`repeat_limit`]
+///
+/// `kind` is a fixed description of the warning used to group occurrences.
+macro_rules! log_warn_repeated {
+    ($target:expr, $kind:expr, $($arg:tt)+) => {
+        if $crate::logging::record_repeat($target, $kind) {
+            $crate::logging::log_warn!($target, $($arg)+)
+        }
+    };
+}
+
+pub(crate) use {log_at, log_debug, log_trace, log_warn, log_warn_repeated};
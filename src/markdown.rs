@@ -0,0 +1,229 @@
+//! Markdown captions.
+//!
+//! Many people write shared album captions with Markdown formatting: several
+//! paragraphs, `**bold**` words, lists and links. Outputs that opt in render
+//! such captions with [`render_markdown`] where HTML is shown (galleries,
+//! exports) and reduce them to plain text with [`strip_markdown`] where it
+//! isn't, e.g. in file names.
+//!
+//! Only the subset that makes sense in captions is supported: paragraphs and
+//! line breaks, `-`/`*` bullet lists, `**strong**`/`__strong__`,
+//! `*emphasis*`/`_emphasis_`, `` `code` `` and `[links](https://...)`.
+//! Everything else is shown as text. Raw HTML is always escaped and links
+//! other than `http`, `https` and `mailto` are shown as their text, so
+//! rendered captions are safe to embed.
+
+use crate::export::escape_xml;
+
+/// A span of inline content
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Inline {
+    Text(String),
+    Code(String),
+    Strong(Vec<Inline>),
+    Emphasis(Vec<Inline>),
+    Link { text: Vec<Inline>, url: String },
+}
+
+/// A block of a caption
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Block {
+    /// Lines of a paragraph
+    Paragraph(Vec<String>),
+    /// Items of a bullet list
+    List(Vec<String>),
+}
+
+/// Renders a Markdown caption as HTML
+///
+/// # Arguments
+///
+/// * `text` - The caption
+///
+/// # Returns
+///
+/// The HTML fragment, e.g. `<p>A <strong>great</strong> day</p>`
+pub fn render_markdown(text: &str) -> String {
+    blocks(text)
+        .iter()
+        .map(|block| match block {
+            Block::Paragraph(lines) => format!(
+                "<p>{}</p>",
+                lines
+                    .iter()
+                    .map(|line| html(&inlines(line)))
+                    .collect::<Vec<_>>()
+                    .join("<br>\n")
+            ),
+            Block::List(items) => format!(
+                "<ul>{}</ul>",
+                items
+                    .iter()
+                    .map(|item| format!("<li>{}</li>", html(&inlines(item))))
+                    .collect::<String>()
+            ),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Reduces a Markdown caption to plain text
+///
+/// Formatting markers are removed and links replaced by their text. Blocks
+/// and lines stay on separate lines.
+///
+/// # Arguments
+///
+/// * `text` - The caption
+///
+/// # Returns
+///
+/// The caption's text
+pub fn strip_markdown(text: &str) -> String {
+    blocks(text)
+        .iter()
+        .flat_map(|block| match block {
+            Block::Paragraph(lines) | Block::List(lines) => lines.iter(),
+        })
+        .map(|line| plain(&inlines(line)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Splits a caption into paragraphs and lists
+fn blocks(text: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    // Whether the next line continues the last block
+    let mut open = false;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            open = false;
+            continue;
+        }
+        let item = line
+            .strip_prefix("- ")
+            .or_else(|| line.strip_prefix("* "))
+            .map(str::trim_start);
+        match (item, blocks.last_mut()) {
+            (Some(item), Some(Block::List(items))) if open => items.push(item.to_string()),
+            (Some(item), _) => blocks.push(Block::List(vec![item.to_string()])),
+            (None, Some(Block::Paragraph(lines))) if open => lines.push(line.to_string()),
+            (None, _) => blocks.push(Block::Paragraph(vec![line.to_string()])),
+        }
+        open = true;
+    }
+    blocks
+}
+
+/// Parses the inline content of a line
+fn inlines(text: &str) -> Vec<Inline> {
+    let chars: Vec<char> = text.chars().collect();
+    parse(&chars)
+}
+
+/// Parses inline content: code, strong, emphasis and links
+fn parse(chars: &[char]) -> Vec<Inline> {
+    let mut spans = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let parsed = match c {
+            '\\' if i + 1 < chars.len() && chars[i + 1].is_ascii_punctuation() => {
+                text.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '`' => find(chars, i + 1, &['`'])
+                .map(|end| (Inline::Code(chars[i + 1..end].iter().collect()), end + 1)),
+            '*' | '_' if chars.get(i + 1) == Some(&c) => find(chars, i + 2, &[c, c])
+                .filter(|&end| end > i + 2)
+                .map(|end| (Inline::Strong(parse(&chars[i + 2..end])), end + 2)),
+            // Underscores within words, as in file_name, are not emphasis
+            '_' if i > 0 && chars[i - 1].is_alphanumeric() => None,
+            '*' | '_' => find(chars, i + 1, &[c])
+                .filter(|&end| end > i + 1 && !chars[i + 1].is_whitespace())
+                .filter(|&end| c == '*' || !chars.get(end + 1).is_some_and(|c| c.is_alphanumeric()))
+                .map(|end| (Inline::Emphasis(parse(&chars[i + 1..end])), end + 1)),
+            '[' => link(chars, i),
+            _ => None,
+        };
+        match parsed {
+            Some((span, next)) => {
+                if !text.is_empty() {
+                    spans.push(Inline::Text(std::mem::take(&mut text)));
+                }
+                spans.push(span);
+                i = next;
+            }
+            None => {
+                text.push(c);
+                i += 1;
+            }
+        }
+    }
+    if !text.is_empty() {
+        spans.push(Inline::Text(text));
+    }
+    spans
+}
+
+/// Parses a `[text](url)` link starting at `start`
+fn link(chars: &[char], start: usize) -> Option<(Inline, usize)> {
+    let close = find(chars, start + 1, &[']'])?;
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+    let end = find(chars, close + 2, &[')'])?;
+    let url: String = chars[close + 2..end].iter().collect();
+    Some((
+        Inline::Link {
+            text: parse(&chars[start + 1..close]),
+            url: url.trim().to_string(),
+        },
+        end + 1,
+    ))
+}
+
+/// Finds the next occurrence of `pattern` at or after `from`
+fn find(chars: &[char], from: usize, pattern: &[char]) -> Option<usize> {
+    (from..chars.len()).find(|&i| chars[i..].starts_with(pattern))
+}
+
+/// Whether a link URL is safe to put in an `href`
+fn is_safe_url(url: &str) -> bool {
+    let lower = url.to_ascii_lowercase();
+    ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|scheme| lower.starts_with(scheme))
+}
+
+/// Renders inline content as HTML
+fn html(spans: &[Inline]) -> String {
+    spans
+        .iter()
+        .map(|span| match span {
+            Inline::Text(text) => escape_xml(text),
+            Inline::Code(code) => format!("<code>{}</code>", escape_xml(code)),
+            Inline::Strong(spans) => format!("<strong>{}</strong>", html(spans)),
+            Inline::Emphasis(spans) => format!("<em>{}</em>", html(spans)),
+            Inline::Link { text, url } if is_safe_url(url) => {
+                format!("<a href=\"{}\">{}</a>", escape_xml(url), html(text))
+            }
+            Inline::Link { text, .. } => html(text),
+        })
+        .collect()
+}
+
+/// Renders inline content as plain text
+fn plain(spans: &[Inline]) -> String {
+    spans
+        .iter()
+        .map(|span| match span {
+            Inline::Text(text) | Inline::Code(text) => text.clone(),
+            Inline::Strong(spans) | Inline::Emphasis(spans) => plain(spans),
+            Inline::Link { text, .. } => plain(text),
+        })
+        .collect()
+}
@@ -0,0 +1,219 @@
+//! Media server friendly layout for downloaded albums.
+//!
+//! Plex, Jellyfin and DLNA servers index photo libraries by folder and file
+//! name. [`export_media_library`] places the files of an album (tracked by
+//! [`SyncState`]) into a tree they present well:
+//!
+//! ```text
+//! <root>/<album>/<year>/<YYYY-MM-DD HH.MM.SS> <id>.<ext>
+//! <root>/<album>/<year>/<YYYY-MM-DD HH.MM.SS> <id>.nfo
+//! <root>/<album>/Undated/<id>.<ext>
+//! ```
+//!
+//! Each file gets an `.nfo` sidecar carrying its title, date, caption and
+//! contributor, which Kodi-style scrapers read.
+
+use crate::export::escape_xml;
+use crate::logging;
+use crate::models::{parse_timestamp, Image, Metadata};
+use crate::sync::SyncState;
+use crate::views::sanitize_component;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directory name for photos without a parseable date
+pub const UNDATED_DIR: &str = "Undated";
+
+/// Number of photo GUID characters used to keep file names unique
+const ID_LEN: usize = 8;
+
+/// Options controlling [`export_media_library`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MediaLibraryOptions {
+    /// Hard-link files to the download directory instead of copying them
+    ///
+    /// Falls back to copying when linking fails (e.g. across filesystems).
+    pub hard_links: bool,
+    /// Write an `.nfo` sidecar next to every file
+    pub sidecars: bool,
+}
+
+impl Default for MediaLibraryOptions {
+    fn default() -> Self {
+        Self {
+            hard_links: false,
+            sidecars: true,
+        }
+    }
+}
+
+impl MediaLibraryOptions {
+    /// Create options copying files and writing sidecars
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether files are hard-linked to the download directory
+    #[must_use]
+    pub fn with_hard_links(mut self, hard_links: bool) -> Self {
+        self.hard_links = hard_links;
+        self
+    }
+
+    /// Sets whether `.nfo` sidecars are written
+    #[must_use]
+    pub fn with_sidecars(mut self, sidecars: bool) -> Self {
+        self.sidecars = sidecars;
+        self
+    }
+}
+
+/// Returns the path of a photo within the album directory, without extension
+///
+/// Dated photos are named after the time they were taken, as recorded, so
+/// file names sort chronologically.
+fn library_stem(photo: &Image) -> PathBuf {
+    let id: String = sanitize_component(&photo.photo_guid)
+        .chars()
+        .take(ID_LEN)
+        .collect();
+    let taken = photo
+        .date_created
+        .as_deref()
+        .filter(|date| parse_timestamp(date).is_some())
+        .and_then(|date| Some((date.get(0..4)?, date.get(0..10)?, date.get(11..19)?)));
+    match taken {
+        Some((year, day, time)) => {
+            PathBuf::from(year).join(format!("{} {} {}", day, time.replace(':', "."), id))
+        }
+        None => PathBuf::from(UNDATED_DIR).join(id),
+    }
+}
+
+/// Appends an extension to a stem that may itself contain dots
+fn append_extension(stem: &Path, extension: &std::ffi::OsStr) -> PathBuf {
+    let mut path = stem.as_os_str().to_os_string();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/// Builds the `.nfo` sidecar of a photo
+fn sidecar(photo: &Image, album: &Metadata) -> String {
+    let caption = photo
+        .caption
+        .as_deref()
+        .map(str::trim)
+        .filter(|caption| !caption.is_empty());
+
+    let mut nfo = String::new();
+    nfo.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n");
+    nfo.push_str("<photo>\n");
+    nfo.push_str(&format!(
+        "  <title>{}</title>\n",
+        escape_xml(caption.unwrap_or(&album.stream_name))
+    ));
+    if let Some(caption) = caption {
+        nfo.push_str(&format!("  <plot>{}</plot>\n", escape_xml(caption)));
+    }
+    if let Some(date) = photo.date_taken() {
+        nfo.push_str(&format!("  <premiered>{}</premiered>\n", date));
+        nfo.push_str(&format!("  <year>{}</year>\n", date.year));
+    }
+    if let Some(added) = &photo.batch_date_created {
+        nfo.push_str(&format!("  <dateadded>{}</dateadded>\n", escape_xml(added)));
+    }
+    nfo.push_str(&format!(
+        "  <album>{}</album>\n",
+        escape_xml(&album.stream_name)
+    ));
+    if let Some(contributor) = &photo.contributor_full_name {
+        nfo.push_str(&format!(
+            "  <credits>{}</credits>\n",
+            escape_xml(contributor)
+        ));
+    }
+    if let Some(place_name) = &photo.place_name {
+        nfo.push_str(&format!("  <tag>{}</tag>\n", escape_xml(place_name)));
+    }
+    nfo.push_str(&format!(
+        "  <uniqueid type=\"icloud\" default=\"true\">{}</uniqueid>\n",
+        escape_xml(&photo.photo_guid)
+    ));
+    nfo.push_str("</photo>\n");
+    nfo
+}
+
+/// Places the downloaded files of an album into a media server layout
+///
+/// Each photo with a recorded, existing file in `state` is linked or copied to
+/// its place under `<root>/<album>`; photos without a file are skipped. Files
+/// left by an earlier export are replaced, so the export can be re-run after
+/// every sync.
+///
+/// # Arguments
+///
+/// * `root` - Library directory the media server scans
+/// * `album` - Metadata of the album, naming its directory
+/// * `photos` - The album's photos
+/// * `state` - Sync state mapping photo GUIDs to downloaded files
+/// * `options` - Linking and sidecar options
+///
+/// # Returns
+///
+/// The paths of the exported media files, in album order
+pub async fn export_media_library(
+    root: impl AsRef<Path>,
+    album: &Metadata,
+    photos: &[Image],
+    state: &SyncState,
+    options: &MediaLibraryOptions,
+) -> io::Result<Vec<PathBuf>> {
+    let album_dir = root.as_ref().join(sanitize_component(&album.stream_name));
+
+    let mut exported = Vec::new();
+    for photo in photos {
+        let Some(file) = state.file(&photo.photo_guid) else {
+            continue;
+        };
+        if tokio::fs::metadata(&file.path).await.is_err() {
+            logging::log_warn!(
+                logging::SYNC,
+                "Skipping {} in media library: {} is missing",
+                photo.photo_guid,
+                file.path.display()
+            );
+            continue;
+        }
+
+        let stem = album_dir.join(library_stem(photo));
+        let target = match file.path.extension() {
+            Some(extension) => append_extension(&stem, extension),
+            None => stem.clone(),
+        };
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // Replace files left by an earlier export
+        if tokio::fs::metadata(&target).await.is_ok() {
+            tokio::fs::remove_file(&target).await?;
+        }
+        let linked = options.hard_links && tokio::fs::hard_link(&file.path, &target).await.is_ok();
+        if !linked {
+            tokio::fs::copy(&file.path, &target).await?;
+        }
+
+        if options.sidecars {
+            tokio::fs::write(
+                append_extension(&stem, "nfo".as_ref()),
+                sidecar(photo, album),
+            )
+            .await?;
+        }
+        exported.push(target);
+    }
+
+    Ok(exported)
+}
@@ -0,0 +1,166 @@
+//! Merging several shared albums into one virtual album.
+//!
+//! Families often share the same moments through several albums, and the
+//! same photo then shows up in more than one of them. [`merge_albums`] unifies
+//! fetched albums into a [`MergedAlbum`]: photos sharing a derivative checksum
+//! are kept once, and every photo remembers which albums it came from. The
+//! merged photos can be shown as a single view or mirrored in one pass into a
+//! single archive.
+
+use crate::models::{ICloudResponse, Image, StreamCtag};
+use std::collections::HashMap;
+
+/// One of the albums a [`MergedAlbum`] was built from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlbumSource {
+    /// Name of the album
+    pub stream_name: String,
+    /// Full name of the album owner
+    pub owner: String,
+    /// `streamCtag` of the album when it was fetched
+    pub stream_ctag: StreamCtag,
+    /// Number of photos the album contributed, duplicates included
+    pub photo_count: usize,
+}
+
+/// Where a merged photo appears
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhotoSource {
+    /// Index of the album in [`MergedAlbum::sources`]
+    pub album: usize,
+    /// GUID of the photo in that album
+    pub photo_guid: String,
+}
+
+/// A photo of a merged album
+#[derive(Debug, Clone)]
+pub struct MergedPhoto {
+    /// The photo as it appears in the first album that has it
+    pub photo: Image,
+    /// The albums the photo appears in, in the order they were merged
+    pub sources: Vec<PhotoSource>,
+}
+
+impl MergedPhoto {
+    /// Whether the photo appears in more than one album
+    pub fn is_shared(&self) -> bool {
+        self.sources.len() > 1
+    }
+}
+
+/// Several albums unified into one
+#[derive(Debug, Clone, Default)]
+pub struct MergedAlbum {
+    /// The merged albums, in the order they were given
+    pub sources: Vec<AlbumSource>,
+    /// The distinct photos, oldest first; undated photos come last
+    pub photos: Vec<MergedPhoto>,
+}
+
+impl MergedAlbum {
+    /// Number of distinct photos
+    pub fn len(&self) -> usize {
+        self.photos.len()
+    }
+
+    /// Whether the merged albums have no photos
+    pub fn is_empty(&self) -> bool {
+        self.photos.is_empty()
+    }
+
+    /// Number of photos left out because another album already had them
+    pub fn duplicates(&self) -> usize {
+        self.photos
+            .iter()
+            .map(|photo| photo.sources.len() - 1)
+            .sum()
+    }
+
+    /// Returns the distinct photos, e.g. to mirror them into one archive
+    pub fn images(&self) -> Vec<Image> {
+        self.photos
+            .iter()
+            .map(|merged| merged.photo.clone())
+            .collect()
+    }
+
+    /// Returns the photos that appear in an album of [`MergedAlbum::sources`]
+    pub fn photos_from(&self, album: usize) -> impl Iterator<Item = &MergedPhoto> {
+        self.photos
+            .iter()
+            .filter(move |merged| merged.sources.iter().any(|source| source.album == album))
+    }
+}
+
+/// Unifies the photos of several albums
+///
+/// Two photos are the same when any of their derivatives share a checksum;
+/// the first album given keeps the photo and the others are recorded as
+/// further sources. Photos without checksums are never merged.
+///
+/// # Arguments
+///
+/// * `albums` - The fetched albums, in order of preference
+///
+/// # Returns
+///
+/// A [`MergedAlbum`] with the distinct photos sorted by when they were taken
+pub fn merge_albums(albums: Vec<ICloudResponse>) -> MergedAlbum {
+    let mut merged = MergedAlbum::default();
+    let mut by_checksum: HashMap<String, usize> = HashMap::new();
+
+    for (album, response) in albums.into_iter().enumerate() {
+        let metadata = &response.metadata;
+        merged.sources.push(AlbumSource {
+            stream_name: metadata.stream_name.clone(),
+            owner: format!("{} {}", metadata.user_first_name, metadata.user_last_name)
+                .trim()
+                .to_string(),
+            stream_ctag: metadata.stream_ctag.clone(),
+            photo_count: response.photos.len(),
+        });
+
+        for photo in response.photos {
+            let source = PhotoSource {
+                album,
+                photo_guid: photo.photo_guid.clone(),
+            };
+            let checksums: Vec<String> = photo
+                .derivatives
+                .values()
+                .map(|derivative| derivative.checksum.clone())
+                .filter(|checksum| !checksum.is_empty())
+                .collect();
+            // The earliest match, should the checksums point at several photos
+            let existing = checksums
+                .iter()
+                .filter_map(|checksum| by_checksum.get(checksum).copied())
+                .min();
+            let index = match existing {
+                Some(index) => {
+                    merged.photos[index].sources.push(source);
+                    index
+                }
+                None => {
+                    merged.photos.push(MergedPhoto {
+                        photo,
+                        sources: vec![source],
+                    });
+                    merged.photos.len() - 1
+                }
+            };
+            for checksum in checksums {
+                by_checksum.entry(checksum).or_insert(index);
+            }
+        }
+    }
+
+    // Stable, so photos taken at the same time keep the order they were merged in
+    merged
+        .photos
+        .sort_by_key(|merged| match merged.photo.timestamp_taken() {
+            Some(taken_at) => (false, taken_at),
+            None => (true, 0),
+        });
+    merged
+}
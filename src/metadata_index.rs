@@ -0,0 +1,345 @@
+//! SQLite index of the photos of archived albums.
+//!
+//! Finding a photo in a large archive of many albums otherwise means reading
+//! every album's [`SyncState`] or walking the filesystem. A [`MetadataIndex`]
+//! keeps one row per downloaded photo and album, with its caption, the time it
+//! was taken, the path of its file and its checksum. Mirror runs bring an
+//! album's rows up to date with [`MetadataIndex::update_album`] after saving
+//! their state; the query helpers then answer from the database alone, and
+//! [`MetadataIndex::search_captions`] searches captions across albums.
+//!
+//! Every method performs blocking I/O; from async code, call them through
+//! `tokio::task::spawn_blocking`.
+
+use crate::models::Image;
+use crate::search::{CaptionHit, CaptionQuery};
+use crate::sync::SyncState;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Schema of the index, created if it doesn't exist
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS photos (
+    album TEXT NOT NULL,
+    photo_guid TEXT NOT NULL,
+    caption TEXT,
+    taken_at INTEGER,
+    path TEXT NOT NULL,
+    checksum TEXT,
+    PRIMARY KEY (album, photo_guid)
+);
+CREATE INDEX IF NOT EXISTS photos_guid ON photos (photo_guid);
+CREATE INDEX IF NOT EXISTS photos_taken_at ON photos (taken_at);
+CREATE INDEX IF NOT EXISTS photos_checksum ON photos (checksum);
+";
+
+/// Columns selected by the queries, in the order [`IndexedPhoto::from_row`] reads them
+const COLUMNS: &str = "album, photo_guid, caption, taken_at, path, checksum";
+
+/// A downloaded photo as recorded in the index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedPhoto {
+    /// Album the photo was downloaded from, as named by the caller
+    pub album: String,
+    /// GUID of the photo
+    pub photo_guid: String,
+    /// Caption of the photo
+    pub caption: Option<String>,
+    /// When the photo was taken, as a Unix timestamp
+    pub taken_at: Option<i64>,
+    /// Path of the downloaded file
+    pub path: PathBuf,
+    /// Apple checksum of the downloaded derivative
+    pub checksum: Option<String>,
+}
+
+impl IndexedPhoto {
+    /// Reads a row selected with [`COLUMNS`]
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            album: row.get(0)?,
+            photo_guid: row.get(1)?,
+            caption: row.get(2)?,
+            taken_at: row.get(3)?,
+            path: PathBuf::from(row.get::<_, String>(4)?),
+            checksum: row.get(5)?,
+        })
+    }
+}
+
+/// What [`MetadataIndex::update_album`] changed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexUpdate {
+    /// Photos added or updated
+    pub indexed: usize,
+    /// Photos no longer in the album's state that were removed
+    pub removed: usize,
+}
+
+/// A SQLite index of downloaded photos across albums
+#[derive(Debug)]
+pub struct MetadataIndex {
+    connection: Mutex<Connection>,
+}
+
+impl MetadataIndex {
+    /// Opens the index at `path`, creating the database if needed
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::with_connection(Connection::open(path).map_err(io::Error::other)?)
+    }
+
+    /// Create an index that lives in memory only
+    pub fn open_in_memory() -> io::Result<Self> {
+        Self::with_connection(Connection::open_in_memory().map_err(io::Error::other)?)
+    }
+
+    /// Keeps the index in the `photos` table of an open connection
+    pub fn with_connection(connection: Connection) -> io::Result<Self> {
+        connection.execute_batch(SCHEMA).map_err(io::Error::other)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Brings the rows of `album` up to date with a mirror run
+    ///
+    /// Every file recorded in `state` is indexed, and rows of photos that are
+    /// no longer recorded are removed, in a single transaction. Captions and
+    /// dates come from `photos`; a photo missing from it keeps the caption it
+    /// was indexed with.
+    ///
+    /// # Arguments
+    ///
+    /// * `album` - Name of the album, e.g. its alias or token
+    /// * `photos` - The photos of the album as fetched by the run
+    /// * `state` - The album's state at the end of the run
+    ///
+    /// # Returns
+    ///
+    /// How many rows were written and removed
+    pub fn update_album(
+        &self,
+        album: &str,
+        photos: &[Image],
+        state: &SyncState,
+    ) -> io::Result<IndexUpdate> {
+        let photos: HashMap<&str, &Image> = photos
+            .iter()
+            .map(|photo| (photo.photo_guid.as_str(), photo))
+            .collect();
+        let mut connection = self.lock();
+        let transaction = connection.transaction().map_err(io::Error::other)?;
+        let mut update = IndexUpdate::default();
+        {
+            let existing: Vec<String> = transaction
+                .prepare("SELECT photo_guid FROM photos WHERE album = ?1")
+                .and_then(|mut statement| {
+                    statement
+                        .query_map([album], |row| row.get(0))?
+                        .collect::<rusqlite::Result<_>>()
+                })
+                .map_err(io::Error::other)?;
+            let mut delete = transaction
+                .prepare("DELETE FROM photos WHERE album = ?1 AND photo_guid = ?2")
+                .map_err(io::Error::other)?;
+            for guid in existing
+                .iter()
+                .filter(|guid| !state.files.contains_key(*guid))
+            {
+                update.removed += delete.execute([album, guid]).map_err(io::Error::other)?;
+            }
+
+            let mut upsert = transaction
+                .prepare(
+                    "INSERT INTO photos (album, photo_guid, caption, taken_at, path, checksum)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                     ON CONFLICT (album, photo_guid) DO UPDATE SET
+                         caption = COALESCE(excluded.caption, caption),
+                         taken_at = COALESCE(excluded.taken_at, taken_at),
+                         path = excluded.path,
+                         checksum = excluded.checksum",
+                )
+                .map_err(io::Error::other)?;
+            for (guid, file) in &state.files {
+                let photo = photos.get(guid.as_str());
+                let caption = photo.and_then(|photo| photo.caption.as_deref());
+                let taken_at = file
+                    .taken_at
+                    .or_else(|| photo.and_then(|photo| photo.timestamp_taken()));
+                upsert
+                    .execute(params![
+                        album,
+                        guid,
+                        caption,
+                        taken_at,
+                        file.path.to_string_lossy(),
+                        file.checksum,
+                    ])
+                    .map_err(io::Error::other)?;
+                update.indexed += 1;
+            }
+        }
+        transaction.commit().map_err(io::Error::other)?;
+        Ok(update)
+    }
+
+    /// Removes every row of `album`, returning how many there were
+    pub fn remove_album(&self, album: &str) -> io::Result<usize> {
+        self.lock()
+            .execute("DELETE FROM photos WHERE album = ?1", [album])
+            .map_err(io::Error::other)
+    }
+
+    /// Returns the indexed albums with their photo counts, by name
+    pub fn albums(&self) -> io::Result<Vec<(String, usize)>> {
+        let connection = self.lock();
+        let mut statement = connection
+            .prepare("SELECT album, COUNT(*) FROM photos GROUP BY album ORDER BY album")
+            .map_err(io::Error::other)?;
+        let albums = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as usize)))
+            .and_then(|rows| rows.collect())
+            .map_err(io::Error::other)?;
+        Ok(albums)
+    }
+
+    /// Number of indexed photos across all albums
+    pub fn len(&self) -> io::Result<usize> {
+        self.lock()
+            .query_row("SELECT COUNT(*) FROM photos", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map(|count| count as usize)
+            .map_err(io::Error::other)
+    }
+
+    /// Whether no photo is indexed
+    pub fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Returns the row of a photo in an album
+    pub fn photo(&self, album: &str, photo_guid: &str) -> io::Result<Option<IndexedPhoto>> {
+        self.lock()
+            .query_row(
+                &format!(
+                    "SELECT {} FROM photos WHERE album = ?1 AND photo_guid = ?2",
+                    COLUMNS
+                ),
+                [album, photo_guid],
+                IndexedPhoto::from_row,
+            )
+            .optional()
+            .map_err(io::Error::other)
+    }
+
+    /// Returns the photos of an album, oldest first
+    pub fn album_photos(&self, album: &str) -> io::Result<Vec<IndexedPhoto>> {
+        self.query(
+            "WHERE album = ?1 ORDER BY taken_at, photo_guid",
+            params![album],
+        )
+    }
+
+    /// Returns every album's row of a photo GUID
+    pub fn find_by_guid(&self, photo_guid: &str) -> io::Result<Vec<IndexedPhoto>> {
+        self.query("WHERE photo_guid = ?1 ORDER BY album", params![photo_guid])
+    }
+
+    /// Returns the photos downloaded with a checksum, e.g. to find copies across albums
+    pub fn find_by_checksum(&self, checksum: &str) -> io::Result<Vec<IndexedPhoto>> {
+        self.query(
+            "WHERE checksum = ?1 ORDER BY album, photo_guid",
+            params![checksum],
+        )
+    }
+
+    /// Returns the photos taken in a range, oldest first
+    ///
+    /// # Arguments
+    ///
+    /// * `since` - Unix timestamp the photos were taken at or after, if any
+    /// * `until` - Unix timestamp the photos were taken before, if any
+    ///
+    /// # Returns
+    ///
+    /// The matching photos; photos without a date only match an open range
+    pub fn taken_between(
+        &self,
+        since: Option<i64>,
+        until: Option<i64>,
+    ) -> io::Result<Vec<IndexedPhoto>> {
+        if since.is_none() && until.is_none() {
+            return self.query("ORDER BY taken_at, album, photo_guid", params![]);
+        }
+        self.query(
+            "WHERE taken_at >= COALESCE(?1, taken_at) AND taken_at < COALESCE(?2, taken_at + 1)
+             ORDER BY taken_at, album, photo_guid",
+            params![since, until],
+        )
+    }
+
+    /// Finds the indexed photos whose captions match a query, across albums
+    ///
+    /// Ranking is the one of [`crate::search::search_captions`]. The date
+    /// range is applied by the database; captions are matched as they are read.
+    ///
+    /// # Returns
+    ///
+    /// The matching photos, best first
+    pub fn search_captions(
+        &self,
+        query: &CaptionQuery,
+    ) -> io::Result<Vec<CaptionHit<IndexedPhoto>>> {
+        if query.words().is_empty() {
+            return Ok(Vec::new());
+        }
+        let candidates = if query.since().is_none() && query.until().is_none() {
+            self.query("WHERE caption IS NOT NULL", params![])?
+        } else {
+            self.query(
+                "WHERE caption IS NOT NULL
+                 AND taken_at >= COALESCE(?1, taken_at) AND taken_at < COALESCE(?2, taken_at + 1)",
+                params![query.since(), query.until()],
+            )?
+        };
+        let mut hits: Vec<_> = candidates
+            .into_iter()
+            .filter_map(|photo| {
+                let score = query.score(photo.caption.as_deref()?)?;
+                Some(CaptionHit {
+                    taken_at: photo.taken_at,
+                    item: photo,
+                    score,
+                })
+            })
+            .collect();
+        query.rank(&mut hits);
+        Ok(hits)
+    }
+
+    /// Selects rows with a `WHERE`/`ORDER BY` clause
+    fn query(
+        &self,
+        clause: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> io::Result<Vec<IndexedPhoto>> {
+        let connection = self.lock();
+        let mut statement = connection
+            .prepare(&format!("SELECT {} FROM photos {}", COLUMNS, clause))
+            .map_err(io::Error::other)?;
+        let photos = statement
+            .query_map(params, IndexedPhoto::from_row)
+            .and_then(|rows| rows.collect())
+            .map_err(io::Error::other)?;
+        Ok(photos)
+    }
+
+    /// Locks the connection, recovering it if a holder panicked
+    fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,213 @@
+//! Counters of the crate's network activity, for monitoring long-running services.
+//!
+//! A [`Metrics`] implementation installed with [`install`] is told about
+//! every album API request, every retry of a request or download, the bytes of
+//! every downloaded file and the outcome of every album sync. Like HAR
+//! recording, this is process-wide: all concurrent fetches and downloads
+//! report to the same implementation.
+//!
+//! [`PrometheusMetrics`] keeps the counters in memory and renders them in the
+//! Prometheus text format; the JSON-RPC server of the `server` feature serves
+//! it at `/metrics`. Albums are labelled with their redacted tokens.
+//!
+//! ```
+//! use icloud_album_rs::metrics::{self, PrometheusMetrics};
+//! use std::sync::Arc;
+//!
+//! let prometheus = Arc::new(PrometheusMetrics::new());
+//! metrics::install(prometheus.clone());
+//! // ... fetch and download albums ...
+//! print!("{}", prometheus.render());
+//! # metrics::uninstall();
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Receives counts of the crate's network activity
+///
+/// All methods have empty default implementations, so implementors only need
+/// to override the counts they are interested in. Methods are called from
+/// concurrent tasks and must return quickly.
+pub trait Metrics: Send + Sync {
+    /// Called for every request to the album API, retries included
+    fn on_api_call(&self) {}
+
+    /// Called before every retry of an API request or a file download
+    fn on_retry(&self) {}
+
+    /// Called with the size of every file downloaded
+    fn on_bytes_downloaded(&self, _bytes: u64) {}
+
+    /// Called once a sync of `album` finished, with the number of files it downloaded
+    fn on_sync_completed(&self, _album: &str, _photos_synced: usize, _at: SystemTime) {}
+}
+
+fn installed() -> &'static RwLock<Option<Arc<dyn Metrics>>> {
+    static INSTALLED: OnceLock<RwLock<Option<Arc<dyn Metrics>>>> = OnceLock::new();
+    INSTALLED.get_or_init(|| RwLock::new(None))
+}
+
+/// Reports the crate's activity to `metrics`, replacing any earlier implementation
+pub fn install(metrics: Arc<dyn Metrics>) {
+    *installed().write().unwrap_or_else(|e| e.into_inner()) = Some(metrics);
+}
+
+/// Stops reporting the crate's activity
+pub fn uninstall() {
+    *installed().write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Calls `report` with the installed implementation, if any
+pub(crate) fn record(report: impl FnOnce(&dyn Metrics)) {
+    let metrics = installed()
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    if let Some(metrics) = metrics {
+        report(metrics.as_ref());
+    }
+}
+
+/// Counters of one album's syncs
+#[derive(Debug, Clone, Copy, Default)]
+struct AlbumCounters {
+    photos_synced: u64,
+    last_sync: Option<SystemTime>,
+}
+
+/// [`Metrics`] kept in memory and rendered in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct PrometheusMetrics {
+    api_calls: AtomicU64,
+    retries: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    albums: Mutex<BTreeMap<String, AlbumCounters>>,
+}
+
+impl PrometheusMetrics {
+    /// Create metrics with every counter at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of album API requests
+    pub fn api_calls(&self) -> u64 {
+        self.api_calls.load(Ordering::Relaxed)
+    }
+
+    /// Number of retried requests and downloads
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    /// Number of bytes downloaded
+    pub fn bytes_downloaded(&self) -> u64 {
+        self.bytes_downloaded.load(Ordering::Relaxed)
+    }
+
+    /// Number of files the syncs of `album` downloaded
+    pub fn photos_synced(&self, album: &str) -> u64 {
+        self.album(album).photos_synced
+    }
+
+    /// When the last sync of `album` finished
+    pub fn last_sync(&self, album: &str) -> Option<SystemTime> {
+        self.album(album).last_sync
+    }
+
+    fn album(&self, album: &str) -> AlbumCounters {
+        self.albums
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(album)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Renders the counters in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, help, value) in [
+            (
+                "icloud_album_api_calls_total",
+                "Requests to the album API",
+                self.api_calls(),
+            ),
+            (
+                "icloud_album_retries_total",
+                "Retried API requests and downloads",
+                self.retries(),
+            ),
+            (
+                "icloud_album_downloaded_bytes_total",
+                "Bytes of downloaded files",
+                self.bytes_downloaded(),
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        let albums = self.albums.lock().unwrap_or_else(|e| e.into_inner());
+        out.push_str("# HELP icloud_album_photos_synced_total Files downloaded by syncs\n");
+        out.push_str("# TYPE icloud_album_photos_synced_total counter\n");
+        for (album, counters) in albums.iter() {
+            let _ = writeln!(
+                out,
+                "icloud_album_photos_synced_total{{album=\"{}\"}} {}",
+                escape_label(album),
+                counters.photos_synced
+            );
+        }
+        out.push_str(
+            "# HELP icloud_album_last_sync_timestamp_seconds When the last sync finished\n",
+        );
+        out.push_str("# TYPE icloud_album_last_sync_timestamp_seconds gauge\n");
+        for (album, counters) in albums.iter() {
+            let Some(at) = counters.last_sync else {
+                continue;
+            };
+            let _ = writeln!(
+                out,
+                "icloud_album_last_sync_timestamp_seconds{{album=\"{}\"}} {}",
+                escape_label(album),
+                at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+            );
+        }
+        out
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn on_api_call(&self) {
+        self.api_calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_bytes_downloaded(&self, bytes: u64) {
+        self.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn on_sync_completed(&self, album: &str, photos_synced: usize, at: SystemTime) {
+        let mut albums = self.albums.lock().unwrap_or_else(|e| e.into_inner());
+        let counters = albums.entry(album.to_string()).or_default();
+        counters.photos_synced += photos_synced as u64;
+        counters.last_sync = Some(at);
+    }
+}
+
+/// Escapes a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,188 @@
+//! UniFFI bindings for Swift and Kotlin apps.
+//!
+//! This module is only compiled with the `uniffi` feature. It exports a
+//! [`SharedAlbum`] object that fetches an album and downloads its photos,
+//! reporting each finished file to a [`DownloadProgress`] implemented in the
+//! app, and throws [`AlbumError`] on failure:
+//!
+//! ```swift
+//! let album = try SharedAlbum.fetch(token: "B0z5qAGN1JIFd3y")
+//! for photo in album.photos() { print(photo.caption ?? photo.guid) }
+//! let paths = try album.download(outputDir: dir, progress: progressView)
+//! ```
+//!
+//! Calls block until they finish, so apps make them off the main thread. Error
+//! messages have tokens and signed URLs redacted.
+//!
+//! The crate builds as an `rlib` only. Build the shared library, then generate
+//! the Swift or Kotlin sources from it with the `uniffi-bindgen` binary of the
+//! `uniffi-bindgen` feature:
+//!
+//! ```text
+//! cargo rustc --lib --release --features uniffi --crate-type cdylib
+//! cargo run --features uniffi-bindgen --bin uniffi-bindgen -- generate \
+//!     --library target/release/libicloud_album_rs.so --language swift --out-dir bindings
+//! ```
+
+use crate::models::{ICloudResponse, Image};
+use crate::redact::Redacted;
+use crate::utils;
+use std::fmt;
+use std::sync::Arc;
+
+/// Error thrown by the bindings, a Swift `Error` or Kotlin exception
+#[derive(Debug, uniffi::Error)]
+pub enum AlbumError {
+    /// The album couldn't be fetched, e.g. for an unknown token or a network failure
+    Fetch {
+        /// What went wrong
+        message: String,
+    },
+    /// A photo couldn't be downloaded
+    Download {
+        /// The GUID of the photo
+        guid: String,
+        /// What went wrong
+        message: String,
+    },
+}
+
+impl fmt::Display for AlbumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlbumError::Fetch { message } => write!(f, "Failed to fetch album: {}", message),
+            AlbumError::Download { guid, message } => {
+                write!(f, "Failed to download photo {}: {}", guid, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AlbumError {}
+
+/// Receives download progress, implemented by the app
+#[uniffi::export(with_foreign)]
+pub trait DownloadProgress: Send + Sync {
+    /// Called after each photo is saved
+    ///
+    /// # Arguments
+    ///
+    /// * `completed` - Number of photos saved so far
+    /// * `total` - Number of photos being downloaded
+    /// * `path` - Where the photo was saved
+    fn photo_downloaded(&self, completed: u32, total: u32, path: String);
+}
+
+/// A photo of a fetched album
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct AlbumPhoto {
+    /// The photo's GUID
+    pub guid: String,
+    /// The caption, if any
+    pub caption: Option<String>,
+    /// When the photo was added to the album, as an ISO 8601 string
+    pub date_created: Option<String>,
+    /// Full name of the person who added the photo
+    pub contributor: Option<String>,
+    /// Width of the photo in pixels
+    pub width: Option<u32>,
+    /// Height of the photo in pixels
+    pub height: Option<u32>,
+    /// URL of the best available derivative, valid for a limited time
+    pub url: Option<String>,
+}
+
+impl From<&Image> for AlbumPhoto {
+    fn from(photo: &Image) -> Self {
+        AlbumPhoto {
+            guid: photo.photo_guid.clone(),
+            caption: photo.caption.clone(),
+            date_created: photo.date_created.clone(),
+            contributor: photo.contributor_full_name.clone(),
+            width: photo.width,
+            height: photo.height,
+            url: utils::select_best_derivative(&photo.derivatives).map(|(_, _, url)| url),
+        }
+    }
+}
+
+/// A shared album fetched with its photo URLs
+#[derive(uniffi::Object)]
+pub struct SharedAlbum {
+    response: ICloudResponse,
+}
+
+#[uniffi::export]
+impl SharedAlbum {
+    /// Fetches a shared album
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The iCloud shared album token
+    #[uniffi::constructor]
+    pub fn fetch(token: String) -> Result<Arc<Self>, AlbumError> {
+        let fetch_error = |message| AlbumError::Fetch { message };
+        let response = block_on(async { crate::get_icloud_photos(&token).await })
+            .map_err(fetch_error)?
+            .map_err(|e| fetch_error(Redacted(e).to_string()))?;
+        Ok(Arc::new(SharedAlbum { response }))
+    }
+
+    /// Returns the album's name
+    pub fn name(&self) -> String {
+        self.response.metadata.stream_name.clone()
+    }
+
+    /// Returns the album's photos
+    pub fn photos(&self) -> Vec<AlbumPhoto> {
+        self.response.photos.iter().map(AlbumPhoto::from).collect()
+    }
+
+    /// Downloads every photo into a directory, stopping at the first failure
+    ///
+    /// # Arguments
+    ///
+    /// * `output_dir` - Directory where the files should be saved
+    /// * `progress` - Told about each saved photo
+    ///
+    /// # Returns
+    ///
+    /// The paths of the saved files, in the order of [`SharedAlbum::photos`]
+    pub fn download(
+        &self,
+        output_dir: String,
+        progress: Option<Arc<dyn DownloadProgress>>,
+    ) -> Result<Vec<String>, AlbumError> {
+        let photos = &self.response.photos;
+        let total = photos.len() as u32;
+        block_on(async {
+            let mut paths = Vec::with_capacity(photos.len());
+            for (index, photo) in photos.iter().enumerate() {
+                let path = crate::download_photo(photo, Some(index), &output_dir, None)
+                    .await
+                    .map_err(|e| AlbumError::Download {
+                        guid: photo.photo_guid.clone(),
+                        message: Redacted(e).to_string(),
+                    })?;
+                if let Some(progress) = &progress {
+                    progress.photo_downloaded(index as u32 + 1, total, path.clone());
+                }
+                paths.push(path);
+            }
+            Ok(paths)
+        })
+        .map_err(|message| AlbumError::Download {
+            guid: String::new(),
+            message,
+        })?
+    }
+}
+
+/// Runs a future to completion on a dedicated runtime
+fn block_on<F: std::future::Future>(future: F) -> Result<F::Output, String> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map(|runtime| runtime.block_on(future))
+        .map_err(|e| format!("Failed to start async runtime: {}", e))
+}
@@ -5,7 +5,7 @@
 //! It handles serialization/deserialization and provides helper methods for
 //! working with the sometimes inconsistent response formats from Apple's API.
 
-use log::{log, Level};
+use log::Level;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
@@ -54,6 +54,7 @@ impl DeserializeContext {
     }
 
     /// Create a new context by extending the current one
+    #[must_use]
     pub fn extend(&self, context: &str) -> Self {
         let mut new_ctx = self.clone();
         new_ctx.push(context);
@@ -62,7 +63,13 @@ impl DeserializeContext {
 
     /// Logs a message with the current context
     pub fn log(&self, level: Level, message: &str) {
-        log!(level, "[Context: {}] {}", self, message);
+        crate::logging::log_at!(
+            crate::logging::SCHEMA,
+            level,
+            "[Context: {}] {}",
+            self,
+            message
+        );
     }
 }
 
@@ -75,7 +82,7 @@ impl fmt::Display for DeserializeContext {
 /// Helper module for deserializing/serializing fields that can be either strings or numbers
 /// iCloud API sometimes returns numbers as strings, so we need to handle both cases
 mod string_or_number {
-    use log::{trace, warn};
+    use crate::logging;
     use serde::de::{self, Visitor};
     use serde::{Deserializer, Serializer};
     use std::fmt;
@@ -123,13 +130,16 @@ mod string_or_number {
                     Ok(num) => Ok(Some(num)),
                     Err(e) => {
                         // Log the error with details and return None instead of failing
-                        warn!(
+                        logging::log_warn_repeated!(
+                            logging::SCHEMA,
+                            "Type inconsistency: string values that are not valid u64 numbers",
                             "Type inconsistency: Failed to parse string '{}' as u64: {}. \
                             This may indicate a change in API format. \
                             Using None as fallback, but this could lead to loss of data.",
-                            value, e
+                            value,
+                            e
                         );
-                        trace!("Parse error details: {:?}", e);
+                        logging::log_trace!(logging::SCHEMA, "Parse error details: {:?}", e);
                         Ok(None)
                     }
                 }
@@ -168,7 +178,7 @@ mod string_or_number {
 
 // Helper module for deserializing u32 values that can be strings or numbers
 mod string_or_u32 {
-    use log::{trace, warn};
+    use crate::logging;
     use serde::de::{self, Visitor};
     use serde::{Deserializer, Serializer};
     use std::fmt;
@@ -219,13 +229,16 @@ mod string_or_u32 {
                     Ok(num) => Ok(Some(num)),
                     Err(e) => {
                         // Log the error with details and return None instead of failing
-                        warn!(
+                        logging::log_warn_repeated!(
+                            logging::SCHEMA,
+                            "Type inconsistency: string values that are not valid u32 numbers",
                             "Type inconsistency: Failed to parse string '{}' as u32: {}. \
                             This may indicate a change in API format. \
                             Field will be treated as null, which may affect application behavior.",
-                            value, e
+                            value,
+                            e
                         );
-                        trace!("Parse error details: {:?}", e);
+                        logging::log_trace!(logging::SCHEMA, "Parse error details: {:?}", e);
                         Ok(None)
                     }
                 }
@@ -282,6 +295,89 @@ pub struct Derivative {
     pub height: Option<u32>,
     /// URL to download the image (populated later in the process)
     pub url: Option<String>,
+    /// Declared type of the file, e.g. `public.jpeg` or `video/mp4`, if the API sent one
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    /// Declared kind of asset, e.g. `image` or `video`, if the API sent one
+    #[serde(
+        rename = "mediaAssetType",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub media_asset_type: Option<String>,
+    /// Processing state of the derivative, e.g. `finished`, if the API sent one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+}
+
+/// Declared types of derivatives and the MIME types they stand for
+///
+/// The API has been seen to declare types both as uniform type identifiers
+/// and as MIME types; MIME types map to themselves.
+const DECLARED_MIME_TYPES: &[(&str, &str)] = &[
+    ("public.jpeg", "image/jpeg"),
+    ("public.png", "image/png"),
+    ("public.heic", "image/heic"),
+    ("public.heif", "image/heif"),
+    ("com.compuserve.gif", "image/gif"),
+    ("public.mpeg-4", "video/mp4"),
+    ("com.apple.quicktime-movie", "video/quicktime"),
+    ("image/jpeg", "image/jpeg"),
+    ("image/png", "image/png"),
+    ("image/heic", "image/heic"),
+    ("image/heif", "image/heif"),
+    ("image/gif", "image/gif"),
+    ("video/mp4", "video/mp4"),
+    ("video/quicktime", "video/quicktime"),
+];
+
+impl Derivative {
+    /// Returns the MIME type of the derivative's declared `type`
+    ///
+    /// # Returns
+    ///
+    /// The MIME type, or `None` if no type was declared or it isn't known
+    pub fn declared_mime_type(&self) -> Option<&'static str> {
+        let declared = self.media_type.as_deref()?.trim().to_lowercase();
+        DECLARED_MIME_TYPES
+            .iter()
+            .find(|(name, _)| *name == declared)
+            .map(|(_, mime_type)| *mime_type)
+    }
+
+    /// Whether the derivative declares itself a video
+    ///
+    /// `mediaAssetType` is consulted first, then the declared `type`.
+    ///
+    /// # Returns
+    ///
+    /// `Some(true)` for a video, `Some(false)` for a still image, or `None`
+    /// if the derivative declares neither
+    pub fn declares_video(&self) -> Option<bool> {
+        let asset_type = self
+            .media_asset_type
+            .as_deref()
+            .map(|asset_type| asset_type.trim().to_lowercase());
+        match asset_type.as_deref() {
+            Some("video") => Some(true),
+            Some("image" | "photo") => Some(false),
+            _ => self
+                .declared_mime_type()
+                .map(|mime_type| mime_type.starts_with("video/")),
+        }
+    }
+
+    /// Whether the derivative's declared state says it can't be downloaded yet
+    ///
+    /// Derivatives without a state are assumed to be ready.
+    pub fn is_pending(&self) -> bool {
+        self.state.as_deref().is_some_and(|state| {
+            matches!(
+                state.trim().to_lowercase().as_str(),
+                "pending" | "processing" | "uploading"
+            )
+        })
+    }
 }
 
 /// Represents an image in the iCloud shared album
@@ -300,6 +396,9 @@ pub struct Image {
     /// Batch creation date
     #[serde(rename = "batchDateCreated")]
     pub batch_date_created: Option<String>,
+    /// Full name of the person who added the photo to the album
+    #[serde(rename = "contributorFullName", default)]
+    pub contributor_full_name: Option<String>,
     /// Width of the original image in pixels
     #[serde(default)]
     #[serde(with = "string_or_u32")]
@@ -308,10 +407,356 @@ pub struct Image {
     #[serde(default)]
     #[serde(with = "string_or_u32")]
     pub height: Option<u32>,
+    /// Where the photo was taken, from the album's `locations` (set during enrichment)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    /// Human-readable place name from a [`crate::enrich::Geocoder`]
+    #[serde(rename = "placeName", default, skip_serializing_if = "Option::is_none")]
+    pub place_name: Option<String>,
+    /// Loading placeholder derived from the downloaded thumbnail (see `placeholder` module)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub placeholder: Option<Placeholder>,
+}
+
+/// What to show while an image loads
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Placeholder {
+    /// BlurHash of the image, see <https://blurha.sh>
+    pub blurhash: String,
+    /// Most common color of the image, as `#rrggbb`
+    #[serde(rename = "dominantColor")]
+    pub dominant_color: String,
+}
+
+/// Geographic coordinates of a photo
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Location {
+    /// Latitude in degrees, between -90 and 90
+    pub latitude: f64,
+    /// Longitude in degrees, between -180 and 180
+    pub longitude: f64,
+    /// Altitude in meters, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub altitude: Option<f64>,
+}
+
+impl Location {
+    /// Keys that have been observed to carry the latitude
+    const LATITUDE_KEYS: &'static [&'static str] = &["latitude", "lat"];
+    /// Keys that have been observed to carry the longitude
+    const LONGITUDE_KEYS: &'static [&'static str] = &["longitude", "lon", "lng"];
+    /// Keys that have been observed to carry the altitude
+    const ALTITUDE_KEYS: &'static [&'static str] = &["altitude", "alt"];
+
+    /// Parse a location from one entry of the `locations` field
+    ///
+    /// Coordinates may be numbers or numeric strings. Entries without both
+    /// coordinates, or with coordinates out of range, yield `None`.
+    pub fn from_value(data: &serde_json::Value) -> Option<Self> {
+        let latitude = Self::first_number(data, Self::LATITUDE_KEYS)?;
+        let longitude = Self::first_number(data, Self::LONGITUDE_KEYS)?;
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return None;
+        }
+        Some(Self {
+            latitude,
+            longitude,
+            altitude: Self::first_number(data, Self::ALTITUDE_KEYS),
+        })
+    }
+
+    fn first_number(data: &serde_json::Value, keys: &[&str]) -> Option<f64> {
+        keys.iter()
+            .find_map(|key| match data.get(*key)? {
+                serde_json::Value::Number(n) => n.as_f64(),
+                serde_json::Value::String(s) => s.trim().parse::<f64>().ok(),
+                _ => None,
+            })
+            .filter(|value| value.is_finite())
+    }
+}
+
+/// A calendar day, parsed from the ISO 8601 dates of the API
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct CalendarDate {
+    /// Year, e.g. 2023
+    pub year: i32,
+    /// Month, from 1 to 12
+    pub month: u32,
+    /// Day of the month, from 1 to 31
+    pub day: u32,
+}
+
+impl CalendarDate {
+    /// Create a date, returning `None` if it does not exist
+    pub fn new(year: i32, month: u32, day: u32) -> Option<Self> {
+        ((1..=12).contains(&month) && (1..=Self::days_in_month(year, month)).contains(&day))
+            .then_some(Self { year, month, day })
+    }
+
+    /// Parse the leading `YYYY-MM-DD` of an ISO 8601 date or date-time
+    ///
+    /// Any time and offset after the date are ignored.
+    pub fn parse(date: &str) -> Option<Self> {
+        let digits = |range: std::ops::Range<usize>| {
+            let part = date.get(range)?;
+            part.chars()
+                .all(|c| c.is_ascii_digit())
+                .then(|| part.parse::<u32>().ok())
+                .flatten()
+        };
+        if date.get(4..5) != Some("-") || date.get(7..8) != Some("-") {
+            return None;
+        }
+        if date.len() > 10 && !matches!(date.as_bytes()[10], b'T' | b't' | b' ') {
+            return None;
+        }
+        Self::new(digits(0..4)? as i32, digits(5..7)?, digits(8..10)?)
+    }
+
+    /// Number of days in a month of a year
+    fn days_in_month(year: i32, month: u32) -> u32 {
+        match month {
+            2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    /// Returns the UTC day of a Unix timestamp
+    pub fn from_timestamp(timestamp: i64) -> Self {
+        // Civil date from days since 1970-01-01 in the proleptic Gregorian calendar
+        let shifted = timestamp.div_euclid(86_400) + 719_468;
+        let era = shifted.div_euclid(146_097);
+        let day_of_era = shifted - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        Self {
+            year: (year_of_era + era * 400 + i64::from(month <= 2)) as i32,
+            month: month as u32,
+            day: day as u32,
+        }
+    }
+
+    /// Returns the following day
+    #[must_use]
+    pub fn next_day(&self) -> Self {
+        if self.day < Self::days_in_month(self.year, self.month) {
+            Self {
+                day: self.day + 1,
+                ..*self
+            }
+        } else if self.month < 12 {
+            Self {
+                month: self.month + 1,
+                day: 1,
+                ..*self
+            }
+        } else {
+            Self {
+                year: self.year + 1,
+                month: 1,
+                day: 1,
+            }
+        }
+    }
+}
+
+impl fmt::Display for CalendarDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// Parses an ISO 8601 date-time such as `2023-01-31T12:00:00Z` into Unix seconds
+///
+/// Fractional seconds are ignored. A missing offset is treated as UTC.
+pub fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    let date = CalendarDate::parse(timestamp)?;
+    let time = timestamp.get(11..)?;
+    let number = |range: std::ops::Range<usize>| {
+        let part = time.get(range)?;
+        part.chars()
+            .all(|c| c.is_ascii_digit())
+            .then(|| part.parse::<i64>().ok())
+            .flatten()
+    };
+    let (hour, minute, second) = (number(0..2)?, number(3..5)?, number(6..8)?);
+    if time.get(2..3) != Some(":") || time.get(5..6) != Some(":") {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // Skip fractional seconds, then read the offset
+    let rest = time[8..].trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    let offset = match rest {
+        "" | "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.get(0..1)? {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            let offset = rest.get(1..)?;
+            let (hours, minutes) = match offset.len() {
+                5 if offset.get(2..3) == Some(":") => (offset.get(0..2)?, offset.get(3..5)?),
+                4 => (offset.get(0..2)?, offset.get(2..4)?),
+                _ => return None,
+            };
+            sign * (hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60)
+        }
+    };
+
+    // Days since 1970-01-01 in the proleptic Gregorian calendar
+    let (year, month) = (date.year as i64, date.month as i64);
+    let shifted_year = if month <= 2 { year - 1 } else { year };
+    let era = shifted_year.div_euclid(400);
+    let year_of_era = shifted_year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + date.day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    Some(days * 86_400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Formats Unix seconds as an ISO 8601 UTC date-time such as `2023-01-31T12:00:00Z`
+pub fn format_timestamp(timestamp: i64) -> String {
+    let seconds = timestamp.rem_euclid(86_400);
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        CalendarDate::from_timestamp(timestamp),
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+impl Image {
+    /// Returns when the photo was taken, in Unix seconds
+    ///
+    /// Only `dateCreated` is used, since `batchDateCreated` is shared by every
+    /// photo uploaded together.
+    pub fn timestamp_taken(&self) -> Option<i64> {
+        self.date_created.as_deref().and_then(parse_timestamp)
+    }
+
+    /// Returns the day the photo was taken
+    ///
+    /// Uses `dateCreated`, falling back to `batchDateCreated` (when the photo
+    /// was added) if the former is missing or cannot be parsed.
+    pub fn date_taken(&self) -> Option<CalendarDate> {
+        self.date_created
+            .as_deref()
+            .and_then(CalendarDate::parse)
+            .or_else(|| {
+                self.batch_date_created
+                    .as_deref()
+                    .and_then(CalendarDate::parse)
+            })
+    }
+}
+
+/// Change tag of an album (`streamCtag`)
+///
+/// The tag is opaque: it changes whenever photos are added, removed or edited,
+/// and carries no ordering. An empty tag stands for an unknown version, e.g.
+/// from a response without the field, and never counts as unchanged, see
+/// [`StreamCtag::unchanged_since`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct StreamCtag(String);
+
+impl StreamCtag {
+    /// Create a tag from its string form
+    pub fn new(ctag: impl Into<String>) -> Self {
+        Self(ctag.into())
+    }
+
+    /// Parses a tag, returning `None` for blank input
+    ///
+    /// Surrounding whitespace is removed.
+    pub fn parse(ctag: &str) -> Option<Self> {
+        let ctag = ctag.trim();
+        (!ctag.is_empty()).then(|| Self::new(ctag))
+    }
+
+    /// The tag's string form
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether the tag is empty, i.e. the album version is unknown
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether the album is known to be unchanged since `previous` was seen
+    ///
+    /// True only if both tags are known and equal.
+    pub fn unchanged_since(&self, previous: Option<&StreamCtag>) -> bool {
+        !self.is_empty() && previous == Some(self)
+    }
+}
+
+impl fmt::Display for StreamCtag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for StreamCtag {
+    fn from(ctag: String) -> Self {
+        Self(ctag)
+    }
+}
+
+impl From<&str> for StreamCtag {
+    fn from(ctag: &str) -> Self {
+        Self::new(ctag)
+    }
+}
+
+impl From<StreamCtag> for String {
+    fn from(ctag: StreamCtag) -> Self {
+        ctag.0
+    }
+}
+
+impl AsRef<str> for StreamCtag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for StreamCtag {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for StreamCtag {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
 }
 
 /// Metadata about the iCloud shared album
+///
+/// Fields are added as responses reveal more; build values with
+/// [`Metadata::new`] rather than struct literals.
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[non_exhaustive]
 pub struct Metadata {
     /// Name of the shared album
     #[serde(rename = "streamName")]
@@ -324,13 +769,138 @@ pub struct Metadata {
     pub user_last_name: String,
     /// Stream change tag for tracking updates
     #[serde(rename = "streamCtag")]
-    pub stream_ctag: String,
+    pub stream_ctag: StreamCtag,
     /// Number of items returned in this response
     /// This value is converted from API's mixed string/number format
     #[serde(rename = "itemsReturned")]
     pub items_returned: u32,
     /// Location information for photos in the album
     pub locations: serde_json::Value,
+    /// Ownership and sharing details, where the response provides them
+    #[serde(default)]
+    pub sharing: SharingInfo,
+}
+
+/// Ownership and sharing details for an album
+///
+/// Apple's webstream response always carries the owner's first and last name.
+/// The fields here are a best-effort guess: Apple doesn't document them, the
+/// key names are candidates rather than a known schema, and most responses
+/// carry none of them. Tools following several albums can use them, when
+/// present, to attribute and group albums by owner.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct SharingInfo {
+    /// Stable identifier of the album owner, if the response includes one
+    #[serde(rename = "ownerId")]
+    pub owner_id: Option<String>,
+    /// Owner's full name as reported by the API, if present
+    #[serde(rename = "ownerFullName")]
+    pub owner_full_name: Option<String>,
+    /// Whether the album is shared publicly, if the response says so
+    #[serde(rename = "isPublic")]
+    pub is_public: Option<bool>,
+    /// Whether subscribers are allowed to contribute, if the response says so
+    #[serde(rename = "allowContributions")]
+    pub allow_contributions: Option<bool>,
+}
+
+impl SharingInfo {
+    /// Keys that may carry the owner identifier, tried in order
+    const OWNER_ID_KEYS: &'static [&'static str] = &["ownerDsid", "userDsid", "ownerId"];
+    /// Keys that may carry the owner's full name, tried in order
+    const OWNER_NAME_KEYS: &'static [&'static str] = &["ownerFullName", "userFullName"];
+    /// Keys that may carry the public sharing flag, tried in order
+    const PUBLIC_KEYS: &'static [&'static str] = &["isPublic", "publicAccess"];
+    /// Keys that may carry the contribution flag, tried in order
+    const CONTRIBUTION_KEYS: &'static [&'static str] =
+        &["allowContributions", "subscriberCanContribute"];
+
+    /// Extract sharing details from a raw webstream response
+    ///
+    /// Missing or unexpectedly typed fields are left as `None`. Identifiers that
+    /// arrive as numbers are converted to strings.
+    pub fn from_response(data: &serde_json::Value) -> Self {
+        Self {
+            owner_id: Self::first_string(data, Self::OWNER_ID_KEYS),
+            owner_full_name: Self::first_string(data, Self::OWNER_NAME_KEYS),
+            is_public: Self::first_bool(data, Self::PUBLIC_KEYS),
+            allow_contributions: Self::first_bool(data, Self::CONTRIBUTION_KEYS),
+        }
+    }
+
+    fn first_string(data: &serde_json::Value, keys: &[&str]) -> Option<String> {
+        keys.iter().find_map(|key| match data.get(*key)? {
+            serde_json::Value::String(s) if !s.is_empty() => Some(s.clone()),
+            serde_json::Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        })
+    }
+
+    fn first_bool(data: &serde_json::Value, keys: &[&str]) -> Option<bool> {
+        keys.iter().find_map(|key| match data.get(*key)? {
+            serde_json::Value::Bool(b) => Some(*b),
+            serde_json::Value::String(s) => match s.as_str() {
+                "true" | "1" => Some(true),
+                "false" | "0" => Some(false),
+                _ => None,
+            },
+            serde_json::Value::Number(n) => n.as_u64().map(|v| v != 0),
+            _ => None,
+        })
+    }
+}
+
+impl Metadata {
+    /// Create metadata for an album with no photos, locations or sharing details
+    ///
+    /// # Arguments
+    ///
+    /// * `stream_name` - Name of the album
+    /// * `user_first_name` - First name of the album owner
+    /// * `user_last_name` - Last name of the album owner
+    pub fn new(
+        stream_name: impl Into<String>,
+        user_first_name: impl Into<String>,
+        user_last_name: impl Into<String>,
+    ) -> Self {
+        Self {
+            stream_name: stream_name.into(),
+            user_first_name: user_first_name.into(),
+            user_last_name: user_last_name.into(),
+            stream_ctag: StreamCtag::default(),
+            items_returned: 0,
+            locations: serde_json::Value::Object(Default::default()),
+            sharing: SharingInfo::default(),
+        }
+    }
+
+    /// Returns the coordinates in `locations`, keyed by photo GUID
+    ///
+    /// Entries that do not carry valid coordinates are left out, so an album
+    /// without location data yields an empty map.
+    pub fn photo_locations(&self) -> HashMap<String, Location> {
+        match &self.locations {
+            serde_json::Value::Object(entries) => entries
+                .iter()
+                .filter_map(|(guid, entry)| Some((guid.clone(), Location::from_value(entry)?)))
+                .collect(),
+            _ => HashMap::new(),
+        }
+    }
+
+    /// Returns a key suitable for grouping albums by owner
+    ///
+    /// Uses the owner identifier when the response provided one, and falls back
+    /// to the owner's first and last name otherwise.
+    pub fn owner_key(&self) -> String {
+        match &self.sharing.owner_id {
+            Some(id) => id.clone(),
+            None => format!("{} {}", self.user_first_name, self.user_last_name)
+                .trim()
+                .to_string(),
+        }
+    }
 }
 
 /// Raw API response from the iCloud webstream endpoint
@@ -338,8 +908,9 @@ pub struct Metadata {
 pub struct ApiResponse {
     /// List of photos in the album
     pub photos: Vec<Image>,
-    /// List of photo GUIDs in the album
+    /// List of photo GUIDs in the album, absent from most responses
     #[serde(rename = "photoGuids")]
+    #[serde(default)]
     pub photo_guids: Vec<String>,
     /// Name of the shared album
     #[serde(rename = "streamName")]
@@ -352,7 +923,7 @@ pub struct ApiResponse {
     pub user_last_name: Option<String>,
     /// Stream change tag for tracking updates
     #[serde(rename = "streamCtag")]
-    pub stream_ctag: Option<String>,
+    pub stream_ctag: Option<StreamCtag>,
     /// Number of items returned in this response
     /// This field may come as either a string or a number from the API
     #[serde(rename = "itemsReturned")]
@@ -363,11 +934,111 @@ pub struct ApiResponse {
     pub locations: Option<serde_json::Value>,
 }
 
+/// Request payload for the webstream endpoint
+///
+/// The default request serializes to `{"streamCtag": null}`, which asks for the
+/// full album. Callers can pass the `streamCtag` from a previous response, and
+/// any additional (e.g. paging) parameters are merged into the top-level payload.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct WebstreamRequest {
+    /// Stream change tag from a previous response, or `None` for a full fetch
+    #[serde(rename = "streamCtag")]
+    pub stream_ctag: Option<StreamCtag>,
+    /// Additional parameters sent alongside `streamCtag`
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl WebstreamRequest {
+    /// Create a request for the full album (null `streamCtag`)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the `streamCtag` to send with the request
+    #[must_use]
+    pub fn with_ctag(mut self, ctag: impl Into<StreamCtag>) -> Self {
+        self.stream_ctag = Some(ctag.into());
+        self
+    }
+
+    /// Add an extra top-level parameter to the payload
+    #[must_use]
+    pub fn with_param(mut self, key: impl Into<String>, value: serde_json::Value) -> Self {
+        self.extra.insert(key.into(), value);
+        self
+    }
+
+    /// Serialize the request into the JSON payload sent to the API
+    pub fn to_payload(&self) -> serde_json::Value {
+        let mut payload = self.extra.clone();
+        payload.insert(
+            "streamCtag".to_string(),
+            match &self.stream_ctag {
+                Some(ctag) => serde_json::Value::String(ctag.to_string()),
+                None => serde_json::Value::Null,
+            },
+        );
+        serde_json::Value::Object(payload)
+    }
+}
+
+/// An item of the webstream `photos` array that failed to parse
+///
+/// Kept so consumers can still count the item or show a placeholder for it,
+/// e.g. for a new kind of asset this crate doesn't understand yet.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct UnparsedItem {
+    /// Index of the item in the `photos` array
+    pub index: usize,
+    /// The item as sent by the API
+    pub raw: serde_json::Value,
+    /// Why the item failed to parse
+    pub error: String,
+}
+
 /// Final response with processed photos and metadata
-#[derive(Debug, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ICloudResponse {
     /// Metadata about the album
     pub metadata: Metadata,
     /// Processed photos with URLs populated
     pub photos: Vec<Image>,
+    /// Items that failed to parse, left out of `photos`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unparsed: Vec<UnparsedItem>,
+}
+
+/// HTTP cache validators returned with a downloaded asset
+///
+/// Persist these alongside a downloaded file and pass them back on the next
+/// download of the same asset, so an unchanged file can be confirmed with a
+/// `304 Not Modified` response instead of transferring it again.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct HttpValidators {
+    /// Value of the `ETag` response header
+    pub etag: Option<String>,
+    /// Value of the `Last-Modified` response header
+    pub last_modified: Option<String>,
+}
+
+impl HttpValidators {
+    /// Returns true if neither validator is known
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Result of a conditional download
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadOutcome {
+    /// The asset was transferred and saved
+    Downloaded {
+        /// Path of the saved file
+        path: String,
+        /// Validators to send with the next download of the asset
+        validators: HttpValidators,
+    },
+    /// The server confirmed the previously downloaded asset is unchanged
+    NotModified,
 }
@@ -0,0 +1,248 @@
+//! Network settings for the HTTP clients used by the crate.
+//!
+//! Some networks advertise IPv6 connectivity but can't route it to iCloud's
+//! CDN hosts. Connection attempts over IPv6 then hang until the operating
+//! system gives up, long before the IPv4 fallback is tried. A
+//! [`NetworkConfig`] can restrict connections to one address family, bound
+//! the connect phase with a timeout, pin host names to fixed addresses, and
+//! plug in a custom DNS resolver.
+//!
+//! Static host mappings help in sandboxed or split-DNS environments where the
+//! `pNN-sharedstreams.icloud.com` partition hosts don't resolve. They can be
+//! read from configuration as `host=ip` strings with [`parse_host_mapping`].
+//! Remember to map both the partition host derived from the token and the
+//! host the API redirects to.
+//!
+//! The settings are applied to a [`reqwest::ClientBuilder`], so they combine
+//! with other client options, e.g. those of
+//! [`crate::url_policy::UrlPolicy::client_builder`]. Downloads pick them up
+//! from [`crate::download::DownloadOptions::with_network`], and album fetches
+//! through [`crate::fetch::FetchOptions::with_client`].
+
+use reqwest::dns::Resolve;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The IP versions connections may use
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddressFamily {
+    /// Use both, trying the other family shortly after the first one stalls
+    #[default]
+    Any,
+    /// Only connect over IPv4
+    Ipv4Only,
+    /// Only connect over IPv6
+    Ipv6Only,
+}
+
+/// Error returned when a `host=ip` mapping can't be parsed
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum HostMappingError {
+    /// The mapping is not of the form `host=ip`
+    #[error("Host mapping is not of the form host=ip: {0}")]
+    Malformed(String),
+    /// The address of the mapping is not an IP address
+    #[error("Invalid IP address in host mapping: {0}")]
+    InvalidAddress(String),
+}
+
+/// Parses a static host mapping of the form `host=ip`
+///
+/// Whitespace around the host and address is ignored. IPv6 addresses may be
+/// given with or without brackets.
+///
+/// # Arguments
+///
+/// * `mapping` - The mapping, e.g. `p23-sharedstreams.icloud.com=17.248.128.1`
+///
+/// # Returns
+///
+/// The host name and its address
+pub fn parse_host_mapping(mapping: &str) -> Result<(String, IpAddr), HostMappingError> {
+    let (host, address) = mapping
+        .split_once('=')
+        .ok_or_else(|| HostMappingError::Malformed(mapping.to_string()))?;
+    let host = host.trim();
+    if host.is_empty() {
+        return Err(HostMappingError::Malformed(mapping.to_string()));
+    }
+    let address = address.trim();
+    let bare = address
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .unwrap_or(address);
+    let ip = IpAddr::from_str(bare)
+        .map_err(|_| HostMappingError::InvalidAddress(address.to_string()))?;
+    Ok((host.to_string(), ip))
+}
+
+/// Installs a custom resolver on a client builder
+type ResolverHook = Arc<dyn Fn(reqwest::ClientBuilder) -> reqwest::ClientBuilder + Send + Sync>;
+
+/// Connection settings for HTTP clients
+///
+/// The default changes nothing about how clients connect.
+#[derive(Clone, Default)]
+pub struct NetworkConfig {
+    /// The IP versions connections may use
+    pub address_family: AddressFamily,
+    /// Maximum time to establish a connection, or `None` for the system default
+    pub connect_timeout: Option<Duration>,
+    /// Fixed addresses for host names, bypassing DNS for them
+    pub host_addresses: Vec<(String, Vec<SocketAddr>)>,
+    /// Cookie jar that clients send and store cookies with, or `None` to ignore cookies
+    #[cfg(feature = "cookies")]
+    pub cookies: Option<Arc<crate::cookies::CookieJar>>,
+    resolver: Option<ResolverHook>,
+}
+
+impl fmt::Debug for NetworkConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("NetworkConfig");
+        debug
+            .field("address_family", &self.address_family)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("host_addresses", &self.host_addresses);
+        #[cfg(feature = "cookies")]
+        debug.field("cookies", &self.cookies);
+        debug
+            .field("resolver", &self.resolver.as_ref().map(|_| "custom"))
+            .finish()
+    }
+}
+
+impl NetworkConfig {
+    /// Create settings that change nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create settings that only connect over IPv4, giving up after `connect_timeout`
+    ///
+    /// This is the usual fix for networks with broken IPv6 routes.
+    pub fn ipv4_only(connect_timeout: Duration) -> Self {
+        Self::new()
+            .with_address_family(AddressFamily::Ipv4Only)
+            .with_connect_timeout(connect_timeout)
+    }
+
+    /// Restrict connections to an address family
+    #[must_use]
+    pub fn with_address_family(mut self, address_family: AddressFamily) -> Self {
+        self.address_family = address_family;
+        self
+    }
+
+    /// Give up on connections that take longer than `timeout` to establish
+    #[must_use]
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Connect to `addresses` for `host` instead of resolving it
+    ///
+    /// The port of each address is replaced with the port of the request URL.
+    #[must_use]
+    pub fn with_host_addresses(mut self, host: &str, addresses: &[SocketAddr]) -> Self {
+        self.host_addresses
+            .push((host.to_string(), addresses.to_vec()));
+        self
+    }
+
+    /// Connect to `ip` for `host` instead of resolving it
+    #[must_use]
+    pub fn with_host_ip(self, host: &str, ip: IpAddr) -> Self {
+        self.with_host_addresses(host, &[SocketAddr::new(ip, 0)])
+    }
+
+    /// Adds static host mappings of the form `host=ip`
+    ///
+    /// Several mappings for the same host give it several addresses.
+    ///
+    /// # Arguments
+    ///
+    /// * `mappings` - The mappings, see [`parse_host_mapping`]
+    ///
+    /// # Returns
+    ///
+    /// The settings with the mappings added, or the first mapping that failed to parse
+    pub fn with_host_mappings<'a>(
+        mut self,
+        mappings: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self, HostMappingError> {
+        for mapping in mappings {
+            let (host, ip) = parse_host_mapping(mapping)?;
+            let address = SocketAddr::new(ip, 0);
+            match self.host_addresses.iter_mut().find(|(h, _)| *h == host) {
+                Some((_, addresses)) => addresses.push(address),
+                None => self.host_addresses.push((host, vec![address])),
+            }
+        }
+        Ok(self)
+    }
+
+    /// Send and store cookies with `jar`
+    ///
+    /// Share the jar between configurations to use the same cookies for API
+    /// requests and downloads, and save it when done, see [`crate::cookies`].
+    #[cfg(feature = "cookies")]
+    #[must_use]
+    pub fn with_cookies(mut self, jar: Arc<crate::cookies::CookieJar>) -> Self {
+        self.cookies = Some(jar);
+        self
+    }
+
+    /// Resolve host names with a custom resolver
+    ///
+    /// The address family restriction still applies to the addresses it returns.
+    #[must_use]
+    pub fn with_resolver<R: Resolve + 'static>(mut self, resolver: Arc<R>) -> Self {
+        self.resolver = Some(Arc::new(move |builder: reqwest::ClientBuilder| {
+            builder.dns_resolver(Arc::clone(&resolver))
+        }));
+        self
+    }
+
+    /// Applies the settings to a client builder
+    ///
+    /// # Arguments
+    ///
+    /// * `builder` - The builder to configure
+    ///
+    /// # Returns
+    ///
+    /// The configured builder
+    pub fn apply(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        // Binding to an unspecified local address of one family makes the
+        // connector skip resolved addresses of the other family
+        builder = match self.address_family {
+            AddressFamily::Any => builder,
+            AddressFamily::Ipv4Only => builder.local_address(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            AddressFamily::Ipv6Only => builder.local_address(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+        };
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(resolver) = &self.resolver {
+            builder = resolver(builder);
+        }
+        for (host, addresses) in &self.host_addresses {
+            builder = builder.resolve_to_addrs(host, addresses);
+        }
+        #[cfg(feature = "cookies")]
+        if let Some(jar) = &self.cookies {
+            builder = builder.cookie_provider(Arc::clone(jar));
+        }
+        builder
+    }
+
+    /// Builds a client with these settings
+    pub fn build_client(&self) -> reqwest::Result<reqwest::Client> {
+        self.apply(reqwest::Client::builder()).build()
+    }
+}
@@ -0,0 +1,90 @@
+//! Node.js bindings built with napi-rs.
+//!
+//! This module is only compiled with the `napi` feature. It exposes the same
+//! fetch and download surface as the C layer of the `ffi` feature to Node.js,
+//! so the JavaScript shared album libraries can use this crate as their
+//! engine. Functions return promises and exchange albums and photos as plain
+//! objects of the JSON shape of [`crate::models::ICloudResponse`]:
+//!
+//! ```js
+//! const { fetchAlbum, downloadPhoto } = require("./icloud_album.node");
+//!
+//! const album = await fetchAlbum("B0z5qAGN1JIFd3y");
+//! for (const photo of album.photos) {
+//!   console.log(await downloadPhoto(photo, "downloads"));
+//! }
+//! ```
+//!
+//! Errors reject the promise with the crate's error message, with tokens and
+//! signed URLs redacted. The crate builds as an `rlib` only; the addon is a
+//! separate artifact, built as a shared library and renamed to `.node`:
+//!
+//! ```text
+//! cargo rustc --lib --release --features napi --crate-type cdylib
+//! cp target/release/libicloud_album_rs.so icloud_album.node
+//! ```
+//!
+//! (`.dylib` on macOS, `icloud_album_rs.dll` on Windows.) The N-API symbols
+//! are looked up in the host process when the addon loads, so the crate's
+//! tests and binaries still link with the feature enabled.
+
+use crate::models::Image;
+use crate::redact::Redacted;
+use napi_derive::napi;
+use serde_json::Value;
+use std::error::Error;
+use std::future::Future;
+
+/// Converts an error into a rejection of the calling promise
+fn to_napi_error(error: impl std::fmt::Display) -> napi::Error {
+    napi::Error::from_reason(Redacted(error).to_string())
+}
+
+/// Runs the future built by `call` on a dedicated runtime on the blocking pool
+///
+/// The crate's futures aren't `Send`, so they can't run on napi-rs' runtime
+/// directly. Errors are converted to strings on that thread.
+async fn run<T, F, Fut>(call: F) -> napi::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<T, Box<dyn Error>>>,
+{
+    tokio::task::spawn_blocking(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| format!("Failed to start async runtime: {}", e))?;
+        runtime
+            .block_on(call())
+            .map_err(|e| Redacted(e).to_string())
+    })
+    .await
+    .map_err(to_napi_error)?
+    .map_err(napi::Error::from_reason)
+}
+
+/// Fetches a shared album, resolving to `{ metadata, photos }`
+///
+/// # Arguments
+///
+/// * `token` - The iCloud shared album token
+#[napi]
+pub async fn fetch_album(token: String) -> napi::Result<Value> {
+    let response = run(move || async move { crate::get_icloud_photos(&token).await }).await?;
+    serde_json::to_value(&response).map_err(to_napi_error)
+}
+
+/// Downloads a photo of a fetched album, resolving to the saved file path
+///
+/// # Arguments
+///
+/// * `photo` - One element of the `photos` array of [`fetch_album`], so that
+///   its derivatives carry download URLs
+/// * `output_dir` - Directory where the file should be saved
+#[napi]
+pub async fn download_photo(photo: Value, output_dir: String) -> napi::Result<String> {
+    let photo: Image = serde_json::from_value(photo)
+        .map_err(|e| napi::Error::from_reason(format!("Invalid photo: {}", e)))?;
+    run(move || async move { crate::download_photo(&photo, None, &output_dir, None).await }).await
+}
@@ -0,0 +1,77 @@
+//! Progress callbacks for the album fetch pipeline.
+//!
+//! This module defines the [`PipelineObserver`] trait, which lets callers hook
+//! into each stage of a fetch through [`crate::fetch::FetchOptions::with_observer`].
+//! It is intended for progress UIs and for debugging which stage of the
+//! pipeline is slow or failing.
+
+use crate::api::ResponseHeaders;
+use crate::models::Metadata;
+use std::sync::Arc;
+
+/// Receives notifications as the fetch pipeline moves through its stages
+///
+/// All methods have empty default implementations, so implementors only need
+/// to override the stages they are interested in. Hooks are invoked in the
+/// order the stages run: base URL, redirect, response headers, metadata,
+/// photos, URLs.
+pub trait PipelineObserver: Send + Sync {
+    /// Called once the base URL has been computed from the token
+    fn on_base_url(&self, _base_url: &str) {}
+
+    /// Called once redirects have been resolved, with the URL that will be used for API calls
+    ///
+    /// This is invoked even when no redirect occurred, in which case the URL
+    /// is identical to the one passed to `on_base_url`.
+    fn on_redirect(&self, _redirected_url: &str) {}
+
+    /// Called with the diagnostic headers of the webstream response
+    ///
+    /// Worth logging when a fetch misbehaves: they include Apple's identifiers
+    /// for the request and the server that answered it.
+    fn on_response_headers(&self, _headers: &ResponseHeaders) {}
+
+    /// Called once the album metadata has been fetched
+    fn on_metadata(&self, _metadata: &Metadata) {}
+
+    /// Called with the number of photos successfully parsed from the webstream response
+    fn on_photos_parsed(&self, _count: usize) {}
+
+    /// Called with the number of asset URLs returned by the webasseturls endpoint
+    fn on_urls_resolved(&self, _count: usize) {}
+}
+
+/// An observer that ignores every notification
+///
+/// Used by [`crate::get_icloud_photos`] when no observer is supplied.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl PipelineObserver for NoopObserver {}
+
+/// Shared observers, so callers can keep a handle to inspect after the fetch
+impl<T: PipelineObserver + ?Sized> PipelineObserver for Arc<T> {
+    fn on_base_url(&self, base_url: &str) {
+        (**self).on_base_url(base_url)
+    }
+
+    fn on_redirect(&self, redirected_url: &str) {
+        (**self).on_redirect(redirected_url)
+    }
+
+    fn on_response_headers(&self, headers: &ResponseHeaders) {
+        (**self).on_response_headers(headers)
+    }
+
+    fn on_metadata(&self, metadata: &Metadata) {
+        (**self).on_metadata(metadata)
+    }
+
+    fn on_photos_parsed(&self, count: usize) {
+        (**self).on_photos_parsed(count)
+    }
+
+    fn on_urls_resolved(&self, count: usize) {
+        (**self).on_urls_resolved(count)
+    }
+}
@@ -0,0 +1,236 @@
+//! Packing many small downloaded files into one.
+//!
+//! Archiving thousands of thumbnails as individual files is slow on
+//! filesystems that handle many small files poorly, such as network mounts
+//! and cloud-synced folders. A [`Packfile`] stores them back to back in one
+//! data file, with an index of where each one starts. Installed through
+//! [`crate::download::DownloadOptions::with_packfile`], it receives the size
+//! classes chosen there instead of individual files; [`Packfile::read`] reads
+//! them back and [`Packfile::extract`] unpacks them.
+//!
+//! The index is kept next to the data file as `<pack>.idx.json` and replaced
+//! atomically by [`Packfile::flush`]. Bytes appended since the last flush are
+//! not indexed yet; opening the pack again cuts them off, so a crash loses at
+//! most the entries of the interrupted pass.
+//!
+//! The pack is read and written with `std::fs`; downloads append to it from
+//! tokio's blocking pool.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A file stored in a [`Packfile`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackEntry {
+    /// File name of the entry, e.g. `ABC123_thumb.jpg`
+    pub name: String,
+    /// GUID of the photo the entry belongs to
+    pub photo_guid: String,
+    /// Position of the entry's first byte in the data file
+    pub offset: u64,
+    /// Size of the entry in bytes
+    pub length: u64,
+}
+
+/// The index file of a pack
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PackIndex {
+    entries: Vec<PackEntry>,
+}
+
+/// The open data file and its entries
+#[derive(Debug)]
+struct PackState {
+    file: File,
+    entries: Vec<PackEntry>,
+    by_name: HashMap<String, usize>,
+    end: u64,
+}
+
+/// Many small files stored in one data file with an index
+#[derive(Debug)]
+pub struct Packfile {
+    path: PathBuf,
+    state: Mutex<PackState>,
+}
+
+impl Packfile {
+    /// Opens the pack at `path`, creating it if needed
+    ///
+    /// Bytes past the last indexed entry, left by a pass that didn't flush,
+    /// are removed.
+    ///
+    /// # Returns
+    ///
+    /// The pack, or an error if it can't be opened or its index doesn't
+    /// match the data file
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let index: PackIndex = match std::fs::read(Self::index_path_of(&path)) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => PackIndex::default(),
+            Err(e) => return Err(e),
+        };
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let end = index
+            .entries
+            .iter()
+            .map(|entry| entry.offset + entry.length)
+            .max()
+            .unwrap_or(0);
+        if file.metadata()?.len() < end {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is shorter than its index", path.display()),
+            ));
+        }
+        file.set_len(end)?;
+        let by_name = index
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.name.clone(), i))
+            .collect();
+        Ok(Self {
+            path,
+            state: Mutex::new(PackState {
+                file,
+                entries: index.entries,
+                by_name,
+                end,
+            }),
+        })
+    }
+
+    /// Path of the data file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of the index file
+    pub fn index_path(&self) -> PathBuf {
+        Self::index_path_of(&self.path)
+    }
+
+    /// Number of entries
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Whether the pack has no entries
+    pub fn is_empty(&self) -> bool {
+        self.lock().entries.is_empty()
+    }
+
+    /// Returns the entries, in the order they were first added
+    pub fn entries(&self) -> Vec<PackEntry> {
+        self.lock().entries.clone()
+    }
+
+    /// Returns the entry called `name`, if any
+    pub fn entry(&self, name: &str) -> Option<PackEntry> {
+        let state = self.lock();
+        state
+            .by_name
+            .get(name)
+            .map(|&index| state.entries[index].clone())
+    }
+
+    /// Appends a file to the pack
+    ///
+    /// An entry of the same name is replaced; its old bytes stay in the data
+    /// file. The entry is indexed on the next [`Packfile::flush`].
+    pub fn append(&self, name: &str, photo_guid: &str, content: &[u8]) -> io::Result<PackEntry> {
+        let mut state = self.lock();
+        let offset = state.end;
+        state.file.seek(SeekFrom::Start(offset))?;
+        state.file.write_all(content)?;
+        state.end += content.len() as u64;
+
+        let entry = PackEntry {
+            name: name.to_string(),
+            photo_guid: photo_guid.to_string(),
+            offset,
+            length: content.len() as u64,
+        };
+        match state.by_name.get(name).copied() {
+            Some(index) => state.entries[index] = entry.clone(),
+            None => {
+                let index = state.entries.len();
+                state.entries.push(entry.clone());
+                state.by_name.insert(name.to_string(), index);
+            }
+        }
+        Ok(entry)
+    }
+
+    /// Reads the file called `name`, or `None` if the pack has no such entry
+    pub fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let mut state = self.lock();
+        let Some(&index) = state.by_name.get(name) else {
+            return Ok(None);
+        };
+        let PackEntry { offset, length, .. } = state.entries[index];
+        let mut content = vec![0; length as usize];
+        state.file.seek(SeekFrom::Start(offset))?;
+        state.file.read_exact(&mut content)?;
+        Ok(Some(content))
+    }
+
+    /// Syncs the data file and writes the index
+    pub fn flush(&self) -> io::Result<()> {
+        let state = self.lock();
+        state.file.sync_data()?;
+        let index = serde_json::to_vec(&PackIndex {
+            entries: state.entries.clone(),
+        })
+        .map_err(io::Error::other)?;
+        let index_path = self.index_path();
+        let partial = index_path.with_extension("json.tmp");
+        std::fs::write(&partial, index)?;
+        std::fs::rename(&partial, &index_path)
+    }
+
+    /// Writes every entry as a file in `dir`
+    ///
+    /// # Returns
+    ///
+    /// The paths of the written files, in entry order
+    pub fn extract(&self, dir: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let mut written = Vec::new();
+        for entry in self.entries() {
+            // Names come from file name templates, which can't contain separators
+            let path = dir.join(&entry.name);
+            let content = self.read(&entry.name)?.unwrap_or_default();
+            std::fs::write(&path, content)?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+
+    fn index_path_of(path: &Path) -> PathBuf {
+        let mut index = path.as_os_str().to_owned();
+        index.push(".idx.json");
+        PathBuf::from(index)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PackState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,305 @@
+//! Perceptual hashing of downloaded images for near-duplicate detection.
+//!
+//! Checksums only identify byte-identical files; the same shot re-encoded or
+//! resized by a contributor gets a new checksum. Perceptual hashes summarize
+//! what an image looks like in 64 bits, so visually identical photos end up a
+//! small Hamming distance apart (see [`near_duplicate_report`]).
+//!
+//! Images are decoded through the [`ImageDecoder`] trait.
+//! [`crate::codec::StandardDecoder`] reads JPEG, PNG and binary PGM/PPM files
+//! with the `image` crate, and applications can implement the trait with the
+//! decoding library they already use; [`PnmDecoder`] only handles binary PGM
+//! and PPM files. Files are read and decoded synchronously.
+
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Default maximum Hamming distance for two hashes to count as near-duplicates
+pub const DEFAULT_THRESHOLD: u32 = 10;
+
+/// An 8-bit grayscale image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrayImage {
+    /// Width in pixels
+    pub width: usize,
+    /// Height in pixels
+    pub height: usize,
+    /// Row-major luminance values, `width * height` of them
+    pub pixels: Vec<u8>,
+}
+
+impl GrayImage {
+    /// Create an image, returning `None` if it is empty or `pixels` has the wrong length
+    pub fn new(width: usize, height: usize, pixels: Vec<u8>) -> Option<Self> {
+        (width > 0 && height > 0 && width.checked_mul(height) == Some(pixels.len())).then_some(
+            Self {
+                width,
+                height,
+                pixels,
+            },
+        )
+    }
+
+    /// Downscales the image to `width` x `height` by averaging the covered pixels
+    fn resize(&self, width: usize, height: usize) -> Vec<f64> {
+        let mut resized = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let y0 = y * self.height / height;
+            let y1 = ((y + 1) * self.height / height).max(y0 + 1);
+            for x in 0..width {
+                let x0 = x * self.width / width;
+                let x1 = ((x + 1) * self.width / width).max(x0 + 1);
+                let mut sum = 0.0;
+                for row in y0..y1 {
+                    let start = row * self.width;
+                    sum += self.pixels[start + x0..start + x1]
+                        .iter()
+                        .map(|&p| p as f64)
+                        .sum::<f64>();
+                }
+                resized.push(sum / ((y1 - y0) * (x1 - x0)) as f64);
+            }
+        }
+        resized
+    }
+}
+
+/// Error returned by an [`ImageDecoder`]
+pub type DecodeError = Box<dyn Error + Send + Sync>;
+
+/// Decodes image files into grayscale pixels
+pub trait ImageDecoder: Send + Sync {
+    /// Decodes the contents of an image file
+    fn decode(&self, bytes: &[u8]) -> Result<GrayImage, DecodeError>;
+}
+
+/// Decoder for binary PGM (`P5`) and PPM (`P6`) images with 8-bit samples
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PnmDecoder;
+
+impl ImageDecoder for PnmDecoder {
+    fn decode(&self, bytes: &[u8]) -> Result<GrayImage, DecodeError> {
+        let image = crate::pnm::parse(bytes)?;
+        let pixels = image
+            .samples
+            .chunks_exact(image.channels)
+            .map(|px| match px {
+                [r, g, b] => ((*r as u32 * 299 + *g as u32 * 587 + *b as u32 * 114) / 1000) as u8,
+                [gray] => *gray,
+                _ => unreachable!("chunks_exact yields {} samples", image.channels),
+            })
+            .collect();
+        GrayImage::new(image.width, image.height, pixels).ok_or_else(|| "Image is empty".into())
+    }
+}
+
+/// Perceptual hash algorithms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    /// Difference hash: compares neighbouring pixels of a 9x8 thumbnail
+    DHash,
+    /// DCT hash: compares low-frequency DCT coefficients of a 32x32 thumbnail
+    PHash,
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HashAlgorithm::DHash => "dhash",
+            HashAlgorithm::PHash => "phash",
+        })
+    }
+}
+
+/// A 64-bit perceptual hash, tagged with the algorithm that produced it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PerceptualHash {
+    /// Algorithm that produced the hash
+    pub algorithm: HashAlgorithm,
+    /// The hash bits
+    pub bits: u64,
+}
+
+impl PerceptualHash {
+    /// Returns the number of differing bits, or `None` for hashes of different algorithms
+    pub fn distance(&self, other: &PerceptualHash) -> Option<u32> {
+        (self.algorithm == other.algorithm).then(|| (self.bits ^ other.bits).count_ones())
+    }
+}
+
+impl fmt::Display for PerceptualHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{:016x}", self.algorithm, self.bits)
+    }
+}
+
+/// Computes the difference hash of an image
+pub fn dhash(image: &GrayImage) -> u64 {
+    let thumbnail = image.resize(9, 8);
+    let mut bits = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            bits <<= 1;
+            if thumbnail[y * 9 + x] < thumbnail[y * 9 + x + 1] {
+                bits |= 1;
+            }
+        }
+    }
+    bits
+}
+
+/// Computes the DCT hash of an image
+pub fn phash(image: &GrayImage) -> u64 {
+    const SIZE: usize = 32;
+    const LOW: usize = 8;
+    let thumbnail = image.resize(SIZE, SIZE);
+
+    // Only the top-left 8x8 coefficients of the 2D DCT-II are needed
+    let cosines: Vec<f64> = (0..LOW)
+        .flat_map(|u| {
+            (0..SIZE).map(move |x| {
+                (std::f64::consts::PI * (2 * x + 1) as f64 * u as f64 / (2 * SIZE) as f64).cos()
+            })
+        })
+        .collect();
+    let mut coefficients = [0.0f64; LOW * LOW];
+    for v in 0..LOW {
+        for u in 0..LOW {
+            let mut sum = 0.0;
+            for y in 0..SIZE {
+                let row = &thumbnail[y * SIZE..(y + 1) * SIZE];
+                let horizontal: f64 = row
+                    .iter()
+                    .zip(&cosines[u * SIZE..(u + 1) * SIZE])
+                    .map(|(p, c)| p * c)
+                    .sum();
+                sum += horizontal * cosines[v * SIZE + y];
+            }
+            coefficients[v * LOW + u] = sum;
+        }
+    }
+
+    // Compare against the median, leaving out the DC term which only reflects brightness
+    let mut sorted: Vec<f64> = coefficients[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+    coefficients
+        .iter()
+        .fold(0u64, |bits, &c| (bits << 1) | u64::from(c > median))
+}
+
+/// Computes a perceptual hash of an image
+pub fn hash_image(image: &GrayImage, algorithm: HashAlgorithm) -> PerceptualHash {
+    let bits = match algorithm {
+        HashAlgorithm::DHash => dhash(image),
+        HashAlgorithm::PHash => phash(image),
+    };
+    PerceptualHash { algorithm, bits }
+}
+
+/// Reads, decodes and hashes an image file
+pub fn hash_file(
+    decoder: &dyn ImageDecoder,
+    algorithm: HashAlgorithm,
+    path: &Path,
+) -> Result<PerceptualHash, DecodeError> {
+    let bytes = std::fs::read(path)?;
+    Ok(hash_image(&decoder.decode(&bytes)?, algorithm))
+}
+
+/// Near-duplicate images found across a set of files
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NearDuplicateReport {
+    /// Groups of at least two visually similar files, in input order
+    pub groups: Vec<Vec<PathBuf>>,
+    /// Files that could not be hashed, with the error message
+    pub failures: Vec<(PathBuf, String)>,
+}
+
+/// Groups hashed items whose hashes are within `threshold` bits of each other
+///
+/// Grouping is transitive: if A is close to B and B to C, all three form one
+/// group even when A and C are further apart.
+///
+/// # Arguments
+///
+/// * `hashes` - Items with their hashes
+/// * `threshold` - Maximum Hamming distance between near-duplicates
+///
+/// # Returns
+///
+/// Groups of at least two items, each in input order
+pub fn group_near_duplicates<T: Clone>(
+    hashes: &[(T, PerceptualHash)],
+    threshold: u32,
+) -> Vec<Vec<T>> {
+    // Union-find over item indices
+    let mut parents: Vec<usize> = (0..hashes.len()).collect();
+    fn root(parents: &mut [usize], mut index: usize) -> usize {
+        while parents[index] != index {
+            parents[index] = parents[parents[index]];
+            index = parents[index];
+        }
+        index
+    }
+
+    for i in 0..hashes.len() {
+        for j in i + 1..hashes.len() {
+            if hashes[i]
+                .1
+                .distance(&hashes[j].1)
+                .is_some_and(|distance| distance <= threshold)
+            {
+                let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut groups: Vec<(usize, Vec<T>)> = Vec::new();
+    for (index, (item, _)) in hashes.iter().enumerate() {
+        let group_root = root(&mut parents, index);
+        match groups.iter_mut().find(|(r, _)| *r == group_root) {
+            Some((_, items)) => items.push(item.clone()),
+            None => groups.push((group_root, vec![item.clone()])),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(_, items)| items)
+        .filter(|items| items.len() > 1)
+        .collect()
+}
+
+/// Hashes image files and reports near-duplicates among them
+///
+/// # Arguments
+///
+/// * `decoder` - Decoder for the image files
+/// * `algorithm` - Hash algorithm to use
+/// * `paths` - The downloaded files to compare
+/// * `threshold` - Maximum Hamming distance between near-duplicates, e.g. [`DEFAULT_THRESHOLD`]
+///
+/// # Returns
+///
+/// The groups of near-duplicates and the files that could not be hashed
+pub fn near_duplicate_report(
+    decoder: &dyn ImageDecoder,
+    algorithm: HashAlgorithm,
+    paths: &[PathBuf],
+    threshold: u32,
+) -> NearDuplicateReport {
+    let mut hashes = Vec::with_capacity(paths.len());
+    let mut failures = Vec::new();
+    for path in paths {
+        match hash_file(decoder, algorithm, path) {
+            Ok(hash) => hashes.push((path.clone(), hash)),
+            Err(e) => failures.push((path.clone(), e.to_string())),
+        }
+    }
+    NearDuplicateReport {
+        groups: group_near_duplicates(&hashes, threshold),
+        failures,
+    }
+}
@@ -0,0 +1,362 @@
+//! Loading placeholders derived from downloaded thumbnails.
+//!
+//! Gallery frontends show a placeholder while a photo loads: a flat
+//! [`dominant_color`] or a [`blurhash`], a short string the frontend decodes
+//! into a blurred preview. [`enrich_photos_with_placeholders`] computes both
+//! for the thumbnails written by
+//! [`crate::download::download_derivative_classes`] and stores them on each
+//! photo, so they end up in any JSON export of the album and in slideshow
+//! manifests.
+//!
+//! Images are decoded through the [`RgbDecoder`] trait.
+//! [`crate::codec::StandardDecoder`] reads the JPEG and PNG thumbnails iCloud
+//! serves with the `image` crate, and [`PpmDecoder`] only handles binary PGM
+//! and PPM files. Thumbnails are read and decoded synchronously, so async
+//! applications enrich photos on the blocking pool.
+
+use crate::derivatives::SizeClass;
+use crate::download::DerivativeDownload;
+use crate::models::{Image, Placeholder};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+/// Default number of horizontal and vertical BlurHash components
+pub const DEFAULT_COMPONENTS: (u32, u32) = (4, 3);
+
+/// Largest side images are downscaled to before computing placeholders
+const SAMPLE_SIZE: usize = 64;
+
+/// Characters of the base 83 encoding used by BlurHash
+const BASE83: &[u8] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+/// An 8-bit RGB image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbImage {
+    /// Width in pixels
+    pub width: usize,
+    /// Height in pixels
+    pub height: usize,
+    /// Row-major pixels, `width * height` of them
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl RgbImage {
+    /// Create an image, returning `None` if it is empty or `pixels` has the wrong length
+    pub fn new(width: usize, height: usize, pixels: Vec<[u8; 3]>) -> Option<Self> {
+        (width > 0 && height > 0 && width.checked_mul(height) == Some(pixels.len())).then_some(
+            Self {
+                width,
+                height,
+                pixels,
+            },
+        )
+    }
+
+    /// Downscales the image to fit within `size` x `size` by averaging the covered pixels
+    fn shrink(&self, size: usize) -> RgbImage {
+        if self.width <= size && self.height <= size {
+            return self.clone();
+        }
+        let scale = self.width.max(self.height) as f64 / size as f64;
+        let width = ((self.width as f64 / scale).round() as usize).max(1);
+        let height = ((self.height as f64 / scale).round() as usize).max(1);
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let y0 = y * self.height / height;
+            let y1 = ((y + 1) * self.height / height).max(y0 + 1);
+            for x in 0..width {
+                let x0 = x * self.width / width;
+                let x1 = ((x + 1) * self.width / width).max(x0 + 1);
+                let mut sum = [0u32; 3];
+                for row in y0..y1 {
+                    for pixel in &self.pixels[row * self.width + x0..row * self.width + x1] {
+                        for (total, &channel) in sum.iter_mut().zip(pixel) {
+                            *total += channel as u32;
+                        }
+                    }
+                }
+                let count = ((y1 - y0) * (x1 - x0)) as u32;
+                pixels.push(sum.map(|total| ((total + count / 2) / count) as u8));
+            }
+        }
+        RgbImage {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+/// Error returned by an [`RgbDecoder`]
+pub type DecodeError = Box<dyn Error + Send + Sync>;
+
+/// Decodes image files into RGB pixels
+pub trait RgbDecoder: Send + Sync {
+    /// Decodes the contents of an image file
+    fn decode(&self, bytes: &[u8]) -> Result<RgbImage, DecodeError>;
+}
+
+/// Decoder for binary PGM (`P5`) and PPM (`P6`) images with 8-bit samples
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PpmDecoder;
+
+impl RgbDecoder for PpmDecoder {
+    fn decode(&self, bytes: &[u8]) -> Result<RgbImage, DecodeError> {
+        let image = crate::pnm::parse(bytes)?;
+        let pixels = image
+            .samples
+            .chunks_exact(image.channels)
+            .map(|px| match px {
+                [r, g, b] => [*r, *g, *b],
+                [gray] => [*gray; 3],
+                _ => unreachable!("chunks_exact yields {} samples", image.channels),
+            })
+            .collect();
+        RgbImage::new(image.width, image.height, pixels).ok_or_else(|| "Image is empty".into())
+    }
+}
+
+/// Options for computing placeholders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PlaceholderOptions {
+    /// Number of horizontal BlurHash components, from 1 to 9
+    pub x_components: u32,
+    /// Number of vertical BlurHash components, from 1 to 9
+    pub y_components: u32,
+}
+
+impl Default for PlaceholderOptions {
+    fn default() -> Self {
+        Self {
+            x_components: DEFAULT_COMPONENTS.0,
+            y_components: DEFAULT_COMPONENTS.1,
+        }
+    }
+}
+
+impl PlaceholderOptions {
+    /// Create options with the default number of components
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of BlurHash components, clamped to 1-9 each
+    ///
+    /// More components keep more detail at the cost of a longer hash; landscape
+    /// images usually get more horizontal than vertical components.
+    #[must_use]
+    pub fn with_components(mut self, x_components: u32, y_components: u32) -> Self {
+        self.x_components = x_components.clamp(1, 9);
+        self.y_components = y_components.clamp(1, 9);
+        self
+    }
+}
+
+/// Returns the most common color of an image
+///
+/// Pixels are grouped into buckets of similar colors; the result is the
+/// average color of the most populated bucket, so a few saturated pixels
+/// don't outweigh a large, evenly colored area.
+pub fn dominant_color(image: &RgbImage) -> [u8; 3] {
+    let image = image.shrink(SAMPLE_SIZE);
+    // 4 bits per channel
+    let bucket =
+        |[r, g, b]: [u8; 3]| (r as usize >> 4) << 8 | (g as usize >> 4) << 4 | b as usize >> 4;
+    let mut counts = vec![0u32; 1 << 12];
+    for &pixel in &image.pixels {
+        counts[bucket(pixel)] += 1;
+    }
+    // Ties go to the lowest bucket, keeping the result deterministic
+    let dominant = counts
+        .iter()
+        .enumerate()
+        .max_by_key(|&(index, &count)| (count, Reverse(index)))
+        .map_or(0, |(index, _)| index);
+
+    let mut sum = [0u32; 3];
+    let mut count = 0;
+    for &pixel in image.pixels.iter().filter(|&&p| bucket(p) == dominant) {
+        for (total, channel) in sum.iter_mut().zip(pixel) {
+            *total += channel as u32;
+        }
+        count += 1;
+    }
+    sum.map(|total| ((total + count / 2) / count) as u8)
+}
+
+/// Formats a color as `#rrggbb`
+pub fn hex_color([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Converts an sRGB channel to linear light
+fn srgb_to_linear(value: u8) -> f64 {
+    let v = value as f64 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts linear light back to an sRGB channel
+fn linear_to_srgb(value: f64) -> u32 {
+    let v = value.clamp(0.0, 1.0);
+    if v <= 0.0031308 {
+        (v * 12.92 * 255.0 + 0.5) as u32
+    } else {
+        ((1.055 * v.powf(1.0 / 2.4) - 0.055) * 255.0 + 0.5) as u32
+    }
+}
+
+/// Appends `value` to `hash` as `length` base 83 digits
+fn push_base83(hash: &mut String, value: u32, length: u32) {
+    for i in 1..=length {
+        let digit = value / 83u32.pow(length - i) % 83;
+        hash.push(BASE83[digit as usize] as char);
+    }
+}
+
+/// Computes the BlurHash of an image
+///
+/// # Arguments
+///
+/// * `image` - The image to encode; large images are downscaled first
+/// * `x_components` - Number of horizontal components, clamped to 1-9
+/// * `y_components` - Number of vertical components, clamped to 1-9
+///
+/// # Returns
+///
+/// The BlurHash, `4 + 2 * x_components * y_components` characters long
+pub fn blurhash(image: &RgbImage, x_components: u32, y_components: u32) -> String {
+    let (x_components, y_components) = (x_components.clamp(1, 9), y_components.clamp(1, 9));
+    let image = image.shrink(SAMPLE_SIZE);
+    let linear: Vec<[f64; 3]> = image
+        .pixels
+        .iter()
+        .map(|pixel| pixel.map(srgb_to_linear))
+        .collect();
+
+    let mut factors = Vec::with_capacity((x_components * y_components) as usize);
+    for j in 0..y_components {
+        for i in 0..x_components {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0f64; 3];
+            for y in 0..image.height {
+                let vertical =
+                    (std::f64::consts::PI * j as f64 * y as f64 / image.height as f64).cos();
+                for x in 0..image.width {
+                    let basis = vertical
+                        * (std::f64::consts::PI * i as f64 * x as f64 / image.width as f64).cos();
+                    for (total, channel) in factor.iter_mut().zip(linear[y * image.width + x]) {
+                        *total += basis * channel;
+                    }
+                }
+            }
+            let scale = normalisation / (image.width * image.height) as f64;
+            factors.push(factor.map(|total| total * scale));
+        }
+    }
+
+    let mut hash = String::new();
+    push_base83(&mut hash, (x_components - 1) + (y_components - 1) * 9, 1);
+
+    let (dc, ac) = factors.split_first().expect("at least one component");
+    let maximum = if ac.is_empty() {
+        push_base83(&mut hash, 0, 1);
+        1.0
+    } else {
+        let actual = ac
+            .iter()
+            .flatten()
+            .fold(0.0f64, |max, value| max.max(value.abs()));
+        let quantised = (actual * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+        push_base83(&mut hash, quantised, 1);
+        (quantised + 1) as f64 / 166.0
+    };
+
+    let [r, g, b] = dc.map(linear_to_srgb);
+    push_base83(&mut hash, (r << 16) + (g << 8) + b, 4);
+    for factor in ac {
+        let [r, g, b] = factor.map(|value| {
+            let scaled = value / maximum;
+            let signed_sqrt = scaled.signum() * scaled.abs().sqrt();
+            (signed_sqrt * 9.0 + 9.5).floor().clamp(0.0, 18.0) as u32
+        });
+        push_base83(&mut hash, r * 19 * 19 + g * 19 + b, 2);
+    }
+    hash
+}
+
+/// Computes the placeholder of an image
+pub fn placeholder(image: &RgbImage, options: &PlaceholderOptions) -> Placeholder {
+    Placeholder {
+        blurhash: blurhash(image, options.x_components, options.y_components),
+        dominant_color: hex_color(dominant_color(image)),
+    }
+}
+
+/// Reads, decodes and computes the placeholder of an image file
+pub fn placeholder_for_file(
+    decoder: &dyn RgbDecoder,
+    path: &Path,
+    options: &PlaceholderOptions,
+) -> Result<Placeholder, DecodeError> {
+    let bytes = std::fs::read(path)?;
+    Ok(placeholder(&decoder.decode(&bytes)?, options))
+}
+
+/// Attaches placeholders computed from downloaded files to photos
+///
+/// For each photo, the smallest downloaded still image is used, normally the
+/// [`SizeClass::Thumbnail`] download. Photos without a downloaded still image
+/// are left unchanged.
+///
+/// # Arguments
+///
+/// * `photos` - A mutable slice of Images to be enriched
+/// * `downloads` - Files written by [`crate::download::download_derivative_classes`]
+/// * `decoder` - Decoder for the downloaded files
+/// * `options` - Options for the BlurHash
+///
+/// # Returns
+///
+/// The photos whose file could not be decoded, as (photo GUID, error)
+pub fn enrich_photos_with_placeholders(
+    photos: &mut [Image],
+    downloads: &[DerivativeDownload],
+    decoder: &dyn RgbDecoder,
+    options: &PlaceholderOptions,
+) -> Vec<(String, String)> {
+    let mut smallest: HashMap<&str, &DerivativeDownload> = HashMap::new();
+    for download in downloads
+        .iter()
+        .filter(|download| download.size_class != SizeClass::Video)
+    {
+        smallest
+            .entry(download.photo_guid.as_str())
+            .and_modify(|current| {
+                if download.size_class < current.size_class {
+                    *current = download;
+                }
+            })
+            .or_insert(download);
+    }
+
+    let mut failures = Vec::new();
+    for photo in photos.iter_mut() {
+        let Some(download) = smallest.get(photo.photo_guid.as_str()) else {
+            continue;
+        };
+        match placeholder_for_file(decoder, Path::new(&download.path), options) {
+            Ok(placeholder) => photo.placeholder = Some(placeholder),
+            Err(e) => failures.push((photo.photo_guid.clone(), e.to_string())),
+        }
+    }
+    failures
+}
@@ -0,0 +1,73 @@
+//! Parsing of binary PGM and PPM images, shared by the image analysis features.
+
+use std::error::Error;
+
+/// Pixels of a decoded PNM image
+pub(crate) struct Pnm {
+    /// Width in pixels
+    pub width: usize,
+    /// Height in pixels
+    pub height: usize,
+    /// Samples per pixel: 1 for PGM, 3 for PPM
+    pub channels: usize,
+    /// Row-major samples, scaled to 0-255
+    pub samples: Vec<u8>,
+}
+
+/// Parses a binary PGM (`P5`) or PPM (`P6`) image with 8-bit samples
+pub(crate) fn parse(bytes: &[u8]) -> Result<Pnm, Box<dyn Error + Send + Sync>> {
+    let channels = match bytes.get(0..2) {
+        Some(b"P5") => 1,
+        Some(b"P6") => 3,
+        _ => return Err("Not a binary PGM or PPM image".into()),
+    };
+
+    // Header: magic, width, height, maxval, separated by whitespace and comments
+    let mut position = 2;
+    let mut fields = [0usize; 3];
+    for field in fields.iter_mut() {
+        loop {
+            match bytes.get(position) {
+                Some(b'#') => {
+                    while !matches!(bytes.get(position), Some(b'\n') | None) {
+                        position += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => position += 1,
+                _ => break,
+            }
+        }
+        let start = position;
+        while bytes.get(position).is_some_and(|c| c.is_ascii_digit()) {
+            position += 1;
+        }
+        *field = std::str::from_utf8(&bytes[start..position])?.parse()?;
+    }
+    let [width, height, maxval] = fields;
+    if maxval == 0 || maxval > 255 {
+        return Err(format!("Unsupported maximum value {}", maxval).into());
+    }
+    // A single whitespace character separates the header from the samples
+    position += 1;
+
+    let count = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(channels))
+        .ok_or("Image dimensions overflow")?;
+    let data = bytes
+        .get(position..)
+        .and_then(|data| data.get(..count))
+        .ok_or("Image data is truncated")?;
+    if data.is_empty() {
+        return Err("Image is empty".into());
+    }
+    Ok(Pnm {
+        width,
+        height,
+        channels,
+        samples: data
+            .iter()
+            .map(|&s| (s as usize * 255 / maxval).min(255) as u8)
+            .collect(),
+    })
+}
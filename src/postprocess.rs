@@ -0,0 +1,246 @@
+//! Plugins processing downloaded files.
+//!
+//! A [`PostProcessor`] receives every file [`download_derivative_classes`]
+//! writes together with the photo's metadata, so applications can plug in
+//! steps such as tagging photos with an ML model, uploading them or
+//! converting them without the crate shipping each integration.
+//!
+//! Processors are registered with [`DownloadOptions::with_post_processor`].
+//! Once a pass has downloaded (and transcoded) its files, each file goes
+//! through the processors in registration order, with up to
+//! [`PostProcessing::concurrency`] files processed at once. A processor may
+//! replace a file, e.g. with a converted copy, in which case the report and
+//! the processors after it see the new path.
+//!
+//! A failing processor skips the remaining processors for that file only; the
+//! failure is recorded in [`MultiDownloadReport::post_process_failures`].
+//! Files appended to a pack are not processed, and no new files are started
+//! once [`DownloadOptions::shutdown`] is triggered.
+//!
+//! [`download_derivative_classes`]: crate::download::download_derivative_classes
+//! [`DownloadOptions::with_post_processor`]: crate::download::DownloadOptions::with_post_processor
+//! [`DownloadOptions::shutdown`]: crate::download::DownloadOptions::shutdown
+//! [`MultiDownloadReport::post_process_failures`]: crate::download::MultiDownloadReport::post_process_failures
+
+use crate::derivatives::SizeClass;
+use crate::download::{DerivativeDownload, DownloadOptions};
+use crate::logging;
+use crate::models::Image;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// Number of files processed at once by default
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Error returned by a [`PostProcessor`]
+pub type PostProcessError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Future returned by [`PostProcessor::process`]
+pub type PostProcessFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Option<PathBuf>, PostProcessError>> + Send + 'a>>;
+
+/// A step run on every downloaded file
+pub trait PostProcessor: Send + Sync {
+    /// Name of the step, used in logs and reports
+    fn name(&self) -> &str;
+
+    /// Processes one downloaded file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The downloaded file
+    /// * `photo` - Metadata of the photo the file belongs to
+    /// * `size_class` - Size class the file was downloaded for
+    ///
+    /// # Returns
+    ///
+    /// The path of a file replacing the downloaded one, e.g. a converted copy,
+    /// or `None` if the file stays as it is
+    fn process<'a>(
+        &'a self,
+        path: &'a Path,
+        photo: &'a Image,
+        size_class: SizeClass,
+    ) -> PostProcessFuture<'a>;
+}
+
+/// Shared processors, e.g. ones whose results the application reads after the pass
+impl<P: PostProcessor + ?Sized> PostProcessor for Arc<P> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn process<'a>(
+        &'a self,
+        path: &'a Path,
+        photo: &'a Image,
+        size_class: SizeClass,
+    ) -> PostProcessFuture<'a> {
+        (**self).process(path, photo, size_class)
+    }
+}
+
+/// Post-processors registered on a downloader, see [`DownloadOptions::post_processing`]
+#[derive(Clone)]
+pub struct PostProcessing {
+    /// The processors, in the order they run on each file
+    pub processors: Vec<Arc<dyn PostProcessor>>,
+    /// How many files are processed at once
+    pub concurrency: usize,
+}
+
+impl Default for PostProcessing {
+    fn default() -> Self {
+        Self {
+            processors: Vec::new(),
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+}
+
+impl fmt::Debug for PostProcessing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.processors.iter().map(|p| p.name()).collect();
+        f.debug_struct("PostProcessing")
+            .field("processors", &names)
+            .field("concurrency", &self.concurrency)
+            .finish()
+    }
+}
+
+/// A file a [`PostProcessor`] failed to process
+#[derive(Debug)]
+pub struct PostProcessFailure {
+    /// GUID of the photo
+    pub photo_guid: String,
+    /// Size class of the file
+    pub size_class: SizeClass,
+    /// Path of the file when the processor ran
+    pub path: String,
+    /// Name of the processor that failed, or empty if processing panicked
+    pub processor: String,
+    /// What went wrong
+    pub error: PostProcessError,
+}
+
+/// Runs the registered post-processors on the files of a download pass
+///
+/// Downloads whose file a processor replaced are updated to the new path.
+///
+/// # Arguments
+///
+/// * `downloads` - Files written by the pass
+/// * `photos` - The photos of the pass
+/// * `options` - Options of the pass, with the processors to run
+///
+/// # Returns
+///
+/// The failures, in download order
+pub(crate) async fn post_process_downloads(
+    downloads: &mut [DerivativeDownload],
+    photos: &[Image],
+    options: &DownloadOptions,
+) -> Vec<PostProcessFailure> {
+    let processing = &options.post_processing;
+    let photos: HashMap<&str, &Image> = photos
+        .iter()
+        .map(|photo| (photo.photo_guid.as_str(), photo))
+        .collect();
+    let semaphore = Arc::new(Semaphore::new(processing.concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    // Download index of each task, to report tasks that panicked
+    let mut task_indexes = HashMap::new();
+    for (index, download) in downloads.iter().enumerate() {
+        if options.packed_classes.contains(&download.size_class) {
+            continue;
+        }
+        let Some(photo) = photos.get(download.photo_guid.as_str()) else {
+            continue;
+        };
+        let permit = match &options.shutdown {
+            Some(shutdown) => tokio::select! {
+                permit = Arc::clone(&semaphore).acquire_owned() => permit,
+                _ = shutdown.triggered() => break,
+            },
+            None => Arc::clone(&semaphore).acquire_owned().await,
+        }
+        .expect("the semaphore is never closed");
+        let processors = processing.processors.clone();
+        let photo = (*photo).clone();
+        let size_class = download.size_class;
+        let mut path = download.path.clone();
+        let task = tasks.spawn(async move {
+            let _permit = permit;
+            for processor in &processors {
+                match processor
+                    .process(Path::new(&path), &photo, size_class)
+                    .await
+                {
+                    Ok(Some(replacement)) => path = replacement.to_string_lossy().into_owned(),
+                    Ok(None) => {}
+                    Err(error) => {
+                        return (
+                            index,
+                            path.clone(),
+                            Some(PostProcessFailure {
+                                photo_guid: photo.photo_guid.clone(),
+                                size_class,
+                                path,
+                                processor: processor.name().to_string(),
+                                error,
+                            }),
+                        );
+                    }
+                }
+            }
+            (index, path, None)
+        });
+        task_indexes.insert(task.id(), index);
+    }
+
+    let mut failures = Vec::new();
+    while let Some(result) = tasks.join_next_with_id().await {
+        match result {
+            Ok((_, (index, path, failure))) => {
+                downloads[index].path = path;
+                failures.extend(failure.map(|failure| (index, failure)));
+            }
+            Err(e) => {
+                let index = task_indexes[&e.id()];
+                let download = &downloads[index];
+                let message = if e.is_panic() {
+                    "post-processing panicked"
+                } else {
+                    "post-processing was cancelled"
+                };
+                failures.push((
+                    index,
+                    PostProcessFailure {
+                        photo_guid: download.photo_guid.clone(),
+                        size_class: download.size_class,
+                        path: download.path.clone(),
+                        processor: String::new(),
+                        error: message.into(),
+                    },
+                ));
+            }
+        }
+    }
+    for (_, failure) in &failures {
+        logging::log_warn!(
+            logging::DOWNLOAD,
+            "Post-processor {} failed on {}: {}",
+            failure.processor,
+            failure.path,
+            failure.error
+        );
+    }
+    failures.sort_by_key(|(index, _)| *index);
+    failures.into_iter().map(|(_, failure)| failure).collect()
+}
@@ -0,0 +1,144 @@
+//! Hooks deciding whether files are downloaded.
+//!
+//! A [`PreDownloadHook`] is consulted by [`download_derivative_classes`] for
+//! every derivative it is about to request, after the derivative has been
+//! selected and before any request is sent. It can allow the download, deny
+//! it, e.g. after asking an external moderation or budget service, or
+//! redirect it to another URL such as a caching proxy. Redirected URLs are
+//! still checked against [`DownloadOptions::url_policy`].
+//!
+//! Hooks are registered with [`DownloadOptions::with_pre_download_hook`] and
+//! consulted in registration order: the first denial wins, and each hook sees
+//! the URL the previous ones redirected to. Denied downloads are listed in
+//! [`MultiDownloadReport::denied`]; when a hook itself fails, the download
+//! fails and is recorded in [`MultiDownloadReport::failures`].
+//!
+//! [`crate::postprocess`] is the counterpart running after files are written.
+//!
+//! [`download_derivative_classes`]: crate::download::download_derivative_classes
+//! [`DownloadOptions::url_policy`]: crate::download::DownloadOptions::url_policy
+//! [`DownloadOptions::with_pre_download_hook`]: crate::download::DownloadOptions::with_pre_download_hook
+//! [`MultiDownloadReport::failures`]: crate::download::MultiDownloadReport::failures
+//! [`MultiDownloadReport::denied`]: crate::download::MultiDownloadReport::denied
+
+use crate::derivatives::SizeClass;
+use crate::models::{Derivative, Image};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Error returned by a [`PreDownloadHook`]
+pub type PreDownloadError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Future returned by [`PreDownloadHook::check`]
+pub type PreDownloadFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<PreDownloadDecision, PreDownloadError>> + Send + 'a>>;
+
+/// A download about to be requested
+#[derive(Debug, Clone, Copy)]
+pub struct PlannedDownload<'a> {
+    /// The photo
+    pub photo: &'a Image,
+    /// Size class the derivative was selected for
+    pub size_class: SizeClass,
+    /// Key of the selected derivative
+    pub key: &'a str,
+    /// The selected derivative
+    pub derivative: &'a Derivative,
+    /// URL that will be requested, after any redirects by earlier hooks
+    pub url: &'a str,
+}
+
+/// What a [`PreDownloadHook`] decided about a download
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreDownloadDecision {
+    /// Download as planned
+    Allow,
+    /// Skip the download, for the given reason
+    Deny(String),
+    /// Request this URL instead
+    Redirect(String),
+}
+
+/// Decides, before each request, whether and from where a file is downloaded
+pub trait PreDownloadHook: Send + Sync {
+    /// Name of the hook, used in logs
+    fn name(&self) -> &str;
+
+    /// Decides about one download
+    ///
+    /// # Arguments
+    ///
+    /// * `download` - The photo, selected derivative and URL about to be requested
+    fn check<'a>(&'a self, download: PlannedDownload<'a>) -> PreDownloadFuture<'a>;
+}
+
+/// Shared hooks, e.g. ones whose state the application reads after the pass
+impl<H: PreDownloadHook + ?Sized> PreDownloadHook for Arc<H> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn check<'a>(&'a self, download: PlannedDownload<'a>) -> PreDownloadFuture<'a> {
+        (**self).check(download)
+    }
+}
+
+/// Hooks registered on a downloader, see [`DownloadOptions::pre_download`]
+///
+/// [`DownloadOptions::pre_download`]: crate::download::DownloadOptions::pre_download
+#[derive(Clone, Default)]
+pub struct PreDownloadHooks {
+    /// The hooks, in the order they are consulted
+    pub hooks: Vec<Arc<dyn PreDownloadHook>>,
+}
+
+impl fmt::Debug for PreDownloadHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.hooks.iter().map(|hook| hook.name()).collect();
+        f.debug_struct("PreDownloadHooks")
+            .field("hooks", &names)
+            .finish()
+    }
+}
+
+/// Outcome of consulting the hooks about a download
+#[derive(Debug)]
+pub(crate) enum Verdict {
+    /// Request this URL
+    Download(String),
+    /// Skip the download, for the given reason
+    Denied(String),
+    /// A hook failed
+    Failed(String, PreDownloadError),
+}
+
+impl PreDownloadHooks {
+    /// Consults the hooks about a download of `url`
+    pub(crate) async fn check(
+        &self,
+        photo: &Image,
+        size_class: SizeClass,
+        key: &str,
+        derivative: &Derivative,
+        mut url: String,
+    ) -> Verdict {
+        for hook in &self.hooks {
+            let planned = PlannedDownload {
+                photo,
+                size_class,
+                key,
+                derivative,
+                url: &url,
+            };
+            match hook.check(planned).await {
+                Ok(PreDownloadDecision::Allow) => {}
+                Ok(PreDownloadDecision::Deny(reason)) => return Verdict::Denied(reason),
+                Ok(PreDownloadDecision::Redirect(redirect)) => url = redirect,
+                Err(e) => return Verdict::Failed(hook.name().to_string(), e),
+            }
+        }
+        Verdict::Download(url)
+    }
+}
@@ -0,0 +1,209 @@
+//! Predicting which photos a gallery viewer will ask for next.
+//!
+//! A viewer scrolling through a gallery asks for photo N, then N+1, and so on;
+//! fetching the next few photos before they are asked for hides the latency
+//! of the asset servers. A [`PrefetchPredictor`] remembers the recent requests
+//! of each client, works out the direction and stride they move in, and
+//! returns the photos to fetch ahead of them. [`PrefetchPredictor::targets`]
+//! turns those into derivative URLs of [`PrefetchConfig::size_class`].
+//!
+//! The predictor only decides what to fetch, and [`PrefetchPredictor::stats`]
+//! reports how often its predictions were right. The server of the `server`
+//! feature records its asset requests in a predictor set with
+//! `ServerConfig::with_prefetch` and keeps the prefetched derivatives in
+//! memory; applications serving albums themselves fetch
+//! [`PrefetchPredictor::targets`] into their own asset cache.
+
+use crate::derivatives::SizeClass;
+use crate::models::Image;
+use crate::utils;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+/// How far ahead a [`PrefetchPredictor`] looks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefetchConfig {
+    /// Number of photos to prefetch after each request
+    pub depth: usize,
+    /// Size class of the derivatives to prefetch
+    pub size_class: SizeClass,
+    /// Number of recent requests remembered per client
+    pub history: usize,
+    /// Largest jump between requests still treated as a stride, e.g. paging
+    pub max_stride: usize,
+}
+
+impl Default for PrefetchConfig {
+    fn default() -> Self {
+        Self {
+            depth: 3,
+            size_class: SizeClass::Medium,
+            history: 8,
+            max_stride: 12,
+        }
+    }
+}
+
+impl PrefetchConfig {
+    /// Prefetch `depth` photos after each request
+    #[must_use]
+    pub fn with_depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Prefetch derivatives of `size_class`
+    #[must_use]
+    pub fn with_size_class(mut self, size_class: SizeClass) -> Self {
+        self.size_class = size_class;
+        self
+    }
+}
+
+/// How well the predictions of a [`PrefetchPredictor`] matched later requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrefetchStats {
+    /// Requests recorded
+    pub requests: u64,
+    /// Photos predicted
+    pub predicted: u64,
+    /// Requests for a photo that had been predicted for the same client
+    pub hits: u64,
+}
+
+impl PrefetchStats {
+    /// Share of the predictions that were requested afterwards
+    pub fn precision(&self) -> Option<f64> {
+        (self.predicted > 0).then(|| self.hits as f64 / self.predicted as f64)
+    }
+}
+
+/// Recent requests and outstanding predictions of one client
+#[derive(Debug, Default)]
+struct ClientHistory {
+    recent: VecDeque<usize>,
+    predicted: HashSet<usize>,
+}
+
+/// Predicts the next photos of each client from its past requests
+#[derive(Debug, Default)]
+pub struct PrefetchPredictor {
+    config: PrefetchConfig,
+    clients: Mutex<HashMap<String, ClientHistory>>,
+    stats: Mutex<PrefetchStats>,
+}
+
+impl PrefetchPredictor {
+    /// Create a predictor with a configuration
+    pub fn new(config: PrefetchConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// The configuration of the predictor
+    pub fn config(&self) -> &PrefetchConfig {
+        &self.config
+    }
+
+    /// Records a request and predicts the photos to prefetch
+    ///
+    /// The stride is the step between the last two requests when the one
+    /// before moved the same way, so paging through a grid prefetches whole
+    /// pages; otherwise the client is assumed to move forward one photo at a
+    /// time. Photos the client asked for recently are not predicted again.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - Identifies the viewer, e.g. a session ID
+    /// * `index` - Position of the requested photo in the album
+    /// * `len` - Number of photos in the album
+    ///
+    /// # Returns
+    ///
+    /// Positions of the photos to prefetch, nearest first
+    pub fn record(&self, client: &str, index: usize, len: usize) -> Vec<usize> {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        let history = clients.entry(client.to_string()).or_default();
+        let hit = history.predicted.remove(&index);
+
+        history.recent.push_back(index);
+        while history.recent.len() > self.config.history.max(2) {
+            history.recent.pop_front();
+        }
+        let stride = self.stride(&history.recent);
+        let predictions: Vec<usize> = (1..=self.config.depth as i64)
+            .map_while(|step| {
+                let next = index as i64 + stride * step;
+                (0..len as i64).contains(&next).then_some(next as usize)
+            })
+            .filter(|next| !history.recent.contains(next))
+            .collect();
+        history.predicted.extend(&predictions);
+        // Forget predictions the client has moved away from
+        let reach = self.config.depth.max(1) * self.config.max_stride.max(1) * 2;
+        history
+            .predicted
+            .retain(|predicted| predicted.abs_diff(index) <= reach);
+
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        stats.requests += 1;
+        stats.predicted += predictions.len() as u64;
+        stats.hits += u64::from(hit);
+        predictions
+    }
+
+    /// Returns the URLs to prefetch for predicted positions
+    ///
+    /// # Returns
+    ///
+    /// The GUID and derivative URL of each predicted photo that has a
+    /// derivative of [`PrefetchConfig::size_class`] with a URL
+    pub fn targets(&self, photos: &[Image], predictions: &[usize]) -> Vec<(String, String)> {
+        predictions
+            .iter()
+            .filter_map(|&index| {
+                let photo = photos.get(index)?;
+                let (_, derivative) =
+                    utils::select_derivative_of_class(&photo.derivatives, self.config.size_class)?;
+                Some((photo.photo_guid.clone(), derivative.url.clone()?))
+            })
+            .collect()
+    }
+
+    /// Forgets the history of a client, e.g. when its session ends
+    pub fn forget(&self, client: &str) {
+        self.clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(client);
+    }
+
+    /// How well predictions matched requests so far
+    pub fn stats(&self) -> PrefetchStats {
+        *self.stats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The step the client is moving in, from its recent requests
+    fn stride(&self, recent: &VecDeque<usize>) -> i64 {
+        let steps: Vec<i64> = recent
+            .iter()
+            .rev()
+            .take(3)
+            .collect::<Vec<_>>()
+            .windows(2)
+            .map(|pair| *pair[0] as i64 - *pair[1] as i64)
+            .collect();
+        match steps[..] {
+            [last, before, ..]
+                if last != 0
+                    && last.signum() == before.signum()
+                    && last.unsigned_abs() as usize <= self.config.max_stride =>
+            {
+                last
+            }
+            _ => 1,
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! QR codes linking back to shared albums.
+//!
+//! Photo frames, printed cards and exported galleries can carry a QR code of
+//! the album's [share URL](crate::album::AlbumRef::share_url), so whoever sees
+//! the photos can open the source album on their phone. [`QrCode::for_album`]
+//! encodes it, and the code renders as SVG for pages, PNG for print and
+//! Unicode block characters for terminal output. Galleries embed it with
+//! [`crate::gallery::GalleryOptions::with_share_url`].
+//!
+//! Codes are encoded with the `qrcodegen` crate. Like the image helpers of
+//! the `placeholders` feature, rendering does not pull in an imaging crate:
+//! PNGs are written uncompressed, which at one bit per pixel keeps a code
+//! of a few hundred pixels across to some tens of kilobytes.
+//! [`QrCode::save`] writes its file synchronously.
+
+use crate::album::AlbumRef;
+use qrcodegen::QrCodeEcc;
+use std::io;
+use std::path::Path;
+
+/// Light modules around the code, as required for reliable scanning
+pub const QUIET_ZONE: usize = 4;
+
+/// Default pixels per module of PNG files written by [`QrCode::save`]
+pub const DEFAULT_SCALE: usize = 8;
+
+/// Error type for QR code encoding
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum QrError {
+    /// The data doesn't fit the largest QR code at the level
+    #[error("{0} bytes don't fit a QR code at this error correction level")]
+    TooLong(usize),
+}
+
+/// How much of a QR code can be damaged and still scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EcLevel {
+    /// About 7% of the code
+    Low,
+    /// About 15% of the code
+    #[default]
+    Medium,
+    /// About 25% of the code
+    Quartile,
+    /// About 30% of the code
+    High,
+}
+
+impl From<EcLevel> for QrCodeEcc {
+    fn from(level: EcLevel) -> Self {
+        match level {
+            EcLevel::Low => QrCodeEcc::Low,
+            EcLevel::Medium => QrCodeEcc::Medium,
+            EcLevel::Quartile => QrCodeEcc::Quartile,
+            EcLevel::High => QrCodeEcc::High,
+        }
+    }
+}
+
+/// An encoded QR code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrCode {
+    version: u8,
+    size: usize,
+    /// Row-major modules, `true` for dark
+    modules: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encodes the share URL of an album at [`EcLevel::Medium`]
+    pub fn for_album(album: &AlbumRef) -> Result<Self, QrError> {
+        Self::encode(album.share_url().as_bytes(), EcLevel::default())
+    }
+
+    /// Encodes data in the smallest code that fits it
+    ///
+    /// The error correction level is raised when that fits the same size.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - Bytes to encode, usually a URL
+    /// * `level` - Lowest error correction level
+    ///
+    /// # Returns
+    ///
+    /// The code, or [`QrError::TooLong`] if even the largest doesn't fit the data
+    pub fn encode(data: &[u8], level: EcLevel) -> Result<Self, QrError> {
+        let code = qrcodegen::QrCode::encode_binary(data, level.into())
+            .map_err(|_| QrError::TooLong(data.len()))?;
+        let size = code.size() as usize;
+        let modules = (0..code.size())
+            .flat_map(|y| (0..code.size()).map(move |x| (x, y)))
+            .map(|(x, y)| code.get_module(x, y))
+            .collect();
+        Ok(Self {
+            version: code.version().value(),
+            size,
+            modules,
+        })
+    }
+
+    /// The code's version, from 1 to 40, which sets its size
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Modules across, without the quiet zone
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the module at column `x` and row `y` is dark
+    ///
+    /// Coordinates outside the code, e.g. in the quiet zone, are light.
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        x < self.size && y < self.size && self.modules[y * self.size + x]
+    }
+
+    /// Renders the code as a scalable SVG document, with its quiet zone
+    pub fn to_svg(&self) -> String {
+        let side = self.size + 2 * QUIET_ZONE;
+        let mut path = String::new();
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if self.is_dark(x, y) {
+                    path.push_str(&format!("M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE));
+                }
+            }
+        }
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {side} {side}\" \
+             shape-rendering=\"crispEdges\"><rect width=\"{side}\" height=\"{side}\" \
+             fill=\"#fff\"/><path d=\"{path}\" fill=\"#000\"/></svg>",
+            side = side,
+            path = path
+        )
+    }
+
+    /// Renders the code as a black and white PNG image, with its quiet zone
+    ///
+    /// # Arguments
+    ///
+    /// * `scale` - Pixels per module, at least 1
+    pub fn to_png(&self, scale: usize) -> Vec<u8> {
+        let scale = scale.max(1);
+        let side = (self.size + 2 * QUIET_ZONE) * scale;
+        let row_len = side.div_ceil(8);
+        let mut pixels = Vec::with_capacity((row_len + 1) * side);
+        for py in 0..side {
+            // No filter, then one bit per pixel with 1 for white
+            pixels.push(0);
+            let y = (py / scale).wrapping_sub(QUIET_ZONE);
+            let mut row = vec![0u8; row_len];
+            for px in 0..side {
+                let x = (px / scale).wrapping_sub(QUIET_ZONE);
+                if !self.is_dark(x, y) {
+                    row[px / 8] |= 0x80 >> (px % 8);
+                }
+            }
+            pixels.extend_from_slice(&row);
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(side as u32).to_be_bytes());
+        header.extend_from_slice(&(side as u32).to_be_bytes());
+        // Bit depth 1, grayscale, deflate, adaptive filtering, no interlace
+        header.extend_from_slice(&[1, 0, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut png, b"IHDR", &header);
+        png_chunk(&mut png, b"IDAT", &zlib_stored(&pixels));
+        png_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    /// Renders the code with Unicode half blocks, two modules per character
+    ///
+    /// Dark modules are drawn in the text color, which suits terminals with
+    /// dark text on a light background. For light text on a dark background,
+    /// pass `invert` so the code still scans as dark on light.
+    pub fn to_unicode(&self, invert: bool) -> String {
+        let side = self.size + 2 * QUIET_ZONE;
+        let dark = |x: usize, y: usize| {
+            let dark = self.is_dark(x.wrapping_sub(QUIET_ZONE), y.wrapping_sub(QUIET_ZONE));
+            dark != invert
+        };
+        let mut text = String::new();
+        for y in (0..side).step_by(2) {
+            for x in 0..side {
+                let lower = y + 1 < side && dark(x, y + 1);
+                text.push(match (dark(x, y), lower) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Writes the code to an `.svg` or `.png` file, chosen by its extension
+    ///
+    /// PNG files use [`DEFAULT_SCALE`].
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        let contents = match extension.as_deref() {
+            Some("svg") => self.to_svg().into_bytes(),
+            Some("png") => self.to_png(DEFAULT_SCALE),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is neither an .svg nor a .png file", path.display()),
+                ))
+            }
+        };
+        std::fs::write(path, contents)
+    }
+}
+
+/// Appends a PNG chunk with its length and checksum
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps data in a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut chunks = data.chunks(0xFFFF).peekable();
+    if chunks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let len = chunk.len() as u16;
+        stream.push(u8::from(chunks.peek().is_none()));
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(chunk);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    stream.extend_from_slice(&(b << 16 | a).to_be_bytes());
+    stream
+}
+
+/// CRC-32 as used by PNG
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
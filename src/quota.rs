@@ -0,0 +1,316 @@
+//! Limiting the requests and bandwidth of self-hosted asset proxies.
+//!
+//! An asset proxy exposed to the internet fetches from iCloud's CDN on behalf
+//! of whoever asks, so without limits a public deployment can be used to
+//! hammer the CDN through the server. A [`ServeLimiter`] enforces two kinds of
+//! limits:
+//!
+//! * a [`RateLimit`] on the requests of each client IP, as a token bucket
+//! * a [`BandwidthQuota`] on the bytes served from each album per window
+//!
+//! The serve mode of the `server` feature applies a limiter to its routes. Other applications call
+//! [`ServeLimiter::check_request`] when a request arrives and
+//! [`ServeLimiter::reserve`] before proxying an asset, or
+//! [`ServeLimiter::check_quota`] before a response of unknown size, and answer a
+//! [`Limited`] with a 429 and its `Retry-After` header.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Number of tracked clients above which idle ones are forgotten
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// How many requests a client may make
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Requests allowed per `per`, on average
+    pub requests: u32,
+    /// The period `requests` refers to
+    pub per: Duration,
+    /// Requests a client may make at once after being idle
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Allow `requests` per `per`, with bursts of up to `requests`
+    pub fn new(requests: u32, per: Duration) -> Self {
+        Self {
+            requests,
+            per,
+            burst: requests,
+        }
+    }
+
+    /// Allow bursts of up to `burst` requests
+    #[must_use]
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    /// Tokens refilled per second
+    fn rate(&self) -> f64 {
+        self.requests as f64 / self.per.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// How many bytes may be served from an album
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BandwidthQuota {
+    /// Bytes allowed per window
+    pub bytes: u64,
+    /// Length of a window; usage resets when a new window starts
+    pub per: Duration,
+}
+
+impl BandwidthQuota {
+    /// Allow `bytes` per `per`
+    pub fn new(bytes: u64, per: Duration) -> Self {
+        Self { bytes, per }
+    }
+}
+
+/// A request denied by a [`ServeLimiter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limited {
+    /// How long until the request would be allowed
+    pub retry_after: Duration,
+}
+
+impl Limited {
+    /// The status code to answer with
+    pub fn status(&self) -> u16 {
+        429
+    }
+
+    /// The `Retry-After` header value, in whole seconds rounded up
+    pub fn retry_after_header(&self) -> String {
+        let seconds = self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0);
+        seconds.max(1).to_string()
+    }
+}
+
+/// Per-client token bucket
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Per-album usage in the current window
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    used: u64,
+}
+
+/// Enforces request rate limits and bandwidth quotas for an asset proxy
+///
+/// The limiter is meant to be shared, e.g. in an `Arc`, by all request
+/// handlers. A limiter without limits allows everything.
+#[derive(Debug, Default)]
+pub struct ServeLimiter {
+    rate_limit: Option<RateLimit>,
+    quota: Option<BandwidthQuota>,
+    album_quotas: HashMap<String, BandwidthQuota>,
+    clients: Mutex<HashMap<IpAddr, Bucket>>,
+    albums: Mutex<HashMap<String, Window>>,
+}
+
+impl ServeLimiter {
+    /// Create a limiter without limits
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the requests of each client IP
+    ///
+    /// IPv6 clients are limited per /64 network, since a single host usually
+    /// has a whole /64 to pick addresses from.
+    #[must_use]
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Limit the bytes served from each album
+    #[must_use]
+    pub fn with_bandwidth_quota(mut self, quota: BandwidthQuota) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Limit the bytes served from the album with ID `album` differently
+    #[must_use]
+    pub fn with_album_quota(mut self, album: impl Into<String>, quota: BandwidthQuota) -> Self {
+        self.album_quotas.insert(album.into(), quota);
+        self
+    }
+
+    /// Checks whether a client may make another request, and counts it
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The IP address of the client
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Limited>` - `Ok` if the request is allowed
+    pub fn check_request(&self, client: IpAddr) -> Result<(), Limited> {
+        let Some(limit) = self.rate_limit else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let capacity = limit.burst.max(1) as f64;
+        let rate = limit.rate();
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if clients.len() >= PRUNE_THRESHOLD {
+            // A bucket that refilled completely is the same as a new one
+            clients.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < capacity
+            });
+        }
+
+        let bucket = clients.entry(client_key(client)).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if rate > 0.0 {
+            Err(Limited {
+                retry_after: Duration::from_secs_f64((1.0 - bucket.tokens) / rate),
+            })
+        } else {
+            Err(Limited {
+                retry_after: limit.per,
+            })
+        }
+    }
+
+    /// Reserves bandwidth for serving `bytes` from an album
+    ///
+    /// An asset larger than the whole quota is still served when nothing else
+    /// was served from the album in the current window, so it doesn't become
+    /// unreachable. When the size isn't known up front, reserve nothing and
+    /// [`ServeLimiter::record`] the bytes once served.
+    ///
+    /// # Arguments
+    ///
+    /// * `album` - ID of the album the asset belongs to
+    /// * `bytes` - Size of the asset
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Limited>` - `Ok` if the bytes were counted and may be served
+    pub fn reserve(&self, album: &str, bytes: u64) -> Result<(), Limited> {
+        let Some(quota) = self.quota_of(album) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let mut albums = self.albums.lock().unwrap_or_else(|e| e.into_inner());
+        let window = current_window(&mut albums, album, quota, now);
+        if window.used > 0 && window.used.saturating_add(bytes) > quota.bytes {
+            return Err(Limited {
+                retry_after: (window.started + quota.per).saturating_duration_since(now),
+            });
+        }
+        window.used = window.used.saturating_add(bytes);
+        Ok(())
+    }
+
+    /// Checks whether anything more may be served from an album
+    ///
+    /// Used before responses whose size is only known once served, which
+    /// are then [`ServeLimiter::record`]ed.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Limited>` - `Ok` unless the album's quota is used up
+    pub fn check_quota(&self, album: &str) -> Result<(), Limited> {
+        let Some(quota) = self.quota_of(album) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let mut albums = self.albums.lock().unwrap_or_else(|e| e.into_inner());
+        let window = current_window(&mut albums, album, quota, now);
+        if window.used > 0 && window.used >= quota.bytes {
+            return Err(Limited {
+                retry_after: (window.started + quota.per).saturating_duration_since(now),
+            });
+        }
+        Ok(())
+    }
+
+    /// Counts `bytes` served from an album without checking the quota
+    ///
+    /// Used for responses whose size is only known once served; the bytes
+    /// count against later [`ServeLimiter::reserve`] calls.
+    pub fn record(&self, album: &str, bytes: u64) {
+        let Some(quota) = self.quota_of(album) else {
+            return;
+        };
+        let mut albums = self.albums.lock().unwrap_or_else(|e| e.into_inner());
+        let window = current_window(&mut albums, album, quota, Instant::now());
+        window.used = window.used.saturating_add(bytes);
+    }
+
+    /// Bytes served from an album in the current window
+    pub fn usage(&self, album: &str) -> u64 {
+        let Some(quota) = self.quota_of(album) else {
+            return 0;
+        };
+        let albums = self.albums.lock().unwrap_or_else(|e| e.into_inner());
+        albums
+            .get(album)
+            .filter(|window| Instant::now() < window.started + quota.per)
+            .map_or(0, |window| window.used)
+    }
+
+    /// The quota applying to an album, if any
+    fn quota_of(&self, album: &str) -> Option<BandwidthQuota> {
+        self.album_quotas.get(album).copied().or(self.quota)
+    }
+}
+
+/// The window of an album at `now`, starting a new one if the last has ended
+fn current_window<'a>(
+    albums: &'a mut HashMap<String, Window>,
+    album: &str,
+    quota: BandwidthQuota,
+    now: Instant,
+) -> &'a mut Window {
+    let window = albums.entry(album.to_string()).or_insert(Window {
+        started: now,
+        used: 0,
+    });
+    if now >= window.started + quota.per {
+        *window = Window {
+            started: now,
+            used: 0,
+        };
+    }
+    window
+}
+
+/// The address clients are limited by: IPv6 addresses are cut to their /64
+fn client_key(client: IpAddr) -> IpAddr {
+    match client {
+        IpAddr::V4(_) => client,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => {
+                let mut segments = v6.segments();
+                segments[4..].fill(0);
+                IpAddr::from(segments)
+            }
+        },
+    }
+}
@@ -0,0 +1,141 @@
+//! Redaction of album tokens and signed URLs in logs and error messages.
+//!
+//! Album tokens grant read access to a shared album, and asset URLs carry
+//! signatures in their query strings. Both tend to end up in logs through error
+//! messages. The [`Redacted`] wrapper formats a value with every embedded URL
+//! stripped of its query string and fragment, and with the token segment of
+//! shared stream URLs truncated, so logs are safe to share.
+//!
+//! Redaction is enabled by default and can be turned off process-wide with
+//! [`set_redaction`] when debugging.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether redaction is applied
+static REDACTION_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Number of leading token characters kept by [`redact_token`]
+const TOKEN_PREFIX_LEN: usize = 4;
+
+/// Placeholder for removed content
+const MASK: &str = "***";
+
+/// Enables or disables redaction for the whole process
+pub fn set_redaction(enabled: bool) {
+    REDACTION_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns true if redaction is currently applied
+pub fn redaction_enabled() -> bool {
+    REDACTION_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Truncates an album token to a short prefix
+///
+/// Tokens of up to four characters are masked entirely.
+pub fn redact_token(token: &str) -> String {
+    if !redaction_enabled() {
+        return token.to_string();
+    }
+    if token.chars().count() <= TOKEN_PREFIX_LEN {
+        return MASK.to_string();
+    }
+    let prefix: String = token.chars().take(TOKEN_PREFIX_LEN).collect();
+    format!("{}{}", prefix, MASK)
+}
+
+/// Redacts a single URL
+fn redact_url(url: &str) -> String {
+    let (base, had_query) = match url.find(['?', '#']) {
+        Some(index) => (&url[..index], true),
+        None => (url, false),
+    };
+
+    // Keep the scheme and host, then walk the path segments
+    let path_start = base
+        .find("://")
+        .and_then(|scheme_end| {
+            base[scheme_end + 3..]
+                .find('/')
+                .map(|slash| scheme_end + 3 + slash)
+        })
+        .unwrap_or(base.len());
+    let (origin, path) = base.split_at(path_start);
+
+    let segments: Vec<&str> = path.split('/').collect();
+    let mut redacted = String::from(origin);
+    for (index, segment) in segments.iter().enumerate() {
+        if index > 0 {
+            redacted.push('/');
+        }
+        // Shared stream URLs have the form /<token>/sharedstreams/...
+        if segments.get(index + 1) == Some(&"sharedstreams") && !segment.is_empty() {
+            redacted.push_str(&redact_token(segment));
+        } else {
+            redacted.push_str(segment);
+        }
+    }
+
+    if had_query {
+        redacted.push('?');
+        redacted.push_str(MASK);
+    }
+    redacted
+}
+
+/// Redacts every URL embedded in a piece of text
+///
+/// URLs start at `http://` or `https://` and end at whitespace, a quote or a
+/// closing bracket. Text is returned unchanged when redaction is disabled.
+pub fn redact_text(text: &str) -> String {
+    if !redaction_enabled() {
+        return text.to_string();
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = ["https://", "http://"]
+        .iter()
+        .filter_map(|scheme| rest.find(scheme))
+        .min()
+    {
+        result.push_str(&rest[..start]);
+        let candidate = &rest[start..];
+        let end = candidate
+            .find(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | ')' | ']' | '>' | '<'))
+            .unwrap_or(candidate.len());
+        result.push_str(&redact_url(&candidate[..end]));
+        rest = &candidate[end..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Formats the wrapped value with URLs and tokens redacted
+///
+/// Both `Display` and `Debug` output are passed through [`redact_text`].
+///
+/// ```
+/// use icloud_album_rs::redact::Redacted;
+///
+/// let url = "https://p42-sharedstreams.icloud.com/B0z5qAGN1JIFd3y/sharedstreams/webstream";
+/// assert_eq!(
+///     Redacted(url).to_string(),
+///     "https://p42-sharedstreams.icloud.com/B0z5***/sharedstreams/webstream"
+/// );
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Redacted<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&redact_text(&self.0.to_string()))
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&redact_text(&format!("{:?}", self.0)))
+    }
+}
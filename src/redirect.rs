@@ -3,9 +3,127 @@
 //! This module handles Apple's custom 330 status redirect mechanism used by the
 //! iCloud shared album API. It implements the logic to extract redirect information
 //! from responses and construct appropriate follow-up URLs.
+//!
+//! A 330 body usually only names the host to use, but some carry more, such as
+//! an explanation when the stream doesn't exist at all. [`RedirectInfo`] keeps
+//! the whole body; [`get_redirect_info`] returns it for a single request.
 
+use crate::api::ApiError;
+use crate::logging;
+use crate::models::WebstreamRequest;
 use reqwest::{Client, StatusCode};
-use serde_json::json;
+
+/// The body of a 330 response
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RedirectInfo {
+    /// Host to send the album's requests to (`X-Apple-MMe-Host`)
+    pub host: Option<String>,
+    /// Explanation given instead of, or along with, the host
+    pub message: Option<String>,
+    /// Every other field of the body, as received
+    pub fields: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Body fields that may explain a 330 response, checked in order
+const MESSAGE_FIELDS: &[&str] = &["errorMessage", "message", "reason", "error"];
+
+impl RedirectInfo {
+    /// Parses the JSON body of a 330 response
+    ///
+    /// Bodies that aren't objects give an empty description.
+    pub fn parse(body: &serde_json::Value) -> Self {
+        let mut fields = body.as_object().cloned().unwrap_or_default();
+        let host = match fields.remove("X-Apple-MMe-Host") {
+            Some(serde_json::Value::String(host)) if !host.trim().is_empty() => {
+                Some(host.trim().to_string())
+            }
+            Some(other) => {
+                fields.insert("X-Apple-MMe-Host".to_string(), other);
+                None
+            }
+            None => None,
+        };
+        let message = MESSAGE_FIELDS
+            .iter()
+            .find_map(|name| match fields.get(*name) {
+                Some(serde_json::Value::String(message)) => Some(message.clone()),
+                _ => None,
+            });
+        Self {
+            host,
+            message,
+            fields,
+        }
+    }
+
+    /// Whether the response says the stream doesn't exist, rather than redirecting
+    ///
+    /// Apple only explains this in prose, so the message is searched for "not
+    /// found". Probes report such responses as [`ApiError::AlbumNotFound`].
+    pub fn is_not_found(&self) -> bool {
+        self.host.is_none()
+            && self
+                .message
+                .as_deref()
+                .is_some_and(|message| message.to_ascii_lowercase().contains("not found"))
+    }
+
+    /// The base URL of the album on the redirect host, if there is one
+    pub fn base_url(&self, token: &str) -> Option<String> {
+        self.host
+            .as_deref()
+            .map(|host| crate::base_url::album_base_url(host, token))
+    }
+}
+
+/// Outcome of probing the webstream endpoint for a redirect
+///
+/// The redirect check is itself a full webstream request, so when the original
+/// host answers successfully its body is kept and can be parsed directly instead
+/// of issuing a second identical request.
+#[derive(Debug, Clone)]
+pub enum WebstreamProbe {
+    /// The API answered with a 330 redirect to a different host
+    Redirected(String),
+    /// The original host answered successfully with a reusable webstream body
+    Response {
+        /// The base URL that produced the response
+        base_url: String,
+        /// The parsed JSON body of the webstream response
+        body: serde_json::Value,
+        /// Diagnostic headers of the webstream response
+        headers: crate::api::ResponseHeaders,
+    },
+    /// No redirect was found and there is no reusable body
+    Unchanged(String),
+}
+
+impl WebstreamProbe {
+    /// Returns the base URL that subsequent API calls should use
+    pub fn base_url(&self) -> &str {
+        match self {
+            WebstreamProbe::Redirected(url) => url,
+            WebstreamProbe::Response { base_url, .. } => base_url,
+            WebstreamProbe::Unchanged(url) => url,
+        }
+    }
+
+    /// Consumes the probe, returning the reusable webstream body if there is one
+    pub fn into_body(self) -> Option<serde_json::Value> {
+        match self {
+            WebstreamProbe::Response { body, .. } => Some(body),
+            _ => None,
+        }
+    }
+
+    /// Returns the diagnostic headers of the reusable webstream response, if there is one
+    pub fn response_headers(&self) -> Option<&crate::api::ResponseHeaders> {
+        match self {
+            WebstreamProbe::Response { headers, .. } => Some(headers),
+            _ => None,
+        }
+    }
+}
 
 /// Handles redirects from the iCloud API
 ///
@@ -27,31 +145,345 @@ pub async fn get_redirected_base_url(
     base_url: &str,
     token: &str,
 ) -> Result<String, Box<dyn std::error::Error>> {
+    get_redirected_base_url_with_request(client, base_url, token, &WebstreamRequest::default())
+        .await
+}
+
+/// Handles redirects from the iCloud API using a custom request payload
+///
+/// This behaves like [`get_redirected_base_url`], but sends the given
+/// [`WebstreamRequest`] instead of the default `{"streamCtag": null}` payload.
+///
+/// # Arguments
+///
+/// * `client` - A reqwest HTTP client
+/// * `base_url` - The original base URL
+/// * `token` - The iCloud album token
+/// * `request` - The payload to send to the webstream endpoint
+///
+/// # Returns
+///
+/// A string containing either the original base URL or a redirected URL
+pub async fn get_redirected_base_url_with_request(
+    client: &Client,
+    base_url: &str,
+    token: &str,
+    request: &WebstreamRequest,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let probe = probe_webstream(client, base_url, token, request).await?;
+    Ok(probe.base_url().to_string())
+}
+
+/// Probes the webstream endpoint for a redirect, keeping the body on success
+///
+/// This makes the same request as [`get_redirected_base_url_with_request`], but
+/// when the original host answers with a 2xx status and a JSON body, the body is
+/// returned in [`WebstreamProbe::Response`] so that it can be used as the album
+/// metadata response without a second round trip.
+///
+/// # Arguments
+///
+/// * `client` - A reqwest HTTP client
+/// * `base_url` - The original base URL
+/// * `token` - The iCloud album token
+/// * `request` - The payload to send to the webstream endpoint
+///
+/// # Returns
+///
+/// A [`WebstreamProbe`] describing where subsequent requests should go
+pub async fn probe_webstream(
+    client: &Client,
+    base_url: &str,
+    token: &str,
+    request: &WebstreamRequest,
+) -> Result<WebstreamProbe, Box<dyn std::error::Error>> {
+    Ok(probe_with_status(client, base_url, token, request).await?.0)
+}
+
+/// Returns the body of the 330 response to a webstream request, if there is one
+///
+/// Unlike [`probe_webstream`], this reports what the response said without
+/// deciding where to go next: a host, an explanation, or both.
+///
+/// # Arguments
+///
+/// * `client` - A reqwest HTTP client
+/// * `base_url` - The base URL to send the request to
+/// * `request` - The payload to send to the webstream endpoint
+///
+/// # Returns
+///
+/// The parsed 330 body, or `None` for any other status
+pub async fn get_redirect_info(
+    client: &Client,
+    base_url: &str,
+    request: &WebstreamRequest,
+) -> Result<Option<RedirectInfo>, Box<dyn std::error::Error>> {
+    let url = format!("{}webstream", base_url);
+    let payload = request.to_payload();
+    let resp = crate::har::send(crate::api::api_request(client.post(&url).json(&payload))).await?;
+    if resp.status().as_u16() != 330 {
+        return Ok(None);
+    }
+    Ok(Some(RedirectInfo::parse(
+        &crate::api::api_json(resp).await?,
+    )))
+}
+
+/// Probes the webstream endpoint, also returning the response status
+async fn probe_with_status(
+    client: &Client,
+    base_url: &str,
+    token: &str,
+    request: &WebstreamRequest,
+) -> Result<(WebstreamProbe, StatusCode), Box<dyn std::error::Error>> {
     // Build the URL for the webstream endpoint
     let url = format!("{}webstream", base_url);
 
-    // Create the payload with a null streamCtag
-    let payload = json!({ "streamCtag": null });
+    // Build the payload from the request
+    let payload = request.to_payload();
 
     // Make the POST request
-    let resp = client.post(&url).json(&payload).send().await?;
+    let resp = crate::har::send(crate::api::api_request(client.post(&url).json(&payload))).await?;
+    let status = resp.status();
 
     // Check if we got a 330 status code (Apple's redirect)
     if let Ok(redirect_status) = StatusCode::from_u16(330) {
         if resp.status() == redirect_status {
             // Parse the response body as JSON
-            let body: serde_json::Value = resp.json().await?;
+            let info = RedirectInfo::parse(&crate::api::api_json(resp).await?);
 
-            // Look for the X-Apple-MMe-Host field
-            if let Some(host_val) = body["X-Apple-MMe-Host"].as_str() {
-                // Build and return the new base URL
-                return Ok(format!("https://{}/{}/sharedstreams/", host_val, token));
+            // Build and return the new base URL from the X-Apple-MMe-Host field
+            if let Some(redirected) = info.base_url(token) {
+                return Ok((WebstreamProbe::Redirected(redirected), status));
             }
+            if info.is_not_found() {
+                return Err(ApiError::AlbumNotFound(info.message.unwrap_or_default()).into());
+            }
+            logging::log_debug!(
+                logging::API,
+                "330 response without a host: {:?}",
+                info.fields
+            );
+
+            return Ok((WebstreamProbe::Unchanged(base_url.to_string()), status));
+        }
+    }
+
+    // A successful response is the webstream response itself, so keep its body
+    if resp.status().is_success() {
+        let headers = crate::api::ResponseHeaders::from_response(&resp);
+        if let Ok(body) = crate::api::api_json(resp).await {
+            return Ok((
+                WebstreamProbe::Response {
+                    base_url: base_url.to_string(),
+                    body,
+                    headers,
+                },
+                status,
+            ));
         }
     }
 
     // If we didn't get a redirect or couldn't parse the host, return the original URL
-    Ok(base_url.to_string())
+    Ok((WebstreamProbe::Unchanged(base_url.to_string()), status))
+}
+
+/// Probes the webstream endpoint of an album, trying candidate partitions
+///
+/// The partitions of [`crate::base_url::PartitionScheme`] are tried in order:
+/// the one that last answered for the token, the computed one, then the
+/// scheme's candidates. If the computed partition's host doesn't resolve or
+/// answers 404 and the scheme has a [`crate::base_url::NeighborScan`], the
+/// neighboring partitions are probed next. A partition fails when the request
+/// errors or the probe ends [`WebstreamProbe::Unchanged`]; the first that
+/// answers with a redirect or a body is remembered for the token. When every
+/// partition fails, the outcome of the first one is returned.
+///
+/// # Arguments
+///
+/// * `client` - A reqwest HTTP client
+/// * `token` - The iCloud album token
+/// * `request` - The payload to send to the webstream endpoint
+///
+/// # Returns
+///
+/// A [`WebstreamProbe`] describing where subsequent requests should go
+pub async fn probe_album(
+    client: &Client,
+    token: &str,
+    request: &WebstreamRequest,
+) -> Result<WebstreamProbe, Box<dyn std::error::Error>> {
+    let scheme = crate::base_url::partition_scheme();
+    let computed = scheme.partition(token)?;
+    let mut attempts = PartitionAttempts::default();
+
+    let remembered = crate::base_url::cached_partition(token).filter(|p| *p != computed);
+    if let (Some(delay), Some(remembered)) = (scheme.hedge_delay, remembered) {
+        let hedged = attempts
+            .hedge(client, [remembered, computed], delay, token, request)
+            .await;
+        if let Some(probe) = hedged {
+            return Ok(probe);
+        }
+    }
+
+    let mut host_missing = false;
+    for partition in crate::base_url::candidate_partitions(token)? {
+        if let Some(probe) = attempts.probe(client, partition, token, request).await {
+            return Ok(probe);
+        }
+        if partition == computed {
+            host_missing = attempts.missing.contains(&computed);
+        }
+    }
+
+    if let Some(scan) = scheme.neighbor_scan.filter(|_| host_missing) {
+        for partition in scan.partitions(computed, scheme.count) {
+            if attempts.has_tried(partition, token) {
+                continue;
+            }
+            tokio::time::sleep(scan.delay).await;
+            if let Some(probe) = attempts.probe(client, partition, token, request).await {
+                return Ok(probe);
+            }
+        }
+    }
+
+    attempts
+        .first
+        .unwrap_or_else(|| Err("no partitions to try".into()))
+}
+
+/// The outcome of probing one partition
+type ProbeOutcome = Result<(WebstreamProbe, StatusCode), Box<dyn std::error::Error>>;
+
+/// The partitions probed by [`probe_album`] so far
+#[derive(Default)]
+struct PartitionAttempts {
+    tried: Vec<String>,
+    first: Option<Result<WebstreamProbe, Box<dyn std::error::Error>>>,
+    /// Partitions whose host doesn't exist
+    missing: Vec<u32>,
+}
+
+impl PartitionAttempts {
+    /// Returns whether `partition` maps to a base URL that was already probed
+    fn has_tried(&self, partition: u32, token: &str) -> bool {
+        // With an origin override, every partition maps to the same URL
+        self.tried
+            .contains(&crate::base_url::partition_base_url(partition, token))
+    }
+
+    /// Marks `partition` as tried, returning its base URL unless it was tried before
+    fn start(&mut self, partition: u32, token: &str) -> Option<String> {
+        if self.has_tried(partition, token) {
+            return None;
+        }
+        let base_url = crate::base_url::partition_base_url(partition, token);
+        self.tried.push(base_url.clone());
+        Some(base_url)
+    }
+
+    /// Probes `partition`, returning the probe if it answered
+    async fn probe(
+        &mut self,
+        client: &Client,
+        partition: u32,
+        token: &str,
+        request: &WebstreamRequest,
+    ) -> Option<WebstreamProbe> {
+        let base_url = self.start(partition, token)?;
+        let outcome = probe_with_status(client, &base_url, token, request).await;
+        self.settle(partition, token, outcome)
+    }
+
+    /// Probes the first partition, and the second too once `delay` has passed
+    ///
+    /// # Returns
+    ///
+    /// The probe of whichever partition answered first, if any did
+    async fn hedge(
+        &mut self,
+        client: &Client,
+        [first, second]: [u32; 2],
+        delay: std::time::Duration,
+        token: &str,
+        request: &WebstreamRequest,
+    ) -> Option<WebstreamProbe> {
+        let first_url = self.start(first, token)?;
+        let Some(second_url) = self.start(second, token) else {
+            let outcome = probe_with_status(client, &first_url, token, request).await;
+            return self.settle(first, token, outcome);
+        };
+
+        let first_probe = probe_with_status(client, &first_url, token, request);
+        let second_probe = async {
+            tokio::time::sleep(delay).await;
+            logging::log_debug!(
+                logging::API,
+                "Partition {} is slow to answer, also probing partition {}",
+                first,
+                second
+            );
+            probe_with_status(client, &second_url, token, request).await
+        };
+        tokio::pin!(first_probe, second_probe);
+        let (mut first_done, mut second_done) = (false, false);
+        while !(first_done && second_done) {
+            let (partition, outcome) = tokio::select! {
+                outcome = &mut first_probe, if !first_done => {
+                    first_done = true;
+                    (first, outcome)
+                }
+                outcome = &mut second_probe, if !second_done => {
+                    second_done = true;
+                    (second, outcome)
+                }
+            };
+            if let Some(probe) = self.settle(partition, token, outcome) {
+                return Some(probe);
+            }
+        }
+        None
+    }
+
+    /// Records the outcome of probing `partition`, returning the probe if it answered
+    fn settle(
+        &mut self,
+        partition: u32,
+        token: &str,
+        outcome: ProbeOutcome,
+    ) -> Option<WebstreamProbe> {
+        let outcome = match outcome {
+            Ok((probe @ (WebstreamProbe::Redirected(_) | WebstreamProbe::Response { .. }), _)) => {
+                crate::base_url::remember_partition(token, partition);
+                return Some(probe);
+            }
+            Ok((probe, status)) => {
+                if status == StatusCode::NOT_FOUND {
+                    self.missing.push(partition);
+                }
+                Ok(probe)
+            }
+            Err(e) => {
+                // DNS failures surface as connect errors
+                if e.downcast_ref::<reqwest::Error>()
+                    .is_some_and(|e| e.is_connect())
+                {
+                    self.missing.push(partition);
+                }
+                Err(e)
+            }
+        };
+        logging::log_debug!(
+            logging::API,
+            "Partition {} did not answer for the album",
+            partition
+        );
+        self.first.get_or_insert(outcome);
+        None
+    }
 }
 
 // All testing is done in the separate integration tests
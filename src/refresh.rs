@@ -0,0 +1,306 @@
+//! Keeping the asset URLs of albums valid around the clock.
+//!
+//! Asset URLs returned by the webasseturls endpoint expire after a while, so
+//! applications that show albums for days (signage, kiosks, photo frames) end
+//! up with broken images unless they resolve the URLs again. A
+//! [`UrlRefresher`] tracks a set of albums and, shortly before the URLs of an
+//! album expire, resolves them again in batched webasseturls calls. Consumers
+//! read the photos through a [`RefreshedPhotos`] handle, which always holds
+//! the latest URLs.
+//!
+//! The refresher only renews URLs; it does not notice photos being added or
+//! removed. Pair it with an [`crate::watch::AlbumWatcher`] for that.
+
+use crate::api::ApiError;
+use crate::enrich;
+use crate::logging;
+use crate::models::Image;
+use crate::observer::NoopObserver;
+use crate::redact::Redacted;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Default for [`RefreshConfig::url_lifetime`]
+pub const DEFAULT_URL_LIFETIME: Duration = Duration::from_secs(60 * 60);
+/// Default for [`RefreshConfig::margin`]
+pub const DEFAULT_MARGIN: Duration = Duration::from_secs(10 * 60);
+/// Default for [`RefreshConfig::batch_size`]
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+/// Default for [`RefreshConfig::retry_delay`]
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// When and how asset URLs are refreshed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshConfig {
+    /// How long asset URLs stay valid after they were resolved
+    pub url_lifetime: Duration,
+    /// How long before expiry the URLs are refreshed
+    pub margin: Duration,
+    /// Maximum number of photos per webasseturls request
+    pub batch_size: usize,
+    /// How long to wait before retrying a failed refresh
+    pub retry_delay: Duration,
+}
+
+impl Default for RefreshConfig {
+    fn default() -> Self {
+        Self {
+            url_lifetime: DEFAULT_URL_LIFETIME,
+            margin: DEFAULT_MARGIN,
+            batch_size: DEFAULT_BATCH_SIZE,
+            retry_delay: DEFAULT_RETRY_DELAY,
+        }
+    }
+}
+
+/// Photos and refresh bookkeeping shared with consumers
+#[derive(Debug)]
+struct AlbumState {
+    photos: Vec<Image>,
+    refreshed_at: Instant,
+    refreshes: u64,
+}
+
+/// Read access to the photos of an album tracked by a [`UrlRefresher`]
+///
+/// Handles are cheap to clone and can be passed to other tasks.
+#[derive(Debug, Clone)]
+pub struct RefreshedPhotos {
+    state: Arc<RwLock<AlbumState>>,
+}
+
+impl RefreshedPhotos {
+    /// Returns the photos with their latest URLs
+    pub fn photos(&self) -> Vec<Image> {
+        self.read(|state| state.photos.clone())
+    }
+
+    /// Returns when the URLs were last resolved
+    pub fn refreshed_at(&self) -> Instant {
+        self.read(|state| state.refreshed_at)
+    }
+
+    /// Returns how often the URLs have been refreshed
+    pub fn refreshes(&self) -> u64 {
+        self.read(|state| state.refreshes)
+    }
+
+    fn read<T>(&self, f: impl FnOnce(&AlbumState) -> T) -> T {
+        f(&self.state.read().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// An album tracked by the refresher
+#[derive(Debug)]
+struct TrackedAlbum {
+    base_url: String,
+    state: Arc<RwLock<AlbumState>>,
+    next_refresh: Instant,
+}
+
+/// Outcome of a refresh pass
+#[derive(Debug, Default)]
+pub struct RefreshReport {
+    /// Base URLs of the albums whose URLs were refreshed
+    pub refreshed: Vec<String>,
+    /// Base URLs of the albums that failed, with the error; they are retried later
+    pub failed: Vec<(String, ApiError)>,
+    /// Number of webasseturls requests made
+    pub requests: usize,
+}
+
+/// Background task type refreshing the asset URLs of albums before they expire
+///
+/// Add albums with [`UrlRefresher::add_album`] or [`UrlRefresher::track`],
+/// then either call [`UrlRefresher::refresh_due`] from your own loop or hand
+/// the refresher to [`UrlRefresher::spawn`].
+#[derive(Debug)]
+pub struct UrlRefresher {
+    client: reqwest::Client,
+    config: RefreshConfig,
+    albums: Vec<TrackedAlbum>,
+}
+
+impl UrlRefresher {
+    /// Create a refresher without albums
+    pub fn new(config: RefreshConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            config,
+            albums: Vec::new(),
+        }
+    }
+
+    /// Send requests through `client`
+    #[must_use]
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Returns the configuration of the refresher
+    pub fn config(&self) -> &RefreshConfig {
+        &self.config
+    }
+
+    /// Fetches an album, resolves its URLs and starts tracking it
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The iCloud shared album token
+    ///
+    /// # Returns
+    ///
+    /// A handle to the album's photos, or an error if the album could not be fetched
+    pub async fn track(
+        &mut self,
+        token: &str,
+    ) -> Result<RefreshedPhotos, Box<dyn std::error::Error>> {
+        let (base_url, mut photos, _metadata) =
+            crate::fetch_webstream(&self.client, token, &NoopObserver).await?;
+        resolve_in_batches(
+            &self.client,
+            &base_url,
+            &mut photos,
+            self.config.batch_size,
+            &mut 0,
+        )
+        .await?;
+        Ok(self.add_album(&base_url, photos))
+    }
+
+    /// Starts tracking an album whose URLs were just resolved
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - The (redirected) base URL for API requests of the album
+    /// * `photos` - The photos of the album, enriched with URLs
+    ///
+    /// # Returns
+    ///
+    /// A handle to the album's photos
+    pub fn add_album(&mut self, base_url: &str, photos: Vec<Image>) -> RefreshedPhotos {
+        let now = Instant::now();
+        let state = Arc::new(RwLock::new(AlbumState {
+            photos,
+            refreshed_at: now,
+            refreshes: 0,
+        }));
+        self.albums.push(TrackedAlbum {
+            base_url: base_url.to_string(),
+            state: Arc::clone(&state),
+            next_refresh: now + self.refresh_after(),
+        });
+        RefreshedPhotos { state }
+    }
+
+    /// Returns the number of tracked albums
+    pub fn len(&self) -> usize {
+        self.albums.len()
+    }
+
+    /// Returns true if no albums are tracked
+    pub fn is_empty(&self) -> bool {
+        self.albums.is_empty()
+    }
+
+    /// Returns when the next album becomes due, or `None` without albums
+    pub fn next_refresh(&self) -> Option<Instant> {
+        self.albums.iter().map(|album| album.next_refresh).min()
+    }
+
+    /// Time from resolving URLs to refreshing them
+    fn refresh_after(&self) -> Duration {
+        self.config.url_lifetime.saturating_sub(self.config.margin)
+    }
+
+    /// Refreshes the URLs of every album that is due
+    ///
+    /// An album is due once its URLs expire within the configured margin.
+    /// Photos are resolved in batches of at most
+    /// [`RefreshConfig::batch_size`]; an album whose refresh fails keeps its
+    /// previous URLs and is retried after [`RefreshConfig::retry_delay`].
+    ///
+    /// # Returns
+    ///
+    /// A report of the refreshed and failed albums
+    pub async fn refresh_due(&mut self) -> RefreshReport {
+        let mut report = RefreshReport::default();
+        let now = Instant::now();
+        let refresh_after = self.refresh_after();
+        for album in self.albums.iter_mut().filter(|a| a.next_refresh <= now) {
+            let mut photos = album
+                .state
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .photos
+                .clone();
+            match resolve_in_batches(
+                &self.client,
+                &album.base_url,
+                &mut photos,
+                self.config.batch_size,
+                &mut report.requests,
+            )
+            .await
+            {
+                Ok(()) => {
+                    let refreshed_at = Instant::now();
+                    let mut state = album.state.write().unwrap_or_else(|e| e.into_inner());
+                    state.photos = photos;
+                    state.refreshed_at = refreshed_at;
+                    state.refreshes += 1;
+                    album.next_refresh = refreshed_at + refresh_after;
+                    report.refreshed.push(album.base_url.clone());
+                }
+                Err(e) => {
+                    logging::log_warn!(
+                        logging::API,
+                        "Refreshing asset URLs of {} failed: {}",
+                        Redacted(&album.base_url),
+                        e
+                    );
+                    album.next_refresh = Instant::now() + self.config.retry_delay;
+                    report.failed.push((album.base_url.clone(), e));
+                }
+            }
+        }
+        report
+    }
+
+    /// Refreshes URLs forever, sleeping until the next album is due
+    ///
+    /// Returns immediately if no albums are tracked.
+    pub async fn run(&mut self) {
+        while let Some(next) = self.next_refresh() {
+            tokio::time::sleep_until(next).await;
+            logging::in_operation(self.refresh_due()).await;
+        }
+    }
+
+    /// Runs the refresher on a background task
+    ///
+    /// Abort the returned handle to stop it.
+    pub fn spawn(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move { self.run().await })
+    }
+}
+
+/// Resolves the URLs of `photos` with at most `batch_size` photos per request
+///
+/// `requests` is incremented for every request sent.
+async fn resolve_in_batches(
+    client: &reqwest::Client,
+    base_url: &str,
+    photos: &mut [Image],
+    batch_size: usize,
+    requests: &mut usize,
+) -> Result<(), ApiError> {
+    for batch in photos.chunks_mut(batch_size.max(1)) {
+        *requests += 1;
+        enrich::resolve_photo_urls(client, base_url, batch).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,522 @@
+//! Resizing images on the fly for frontends that proxy assets.
+//!
+//! iCloud only offers a few fixed renditions of each photo, while frontends
+//! want images of exactly the size they lay out. A [`ResizeRequest`], parsed
+//! from `?w=&h=&fit=` query parameters, says which size is wanted, and a
+//! [`Resizer`] produces it from a downloaded derivative. Resized images are
+//! kept in a [`ResizeCache`] on disk, which evicts the least recently used
+//! ones once it outgrows its budget, so popular sizes are encoded once.
+//!
+//! Images are decoded through [`RgbDecoder`] and encoded through
+//! [`ImageEncoder`]. [`crate::codec::StandardDecoder`] decodes JPEG and PNG
+//! derivatives, and [`JpegEncoder`] and [`PngEncoder`] encode the results,
+//! all with the `image` crate. Applications can implement the traits with
+//! another library; [`PpmEncoder`] writes binary PPM files.
+//!
+//! Resizing is CPU-bound and the cache is read and written with `std::fs`,
+//! so servers run [`Resizer::resize_asset`] on tokio's blocking pool.
+//! The serve mode does so for asset URLs with `?w=&h=` when its
+//! `ServerConfig` has a resizer.
+
+use crate::models::Derivative;
+use crate::placeholder::{DecodeError, RgbDecoder, RgbImage};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Largest width or height a [`ResizeRequest`] may ask for
+pub const MAX_DIMENSION: u32 = 4096;
+
+/// How an image is fitted into the requested box
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Fit {
+    /// Scale to fit within the box, keeping the aspect ratio; never enlarges
+    #[default]
+    Contain,
+    /// Scale to cover the box, keeping the aspect ratio, and crop the overflow
+    Cover,
+    /// Stretch to exactly the box
+    Fill,
+}
+
+impl fmt::Display for Fit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Fit::Contain => "contain",
+            Fit::Cover => "cover",
+            Fit::Fill => "fill",
+        })
+    }
+}
+
+/// The size an image should be resized to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResizeRequest {
+    /// Requested width, or `None` to follow the height
+    pub width: Option<u32>,
+    /// Requested height, or `None` to follow the width
+    pub height: Option<u32>,
+    /// How the image is fitted when both sides are given
+    pub fit: Fit,
+}
+
+impl ResizeRequest {
+    /// Parses the `w`, `h` and `fit` parameters of a URL query string
+    ///
+    /// Other parameters are ignored. `fit` is `contain` (the default),
+    /// `cover` or `fill`.
+    ///
+    /// # Returns
+    ///
+    /// The request, `None` if neither `w` nor `h` is given, or an error
+    /// describing an invalid parameter, e.g. to answer with 400 Bad Request
+    pub fn from_query(query: &str) -> Result<Option<Self>, String> {
+        let mut request = ResizeRequest {
+            width: None,
+            height: None,
+            fit: Fit::default(),
+        };
+        let dimension = |name: &str, value: &str| match value.parse::<u32>() {
+            Ok(pixels) if (1..=MAX_DIMENSION).contains(&pixels) => Ok(pixels),
+            _ => Err(format!(
+                "{} must be a number of pixels from 1 to {}, not {:?}",
+                name, MAX_DIMENSION, value
+            )),
+        };
+        for pair in query.trim_start_matches('?').split('&') {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match name {
+                "w" => request.width = Some(dimension(name, value)?),
+                "h" => request.height = Some(dimension(name, value)?),
+                "fit" => {
+                    request.fit = match value {
+                        "contain" => Fit::Contain,
+                        "cover" => Fit::Cover,
+                        "fill" => Fit::Fill,
+                        _ => return Err(format!("unknown fit {:?}", value)),
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok((request.width.is_some() || request.height.is_some()).then_some(request))
+    }
+
+    /// Identifies the resized rendition of a derivative, e.g. for a cache or an ETag
+    pub fn rendition_key(&self, checksum: &str) -> String {
+        let checksum: String = checksum
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect();
+        format!(
+            "{}-{}x{}-{}",
+            checksum,
+            self.width.unwrap_or(0),
+            self.height.unwrap_or(0),
+            self.fit
+        )
+    }
+}
+
+/// Resizes an image as requested
+///
+/// With one side given, the other follows the aspect ratio and, as with
+/// [`Fit::Contain`], the image is never enlarged. Images are downscaled by
+/// averaging the pixels each output pixel covers.
+pub fn resize(image: &RgbImage, request: &ResizeRequest) -> RgbImage {
+    let (source_width, source_height) = (image.width as f64, image.height as f64);
+    let proportional = |scale: f64| {
+        (
+            ((source_width * scale).round() as usize).max(1),
+            ((source_height * scale).round() as usize).max(1),
+        )
+    };
+    match (request.width, request.height, request.fit) {
+        (Some(width), None, _) => {
+            let (width, height) = proportional((width as f64 / source_width).min(1.0));
+            scale(image, (0, 0, image.width, image.height), width, height)
+        }
+        (None, Some(height), _) => {
+            let (width, height) = proportional((height as f64 / source_height).min(1.0));
+            scale(image, (0, 0, image.width, image.height), width, height)
+        }
+        (Some(width), Some(height), Fit::Contain) => {
+            let ratio = (width as f64 / source_width)
+                .min(height as f64 / source_height)
+                .min(1.0);
+            let (width, height) = proportional(ratio);
+            scale(image, (0, 0, image.width, image.height), width, height)
+        }
+        (Some(width), Some(height), Fit::Cover) => {
+            // Crop the source to the box's aspect ratio, centered
+            let (width, height) = (width as usize, height as usize);
+            let box_ratio = width as f64 / height as f64;
+            let (crop_width, crop_height) = if source_width / source_height > box_ratio {
+                (
+                    ((source_height * box_ratio).round() as usize).max(1),
+                    image.height,
+                )
+            } else {
+                (
+                    image.width,
+                    ((source_width / box_ratio).round() as usize).max(1),
+                )
+            };
+            let left = (image.width - crop_width) / 2;
+            let top = (image.height - crop_height) / 2;
+            scale(image, (left, top, crop_width, crop_height), width, height)
+        }
+        (Some(width), Some(height), Fit::Fill) => scale(
+            image,
+            (0, 0, image.width, image.height),
+            width as usize,
+            height as usize,
+        ),
+        (None, None, _) => image.clone(),
+    }
+}
+
+/// Scales the `(left, top, width, height)` region of an image to `width` x `height`
+fn scale(
+    image: &RgbImage,
+    (left, top, region_width, region_height): (usize, usize, usize, usize),
+    width: usize,
+    height: usize,
+) -> RgbImage {
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let y0 = top + y * region_height / height;
+        let y1 = (top + (y + 1) * region_height / height).max(y0 + 1);
+        for x in 0..width {
+            let x0 = left + x * region_width / width;
+            let x1 = (left + (x + 1) * region_width / width).max(x0 + 1);
+            let mut sum = [0u32; 3];
+            for row in y0..y1 {
+                for pixel in &image.pixels[row * image.width + x0..row * image.width + x1] {
+                    for (total, &channel) in sum.iter_mut().zip(pixel) {
+                        *total += channel as u32;
+                    }
+                }
+            }
+            let count = ((y1 - y0) * (x1 - x0)) as u32;
+            pixels.push(sum.map(|total| ((total + count / 2) / count) as u8));
+        }
+    }
+    RgbImage {
+        width,
+        height,
+        pixels,
+    }
+}
+
+/// Encodes RGB pixels into an image file
+pub trait ImageEncoder: Send + Sync {
+    /// Content type of the encoded files, e.g. `image/jpeg`
+    fn content_type(&self) -> &str;
+
+    /// Encodes an image
+    fn encode(&self, image: &RgbImage) -> Result<Vec<u8>, DecodeError>;
+}
+
+/// Encoder for binary PPM (`P6`) images
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PpmEncoder;
+
+impl ImageEncoder for PpmEncoder {
+    fn content_type(&self) -> &str {
+        "image/x-portable-pixmap"
+    }
+
+    fn encode(&self, image: &RgbImage) -> Result<Vec<u8>, DecodeError> {
+        let mut bytes = format!("P6\n{} {}\n255\n", image.width, image.height).into_bytes();
+        bytes.extend(image.pixels.iter().flatten());
+        Ok(bytes)
+    }
+}
+
+/// Encoder for baseline JPEG images
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JpegEncoder {
+    /// Quality from 1 to 100
+    pub quality: u8,
+}
+
+impl Default for JpegEncoder {
+    fn default() -> Self {
+        Self { quality: 85 }
+    }
+}
+
+impl ImageEncoder for JpegEncoder {
+    fn content_type(&self) -> &str {
+        "image/jpeg"
+    }
+
+    fn encode(&self, image: &RgbImage) -> Result<Vec<u8>, DecodeError> {
+        let mut bytes = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, self.quality.clamp(1, 100))
+            .encode(
+                &image.pixels.concat(),
+                image.width as u32,
+                image.height as u32,
+                image::ExtendedColorType::Rgb8,
+            )?;
+        Ok(bytes)
+    }
+}
+
+/// Encoder for PNG images
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PngEncoder;
+
+impl ImageEncoder for PngEncoder {
+    fn content_type(&self) -> &str {
+        "image/png"
+    }
+
+    fn encode(&self, image: &RgbImage) -> Result<Vec<u8>, DecodeError> {
+        use image::ImageEncoder as _;
+        let mut bytes = Vec::new();
+        image::codecs::png::PngEncoder::new(&mut bytes).write_image(
+            &image.pixels.concat(),
+            image.width as u32,
+            image.height as u32,
+            image::ExtendedColorType::Rgb8,
+        )?;
+        Ok(bytes)
+    }
+}
+
+/// Resized images kept on disk, evicting the least recently used
+#[derive(Debug)]
+pub struct ResizeCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    state: Mutex<CacheState>,
+}
+
+/// Size and last use of every cached image
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<String, (u64, u64)>,
+    total: u64,
+    clock: u64,
+}
+
+impl ResizeCache {
+    /// Opens the cache in `dir`, creating it if needed
+    ///
+    /// Images already in the directory are kept, and considered used in the
+    /// order they were last written.
+    pub fn open(dir: impl Into<PathBuf>, max_bytes: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if metadata.is_file() && !name.ends_with(".tmp") {
+                files.push((metadata.modified()?, name, metadata.len()));
+            }
+        }
+        files.sort();
+
+        let mut state = CacheState::default();
+        for (_, name, size) in files {
+            state.clock += 1;
+            state.total += size;
+            state.entries.insert(name, (size, state.clock));
+        }
+        let cache = Self {
+            dir,
+            max_bytes,
+            state: Mutex::new(state),
+        };
+        cache.evict(&mut cache.lock())?;
+        Ok(cache)
+    }
+
+    /// Directory the images are kept in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Number of cached images
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Whether the cache holds no images
+    pub fn is_empty(&self) -> bool {
+        self.lock().entries.is_empty()
+    }
+
+    /// Total size of the cached images in bytes
+    pub fn size_bytes(&self) -> u64 {
+        self.lock().total
+    }
+
+    /// Returns the image cached under `key`, marking it as used
+    pub fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let mut state = self.lock();
+        if !state.entries.contains_key(key) {
+            return Ok(None);
+        }
+        match std::fs::read(self.dir.join(key)) {
+            Ok(bytes) => {
+                state.clock += 1;
+                let clock = state.clock;
+                if let Some(entry) = state.entries.get_mut(key) {
+                    entry.1 = clock;
+                }
+                Ok(Some(bytes))
+            }
+            // Removed behind the cache's back
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if let Some((size, _)) = state.entries.remove(key) {
+                    state.total -= size;
+                }
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Stores an image under `key`, evicting others to stay within the budget
+    ///
+    /// Images larger than the whole budget are not stored.
+    pub fn put(&self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        let size = bytes.len() as u64;
+        if size > self.max_bytes {
+            return Ok(());
+        }
+        let mut state = self.lock();
+        let path = self.dir.join(key);
+        let partial = self.dir.join(format!("{}.tmp", key));
+        std::fs::write(&partial, bytes)?;
+        std::fs::rename(&partial, &path)?;
+
+        state.clock += 1;
+        let clock = state.clock;
+        if let Some((previous, _)) = state.entries.insert(key.to_string(), (size, clock)) {
+            state.total -= previous;
+        }
+        state.total += size;
+        self.evict(&mut state)
+    }
+
+    /// Removes the least recently used images until the cache fits its budget
+    fn evict(&self, state: &mut CacheState) -> io::Result<()> {
+        while state.total > self.max_bytes {
+            let Some(oldest) = state
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            match std::fs::remove_file(self.dir.join(&oldest)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            if let Some((size, _)) = state.entries.remove(&oldest) {
+                state.total -= size;
+            }
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A resized image, ready to send
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResizedImage {
+    /// The encoded image
+    pub bytes: Vec<u8>,
+    /// Its content type, see [`ImageEncoder::content_type`]
+    pub content_type: String,
+    /// Whether it came from the [`ResizeCache`]
+    pub cached: bool,
+}
+
+/// Decodes, resizes and encodes derivatives, caching the results
+#[derive(Clone)]
+pub struct Resizer {
+    decoder: Arc<dyn RgbDecoder>,
+    encoder: Arc<dyn ImageEncoder>,
+    cache: Option<Arc<ResizeCache>>,
+}
+
+impl fmt::Debug for Resizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resizer")
+            .field("content_type", &self.encoder.content_type())
+            .field("cache", &self.cache)
+            .finish()
+    }
+}
+
+impl Resizer {
+    /// Create a resizer with a codec and no cache
+    pub fn new(decoder: Arc<dyn RgbDecoder>, encoder: Arc<dyn ImageEncoder>) -> Self {
+        Self {
+            decoder,
+            encoder,
+            cache: None,
+        }
+    }
+
+    /// Keep resized images in `cache`
+    #[must_use]
+    pub fn with_cache(mut self, cache: Arc<ResizeCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Resizes the content of a derivative
+    ///
+    /// Renditions are cached under [`ResizeRequest::rendition_key`] of the
+    /// derivative's checksum; derivatives without a checksum aren't cached.
+    ///
+    /// # Returns
+    ///
+    /// The resized image, or an error if the content could not be decoded or
+    /// the result encoded
+    pub fn resize_asset(
+        &self,
+        derivative: &Derivative,
+        content: &[u8],
+        request: &ResizeRequest,
+    ) -> Result<ResizedImage, DecodeError> {
+        let key =
+            (!derivative.checksum.is_empty()).then(|| request.rendition_key(&derivative.checksum));
+        let content_type = self.encoder.content_type().to_string();
+        if let (Some(cache), Some(key)) = (&self.cache, &key) {
+            if let Some(bytes) = cache.get(key)? {
+                return Ok(ResizedImage {
+                    bytes,
+                    content_type,
+                    cached: true,
+                });
+            }
+        }
+
+        let image = resize(&self.decoder.decode(content)?, request);
+        let bytes = self.encoder.encode(&image)?;
+        if let (Some(cache), Some(key)) = (&self.cache, &key) {
+            cache.put(key, &bytes)?;
+        }
+        Ok(ResizedImage {
+            bytes,
+            content_type,
+            cached: false,
+        })
+    }
+}
@@ -0,0 +1,683 @@
+//! Scheduling the polls of many followed albums.
+//!
+//! Polling every followed album at the same moment sends a burst of requests
+//! to iCloud each interval and starves albums whose turn comes last. A
+//! [`PollScheduler`] instead:
+//!
+//! * staggers the first polls of new albums over a window, so albums added
+//!   together don't stay in lockstep
+//! * runs polls through one shared [`AdaptiveLimit`], e.g. the one the
+//!   application's downloads use, always giving a freed slot to the album
+//!   that has waited longest past its due time
+//! * polls albums that change often more frequently, down to a fraction of
+//!   their configured interval, and quiet albums at their configured interval
+//!
+//! An album whose polls fail is backed off exponentially, up to a maximum,
+//! and reported with a [`PollHealth::PersistentError`] once it keeps failing
+//! or its token is rejected, e.g. because sharing was disabled.
+//!
+//! A [`Jitter`] lengthens or shortens each album's interval by a fixed,
+//! token-dependent amount, so albums and deployments sharing an interval
+//! drift apart instead of polling at the same second.
+//!
+//! [`PollScheduler::overall_health`] summarizes the scheduler for liveness checks of
+//! long-running mirror jobs: when a poll last succeeded, the last error and
+//! how many albums are waiting for a free slot.
+//!
+//! Albums are identified by their token and can be added, removed and
+//! rescheduled while the scheduler runs, e.g. by applying the changes of a
+//! [`crate::album_config::ConfigWatcher`] with [`PollScheduler::apply`].
+
+use crate::album_config::ConfigChange;
+use crate::api::ApiError;
+use crate::logging;
+use crate::redact::Redacted;
+use crate::shutdown::Shutdown;
+use crate::throttle::AdaptiveLimit;
+use crate::utils;
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Notify;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+/// Default number of polls running at once when no limit is shared
+pub const DEFAULT_CONCURRENT_POLLS: usize = 4;
+
+/// Default window over which the first polls of new albums are spread
+pub const DEFAULT_STAGGER: Duration = Duration::from_secs(60);
+
+/// Default fraction of the configured interval busy albums are polled at
+pub const DEFAULT_BUSY_FACTOR: f64 = 0.25;
+
+/// Default for the longest time a failing album is backed off
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Default number of consecutive failures after which an album's error is persistent
+pub const DEFAULT_PERSISTENT_AFTER: u32 = 3;
+
+/// Status codes with which iCloud rejects the token of an album
+const REJECTED_STATUS_CODES: &[u16] = &[401, 403, 404, 410];
+
+/// Weight of the latest poll in an album's activity
+const ACTIVITY_WEIGHT: f64 = 0.3;
+
+/// Spreads successive values evenly over `[0, 1)`
+const GOLDEN_RATIO_FRACTION: f64 = 0.618_033_988_749_895;
+
+/// Error returned by a poll run through [`PollScheduler::run`]
+pub type PollError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Deterministic variation of poll intervals
+///
+/// Every album's interval is scaled by a factor between `1 - fraction` and
+/// `1 + fraction` derived from a hash of its token and the seed. The factor
+/// is the same on every run, so schedules stay predictable, while albums (and,
+/// with different seeds, instances following the same album) are spread out.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Jitter {
+    /// Largest relative change of an interval, at most 0.5; 0 disables jitter
+    pub fraction: f64,
+    /// Mixed into the hash, e.g. an instance ID, to spread instances following the same album
+    pub seed: u64,
+}
+
+impl Jitter {
+    /// Vary intervals by up to `fraction` of their length
+    pub fn new(fraction: f64) -> Self {
+        Self { fraction, seed: 0 }
+    }
+
+    /// Mix `seed` into the hash of the token
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Returns `interval` varied for the album with the given token
+    ///
+    /// `fraction` is clamped between 0 and 0.5; a NaN or infinite `fraction`
+    /// disables jitter.
+    pub fn apply(&self, interval: Duration, token: &str) -> Duration {
+        let fraction = if self.fraction.is_finite() {
+            self.fraction.clamp(0.0, 0.5)
+        } else {
+            0.0
+        };
+        if fraction == 0.0 {
+            return interval;
+        }
+        let hash = utils::stable_hash(
+            self.seed
+                .to_le_bytes()
+                .iter()
+                .chain(token.as_bytes())
+                .copied(),
+        );
+        // Map the hash to [-1, 1]
+        let offset = hash as f64 / u64::MAX as f64 * 2.0 - 1.0;
+        interval.mul_f64(1.0 + fraction * offset)
+    }
+}
+
+/// Whether the polls of an album succeed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PollHealth {
+    /// The last poll succeeded, or the album wasn't polled yet
+    Healthy,
+    /// Recent polls failed; the album is retried with exponential back-off
+    Retrying {
+        /// Consecutive failed polls
+        failures: u32,
+        /// Error of the last poll
+        last_error: String,
+    },
+    /// The album keeps failing or its token was rejected
+    ///
+    /// The album is still retried, with exponential back-off, in case the
+    /// problem goes away; applications should surface the error, e.g. to
+    /// prompt removing the album.
+    PersistentError {
+        /// Consecutive failed polls
+        failures: u32,
+        /// Error of the last poll
+        last_error: String,
+    },
+}
+
+/// The schedule of one album, as returned by [`PollScheduler::schedule`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledAlbum {
+    /// Token of the album
+    pub token: String,
+    /// The configured time between polls
+    pub interval: Duration,
+    /// The time between polls given the album's activity and the jitter
+    pub effective_interval: Duration,
+    /// When the album is polled next
+    pub next_due: Instant,
+    /// How often recent polls found changes, from 0 (never) to 1 (every time)
+    pub activity: f64,
+    /// Whether a poll of the album is running
+    pub polling: bool,
+    /// Whether the album's polls succeed
+    pub health: PollHealth,
+}
+
+/// A failed poll, as reported by [`PollScheduler::overall_health`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PollFailure {
+    /// Token of the album
+    pub token: String,
+    /// Why the poll failed
+    pub error: String,
+    /// When the poll failed
+    pub at: SystemTime,
+}
+
+/// Health of the scheduler as a whole, as returned by [`PollScheduler::overall_health`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SchedulerHealth {
+    /// Number of followed albums
+    pub albums: usize,
+    /// Albums past their due time that wait for a free slot
+    pub queue_depth: usize,
+    /// Polls running
+    pub polling: usize,
+    /// When a poll of any album last succeeded
+    pub last_success: Option<SystemTime>,
+    /// The most recent failed poll of any album, even if it has recovered since
+    pub last_error: Option<PollFailure>,
+    /// Albums whose last poll failed
+    pub failing: usize,
+    /// Albums reported with [`PollHealth::PersistentError`]
+    pub persistent_errors: usize,
+}
+
+impl SchedulerHealth {
+    /// Whether no album's errors are persistent
+    pub fn is_healthy(&self) -> bool {
+        self.persistent_errors == 0
+    }
+
+    /// Time since a poll last succeeded, or `None` if none has yet
+    pub fn since_last_success(&self) -> Option<Duration> {
+        self.last_success
+            .map(|at| at.elapsed().unwrap_or(Duration::ZERO))
+    }
+}
+
+/// Scheduling state of one album
+#[derive(Debug)]
+struct Slot {
+    interval: Duration,
+    next_due: Instant,
+    activity: f64,
+    polling: bool,
+    failures: u32,
+    last_error: Option<String>,
+    persistent: bool,
+}
+
+impl Slot {
+    fn health(&self) -> PollHealth {
+        match (&self.last_error, self.persistent) {
+            (None, _) => PollHealth::Healthy,
+            (Some(last_error), false) => PollHealth::Retrying {
+                failures: self.failures,
+                last_error: last_error.clone(),
+            },
+            (Some(last_error), true) => PollHealth::PersistentError {
+                failures: self.failures,
+                last_error: last_error.clone(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    albums: HashMap<String, Slot>,
+    added: u64,
+    last_success: Option<SystemTime>,
+    last_error: Option<PollFailure>,
+}
+
+/// Schedules the polls of a changing set of albums
+///
+/// The scheduler is shared through an [`Arc`]: one task drives it with
+/// [`PollScheduler::run`] while others add and remove albums.
+#[derive(Debug)]
+pub struct PollScheduler {
+    limit: Arc<AdaptiveLimit>,
+    stagger: Duration,
+    busy_factor: f64,
+    jitter: Jitter,
+    max_backoff: Duration,
+    persistent_after: u32,
+    shutdown: Shutdown,
+    state: Mutex<State>,
+    changed: Notify,
+}
+
+impl Default for PollScheduler {
+    fn default() -> Self {
+        Self {
+            limit: Arc::new(AdaptiveLimit::fixed(DEFAULT_CONCURRENT_POLLS)),
+            stagger: DEFAULT_STAGGER,
+            busy_factor: DEFAULT_BUSY_FACTOR,
+            jitter: Jitter::default(),
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            persistent_after: DEFAULT_PERSISTENT_AFTER,
+            shutdown: Shutdown::new(),
+            state: Mutex::new(State::default()),
+            changed: Notify::new(),
+        }
+    }
+}
+
+impl PollScheduler {
+    /// Create a scheduler without albums
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run polls through `limit`, shared with the application's other requests
+    #[must_use]
+    pub fn with_limit(mut self, limit: Arc<AdaptiveLimit>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Spread the first polls of new albums over `stagger`
+    ///
+    /// An album's first poll is never later than its interval.
+    #[must_use]
+    pub fn with_stagger(mut self, stagger: Duration) -> Self {
+        self.stagger = stagger;
+        self
+    }
+
+    /// Poll the busiest albums at `busy_factor` times their interval
+    ///
+    /// `busy_factor` is clamped between 0.01 and 1; 1, or a NaN or infinite
+    /// `busy_factor`, disables prioritizing.
+    #[must_use]
+    pub fn with_busy_factor(mut self, busy_factor: f64) -> Self {
+        self.busy_factor = if busy_factor.is_finite() {
+            busy_factor.clamp(0.01, 1.0)
+        } else {
+            1.0
+        };
+        self
+    }
+
+    /// Spread the polls of albums with the same interval, see [`Jitter`]
+    #[must_use]
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Back failing albums off for at most `max_backoff`
+    ///
+    /// Albums with an interval longer than `max_backoff` are retried at their interval.
+    #[must_use]
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Report an album's error as persistent after `failures` consecutive failed polls
+    #[must_use]
+    pub fn with_persistent_after(mut self, failures: u32) -> Self {
+        self.persistent_after = failures.max(1);
+        self
+    }
+
+    /// Stop when `shutdown` is triggered, e.g. a signal shared with other components
+    #[must_use]
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Returns the scheduler's shutdown signal, for stopping it from another task
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Time between polls of an album with the given interval and activity
+    fn effective_interval(&self, token: &str, interval: Duration, activity: f64) -> Duration {
+        let interval = interval.mul_f64(1.0 - (1.0 - self.busy_factor) * activity);
+        self.jitter.apply(interval, token)
+    }
+
+    /// Starts following an album, or changes its interval if already followed
+    pub fn add(&self, token: &str, interval: Duration) {
+        let mut state = self.state();
+        if state.albums.contains_key(token) {
+            drop(state);
+            self.set_interval(token, interval);
+            return;
+        }
+        let offset = (state.added as f64 * GOLDEN_RATIO_FRACTION).fract();
+        state.added += 1;
+        let next_due = Instant::now() + self.stagger.min(interval).mul_f64(offset);
+        state.albums.insert(
+            token.to_string(),
+            Slot {
+                interval,
+                next_due,
+                activity: 0.0,
+                polling: false,
+                failures: 0,
+                last_error: None,
+                persistent: false,
+            },
+        );
+        drop(state);
+        self.changed.notify_one();
+    }
+
+    /// Stops following an album; a poll already running still completes
+    pub fn remove(&self, token: &str) {
+        if self.state().albums.remove(token).is_some() {
+            self.changed.notify_one();
+        }
+    }
+
+    /// Changes the configured interval of an album
+    ///
+    /// A shorter interval brings the next poll forward; a longer one applies
+    /// from the next poll on.
+    pub fn set_interval(&self, token: &str, interval: Duration) {
+        let mut state = self.state();
+        let Some(slot) = state.albums.get_mut(token) else {
+            return;
+        };
+        slot.interval = interval;
+        let due = Instant::now() + self.effective_interval(token, interval, slot.activity);
+        if due < slot.next_due {
+            slot.next_due = due;
+        }
+        drop(state);
+        self.changed.notify_one();
+    }
+
+    /// Applies the changes reported by a [`crate::album_config::ConfigWatcher`]
+    ///
+    /// Changes that don't affect scheduling, such as output directories, are
+    /// left to the application.
+    pub fn apply(&self, changes: &[ConfigChange]) {
+        for change in changes {
+            match change {
+                ConfigChange::AlbumAdded { album } => self.add(&album.token, album.interval()),
+                ConfigChange::AlbumRemoved { token } => self.remove(token),
+                ConfigChange::IntervalChanged {
+                    token, new_secs, ..
+                } => self.set_interval(token, Duration::from_secs(*new_secs)),
+                ConfigChange::OutputDirChanged { .. } | ConfigChange::NameChanged { .. } => {}
+            }
+        }
+    }
+
+    /// Returns the schedule of every album, soonest due first
+    pub fn schedule(&self) -> Vec<ScheduledAlbum> {
+        let state = self.state();
+        let mut albums: Vec<ScheduledAlbum> = state
+            .albums
+            .iter()
+            .map(|(token, slot)| ScheduledAlbum {
+                token: token.clone(),
+                interval: slot.interval,
+                effective_interval: self.effective_interval(token, slot.interval, slot.activity),
+                next_due: slot.next_due,
+                activity: slot.activity,
+                polling: slot.polling,
+                health: slot.health(),
+            })
+            .collect();
+        albums.sort_by(|a, b| a.next_due.cmp(&b.next_due).then(a.token.cmp(&b.token)));
+        albums
+    }
+
+    /// Returns whether the polls of an album succeed, or `None` if it isn't followed
+    pub fn health(&self, token: &str) -> Option<PollHealth> {
+        self.state().albums.get(token).map(Slot::health)
+    }
+
+    /// Returns the health of the scheduler as a whole
+    ///
+    /// This is cheap enough to serve from a liveness endpoint on every request.
+    pub fn overall_health(&self) -> SchedulerHealth {
+        let now = Instant::now();
+        let state = self.state();
+        let slots = state.albums.values();
+        SchedulerHealth {
+            albums: state.albums.len(),
+            queue_depth: slots
+                .clone()
+                .filter(|slot| !slot.polling && slot.next_due <= now)
+                .count(),
+            polling: slots.clone().filter(|slot| slot.polling).count(),
+            last_success: state.last_success,
+            last_error: state.last_error.clone(),
+            failing: slots
+                .clone()
+                .filter(|slot| slot.last_error.is_some())
+                .count(),
+            persistent_errors: slots.filter(|slot| slot.persistent).count(),
+        }
+    }
+
+    /// When the next album not being polled is due, if any
+    pub fn next_due(&self) -> Option<Instant> {
+        self.state()
+            .albums
+            .values()
+            .filter(|slot| !slot.polling)
+            .map(|slot| slot.next_due)
+            .min()
+    }
+
+    /// Takes the most overdue album for polling, if any is due
+    ///
+    /// Overdue albums are ordered by how far past their due time they are,
+    /// ties going to the busier album. The album counts as being polled
+    /// until [`PollScheduler::complete`] or [`PollScheduler::fail`] is called
+    /// for it.
+    pub fn take_due(&self) -> Option<String> {
+        let now = Instant::now();
+        let mut state = self.state();
+        let (token, slot) = state
+            .albums
+            .iter_mut()
+            .filter(|(_, slot)| !slot.polling && slot.next_due <= now)
+            .min_by(|(a_token, a), (b_token, b)| {
+                a.next_due
+                    .cmp(&b.next_due)
+                    .then(b.activity.total_cmp(&a.activity))
+                    .then(a_token.cmp(b_token))
+            })?;
+        slot.polling = true;
+        Some(token.clone())
+    }
+
+    /// Records a successful poll and schedules the album's next one
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Token of the polled album
+    /// * `changed` - Whether the poll found changes
+    pub fn complete(&self, token: &str, changed: bool) {
+        let mut state = self.state();
+        let Some(slot) = state.albums.get_mut(token) else {
+            return;
+        };
+        let sample = if changed { 1.0 } else { 0.0 };
+        slot.activity = slot.activity * (1.0 - ACTIVITY_WEIGHT) + sample * ACTIVITY_WEIGHT;
+        if slot.failures > 0 {
+            logging::log_debug!(
+                logging::WATCH,
+                "Polling album succeeded again after {} failure(s)",
+                slot.failures
+            );
+        }
+        slot.failures = 0;
+        slot.last_error = None;
+        slot.persistent = false;
+        slot.polling = false;
+        slot.next_due =
+            Instant::now() + self.effective_interval(token, slot.interval, slot.activity);
+        state.last_success = Some(SystemTime::now());
+        drop(state);
+        self.changed.notify_one();
+    }
+
+    /// Records a failed poll and backs the album off
+    ///
+    /// The album is retried after its interval doubled for every consecutive
+    /// failure, up to the maximum back-off. Only the first failure and the
+    /// error becoming persistent are logged as warnings, so a broken album
+    /// doesn't fill the logs.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Token of the polled album
+    /// * `error` - Why the poll failed
+    pub fn fail(&self, token: &str, error: &(dyn Error + 'static)) {
+        let mut state = self.state();
+        let Some(slot) = state.albums.get_mut(token) else {
+            return;
+        };
+        slot.failures = slot.failures.saturating_add(1);
+        let message = Redacted(error).to_string();
+        slot.last_error = Some(message.clone());
+        let was_persistent = slot.persistent;
+        slot.persistent |= slot.failures >= self.persistent_after || is_token_rejected(error);
+
+        let interval = self.effective_interval(token, slot.interval, slot.activity);
+        let backoff = interval
+            .saturating_mul(1 << (slot.failures - 1).min(20))
+            .min(self.max_backoff.max(interval));
+        slot.polling = false;
+        slot.next_due = Instant::now() + backoff;
+
+        if slot.persistent && !was_persistent {
+            logging::log_warn!(
+                logging::WATCH,
+                "Polling album keeps failing ({} failure(s)), retrying in {:?}: {}",
+                slot.failures,
+                backoff,
+                Redacted(error)
+            );
+        } else if slot.failures == 1 {
+            logging::log_warn!(
+                logging::WATCH,
+                "Polling album failed, retrying in {:?}: {}",
+                backoff,
+                Redacted(error)
+            );
+        } else {
+            logging::log_debug!(
+                logging::WATCH,
+                "Polling album failed again ({} failure(s)), retrying in {:?}: {}",
+                slot.failures,
+                backoff,
+                Redacted(error)
+            );
+        }
+        state.last_error = Some(PollFailure {
+            token: token.to_string(),
+            error: message,
+            at: SystemTime::now(),
+        });
+        drop(state);
+        self.changed.notify_one();
+    }
+
+    /// Polls the albums until shut down
+    ///
+    /// `poll` is called with an album's token whenever it is due and a slot
+    /// of the shared limit is free, and returns whether the album changed.
+    /// Failures back the album off, see [`PollScheduler::fail`].
+    /// Once the shutdown signal is triggered no new polls start, and this
+    /// returns when the running ones have completed.
+    pub async fn run<F, Fut>(self: &Arc<Self>, poll: F)
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<bool, PollError>> + Send + 'static,
+    {
+        let shutdown = self.shutdown.clone();
+        let mut polls = JoinSet::new();
+        while !shutdown.is_triggered() {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            let wait_until = match self.next_due() {
+                Some(due) if due <= Instant::now() => {
+                    let Some(permit) = shutdown.run_until(self.limit.acquire()).await else {
+                        break;
+                    };
+                    // Pick the album only once a slot is free, so it goes to
+                    // the one that is most overdue by then
+                    if let Some(token) = self.take_due() {
+                        let scheduler = Arc::clone(self);
+                        let poll = poll(token.clone());
+                        polls.spawn(async move {
+                            let result = poll.await;
+                            drop(permit);
+                            match result {
+                                Ok(changed) => scheduler.complete(&token, changed),
+                                Err(e) => scheduler.fail(&token, e.as_ref()),
+                            }
+                        });
+                    }
+                    continue;
+                }
+                Some(due) => due,
+                None => Instant::now() + self.stagger.max(Duration::from_secs(1)),
+            };
+            while polls.try_join_next().is_some() {}
+            shutdown
+                .run_until(async {
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(wait_until) => {}
+                        _ = &mut changed => {}
+                    }
+                })
+                .await;
+        }
+        while polls.join_next().await.is_some() {}
+        logging::log_debug!(logging::WATCH, "Poll scheduler stopped");
+    }
+}
+
+/// Whether an error, or one of its sources, is iCloud rejecting the album's token
+fn is_token_rejected(error: &(dyn Error + 'static)) -> bool {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(ApiError::RequestError {
+            status: Some(status),
+            ..
+        }) = error.downcast_ref::<ApiError>().map(ApiError::root)
+        {
+            if REJECTED_STATUS_CODES.contains(status) {
+                return true;
+            }
+        }
+        current = error.source();
+    }
+    false
+}
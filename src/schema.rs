@@ -0,0 +1,257 @@
+//! JSON Schema documents describing the API responses this crate expects.
+//!
+//! The schemas mirror what [`crate::api::validate_api_schema`] checks and what
+//! the models in [`crate::models`] accept, including fields Apple sends either
+//! as strings or as numbers. External monitoring can use them to validate
+//! recorded responses, and users can compare them against what they observe.
+//!
+//! [`openapi_document`] describes the endpoints themselves, embedding these
+//! schemas, so clients of the album API or of the simulator can be generated.
+//!
+//! ```
+//! use icloud_album_rs::schema;
+//!
+//! let schema = schema::json_schema("webstream").unwrap();
+//! assert_eq!(schema["required"][0], "streamName");
+//! ```
+
+use serde_json::{json, Value};
+
+/// JSON Schema dialect used by the exported documents
+pub const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Names accepted by [`json_schema`], matching [`crate::api::validate_api_schema`]
+pub const SCHEMA_NAMES: &[&str] = &["webstream", "webasseturls"];
+
+/// A value sent either as a JSON number or as a string of digits
+fn numeric_or_string() -> Value {
+    json!({
+        "anyOf": [
+            { "type": "integer", "minimum": 0 },
+            { "type": "string", "pattern": "^[0-9]+$" }
+        ]
+    })
+}
+
+/// Schema of a single derivative inside a photo
+fn derivative_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["checksum"],
+        "properties": {
+            "checksum": { "type": "string" },
+            "fileSize": numeric_or_string(),
+            "width": numeric_or_string(),
+            "height": numeric_or_string(),
+            "type": { "type": ["string", "null"] },
+            "mediaAssetType": { "type": ["string", "null"] },
+            "state": { "type": ["string", "null"] }
+        }
+    })
+}
+
+/// Schema of a single photo in the `photos` array
+fn photo_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["photoGuid", "derivatives"],
+        "properties": {
+            "photoGuid": { "type": "string" },
+            "derivatives": {
+                "type": "object",
+                "additionalProperties": derivative_schema()
+            },
+            "caption": { "type": ["string", "null"] },
+            "dateCreated": { "type": ["string", "null"] },
+            "batchDateCreated": { "type": ["string", "null"] },
+            "contributorFullName": { "type": ["string", "null"] },
+            "width": numeric_or_string(),
+            "height": numeric_or_string()
+        }
+    })
+}
+
+/// Returns the JSON Schema of a webstream response
+///
+/// Sharing fields are optional and arrive under several names, see
+/// [`crate::models::SharingInfo`].
+pub fn webstream_schema() -> Value {
+    let owner_id = json!({ "type": ["string", "integer"] });
+    let flag = json!({ "type": ["boolean", "string", "integer"] });
+    json!({
+        "$schema": DIALECT,
+        "title": "webstream",
+        "description": "Album metadata and photos returned by the webstream endpoint",
+        "type": "object",
+        "required": ["streamName", "streamCtag", "photos"],
+        "properties": {
+            "streamName": { "type": "string" },
+            "streamCtag": { "type": "string" },
+            "userFirstName": { "type": "string" },
+            "userLastName": { "type": "string" },
+            "itemsReturned": numeric_or_string(),
+            "locations": {},
+            "photoGuids": { "type": "array", "items": { "type": "string" } },
+            "photos": { "type": "array", "items": photo_schema() },
+            "ownerDsid": owner_id.clone(),
+            "userDsid": owner_id.clone(),
+            "ownerId": owner_id,
+            "ownerFullName": { "type": "string" },
+            "userFullName": { "type": "string" },
+            "isPublic": flag.clone(),
+            "publicAccess": flag.clone(),
+            "allowContributions": flag.clone(),
+            "subscriberCanContribute": flag
+        }
+    })
+}
+
+/// Returns the JSON Schema of a webasseturls response
+pub fn webasseturls_schema() -> Value {
+    json!({
+        "$schema": DIALECT,
+        "title": "webasseturls",
+        "description": "Download locations returned by the webasseturls endpoint, keyed by checksum",
+        "type": "object",
+        "required": ["items"],
+        "properties": {
+            "items": {
+                "type": "object",
+                "additionalProperties": {
+                    "type": "object",
+                    "required": ["url_location", "url_path"],
+                    "properties": {
+                        "url_location": { "type": "string", "minLength": 1 },
+                        "url_path": { "type": "string", "minLength": 1 }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Returns the JSON Schema for one of the [`SCHEMA_NAMES`]
+///
+/// # Arguments
+///
+/// * `schema_name` - `"webstream"` or `"webasseturls"`
+///
+/// # Returns
+///
+/// The schema document, or `None` for an unknown name
+pub fn json_schema(schema_name: &str) -> Option<Value> {
+    match schema_name {
+        "webstream" => Some(webstream_schema()),
+        "webasseturls" => Some(webasseturls_schema()),
+        _ => None,
+    }
+}
+
+/// Returns an OpenAPI 3.1 description of the shared album API
+///
+/// The document covers the webstream and webasseturls endpoints, including
+/// the 330 redirect to another partition, with the response schemas of
+/// [`json_schema`] as components. The simulator serves it at `/openapi.json`.
+pub fn openapi_document() -> Value {
+    let token = json!({
+        "name": "token",
+        "in": "path",
+        "required": true,
+        "description": "The album token from the share URL",
+        "schema": { "type": "string" }
+    });
+    let response = |schema: &str, description: &str| {
+        json!({
+            "description": description,
+            "content": {
+                "application/json": {
+                    "schema": { "$ref": format!("#/components/schemas/{}", schema) }
+                }
+            }
+        })
+    };
+    let mut components = serde_json::Map::new();
+    for name in SCHEMA_NAMES {
+        let mut schema = json_schema(name).unwrap_or_default();
+        if let Some(schema) = schema.as_object_mut() {
+            schema.remove("$schema");
+        }
+        components.insert(name.to_string(), schema);
+    }
+    components.insert(
+        "redirect".to_string(),
+        json!({
+            "type": "object",
+            "required": ["X-Apple-MMe-Host"],
+            "properties": { "X-Apple-MMe-Host": { "type": "string" } }
+        }),
+    );
+
+    json!({
+        "openapi": "3.1.0",
+        "jsonSchemaDialect": DIALECT,
+        "info": {
+            "title": "iCloud shared album API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "The public endpoints of shared iCloud photo albums, as used by this crate"
+        },
+        "servers": [{
+            "url": "https://p{partition}-sharedstreams.icloud.com",
+            "variables": {
+                "partition": {
+                    "default": "01",
+                    "description": "Server partition derived from the token"
+                }
+            }
+        }],
+        "paths": {
+            "/{token}/sharedstreams/webstream": {
+                "post": {
+                    "operationId": "webstream",
+                    "summary": "Album metadata and photos",
+                    "parameters": [token.clone()],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "object",
+                                    "properties": { "streamCtag": { "type": ["string", "null"] } }
+                                }
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": response("webstream", "The album"),
+                        "330": response("redirect", "The album lives on another partition")
+                    }
+                }
+            },
+            "/{token}/sharedstreams/webasseturls": {
+                "post": {
+                    "operationId": "webasseturls",
+                    "summary": "Download locations of photos",
+                    "parameters": [token],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "object",
+                                    "required": ["photoGuids"],
+                                    "properties": {
+                                        "photoGuids": { "type": "array", "items": { "type": "string" } }
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": response("webasseturls", "Locations keyed by derivative checksum")
+                    }
+                }
+            }
+        },
+        "components": { "schemas": components }
+    })
+}
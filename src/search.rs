@@ -0,0 +1,177 @@
+//! Caption search across albums.
+//!
+//! Shared albums have no search, but captions often name what's in a photo.
+//! A [`CaptionQuery`] holds the words to look for and an optional date range;
+//! [`search_captions`] ranks the photos of fetched albums against it, and
+//! `MetadataIndex::search_captions` (with the `sqlite` feature) does the same
+//! over every album in the metadata index.
+//!
+//! Matching is case-insensitive and word based: every query word must match a
+//! caption word, exactly or as its prefix ("birth" finds "Birthday"). Exact
+//! matches score higher than prefix matches, and ties go to the newest photo.
+
+use crate::models::Image;
+use std::cmp::Reverse;
+
+/// Score of a query word matching a caption word exactly
+const EXACT_SCORE: u32 = 2;
+/// Score of a query word matching the start of a caption word
+const PREFIX_SCORE: u32 = 1;
+
+/// Words to find in captions and the dates to search
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CaptionQuery {
+    words: Vec<String>,
+    since: Option<i64>,
+    until: Option<i64>,
+    limit: Option<usize>,
+}
+
+impl CaptionQuery {
+    /// Create a query for the words of `query`
+    ///
+    /// Words are split on anything that isn't a letter or digit. A query
+    /// without words matches nothing.
+    pub fn new(query: &str) -> Self {
+        Self {
+            words: words(query),
+            ..Self::default()
+        }
+    }
+
+    /// Only match photos taken at or after a Unix timestamp
+    #[must_use]
+    pub fn with_since(mut self, since: i64) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Only match photos taken before a Unix timestamp
+    #[must_use]
+    pub fn with_until(mut self, until: i64) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    /// Return at most `limit` matches
+    #[must_use]
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// The lowercase words of the query
+    pub fn words(&self) -> &[String] {
+        &self.words
+    }
+
+    /// Start of the date range, if any
+    pub fn since(&self) -> Option<i64> {
+        self.since
+    }
+
+    /// End of the date range, if any
+    pub fn until(&self) -> Option<i64> {
+        self.until
+    }
+
+    /// Ranks a caption against the query
+    ///
+    /// # Returns
+    ///
+    /// The score of the caption, or `None` if a query word doesn't match it
+    pub fn score(&self, caption: &str) -> Option<u32> {
+        if self.words.is_empty() {
+            return None;
+        }
+        let caption_words = words(caption);
+        self.words.iter().try_fold(0, |total, word| {
+            let best = caption_words
+                .iter()
+                .filter_map(|candidate| {
+                    if candidate == word {
+                        Some(EXACT_SCORE)
+                    } else if candidate.starts_with(word.as_str()) {
+                        Some(PREFIX_SCORE)
+                    } else {
+                        None
+                    }
+                })
+                .max()?;
+            Some(total + best)
+        })
+    }
+
+    /// Whether a photo taken at `taken_at` falls in the date range
+    ///
+    /// Photos without a date only fall in an open range.
+    pub fn in_range(&self, taken_at: Option<i64>) -> bool {
+        match taken_at {
+            Some(taken_at) => {
+                self.since.map_or(true, |since| taken_at >= since)
+                    && self.until.map_or(true, |until| taken_at < until)
+            }
+            None => self.since.is_none() && self.until.is_none(),
+        }
+    }
+
+    /// Orders matches by score then date, newest first, and applies the limit
+    pub(crate) fn rank<T>(&self, hits: &mut Vec<CaptionHit<T>>) {
+        hits.sort_by_key(|hit| Reverse((hit.score, hit.taken_at)));
+        if let Some(limit) = self.limit {
+            hits.truncate(limit);
+        }
+    }
+}
+
+/// A photo whose caption matched a [`CaptionQuery`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptionHit<T> {
+    /// The matching photo
+    pub item: T,
+    /// How well the caption matched; higher is better
+    pub score: u32,
+    /// When the photo was taken, as a Unix timestamp
+    pub taken_at: Option<i64>,
+}
+
+/// Finds the photos whose captions match a query
+///
+/// # Arguments
+///
+/// * `photos` - Photos of one or more fetched albums
+/// * `query` - The words and dates to search
+///
+/// # Returns
+///
+/// The matching photos, best first
+pub fn search_captions<'a>(
+    photos: &'a [Image],
+    query: &CaptionQuery,
+) -> Vec<CaptionHit<&'a Image>> {
+    let mut hits: Vec<_> = photos
+        .iter()
+        .filter_map(|photo| {
+            let taken_at = photo.timestamp_taken();
+            if !query.in_range(taken_at) {
+                return None;
+            }
+            let score = query.score(photo.caption.as_deref()?)?;
+            Some(CaptionHit {
+                item: photo,
+                score,
+                taken_at,
+            })
+        })
+        .collect();
+    query.rank(&mut hits);
+    hits
+}
+
+/// Splits text into lowercase words of letters and digits
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
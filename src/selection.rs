@@ -0,0 +1,133 @@
+//! Named photo selections that persist between sessions.
+//!
+//! Interactive tools let users pick photos one by one; a [`Selection`] keeps
+//! those picks by GUID under a name, so curating can continue in a later
+//! session and a download can fetch just the selected photos with
+//! [`Selection::filter`]. Selections are kept in a [`StateStore`] under
+//! [`SELECTION_KEY_PREFIX`] followed by their name. As with the store
+//! itself, loading and saving them blocks.
+
+use crate::models::Image;
+use crate::state_store::{self, StateStore};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io;
+
+/// Prefix of the state store keys selections are kept under
+pub const SELECTION_KEY_PREFIX: &str = "selections/";
+
+/// A named list of photos picked by a user
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selection {
+    /// Name of the selection, e.g. `mylist`
+    pub name: String,
+    /// GUIDs of the selected photos, in the order they were picked
+    #[serde(default)]
+    pub photo_guids: Vec<String>,
+}
+
+impl Selection {
+    /// Create an empty selection
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            photo_guids: Vec::new(),
+        }
+    }
+
+    /// Loads the selection called `name`, or `None` if there is none
+    pub fn load(store: &(impl StateStore + ?Sized), name: &str) -> io::Result<Option<Self>> {
+        state_store::get_json(store, &selection_key(name))
+    }
+
+    /// Loads the selection called `name`, or an empty one if there is none
+    pub fn load_or_new(store: &(impl StateStore + ?Sized), name: &str) -> io::Result<Self> {
+        Ok(Self::load(store, name)?.unwrap_or_else(|| Self::new(name)))
+    }
+
+    /// Saves the selection under its name, replacing any previous version
+    pub fn save(&self, store: &(impl StateStore + ?Sized)) -> io::Result<()> {
+        state_store::put_json(store, &selection_key(&self.name), self)
+    }
+
+    /// Adds a photo, returning `false` if it was already selected
+    pub fn add(&mut self, photo_guid: &str) -> bool {
+        if self.contains(photo_guid) {
+            return false;
+        }
+        self.photo_guids.push(photo_guid.to_string());
+        true
+    }
+
+    /// Removes a photo, returning whether it was selected
+    pub fn remove(&mut self, photo_guid: &str) -> bool {
+        let before = self.photo_guids.len();
+        self.photo_guids.retain(|guid| guid != photo_guid);
+        self.photo_guids.len() != before
+    }
+
+    /// Selects a photo that isn't selected and deselects one that is
+    ///
+    /// # Returns
+    ///
+    /// Whether the photo is selected afterwards
+    pub fn toggle(&mut self, photo_guid: &str) -> bool {
+        !self.remove(photo_guid) && self.add(photo_guid)
+    }
+
+    /// Whether a photo is selected
+    pub fn contains(&self, photo_guid: &str) -> bool {
+        self.photo_guids.iter().any(|guid| guid == photo_guid)
+    }
+
+    /// Number of selected photos
+    pub fn len(&self) -> usize {
+        self.photo_guids.len()
+    }
+
+    /// Whether no photo is selected
+    pub fn is_empty(&self) -> bool {
+        self.photo_guids.is_empty()
+    }
+
+    /// Returns the selected photos of an album, e.g. to download only those
+    ///
+    /// # Returns
+    ///
+    /// Copies of the selected photos, in the album's order
+    pub fn filter(&self, photos: &[Image]) -> Vec<Image> {
+        let selected: HashSet<&str> = self.photo_guids.iter().map(String::as_str).collect();
+        photos
+            .iter()
+            .filter(|photo| selected.contains(photo.photo_guid.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the selected GUIDs that aren't in an album anymore
+    pub fn missing<'a>(&'a self, photos: &[Image]) -> Vec<&'a str> {
+        let present: HashSet<&str> = photos
+            .iter()
+            .map(|photo| photo.photo_guid.as_str())
+            .collect();
+        self.photo_guids
+            .iter()
+            .map(String::as_str)
+            .filter(|guid| !present.contains(guid))
+            .collect()
+    }
+}
+
+/// Returns the names of the selections kept in a store, in order
+pub fn list_selections(store: &(impl StateStore + ?Sized)) -> io::Result<Vec<String>> {
+    Ok(store
+        .list(SELECTION_KEY_PREFIX)?
+        .into_iter()
+        .filter_map(|key| key.strip_prefix(SELECTION_KEY_PREFIX).map(str::to_string))
+        .collect())
+}
+
+/// The state store key of the selection called `name`
+fn selection_key(name: &str) -> String {
+    format!("{}{}", SELECTION_KEY_PREFIX, name)
+}
@@ -0,0 +1,1787 @@
+//! A JSON-RPC API driving album fetches, syncs and downloads over HTTP.
+//!
+//! A [`Server`] lets other services, such as home automation or NAS apps,
+//! use the crate without linking Rust. It answers JSON-RPC 2.0 requests
+//! POSTed to `/rpc`:
+//!
+//! * `fetch` - `{"token"}`, optionally `"resolve_urls": false`; returns the
+//!   album as [`ICloudResponse`] JSON
+//! * `download` - `{"token", "dir"}`, optionally `"classes"` such as
+//!   `["original", "thumb"]`; downloads every photo into `dir`
+//! * `sync` - `{"token", "dir"}`; downloads the originals of the photos that
+//!   aren't in `dir` yet, keeping a [`SyncState`] in [`SYNC_STATE_FILE`]
+//!
+//! ```text
+//! $ curl -s localhost:8787/rpc -d '{"jsonrpc": "2.0", "id": 1, "method": "sync",
+//!     "params": {"token": "B0z5qAGN1JIFd3y", "dir": "family"}}'
+//! {"id":1,"jsonrpc":"2.0","result":{"downloaded":[...],"failures":[],"missing":0,"photos":42,"skipped":40}}
+//! ```
+//!
+//! `dir` is relative to the server's root directory and can't leave it.
+//! Requests are checked against an [`AccessPolicy`]; album tokens
+//! only open the album they were issued for. `GET /health` reports liveness,
+//! and the health of a [`PollScheduler`] when one is attached, for
+//! container orchestrators, and `GET /metrics` the counters of a
+//! [`PrometheusMetrics`] when one is attached. Neither needs credentials,
+//! and both name albums by their redacted tokens.
+//!
+//! Web UIs can offer "download all" through `GET /albums/{token}/zip`, which
+//! answers with a ZIP archive of the album streamed by
+//! [`crate::zip_stream::stream_zip`] as the photos download, so nothing is staged on the
+//! server. The comma-separated `classes` query parameter picks the size
+//! classes, originals by default, and `guids` limits the archive to some
+//! photos. Expired asset URLs are resolved again while the archive is written.
+//!
+//! `GET /albums/{token}/assets/{guid}/{class}` proxies the derivative of a
+//! photo in a size class such as `thumb` or `original`, so pages can link
+//! assets whose iCloud URLs expire. Responses carry the derivative's checksum
+//! as their ETag and an [`AssetCachePolicy`] `Cache-Control` header, and
+//! honor `Range` requests so browsers can seek in videos, see
+//! [`crate::asset_response`]. Fetched albums are reused for a minute, so a
+//! page of thumbnails doesn't fetch the album for every one.
+//!
+//! With the `resize` feature and a [`Resizer`] in
+//! [`ServerConfig::resizer`], `?w=&h=&fit=` on an asset URL asks for the
+//! photo resized to that box, see [`ResizeRequest`]. Resizing runs on
+//! tokio's blocking pool.
+//!
+//! With a [`PrefetchPredictor`] in [`ServerConfig::prefetch`], each asset
+//! request is recorded for the requesting IP, and the derivatives the
+//! predictor expects next are downloaded in the background and kept in memory
+//! for a minute, so a viewer scrolling through a gallery finds them there.
+//!
+//! A [`ServeLimiter`] in [`ServerConfig::limiter`] limits the server:
+//! each request other than `/health` and `/metrics` counts against the client
+//! IP's rate limit, and the bytes of assets and archives against the album's
+//! bandwidth quota. Limited
+//! requests are answered with `429 Too Many Requests` and `Retry-After`.
+//!
+//! With the `web-ui` feature, `GET /ui/` serves the page of
+//! [`crate::web_ui`] for the albums added with
+//! [`ServerConfig::with_ui_album`], along with the `albums.json` and
+//! `albums/{id}.json` documents it loads. An album's ID is its token, and its
+//! photos are shown through the asset route. The page needs no credentials,
+//! the documents do; album tokens only list their own album.
+//! These routes take the same credentials as the API, e.g. an `access_token`
+//! query parameter for links.
+//!
+//! `GET /openapi.json` describes these routes, see [`openapi_document`].
+//!
+//! The `icloud-album` binary runs a server with `icloud-album serve --port N`.
+//! This module is only compiled with the `server` feature.
+
+use crate::access::AccessPolicy;
+use crate::api::FailureClass;
+use crate::asset_response::{self, AssetCachePolicy, AssetRequest};
+use crate::base_url;
+use crate::derivatives::SizeClass;
+use crate::download::{self, DownloadOptions, MultiDownloadReport, Staged};
+use crate::fetch::FetchOptions;
+use crate::local_http::{read_request, write_head, write_response, Request, Response};
+use crate::logging;
+use crate::metrics::{self, PrometheusMetrics};
+use crate::models::{Derivative, ICloudResponse, Image, Metadata};
+use crate::prefetch::PrefetchPredictor;
+use crate::quota::{Limited, ServeLimiter};
+use crate::redact::{redact_token, Redacted};
+#[cfg(feature = "resize")]
+use crate::resize::{ResizeRequest, Resizer};
+use crate::scheduler::PollScheduler;
+use crate::sync::{SyncState, SyncedFile};
+use crate::throttle::HostLimits;
+use crate::utils;
+#[cfg(feature = "web-ui")]
+use crate::web_ui;
+use crate::zip_stream::{self, ZIP_CONTENT_TYPE};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::SeekFrom;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Port the `icloud-album` binary listens on by default
+pub const DEFAULT_PORT: u16 = 8787;
+
+/// Name of the file in a synced directory holding its [`SyncState`]
+pub const SYNC_STATE_FILE: &str = ".sync-state.json";
+
+/// JSON-RPC error code of a body that isn't JSON
+pub const PARSE_ERROR: i64 = -32700;
+/// JSON-RPC error code of a request missing its version or method
+pub const INVALID_REQUEST: i64 = -32600;
+/// JSON-RPC error code of an unknown method
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// JSON-RPC error code of missing or invalid parameters
+pub const INVALID_PARAMS: i64 = -32602;
+/// JSON-RPC error code of a fetch, sync or download that failed
+pub const OPERATION_FAILED: i64 = -32000;
+
+/// How long the album routes reuse a fetched album
+const ALBUM_TTL: Duration = Duration::from_secs(60);
+
+/// How many bytes of prefetched assets a server keeps in memory
+const PREFETCH_BUDGET: usize = 64 * 1024 * 1024;
+
+/// What a server serves and who may use it
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ServerConfig {
+    /// Directory the `dir` parameters of downloads and syncs are relative to
+    pub root: PathBuf,
+    /// Credentials requests must present
+    pub access: AccessPolicy,
+    /// How albums are fetched
+    pub fetch: FetchOptions,
+    /// How files are downloaded
+    pub download: DownloadOptions,
+    /// Scheduler whose health `/health` reports, if any
+    pub scheduler: Option<Arc<PollScheduler>>,
+    /// Counters `/metrics` renders, if any
+    pub metrics: Option<Arc<PrometheusMetrics>>,
+    /// How long browsers may cache proxied assets
+    pub asset_cache: AssetCachePolicy,
+    /// Rate limits and bandwidth quotas of the album routes, if any
+    pub limiter: Option<Arc<ServeLimiter>>,
+    /// Predictor of the assets to fetch ahead of requests, if any
+    pub prefetch: Option<Arc<PrefetchPredictor>>,
+    /// Resizer of proxied assets asked for with `?w=&h=`, or `None` to ignore those parameters
+    #[cfg(feature = "resize")]
+    pub resizer: Option<Arc<Resizer>>,
+    /// Albums the web UI lists, in order
+    #[cfg(feature = "web-ui")]
+    pub ui_albums: Vec<UiAlbum>,
+}
+
+/// An album listed by the web UI
+#[cfg(feature = "web-ui")]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct UiAlbum {
+    /// Token of the album, also its ID in the UI
+    pub token: String,
+    /// Name shown for the album
+    pub name: String,
+    /// Directory the album is synced to, relative to the server's root
+    ///
+    /// Its [`SYNC_STATE_FILE`] provides the album's sync status.
+    pub dir: PathBuf,
+}
+
+impl ServerConfig {
+    /// Create a configuration writing below `root`, open to every request
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            access: AccessPolicy::new(),
+            fetch: FetchOptions::new(),
+            download: DownloadOptions::new(),
+            scheduler: None,
+            metrics: None,
+            asset_cache: AssetCachePolicy::default(),
+            limiter: None,
+            prefetch: None,
+            #[cfg(feature = "resize")]
+            resizer: None,
+            #[cfg(feature = "web-ui")]
+            ui_albums: Vec::new(),
+        }
+    }
+
+    /// Only answer requests with the credentials of `access`
+    #[must_use]
+    pub fn with_access(mut self, access: AccessPolicy) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Fetch albums with `options`
+    #[must_use]
+    pub fn with_fetch_options(mut self, options: FetchOptions) -> Self {
+        self.fetch = options;
+        self
+    }
+
+    /// Download files with `options`
+    #[must_use]
+    pub fn with_download_options(mut self, options: DownloadOptions) -> Self {
+        self.download = options;
+        self
+    }
+
+    /// Report the health of `scheduler` at `/health`
+    #[must_use]
+    pub fn with_scheduler(mut self, scheduler: Arc<PollScheduler>) -> Self {
+        self.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Serve the counters of `metrics` at `/metrics`
+    ///
+    /// Only activity reported to `metrics` is counted: install it with
+    /// [`crate::metrics::install`] as well.
+    #[must_use]
+    pub fn with_metrics(mut self, metrics: Arc<PrometheusMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Let browsers cache proxied assets as `policy` says
+    #[must_use]
+    pub fn with_asset_cache_policy(mut self, policy: AssetCachePolicy) -> Self {
+        self.asset_cache = policy;
+        self
+    }
+
+    /// Limit the album routes with `limiter`
+    #[must_use]
+    pub fn with_limiter(mut self, limiter: Arc<ServeLimiter>) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Fetch the assets `predictor` expects next ahead of their requests
+    ///
+    /// The predictor's [`PrefetchPredictor::stats`] tell how often the
+    /// prefetched assets were asked for.
+    #[must_use]
+    pub fn with_prefetch(mut self, predictor: Arc<PrefetchPredictor>) -> Self {
+        self.prefetch = Some(predictor);
+        self
+    }
+
+    /// Resize proxied assets whose URL asks for a size with `resizer`
+    #[cfg(feature = "resize")]
+    #[must_use]
+    pub fn with_resizer(mut self, resizer: Resizer) -> Self {
+        self.resizer = Some(Arc::new(resizer));
+        self
+    }
+
+    /// List an album in the web UI
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Token or share URL of the album; kept as given if it is invalid
+    /// * `name` - Name shown for the album
+    /// * `dir` - Directory the album is synced to, relative to the server's root
+    #[cfg(feature = "web-ui")]
+    #[must_use]
+    pub fn with_ui_album(
+        mut self,
+        token: &str,
+        name: impl Into<String>,
+        dir: impl Into<PathBuf>,
+    ) -> Self {
+        let token = base_url::normalize_token(token)
+            .map_or_else(|_| token.to_string(), |normalized| normalized.token);
+        self.ui_albums.push(UiAlbum {
+            token,
+            name: name.into(),
+            dir: dir.into(),
+        });
+        self
+    }
+}
+
+/// State shared by the connections of a server
+#[derive(Debug)]
+struct Shared {
+    config: ServerConfig,
+    started: Instant,
+    /// Directories a sync is running in
+    syncing: Mutex<HashSet<PathBuf>>,
+    /// Albums fetched by the album routes, by token, with when they were fetched
+    albums: Mutex<HashMap<String, (Instant, Arc<ICloudResponse>)>>,
+    /// Assets fetched ahead of their requests
+    prefetched: Arc<Mutex<Prefetched>>,
+}
+
+/// Identifies a derivative: album token, photo GUID and derivative key
+type AssetKey = (String, String, String);
+
+/// Asset bodies fetched for a [`PrefetchPredictor`]
+#[derive(Debug, Default)]
+struct Prefetched {
+    /// Bodies by derivative, with when they were fetched
+    bodies: HashMap<AssetKey, (Instant, Arc<Vec<u8>>)>,
+    /// Derivatives being fetched
+    pending: HashSet<AssetKey>,
+    /// Total size of the bodies
+    bytes: usize,
+}
+
+impl Prefetched {
+    /// Keeps a fetched body, dropping expired and then the oldest bodies over [`PREFETCH_BUDGET`]
+    fn insert(&mut self, key: AssetKey, body: Vec<u8>) {
+        self.pending.remove(&key);
+        if body.len() > PREFETCH_BUDGET {
+            return;
+        }
+        self.bytes += body.len();
+        if let Some((_, replaced)) = self.bodies.insert(key, (Instant::now(), Arc::new(body))) {
+            self.bytes -= replaced.len();
+        }
+        let mut by_age: Vec<(Instant, AssetKey)> = self
+            .bodies
+            .iter()
+            .map(|(key, (fetched, _))| (*fetched, key.clone()))
+            .collect();
+        by_age.sort();
+        for (fetched, key) in by_age {
+            if fetched.elapsed() < ALBUM_TTL && self.bytes <= PREFETCH_BUDGET {
+                break;
+            }
+            if let Some((_, body)) = self.bodies.remove(&key) {
+                self.bytes -= body.len();
+            }
+        }
+    }
+
+    /// The body of a derivative fetched less than [`ALBUM_TTL`] ago, if any
+    fn get(&self, key: &AssetKey) -> Option<Arc<Vec<u8>>> {
+        self.bodies
+            .get(key)
+            .filter(|(fetched, _)| fetched.elapsed() < ALBUM_TTL)
+            .map(|(_, body)| Arc::clone(body))
+    }
+}
+
+/// A JSON-RPC API listening on a local address
+#[derive(Debug)]
+pub struct Server {
+    listener: TcpListener,
+    shared: Arc<Shared>,
+}
+
+impl Server {
+    /// Binds a server to `addr`, e.g. `127.0.0.1:0` for any free port
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address to listen on
+    /// * `config` - What the server serves and who may use it
+    ///
+    /// # Returns
+    ///
+    /// The bound server, which serves nothing until [`Server::run`]
+    pub async fn bind(addr: &str, config: ServerConfig) -> std::io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            shared: Arc::new(Shared {
+                config,
+                started: Instant::now(),
+                syncing: Mutex::new(HashSet::new()),
+                albums: Mutex::new(HashMap::new()),
+                prefetched: Arc::default(),
+            }),
+        })
+    }
+
+    /// Returns the address the server listens on
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Returns the URL of the server, e.g. `http://127.0.0.1:8787`
+    pub fn origin(&self) -> String {
+        match self.local_addr() {
+            Ok(addr) => format!("http://{}", addr),
+            Err(_) => "http://127.0.0.1".to_string(),
+        }
+    }
+
+    /// Serves requests until the future is dropped or accepting fails
+    ///
+    /// Connections are served concurrently on the calling task, as downloads
+    /// keep their reports on the task that started them. The future is thus
+    /// not `Send`: await it, or select on it, rather than spawning it.
+    ///
+    /// An origin installed with [`crate::base_url::with_api_origin`] around
+    /// this call applies to the album requests of every connection.
+    pub async fn run(self) -> std::io::Result<()> {
+        let origin = base_url::api_origin();
+        let connections = tokio::task::LocalSet::new();
+        connections
+            .run_until(async {
+                loop {
+                    let (stream, _) = self.listener.accept().await?;
+                    let shared = Arc::clone(&self.shared);
+                    let origin = origin.clone();
+                    tokio::task::spawn_local(async move {
+                        let serve = serve_connection(stream, &shared);
+                        let result = match &origin {
+                            Some(origin) => base_url::with_api_origin(origin, serve).await,
+                            None => serve.await,
+                        };
+                        if let Err(e) = result {
+                            logging::log_debug!(logging::API, "Server connection failed: {}", e);
+                        }
+                    });
+                }
+            })
+            .await
+    }
+}
+
+/// Answers one request on `stream`, then closes it
+async fn serve_connection(mut stream: TcpStream, shared: &Shared) -> std::io::Result<()> {
+    let request = read_request(&mut stream).await?;
+    let client = stream.peer_addr()?.ip();
+    match route(&request, client, shared).await {
+        Reply::Full(response) => write_response(&mut stream, &response).await,
+        Reply::Zip(zip) => write_zip(&mut stream, *zip).await,
+        Reply::Asset(asset) => write_asset(&mut stream, *asset).await,
+    }
+}
+
+/// What a request is answered with
+enum Reply {
+    /// A response held in memory
+    Full(Response),
+    /// An album archive, streamed as it is written
+    Zip(Box<ZipReply>),
+    /// Bytes of a downloaded asset
+    Asset(Box<AssetReply>),
+}
+
+/// Routes a request from `client` to its handler
+async fn route(request: &Request, client: IpAddr, shared: &Shared) -> Reply {
+    // Probes and scrapes are never limited, so a busy server still looks alive
+    if !matches!(request.path.as_str(), "/health" | "/metrics") {
+        if let Some(limiter) = &shared.config.limiter {
+            if let Err(limited) = limiter.check_request(client) {
+                return Reply::Full(too_many_requests(limited));
+            }
+        }
+    }
+    let response = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/health") => health(shared),
+        ("GET", "/metrics") => match &shared.config.metrics {
+            Some(metrics) => Response::new(
+                200,
+                "text/plain; version=0.0.4",
+                metrics.render().into_bytes(),
+            ),
+            None => Response::text(404, "not found"),
+        },
+        ("GET", "/openapi.json") => Response::json(200, &openapi_document()),
+        ("POST", "/rpc") => rpc(request, shared).await,
+        (_, "/health" | "/rpc" | "/openapi.json") => Response::text(405, "method not allowed"),
+        #[cfg(feature = "web-ui")]
+        ("GET", "/ui") => Response::text(308, "moved").with_header("location", "/ui/"),
+        #[cfg(feature = "web-ui")]
+        ("GET", path) if path.starts_with("/ui/") => ui_route(request, &path[3..], shared).await,
+        (method, path) => match AlbumRoute::parse(path) {
+            Some((token, route)) if method == "GET" => {
+                return album_route(request, client, token, route, shared).await
+            }
+            Some(_) => Response::text(405, "method not allowed"),
+            None => Response::text(404, "not found"),
+        },
+    };
+    Reply::Full(response)
+}
+
+/// The routes below `/albums/{token}/`
+#[derive(Debug, Clone, Copy)]
+enum AlbumRoute<'a> {
+    /// `zip`: an archive of the album
+    Zip,
+    /// `assets/{guid}/{class}`: a derivative of a photo
+    Asset { guid: &'a str, class: &'a str },
+}
+
+impl<'a> AlbumRoute<'a> {
+    /// Splits a path into the album token and the route, if it is one
+    fn parse(path: &'a str) -> Option<(&'a str, Self)> {
+        let (token, rest) = path.strip_prefix("/albums/")?.split_once('/')?;
+        let route = match rest.split('/').collect::<Vec<_>>().as_slice() {
+            ["zip"] => AlbumRoute::Zip,
+            ["assets", guid, class] => AlbumRoute::Asset { guid, class },
+            _ => return None,
+        };
+        Some((token, route))
+    }
+}
+
+/// Answers a request from `client` for a resource of an album
+async fn album_route(
+    request: &Request,
+    client: IpAddr,
+    token: &str,
+    route: AlbumRoute<'_>,
+    shared: &Shared,
+) -> Reply {
+    let token = match base_url::normalize_token(token) {
+        Ok(normalized) => normalized.token,
+        Err(e) => return Reply::Full(Response::text(400, &e.to_string())),
+    };
+    if let Some(denied) = denial(request, Some(&token), shared) {
+        return Reply::Full(denied);
+    }
+    match route {
+        AlbumRoute::Zip => zip_reply(request, &token, shared).await,
+        AlbumRoute::Asset { guid, class } => {
+            if let Some(predictor) = &shared.config.prefetch {
+                prefetch_next(predictor, client, &token, guid, shared).await;
+            }
+            asset_reply(request, &token, guid, class, shared).await
+        }
+    }
+}
+
+/// Records a request for a photo and starts fetching the photos predicted next
+///
+/// The downloads run on the connection's [`tokio::task::LocalSet`] and keep
+/// their bodies in [`Shared::prefetched`]; failures are only logged, as the
+/// request for the asset downloads it again.
+async fn prefetch_next(
+    predictor: &PrefetchPredictor,
+    client: IpAddr,
+    token: &str,
+    guid: &str,
+    shared: &Shared,
+) {
+    let Ok(album) = fetch_for_route(token, shared).await else {
+        return;
+    };
+    let Some(index) = album
+        .photos
+        .iter()
+        .position(|photo| photo.photo_guid == guid)
+    else {
+        return;
+    };
+    let predictions = predictor.record(&client.to_string(), index, album.photos.len());
+    let size_class = predictor.config().size_class;
+    for photo in predictions
+        .iter()
+        .filter_map(|&index| album.photos.get(index))
+    {
+        let Some((key, _)) = utils::select_derivative_of_class(&photo.derivatives, size_class)
+        else {
+            continue;
+        };
+        let asset = (token.to_string(), photo.photo_guid.clone(), key.clone());
+        {
+            let mut prefetched = shared.prefetched.lock().unwrap_or_else(|e| e.into_inner());
+            if prefetched.get(&asset).is_some() || !prefetched.pending.insert(asset.clone()) {
+                continue;
+            }
+        }
+        let photo = photo.clone();
+        let options = route_download_options(token, shared);
+        let prefetched = Arc::clone(&shared.prefetched);
+        let origin = base_url::api_origin();
+        tokio::task::spawn_local(async move {
+            let fetched = async {
+                let client = options.build_client()?;
+                let hosts =
+                    HostLimits::new(options.concurrency.clone(), options.circuit_breaker.clone());
+                let staged =
+                    download::fetch_derivative(&client, &hosts, &photo, &key, &options).await?;
+                let body = tokio::fs::read(staged.path()).await;
+                staged.discard().await;
+                Ok::<_, Box<dyn Error>>(body?)
+            };
+            let fetched = match &origin {
+                Some(origin) => base_url::with_api_origin(origin, fetched).await,
+                None => fetched.await,
+            };
+            match fetched {
+                Ok(body) => prefetched
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(asset, body),
+                Err(e) => {
+                    logging::log_debug!(
+                        logging::API,
+                        "Prefetching {} failed: {}",
+                        photo.photo_guid,
+                        Redacted(e.as_ref())
+                    );
+                    prefetched
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .pending
+                        .remove(&asset);
+                }
+            }
+        });
+    }
+}
+
+/// Answers `GET /ui/{path}`: the page of the web UI and the documents it loads
+#[cfg(feature = "web-ui")]
+async fn ui_route(request: &Request, path: &str, shared: &Shared) -> Response {
+    if let Some((content_type, content)) = web_ui::asset(path) {
+        return Response::new(200, content_type, content.as_bytes().to_vec());
+    }
+    if path == "/albums.json" {
+        if let Some(denied) = denial(request, None, shared) {
+            return denied;
+        }
+        let access = shared.config.access.authorize(
+            request.header("authorization"),
+            request.query.as_deref(),
+            None,
+        );
+        let mut albums = Vec::new();
+        for album in shared
+            .config
+            .ui_albums
+            .iter()
+            .filter(|album| access.allows(&album.token))
+        {
+            let state_path = shared.config.root.join(&album.dir).join(SYNC_STATE_FILE);
+            let state = SyncState::load(&state_path).await.unwrap_or_else(|e| {
+                logging::log_debug!(
+                    logging::API,
+                    "Could not load {}: {}",
+                    state_path.display(),
+                    e
+                );
+                SyncState::new()
+            });
+            albums.push(web_ui::AlbumStatus::from_state(
+                &album.token,
+                &album.name,
+                &state,
+            ));
+        }
+        return Response::json(200, &json!(albums));
+    }
+
+    let Some(id) = path
+        .strip_prefix("/albums/")
+        .and_then(|name| name.strip_suffix(".json"))
+    else {
+        return Response::text(404, "not found");
+    };
+    let Some(album) = shared
+        .config
+        .ui_albums
+        .iter()
+        .find(|album| album.token == id)
+    else {
+        return Response::text(404, "no such album");
+    };
+    if let Some(denied) = denial(request, Some(&album.token), shared) {
+        return denied;
+    }
+    match fetch_for_route(&album.token, shared).await {
+        Ok(fetched) => {
+            let url_template = format!("/albums/{}/assets/{{guid}}/{{class}}", album.token);
+            let grid = web_ui::album_grid(&album.token, &fetched, &url_template);
+            Response::json(200, &json!(grid))
+        }
+        Err(response) => response,
+    }
+}
+
+/// The response refusing a request that may not access `album`, if any
+fn denial(request: &Request, album: Option<&str>, shared: &Shared) -> Option<Response> {
+    let access = shared.config.access.authorize(
+        request.header("authorization"),
+        request.query.as_deref(),
+        album,
+    );
+    match access.denial_status()? {
+        401 => Some(
+            Response::text(401, "unauthorized")
+                .with_header("www-authenticate", shared.config.access.challenge()),
+        ),
+        status => Some(Response::text(status, "forbidden")),
+    }
+}
+
+/// Counts `bytes` served from an album against its quota, or the response to answer with
+///
+/// Without `bytes`, only checks whether the album's quota is used up.
+fn reserve_bandwidth(token: &str, bytes: Option<u64>, shared: &Shared) -> Result<(), Response> {
+    let Some(limiter) = &shared.config.limiter else {
+        return Ok(());
+    };
+    match bytes {
+        Some(bytes) => limiter.reserve(token, bytes),
+        None => limiter.check_quota(token),
+    }
+    .map_err(too_many_requests)
+}
+
+/// The response to a request denied by the limiter
+fn too_many_requests(limited: Limited) -> Response {
+    Response::text(limited.status(), "too many requests")
+        .with_header("retry-after", limited.retry_after_header())
+}
+
+/// Fetches an album for a route, or the error response to answer with
+///
+/// Albums fetched less than [`ALBUM_TTL`] ago are reused.
+async fn fetch_for_route(token: &str, shared: &Shared) -> Result<Arc<ICloudResponse>, Response> {
+    let cached = shared
+        .albums
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(token)
+        .filter(|(fetched, _)| fetched.elapsed() < ALBUM_TTL)
+        .map(|(_, album)| Arc::clone(album));
+    if let Some(album) = cached {
+        return Ok(album);
+    }
+
+    let album = crate::get_icloud_photos_with(token, &shared.config.fetch)
+        .await
+        .map_err(|e| {
+            let status = match FailureClass::of(e.as_ref()) {
+                FailureClass::Token => 404,
+                _ => 502,
+            };
+            Response::text(status, &Redacted(e.as_ref()).to_string())
+        })?;
+    let album = Arc::new(album);
+    let mut albums = shared.albums.lock().unwrap_or_else(|e| e.into_inner());
+    albums.retain(|_, (fetched, _)| fetched.elapsed() < ALBUM_TTL);
+    albums.insert(token.to_string(), (Instant::now(), Arc::clone(&album)));
+    Ok(album)
+}
+
+/// The download options of the album routes
+///
+/// Unless the server's options say otherwise, expired asset URLs are
+/// resolved again through the album's base URL.
+fn route_download_options(token: &str, shared: &Shared) -> DownloadOptions {
+    let options = shared.config.download.clone();
+    match (&options.url_refresh, base_url::get_base_url(token)) {
+        (None, Ok(base_url)) => options.with_url_refresh(base_url),
+        _ => options,
+    }
+}
+
+/// An album archive to stream, see [`write_zip`]
+struct ZipReply {
+    head: Response,
+    photos: Vec<Image>,
+    classes: Vec<SizeClass>,
+    options: DownloadOptions,
+    /// Token of the album, whose quota the archive counts against
+    token: String,
+    limiter: Option<Arc<ServeLimiter>>,
+}
+
+/// Prepares the archive answering `GET /albums/{token}/zip`
+async fn zip_reply(request: &Request, token: &str, shared: &Shared) -> Reply {
+    let classes = match request.query_param("classes") {
+        Some(names) => match parse_size_classes(&names) {
+            Ok(classes) => classes,
+            Err(message) => return Reply::Full(Response::text(400, &message)),
+        },
+        None => vec![SizeClass::Original],
+    };
+    if let Err(response) = reserve_bandwidth(token, None, shared) {
+        return Reply::Full(response);
+    }
+    let album = match fetch_for_route(token, shared).await {
+        Ok(album) => album,
+        Err(response) => return Reply::Full(response),
+    };
+    let mut photos = album.photos.clone();
+    if let Some(guids) = request.query_param("guids") {
+        let wanted: HashSet<&str> = guids.split(',').map(str::trim).collect();
+        photos.retain(|photo| wanted.contains(photo.photo_guid.as_str()));
+    }
+
+    let options = route_download_options(token, shared);
+    let name: String = utils::sanitize_filename(&album.metadata.stream_name)
+        .chars()
+        .map(|c| if c.is_ascii() && c != '"' { c } else { '_' })
+        .collect();
+    let name = if name.is_empty() {
+        "album".to_string()
+    } else {
+        name
+    };
+    let head = Response::new(200, ZIP_CONTENT_TYPE, Vec::new()).with_header(
+        "content-disposition",
+        format!("attachment; filename=\"{}.zip\"", name),
+    );
+    Reply::Zip(Box::new(ZipReply {
+        head,
+        photos,
+        classes,
+        options,
+        token: token.to_string(),
+        limiter: shared.config.limiter.clone(),
+    }))
+}
+
+/// A proxied asset to send, see [`write_asset`]
+struct AssetReply {
+    head: Response,
+    staged: Staged,
+    body: Range<u64>,
+}
+
+/// Proxies `GET /albums/{token}/assets/{guid}/{class}`
+///
+/// A request whose `If-None-Match` names the derivative's checksum is
+/// answered without downloading the asset. Resized renditions have ETags of
+/// their own, see [`ResizeRequest::rendition_key`].
+async fn asset_reply(
+    request: &Request,
+    token: &str,
+    guid: &str,
+    class: &str,
+    shared: &Shared,
+) -> Reply {
+    let Some(size_class) = SizeClass::from_file_suffix(class) else {
+        let message = format!("Unknown size class {:?}", class);
+        return Reply::Full(Response::text(400, &message));
+    };
+    let album = match fetch_for_route(token, shared).await {
+        Ok(album) => album,
+        Err(response) => return Reply::Full(response),
+    };
+    let Some(photo) = album.photos.iter().find(|photo| photo.photo_guid == guid) else {
+        return Reply::Full(Response::text(404, "no such photo"));
+    };
+    let Some((key, derivative)) = utils::select_derivative_of_class(&photo.derivatives, size_class)
+    else {
+        return Reply::Full(Response::text(404, "no derivative of this class"));
+    };
+    #[cfg(feature = "resize")]
+    let resize = match resize_request(request, shared) {
+        Ok(resize) => resize,
+        Err(response) => return Reply::Full(response),
+    };
+    #[cfg(feature = "resize")]
+    let rendition = resize.as_ref().map(|(_, resize)| Derivative {
+        checksum: resize.rendition_key(&derivative.checksum),
+        ..derivative.clone()
+    });
+    #[cfg(feature = "resize")]
+    let etag_derivative = rendition.as_ref().unwrap_or(derivative);
+    #[cfg(not(feature = "resize"))]
+    let etag_derivative = derivative;
+    let asset_request = AssetRequest::from_headers(
+        ["range", "if-none-match", "if-range"]
+            .into_iter()
+            .filter_map(|name| Some((name, request.header(name)?))),
+    );
+    let policy = &shared.config.asset_cache;
+    let cached = asset_response::respond(&asset_request, etag_derivative, 0, None, policy);
+    if cached.status == 304 {
+        return Reply::Full(asset_head(cached, None));
+    }
+    if let Err(response) = reserve_bandwidth(token, None, shared) {
+        return Reply::Full(response);
+    }
+
+    #[cfg(feature = "resize")]
+    let resizing = resize.is_some();
+    #[cfg(not(feature = "resize"))]
+    let resizing = false;
+    let asset = (token.to_string(), guid.to_string(), key.clone());
+    let prefetched = shared
+        .prefetched
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&asset);
+    if let Some(body) = prefetched.filter(|_| !resizing) {
+        let content_type = utils::detect_mime_type(&body, None);
+        let response = buffered_asset(
+            &asset_request,
+            derivative,
+            &body,
+            content_type,
+            token,
+            shared,
+        );
+        return Reply::Full(response);
+    }
+
+    let options = route_download_options(token, shared);
+    let fetched = async {
+        let client = options.build_client()?;
+        let hosts = HostLimits::new(options.concurrency.clone(), options.circuit_breaker.clone());
+        download::fetch_derivative(&client, &hosts, photo, &key, &options).await
+    };
+    let staged = match fetched.await {
+        Ok(staged) => staged,
+        Err(e) => return Reply::Full(Response::text(502, &Redacted(e.as_ref()).to_string())),
+    };
+    #[cfg(feature = "resize")]
+    if let Some((resizer, resize)) = resize {
+        let resized = resize_staged(resizer, derivative.clone(), staged, resize).await;
+        return Reply::Full(match resized {
+            Ok((bytes, content_type)) => buffered_asset(
+                &asset_request,
+                etag_derivative,
+                &bytes,
+                content_type,
+                token,
+                shared,
+            ),
+            Err(e) => Response::text(502, &format!("could not resize the asset: {}", e)),
+        });
+    }
+    let content_type = utils::detect_mime_type(staged.head(), None);
+    let response = asset_response::respond(
+        &asset_request,
+        derivative,
+        staged.len(),
+        Some(&content_type),
+        policy,
+    );
+    let body = response.body.clone();
+    if let Err(response) = reserve_bandwidth(token, Some(body.end - body.start), shared) {
+        staged.discard().await;
+        return Reply::Full(response);
+    }
+    Reply::Asset(Box::new(AssetReply {
+        head: asset_head(response, Some(content_type)),
+        staged,
+        body,
+    }))
+}
+
+/// Answers an asset request from a body held in memory
+///
+/// The bytes sent count against the album's quota.
+fn buffered_asset(
+    asset_request: &AssetRequest,
+    derivative: &Derivative,
+    bytes: &[u8],
+    content_type: String,
+    token: &str,
+    shared: &Shared,
+) -> Response {
+    let response = asset_response::respond(
+        asset_request,
+        derivative,
+        bytes.len() as u64,
+        Some(&content_type),
+        &shared.config.asset_cache,
+    );
+    if let Err(response) =
+        reserve_bandwidth(token, Some(response.body.end - response.body.start), shared)
+    {
+        return response;
+    }
+    let body = bytes[response.body.start as usize..response.body.end as usize].to_vec();
+    let mut full = asset_head(response, Some(content_type));
+    full.body = body;
+    full
+}
+
+/// The resizer and size a proxied asset is asked for in, if any
+///
+/// Size parameters are ignored without a configured resizer.
+#[cfg(feature = "resize")]
+fn resize_request(
+    request: &Request,
+    shared: &Shared,
+) -> Result<Option<(Arc<Resizer>, ResizeRequest)>, Response> {
+    let Some(resizer) = &shared.config.resizer else {
+        return Ok(None);
+    };
+    let resize = ResizeRequest::from_query(request.query.as_deref().unwrap_or(""))
+        .map_err(|message| Response::text(400, &message))?;
+    Ok(resize.map(|resize| (Arc::clone(resizer), resize)))
+}
+
+/// Resizes a downloaded asset on the blocking pool, then deletes the download
+///
+/// # Returns
+///
+/// The encoded image and its content type
+#[cfg(feature = "resize")]
+async fn resize_staged(
+    resizer: Arc<Resizer>,
+    derivative: Derivative,
+    staged: Staged,
+    resize: ResizeRequest,
+) -> Result<(Vec<u8>, String), Box<dyn Error + Send + Sync>> {
+    let content = tokio::fs::read(staged.path()).await;
+    staged.discard().await;
+    let content = content?;
+    let resized =
+        tokio::task::spawn_blocking(move || resizer.resize_asset(&derivative, &content, &resize))
+            .await??;
+    Ok((resized.bytes, resized.content_type))
+}
+
+/// The head of a response built by [`asset_response::respond`]
+///
+/// The content type and length are left to the response's own fields.
+fn asset_head(response: asset_response::AssetResponse, content_type: Option<String>) -> Response {
+    let content_type = content_type.unwrap_or_else(|| "application/octet-stream".to_string());
+    response
+        .headers
+        .into_iter()
+        .filter(|(name, _)| {
+            !name.eq_ignore_ascii_case("content-type")
+                && !name.eq_ignore_ascii_case("content-length")
+        })
+        .fold(
+            Response::new(response.status, content_type, Vec::new()),
+            |head, (name, value)| head.with_header(name, value),
+        )
+}
+
+/// Sends the bytes of a proxied asset, then closes the connection
+async fn write_asset(stream: &mut TcpStream, asset: AssetReply) -> std::io::Result<()> {
+    let AssetReply { head, staged, body } = asset;
+    let result = async {
+        write_head(stream, &head, Some(body.end - body.start)).await?;
+        if !body.is_empty() {
+            let mut file = tokio::fs::File::open(staged.path()).await?;
+            file.seek(SeekFrom::Start(body.start)).await?;
+            tokio::io::copy(&mut file.take(body.end - body.start), stream).await?;
+        }
+        stream.shutdown().await
+    }
+    .await;
+    staged.discard().await;
+    result
+}
+
+/// Parses a comma-separated list of size classes, e.g. `original,thumb`
+fn parse_size_classes(names: &str) -> Result<Vec<SizeClass>, String> {
+    let classes = names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            SizeClass::from_file_suffix(name)
+                .ok_or_else(|| format!("Unknown size class {:?}", name))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if classes.is_empty() {
+        return Err("classes must list at least one size class".to_string());
+    }
+    Ok(classes)
+}
+
+/// Streams an album archive, then closes the connection
+///
+/// Once the head is sent, a failure can only cut the archive short; failed
+/// downloads are left out of it, see [`zip_stream::stream_zip`].
+async fn write_zip(stream: &mut TcpStream, zip: ZipReply) -> std::io::Result<()> {
+    write_head(stream, &zip.head, None).await?;
+    let mut body = tokio::io::BufWriter::new(&mut *stream);
+    match zip_stream::stream_zip(&zip.photos, &zip.classes, &mut body, &zip.options).await {
+        Ok(report) => {
+            if let Some(limiter) = &zip.limiter {
+                limiter.record(&zip.token, report.bytes);
+            }
+            logging::log_debug!(
+                logging::API,
+                "Streamed an archive of {} files ({} bytes), {} failed",
+                report.entries.len(),
+                report.bytes,
+                report.failures.len()
+            )
+        }
+        Err(e) => logging::log_debug!(
+            logging::API,
+            "Streaming an archive failed: {}",
+            Redacted(e.as_ref())
+        ),
+    }
+    body.flush().await?;
+    stream.shutdown().await
+}
+
+/// Seconds since the Unix epoch of `time`
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Answers `/health`, with 503 while an attached scheduler has persistent errors
+fn health(shared: &Shared) -> Response {
+    let mut body = json!({
+        "status": "ok",
+        "uptime_secs": shared.started.elapsed().as_secs(),
+    });
+    let mut status = 200;
+    if let Some(scheduler) = &shared.config.scheduler {
+        let health = scheduler.overall_health();
+        if !health.is_healthy() {
+            status = 503;
+            body["status"] = json!("degraded");
+        }
+        body["scheduler"] = json!({
+            "albums": health.albums,
+            "queue_depth": health.queue_depth,
+            "polling": health.polling,
+            "failing": health.failing,
+            "persistent_errors": health.persistent_errors,
+            "last_success": health.last_success.map(unix_secs),
+            "last_error": health.last_error.map(|failure| json!({
+                "album": redact_token(&failure.token),
+                "error": failure.error,
+                "at": unix_secs(failure.at),
+            })),
+        });
+    }
+    Response::json(status, &body)
+}
+
+/// A JSON-RPC error
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self {
+            code: INVALID_PARAMS,
+            message: message.into(),
+        }
+    }
+
+    fn failed(error: &(dyn Error + 'static)) -> Self {
+        Self {
+            code: OPERATION_FAILED,
+            message: Redacted(error).to_string(),
+        }
+    }
+}
+
+/// Builds the response carrying a JSON-RPC error
+fn rpc_error(id: Value, error: RpcError) -> Response {
+    Response::json(
+        200,
+        &json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": error.code, "message": error.message },
+        }),
+    )
+}
+
+/// The methods of the API
+#[derive(Clone, Copy)]
+enum Method {
+    Fetch,
+    Download,
+    Sync,
+}
+
+/// Answers a JSON-RPC request POSTed to `/rpc`
+async fn rpc(request: &Request, shared: &Shared) -> Response {
+    let call: Value = match serde_json::from_slice(&request.body) {
+        Ok(call) => call,
+        Err(e) => {
+            return rpc_error(
+                Value::Null,
+                RpcError {
+                    code: PARSE_ERROR,
+                    message: e.to_string(),
+                },
+            )
+        }
+    };
+    let id = call.get("id").cloned().unwrap_or(Value::Null);
+    let method = match (
+        call.get("jsonrpc").and_then(Value::as_str),
+        call.get("method").and_then(Value::as_str),
+    ) {
+        (Some("2.0"), Some("fetch")) => Method::Fetch,
+        (Some("2.0"), Some("download")) => Method::Download,
+        (Some("2.0"), Some("sync")) => Method::Sync,
+        (Some("2.0"), Some(other)) => {
+            return rpc_error(
+                id,
+                RpcError {
+                    code: METHOD_NOT_FOUND,
+                    message: format!("Unknown method {:?}", other),
+                },
+            )
+        }
+        _ => {
+            return rpc_error(
+                id,
+                RpcError {
+                    code: INVALID_REQUEST,
+                    message: "Expected a JSON-RPC 2.0 request with a method".to_string(),
+                },
+            )
+        }
+    };
+    let params = call.get("params").cloned().unwrap_or_else(|| json!({}));
+    let token = match params.get("token").and_then(Value::as_str) {
+        Some(token) => match base_url::normalize_token(token) {
+            Ok(normalized) => normalized.token,
+            Err(e) => return rpc_error(id, RpcError::invalid_params(e.to_string())),
+        },
+        None => return rpc_error(id, RpcError::invalid_params("Missing token")),
+    };
+
+    if let Some(denied) = denial(request, Some(&token), shared) {
+        return denied;
+    }
+
+    let result = match method {
+        Method::Fetch => fetch(&token, &params, shared).await,
+        Method::Download => download(&token, &params, shared).await,
+        Method::Sync => sync(&token, &params, shared).await,
+    };
+    match result {
+        Ok(result) => Response::json(
+            200,
+            &json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        ),
+        Err(error) => rpc_error(id, error),
+    }
+}
+
+/// Fetches an album with the server's fetch options
+async fn fetch_album(token: &str, options: &FetchOptions) -> Result<ICloudResponse, RpcError> {
+    crate::get_icloud_photos_with(token, options)
+        .await
+        .map_err(|e| RpcError::failed(e.as_ref()))
+}
+
+/// Runs the `fetch` method
+async fn fetch(token: &str, params: &Value, shared: &Shared) -> Result<Value, RpcError> {
+    let resolve_urls = match params.get("resolve_urls") {
+        None => true,
+        Some(value) => value
+            .as_bool()
+            .ok_or_else(|| RpcError::invalid_params("resolve_urls must be a boolean"))?,
+    };
+    let options = shared.config.fetch.clone().with_resolve_urls(resolve_urls);
+    let album = fetch_album(token, &options).await?;
+    serde_json::to_value(album).map_err(|e| RpcError::failed(&e))
+}
+
+/// Runs the `download` method
+async fn download(token: &str, params: &Value, shared: &Shared) -> Result<Value, RpcError> {
+    let dir = output_dir(params, &shared.config.root)?;
+    let classes = size_classes(params)?;
+    let album = fetch_album(token, &shared.config.fetch).await?;
+    let report = download_photos(&album.photos, &dir, &classes, shared).await?;
+    let mut result = report_json(&report);
+    result["photos"] = json!(album.photos.len());
+    Ok(result)
+}
+
+/// Runs the `sync` method
+async fn sync(token: &str, params: &Value, shared: &Shared) -> Result<Value, RpcError> {
+    let dir = output_dir(params, &shared.config.root)?;
+    let _running = SyncGuard::acquire(&shared.syncing, &dir)?;
+    let run = sync_album(token, &dir, &shared.config.fetch, &shared.config.download)
+        .await
+        .map_err(|e| RpcError::failed(e.as_ref()))?;
+    let mut result = report_json(&run.report);
+    result["photos"] = json!(run.photos);
+    result["skipped"] = json!(run.skipped);
+    Ok(result)
+}
+
+/// Outcome of [`sync_album`]
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct SyncRun {
+    /// Metadata of the album
+    pub metadata: Metadata,
+    /// Number of photos in the album
+    pub photos: usize,
+    /// Photos already in the directory, which were not downloaded again
+    pub skipped: usize,
+    /// The download pass of the other photos
+    pub report: MultiDownloadReport,
+    /// The photos whose originals the run downloaded, in album order
+    pub new_photos: Vec<Image>,
+}
+
+/// Downloads the originals of an album's photos that aren't in `dir` yet
+///
+/// This is what the `sync` method and the `icloud-album mirror` command run.
+/// What was downloaded is kept in a [`SyncState`] in `dir`'s
+/// [`SYNC_STATE_FILE`]; photos whose file is still in place, or that were
+/// evicted, are skipped.
+///
+/// # Arguments
+///
+/// * `token` - The album token
+/// * `dir` - The directory mirroring the album, created if needed
+/// * `fetch` - How the album is fetched
+/// * `download` - How the photos are downloaded
+///
+/// # Returns
+///
+/// The outcome of the run, or an error if the album could not be fetched or
+/// the state could not be read or saved; failed downloads are in the report
+pub async fn sync_album(
+    token: &str,
+    dir: &Path,
+    fetch: &FetchOptions,
+    download: &DownloadOptions,
+) -> Result<SyncRun, Box<dyn Error>> {
+    let album = crate::get_icloud_photos_with(token, fetch).await?;
+
+    let state_path = dir.join(SYNC_STATE_FILE);
+    let mut state = SyncState::load(&state_path).await?;
+    let pending: Vec<Image> = album
+        .photos
+        .iter()
+        .filter(|photo| {
+            !state.evictions.contains_key(&photo.photo_guid)
+                && !state
+                    .file(&photo.photo_guid)
+                    .is_some_and(|file| file.path.is_file())
+        })
+        .cloned()
+        .collect();
+    let report = download::download_derivative_classes(
+        &pending,
+        &dir.to_string_lossy(),
+        &[SizeClass::Original],
+        download,
+    )
+    .await?;
+    for file in &report.downloaded {
+        let Some(photo) = pending.iter().find(|p| p.photo_guid == file.photo_guid) else {
+            continue;
+        };
+        let mut synced = SyncedFile::for_photo(photo, &file.path);
+        synced.checksum = photo
+            .derivatives
+            .get(&file.key)
+            .map(|derivative| derivative.checksum.clone());
+        state.record(&photo.photo_guid, synced);
+    }
+    state.stream_ctag = Some(album.metadata.stream_ctag.clone());
+    state.record_run(album.photos.len()).await;
+    state.save(&state_path).await?;
+    let synced = report.downloaded.len();
+    metrics::record(|metrics| {
+        metrics.on_sync_completed(&redact_token(token), synced, SystemTime::now())
+    });
+
+    let downloaded: HashSet<&str> = report
+        .downloaded
+        .iter()
+        .map(|file| file.photo_guid.as_str())
+        .collect();
+    let new_photos = pending
+        .iter()
+        .filter(|photo| downloaded.contains(photo.photo_guid.as_str()))
+        .cloned()
+        .collect();
+    Ok(SyncRun {
+        metadata: album.metadata,
+        photos: album.photos.len(),
+        skipped: album.photos.len() - pending.len(),
+        report,
+        new_photos,
+    })
+}
+
+/// Returns an OpenAPI 3.1 description of the server's HTTP API
+///
+/// The document covers the JSON-RPC endpoint and its methods, the album
+/// archive and asset routes, `/health`, `/metrics` and the web UI, so
+/// clients and UIs can be generated against a running server. The server
+/// serves it at `GET /openapi.json` without credentials. The album API the
+/// server fetches from is described by [`crate::schema::openapi_document`].
+pub fn openapi_document() -> Value {
+    let token = json!({
+        "name": "token",
+        "in": "path",
+        "required": true,
+        "description": "The album token from the share URL",
+        "schema": { "type": "string" }
+    });
+    let text = |description: &str| {
+        json!({
+            "description": description,
+            "content": { "text/plain": { "schema": { "type": "string" } } }
+        })
+    };
+    let json_body = |description: &str, schema: &str| {
+        json!({
+            "description": description,
+            "content": {
+                "application/json": {
+                    "schema": { "$ref": format!("#/components/schemas/{}", schema) }
+                }
+            }
+        })
+    };
+    let limited = json!({
+        "description": "The client's rate limit or the album's bandwidth quota is used up",
+        "headers": { "Retry-After": { "schema": { "type": "integer" } } }
+    });
+    let credentials = json!([{ "bearer": [] }, { "accessToken": [] }]);
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "icloud-album server",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "JSON-RPC API and album routes of the icloud-album serve mode"
+        },
+        "servers": [{ "url": format!("http://localhost:{}", DEFAULT_PORT) }],
+        "paths": {
+            "/rpc": {
+                "post": {
+                    "operationId": "rpc",
+                    "summary": "Fetch, download or sync an album",
+                    "description": "A JSON-RPC 2.0 call. `fetch` takes `token` and optionally \
+                        `resolve_urls`; `download` takes `token`, `dir` and optionally `classes`; \
+                        `sync` takes `token` and `dir`. `dir` is relative to the server's root.",
+                    "security": credentials,
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/RpcRequest" }
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": json_body("The result or the error of the call", "RpcResponse"),
+                        "401": text("Credentials are missing"),
+                        "403": text("The credentials don't open this album"),
+                        "429": limited.clone()
+                    }
+                }
+            },
+            "/albums/{token}/zip": {
+                "get": {
+                    "operationId": "albumZip",
+                    "summary": "A ZIP archive of the album, streamed as the photos download",
+                    "security": credentials,
+                    "parameters": [
+                        token.clone(),
+                        {
+                            "name": "classes",
+                            "in": "query",
+                            "description": "Comma-separated size classes, originals by default",
+                            "schema": { "type": "string" }
+                        },
+                        {
+                            "name": "guids",
+                            "in": "query",
+                            "description": "Comma-separated GUIDs of the photos to include",
+                            "schema": { "type": "string" }
+                        }
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The archive",
+                            "content": { ZIP_CONTENT_TYPE: {} }
+                        },
+                        "400": text("Unknown size class or invalid token"),
+                        "404": text("No such album"),
+                        "429": limited.clone(),
+                        "502": text("The album could not be fetched")
+                    }
+                }
+            },
+            "/albums/{token}/assets/{guid}/{class}": {
+                "get": {
+                    "operationId": "albumAsset",
+                    "summary": "A derivative of a photo in a size class, such as thumb or original",
+                    "description": "The ETag is the derivative's checksum, and Range requests \
+                        are honored. With a resizer, w, h and fit ask for the photo resized \
+                        to that box.",
+                    "security": credentials,
+                    "parameters": [
+                        token,
+                        {
+                            "name": "guid",
+                            "in": "path",
+                            "required": true,
+                            "schema": { "type": "string" }
+                        },
+                        {
+                            "name": "class",
+                            "in": "path",
+                            "required": true,
+                            "schema": { "type": "string" }
+                        },
+                        { "name": "w", "in": "query", "schema": { "type": "integer", "minimum": 1 } },
+                        { "name": "h", "in": "query", "schema": { "type": "integer", "minimum": 1 } },
+                        {
+                            "name": "fit",
+                            "in": "query",
+                            "schema": { "enum": ["contain", "cover", "fill"] }
+                        }
+                    ],
+                    "responses": {
+                        "200": { "description": "The asset" },
+                        "206": { "description": "The requested range of the asset" },
+                        "304": { "description": "The asset matches If-None-Match" },
+                        "400": text("Unknown size class or resize parameters"),
+                        "404": text("No such album, photo or derivative"),
+                        "416": text("The range is outside the asset"),
+                        "429": limited,
+                        "502": text("The album or the asset could not be fetched")
+                    }
+                }
+            },
+            "/health": {
+                "get": {
+                    "operationId": "health",
+                    "summary": "Liveness, and the health of the attached scheduler",
+                    "responses": {
+                        "200": json_body("The server is healthy", "Health"),
+                        "503": json_body("Albums are failing persistently", "Health")
+                    }
+                }
+            },
+            "/metrics": {
+                "get": {
+                    "operationId": "metrics",
+                    "summary": "Prometheus counters, when metrics are attached",
+                    "responses": {
+                        "200": {
+                            "description": "Counters in the Prometheus text format",
+                            "content": { "text/plain; version=0.0.4": {} }
+                        },
+                        "404": text("Metrics are not attached")
+                    }
+                }
+            },
+            "/ui/": {
+                "get": {
+                    "operationId": "webUi",
+                    "summary": "The web UI listing albums, with the web-ui feature",
+                    "responses": {
+                        "200": { "description": "The page", "content": { "text/html": {} } }
+                    }
+                }
+            },
+            "/ui/albums.json": {
+                "get": {
+                    "operationId": "webUiAlbums",
+                    "summary": "The albums of the web UI the credentials open",
+                    "security": credentials,
+                    "responses": {
+                        "200": { "description": "The albums and their sync status" }
+                    }
+                }
+            },
+            "/ui/albums/{id}.json": {
+                "get": {
+                    "operationId": "webUiAlbum",
+                    "summary": "The photo grid of an album of the web UI",
+                    "security": credentials,
+                    "parameters": [{
+                        "name": "id",
+                        "in": "path",
+                        "required": true,
+                        "description": "The album token",
+                        "schema": { "type": "string" }
+                    }],
+                    "responses": {
+                        "200": { "description": "The grid" },
+                        "404": text("No such album")
+                    }
+                }
+            },
+            "/openapi.json": {
+                "get": {
+                    "operationId": "openapi",
+                    "summary": "This document",
+                    "responses": { "200": { "description": "The OpenAPI document" } }
+                }
+            }
+        },
+        "components": {
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+                "accessToken": { "type": "apiKey", "in": "query", "name": "access_token" }
+            },
+            "schemas": {
+                "RpcRequest": {
+                    "type": "object",
+                    "required": ["jsonrpc", "method", "params"],
+                    "properties": {
+                        "jsonrpc": { "const": "2.0" },
+                        "id": {},
+                        "method": { "enum": ["fetch", "download", "sync"] },
+                        "params": {
+                            "type": "object",
+                            "required": ["token"],
+                            "properties": {
+                                "token": { "type": "string" },
+                                "dir": { "type": "string" },
+                                "classes": { "type": "array", "items": { "type": "string" } },
+                                "resolve_urls": { "type": "boolean" }
+                            }
+                        }
+                    }
+                },
+                "RpcResponse": {
+                    "type": "object",
+                    "required": ["jsonrpc", "id"],
+                    "properties": {
+                        "jsonrpc": { "const": "2.0" },
+                        "id": {},
+                        "result": { "type": "object" },
+                        "error": {
+                            "type": "object",
+                            "required": ["code", "message"],
+                            "properties": {
+                                "code": { "type": "integer" },
+                                "message": { "type": "string" }
+                            }
+                        }
+                    }
+                },
+                "Health": {
+                    "type": "object",
+                    "required": ["status", "uptime_secs"],
+                    "properties": {
+                        "status": { "enum": ["ok", "degraded"] },
+                        "uptime_secs": { "type": "integer" },
+                        "scheduler": {
+                            "type": "object",
+                            "properties": {
+                                "albums": { "type": "integer" },
+                                "queue_depth": { "type": "integer" },
+                                "polling": { "type": "integer" },
+                                "failing": { "type": "integer" },
+                                "persistent_errors": { "type": "integer" },
+                                "last_success": { "type": ["integer", "null"] },
+                                "last_error": { "type": ["object", "null"] }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Downloads the given classes of `photos` into `dir`
+async fn download_photos(
+    photos: &[Image],
+    dir: &Path,
+    classes: &[SizeClass],
+    shared: &Shared,
+) -> Result<MultiDownloadReport, RpcError> {
+    download::download_derivative_classes(
+        photos,
+        &dir.to_string_lossy(),
+        classes,
+        &shared.config.download,
+    )
+    .await
+    .map_err(|e| RpcError::failed(e.as_ref()))
+}
+
+/// Describes the outcome of a download pass
+fn report_json(report: &MultiDownloadReport) -> Value {
+    let downloaded: Vec<Value> = report
+        .downloaded
+        .iter()
+        .map(|file| {
+            json!({
+                "photo_guid": file.photo_guid,
+                "class": file.size_class.file_suffix(),
+                "path": file.path,
+            })
+        })
+        .collect();
+    let failures: Vec<Value> = report
+        .failures
+        .iter()
+        .map(|(photo_guid, class, error)| {
+            json!({
+                "photo_guid": photo_guid,
+                "class": class.file_suffix(),
+                "error": Redacted(error).to_string(),
+            })
+        })
+        .collect();
+    json!({
+        "downloaded": downloaded,
+        "missing": report.missing.len(),
+        "failures": failures,
+    })
+}
+
+/// Resolves the `dir` parameter below `root`
+fn output_dir(params: &Value, root: &Path) -> Result<PathBuf, RpcError> {
+    let dir = params
+        .get("dir")
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::invalid_params("Missing dir"))?;
+    let relative = Path::new(dir);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(RpcError::invalid_params(
+            "dir must be a relative path inside the server's root",
+        ));
+    }
+    Ok(root.join(relative))
+}
+
+/// Parses the `classes` parameter, defaulting to the originals
+fn size_classes(params: &Value) -> Result<Vec<SizeClass>, RpcError> {
+    let Some(classes) = params.get("classes") else {
+        return Ok(vec![SizeClass::Original]);
+    };
+    let invalid = || RpcError::invalid_params("classes must be a list of size classes");
+    let classes = classes.as_array().ok_or_else(invalid)?;
+    if classes.is_empty() {
+        return Err(invalid());
+    }
+    classes
+        .iter()
+        .map(|class| {
+            let name = class.as_str().ok_or_else(invalid)?;
+            SizeClass::from_file_suffix(name)
+                .ok_or_else(|| RpcError::invalid_params(format!("Unknown size class {:?}", name)))
+        })
+        .collect()
+}
+
+/// Marks a directory as being synced until dropped
+struct SyncGuard<'a> {
+    syncing: &'a Mutex<HashSet<PathBuf>>,
+    dir: PathBuf,
+}
+
+impl<'a> SyncGuard<'a> {
+    fn acquire(syncing: &'a Mutex<HashSet<PathBuf>>, dir: &Path) -> Result<Self, RpcError> {
+        let mut running = syncing.lock().unwrap_or_else(|e| e.into_inner());
+        if !running.insert(dir.to_path_buf()) {
+            return Err(RpcError {
+                code: OPERATION_FAILED,
+                message: "A sync of this directory is already running".to_string(),
+            });
+        }
+        Ok(Self {
+            syncing,
+            dir: dir.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for SyncGuard<'_> {
+    fn drop(&mut self) {
+        self.syncing
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.dir);
+    }
+}
@@ -0,0 +1,195 @@
+//! Chunked download sessions for very large albums.
+//!
+//! A single [`download_derivative_classes`] pass over a 50,000-photo album
+//! takes hours, and an interruption means starting over. [`download_chunked`]
+//! splits the photos into chunks of [`ChunkedDownload::chunk_size`] and runs
+//! one pass per chunk. Once a chunk completes, its boundaries are recorded in
+//! [`SyncState::chunk_progress`] and the state is saved, so the next session
+//! skips the chunks that are done and resumes with the first one that isn't.
+//! When every chunk has completed, the progress is cleared.
+//!
+//! Chunks are matched by position and by the GUIDs of their first and last
+//! photos, so photos must be passed in a stable order, e.g. as fetched. If the
+//! album changed in between, the chunks whose boundaries moved run again.
+
+use crate::derivatives::SizeClass;
+use crate::download::{download_derivative_classes, DownloadOptions, MultiDownloadReport};
+use crate::logging::{self, log_debug};
+use crate::models::Image;
+use crate::sync::SyncState;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Default number of photos per chunk
+pub const DEFAULT_CHUNK_SIZE: usize = 500;
+
+/// How [`download_chunked`] splits and checkpoints a download
+#[derive(Debug, Clone)]
+pub struct ChunkedDownload {
+    /// Number of photos per chunk
+    pub chunk_size: usize,
+    /// File the state is saved to after every chunk, or `None` not to save it
+    pub state_path: Option<PathBuf>,
+}
+
+impl Default for ChunkedDownload {
+    fn default() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            state_path: None,
+        }
+    }
+}
+
+impl ChunkedDownload {
+    /// Create a session with chunks of `chunk_size` photos (at least 1)
+    pub fn new(chunk_size: usize) -> Self {
+        Self {
+            chunk_size: chunk_size.max(1),
+            ..Self::default()
+        }
+    }
+
+    /// Save the state to `path` after every chunk, see [`SyncState::save`]
+    #[must_use]
+    pub fn with_state_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_path = Some(path.into());
+        self
+    }
+}
+
+/// Result of [`download_chunked`]
+#[derive(Debug, Default)]
+pub struct ChunkedReport {
+    /// Number of chunks the photos were split into
+    pub chunks: usize,
+    /// Chunks skipped because an earlier session completed them
+    pub skipped: usize,
+    /// Chunks completed by this session
+    pub completed: usize,
+    /// Chunks with failed downloads, which stay pending for the next session
+    pub failed: usize,
+    /// Whether every chunk has now completed; `false` if the session was
+    /// stopped or a chunk failed
+    pub finished: bool,
+    /// The passes over the chunks run by this session, combined
+    pub report: MultiDownloadReport,
+}
+
+/// Downloads the photos of a large album in checkpointed chunks
+///
+/// Each chunk is downloaded with [`download_derivative_classes`]. A chunk
+/// completes when its pass returns without failed downloads. A chunk with
+/// failures stays pending and the session moves on to the next one; when
+/// [`DownloadOptions::shutdown`] cancelled some of its downloads, the session
+/// stops. Either way, the chunk runs again next time.
+///
+/// # Arguments
+///
+/// * `photos` - The photos to download, enriched with URLs, in a stable order
+/// * `output_dir` - Directory where the files should be saved
+/// * `classes` - The size classes to download for every photo
+/// * `options` - Options controlling how the files are written
+/// * `session` - Chunk size and where to save the state
+/// * `state` - The album's state, holding the progress of earlier sessions
+/// * `on_chunk` - Called with every chunk's report before its checkpoint is
+///   saved, e.g. to record the written files in the state
+///
+/// # Returns
+///
+/// A report of the session, or an error if a pass could not start or the
+/// state could not be saved
+pub async fn download_chunked<F>(
+    photos: &[Image],
+    output_dir: &str,
+    classes: &[SizeClass],
+    options: &DownloadOptions,
+    session: &ChunkedDownload,
+    state: &mut SyncState,
+    mut on_chunk: F,
+) -> Result<ChunkedReport, Box<dyn Error>>
+where
+    F: FnMut(&mut SyncState, &MultiDownloadReport),
+{
+    let chunk_size = session.chunk_size.max(1);
+    // Cloned, so the state keeps its progress if a pass fails to start
+    let mut progress = state
+        .chunk_progress
+        .clone()
+        .filter(|progress| progress.chunk_size == chunk_size)
+        .unwrap_or_default();
+    progress.chunk_size = chunk_size;
+
+    let mut result = ChunkedReport {
+        chunks: photos.len().div_ceil(chunk_size),
+        ..ChunkedReport::default()
+    };
+    let mut stopped = false;
+    for (index, chunk) in photos.chunks(chunk_size).enumerate() {
+        if progress.is_completed(index, chunk) {
+            result.skipped += 1;
+            continue;
+        }
+        if options
+            .shutdown
+            .as_ref()
+            .is_some_and(|shutdown| shutdown.is_triggered())
+        {
+            stopped = true;
+            break;
+        }
+
+        log_debug!(
+            logging::DOWNLOAD,
+            "Downloading chunk {} of {} ({} photos)",
+            index + 1,
+            result.chunks,
+            chunk.len()
+        );
+        let report = download_derivative_classes(chunk, output_dir, classes, options).await?;
+        on_chunk(state, &report);
+        let cancelled = !report.cancelled.is_empty();
+        let failed = !report.failures.is_empty();
+        merge_report(&mut result.report, report);
+        if cancelled {
+            stopped = true;
+            break;
+        }
+        if failed {
+            // Left pending, so the next session retries the failed downloads
+            result.failed += 1;
+            continue;
+        }
+
+        progress.complete(index, chunk);
+        result.completed += 1;
+        state.chunk_progress = Some(progress.clone());
+        if let Some(path) = &session.state_path {
+            state.save(path).await?;
+        }
+    }
+
+    result.finished = !stopped && result.failed == 0;
+    state.chunk_progress = if result.finished {
+        None
+    } else {
+        Some(progress)
+    };
+    if let Some(path) = &session.state_path {
+        state.save(path).await?;
+    }
+    Ok(result)
+}
+
+/// Adds the outcome of a chunk's pass to the session's report
+fn merge_report(total: &mut MultiDownloadReport, chunk: MultiDownloadReport) {
+    total.downloaded.extend(chunk.downloaded);
+    total.missing.extend(chunk.missing);
+    total.failures.extend(chunk.failures);
+    total.cancelled.extend(chunk.cancelled);
+    total.hedged += chunk.hedged;
+    // The limits at the end of the latest pass are the ones to start from
+    total.concurrency = chunk.concurrency;
+    #[cfg(feature = "transcode")]
+    total.transcodes.extend(chunk.transcodes);
+}
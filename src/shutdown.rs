@@ -0,0 +1,66 @@
+//! Cooperative shutdown of long-running components.
+//!
+//! A [`Shutdown`] handle is shared between an application and the components
+//! it embeds, such as [`crate::watch::AlbumWatcher::run`] and
+//! [`crate::download::download_derivative_classes`] (through
+//! [`crate::download::DownloadOptions::with_shutdown`]). Triggering it makes
+//! them stop polling, cancel requests in flight and return promptly, without
+//! leaving partial files behind. Components don't own a
+//! [`crate::sync::SyncState`]; save it with [`crate::sync::SyncState::save`]
+//! once they have returned.
+
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// A shareable signal asking components to stop
+///
+/// Clones share the same signal. Once triggered, it stays triggered.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            sender: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl Shutdown {
+    /// Create a signal that has not been triggered
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks every component sharing this signal to stop
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Whether the signal has been triggered
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Completes once the signal is triggered
+    pub async fn triggered(&self) {
+        let mut receiver = self.sender.subscribe();
+        // The sender lives as long as `self`, so waiting cannot fail
+        let _ = receiver.wait_for(|triggered| *triggered).await;
+    }
+
+    /// Runs `future` unless the signal is triggered first
+    ///
+    /// # Returns
+    ///
+    /// The output of `future`, or `None` if it was cancelled
+    pub async fn run_until<F: std::future::Future>(&self, future: F) -> Option<F::Output> {
+        tokio::select! {
+            biased;
+            _ = self.triggered() => None,
+            output = future => Some(output),
+        }
+    }
+}
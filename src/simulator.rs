@@ -0,0 +1,351 @@
+//! Local simulator of the shared album API for development.
+//!
+//! A [`Simulator`] serves fake webstream, webasseturls and asset endpoints
+//! over plain HTTP, so the full pipeline can be exercised without a real
+//! album token. Point the crate at it with
+//! [`crate::base_url::with_api_origin`] or the
+//! [`crate::base_url::API_ORIGIN_ENV`] environment variable:
+//!
+//! ```no_run
+//! use icloud_album_rs::base_url::with_api_origin;
+//! use icloud_album_rs::simulator::{Simulator, SimulatorConfig};
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let simulator = Simulator::bind("127.0.0.1:0", SimulatorConfig::new().with_photos(50)).await?;
+//! let origin = simulator.origin();
+//! tokio::spawn(simulator.run());
+//!
+//! let album = with_api_origin(&origin, icloud_album_rs::get_icloud_photos("AnyToken")).await?;
+//! assert_eq!(album.photos.len(), 50);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Every token is served the same generated album, and `/openapi.json`
+//! describes the API, see [`crate::schema::openapi_document`]. The
+//! `album-simulator` binary runs a simulator from the command line. This
+//! module is only compiled with the `simulator` feature.
+
+use crate::local_http::{read_request, write_response, Request, Response};
+use crate::logging;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+
+/// Host named in the simulated 330 redirect
+const SIMULATED_HOST: &str = "p99-sharedstreams.icloud.com";
+
+/// Host named in simulated asset URLs
+const SIMULATED_ASSET_HOST: &str = "cvws.icloud-content.com";
+
+/// How the simulator misbehaves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailureMode {
+    /// Answer every request normally
+    #[default]
+    None,
+    /// Answer every Nth request with the given status
+    Status {
+        /// Fail every Nth request
+        every: u64,
+        /// Status code of the failures
+        status: u16,
+    },
+    /// Answer every Nth API request with truncated JSON
+    Malformed {
+        /// Corrupt every Nth API request
+        every: u64,
+    },
+    /// Leave every second photo out of webasseturls responses
+    MissingUrls,
+    /// Answer the first webstream request of each token with a 330 redirect
+    Redirect,
+}
+
+/// The album served and how the simulator behaves
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulatorConfig {
+    /// Number of photos in the album
+    pub photos: usize,
+    /// Name of the album
+    pub album_name: String,
+    /// Delay added to every response
+    pub latency: Duration,
+    /// How requests fail
+    pub failure: FailureMode,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        Self {
+            photos: 10,
+            album_name: "Simulated Album".to_string(),
+            latency: Duration::ZERO,
+            failure: FailureMode::None,
+        }
+    }
+}
+
+impl SimulatorConfig {
+    /// Create a configuration serving ten photos without failures
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve an album with `photos` photos
+    #[must_use]
+    pub fn with_photos(mut self, photos: usize) -> Self {
+        self.photos = photos;
+        self
+    }
+
+    /// Serve an album named `name`
+    #[must_use]
+    pub fn with_album_name(mut self, name: &str) -> Self {
+        self.album_name = name.to_string();
+        self
+    }
+
+    /// Delay every response by `latency`
+    #[must_use]
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Misbehave according to `failure`
+    #[must_use]
+    pub fn with_failure(mut self, failure: FailureMode) -> Self {
+        self.failure = match failure {
+            FailureMode::Status { every, status } => FailureMode::Status {
+                every: every.max(1),
+                status,
+            },
+            FailureMode::Malformed { every } => FailureMode::Malformed {
+                every: every.max(1),
+            },
+            other => other,
+        };
+        self
+    }
+}
+
+/// State shared by the connections of a simulator
+#[derive(Debug, Default)]
+struct State {
+    requests: AtomicU64,
+    redirected: Mutex<HashSet<String>>,
+}
+
+/// A simulated album API listening on a local address
+#[derive(Debug)]
+pub struct Simulator {
+    listener: TcpListener,
+    config: Arc<SimulatorConfig>,
+    state: Arc<State>,
+}
+
+impl Simulator {
+    /// Binds a simulator to `addr`, e.g. `127.0.0.1:0` for any free port
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address to listen on
+    /// * `config` - The album served and how the simulator behaves
+    ///
+    /// # Returns
+    ///
+    /// The bound simulator, which serves nothing until [`Simulator::run`]
+    pub async fn bind(addr: &str, config: SimulatorConfig) -> std::io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            config: Arc::new(config),
+            state: Arc::new(State::default()),
+        })
+    }
+
+    /// Returns the address the simulator listens on
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Returns the origin to pass to [`crate::base_url::with_api_origin`]
+    pub fn origin(&self) -> String {
+        match self.local_addr() {
+            Ok(addr) => format!("http://{}", addr),
+            Err(_) => "http://127.0.0.1".to_string(),
+        }
+    }
+
+    /// Serves requests until the task is dropped or accepting fails
+    pub async fn run(self) -> std::io::Result<()> {
+        loop {
+            let (stream, _) = self.listener.accept().await?;
+            let config = Arc::clone(&self.config);
+            let state = Arc::clone(&self.state);
+            tokio::spawn(async move {
+                if let Err(e) = serve_connection(stream, &config, &state).await {
+                    logging::log_debug!(logging::API, "Simulator connection failed: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// Answers one request on `stream`, then closes it
+async fn serve_connection(
+    mut stream: TcpStream,
+    config: &SimulatorConfig,
+    state: &State,
+) -> std::io::Result<()> {
+    let request = read_request(&mut stream).await?;
+    if !config.latency.is_zero() {
+        tokio::time::sleep(config.latency).await;
+    }
+    let response = respond(&request, config, state);
+    write_response(&mut stream, &response).await
+}
+
+/// Builds the response to a request
+fn respond(request: &Request, config: &SimulatorConfig, state: &State) -> Response {
+    let number = state.requests.fetch_add(1, Ordering::SeqCst) + 1;
+    if let FailureMode::Status { every, status } = config.failure {
+        if every != 0 && number % every == 0 {
+            return Response::text(status, "simulated failure");
+        }
+    }
+
+    let path = request.path.as_str();
+    let api = match (request.method.as_str(), path.rsplit_once('/')) {
+        ("POST", Some((prefix, "webstream"))) => Some((prefix, true)),
+        ("POST", Some((prefix, "webasseturls"))) => Some((prefix, false)),
+        _ => None,
+    };
+    let Some((prefix, is_webstream)) = api else {
+        return match request.method.as_str() {
+            "GET" if path == "/openapi.json" => {
+                Response::json(200, &crate::schema::openapi_document())
+            }
+            "GET" if path.starts_with("/S/") => {
+                Response::new(200, "image/jpeg", crate::transport::JPEG_BYTES.to_vec())
+            }
+            _ => Response::text(404, "not found"),
+        };
+    };
+
+    if let FailureMode::Malformed { every } = config.failure {
+        if every != 0 && number % every == 0 {
+            return Response::new(
+                200,
+                "application/json",
+                br#"{"streamName": "Simulated"#.to_vec(),
+            );
+        }
+    }
+
+    if is_webstream {
+        if config.failure == FailureMode::Redirect {
+            let first = state
+                .redirected
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(prefix.to_string());
+            if first {
+                return Response::json(330, &json!({ "X-Apple-MMe-Host": SIMULATED_HOST }));
+            }
+        }
+        Response::json(200, &webstream(config))
+    } else {
+        let guids: Vec<String> = serde_json::from_slice::<Value>(&request.body)
+            .ok()
+            .and_then(|body| serde_json::from_value(body["photoGuids"].clone()).ok())
+            .unwrap_or_default();
+        Response::json(200, &webasseturls(config, &guids))
+    }
+}
+
+/// GUID of the photo at `index`
+fn photo_guid(index: usize) -> String {
+    format!("51A70000-0000-4000-8000-{:012}", index)
+}
+
+/// Checksum of derivative `size` of the photo at `index`
+fn checksum(index: usize, size: &str) -> String {
+    format!("01{:08}{}", index, size)
+}
+
+/// Builds the webstream body of the simulated album
+fn webstream(config: &SimulatorConfig) -> Value {
+    let photos: Vec<Value> = (0..config.photos)
+        .map(|index| {
+            let date = format!(
+                "2024-01-{:02}T{:02}:00:00Z",
+                1 + (index / 24) % 28,
+                index % 24
+            );
+            json!({
+                "photoGuid": photo_guid(index),
+                "batchGuid": "51A7BA7C-0000-4000-8000-000000000000",
+                "batchDateCreated": date,
+                "dateCreated": date,
+                "caption": format!("Photo {}", index + 1),
+                "contributorFullName": "Sim Ulator",
+                "width": "4032",
+                "height": "3024",
+                "mediaAssetType": "image",
+                "derivatives": {
+                    "342": {
+                        "checksum": checksum(index, "342"),
+                        "fileSize": "29034",
+                        "width": "342",
+                        "height": "257"
+                    },
+                    "original": {
+                        "checksum": checksum(index, "original"),
+                        "fileSize": "3481920",
+                        "width": "4032",
+                        "height": "3024"
+                    }
+                }
+            })
+        })
+        .collect();
+
+    json!({
+        "streamName": config.album_name,
+        "userFirstName": "Sim",
+        "userLastName": "Ulator",
+        "streamCtag": format!("SIM;{}", config.photos),
+        "itemsReturned": config.photos.to_string(),
+        "locations": {},
+        "photos": photos
+    })
+}
+
+/// Builds the webasseturls body for the requested photos
+fn webasseturls(config: &SimulatorConfig, guids: &[String]) -> Value {
+    let mut items = serde_json::Map::new();
+    for index in 0..config.photos {
+        if !guids.contains(&photo_guid(index)) {
+            continue;
+        }
+        if config.failure == FailureMode::MissingUrls && index % 2 == 1 {
+            continue;
+        }
+        for size in ["342", "original"] {
+            let checksum = checksum(index, size);
+            items.insert(
+                checksum.clone(),
+                json!({
+                    "url_location": SIMULATED_ASSET_HOST,
+                    "url_path": format!("/S/{}/IMG_{:04}.JPG", checksum, index + 1)
+                }),
+            );
+        }
+    }
+    json!({ "items": items, "locations": {} })
+}
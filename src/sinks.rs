@@ -0,0 +1,41 @@
+//! Built-in [`crate::watch::EventSink`] implementations for album watchers.
+//!
+//! [`JsonlSink`] is always available. Sinks that talk to external services are
+//! behind their own feature so that applications only compile the delivery
+//! mechanisms they use:
+//!
+//! * `smtp` - [`SmtpSink`] emails album changes through an SMTP relay
+//! * `webhooks` - [`TelegramSink`] and [`DiscordSink`] post new photos to a chat
+
+use serde::{Deserialize, Serialize};
+
+mod jsonl;
+pub use jsonl::{read_event_log, EventRecord, JsonlSink};
+
+#[cfg(feature = "smtp")]
+mod smtp;
+#[cfg(feature = "smtp")]
+pub use smtp::{EmailMode, SmtpSink};
+
+/// How an SMTP sink secures its connection to the relay
+///
+/// Always available so that [`crate::app_config::SinkSettings`] can carry it,
+/// used by `SmtpSink` with the `smtp` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain SMTP, for a local or otherwise trusted relay
+    None,
+    /// Upgrade with STARTTLS when the relay offers it
+    #[default]
+    Opportunistic,
+    /// Require STARTTLS, usually on port 587
+    StartTls,
+    /// TLS from the start of the connection, usually on port 465
+    Tls,
+}
+
+#[cfg(feature = "webhooks")]
+mod webhook;
+#[cfg(feature = "webhooks")]
+pub use webhook::{DiscordSink, TelegramSink};
@@ -0,0 +1,104 @@
+//! Append-only JSONL event log.
+
+use crate::models::{Metadata, StreamCtag};
+use crate::watch::{AlbumEvent, EventSink, SinkError, SinkFuture};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+
+/// One line of an event log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventRecord {
+    /// Time the event was recorded, in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Name of the album the event belongs to
+    pub album: String,
+    /// The album's `streamCtag` when the event was detected
+    pub stream_ctag: StreamCtag,
+    /// The event itself
+    #[serde(flatten)]
+    pub event: AlbumEvent,
+}
+
+/// Sink that appends every event to a JSON Lines file
+///
+/// Each event is written as one [`EventRecord`] object per line. The file is
+/// created on first use and never truncated, so it forms a durable audit trail
+/// that can be fed into other tools or read back with [`read_event_log`].
+#[derive(Debug, Clone)]
+pub struct JsonlSink {
+    path: PathBuf,
+}
+
+impl JsonlSink {
+    /// Create a sink appending to the file at `path`
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Returns the path of the log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl EventSink for JsonlSink {
+    fn deliver<'a>(&'a self, album: &'a Metadata, events: &'a [AlbumEvent]) -> SinkFuture<'a> {
+        Box::pin(async move {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+
+            let mut lines = String::new();
+            for event in events {
+                let record = EventRecord {
+                    timestamp,
+                    album: album.stream_name.clone(),
+                    stream_ctag: album.stream_ctag.clone(),
+                    event: event.clone(),
+                };
+                lines.push_str(&serde_json::to_string(&record)?);
+                lines.push('\n');
+            }
+
+            // Write the whole batch at once so concurrent readers never see half a batch
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await?;
+            file.write_all(lines.as_bytes()).await?;
+            file.flush().await?;
+            Ok(())
+        })
+    }
+}
+
+/// Reads back an event log written by [`JsonlSink`]
+///
+/// Blank lines are ignored. A malformed line is reported as an error together
+/// with its line number.
+///
+/// # Arguments
+///
+/// * `path` - Path of the log file
+///
+/// # Returns
+///
+/// The records in the order they were written
+pub async fn read_event_log(path: impl AsRef<Path>) -> Result<Vec<EventRecord>, SinkError> {
+    let contents = tokio::fs::read_to_string(path).await?;
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| format!("Invalid event log line {}: {}", index + 1, e).into())
+        })
+        .collect()
+}
@@ -0,0 +1,204 @@
+//! SMTP email sink.
+
+use super::SmtpTls;
+use crate::models::Metadata;
+use crate::watch::{AlbumEvent, EventSink, SinkError, SinkFuture};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::fmt;
+use std::time::Duration;
+
+/// Timeout of each SMTP command, so a stalled relay cannot hang a watcher
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How an [`SmtpSink`] groups events into emails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmailMode {
+    /// One email summarizing all events detected by a poll
+    #[default]
+    Digest,
+    /// One email per event
+    PerEvent,
+}
+
+/// Sink that emails album events through an SMTP relay
+///
+/// By default the connection is upgraded with STARTTLS when the relay offers
+/// it, see [`SmtpSink::with_tls`], and no authentication is attempted, see
+/// [`SmtpSink::with_credentials`].
+#[derive(Clone)]
+pub struct SmtpSink {
+    host: String,
+    port: u16,
+    from: String,
+    to: Vec<String>,
+    mode: EmailMode,
+    tls: SmtpTls,
+    credentials: Option<(String, String)>,
+}
+
+impl fmt::Debug for SmtpSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SmtpSink")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("from", &self.from)
+            .field("to", &self.to)
+            .field("mode", &self.mode)
+            .field("tls", &self.tls)
+            .field(
+                "username",
+                &self.credentials.as_ref().map(|(username, _)| username),
+            )
+            .finish()
+    }
+}
+
+impl SmtpSink {
+    /// Create a sink that sends digest emails through `host:port`
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - Hostname or IP address of the SMTP relay
+    /// * `port` - Port of the SMTP relay
+    /// * `from` - Sender address
+    /// * `to` - Recipient addresses
+    pub fn new(host: &str, port: u16, from: &str, to: &[&str]) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            from: from.to_string(),
+            to: to.iter().map(|s| s.to_string()).collect(),
+            mode: EmailMode::default(),
+            tls: SmtpTls::default(),
+            credentials: None,
+        }
+    }
+
+    /// Set how events are grouped into emails
+    #[must_use]
+    pub fn with_mode(mut self, mode: EmailMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Set how the connection to the relay is secured
+    #[must_use]
+    pub fn with_tls(mut self, tls: SmtpTls) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Authenticate to the relay with a username and password
+    ///
+    /// Use with [`SmtpTls::StartTls`] or [`SmtpTls::Tls`] so the password is
+    /// never sent in the clear.
+    #[must_use]
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    /// Builds the (subject, body) pairs of the emails for a batch of events
+    ///
+    /// # Arguments
+    ///
+    /// * `album` - Metadata of the album the events belong to
+    /// * `events` - The events to describe
+    ///
+    /// # Returns
+    ///
+    /// One pair in digest mode, one pair per event in per-event mode
+    pub fn compose(&self, album: &Metadata, events: &[AlbumEvent]) -> Vec<(String, String)> {
+        match self.mode {
+            EmailMode::Digest => {
+                let subject = format!(
+                    "[{}] {} change{}",
+                    album.stream_name,
+                    events.len(),
+                    if events.len() == 1 { "" } else { "s" }
+                );
+                let body = events
+                    .iter()
+                    .map(|event| event.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                vec![(subject, body)]
+            }
+            EmailMode::PerEvent => events
+                .iter()
+                .map(|event| {
+                    let description = event.to_string();
+                    (
+                        format!("[{}] {}", album.stream_name, description),
+                        description,
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Builds a message, rejecting malformed addresses
+    ///
+    /// Addresses are parsed as mailboxes, so CR/LF and other characters that
+    /// could inject headers or SMTP commands are refused. The builder adds the
+    /// Date header and encodes non-ASCII subjects per RFC 2047.
+    fn message(&self, subject: &str, body: String) -> Result<Message, SinkError> {
+        let mut builder = Message::builder()
+            .from(mailbox(&self.from)?)
+            .subject(subject.replace(['\r', '\n'], " "))
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            builder = builder.to(mailbox(to)?);
+        }
+        Ok(builder.body(body)?)
+    }
+
+    /// Creates the transport for a single delivery
+    fn transport(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>, SinkError> {
+        let parameters = || TlsParameters::new(self.host.clone());
+        let tls = match self.tls {
+            SmtpTls::None => Tls::None,
+            SmtpTls::Opportunistic => Tls::Opportunistic(parameters()?),
+            SmtpTls::StartTls => Tls::Required(parameters()?),
+            SmtpTls::Tls => Tls::Wrapper(parameters()?),
+        };
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.host)
+            .port(self.port)
+            .tls(tls)
+            .timeout(Some(TIMEOUT));
+        if let Some((username, password)) = &self.credentials {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(builder.build())
+    }
+
+    /// Sends the given messages, stopping at the first failure
+    async fn send(&self, messages: Vec<(String, String)>) -> Result<(), SinkError> {
+        let messages = messages
+            .into_iter()
+            .map(|(subject, body)| self.message(&subject, body))
+            .collect::<Result<Vec<_>, _>>()?;
+        let transport = self.transport()?;
+        for message in messages {
+            transport.send(message).await?;
+        }
+        Ok(())
+    }
+}
+
+impl EventSink for SmtpSink {
+    fn deliver<'a>(&'a self, album: &'a Metadata, events: &'a [AlbumEvent]) -> SinkFuture<'a> {
+        Box::pin(async move { self.send(self.compose(album, events)).await })
+    }
+}
+
+/// Parses an address into a mailbox
+fn mailbox(address: &str) -> Result<Mailbox, SinkError> {
+    address
+        .parse()
+        .map_err(|e| format!("Invalid email address {:?}: {}", address, e).into())
+}
@@ -0,0 +1,173 @@
+//! Chat webhook sinks.
+//!
+//! These sinks only announce additions. When the watcher resolves thumbnails
+//! (see [`crate::watch::AlbumWatcher::with_thumbnails`]), each new photo is
+//! posted with its preview image; otherwise only its caption is posted.
+
+use crate::models::Metadata;
+use crate::watch::{AlbumEvent, EventSink, SinkError, SinkFuture};
+use serde_json::{json, Value};
+
+/// Maximum number of embeds Discord accepts in a single webhook message
+const DISCORD_MAX_EMBEDS: usize = 10;
+
+/// A photo addition extracted from an event batch
+struct NewPhoto<'a> {
+    caption: Option<&'a str>,
+    thumbnail_url: Option<&'a str>,
+}
+
+/// Returns the additions contained in a batch of events
+fn new_photos(events: &[AlbumEvent]) -> Vec<NewPhoto<'_>> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            AlbumEvent::PhotoAdded {
+                caption,
+                thumbnail_url,
+                ..
+            } => Some(NewPhoto {
+                caption: caption.as_deref(),
+                thumbnail_url: thumbnail_url.as_deref(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Formats the text posted alongside a new photo
+fn photo_text(album: &Metadata, photo: &NewPhoto<'_>) -> String {
+    match photo.caption {
+        Some(caption) => format!("New photo in {}: {}", album.stream_name, caption),
+        None => format!("New photo in {}", album.stream_name),
+    }
+}
+
+/// Sends a JSON POST and fails on non-success responses
+async fn post_json(client: &reqwest::Client, url: &str, payload: &Value) -> Result<(), SinkError> {
+    client
+        .post(url)
+        .json(payload)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Sink that posts new photos to a Telegram chat through a bot
+#[derive(Debug, Clone)]
+pub struct TelegramSink {
+    client: reqwest::Client,
+    api_base: String,
+    bot_token: String,
+    chat_id: String,
+}
+
+impl TelegramSink {
+    /// Default Telegram Bot API endpoint
+    pub const DEFAULT_API_BASE: &'static str = "https://api.telegram.org";
+
+    /// Create a sink posting to `chat_id` as the bot identified by `bot_token`
+    pub fn new(bot_token: &str, chat_id: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_base: Self::DEFAULT_API_BASE.to_string(),
+            bot_token: bot_token.to_string(),
+            chat_id: chat_id.to_string(),
+        }
+    }
+
+    /// Use a different Bot API endpoint (e.g. a self-hosted Bot API server)
+    #[must_use]
+    pub fn with_api_base(mut self, api_base: &str) -> Self {
+        self.api_base = api_base.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Returns the URL of a Bot API method
+    fn method_url(&self, method: &str) -> String {
+        format!("{}/bot{}/{}", self.api_base, self.bot_token, method)
+    }
+}
+
+impl EventSink for TelegramSink {
+    fn deliver<'a>(&'a self, album: &'a Metadata, events: &'a [AlbumEvent]) -> SinkFuture<'a> {
+        Box::pin(async move {
+            for photo in new_photos(events) {
+                let text = photo_text(album, &photo);
+                // Telegram fetches the preview itself when given a URL
+                let (method, payload) = match photo.thumbnail_url {
+                    Some(url) => (
+                        "sendPhoto",
+                        json!({ "chat_id": self.chat_id, "photo": url, "caption": text }),
+                    ),
+                    None => (
+                        "sendMessage",
+                        json!({ "chat_id": self.chat_id, "text": text }),
+                    ),
+                };
+                post_json(&self.client, &self.method_url(method), &payload).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Sink that posts new photos to a Discord channel through a webhook
+#[derive(Debug, Clone)]
+pub struct DiscordSink {
+    client: reqwest::Client,
+    webhook_url: String,
+}
+
+impl DiscordSink {
+    /// Create a sink posting to the given webhook URL
+    pub fn new(webhook_url: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            webhook_url: webhook_url.to_string(),
+        }
+    }
+
+    /// Builds the webhook payloads for a batch of events
+    ///
+    /// Each new photo becomes one embed, and embeds are split across messages
+    /// to stay within Discord's per-message limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `album` - Metadata of the album the events belong to
+    /// * `events` - The events to describe
+    ///
+    /// # Returns
+    ///
+    /// The JSON payloads to post, empty if the batch contains no additions
+    pub fn payloads(album: &Metadata, events: &[AlbumEvent]) -> Vec<Value> {
+        let embeds: Vec<Value> = new_photos(events)
+            .iter()
+            .map(|photo| {
+                let mut embed = json!({ "title": photo_text(album, photo) });
+                if let Some(url) = photo.thumbnail_url {
+                    embed["image"] = json!({ "url": url });
+                }
+                embed
+            })
+            .collect();
+
+        embeds
+            .chunks(DISCORD_MAX_EMBEDS)
+            .map(|chunk| json!({ "embeds": chunk }))
+            .collect()
+    }
+}
+
+impl EventSink for DiscordSink {
+    fn deliver<'a>(&'a self, album: &'a Metadata, events: &'a [AlbumEvent]) -> SinkFuture<'a> {
+        Box::pin(async move {
+            for payload in Self::payloads(album, events) {
+                post_json(&self.client, &self.webhook_url, &payload).await?;
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,422 @@
+//! Slideshow output for digital photo frames.
+//!
+//! [`build_slideshow`] downloads an album sized for a target display and writes
+//! a `slideshow.json` manifest listing the slides in order, with their display
+//! duration and caption, so a photo frame (e.g. a Raspberry Pi project) only
+//! has to play the manifest.
+//!
+//! iCloud already serves every photo in several sizes, so the derivative
+//! closest to the display resolution is downloaded. Applications that need
+//! exact dimensions can additionally plug in an [`ImageResizer`].
+
+use crate::derivatives::{classify_derivative, SizeClass};
+use crate::download::{self, DownloadOptions};
+use crate::logging;
+use crate::models::{Derivative, ICloudResponse, Image, Placeholder};
+#[cfg(feature = "transcode")]
+use crate::utils::select_derivative_of_class;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// File name of the manifest written by [`build_slideshow`]
+pub const MANIFEST_FILE: &str = "slideshow.json";
+
+/// Order of the slides in a slideshow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlideOrder {
+    /// The order of the album
+    Album,
+    /// Oldest photo first, undated photos last
+    #[default]
+    Chronological,
+    /// A random order, fixed when the slideshow is built
+    Shuffled,
+}
+
+/// Error returned by an [`ImageResizer`]
+pub type ResizeError = Box<dyn Error + Send + Sync>;
+
+/// Resizes downloaded images to the display resolution
+pub trait ImageResizer: Send + Sync {
+    /// Scales an encoded image to fit within `width` x `height`
+    ///
+    /// # Returns
+    ///
+    /// The encoded, resized image
+    fn resize(&self, content: &[u8], width: u32, height: u32) -> Result<Vec<u8>, ResizeError>;
+}
+
+/// Options for [`build_slideshow`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct SlideshowOptions {
+    /// Display width in pixels
+    pub width: u32,
+    /// Display height in pixels
+    pub height: u32,
+    /// How long each slide is shown, in seconds
+    pub duration_secs: u32,
+    /// Include photo captions as overlay text
+    pub captions: bool,
+    /// Order of the slides
+    pub order: SlideOrder,
+    /// Options for writing the downloaded files
+    pub download: DownloadOptions,
+    /// How looping clips of Live Photos and animated images are made, or `None` for stills only
+    #[cfg(feature = "transcode")]
+    pub motion: Option<crate::transcode::LoopOptions>,
+}
+
+impl Default for SlideshowOptions {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            duration_secs: 10,
+            captions: true,
+            order: SlideOrder::default(),
+            download: DownloadOptions::default(),
+            #[cfg(feature = "transcode")]
+            motion: None,
+        }
+    }
+}
+
+impl SlideshowOptions {
+    /// Create options for a display of the given resolution
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            ..Self::default()
+        }
+    }
+
+    /// Sets how long each slide is shown
+    #[must_use]
+    pub fn with_duration(mut self, duration_secs: u32) -> Self {
+        self.duration_secs = duration_secs;
+        self
+    }
+
+    /// Sets whether captions are included
+    #[must_use]
+    pub fn with_captions(mut self, captions: bool) -> Self {
+        self.captions = captions;
+        self
+    }
+
+    /// Sets the order of the slides
+    #[must_use]
+    pub fn with_order(mut self, order: SlideOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Adds a looping clip to slides of Live Photos, videos and animated GIFs
+    ///
+    /// The clip is made with [`crate::transcode::make_loop`] from the photo's
+    /// video derivative or, for animated GIFs, from the slide itself.
+    #[cfg(feature = "transcode")]
+    #[must_use]
+    pub fn with_motion(mut self, motion: crate::transcode::LoopOptions) -> Self {
+        self.motion = Some(motion);
+        self
+    }
+}
+
+/// A single slide of a slideshow
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Slide {
+    /// File name of the image, relative to the manifest
+    pub file: String,
+    /// GUID of the photo shown
+    pub photo_guid: String,
+    /// How long the slide is shown, in seconds
+    pub duration_secs: u32,
+    /// Caption to overlay, if captions are enabled and the photo has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    /// When the photo was taken, as reported by the API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_created: Option<String>,
+    /// Width of the downloaded derivative, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    /// Height of the downloaded derivative, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Loading placeholder of the photo, if it has been computed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub placeholder: Option<Placeholder>,
+    /// File name of a looping clip of the photo, relative to the manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion: Option<String>,
+}
+
+/// Manifest of a slideshow, written as [`MANIFEST_FILE`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlideshowManifest {
+    /// Name of the album
+    pub album: String,
+    /// Display width the slideshow was built for
+    pub width: u32,
+    /// Display height the slideshow was built for
+    pub height: u32,
+    /// Order the slides were arranged in
+    pub order: SlideOrder,
+    /// The slides, in playing order
+    pub slides: Vec<Slide>,
+}
+
+/// Selects the still derivative best suited to a display
+///
+/// This is the smallest derivative that fills the display in at least one
+/// dimension, or the largest one if none does. Derivatives without known
+/// dimensions are only used when no dimensions are known at all.
+///
+/// # Arguments
+///
+/// * `derivatives` - HashMap of derivative key to Derivative
+/// * `width` - Display width in pixels
+/// * `height` - Display height in pixels
+///
+/// # Returns
+///
+/// An Option containing the derivative key and Derivative
+pub fn select_display_derivative(
+    derivatives: &HashMap<String, Derivative>,
+    width: u32,
+    height: u32,
+) -> Option<(String, &Derivative)> {
+    let sized: Vec<(&String, &Derivative, u64)> = derivatives
+        .iter()
+        .filter(|(key, d)| classify_derivative(key, d) != Some(SizeClass::Video))
+        .filter_map(|(key, d)| Some((key, d, d.width? as u64 * d.height? as u64)))
+        .collect();
+
+    let covering = sized
+        .iter()
+        .filter(|(_, d, _)| d.width >= Some(width) || d.height >= Some(height))
+        .min_by(|a, b| a.2.cmp(&b.2).then_with(|| a.0.cmp(b.0)));
+    let chosen = covering.or_else(|| {
+        sized
+            .iter()
+            .max_by(|a, b| a.2.cmp(&b.2).then_with(|| b.0.cmp(a.0)))
+    });
+
+    match chosen {
+        Some((key, derivative, _)) => Some(((*key).clone(), *derivative)),
+        // Without dimensions, the largest still image is the safest choice
+        None => derivatives
+            .iter()
+            .filter(|(key, d)| classify_derivative(key, d) != Some(SizeClass::Video))
+            .max_by(|a, b| a.1.file_size.cmp(&b.1.file_size).then_with(|| b.0.cmp(a.0)))
+            .map(|(key, derivative)| (key.clone(), derivative)),
+    }
+}
+
+/// Orders the photos of a slideshow
+fn ordered_photos(photos: &[Image], order: SlideOrder) -> Vec<&Image> {
+    let mut ordered: Vec<&Image> = photos.iter().collect();
+    match order {
+        SlideOrder::Album => {}
+        SlideOrder::Chronological => {
+            // Stable sort: undated photos keep their album order at the end
+            ordered
+                .sort_by_key(|photo| (photo.timestamp_taken().is_none(), photo.timestamp_taken()))
+        }
+        SlideOrder::Shuffled => ordered.shuffle(&mut rand::thread_rng()),
+    }
+    ordered
+}
+
+/// Downloads and writes a single slide
+async fn write_slide(
+    client: &reqwest::Client,
+    photo: &Image,
+    position: usize,
+    output_dir: &str,
+    options: &SlideshowOptions,
+    resizer: Option<&dyn ImageResizer>,
+) -> Result<Slide, Box<dyn Error>> {
+    let (_key, derivative) =
+        select_display_derivative(&photo.derivatives, options.width, options.height)
+            .ok_or("No still image derivative")?;
+    let url = derivative.url.as_deref().ok_or("Derivative has no URL")?;
+    if let Some(policy) = &options.download.url_policy {
+        policy.validate(url)?;
+    }
+
+    let mut content = crate::har::send(client.get(url))
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?
+        .to_vec();
+    let (mut width, mut height) = (derivative.width, derivative.height);
+    if let Some(resizer) = resizer {
+        content = resizer
+            .resize(&content, options.width, options.height)
+            .map_err(|e| -> Box<dyn Error> { e })?;
+        (width, height) = (None, None);
+    }
+
+    let path = download::save_photo_content(
+        &content,
+        photo,
+        None,
+        output_dir,
+        Some(format!("slide-{:04}", position + 1)),
+        &options.download,
+    )
+    .await?;
+    let motion = motion_clip(client, photo, Path::new(&path), options).await;
+    let file = Path::new(&path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or(path);
+
+    Ok(Slide {
+        file,
+        photo_guid: photo.photo_guid.clone(),
+        duration_secs: options.duration_secs,
+        caption: photo
+            .caption
+            .clone()
+            .filter(|caption| options.captions && !caption.trim().is_empty()),
+        date_created: photo.date_created.clone(),
+        width,
+        height,
+        placeholder: photo.placeholder.clone(),
+        motion,
+    })
+}
+
+/// Writes a looping clip of a Live Photo, video or animated GIF next to its slide
+///
+/// Failures are logged and leave the slide without a clip.
+///
+/// # Returns
+///
+/// The file name of the clip, if one was made
+#[cfg(feature = "transcode")]
+async fn motion_clip(
+    client: &reqwest::Client,
+    photo: &Image,
+    slide: &Path,
+    options: &SlideshowOptions,
+) -> Option<String> {
+    let motion = options.motion.as_ref()?;
+    let stem = slide.file_stem()?.to_string_lossy().into_owned();
+    let output = slide.with_file_name(format!("{}-motion.{}", stem, motion.format.extension()));
+    let staged = slide.with_file_name(format!(".{}-motion.source", stem));
+
+    let result: Result<bool, Box<dyn Error>> = async {
+        let source = match select_derivative_of_class(&photo.derivatives, SizeClass::Video) {
+            Some((_key, derivative)) => {
+                // Selected derivatives always have a URL
+                let url = derivative.url.as_deref().unwrap_or_default();
+                if let Some(policy) = &options.download.url_policy {
+                    policy.validate(url)?;
+                }
+                let content = crate::har::send(client.get(url))
+                    .await?
+                    .error_for_status()?
+                    .bytes()
+                    .await?;
+                tokio::fs::write(&staged, content).await?;
+                staged.as_path()
+            }
+            None if slide.extension().is_some_and(|ext| ext == "gif") => slide,
+            None => return Ok(false),
+        };
+        crate::transcode::make_loop(source, &output, motion).await?;
+        Ok(true)
+    }
+    .await;
+    let _ = tokio::fs::remove_file(&staged).await;
+
+    match result {
+        Ok(true) => output
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned()),
+        Ok(false) => None,
+        Err(e) => {
+            logging::log_warn!(
+                logging::DOWNLOAD,
+                "Leaving the looping clip of photo {} out of the slideshow: {}",
+                photo.photo_guid,
+                crate::redact::Redacted(e)
+            );
+            None
+        }
+    }
+}
+
+/// Writes a looping clip of a Live Photo, video or animated GIF next to its slide
+#[cfg(not(feature = "transcode"))]
+async fn motion_clip(
+    _client: &reqwest::Client,
+    _photo: &Image,
+    _slide: &Path,
+    _options: &SlideshowOptions,
+) -> Option<String> {
+    None
+}
+
+/// Downloads an album as a slideshow for a display and writes its manifest
+///
+/// Photos that cannot be downloaded are logged and left out of the slideshow.
+/// The photos must have been enriched with URLs, e.g. by
+/// [`crate::get_icloud_photos`].
+///
+/// # Arguments
+///
+/// * `response` - The album to show
+/// * `output_dir` - Directory for the slides and the manifest
+/// * `options` - Display resolution, timing and order
+/// * `resizer` - Optional resizer applied to every downloaded image
+///
+/// # Returns
+///
+/// The manifest, which has also been written to `output_dir`
+pub async fn build_slideshow(
+    response: &ICloudResponse,
+    output_dir: impl AsRef<Path>,
+    options: &SlideshowOptions,
+    resizer: Option<&dyn ImageResizer>,
+) -> Result<SlideshowManifest, Box<dyn Error>> {
+    let output_dir: PathBuf = output_dir.as_ref().to_path_buf();
+    let output_str = output_dir.to_string_lossy().into_owned();
+    tokio::fs::create_dir_all(&output_dir).await?;
+    let client = options.download.build_client()?;
+
+    let mut slides = Vec::new();
+    for photo in ordered_photos(&response.photos, options.order) {
+        match write_slide(&client, photo, slides.len(), &output_str, options, resizer).await {
+            Ok(slide) => slides.push(slide),
+            Err(e) => logging::log_warn!(
+                logging::DOWNLOAD,
+                "Leaving photo {} out of the slideshow: {}",
+                photo.photo_guid,
+                crate::redact::Redacted(e)
+            ),
+        }
+    }
+
+    let manifest = SlideshowManifest {
+        album: response.metadata.stream_name.clone(),
+        width: options.width,
+        height: options.height,
+        order: options.order,
+        slides,
+    };
+    let json = serde_json::to_vec_pretty(&manifest)?;
+    tokio::fs::write(output_dir.join(MANIFEST_FILE), json).await?;
+    Ok(manifest)
+}
@@ -0,0 +1,393 @@
+//! Content-addressed album snapshots.
+//!
+//! A [`ContentStore`] keeps every downloaded asset exactly once, under the
+//! derivative checksum reported by iCloud, and records each freeze of an album
+//! as a [`SnapshotManifest`] mapping photo GUIDs to checksums. Freezing the same
+//! album repeatedly therefore only transfers assets that were not seen before,
+//! while every manifest still describes a complete historical state.
+//! [`restore_snapshot`] turns a manifest back into a browsable album directory.
+//!
+//! Layout of a store:
+//!
+//! ```text
+//! <root>/objects/<first two checksum chars>/<checksum>
+//! <root>/manifests/<created_at>-<stream ctag>.json
+//! ```
+
+use crate::download::move_file;
+use crate::integrity::{self, IntegrityDigest, Sha256Hasher};
+use crate::models::{ICloudResponse, StreamCtag};
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncReadExt;
+
+/// One photo of a snapshot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// GUID of the photo
+    pub photo_guid: String,
+    /// Key of the derivative that was stored
+    pub derivative_key: String,
+    /// Checksum of the derivative, which is also its key in the store
+    pub checksum: String,
+    /// File extension (with leading dot) detected from the content
+    pub extension: String,
+    /// Caption of the photo at snapshot time
+    #[serde(default)]
+    pub caption: Option<String>,
+    /// Creation date of the photo
+    #[serde(default)]
+    pub date_created: Option<String>,
+    /// Size of the stored object in bytes
+    pub file_size: u64,
+    /// Locally computed digest of the stored object
+    ///
+    /// Unlike `checksum`, which is an opaque identifier assigned by Apple, this
+    /// can be recomputed from the object to detect corruption.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<IntegrityDigest>,
+}
+
+/// Description of an album at one point in time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    /// Time the snapshot was taken, in seconds since the Unix epoch
+    pub created_at: u64,
+    /// Name of the album
+    pub stream_name: String,
+    /// `streamCtag` of the album at snapshot time
+    pub stream_ctag: StreamCtag,
+    /// The photos of the album, in album order
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// Counts of what a freeze did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FreezeReport {
+    /// Assets transferred and added to the store
+    pub downloaded: usize,
+    /// Assets already present in the store
+    pub reused: usize,
+    /// Photos skipped because no downloadable derivative was available
+    pub skipped: Vec<String>,
+}
+
+/// Result of [`freeze_album`]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct FrozenAlbum {
+    /// The manifest written to the store
+    pub manifest: SnapshotManifest,
+    /// What the freeze did
+    pub report: FreezeReport,
+}
+
+/// A directory storing assets by checksum, together with snapshot manifests
+#[derive(Debug, Clone)]
+pub struct ContentStore {
+    root: PathBuf,
+}
+
+/// Makes a checksum safe to use as a file name
+fn object_name(checksum: &str) -> String {
+    checksum
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+impl ContentStore {
+    /// Open (or lazily create) a store rooted at `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Returns the root directory of the store
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the path at which the object with `checksum` is stored
+    pub fn object_path(&self, checksum: &str) -> PathBuf {
+        let name = object_name(checksum);
+        let prefix: String = name.chars().take(2).collect();
+        self.root.join("objects").join(prefix).join(name)
+    }
+
+    /// Returns true if an object with `checksum` is stored
+    pub async fn contains(&self, checksum: &str) -> bool {
+        tokio::fs::metadata(self.object_path(checksum))
+            .await
+            .map(|m| m.is_file())
+            .unwrap_or(false)
+    }
+
+    /// Stores content under `checksum`, replacing the object atomically
+    pub async fn put(&self, checksum: &str, content: &[u8]) -> io::Result<PathBuf> {
+        let path = self.object_path(checksum);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut partial_name = path.file_name().unwrap_or_default().to_os_string();
+        partial_name.push(".part");
+        let partial = path.with_file_name(partial_name);
+
+        tokio::fs::write(&partial, content).await?;
+        move_file(&partial, &path).await?;
+        Ok(path)
+    }
+
+    /// Writes a manifest into the store
+    ///
+    /// # Returns
+    ///
+    /// The path of the written manifest file
+    pub async fn write_manifest(&self, manifest: &SnapshotManifest) -> io::Result<PathBuf> {
+        let dir = self.root.join("manifests");
+        tokio::fs::create_dir_all(&dir).await?;
+        let path = dir.join(format!(
+            "{}-{}.json",
+            manifest.created_at,
+            object_name(manifest.stream_ctag.as_str())
+        ));
+        let json = serde_json::to_vec_pretty(manifest)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        tokio::fs::write(&path, json).await?;
+        Ok(path)
+    }
+
+    /// Lists the manifest files in the store, oldest first
+    pub async fn list_manifests(&self) -> io::Result<Vec<PathBuf>> {
+        let dir = self.root.join("manifests");
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut manifests = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                manifests.push(path);
+            }
+        }
+        // Names start with the creation timestamp, so a numeric-aware sort is chronological
+        manifests.sort_by_key(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let timestamp = name
+                .split('-')
+                .next()
+                .and_then(|t| t.parse::<u64>().ok())
+                .unwrap_or(0);
+            (timestamp, name.into_owned())
+        });
+        Ok(manifests)
+    }
+
+    /// Reads a manifest file
+    pub async fn read_manifest(path: impl AsRef<Path>) -> io::Result<SnapshotManifest> {
+        let bytes = tokio::fs::read(path).await?;
+        serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Detects the extension of a stored object from its leading bytes
+    async fn object_extension(&self, checksum: &str) -> io::Result<String> {
+        let mut file = tokio::fs::File::open(self.object_path(checksum)).await?;
+        let mut buffer = [0u8; 16];
+        let mut filled = 0;
+        while filled < buffer.len() {
+            let read = file.read(&mut buffer[filled..]).await?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+        Ok(utils::get_extension_for_content(&buffer[..filled], None))
+    }
+}
+
+/// Freezes the current state of an album into a content-addressed store
+///
+/// For each photo the best derivative is selected (see
+/// [`utils::select_best_derivative`]); it is downloaded only if the store does
+/// not already hold an object with the same checksum. A manifest describing the
+/// album is then written to the store.
+///
+/// # Arguments
+///
+/// * `response` - The album, with derivative URLs populated
+/// * `store` - The store to add assets and the manifest to
+///
+/// # Returns
+///
+/// The written manifest and a report of downloaded and reused assets
+pub async fn freeze_album(
+    response: &ICloudResponse,
+    store: &ContentStore,
+) -> Result<FrozenAlbum, Box<dyn std::error::Error>> {
+    let client = reqwest::Client::new();
+    let mut report = FreezeReport::default();
+    let mut entries = Vec::new();
+
+    for photo in &response.photos {
+        let Some((key, derivative, url)) = utils::select_best_derivative(&photo.derivatives) else {
+            report.skipped.push(photo.photo_guid.clone());
+            continue;
+        };
+        if derivative.checksum.is_empty() {
+            report.skipped.push(photo.photo_guid.clone());
+            continue;
+        }
+
+        let (extension, file_size, digest) = if store.contains(&derivative.checksum).await {
+            report.reused += 1;
+            let object = store.object_path(&derivative.checksum);
+            let size = tokio::fs::metadata(&object).await?.len();
+            let digest =
+                tokio::task::spawn_blocking(move || integrity::hash_file(&Sha256Hasher, &object))
+                    .await??;
+            (
+                store.object_extension(&derivative.checksum).await?,
+                size,
+                digest,
+            )
+        } else {
+            let content = crate::har::send(client.get(&url))
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?;
+            store.put(&derivative.checksum, &content).await?;
+            report.downloaded += 1;
+            (
+                utils::get_extension_for_content(&content, None),
+                content.len() as u64,
+                integrity::hash_bytes(&Sha256Hasher, &content),
+            )
+        };
+
+        entries.push(ManifestEntry {
+            photo_guid: photo.photo_guid.clone(),
+            derivative_key: key,
+            checksum: derivative.checksum.clone(),
+            extension,
+            caption: photo.caption.clone(),
+            date_created: photo.date_created.clone(),
+            file_size,
+            integrity: Some(digest),
+        });
+    }
+
+    let manifest = SnapshotManifest {
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        stream_name: response.metadata.stream_name.clone(),
+        stream_ctag: response.metadata.stream_ctag.clone(),
+        entries,
+    };
+    store.write_manifest(&manifest).await?;
+
+    Ok(FrozenAlbum { manifest, report })
+}
+
+/// Options controlling [`restore_snapshot`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RestoreOptions {
+    /// Prefix filenames with the photo's 1-based position in the album
+    pub numbered: bool,
+    /// Hard-link files to the store instead of copying them
+    ///
+    /// Falls back to copying when linking fails (e.g. across filesystems).
+    pub hard_links: bool,
+}
+
+impl RestoreOptions {
+    /// Create options copying files under their plain names
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether filenames are prefixed with the photo's position
+    #[must_use]
+    pub fn with_numbered(mut self, numbered: bool) -> Self {
+        self.numbered = numbered;
+        self
+    }
+
+    /// Sets whether files are hard-linked to the store
+    #[must_use]
+    pub fn with_hard_links(mut self, hard_links: bool) -> Self {
+        self.hard_links = hard_links;
+        self
+    }
+}
+
+/// Reconstructs a human-readable album directory from a manifest
+///
+/// Each entry is written to `output_dir` under the same name
+/// [`crate::download_photo`] would have used (photo GUID plus sanitized
+/// caption) and the extension recorded in the manifest, so the archive is
+/// usable without any other tool state.
+///
+/// # Arguments
+///
+/// * `manifest` - The snapshot to restore
+/// * `store` - The store holding the snapshot's objects
+/// * `output_dir` - Directory to write the album into
+/// * `options` - Naming and linking options
+///
+/// # Returns
+///
+/// The paths of the restored files, in manifest order
+pub async fn restore_snapshot(
+    manifest: &SnapshotManifest,
+    store: &ContentStore,
+    output_dir: impl AsRef<Path>,
+    options: &RestoreOptions,
+) -> io::Result<Vec<PathBuf>> {
+    let output_dir = output_dir.as_ref();
+    tokio::fs::create_dir_all(output_dir).await?;
+
+    let mut restored = Vec::with_capacity(manifest.entries.len());
+    for (index, entry) in manifest.entries.iter().enumerate() {
+        let object = store.object_path(&entry.checksum);
+        if tokio::fs::metadata(&object).await.is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Object {} for photo {} is missing from the store",
+                    entry.checksum, entry.photo_guid
+                ),
+            ));
+        }
+
+        let photo = crate::models::Image {
+            photo_guid: entry.photo_guid.clone(),
+            caption: entry.caption.clone(),
+            ..Default::default()
+        };
+        let base_filename =
+            utils::photo_base_filename(&photo, options.numbered.then_some(index), None);
+        let target = output_dir.join(format!("{}{}", base_filename, entry.extension));
+
+        // Replace files left by an earlier restore
+        if tokio::fs::metadata(&target).await.is_ok() {
+            tokio::fs::remove_file(&target).await?;
+        }
+        let linked = options.hard_links && tokio::fs::hard_link(&object, &target).await.is_ok();
+        if !linked {
+            tokio::fs::copy(&object, &target).await?;
+        }
+
+        restored.push(target);
+    }
+
+    Ok(restored)
+}
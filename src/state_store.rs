@@ -0,0 +1,298 @@
+//! Pluggable persistence for sync state and other saved data.
+//!
+//! [`crate::sync::SyncState::save`] and the cookie jar of the `cookies`
+//! feature write loose files, which doesn't suit daemons that keep their
+//! state in a database. A [`StateStore`] is a small key-value interface they can
+//! implement instead; [`crate::sync::SyncState::save_to`] and
+//! [`crate::sync::SyncState::load_from`] keep the state under a key of it.
+//!
+//! Three stores come with the crate:
+//!
+//! * [`JsonFileStore`] keeps one file per key in a directory
+//! * `SqliteStore` keeps the values in a table of a SQLite database (with the
+//!   `sqlite` feature)
+//! * `SledStore` keeps them in a sled tree (with the `sled` feature)
+//!
+//! Values are opaque bytes; the ones written by this crate are JSON documents.
+//! Every method performs blocking I/O; from async code, call them through
+//! `tokio::task::spawn_blocking`.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A key-value store for persisted state
+///
+/// Implementations must be safe to share between threads. A `put` replaces
+/// the value under the key as a whole: readers see either the old or the new
+/// value, never a mix.
+pub trait StateStore: Send + Sync {
+    /// Returns the value stored under `key`, or `None` if there is none
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Stores `value` under `key`, replacing any previous value
+    fn put(&self, key: &str, value: &[u8]) -> io::Result<()>;
+
+    /// Returns the keys starting with `prefix`, in lexicographic order
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>>;
+}
+
+/// Reads the JSON value stored under `key`
+///
+/// # Returns
+///
+/// The deserialized value, `None` if the key has no value, or an
+/// `InvalidData` error if the value isn't valid JSON for `T`
+pub fn get_json<T: DeserializeOwned>(
+    store: &(impl StateStore + ?Sized),
+    key: &str,
+) -> io::Result<Option<T>> {
+    match store.get(key)? {
+        Some(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        None => Ok(None),
+    }
+}
+
+/// Stores `value` as JSON under `key`
+pub fn put_json<T: Serialize + ?Sized>(
+    store: &(impl StateStore + ?Sized),
+    key: &str,
+    value: &T,
+) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(value)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    store.put(key, &json)
+}
+
+/// A store keeping each value in a `<key>.json` file of a directory
+///
+/// Keys are percent-encoded into file names, so any key is safe to use and
+/// `/` in keys doesn't create subdirectories. Values are written to a
+/// temporary file that is then renamed into place.
+#[derive(Debug, Clone)]
+pub struct JsonFileStore {
+    dir: PathBuf,
+}
+
+/// Extension of the files of a [`JsonFileStore`]
+const FILE_EXTENSION: &str = ".json";
+
+impl JsonFileStore {
+    /// Create a store in `dir`, which is created on the first `put`
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Directory the files are kept in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path of the file holding the value of `key`
+    pub fn path_of(&self, key: &str) -> PathBuf {
+        let encoded: String = url::form_urlencoded::byte_serialize(key.as_bytes()).collect();
+        self.dir.join(format!("{}{}", encoded, FILE_EXTENSION))
+    }
+}
+
+impl StateStore for JsonFileStore {
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.path_of(key)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.path_of(key);
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+        std::fs::write(&temp_path, value)?;
+        std::fs::rename(&temp_path, &path)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut keys = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            let Some(encoded) = name.to_str().and_then(|n| n.strip_suffix(FILE_EXTENSION)) else {
+                continue;
+            };
+            // Encoded keys contain no `&` or `=`, so they parse as a single name
+            let key: String = url::form_urlencoded::parse(encoded.as_bytes())
+                .map(|(name, _)| name.into_owned())
+                .collect();
+            if key.starts_with(prefix) {
+                keys.push(key);
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+/// A store keeping the values in a table of a SQLite database
+///
+/// The table has a `key TEXT PRIMARY KEY` and a `value BLOB` column and is
+/// created if it doesn't exist, so a daemon can share its own database.
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct SqliteStore {
+    connection: std::sync::Mutex<rusqlite::Connection>,
+    table: String,
+}
+
+/// Table used by [`SqliteStore::open`]
+#[cfg(feature = "sqlite")]
+pub const DEFAULT_SQLITE_TABLE: &str = "icloud_album_state";
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    /// Opens the database at `path` and keeps the values in [`DEFAULT_SQLITE_TABLE`]
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let connection = rusqlite::Connection::open(path).map_err(io::Error::other)?;
+        Self::with_connection(connection, DEFAULT_SQLITE_TABLE)
+    }
+
+    /// Keeps the values in `table` of an open connection
+    ///
+    /// # Arguments
+    ///
+    /// * `connection` - A connection to the database
+    /// * `table` - Name of the table, made of ASCII letters, digits and `_`
+    pub fn with_connection(connection: rusqlite::Connection, table: &str) -> io::Result<Self> {
+        let valid = !table.is_empty()
+            && !table.starts_with(|c: char| c.is_ascii_digit())
+            && table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid table name: {}", table),
+            ));
+        }
+        connection
+            .execute(
+                &format!(
+                    "CREATE TABLE IF NOT EXISTS {} (key TEXT PRIMARY KEY, value BLOB NOT NULL)",
+                    table
+                ),
+                [],
+            )
+            .map_err(io::Error::other)?;
+        Ok(Self {
+            connection: std::sync::Mutex::new(connection),
+            table: table.to_string(),
+        })
+    }
+
+    /// Locks the connection, recovering it if a holder panicked
+    fn lock(&self) -> std::sync::MutexGuard<'_, rusqlite::Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl StateStore for SqliteStore {
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        use rusqlite::OptionalExtension;
+        self.lock()
+            .query_row(
+                &format!("SELECT value FROM {} WHERE key = ?1", self.table),
+                [key],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(io::Error::other)
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.lock()
+            .execute(
+                &format!(
+                    "INSERT OR REPLACE INTO {} (key, value) VALUES (?1, ?2)",
+                    self.table
+                ),
+                rusqlite::params![key, value],
+            )
+            .map(|_| ())
+            .map_err(io::Error::other)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let connection = self.lock();
+        // substr() rather than LIKE, which would need its wildcards escaped
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT key FROM {} WHERE substr(key, 1, length(?1)) = ?1 ORDER BY key",
+                self.table
+            ))
+            .map_err(io::Error::other)?;
+        let keys = statement
+            .query_map([prefix], |row| row.get(0))
+            .and_then(|rows| rows.collect::<Result<Vec<String>, _>>())
+            .map_err(io::Error::other)?;
+        Ok(keys)
+    }
+}
+
+/// A store keeping the values in a sled tree
+///
+/// Every `put` is flushed to disk before it returns.
+#[cfg(feature = "sled")]
+#[derive(Debug, Clone)]
+pub struct SledStore {
+    tree: sled::Tree,
+}
+
+#[cfg(feature = "sled")]
+impl SledStore {
+    /// Opens the sled database at `path` and keeps the values in its default tree
+    ///
+    /// sled releases its lock on the database in the background once the
+    /// store is dropped, so opening the same path again right away can fail.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let db = sled::open(path)?;
+        Ok(Self::with_tree((*db).clone()))
+    }
+
+    /// Keeps the values in a tree of a database opened by the caller
+    pub fn with_tree(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+}
+
+#[cfg(feature = "sled")]
+impl StateStore for SledStore {
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.tree.get(key)?.map(|value| value.to_vec()))
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.tree.insert(key, value)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        self.tree
+            .scan_prefix(prefix)
+            .keys()
+            .map(|key| {
+                let key = key?;
+                String::from_utf8(key.to_vec())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            })
+            .collect()
+    }
+}
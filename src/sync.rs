@@ -0,0 +1,811 @@
+//! Persistent state for incremental album syncs.
+//!
+//! [`SyncState`] records what a previous run wrote to disk: the album's
+//! `streamCtag` and, for every photo GUID, the final path of the downloaded
+//! file together with the HTTP validators it was served with. Keeping the
+//! GUID-to-path mapping lets a later run detect that a photo only needs to be
+//! renamed (e.g. because the filename template changed) rather than downloaded
+//! again.
+//!
+//! Mirrors on small devices can be kept within a [`SizeBudget`] with
+//! [`SyncState::enforce_size_budget`], and limited to recent photos with
+//! [`SyncState::enforce_retention`]. Evicted files are deleted and recorded
+//! in [`SyncState::evictions`], so later runs don't download them again.
+//!
+//! Files of photos removed from the album are not deleted right away:
+//! [`SyncState::quarantine_removed`] moves them into a [`TRASH_DIR`], from
+//! where [`SyncState::reconcile`] restores them if the photos come back, and
+//! [`SyncState::purge_quarantine`] deletes them once they have been there
+//! long enough.
+//!
+//! [`SyncState::record_run`] appends a [`RunSnapshot`] of the album at the
+//! end of each run to [`SyncState::history`], so dashboards can show how an
+//! album grew over time.
+//!
+//! Curators can attach local tags to photos with [`SyncState::tag`] and
+//! download only the tagged ones through [`SyncState::filter_tagged`] or
+//! [`SyncState::tag_filter`].
+//!
+//! Besides JSON files, state can be kept in any [`StateStore`], such as a
+//! daemon's own database, with [`SyncState::save_to`] and
+//! [`SyncState::load_from`].
+//!
+//! Loading, saving and the file moves above use synchronous I/O; async
+//! applications call them between runs or on the blocking pool.
+
+use crate::integrity::IntegrityDigest;
+use crate::logging;
+use crate::models::{HttpValidators, Image, StreamCtag};
+use crate::state_store::{self, StateStore};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Record of a photo written to disk by a previous run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncedFile {
+    /// Path of the downloaded file
+    pub path: PathBuf,
+    /// Apple checksum of the derivative that was downloaded
+    #[serde(default)]
+    pub checksum: Option<String>,
+    /// Validators returned with the download, for conditional re-downloads
+    #[serde(default)]
+    pub validators: HttpValidators,
+    /// Locally computed digest of the file, for later verification
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<IntegrityDigest>,
+    /// When the photo was taken, as a Unix timestamp, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taken_at: Option<i64>,
+}
+
+impl SyncedFile {
+    /// Create a record of a photo downloaded to `path`
+    pub fn for_photo(photo: &Image, path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            taken_at: photo.timestamp_taken(),
+            ..Self::default()
+        }
+    }
+}
+
+/// Directory, relative to the mirror, that files of removed photos are moved to
+pub const TRASH_DIR: &str = ".trash";
+
+/// A file of a removed photo, kept in the trash until purged
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantinedFile {
+    /// The record of the file before it was moved
+    pub file: SyncedFile,
+    /// Path of the file in the trash
+    pub trash_path: PathBuf,
+    /// When the file was moved to the trash, as a Unix timestamp
+    pub quarantined_at: u64,
+}
+
+/// Returns the current Unix timestamp
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Why a file was evicted from a mirror
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionReason {
+    /// The mirror exceeded its [`SizeBudget`]
+    SizeBudget,
+    /// The photo was taken before the [`Retention`] window
+    Retention,
+}
+
+/// Record of a file deleted to keep a mirror within its limits
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Eviction {
+    /// Path the file was stored at
+    pub path: PathBuf,
+    /// Size of the deleted file in bytes
+    pub size: u64,
+    /// Why the file was evicted
+    pub reason: EvictionReason,
+    /// When the file was evicted, as a Unix timestamp
+    pub evicted_at: u64,
+}
+
+/// Priority function of [`EvictionPolicy::LowestPriority`]
+pub type PriorityFn = Arc<dyn Fn(&str, &SyncedFile) -> i64 + Send + Sync>;
+
+/// Order in which files are evicted
+#[derive(Clone, Default)]
+pub enum EvictionPolicy {
+    /// Photos taken longest ago first; photos without a known date go last
+    #[default]
+    OldestFirst,
+    /// Photos with the lowest priority first, as computed from the GUID and record
+    LowestPriority(PriorityFn),
+}
+
+impl fmt::Debug for EvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvictionPolicy::OldestFirst => f.write_str("OldestFirst"),
+            EvictionPolicy::LowestPriority(_) => f.write_str("LowestPriority(..)"),
+        }
+    }
+}
+
+/// How long photos are kept in a mirror, counted from when they were taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    /// Photos taken longer ago than this are evicted
+    pub max_age: Duration,
+}
+
+impl Retention {
+    /// Keep photos taken within the last `days` days
+    pub fn days(days: u64) -> Self {
+        Self {
+            max_age: Duration::from_secs(days * 24 * 60 * 60),
+        }
+    }
+
+    /// Keep photos taken within the last `months` months, counted as 30 days each
+    pub fn months(months: u64) -> Self {
+        Self::days(months * 30)
+    }
+
+    /// Returns the Unix timestamp before which photos are evicted
+    pub fn cutoff(&self, now: SystemTime) -> i64 {
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        now.saturating_sub(self.max_age.as_secs() as i64)
+    }
+}
+
+/// Maximum size of a mirror on disk
+#[derive(Debug, Clone)]
+pub struct SizeBudget {
+    /// Maximum total size of the recorded files in bytes
+    pub max_bytes: u64,
+    /// Which files are deleted first when the budget is exceeded
+    pub policy: EvictionPolicy,
+}
+
+impl SizeBudget {
+    /// Create a budget evicting the oldest photos first
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            policy: EvictionPolicy::default(),
+        }
+    }
+
+    /// Sets which files are deleted first
+    #[must_use]
+    pub fn with_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+}
+
+/// What needs to happen for a photo to end up at its desired path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathPlan {
+    /// The photo is already stored at the desired path
+    UpToDate(PathBuf),
+    /// The photo is stored elsewhere and only needs to be renamed
+    Rename {
+        /// Current path of the file
+        from: PathBuf,
+        /// Desired path of the file
+        to: PathBuf,
+    },
+    /// The photo is unknown or its file is missing and must be downloaded
+    Download,
+    /// The photo's file was evicted (see [`SyncState::evictions`]) and is not downloaded again
+    Evicted,
+}
+
+/// Number of run snapshots kept in [`SyncState::history`]; older ones are dropped
+pub const HISTORY_LIMIT: usize = 1000;
+
+/// Summary of an album and its mirror at the end of a sync run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunSnapshot {
+    /// When the run finished, as a Unix timestamp
+    pub finished_at: u64,
+    /// Number of photos in the album
+    pub photo_count: usize,
+    /// `streamCtag` of the album
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_ctag: Option<StreamCtag>,
+    /// Number of files in the mirror
+    pub file_count: usize,
+    /// Total size of the files in the mirror, in bytes
+    pub bytes: u64,
+}
+
+/// A chunk of a chunked download that completed, see [`crate::session`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkCheckpoint {
+    /// Position of the chunk, starting at 0
+    pub index: usize,
+    /// GUID of the first photo of the chunk
+    pub first_guid: String,
+    /// GUID of the last photo of the chunk
+    pub last_guid: String,
+    /// Number of photos in the chunk
+    pub photo_count: usize,
+    /// When the chunk completed, as a Unix timestamp
+    pub completed_at: u64,
+}
+
+/// Progress of an interrupted chunked download
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkProgress {
+    /// Number of photos per chunk
+    pub chunk_size: usize,
+    /// Chunks that completed, in the order they did
+    pub completed: Vec<ChunkCheckpoint>,
+}
+
+impl ChunkProgress {
+    /// Whether a chunk with these boundaries already completed
+    pub fn is_completed(&self, index: usize, chunk: &[Image]) -> bool {
+        let (Some(first), Some(last)) = (chunk.first(), chunk.last()) else {
+            return false;
+        };
+        self.completed.iter().any(|checkpoint| {
+            checkpoint.index == index
+                && checkpoint.photo_count == chunk.len()
+                && checkpoint.first_guid == first.photo_guid
+                && checkpoint.last_guid == last.photo_guid
+        })
+    }
+
+    /// Records that a chunk completed
+    pub fn complete(&mut self, index: usize, chunk: &[Image]) {
+        self.completed
+            .retain(|checkpoint| checkpoint.index != index);
+        self.completed.push(ChunkCheckpoint {
+            index,
+            first_guid: chunk
+                .first()
+                .map(|photo| photo.photo_guid.clone())
+                .unwrap_or_default(),
+            last_guid: chunk
+                .last()
+                .map(|photo| photo.photo_guid.clone())
+                .unwrap_or_default(),
+            photo_count: chunk.len(),
+            completed_at: now_secs(),
+        });
+    }
+}
+
+/// State persisted between sync runs of one album
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncState {
+    /// `streamCtag` of the album at the end of the last run
+    #[serde(default)]
+    pub stream_ctag: Option<StreamCtag>,
+    /// Downloaded files keyed by photo GUID
+    #[serde(default)]
+    pub files: BTreeMap<String, SyncedFile>,
+    /// Files deleted to keep the mirror within its limits, keyed by photo GUID
+    ///
+    /// Removing an entry lets the photo be downloaded again.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub evictions: BTreeMap<String, Eviction>,
+    /// Files of photos removed from the album, keyed by photo GUID
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub quarantine: BTreeMap<String, QuarantinedFile>,
+    /// Snapshots of past runs, oldest first, see [`SyncState::record_run`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<RunSnapshot>,
+    /// Local tags keyed by photo GUID, see [`SyncState::tag`]
+    ///
+    /// Tags are kept apart from the files, so they survive re-downloads,
+    /// evictions and photos leaving and rejoining the album.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, BTreeSet<String>>,
+    /// Chunks of an unfinished chunked download, see [`crate::session`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_progress: Option<ChunkProgress>,
+}
+
+impl SyncState {
+    /// Create an empty state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads state from a JSON file, returning an empty state if it does not exist
+    pub async fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        match tokio::fs::read(path.as_ref()).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Saves state as JSON, replacing the file atomically
+    ///
+    /// The state is written to a sibling temporary file which is then renamed
+    /// over `path`, so an interrupted save never leaves a truncated state file.
+    pub async fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+
+        tokio::fs::write(&temp_path, json).await?;
+        tokio::fs::rename(&temp_path, path).await
+    }
+
+    /// Loads state kept under `key` of a [`StateStore`]
+    ///
+    /// Returns an empty state if the key has no value.
+    pub fn load_from(store: &(impl StateStore + ?Sized), key: &str) -> io::Result<Self> {
+        Ok(state_store::get_json(store, key)?.unwrap_or_default())
+    }
+
+    /// Saves state as JSON under `key` of a [`StateStore`]
+    pub fn save_to(&self, store: &(impl StateStore + ?Sized), key: &str) -> io::Result<()> {
+        state_store::put_json(store, key, self)
+    }
+
+    /// Records the file a photo was downloaded to
+    pub fn record(&mut self, photo_guid: &str, file: SyncedFile) {
+        self.files.insert(photo_guid.to_string(), file);
+    }
+
+    /// Appends a snapshot of the album to [`SyncState::history`]
+    ///
+    /// Call this at the end of a run, after [`SyncState::stream_ctag`] and the
+    /// files have been updated. File sizes are read from disk; records of
+    /// missing files don't count. At most [`HISTORY_LIMIT`] snapshots are
+    /// kept. Save the state afterwards to keep the snapshot.
+    ///
+    /// # Arguments
+    ///
+    /// * `photo_count` - Number of photos in the album as fetched by the run
+    ///
+    /// # Returns
+    ///
+    /// The recorded snapshot
+    pub async fn record_run(&mut self, photo_count: usize) -> &RunSnapshot {
+        let mut file_count = 0;
+        let mut bytes = 0;
+        for file in self.files.values() {
+            if let Ok(metadata) = tokio::fs::metadata(&file.path).await {
+                file_count += 1;
+                bytes += metadata.len();
+            }
+        }
+        if self.history.len() >= HISTORY_LIMIT {
+            let excess = self.history.len() + 1 - HISTORY_LIMIT;
+            self.history.drain(..excess);
+        }
+        self.history.push(RunSnapshot {
+            finished_at: now_secs(),
+            photo_count,
+            stream_ctag: self.stream_ctag.clone(),
+            file_count,
+            bytes,
+        });
+        // Just pushed
+        self.history.last().expect("history is not empty")
+    }
+
+    /// Returns the snapshots of the runs that finished at or after `since` (a Unix timestamp)
+    pub fn history_since(&self, since: u64) -> &[RunSnapshot] {
+        let start = self
+            .history
+            .partition_point(|snapshot| snapshot.finished_at < since);
+        &self.history[start..]
+    }
+
+    /// Attaches a local tag to a photo
+    ///
+    /// Shared albums have no tags; these only live in the state. Surrounding
+    /// whitespace is trimmed and tags are case-sensitive.
+    ///
+    /// # Returns
+    ///
+    /// Whether the tag was added; `false` if the photo already had it or the
+    /// tag is empty
+    pub fn tag(&mut self, photo_guid: &str, tag: &str) -> bool {
+        let tag = tag.trim();
+        if tag.is_empty() {
+            return false;
+        }
+        self.tags
+            .entry(photo_guid.to_string())
+            .or_default()
+            .insert(tag.to_string())
+    }
+
+    /// Removes a local tag from a photo, returning whether it had it
+    pub fn untag(&mut self, photo_guid: &str, tag: &str) -> bool {
+        let Some(tags) = self.tags.get_mut(photo_guid) else {
+            return false;
+        };
+        let removed = tags.remove(tag.trim());
+        if tags.is_empty() {
+            self.tags.remove(photo_guid);
+        }
+        removed
+    }
+
+    /// Returns the local tags of a photo, in order
+    pub fn tags_of(&self, photo_guid: &str) -> impl Iterator<Item = &str> {
+        self.tags
+            .get(photo_guid)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+    }
+
+    /// Returns the GUIDs of the photos with a tag
+    pub fn tagged(&self, tag: &str) -> BTreeSet<String> {
+        let tag = tag.trim();
+        self.tags
+            .iter()
+            .filter(|(_, tags)| tags.contains(tag))
+            .map(|(guid, _)| guid.clone())
+            .collect()
+    }
+
+    /// Returns the photos with a tag, e.g. to download only those
+    ///
+    /// # Arguments
+    ///
+    /// * `photos` - Photos of the album
+    /// * `tag` - The tag to select
+    ///
+    /// # Returns
+    ///
+    /// Copies of the tagged photos, in their original order
+    pub fn filter_tagged(&self, photos: &[Image], tag: &str) -> Vec<Image> {
+        let tagged = self.tagged(tag);
+        photos
+            .iter()
+            .filter(|photo| tagged.contains(&photo.photo_guid))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns a filter keeping the photos with a tag
+    ///
+    /// The filter is a snapshot of the current tags and can be installed with
+    /// [`crate::fetch::FetchOptions::with_filter`].
+    pub fn tag_filter(&self, tag: &str) -> impl Fn(&Image) -> bool + Send + Sync + 'static {
+        let tagged = self.tagged(tag);
+        move |photo| tagged.contains(&photo.photo_guid)
+    }
+
+    /// Returns the recorded file of a photo, if any
+    pub fn file(&self, photo_guid: &str) -> Option<&SyncedFile> {
+        self.files.get(photo_guid)
+    }
+
+    /// Decides how a photo gets to its desired location
+    ///
+    /// The desired path is `output_dir/base_filename` plus the extension of the
+    /// previously recorded file, since the extension is derived from content.
+    ///
+    /// # Arguments
+    ///
+    /// * `photo_guid` - GUID of the photo
+    /// * `output_dir` - Directory the photo should be stored in
+    /// * `base_filename` - Desired filename without extension (see [`crate::utils::photo_base_filename`])
+    ///
+    /// # Returns
+    ///
+    /// The [`PathPlan`] for the photo
+    pub fn plan(&self, photo_guid: &str, output_dir: &Path, base_filename: &str) -> PathPlan {
+        if self.evictions.contains_key(photo_guid) {
+            return PathPlan::Evicted;
+        }
+        let (recorded, path) = match (self.files.get(photo_guid), self.quarantine.get(photo_guid)) {
+            (Some(recorded), _) => (recorded, &recorded.path),
+            (None, Some(quarantined)) => (&quarantined.file, &quarantined.trash_path),
+            (None, None) => return PathPlan::Download,
+        };
+        if !path.is_file() {
+            return PathPlan::Download;
+        }
+
+        let mut filename = base_filename.to_string();
+        if let Some(extension) = recorded.path.extension() {
+            filename.push('.');
+            filename.push_str(&extension.to_string_lossy());
+        }
+        let desired = output_dir.join(filename);
+
+        if *path == desired {
+            PathPlan::UpToDate(desired)
+        } else {
+            PathPlan::Rename {
+                from: path.clone(),
+                to: desired,
+            }
+        }
+    }
+
+    /// Plans a photo's location and performs any needed rename
+    ///
+    /// On a rename, the target directory is created if needed and the recorded
+    /// path is updated. Photos in the quarantine are renamed back out of the
+    /// trash. A rename is never allowed to overwrite an existing file;
+    /// in that case the photo is left in place and `Download` is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `photo_guid` - GUID of the photo
+    /// * `output_dir` - Directory the photo should be stored in
+    /// * `base_filename` - Desired filename without extension
+    ///
+    /// # Returns
+    ///
+    /// The plan that was carried out
+    pub async fn reconcile(
+        &mut self,
+        photo_guid: &str,
+        output_dir: &Path,
+        base_filename: &str,
+    ) -> io::Result<PathPlan> {
+        let plan = self.plan(photo_guid, output_dir, base_filename);
+        if let PathPlan::Rename { from, to } = &plan {
+            if tokio::fs::metadata(to).await.is_ok() {
+                logging::log_warn!(
+                    logging::SYNC,
+                    "Not renaming {} to {}: target already exists",
+                    from.display(),
+                    to.display()
+                );
+                return Ok(PathPlan::Download);
+            }
+            if let Some(parent) = to.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::rename(from, to).await?;
+            // A photo that came back is restored from the trash
+            if let Some(quarantined) = self.quarantine.remove(photo_guid) {
+                self.files.insert(photo_guid.to_string(), quarantined.file);
+            }
+            if let Some(file) = self.files.get_mut(photo_guid) {
+                file.path = to.clone();
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Deletes the files of photos and records them as evicted
+    ///
+    /// The state is updated after every deleted file, so it stays accurate
+    /// if a deletion fails part way through.
+    async fn evict(
+        &mut self,
+        photo_guids: Vec<(String, u64)>,
+        reason: EvictionReason,
+    ) -> io::Result<Vec<String>> {
+        let evicted_at = now_secs();
+        let mut evicted = Vec::new();
+        for (photo_guid, size) in photo_guids {
+            let Some(file) = self.files.get(&photo_guid) else {
+                continue;
+            };
+            match tokio::fs::remove_file(&file.path).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            logging::log_at!(
+                logging::SYNC,
+                log::Level::Info,
+                "Evicted {} ({} bytes): {:?}",
+                file.path.display(),
+                size,
+                reason
+            );
+            let file = self.files.remove(&photo_guid).unwrap_or_default();
+            self.evictions.insert(
+                photo_guid.clone(),
+                Eviction {
+                    path: file.path,
+                    size,
+                    reason,
+                    evicted_at,
+                },
+            );
+            evicted.push(photo_guid);
+        }
+        Ok(evicted)
+    }
+
+    /// Deletes files until the recorded files fit within a size budget
+    ///
+    /// File sizes are read from disk; records of missing files don't count.
+    /// Files are deleted in the order of [`SizeBudget::policy`] until the
+    /// total is within [`SizeBudget::max_bytes`]. Save the state afterwards
+    /// to keep the evictions.
+    ///
+    /// # Arguments
+    ///
+    /// * `budget` - The maximum size and eviction order
+    ///
+    /// # Returns
+    ///
+    /// The GUIDs of the evicted photos, in eviction order
+    pub async fn enforce_size_budget(&mut self, budget: &SizeBudget) -> io::Result<Vec<String>> {
+        let mut candidates = Vec::new();
+        let mut total = 0u64;
+        for (photo_guid, file) in &self.files {
+            if let Ok(metadata) = tokio::fs::metadata(&file.path).await {
+                total += metadata.len();
+                candidates.push((photo_guid.clone(), file, metadata.len()));
+            }
+        }
+        if total <= budget.max_bytes {
+            return Ok(Vec::new());
+        }
+
+        // Stable sorts keep GUID order among equals
+        match &budget.policy {
+            EvictionPolicy::OldestFirst => {
+                candidates.sort_by_key(|(_, file, _)| (file.taken_at.is_none(), file.taken_at))
+            }
+            EvictionPolicy::LowestPriority(priority) => {
+                candidates.sort_by_cached_key(|(photo_guid, file, _)| priority(photo_guid, file))
+            }
+        }
+        let mut selected = Vec::new();
+        for (photo_guid, _, size) in candidates {
+            if total <= budget.max_bytes {
+                break;
+            }
+            total -= size;
+            selected.push((photo_guid, size));
+        }
+        self.evict(selected, EvictionReason::SizeBudget).await
+    }
+    /// Deletes the files of photos taken before a retention window
+    ///
+    /// Only photos with a known date (see [`SyncedFile::taken_at`]) are
+    /// evicted; undated photos are always kept. Save the state afterwards to
+    /// keep the evictions.
+    ///
+    /// # Arguments
+    ///
+    /// * `retention` - How long photos are kept
+    ///
+    /// # Returns
+    ///
+    /// The GUIDs of the evicted photos
+    pub async fn enforce_retention(&mut self, retention: &Retention) -> io::Result<Vec<String>> {
+        let cutoff = retention.cutoff(SystemTime::now());
+        let mut expired = Vec::new();
+        for (photo_guid, file) in &self.files {
+            if file.taken_at.is_some_and(|taken_at| taken_at < cutoff) {
+                let size = match tokio::fs::metadata(&file.path).await {
+                    Ok(metadata) => metadata.len(),
+                    Err(_) => 0,
+                };
+                expired.push((photo_guid.clone(), size));
+            }
+        }
+        self.evict(expired, EvictionReason::Retention).await
+    }
+    /// Moves the files of photos no longer in the album to the trash
+    ///
+    /// Nothing is deleted: the files can be restored by [`SyncState::reconcile`]
+    /// until [`SyncState::purge_quarantine`] removes them. Save the state
+    /// afterwards to keep track of the quarantined files.
+    ///
+    /// # Arguments
+    ///
+    /// * `photo_guids` - GUIDs of the photos currently in the album
+    /// * `mirror_dir` - Root of the mirror; the trash is its [`TRASH_DIR`]
+    ///
+    /// # Returns
+    ///
+    /// The GUIDs of the quarantined photos
+    pub async fn quarantine_removed<'a>(
+        &mut self,
+        photo_guids: impl IntoIterator<Item = &'a str>,
+        mirror_dir: &Path,
+    ) -> io::Result<Vec<String>> {
+        let current: std::collections::HashSet<&str> = photo_guids.into_iter().collect();
+        let removed: Vec<String> = self
+            .files
+            .keys()
+            .filter(|photo_guid| !current.contains(photo_guid.as_str()))
+            .cloned()
+            .collect();
+        let trash = mirror_dir.join(TRASH_DIR);
+
+        let mut quarantined = Vec::new();
+        for photo_guid in removed {
+            let Some(file) = self.files.get(&photo_guid) else {
+                continue;
+            };
+            if !file.path.is_file() {
+                // Nothing to keep; forget the record
+                self.files.remove(&photo_guid);
+                continue;
+            }
+            tokio::fs::create_dir_all(&trash).await?;
+            let name = file.path.file_name().unwrap_or_default();
+            let mut trash_path = trash.join(name);
+            if tokio::fs::metadata(&trash_path).await.is_ok() {
+                let mut unique = std::ffi::OsString::from(format!("{}-", photo_guid));
+                unique.push(name);
+                trash_path = trash.join(unique);
+            }
+            crate::download::move_file(&file.path, &trash_path).await?;
+            logging::log_debug!(
+                logging::SYNC,
+                "Moved {} of removed photo {} to the trash",
+                file.path.display(),
+                photo_guid
+            );
+
+            let file = self.files.remove(&photo_guid).unwrap_or_default();
+            self.quarantine.insert(
+                photo_guid.clone(),
+                QuarantinedFile {
+                    file,
+                    trash_path,
+                    quarantined_at: now_secs(),
+                },
+            );
+            quarantined.push(photo_guid);
+        }
+        Ok(quarantined)
+    }
+
+    /// Deletes quarantined files that have been in the trash long enough
+    ///
+    /// # Arguments
+    ///
+    /// * `retention` - How long files stay in the trash; zero purges everything
+    ///
+    /// # Returns
+    ///
+    /// The GUIDs of the purged photos
+    pub async fn purge_quarantine(&mut self, retention: Duration) -> io::Result<Vec<String>> {
+        let cutoff = now_secs().saturating_sub(retention.as_secs());
+        let expired: Vec<String> = self
+            .quarantine
+            .iter()
+            .filter(|(_, quarantined)| retention.is_zero() || quarantined.quarantined_at < cutoff)
+            .map(|(photo_guid, _)| photo_guid.clone())
+            .collect();
+
+        for photo_guid in &expired {
+            if let Some(quarantined) = self.quarantine.get(photo_guid) {
+                match tokio::fs::remove_file(&quarantined.trash_path).await {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+                self.quarantine.remove(photo_guid);
+            }
+        }
+        Ok(expired)
+    }
+}
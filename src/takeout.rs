@@ -0,0 +1,161 @@
+//! Google Takeout compatible metadata files.
+//!
+//! Google Takeout exports every photo with a JSON file next to it, named after
+//! the photo with `.json` appended, and describes each album folder in a
+//! `metadata.json`. Many import tools for other photo managers read these
+//! files, so writing them alongside downloads makes an album archive importable
+//! without new code (see [`crate::download::DownloadOptions::with_takeout_metadata`]).
+
+use crate::models::{parse_timestamp, CalendarDate, Image, Metadata};
+use serde_json::{json, Value};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// File name of the album metadata written by [`write_album_metadata`]
+pub const ALBUM_METADATA_FILE: &str = "metadata.json";
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats Unix seconds the way Takeout does, e.g. `Jul 14, 2023, 6:30:05 PM UTC`
+fn format_time(timestamp: i64) -> String {
+    let date = CalendarDate::from_timestamp(timestamp);
+    let seconds = timestamp.rem_euclid(86_400);
+    let (hour, minute, second) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    let hour12 = match hour % 12 {
+        0 => 12,
+        h => h,
+    };
+    format!(
+        "{} {}, {}, {}:{:02}:{:02} {} UTC",
+        MONTHS[(date.month - 1) as usize],
+        date.day,
+        date.year,
+        hour12,
+        minute,
+        second,
+        if hour < 12 { "AM" } else { "PM" }
+    )
+}
+
+/// Builds a Takeout time object from Unix seconds
+fn time_object(timestamp: i64) -> Value {
+    json!({
+        "timestamp": timestamp.to_string(),
+        "formatted": format_time(timestamp),
+    })
+}
+
+/// Builds the Takeout metadata of a photo
+///
+/// `photoTakenTime` comes from `dateCreated` and `creationTime` (when the photo
+/// was added) from `batchDateCreated`; either is left out if unknown. Like
+/// Takeout, `geoData` is all zeros for photos without a location.
+///
+/// # Arguments
+///
+/// * `photo` - The photo to describe
+/// * `title` - The file name of the downloaded photo
+///
+/// # Returns
+///
+/// The metadata as a JSON object
+pub fn photo_metadata(photo: &Image, title: &str) -> Value {
+    let (latitude, longitude, altitude) = photo
+        .location
+        .as_ref()
+        .map(|l| (l.latitude, l.longitude, l.altitude.unwrap_or(0.0)))
+        .unwrap_or_default();
+    let geo_data = json!({
+        "latitude": latitude,
+        "longitude": longitude,
+        "altitude": altitude,
+        "latitudeSpan": 0.0,
+        "longitudeSpan": 0.0,
+    });
+
+    let mut metadata = json!({
+        "title": title,
+        "description": photo.caption.as_deref().map(str::trim).unwrap_or_default(),
+        "imageViews": "0",
+        "geoData": geo_data,
+        "geoDataExif": geo_data,
+    });
+    if let Some(created) = photo
+        .batch_date_created
+        .as_deref()
+        .and_then(parse_timestamp)
+    {
+        metadata["creationTime"] = time_object(created);
+    }
+    if let Some(taken) = photo.timestamp_taken() {
+        metadata["photoTakenTime"] = time_object(taken);
+    }
+    if let Some(contributor) = &photo.contributor_full_name {
+        metadata["people"] = json!([{ "name": contributor }]);
+    }
+    metadata
+}
+
+/// Returns the path of the metadata file of a downloaded photo (`<file>.json`)
+pub fn metadata_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_os_string();
+    sidecar.push(".json");
+    PathBuf::from(sidecar)
+}
+
+/// Writes the Takeout metadata file next to a downloaded photo
+///
+/// # Arguments
+///
+/// * `path` - The downloaded file
+/// * `photo` - The photo's metadata
+///
+/// # Returns
+///
+/// The path of the written metadata file
+pub async fn write_photo_metadata(path: &Path, photo: &Image) -> io::Result<PathBuf> {
+    let title = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let sidecar = metadata_path(path);
+    let json = serde_json::to_vec_pretty(&photo_metadata(photo, &title))?;
+    tokio::fs::write(&sidecar, json).await?;
+    Ok(sidecar)
+}
+
+/// Writes the Takeout album description (`metadata.json`) into an album folder
+///
+/// # Arguments
+///
+/// * `dir` - The folder holding the album's downloads
+/// * `album` - Metadata of the album
+/// * `photos` - The album's photos, whose earliest upload dates the album
+///
+/// # Returns
+///
+/// The path of the written file
+pub async fn write_album_metadata(
+    dir: &Path,
+    album: &Metadata,
+    photos: &[Image],
+) -> io::Result<PathBuf> {
+    let mut metadata = json!({
+        "title": album.stream_name,
+        "description": "",
+        "access": "protected",
+    });
+    let created = photos
+        .iter()
+        .filter_map(|photo| parse_timestamp(photo.batch_date_created.as_deref()?))
+        .min();
+    if let Some(created) = created {
+        metadata["date"] = time_object(created);
+    }
+
+    let path = dir.join(ALBUM_METADATA_FILE);
+    tokio::fs::write(&path, serde_json::to_vec_pretty(&metadata)?).await?;
+    Ok(path)
+}
@@ -0,0 +1,416 @@
+//! Adaptive concurrency for downloads.
+//!
+//! iCloud's asset servers answer with 429 or 503 when a client downloads too
+//! aggressively, and the rate they accept varies over time. Instead of a fixed
+//! number of parallel downloads, an [`AdaptiveLimit`] adjusts the limit
+//! AIMD-style (additive increase, multiplicative decrease):
+//!
+//! * every response that arrives within the target latency raises the limit by
+//!   `1 / limit`, i.e. by about one slot per round of requests
+//! * a throttled (429/503) or slow response multiplies the limit by the
+//!   back-off factor
+//!
+//! Only one decrease is made per round: responses to requests that started
+//! before the last decrease don't decrease the limit again, so a burst of 429s
+//! from requests already in flight doesn't collapse the limit to the minimum.
+//!
+//! Assets are spread over several CDN hosts (the `url_location` of each
+//! asset), and one of them being unhealthy says little about the others.
+//! [`HostLimits`] therefore keeps a separate limit per host, together with a
+//! circuit breaker that pauses requests to a host after repeated failures.
+
+use crate::logging;
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Settings of an [`AdaptiveLimit`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct AimdConfig {
+    /// Lowest number of concurrent requests
+    pub min: usize,
+    /// Highest number of concurrent requests
+    pub max: usize,
+    /// Number of concurrent requests to start with
+    pub initial: usize,
+    /// Responses slower than this count as a sign of overload
+    pub target_latency: Duration,
+    /// Factor the limit is multiplied with on overload, between 0 and 1
+    pub backoff: f64,
+}
+
+impl Default for AimdConfig {
+    fn default() -> Self {
+        Self {
+            min: 1,
+            max: 16,
+            initial: 4,
+            target_latency: Duration::from_secs(5),
+            backoff: 0.5,
+        }
+    }
+}
+
+/// How many downloads run at the same time
+#[derive(Debug, Clone, PartialEq)]
+pub enum Concurrency {
+    /// A fixed number of concurrent downloads
+    Fixed(usize),
+    /// A number adjusted to the responses of the server
+    Adaptive(AimdConfig),
+}
+
+impl Default for Concurrency {
+    fn default() -> Self {
+        Concurrency::Fixed(1)
+    }
+}
+
+/// What happened to a request made under an [`AdaptiveLimit`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The request succeeded after the given time
+    Success(Duration),
+    /// The server asked the client to slow down (429 or 503)
+    Throttled,
+    /// The host could not be reached or answered with a server error
+    ///
+    /// This leaves the limit unchanged, but counts towards the host's circuit
+    /// breaker in [`HostLimits`].
+    Unavailable,
+    /// The request failed for another reason, which leaves the limit unchanged
+    Failed,
+}
+
+impl Outcome {
+    /// Classifies a response by its status code
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The HTTP status of the response
+    /// * `latency` - Time from sending the request until the response arrived
+    pub fn from_status(status: StatusCode, latency: Duration) -> Self {
+        if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+            Outcome::Throttled
+        } else if status.is_success() {
+            Outcome::Success(latency)
+        } else if status.is_server_error() {
+            Outcome::Unavailable
+        } else {
+            Outcome::Failed
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LimitState {
+    limit: f64,
+    in_flight: usize,
+    last_decrease: Option<Instant>,
+}
+
+/// A concurrency limit that adapts to throttling and latency
+///
+/// The limit is internally synchronized, so it is shared between download
+/// tasks through an [`Arc`]. Tasks take a [`LimitPermit`] with
+/// [`AdaptiveLimit::acquire`] before each request and report the outcome with
+/// [`LimitPermit::finish`].
+#[derive(Debug)]
+pub struct AdaptiveLimit {
+    config: AimdConfig,
+    state: Mutex<LimitState>,
+    notify: Notify,
+}
+
+impl AdaptiveLimit {
+    /// Create a limit with the given settings
+    ///
+    /// `min` is raised to at least 1, `max` to at least `min`, and `initial` is
+    /// clamped between the two.
+    pub fn new(mut config: AimdConfig) -> Self {
+        config.min = config.min.max(1);
+        config.max = config.max.max(config.min);
+        config.initial = config.initial.clamp(config.min, config.max);
+        config.backoff = config.backoff.clamp(0.0, 1.0);
+        Self {
+            state: Mutex::new(LimitState {
+                limit: config.initial as f64,
+                in_flight: 0,
+                last_decrease: None,
+            }),
+            config,
+            notify: Notify::new(),
+        }
+    }
+
+    /// Create a limit that never changes
+    pub fn fixed(concurrency: usize) -> Self {
+        let concurrency = concurrency.max(1);
+        Self::new(AimdConfig {
+            min: concurrency,
+            max: concurrency,
+            initial: concurrency,
+            ..AimdConfig::default()
+        })
+    }
+
+    /// Create the limit described by a [`Concurrency`] setting
+    pub fn from_concurrency(concurrency: &Concurrency) -> Self {
+        match concurrency {
+            Concurrency::Fixed(concurrency) => Self::fixed(*concurrency),
+            Concurrency::Adaptive(config) => Self::new(config.clone()),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, LimitState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the current number of requests allowed to run at once
+    pub fn limit(&self) -> usize {
+        self.state().limit as usize
+    }
+
+    /// Returns the number of requests currently holding a permit
+    pub fn in_flight(&self) -> usize {
+        self.state().in_flight
+    }
+
+    /// Waits until a request may start
+    ///
+    /// # Returns
+    ///
+    /// A permit that frees its slot when finished or dropped
+    pub async fn acquire(self: &Arc<Self>) -> LimitPermit {
+        loop {
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            // Register before checking, so a release in between isn't missed
+            notified.as_mut().enable();
+            {
+                let mut state = self.state();
+                if state.in_flight < state.limit as usize {
+                    state.in_flight += 1;
+                    return LimitPermit {
+                        limit: Arc::clone(self),
+                        started: Instant::now(),
+                    };
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Adjusts the limit to the outcome of a request started at `started`
+    ///
+    /// # Arguments
+    ///
+    /// * `started` - When the request was started
+    /// * `outcome` - What happened to the request
+    pub fn record(&self, started: Instant, outcome: Outcome) {
+        let overloaded = match outcome {
+            Outcome::Success(latency) => latency > self.config.target_latency,
+            Outcome::Throttled => true,
+            Outcome::Unavailable | Outcome::Failed => return,
+        };
+
+        let mut state = self.state();
+        let before = state.limit as usize;
+        if overloaded {
+            if state.last_decrease.is_some_and(|last| started < last) {
+                return;
+            }
+            state.limit = (state.limit * self.config.backoff).max(self.config.min as f64);
+            state.last_decrease = Some(Instant::now());
+        } else {
+            state.limit = (state.limit + 1.0 / state.limit).min(self.config.max as f64);
+        }
+        let after = state.limit as usize;
+        drop(state);
+
+        if after != before {
+            logging::log_debug!(
+                logging::DOWNLOAD,
+                "Download concurrency changed from {} to {} after {:?}",
+                before,
+                after,
+                outcome
+            );
+        }
+        if after > before {
+            self.notify.notify_waiters();
+        }
+    }
+
+    fn release(&self) {
+        self.state().in_flight -= 1;
+        self.notify.notify_waiters();
+    }
+}
+
+/// A slot in an [`AdaptiveLimit`], held while a request runs
+#[derive(Debug)]
+pub struct LimitPermit {
+    limit: Arc<AdaptiveLimit>,
+    started: Instant,
+}
+
+impl LimitPermit {
+    /// Returns when the permit was acquired
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// Reports the outcome of the request and frees the slot
+    pub fn finish(self, outcome: Outcome) {
+        self.limit.record(self.started, outcome);
+    }
+}
+
+impl Drop for LimitPermit {
+    fn drop(&mut self) {
+        self.limit.release();
+    }
+}
+
+/// Settings of the per-host circuit breaker in [`HostLimits`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive throttled or unavailable responses that pause a host
+    pub failure_threshold: usize,
+    /// How long requests to a paused host fail without being sent
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HostEntry {
+    limit: Arc<AdaptiveLimit>,
+    consecutive_failures: usize,
+    /// End of the last pause; kept after it passes, until a request succeeds
+    paused_until: Option<Instant>,
+}
+
+/// Concurrency limits and circuit breakers kept separately for each host
+///
+/// Every host gets its own [`AdaptiveLimit`] built from the same
+/// [`Concurrency`] setting, so throttling by one CDN host doesn't slow down
+/// downloads from the others. After `failure_threshold` consecutive throttled
+/// or unavailable responses, a host is paused for the cooldown. Once the pause
+/// is over, requests are let through again, but a single further failure
+/// pauses the host again until a request succeeds.
+#[derive(Debug)]
+pub struct HostLimits {
+    concurrency: Concurrency,
+    breaker: CircuitBreakerConfig,
+    hosts: Mutex<HashMap<String, HostEntry>>,
+}
+
+impl HostLimits {
+    /// Create per-host limits
+    ///
+    /// # Arguments
+    ///
+    /// * `concurrency` - The concurrency setting applied to each host
+    /// * `breaker` - When to pause requests to a failing host
+    pub fn new(concurrency: Concurrency, breaker: CircuitBreakerConfig) -> Self {
+        Self {
+            concurrency,
+            breaker,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn hosts(&self) -> std::sync::MutexGuard<'_, HashMap<String, HostEntry>> {
+        self.hosts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the concurrency limit of a host, creating it on first use
+    pub fn limit(&self, host: &str) -> Arc<AdaptiveLimit> {
+        let mut hosts = self.hosts();
+        let entry = hosts.entry(host.to_string()).or_insert_with(|| HostEntry {
+            limit: Arc::new(AdaptiveLimit::from_concurrency(&self.concurrency)),
+            consecutive_failures: 0,
+            paused_until: None,
+        });
+        Arc::clone(&entry.limit)
+    }
+
+    /// Returns true if requests to a host are currently paused
+    pub fn is_paused(&self, host: &str) -> bool {
+        self.hosts()
+            .get(host)
+            .and_then(|entry| entry.paused_until)
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Updates the circuit breaker of a host with the outcome of a request
+    ///
+    /// The host's concurrency limit is updated separately, through the
+    /// [`LimitPermit`] the request held.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host the request went to
+    /// * `outcome` - What happened to the request
+    pub fn record(&self, host: &str, outcome: Outcome) {
+        let mut hosts = self.hosts();
+        let Some(entry) = hosts.get_mut(host) else {
+            return;
+        };
+        match outcome {
+            Outcome::Success(_) | Outcome::Failed => {
+                entry.consecutive_failures = 0;
+                entry.paused_until = None;
+            }
+            Outcome::Throttled | Outcome::Unavailable => {
+                entry.consecutive_failures += 1;
+                // A host that fails right after a pause is paused again at once
+                let probing = entry.paused_until.is_some();
+                if probing || entry.consecutive_failures >= self.breaker.failure_threshold {
+                    entry.consecutive_failures = 0;
+                    entry.paused_until = Some(Instant::now() + self.breaker.cooldown);
+                    logging::log_warn!(
+                        logging::DOWNLOAD,
+                        "Pausing downloads from {} for {:?} after repeated failures",
+                        host,
+                        self.breaker.cooldown
+                    );
+                }
+            }
+        }
+    }
+
+    /// Returns the current concurrency limit of every host seen so far
+    pub fn concurrency(&self) -> HashMap<String, usize> {
+        self.hosts()
+            .iter()
+            .map(|(host, entry)| (host.clone(), entry.limit.limit()))
+            .collect()
+    }
+}
+
+/// Returns the key [`HostLimits`] uses for the host of a URL
+///
+/// The key is the host name, followed by the port if the URL has a
+/// non-default one. URLs that can't be parsed share the key `""`.
+pub fn host_key(url: &str) -> String {
+    let Ok(parsed) = url::Url::parse(url) else {
+        return String::new();
+    };
+    let host = parsed.host_str().unwrap_or_default();
+    match parsed.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
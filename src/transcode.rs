@@ -0,0 +1,439 @@
+//! Transcoding downloaded videos to web-friendly MP4 with ffmpeg.
+//!
+//! iCloud often serves videos as QuickTime files or HEVC streams, which many
+//! browsers cannot play. With [`crate::download::DownloadOptions::with_transcode`],
+//! [`crate::download::download_derivative_classes`] runs every downloaded
+//! video that needs it through an external `ffmpeg` binary, producing H.264
+//! video with AAC audio in an MP4 container. The outcome for each video is
+//! recorded in [`crate::download::MultiDownloadReport::transcodes`].
+//!
+//! [`make_loop`] turns Live Photo videos and animated GIFs into short, silent
+//! looping clips for web embedding; slideshows can include one for every such
+//! photo, see [`crate::slideshow::SlideshowOptions::with_motion`].
+
+use crate::derivatives::SizeClass;
+use crate::download::{self, DerivativeDownload, DownloadOptions};
+use crate::logging;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Program run when no ffmpeg path is configured, looked up in `PATH`
+pub const DEFAULT_FFMPEG: &str = "ffmpeg";
+
+/// Default x264 constant rate factor, a good balance of size and quality
+pub const DEFAULT_CRF: u8 = 23;
+
+/// Default x264 preset
+pub const DEFAULT_PRESET: &str = "medium";
+
+/// Options for transcoding downloaded videos
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscodeConfig {
+    /// Path of the ffmpeg binary
+    pub ffmpeg: PathBuf,
+    /// x264 constant rate factor, from 0 (lossless) to 51; lower is better quality
+    pub crf: u8,
+    /// x264 preset, e.g. `veryfast` or `slow`; slower presets give smaller files
+    pub preset: String,
+    /// Keep the downloaded file next to the transcoded one
+    pub keep_original: bool,
+    /// Transcode every video, not only QuickTime and HEVC files
+    pub all_videos: bool,
+}
+
+impl Default for TranscodeConfig {
+    fn default() -> Self {
+        Self {
+            ffmpeg: PathBuf::from(DEFAULT_FFMPEG),
+            crf: DEFAULT_CRF,
+            preset: DEFAULT_PRESET.to_string(),
+            keep_original: false,
+            all_videos: false,
+        }
+    }
+}
+
+impl TranscodeConfig {
+    /// Create a configuration with the default quality, using `ffmpeg` from `PATH`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run the given ffmpeg binary
+    #[must_use]
+    pub fn with_ffmpeg(mut self, ffmpeg: impl Into<PathBuf>) -> Self {
+        self.ffmpeg = ffmpeg.into();
+        self
+    }
+
+    /// Set the x264 constant rate factor and preset
+    #[must_use]
+    pub fn with_quality(mut self, crf: u8, preset: &str) -> Self {
+        self.crf = crf.min(51);
+        self.preset = preset.to_string();
+        self
+    }
+
+    /// Keep downloaded files after transcoding them
+    #[must_use]
+    pub fn with_keep_original(mut self, keep_original: bool) -> Self {
+        self.keep_original = keep_original;
+        self
+    }
+
+    /// Transcode every video, e.g. to normalize bitrates
+    #[must_use]
+    pub fn with_all_videos(mut self, all_videos: bool) -> Self {
+        self.all_videos = all_videos;
+        self
+    }
+}
+
+/// Error returned when a video could not be transcoded
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum TranscodeError {
+    /// The ffmpeg binary could not be started
+    #[error("Could not run {program}: {source}")]
+    Spawn {
+        /// The program that was run
+        program: String,
+        /// Why it could not be started
+        source: io::Error,
+    },
+    /// ffmpeg ran but failed
+    #[error("ffmpeg failed ({status}): {stderr}")]
+    Failed {
+        /// Exit status of ffmpeg
+        status: std::process::ExitStatus,
+        /// What ffmpeg wrote to standard error
+        stderr: String,
+    },
+    /// Reading the video or moving the result into place failed
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Outcome of transcoding one downloaded video
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TranscodeStatus {
+    /// The video was transcoded to the given MP4 file
+    Transcoded {
+        /// Path of the MP4 file
+        path: String,
+    },
+    /// The video already plays in browsers and was left as is
+    Compatible,
+    /// Transcoding failed; the downloaded file is kept
+    Failed(String),
+}
+
+/// A downloaded video considered for transcoding
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoTranscode {
+    /// GUID of the photo
+    pub photo_guid: String,
+    /// Path of the downloaded file
+    pub source: String,
+    /// What happened to it
+    pub status: TranscodeStatus,
+}
+
+/// Returns true if a video is unlikely to play in browsers
+///
+/// This is the case for QuickTime files and for HEVC video in any container,
+/// recognized by its `hvc1` or `hev1` sample entry.
+///
+/// # Arguments
+///
+/// * `content` - The complete video file
+pub fn needs_transcode(content: &[u8]) -> bool {
+    if crate::utils::sniff_mime_type(content) == Some("video/quicktime") {
+        return true;
+    }
+    content
+        .windows(4)
+        .any(|window| window == b"hvc1" || window == b"hev1")
+}
+
+/// Returns the path the transcoded version of a video is written to
+///
+/// This is the source path with an `.mp4` extension, or with an `_h264.mp4`
+/// suffix if the source already is an MP4 file.
+pub fn output_path(source: &Path) -> PathBuf {
+    let output = source.with_extension("mp4");
+    if output != source {
+        return output;
+    }
+    let mut name = source.file_stem().unwrap_or_default().to_os_string();
+    name.push("_h264.mp4");
+    source.with_file_name(name)
+}
+
+/// Splits a fixed list of ffmpeg options
+fn ffmpeg_args(args: &str) -> Vec<String> {
+    args.split_whitespace().map(str::to_string).collect()
+}
+
+/// Runs ffmpeg on `source`, writing `output` through a partial file
+///
+/// `args` are the output options placed between the input and the output.
+async fn run_ffmpeg(
+    ffmpeg: &Path,
+    source: &Path,
+    args: &[String],
+    output: &Path,
+) -> Result<(), TranscodeError> {
+    let mut partial_name = std::ffi::OsString::from(".");
+    partial_name.push(output.file_name().unwrap_or_default());
+    partial_name.push(".part");
+    let partial = output.with_file_name(partial_name);
+
+    let result = tokio::process::Command::new(ffmpeg)
+        .args(["-hide_banner", "-loglevel", "error", "-nostdin", "-y", "-i"])
+        .arg(source)
+        .args(args)
+        .arg(&partial)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output()
+        .await;
+    let result = match result {
+        Err(source) => Err(TranscodeError::Spawn {
+            program: ffmpeg.display().to_string(),
+            source,
+        }),
+        Ok(run) if !run.status.success() => Err(TranscodeError::Failed {
+            status: run.status,
+            stderr: String::from_utf8_lossy(&run.stderr).trim().to_string(),
+        }),
+        Ok(_) => download::move_file(&partial, output)
+            .await
+            .map_err(TranscodeError::from),
+    };
+    if result.is_err() {
+        // Best effort: don't leave partial files behind
+        let _ = tokio::fs::remove_file(&partial).await;
+    }
+    result
+}
+
+/// Transcodes a video file to H.264 MP4
+///
+/// The output is written to a partial file first and moved into place once
+/// ffmpeg succeeds, so a failed run never leaves a truncated MP4 behind.
+///
+/// # Arguments
+///
+/// * `source` - The video to transcode
+/// * `output` - Path of the MP4 file to write
+/// * `config` - The ffmpeg binary and quality settings
+pub async fn transcode_file(
+    source: &Path,
+    output: &Path,
+    config: &TranscodeConfig,
+) -> Result<(), TranscodeError> {
+    let mut args = ffmpeg_args(
+        "-map 0:v:0 -map 0:a? -c:v libx264 -pix_fmt yuv420p -c:a aac -movflags +faststart -f mp4",
+    );
+    args.extend([
+        "-preset".to_string(),
+        config.preset.clone(),
+        "-crf".to_string(),
+        config.crf.to_string(),
+    ]);
+    run_ffmpeg(&config.ffmpeg, source, &args, output).await
+}
+
+/// Container of a looping clip
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoopFormat {
+    /// Silent H.264 MP4, played with `<video autoplay loop muted playsinline>`
+    #[default]
+    Mp4,
+    /// Animated WebP, which loops by itself and can be used in an `<img>`
+    WebP,
+}
+
+impl LoopFormat {
+    /// File extension of the format, without the dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            LoopFormat::Mp4 => "mp4",
+            LoopFormat::WebP => "webp",
+        }
+    }
+}
+
+/// Default length of looping clips
+pub const DEFAULT_LOOP_DURATION: Duration = Duration::from_secs(3);
+
+/// Default maximum width of looping clips, in pixels
+pub const DEFAULT_LOOP_WIDTH: u32 = 720;
+
+/// Options for [`make_loop`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LoopOptions {
+    /// Path of the ffmpeg binary
+    pub ffmpeg: PathBuf,
+    /// Container of the clip
+    pub format: LoopFormat,
+    /// The clip is cut after this long
+    pub max_duration: Duration,
+    /// Wider sources are scaled down to this width
+    pub max_width: u32,
+}
+
+impl Default for LoopOptions {
+    fn default() -> Self {
+        Self {
+            ffmpeg: PathBuf::from(DEFAULT_FFMPEG),
+            format: LoopFormat::default(),
+            max_duration: DEFAULT_LOOP_DURATION,
+            max_width: DEFAULT_LOOP_WIDTH,
+        }
+    }
+}
+
+impl LoopOptions {
+    /// Create options for a short MP4 loop, using `ffmpeg` from `PATH`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run the given ffmpeg binary
+    #[must_use]
+    pub fn with_ffmpeg(mut self, ffmpeg: impl Into<PathBuf>) -> Self {
+        self.ffmpeg = ffmpeg.into();
+        self
+    }
+
+    /// Set the container of the clip
+    #[must_use]
+    pub fn with_format(mut self, format: LoopFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the maximum length and width of the clip
+    #[must_use]
+    pub fn with_limits(mut self, max_duration: Duration, max_width: u32) -> Self {
+        self.max_duration = max_duration;
+        self.max_width = max_width.max(2);
+        self
+    }
+}
+
+/// Converts a Live Photo video or an animated image into a short looping clip
+///
+/// Live Photo MOV components, other videos and animated GIFs are accepted.
+/// The clip is silent, cut to [`LoopOptions::max_duration`] and scaled down
+/// to [`LoopOptions::max_width`], which suits inline web embedding.
+///
+/// # Arguments
+///
+/// * `source` - The video or animated image
+/// * `output` - Path of the clip to write, usually with [`LoopFormat::extension`]
+/// * `options` - Format and limits of the clip
+pub async fn make_loop(
+    source: &Path,
+    output: &Path,
+    options: &LoopOptions,
+) -> Result<(), TranscodeError> {
+    // Even dimensions are required by yuv420p
+    let scale = format!(
+        "scale='min({},trunc(iw/2)*2)':-2:flags=lanczos",
+        options.max_width - options.max_width % 2
+    );
+    let mut args = vec![
+        "-t".to_string(),
+        format!("{:.3}", options.max_duration.as_secs_f64()),
+        "-an".to_string(),
+        "-vf".to_string(),
+        scale,
+    ];
+    args.extend(ffmpeg_args(match options.format {
+        LoopFormat::Mp4 => "-c:v libx264 -pix_fmt yuv420p -movflags +faststart -f mp4",
+        LoopFormat::WebP => "-c:v libwebp -loop 0 -f webp",
+    }));
+    run_ffmpeg(&options.ffmpeg, source, &args, output).await
+}
+
+/// Transcodes one downloaded video as configured
+async fn transcode_download(
+    source: &Path,
+    config: &TranscodeConfig,
+    options: &DownloadOptions,
+) -> Result<Option<PathBuf>, TranscodeError> {
+    if !config.all_videos && !needs_transcode(&tokio::fs::read(source).await?) {
+        return Ok(None);
+    }
+    let output = output_path(source);
+    transcode_file(source, &output, config).await?;
+    download::apply_permissions(&output, options.file_mode, options).await?;
+    if !config.keep_original {
+        tokio::fs::remove_file(source).await?;
+    }
+    if options.fsync {
+        download::sync_parent_dir(&output).await?;
+    }
+    Ok(Some(output))
+}
+
+/// Transcodes the videos of a download pass
+///
+/// Transcoded downloads are updated to point at the MP4 file. Failures are
+/// logged and leave the downloaded file in place.
+///
+/// # Arguments
+///
+/// * `downloads` - Files written by the pass; only [`SizeClass::Video`] files are considered
+/// * `config` - The ffmpeg binary and quality settings
+/// * `options` - Options the files were written with
+///
+/// # Returns
+///
+/// The outcome for every video, in download order
+pub(crate) async fn transcode_downloads(
+    downloads: &mut [DerivativeDownload],
+    config: &TranscodeConfig,
+    options: &DownloadOptions,
+) -> Vec<VideoTranscode> {
+    let mut transcodes = Vec::new();
+    for download in downloads
+        .iter_mut()
+        .filter(|download| download.size_class == SizeClass::Video)
+    {
+        let source = download.path.clone();
+        let status = match transcode_download(Path::new(&source), config, options).await {
+            Ok(Some(output)) => {
+                download.path = output.to_string_lossy().into_owned();
+                TranscodeStatus::Transcoded {
+                    path: download.path.clone(),
+                }
+            }
+            Ok(None) => TranscodeStatus::Compatible,
+            Err(e) => {
+                logging::log_warn!(
+                    logging::DOWNLOAD,
+                    "Could not transcode video of photo {}: {}",
+                    download.photo_guid,
+                    e
+                );
+                TranscodeStatus::Failed(e.to_string())
+            }
+        };
+        transcodes.push(VideoTranscode {
+            photo_guid: download.photo_guid.clone(),
+            source,
+            status,
+        });
+    }
+    transcodes
+}
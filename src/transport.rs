@@ -0,0 +1,314 @@
+//! Pluggable transport for the requests the crate sends.
+//!
+//! Every request the crate sends (webstream, webasseturls and downloads) can
+//! be answered by a [`Transport`] instead of the network. A transport is
+//! installed for the duration of a future with [`with_transport`], so tests
+//! running in parallel don't see each other's transports.
+//!
+//! [`MockTransport`] answers from bundled fixtures of anonymized, realistic
+//! albums, so downstream tests and examples run offline and deterministically
+//! without an HTTP mock server:
+//!
+//! ```
+//! use icloud_album_rs::transport::{with_transport, MockTransport};
+//! use std::sync::Arc;
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let transport = Arc::new(MockTransport::with_fixture("family")?);
+//! let album = with_transport(transport, icloud_album_rs::get_icloud_photos("B0z5qAGN1JIFd3y"))
+//!     .await?;
+//! assert_eq!(album.metadata.stream_name, "Summer Trip");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The available fixtures are listed by [`FIXTURES`]:
+//!
+//! - `family`: three photos with the string-typed numbers the API sends for
+//!   some fields, a location, and a video
+//! - `empty`: an album without photos
+//! - `redirect`: the `family` album behind a 330 redirect to another
+//!   partition host
+//!
+//! Downloads from a mock transport receive a small JPEG. Requests no route
+//! matches are answered with 404 and never reach the network. Answered
+//! requests are not recorded by [`crate::har`].
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+/// A request as seen by a [`Transport`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportRequest {
+    /// The HTTP method, e.g. `POST`
+    pub method: String,
+    /// The full request URL
+    pub url: String,
+    /// The request body, if any
+    pub body: Option<Vec<u8>>,
+}
+
+/// A response produced by a [`Transport`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportResponse {
+    /// The HTTP status code
+    pub status: u16,
+    /// Response headers as (name, value)
+    pub headers: Vec<(String, String)>,
+    /// The response body
+    pub body: Vec<u8>,
+}
+
+impl TransportResponse {
+    /// Create a response with the given status and body
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    /// Create a JSON response
+    pub fn json(status: u16, body: &serde_json::Value) -> Self {
+        Self::new(status, body.to_string()).with_header("content-type", "application/json")
+    }
+
+    /// Add a header
+    #[must_use]
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Converts the response into the type returned by reqwest
+    fn into_reqwest(self) -> reqwest::Response {
+        let mut builder = http::Response::builder().status(self.status);
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        let response = builder.body(self.body).unwrap_or_else(|_| {
+            let mut response = http::Response::new(b"invalid mock response".to_vec());
+            *response.status_mut() = http::StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        });
+        reqwest::Response::from(response)
+    }
+}
+
+/// Answers requests instead of the network
+pub trait Transport: Send + Sync {
+    /// Returns the response to `request`, or `None` to send it over the network
+    fn respond(&self, request: &TransportRequest) -> Option<TransportResponse>;
+}
+
+tokio::task_local! {
+    /// Transport installed for the current task
+    static TRANSPORT: Arc<dyn Transport>;
+}
+
+/// Runs `future` with requests answered by `transport`
+///
+/// # Arguments
+///
+/// * `transport` - The transport answering requests
+/// * `future` - The work to run
+///
+/// # Returns
+///
+/// The output of `future`
+pub async fn with_transport<F: Future>(transport: Arc<dyn Transport>, future: F) -> F::Output {
+    TRANSPORT.scope(transport, future).await
+}
+
+/// Returns the transport installed for the current task, if any
+pub(crate) fn current() -> Option<Arc<dyn Transport>> {
+    TRANSPORT.try_with(Arc::clone).ok()
+}
+
+/// Runs `future` with `transport` installed, if there is one
+///
+/// Used to carry the transport over to spawned tasks.
+pub(crate) async fn scoped<F: Future>(
+    transport: Option<Arc<dyn Transport>>,
+    future: F,
+) -> F::Output {
+    match transport {
+        Some(transport) => with_transport(transport, future).await,
+        None => future.await,
+    }
+}
+
+/// Answers a request with the installed transport
+///
+/// # Returns
+///
+/// The request to send over the network, or the transport's response
+pub(crate) fn intercept(
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::RequestBuilder, Box<reqwest::Response>> {
+    let Some(transport) = current() else {
+        return Ok(request);
+    };
+    let Some(built) = request.try_clone().and_then(|clone| clone.build().ok()) else {
+        return Ok(request);
+    };
+    let request_parts = TransportRequest {
+        method: built.method().to_string(),
+        url: built.url().to_string(),
+        body: built
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(<[u8]>::to_vec),
+    };
+    match transport.respond(&request_parts) {
+        Some(response) => Err(Box::new(response.into_reqwest())),
+        None => Ok(request),
+    }
+}
+
+/// Names of the fixtures available to [`MockTransport::with_fixture`]
+pub const FIXTURES: &[&str] = &["family", "empty", "redirect"];
+
+/// Partition host the `redirect` fixture redirects to
+const REDIRECT_HOST: &str = "p99-sharedstreams.icloud.com";
+
+/// Body served for downloads: a JPEG header
+pub(crate) const JPEG_BYTES: &[u8] = &[
+    0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, b'J', b'F', b'I', b'F', 0x00, 0x01, 0x01, 0x00, 0x00, 0x01,
+    0x00, 0x01, 0x00, 0x00, 0xFF, 0xD9,
+];
+
+/// Error returned for an unknown fixture name
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Unknown fixture: {0}")]
+pub struct UnknownFixture(pub String);
+
+/// A route of a [`MockTransport`]
+#[derive(Debug, Clone)]
+struct Route {
+    method: String,
+    path_suffix: String,
+    response: TransportResponse,
+}
+
+/// Transport answering from fixed routes and bundled fixtures
+///
+/// Routes match on the method and the end of the URL path; routes added
+/// later take precedence. Requests are recorded for inspection.
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    routes: Vec<Route>,
+    assets: Option<Vec<u8>>,
+    redirect_host: Option<String>,
+    requests: Mutex<Vec<TransportRequest>>,
+}
+
+impl MockTransport {
+    /// Create a transport answering every request with 404
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a transport serving one of the bundled [`FIXTURES`]
+    ///
+    /// The album is served for any token, and every download receives a
+    /// small JPEG.
+    pub fn with_fixture(name: &str) -> Result<Self, UnknownFixture> {
+        let (webstream, webasseturls) = match name {
+            "family" | "redirect" => (
+                include_str!("../fixtures/family/webstream.json"),
+                include_str!("../fixtures/family/webasseturls.json"),
+            ),
+            "empty" => (
+                include_str!("../fixtures/empty/webstream.json"),
+                include_str!("../fixtures/empty/webasseturls.json"),
+            ),
+            _ => return Err(UnknownFixture(name.to_string())),
+        };
+        let mut transport = Self::new()
+            .with_route("POST", "/webstream", json_response(webstream))
+            .with_route("POST", "/webasseturls", json_response(webasseturls))
+            .with_assets(JPEG_BYTES);
+        if name == "redirect" {
+            transport.redirect_host = Some(REDIRECT_HOST.to_string());
+        }
+        Ok(transport)
+    }
+
+    /// Answer requests with `method` whose URL path ends with `path_suffix`
+    #[must_use]
+    pub fn with_route(
+        mut self,
+        method: &str,
+        path_suffix: &str,
+        response: TransportResponse,
+    ) -> Self {
+        self.routes.push(Route {
+            method: method.to_uppercase(),
+            path_suffix: path_suffix.to_string(),
+            response,
+        });
+        self
+    }
+
+    /// Answer GET requests no route matches with `body`, as asset downloads
+    #[must_use]
+    pub fn with_assets(mut self, body: &[u8]) -> Self {
+        self.assets = Some(body.to_vec());
+        self
+    }
+
+    /// Returns the requests answered so far
+    pub fn requests(&self) -> Vec<TransportRequest> {
+        self.requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+/// Builds a 200 response from fixture JSON
+fn json_response(body: &str) -> TransportResponse {
+    TransportResponse::new(200, body).with_header("content-type", "application/json")
+}
+
+impl Transport for MockTransport {
+    fn respond(&self, request: &TransportRequest) -> Option<TransportResponse> {
+        self.requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(request.clone());
+
+        let url = url::Url::parse(&request.url).ok();
+        let path = url.as_ref().map(|url| url.path()).unwrap_or_default();
+        let host = url
+            .as_ref()
+            .and_then(|url| url.host_str())
+            .unwrap_or_default();
+
+        if let Some(redirect_host) = &self.redirect_host {
+            if path.ends_with("/webstream") && host != redirect_host {
+                return Some(TransportResponse::json(
+                    330,
+                    &serde_json::json!({ "X-Apple-MMe-Host": redirect_host }),
+                ));
+            }
+        }
+
+        let route = self
+            .routes
+            .iter()
+            .rev()
+            .find(|route| route.method == request.method && path.ends_with(&route.path_suffix));
+        Some(match (route, &self.assets) {
+            (Some(route), _) => route.response.clone(),
+            (None, Some(assets)) if request.method == "GET" => {
+                TransportResponse::new(200, assets.clone())
+                    .with_header("content-type", "image/jpeg")
+            }
+            _ => TransportResponse::new(404, "no mock route"),
+        })
+    }
+}
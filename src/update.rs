@@ -0,0 +1,263 @@
+//! Opt-in check for a newer release of this crate on crates.io.
+//!
+//! Apple changes the shared album API without notice, and fixes for such
+//! protocol drift only reach users who upgrade. Tools built on this crate can
+//! offer a `--check-update` flag that calls [`check_for_update`] and prints
+//! [`UpdateInfo::instructions`] when a newer version is out. Nothing here runs
+//! unless called; the crate never contacts crates.io on its own.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use icloud_album_rs::update;
+//!
+//! match update::check_for_update(&reqwest::Client::new()).await? {
+//!     Some(update) => eprintln!("{}", update.instructions()),
+//!     None => eprintln!("{} is up to date", update::CURRENT_VERSION),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use reqwest::Client;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// Name of this crate on crates.io
+pub const CRATE_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// Version of this build of the crate
+pub const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// URL of the crates.io API entry of this crate
+pub const CRATES_IO_URL: &str = concat!("https://crates.io/api/v1/crates/", env!("CARGO_PKG_NAME"));
+
+/// User agent sent to crates.io, which rejects requests without one
+const USER_AGENT: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    "/",
+    env!("CARGO_PKG_VERSION"),
+    " (update check; ",
+    env!("CARGO_PKG_REPOSITORY"),
+    ")"
+);
+
+/// Error type for update checks
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum UpdateError {
+    /// The registry could not be reached
+    #[error("Failed to query the registry: {0}")]
+    Network(#[from] reqwest::Error),
+    /// The registry answered with an error status
+    #[error("The registry answered with status {0}")]
+    Status(u16),
+    /// The registry's answer didn't name a valid version
+    #[error("Unexpected registry response: {0}")]
+    InvalidResponse(String),
+}
+
+/// A `MAJOR.MINOR.PATCH` version, optionally with a pre-release suffix
+///
+/// Pre-releases order before the release they precede. Their suffixes are
+/// compared as plain strings, which is enough to tell `-rc.1` from `-rc.2`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Version {
+    /// Incremented for incompatible changes
+    pub major: u64,
+    /// Incremented for compatible additions
+    pub minor: u64,
+    /// Incremented for fixes
+    pub patch: u64,
+    /// Pre-release identifier after the `-`, if any
+    pub pre: Option<String>,
+}
+
+impl Version {
+    /// The version of this build of the crate
+    pub fn current() -> Self {
+        CURRENT_VERSION.parse().unwrap_or(Self {
+            major: 0,
+            minor: 0,
+            patch: 0,
+            pre: None,
+        })
+    }
+
+    /// Whether this is a pre-release
+    pub fn is_pre_release(&self) -> bool {
+        self.pre.is_some()
+    }
+}
+
+impl FromStr for Version {
+    type Err = UpdateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || UpdateError::InvalidResponse(format!("invalid version {:?}", s));
+        // Build metadata doesn't take part in comparisons
+        let core = s.trim().split('+').next().unwrap_or_default();
+        let (core, pre) = match core.split_once('-') {
+            Some((core, pre)) if !pre.is_empty() => (core, Some(pre.to_string())),
+            Some(_) => return Err(invalid()),
+            None => (core, None),
+        };
+        let mut parts = core.split('.').map(|part| part.parse::<u64>());
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch)), None) => Ok(Self {
+                major,
+                minor,
+                patch,
+                pre,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (&self.pre, &other.pre) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a), Some(b)) => a.cmp(b),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(pre) = &self.pre {
+            write!(f, "-{}", pre)?;
+        }
+        Ok(())
+    }
+}
+
+/// A newer release than the running one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateInfo {
+    /// The running version
+    pub current: Version,
+    /// The newest stable version on the registry
+    pub latest: Version,
+}
+
+impl UpdateInfo {
+    /// Whether the update may need code changes, i.e. it changes the major
+    /// version, or the minor version before 1.0
+    pub fn is_breaking(&self) -> bool {
+        match self.current.major {
+            0 => self.latest.major > 0 || self.latest.minor > self.current.minor,
+            major => self.latest.major > major,
+        }
+    }
+
+    /// Describes the update and how to install it, for printing to users
+    pub fn instructions(&self) -> String {
+        let mut text = format!(
+            "A newer version of {} is available: {} (running {})\n",
+            CRATE_NAME, self.latest, self.current
+        );
+        if self.is_breaking() {
+            text.push_str(&format!(
+                "Set `{} = \"{}\"` in Cargo.toml and rebuild; this release may need code changes.\n",
+                CRATE_NAME, self.latest
+            ));
+        } else {
+            text.push_str(&format!(
+                "Run `cargo update -p {}` and rebuild.\n",
+                CRATE_NAME
+            ));
+        }
+        text.push_str(&format!(
+            "Release notes: {}/releases\n",
+            env!("CARGO_PKG_REPOSITORY")
+        ));
+        text
+    }
+}
+
+/// Queries crates.io for a release newer than the running one
+///
+/// This makes one request to [`CRATES_IO_URL`]. Pre-releases are not offered.
+///
+/// # Arguments
+///
+/// * `client` - A reqwest HTTP client
+///
+/// # Returns
+///
+/// The update, or `None` if the running version is the newest
+pub async fn check_for_update(client: &Client) -> Result<Option<UpdateInfo>, UpdateError> {
+    check_for_update_at(client, CRATES_IO_URL).await
+}
+
+/// Queries a crates.io compatible registry API for a newer release
+///
+/// This behaves like [`check_for_update`], but asks `url` instead, e.g. a
+/// mirror. The response must have the shape of crates.io's crate endpoint.
+///
+/// # Arguments
+///
+/// * `client` - A reqwest HTTP client
+/// * `url` - URL of the crate's entry in the registry API
+///
+/// # Returns
+///
+/// The update, or `None` if the running version is the newest
+pub async fn check_for_update_at(
+    client: &Client,
+    url: &str,
+) -> Result<Option<UpdateInfo>, UpdateError> {
+    let resp = client
+        .get(url)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .header(reqwest::header::ACCEPT, "application/json")
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        return Err(UpdateError::Status(resp.status().as_u16()));
+    }
+    let body: Value = resp.json().await?;
+    let latest = latest_version(&body)?;
+    let current = Version::current();
+    Ok((latest > current).then_some(UpdateInfo { current, latest }))
+}
+
+/// Returns the newest stable version in a crates.io crate response
+///
+/// `crate.max_stable_version` is used when present, otherwise the newest
+/// version in `versions` that is neither yanked nor a pre-release.
+///
+/// # Arguments
+///
+/// * `body` - The JSON body of the crate endpoint
+pub fn latest_version(body: &Value) -> Result<Version, UpdateError> {
+    if let Some(version) = body
+        .pointer("/crate/max_stable_version")
+        .and_then(Value::as_str)
+    {
+        return version.parse();
+    }
+    body.get("versions")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|version| version.get("yanked").and_then(Value::as_bool) != Some(true))
+        .filter_map(|version| version.get("num")?.as_str()?.parse::<Version>().ok())
+        .filter(|version| !version.is_pre_release())
+        .max()
+        .ok_or_else(|| UpdateError::InvalidResponse("no stable version listed".to_string()))
+}
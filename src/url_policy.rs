@@ -0,0 +1,262 @@
+//! Validation of URLs received from the iCloud API.
+//!
+//! Asset URLs are assembled from `url_location` and `url_path` fields of the
+//! webasseturls response. A [`UrlPolicy`] parses the result with the `url`
+//! crate and checks it against an allowlist of hosts, so that a compromised or
+//! malformed response cannot point downloads at arbitrary servers.
+//!
+//! Services that fetch albums on behalf of untrusted users can go further with
+//! [`validate_token`] and [`UrlPolicy::build_client`], which pin every request
+//! and redirect to the allowed hosts (see
+//! [`crate::fetch::FetchOptions::with_url_policy`]).
+
+use crate::redact::Redacted;
+use url::Url;
+
+/// Hosts allowed by [`UrlPolicy::default`]
+///
+/// Each entry matches the domain itself and any of its subdomains.
+pub const DEFAULT_ALLOWED_HOSTS: &[&str] = &[
+    "icloud.com",
+    "icloud-content.com",
+    "cdn-apple.com",
+    "apple.com",
+];
+
+/// Maximum length accepted by [`validate_token`]
+pub const MAX_TOKEN_LEN: usize = 64;
+
+/// Maximum number of HTTP redirects followed by clients from [`UrlPolicy::build_client`]
+pub const MAX_REDIRECTS: usize = 5;
+
+/// Error type for rejected URLs
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum UrlPolicyError {
+    #[error("Malformed URL: {}", Redacted(.0))]
+    Malformed(String),
+    #[error("URL does not use https: {}", Redacted(.0))]
+    InsecureScheme(String),
+    #[error("Host is not allowed: {0}")]
+    DisallowedHost(String),
+    #[error("Invalid URL path: {}", Redacted(.0))]
+    InvalidPath(String),
+    #[error("Invalid album token: {0}")]
+    InvalidToken(String),
+}
+
+/// Checks that an album token is safe to embed in request URLs
+///
+/// Shared album tokens are short base62 strings. Anything else (separators,
+/// percent escapes, whitespace, or an implausible length) is rejected, so an
+/// untrusted token can never change the host or path of a request.
+pub fn validate_token(token: &str) -> Result<(), UrlPolicyError> {
+    if token.is_empty() {
+        return Err(UrlPolicyError::InvalidToken("empty token".to_string()));
+    }
+    if token.len() > MAX_TOKEN_LEN {
+        return Err(UrlPolicyError::InvalidToken(format!(
+            "token is longer than {} characters",
+            MAX_TOKEN_LEN
+        )));
+    }
+    if let Some(c) = token.chars().find(|c| !c.is_ascii_alphanumeric()) {
+        return Err(UrlPolicyError::InvalidToken(format!(
+            "unexpected character {:?}",
+            c
+        )));
+    }
+    Ok(())
+}
+
+/// Rules a URL must satisfy before the crate requests it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlPolicy {
+    /// Allowed host domains, each matching itself and its subdomains
+    ///
+    /// `None` allows any host.
+    pub allowed_hosts: Option<Vec<String>>,
+    /// Reject URLs that do not use https
+    pub require_https: bool,
+}
+
+impl Default for UrlPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_hosts: Some(
+                DEFAULT_ALLOWED_HOSTS
+                    .iter()
+                    .map(|h| h.to_string())
+                    .collect(),
+            ),
+            require_https: true,
+        }
+    }
+}
+
+impl UrlPolicy {
+    /// Create the default policy (Apple hosts only, https required)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A policy that accepts any well-formed URL
+    pub fn permissive() -> Self {
+        Self {
+            allowed_hosts: None,
+            require_https: false,
+        }
+    }
+
+    /// Replaces the host allowlist
+    #[must_use]
+    pub fn with_allowed_hosts<I, S>(mut self, hosts: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.allowed_hosts = Some(
+            hosts
+                .into_iter()
+                .map(|h| h.into().trim_start_matches("*.").to_ascii_lowercase())
+                .collect(),
+        );
+        self
+    }
+
+    /// Sets whether https is required
+    #[must_use]
+    pub fn with_require_https(mut self, require_https: bool) -> Self {
+        self.require_https = require_https;
+        self
+    }
+
+    /// Returns true if `host` is covered by the allowlist
+    pub fn allows_host(&self, host: &str) -> bool {
+        let Some(allowed) = &self.allowed_hosts else {
+            return true;
+        };
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        allowed.iter().any(|domain| {
+            host == *domain
+                || (host.len() > domain.len()
+                    && host.ends_with(domain.as_str())
+                    && host.as_bytes()[host.len() - domain.len() - 1] == b'.')
+        })
+    }
+
+    /// Checks an already parsed URL against the policy
+    pub fn check(&self, url: &Url) -> Result<(), UrlPolicyError> {
+        match url.scheme() {
+            "https" => {}
+            "http" if !self.require_https => {}
+            _ => return Err(UrlPolicyError::InsecureScheme(url.to_string())),
+        }
+        if !url.username().is_empty() || url.password().is_some() {
+            return Err(UrlPolicyError::Malformed(url.to_string()));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| UrlPolicyError::Malformed(url.to_string()))?;
+        if !self.allows_host(host) {
+            return Err(UrlPolicyError::DisallowedHost(host.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Parses and checks a URL string
+    pub fn validate(&self, url: &str) -> Result<Url, UrlPolicyError> {
+        let parsed = Url::parse(url).map_err(|e| UrlPolicyError::Malformed(e.to_string()))?;
+        self.check(&parsed)?;
+        Ok(parsed)
+    }
+
+    /// Checks a bare host name (no scheme, credentials, port or path) against the policy
+    pub fn validate_host(&self, host: &str) -> Result<(), UrlPolicyError> {
+        if host.is_empty()
+            || !host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        {
+            return Err(UrlPolicyError::Malformed(format!(
+                "invalid host '{}'",
+                host
+            )));
+        }
+        if !self.allows_host(host) {
+            return Err(UrlPolicyError::DisallowedHost(host.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Builds an HTTP client that only follows redirects allowed by the policy
+    ///
+    /// Redirects to other hosts (or to plain http when https is required) fail
+    /// the request instead of being followed, and at most [`MAX_REDIRECTS`]
+    /// redirects are followed.
+    pub fn build_client(&self) -> reqwest::Result<reqwest::Client> {
+        self.client_builder().build()
+    }
+
+    /// Returns a client builder that only follows redirects allowed by the policy
+    ///
+    /// This is what [`UrlPolicy::build_client`] builds, for callers that need
+    /// further client options such as [`crate::net::NetworkConfig`].
+    pub fn client_builder(&self) -> reqwest::ClientBuilder {
+        let policy = self.clone();
+        reqwest::Client::builder().redirect(reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if let Err(e) = policy.check(attempt.url()) {
+                attempt.error(e)
+            } else {
+                attempt.follow()
+            }
+        }))
+    }
+
+    /// Builds and validates an asset URL from webasseturls components
+    ///
+    /// `location` must be a bare host name (no scheme, credentials, port or
+    /// path) and `path` an absolute path without `..` segments or backslashes.
+    /// With a [`crate::base_url::api_origin`] override, the validated path is
+    /// served from that origin instead of `location`.
+    ///
+    /// # Arguments
+    ///
+    /// * `location` - The `url_location` field of an asset
+    /// * `path` - The `url_path` field of an asset
+    ///
+    /// # Returns
+    ///
+    /// The validated https URL
+    pub fn asset_url(&self, location: &str, path: &str) -> Result<Url, UrlPolicyError> {
+        self.validate_host(location)?;
+        let path_part = path.split(['?', '#']).next().unwrap_or_default();
+        if !path.starts_with('/')
+            || path.starts_with("//")
+            || path.contains('\\')
+            || path.chars().any(|c| c.is_whitespace() || c.is_control())
+            || path_part
+                .split('/')
+                .any(|segment| segment == ".." || segment == ".")
+        {
+            return Err(UrlPolicyError::InvalidPath(path.to_string()));
+        }
+
+        let url = self.validate(&format!("https://{}{}", location, path))?;
+        // The parsed host must be exactly the given location
+        let expected = location.to_ascii_lowercase();
+        if url.host_str().map(|h| h.trim_end_matches('.')) != Some(expected.trim_end_matches('.')) {
+            return Err(UrlPolicyError::Malformed(format!(
+                "url_location '{}' does not parse as a host",
+                location
+            )));
+        }
+        match crate::base_url::api_origin() {
+            Some(origin) => Url::parse(&format!("{}{}", origin, path))
+                .map_err(|_| UrlPolicyError::Malformed(format!("{}{}", origin, path))),
+            None => Ok(url),
+        }
+    }
+}
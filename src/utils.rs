@@ -1,10 +1,11 @@
 //! ABOUTME: Utility functions for file operations and media handling
 //! ABOUTME: Contains functions for MIME type detection, file extension mapping, and other utilities
 
-use crate::models::Derivative;
-use log::{debug, warn};
+use crate::derivatives::{self, SizeClass};
+use crate::logging;
+use crate::models::{CalendarDate, Derivative, Image};
 use mime_guess::from_path;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 /// Returns the appropriate file extension based on MIME type
 ///
@@ -25,7 +26,11 @@ pub fn extension_from_mime_type(mime_type: &str) -> String {
         "video/quicktime" => ".mov".to_string(),
         "image/gif" => ".gif".to_string(),
         _ => {
-            warn!("Unknown MIME type: {}, defaulting to .jpg", mime_type);
+            logging::log_warn!(
+                logging::FILES,
+                "Unknown MIME type: {}, defaulting to .jpg",
+                mime_type
+            );
             ".jpg".to_string()
         }
     }
@@ -42,78 +47,8 @@ pub fn extension_from_mime_type(mime_type: &str) -> String {
 ///
 /// A string containing the detected MIME type
 pub fn detect_mime_type(bytes: &[u8], filename: Option<&str>) -> String {
-    // Common image and video file signatures
-    if bytes.len() >= 12 {
-        // JPEG: Starts with FF D8 FF
-        if bytes[0] == 0xFF && bytes[1] == 0xD8 && bytes[2] == 0xFF {
-            return "image/jpeg".to_string();
-        }
-
-        // PNG: Starts with 89 50 4E 47 0D 0A 1A 0A
-        if bytes[0] == 0x89
-            && bytes[1] == 0x50
-            && bytes[2] == 0x4E
-            && bytes[3] == 0x47
-            && bytes[4] == 0x0D
-            && bytes[5] == 0x0A
-            && bytes[6] == 0x1A
-            && bytes[7] == 0x0A
-        {
-            return "image/png".to_string();
-        }
-
-        // Check for MOV first (more specific) - ftyp at bytes 4-8 with qt in next position
-        if bytes.len() > 11
-            && bytes[4] == 0x66
-            && bytes[5] == 0x74
-            && bytes[6] == 0x79
-            && bytes[7] == 0x70
-            && bytes[8] == 0x71
-            && bytes[9] == 0x74
-        {
-            return "video/quicktime".to_string();
-        }
-
-        // MP4: ftyp at bytes 4-8 (more general)
-        if bytes.len() > 11
-            && bytes[4] == 0x66
-            && bytes[5] == 0x74
-            && bytes[6] == 0x79
-            && bytes[7] == 0x70
-        {
-            return "video/mp4".to_string();
-        }
-
-        // GIF: Starts with GIF87a or GIF89a
-        if bytes.len() >= 6
-            && bytes[0] == 0x47
-            && bytes[1] == 0x49
-            && bytes[2] == 0x46
-            && bytes[3] == 0x38
-            && (bytes[4] == 0x37 || bytes[4] == 0x39)
-            && bytes[5] == 0x61
-        {
-            return "image/gif".to_string();
-        }
-
-        // HEIC/HEIF detection
-        if bytes.len() > 12
-            && bytes[4] == 0x66
-            && bytes[5] == 0x74
-            && bytes[6] == 0x79
-            && bytes[7] == 0x70
-            && bytes[8] == 0x68
-            && bytes[9] == 0x65
-            && bytes[10] == 0x69
-            && (bytes[11] == 0x63 || bytes[11] == 0x66)
-        {
-            // Determine if it's HEIC or HEIF based on the last identifier byte
-            if bytes[11] == 0x63 {
-                return "image/heic".to_string();
-            } else {
-                return "image/heif".to_string();
-            }
-        }
+    if let Some(mime_type) = sniff_mime_type(bytes) {
+        return mime_type.to_string();
     }
 
     // If we couldn't detect from bytes, try to use the filename
@@ -123,10 +58,62 @@ pub fn detect_mime_type(bytes: &[u8], filename: Option<&str>) -> String {
     }
 
     // Default to JPEG if we couldn't detect
-    debug!("Could not detect MIME type, defaulting to image/jpeg");
+    logging::log_debug!(
+        logging::FILES,
+        "Could not detect MIME type, defaulting to image/jpeg"
+    );
     "image/jpeg".to_string()
 }
 
+/// Detects the MIME type of content from its signature ("magic bytes") alone
+///
+/// Unlike [`detect_mime_type`], this never falls back to the filename or to a
+/// default, so it can be used to check whether an existing file's extension
+/// matches its content.
+///
+/// # Arguments
+///
+/// * `bytes` - The leading content bytes to analyze (at least 12 are needed)
+///
+/// # Returns
+///
+/// The detected MIME type, or `None` if the signature is not recognized
+pub fn sniff_mime_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.len() < 12 {
+        return None;
+    }
+
+    // JPEG: Starts with FF D8 FF
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Some("image/jpeg");
+    }
+
+    // PNG: Starts with 89 50 4E 47 0D 0A 1A 0A
+    if bytes.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]) {
+        return Some("image/png");
+    }
+
+    // GIF: Starts with GIF87a or GIF89a
+    if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        return Some("image/gif");
+    }
+
+    // ISO base media files: "ftyp" at bytes 4-8 followed by the major brand.
+    // The brand must be checked before falling back to MP4, since HEIC/HEIF
+    // and QuickTime files share the same container signature.
+    if &bytes[4..8] == b"ftyp" {
+        let brand = &bytes[8..12];
+        return Some(match brand {
+            b"heic" | b"heix" => "image/heic",
+            b"heif" | b"mif1" => "image/heif",
+            _ if brand.starts_with(b"qt") => "video/quicktime",
+            _ => "video/mp4",
+        });
+    }
+
+    None
+}
+
 /// Returns the appropriate file extension for the given content
 ///
 /// # Arguments
@@ -142,6 +129,127 @@ pub fn get_extension_for_content(bytes: &[u8], filename: Option<&str>) -> String
     extension_from_mime_type(&mime_type)
 }
 
+/// Determines the file extension for downloaded derivative content
+///
+/// The content's signature decides when it is recognized (see
+/// [`sniff_mime_type`]); otherwise the derivative's declared type is used
+/// (see [`Derivative::declared_mime_type`]) before falling back to `.jpg`.
+///
+/// # Arguments
+///
+/// * `bytes` - The downloaded content
+/// * `derivative` - The derivative the content was downloaded from
+///
+/// # Returns
+///
+/// A string containing the appropriate file extension with leading dot
+pub fn get_extension_for_derivative(bytes: &[u8], derivative: &Derivative) -> String {
+    match sniff_mime_type(bytes).or_else(|| derivative.declared_mime_type()) {
+        Some(mime_type) => extension_from_mime_type(mime_type),
+        None => get_extension_for_content(bytes, None),
+    }
+}
+
+/// Longest base filename, in bytes, that [`photo_base_filename`] returns
+///
+/// Most filesystems limit names to 255 bytes; the rest is left for the size
+/// class suffix and the extension added when the file is written.
+pub const MAX_BASE_FILENAME_BYTES: usize = 200;
+
+/// Returns the longest prefix of `text` that is at most `max_bytes` long
+///
+/// Unlike slicing with `&text[..max_bytes]`, this never splits a UTF-8
+/// character and so never panics.
+pub fn truncate_at_char_boundary(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let end = (0..=max_bytes)
+        .rev()
+        .find(|&index| text.is_char_boundary(index))
+        .unwrap_or(0);
+    &text[..end]
+}
+
+/// Makes arbitrary text, e.g. a caption, safe to use as a filename
+///
+/// Control characters, characters that are illegal on Windows, macOS or
+/// Linux, and characters with a special meaning in shells are replaced with
+/// underscores. Leading and trailing whitespace and dots are removed, and
+/// names longer than [`MAX_BASE_FILENAME_BYTES`] are cut at a character
+/// boundary and marked with a `_truncated` suffix, staying within that length.
+///
+/// # Arguments
+///
+/// * `input` - The text to turn into a filename
+///
+/// # Returns
+///
+/// The sanitized name, which is empty if `input` has no usable characters
+pub fn sanitize_filename(input: &str) -> String {
+    let sanitized: String = input
+        .chars()
+        .map(|c| match c {
+            c if c.is_control() => '_',
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            '!' | '@' | '#' | '$' | '%' | '^' | '&' | '\'' | ';' | '=' | '+' | ',' | '`' | '~' => {
+                '_'
+            }
+            _ => c,
+        })
+        .collect();
+    let sanitized = sanitized.trim_matches(|c: char| c.is_whitespace() || c == '.');
+
+    if sanitized.len() > MAX_BASE_FILENAME_BYTES {
+        const SUFFIX: &str = "_truncated";
+        let kept = truncate_at_char_boundary(sanitized, MAX_BASE_FILENAME_BYTES - SUFFIX.len());
+        format!("{}{}", kept, SUFFIX)
+    } else {
+        sanitized.to_string()
+    }
+}
+
+/// Builds the filename (without extension) used when saving a photo
+///
+/// Custom names and captions are combined with the photo GUID so that names
+/// stay unique; both are made safe with [`sanitize_filename`] first. An index,
+/// when given, is prefixed as a 1-based number except to custom names. Names
+/// are cut so they stay within [`MAX_BASE_FILENAME_BYTES`].
+///
+/// # Arguments
+///
+/// * `photo` - The photo being saved
+/// * `index` - Optional index for numbering purposes
+/// * `custom_filename` - Optional custom filename to use instead of the caption
+///
+/// # Returns
+///
+/// The base filename without directory or extension
+pub fn photo_base_filename(
+    photo: &Image,
+    index: Option<usize>,
+    custom_filename: Option<&str>,
+) -> String {
+    // Custom names and captions are sanitized, and the photo_guid is always
+    // included for uniqueness
+    let name = custom_filename
+        .or(photo.caption.as_deref())
+        .map(sanitize_filename)
+        .filter(|name| !name.is_empty());
+    if let Some(name) = name {
+        let prefix = match index.filter(|_| custom_filename.is_none()) {
+            Some(idx) => format!("{}_{}_", idx + 1, photo.photo_guid),
+            None => format!("{}_", photo.photo_guid),
+        };
+        let budget = MAX_BASE_FILENAME_BYTES.saturating_sub(prefix.len());
+        format!("{}{}", prefix, truncate_at_char_boundary(&name, budget))
+    } else if let Some(idx) = index {
+        format!("{}_{}", idx + 1, photo.photo_guid)
+    } else {
+        photo.photo_guid.clone()
+    }
+}
+
 /// Selects the best derivative based on resolution and other criteria
 ///
 /// This function implements a smarter algorithm for selecting the best derivative:
@@ -172,17 +280,12 @@ pub fn select_best_derivative(
     // First pass: check for original or highest resolution with dimensions
     for (key, derivative) in derivatives {
         // Skip derivatives without URLs
-        if derivative.url.is_none() {
+        let Some(url) = derivative.url.as_ref() else {
             continue;
-        }
+        };
 
-        let url = derivative.url.as_ref().unwrap();
-
-        // Check if this is likely an original (by key name pattern)
-        let is_original = key.to_lowercase().contains("original") || 
-                          key.to_lowercase().contains("full") || 
-                          key == "3" || // iCloud often uses "3" as original
-                          key == "4"; // Sometimes "4" is the highest quality
+        // Check if this is likely an original (by key name or known key table)
+        let is_original = derivatives::is_original_key(key);
 
         if is_original {
             has_original = true;
@@ -226,3 +329,409 @@ pub fn select_best_derivative(
 
     best_derivative
 }
+
+/// Selects the smallest still-image derivative, for use as a preview
+///
+/// Unlike [`select_best_derivative`], this does not require the derivative to
+/// have a URL, so it can be used before photos are enriched. Video renditions
+/// are skipped; among the remaining derivatives the one with the lowest
+/// resolution wins, falling back to the smallest file size when dimensions are
+/// unknown.
+///
+/// # Arguments
+///
+/// * `derivatives` - HashMap of derivative key to Derivative
+///
+/// # Returns
+///
+/// An Option containing the derivative key and Derivative if any still image exists
+pub fn select_thumbnail_derivative(
+    derivatives: &HashMap<String, Derivative>,
+) -> Option<(String, &Derivative)> {
+    derivatives
+        .iter()
+        .filter(|(key, derivative)| {
+            derivatives::classify_derivative(key, derivative) != Some(SizeClass::Video)
+        })
+        .min_by_key(|(key, derivative)| {
+            let resolution = match (derivative.width, derivative.height) {
+                (Some(width), Some(height)) => width as u64 * height as u64,
+                _ => u64::MAX,
+            };
+            // Ties are broken by file size, then key, for a deterministic choice
+            (
+                resolution,
+                derivative.file_size.unwrap_or(u64::MAX),
+                (*key).clone(),
+            )
+        })
+        .map(|(key, derivative)| (key.clone(), derivative))
+}
+
+/// Selects the largest derivative of a size class that has a URL
+///
+/// Derivatives are classified with [`derivatives::classify_derivative`], so
+/// declared types take precedence over key names, and derivatives whose
+/// declared state is still pending are passed over.
+///
+/// # Arguments
+///
+/// * `derivatives` - HashMap of derivative key to Derivative
+/// * `size_class` - The size class to select from
+///
+/// # Returns
+///
+/// An Option containing the derivative key and Derivative
+pub fn select_derivative_of_class(
+    derivatives: &HashMap<String, Derivative>,
+    size_class: SizeClass,
+) -> Option<(String, &Derivative)> {
+    derivatives
+        .iter()
+        .filter(|(key, derivative)| {
+            derivative.url.is_some()
+                && !derivative.is_pending()
+                && derivatives::classify_derivative(key, derivative) == Some(size_class)
+        })
+        .max_by_key(|(key, derivative)| {
+            let resolution = match (derivative.width, derivative.height) {
+                (Some(width), Some(height)) => width as u64 * height as u64,
+                _ => 0,
+            };
+            // Ties are broken by file size, then key, for a deterministic choice
+            (
+                resolution,
+                derivative.file_size.unwrap_or(0),
+                std::cmp::Reverse((*key).clone()),
+            )
+        })
+        .map(|(key, derivative)| (key.clone(), derivative))
+}
+
+/// Keys whose string values are personal names or free text
+const ANONYMIZED_TEXT_KEYS: &[&str] = &[
+    "streamName",
+    "userFirstName",
+    "userLastName",
+    "userFullName",
+    "ownerFullName",
+    "contributorFirstName",
+    "contributorLastName",
+    "contributorFullName",
+    "caption",
+    "placeName",
+];
+
+/// Keys whose string values are identifiers that link data together
+const PSEUDONYMIZED_ID_KEYS: &[&str] = &[
+    "photoGuid",
+    "batchGuid",
+    "checksum",
+    "streamCtag",
+    "ownerId",
+    "ownerDsid",
+    "userDsid",
+    "contributorDsid",
+];
+
+/// Replaces personal data in captured API responses with stable placeholders
+///
+/// Identifiers (photo GUIDs, checksums, owner ids) are pseudonymized: the same
+/// value is always replaced by the same placeholder, so a webstream response and
+/// the webasseturls response for its checksums stay consistent when they are
+/// passed through the same anonymizer. Names and captions are replaced, location
+/// data is dropped and the query strings of asset paths (which hold the URL
+/// signatures) are removed.
+#[derive(Debug, Default, Clone)]
+pub struct ResponseAnonymizer {
+    pseudonyms: HashMap<String, String>,
+}
+
+impl ResponseAnonymizer {
+    /// Create an anonymizer with no pseudonyms assigned yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the placeholder for an identifier, assigning a new one if needed
+    fn pseudonym(&mut self, id: &str) -> String {
+        let next = self.pseudonyms.len() + 1;
+        self.pseudonyms
+            .entry(id.to_string())
+            .or_insert_with(|| format!("ANON{:06}", next))
+            .clone()
+    }
+
+    /// Anonymizes a captured webstream or webasseturls response
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The JSON body of the response
+    ///
+    /// # Returns
+    ///
+    /// The anonymized JSON, with the same structure as the input
+    pub fn anonymize(&mut self, value: serde_json::Value) -> serde_json::Value {
+        use serde_json::Value;
+
+        match value {
+            Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(key, value)| {
+                        let value = self.anonymize_field(&key, value);
+                        (key, value)
+                    })
+                    .collect(),
+            ),
+            Value::Array(values) => {
+                Value::Array(values.into_iter().map(|v| self.anonymize(v)).collect())
+            }
+            other => other,
+        }
+    }
+
+    /// Anonymizes the value of a single object field
+    fn anonymize_field(&mut self, key: &str, value: serde_json::Value) -> serde_json::Value {
+        use serde_json::Value;
+
+        match (key, value) {
+            (key, Value::String(_)) if ANONYMIZED_TEXT_KEYS.contains(&key) => {
+                Value::String(format!("Anonymized {}", key))
+            }
+            (key, Value::String(id)) if PSEUDONYMIZED_ID_KEYS.contains(&key) => {
+                Value::String(self.pseudonym(&id))
+            }
+            ("photoGuids", Value::Array(ids)) => Value::Array(
+                ids.into_iter()
+                    .map(|id| match id {
+                        Value::String(id) => Value::String(self.pseudonym(&id)),
+                        other => other,
+                    })
+                    .collect(),
+            ),
+            // webasseturls items are keyed by checksum
+            ("items", Value::Object(items)) => Value::Object(
+                items
+                    .into_iter()
+                    .map(|(checksum, item)| (self.pseudonym(&checksum), self.anonymize(item)))
+                    .collect(),
+            ),
+            ("url_path", Value::String(path)) => {
+                let path = match path.split_once('?') {
+                    Some((path, _signature)) => format!("{}?anonymized", path),
+                    None => path,
+                };
+                Value::String(path)
+            }
+            ("locations", _) => Value::Object(serde_json::Map::new()),
+            (_, value) => self.anonymize(value),
+        }
+    }
+}
+
+/// Strips personal data from a captured API response for use in bug reports
+///
+/// This is a shorthand for [`ResponseAnonymizer::anonymize`] with a fresh
+/// anonymizer. To anonymize a webstream and a webasseturls response of the same
+/// album consistently, use one [`ResponseAnonymizer`] for both.
+///
+/// # Arguments
+///
+/// * `value` - The JSON body of a webstream or webasseturls response
+///
+/// # Returns
+///
+/// The anonymized JSON
+pub fn anonymize_response(value: serde_json::Value) -> serde_json::Value {
+    ResponseAnonymizer::new().anonymize(value)
+}
+
+/// Returns the pixel count used to rank photos within a burst
+///
+/// Uses the dimensions of the original when known, and the largest derivative otherwise.
+fn photo_resolution(photo: &Image) -> u64 {
+    match (photo.width, photo.height) {
+        (Some(width), Some(height)) => width as u64 * height as u64,
+        _ => photo
+            .derivatives
+            .values()
+            .filter_map(|d| Some(d.width? as u64 * d.height? as u64))
+            .max()
+            .unwrap_or(0),
+    }
+}
+
+/// Groups photos taken in quick succession into bursts
+///
+/// Photos are ordered by [`Image::timestamp_taken`], and a photo taken at most
+/// `window_secs` seconds after the previous one joins its burst, so a burst can
+/// span longer than the window as long as there are no gaps. Photos without a
+/// parseable date each form a burst of their own, after the dated ones.
+///
+/// # Arguments
+///
+/// * `photos` - The photos to cluster
+/// * `window_secs` - Maximum gap between consecutive photos of a burst
+///
+/// # Returns
+///
+/// The bursts in chronological order, each in chronological order
+pub fn cluster_bursts(photos: &[Image], window_secs: u64) -> Vec<Vec<&Image>> {
+    let mut dated: Vec<(i64, &Image)> = Vec::new();
+    let mut undated: Vec<&Image> = Vec::new();
+    for photo in photos {
+        match photo.timestamp_taken() {
+            Some(timestamp) => dated.push((timestamp, photo)),
+            None => undated.push(photo),
+        }
+    }
+    dated.sort_by_key(|(timestamp, _)| *timestamp);
+
+    let mut bursts: Vec<Vec<&Image>> = Vec::new();
+    let mut previous: Option<i64> = None;
+    for (timestamp, photo) in dated {
+        match (previous, bursts.last_mut()) {
+            (Some(prev), Some(burst)) if timestamp.abs_diff(prev) <= window_secs => {
+                burst.push(photo)
+            }
+            _ => bursts.push(vec![photo]),
+        }
+        previous = Some(timestamp);
+    }
+    bursts.extend(undated.into_iter().map(|photo| vec![photo]));
+    bursts
+}
+
+/// Picks the best photo of each burst, for downloading without burst duplicates
+///
+/// The best photo is the one with the largest resolution; ties go to the
+/// earliest photo of the burst.
+///
+/// # Arguments
+///
+/// * `photos` - The photos to cluster
+/// * `window_secs` - Maximum gap between consecutive photos of a burst
+///
+/// # Returns
+///
+/// One photo per burst, in chronological order
+pub fn best_of_bursts(photos: &[Image], window_secs: u64) -> Vec<&Image> {
+    cluster_bursts(photos, window_secs)
+        .into_iter()
+        .filter_map(|burst| {
+            burst
+                .into_iter()
+                .rev()
+                .max_by_key(|photo| photo_resolution(photo))
+        })
+        .collect()
+}
+
+/// How [`select_per_day`] ranks the photos of a day
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DayRanking {
+    /// Largest resolution first
+    #[default]
+    Resolution,
+    /// Most recently taken first
+    Recency,
+}
+
+/// Keeps at most `per_day` photos for each day, e.g. for photo frame syncs
+///
+/// Photos are grouped by [`Image::date_taken`]; photos without a parseable date
+/// are not selected. Ties are broken by the order of `photos`.
+///
+/// # Arguments
+///
+/// * `photos` - The photos to select from
+/// * `per_day` - Maximum number of photos kept per day
+/// * `ranking` - Which photos of a day are kept
+///
+/// # Returns
+///
+/// The selected photos, day by day in chronological order, best first within a day
+pub fn select_per_day(photos: &[Image], per_day: usize, ranking: DayRanking) -> Vec<&Image> {
+    let mut days: BTreeMap<CalendarDate, Vec<&Image>> = BTreeMap::new();
+    for photo in photos {
+        if let Some(date) = photo.date_taken() {
+            days.entry(date).or_default().push(photo);
+        }
+    }
+
+    days.into_values()
+        .flat_map(|mut day| {
+            // Stable sorts keep the album order for ties
+            match ranking {
+                DayRanking::Resolution => {
+                    day.sort_by_key(|photo| std::cmp::Reverse(photo_resolution(photo)))
+                }
+                DayRanking::Recency => {
+                    day.sort_by_key(|photo| std::cmp::Reverse(photo.timestamp_taken()))
+                }
+            }
+            day.truncate(per_day);
+            day
+        })
+        .collect()
+}
+
+/// Standard base64 alphabet
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes as standard base64 with padding
+pub(crate) fn encode_base64(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let buffer = chunk.iter().enumerate().fold(0u32, |buffer, (i, byte)| {
+            buffer | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(BASE64_ALPHABET[(buffer >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+/// Decodes standard base64 with optional padding
+pub(crate) fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=');
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in input.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = buffer << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+    Some(output)
+}
+
+/// 64-bit FNV-1a with a final avalanche step, so similar inputs land far
+/// apart; unlike the standard hasher it is stable across releases
+pub(crate) fn stable_hash(bytes: impl Iterator<Item = u8>) -> u64 {
+    let mut hash = bytes.fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ hash >> 33
+}
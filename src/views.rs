@@ -0,0 +1,247 @@
+//! Symlink views over a flat download directory.
+//!
+//! Photos are stored once in a canonical location (tracked by
+//! [`SyncState`]). This module maintains auxiliary directory trees of symbolic
+//! links that organize the same files differently, without duplicating bytes:
+//!
+//! * `by-date/<year>/<month>/` - grouped by the photo's creation date
+//! * `by-contributor/<name>/` - grouped by the person who added the photo
+//! * `latest/` - the most recently created photos
+//!
+//! Views are rebuilt from scratch on every call. Only symbolic links and the
+//! directories that become empty are removed from the view trees, so regular
+//! files placed there by hand are never deleted.
+
+use crate::logging;
+use crate::models::Image;
+use crate::sync::SyncState;
+use crate::utils;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+/// Directory name of the by-date view
+pub const BY_DATE_DIR: &str = "by-date";
+/// Directory name of the by-contributor view
+pub const BY_CONTRIBUTOR_DIR: &str = "by-contributor";
+/// Directory name of the latest-photos view
+pub const LATEST_DIR: &str = "latest";
+
+/// Group name used when a photo's date or contributor is unknown
+const UNKNOWN: &str = "unknown";
+
+/// Which views [`build_views`] maintains
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ViewOptions {
+    /// Maintain the `by-date` view
+    pub by_date: bool,
+    /// Maintain the `by-contributor` view
+    pub by_contributor: bool,
+    /// Maintain a `latest` view with this many photos
+    pub latest: Option<usize>,
+}
+
+impl ViewOptions {
+    /// Options enabling every view, with `latest` photos in the latest view
+    pub fn all(latest: usize) -> Self {
+        Self {
+            by_date: true,
+            by_contributor: true,
+            latest: Some(latest),
+        }
+    }
+
+    /// Create options maintaining no view
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether the `by-date` view is maintained
+    #[must_use]
+    pub fn with_by_date(mut self, by_date: bool) -> Self {
+        self.by_date = by_date;
+        self
+    }
+
+    /// Sets whether the `by-contributor` view is maintained
+    #[must_use]
+    pub fn with_by_contributor(mut self, by_contributor: bool) -> Self {
+        self.by_contributor = by_contributor;
+        self
+    }
+
+    /// Maintains a `latest` view with this many photos, or none
+    #[must_use]
+    pub fn with_latest(mut self, latest: Option<usize>) -> Self {
+        self.latest = latest;
+        self
+    }
+}
+
+/// Returns the (year, month) of a photo's creation date, if it can be parsed
+///
+/// iCloud dates are ISO 8601 strings such as `2023-01-31T12:00:00Z`; only the
+/// leading `YYYY-MM` is used.
+fn year_month(photo: &Image) -> Option<(String, String)> {
+    let date = photo
+        .date_created
+        .as_deref()
+        .or(photo.batch_date_created.as_deref())?;
+    let year = date.get(0..4)?;
+    let month = date.get(5..7)?;
+    let all_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    (all_digits(year) && all_digits(month) && date.get(4..5) == Some("-"))
+        .then(|| (year.to_string(), month.to_string()))
+}
+
+/// Makes a contributor name safe to use as a directory name
+///
+/// Uses the same rules as downloaded file names, see
+/// [`utils::sanitize_filename`].
+pub(crate) fn sanitize_component(name: &str) -> String {
+    let sanitized = utils::sanitize_filename(name);
+    if sanitized.is_empty() || sanitized == "." || sanitized == ".." {
+        UNKNOWN.to_string()
+    } else {
+        sanitized
+    }
+}
+
+/// Removes the symbolic links in a view tree and any directories left empty
+///
+/// Boxed by hand, as the recursion needs a boxed future and `async fn`
+/// recursion is newer than the minimum supported Rust version.
+fn clear_links(dir: &Path) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send + '_>> {
+    Box::pin(async move {
+        let mut entries = match tokio::fs::read_dir(dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            if file_type.is_symlink() {
+                tokio::fs::remove_file(entry.path()).await?;
+            } else if file_type.is_dir() {
+                clear_links(&entry.path()).await?;
+            }
+        }
+
+        // Directories still holding regular files are kept
+        let _ = tokio::fs::remove_dir(dir).await;
+        Ok(())
+    })
+}
+
+/// Creates a symbolic link at `link` pointing to `target`
+async fn create_link(target: &Path, link: &Path) -> io::Result<()> {
+    if let Some(parent) = link.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    #[cfg(unix)]
+    {
+        tokio::fs::symlink(target, link).await
+    }
+    #[cfg(windows)]
+    {
+        tokio::fs::symlink_file(target, link).await
+    }
+}
+
+/// Rebuilds the symlink views for an album
+///
+/// Each photo that has a recorded, existing file in `state` is linked into the
+/// enabled views under `views_root`. Link targets are absolute paths, so the
+/// view trees can live anywhere. Photos without a file are skipped.
+///
+/// # Arguments
+///
+/// * `views_root` - Directory under which the view trees are maintained
+/// * `photos` - The album's photos
+/// * `state` - Sync state mapping photo GUIDs to downloaded files
+/// * `options` - Which views to maintain
+///
+/// # Returns
+///
+/// The paths of all links created, in creation order
+pub async fn build_views(
+    views_root: impl AsRef<Path>,
+    photos: &[Image],
+    state: &SyncState,
+    options: &ViewOptions,
+) -> io::Result<Vec<PathBuf>> {
+    let root = views_root.as_ref();
+
+    // Resolve each photo's canonical file once
+    let mut stored: Vec<(&Image, PathBuf)> = Vec::new();
+    for photo in photos {
+        let Some(file) = state.file(&photo.photo_guid) else {
+            continue;
+        };
+        match tokio::fs::canonicalize(&file.path).await {
+            Ok(target) => stored.push((photo, target)),
+            Err(e) => logging::log_warn!(
+                logging::SYNC,
+                "Skipping {} in views: cannot resolve {}: {}",
+                photo.photo_guid,
+                file.path.display(),
+                e
+            ),
+        }
+    }
+
+    let mut created = Vec::new();
+    let file_name = |target: &Path| target.file_name().unwrap_or_default().to_os_string();
+
+    if options.by_date {
+        let dir = root.join(BY_DATE_DIR);
+        clear_links(&dir).await?;
+        for (photo, target) in &stored {
+            let group = match year_month(photo) {
+                Some((year, month)) => dir.join(year).join(month),
+                None => dir.join(UNKNOWN),
+            };
+            let link = group.join(file_name(target));
+            create_link(target, &link).await?;
+            created.push(link);
+        }
+    }
+
+    if options.by_contributor {
+        let dir = root.join(BY_CONTRIBUTOR_DIR);
+        clear_links(&dir).await?;
+        for (photo, target) in &stored {
+            let name = photo
+                .contributor_full_name
+                .as_deref()
+                .map(sanitize_component)
+                .unwrap_or_else(|| UNKNOWN.to_string());
+            let link = dir.join(name).join(file_name(target));
+            create_link(target, &link).await?;
+            created.push(link);
+        }
+    }
+
+    if let Some(count) = options.latest {
+        let dir = root.join(LATEST_DIR);
+        clear_links(&dir).await?;
+
+        // ISO 8601 dates sort chronologically as strings; undated photos sort last
+        let mut by_recency: Vec<&(&Image, PathBuf)> = stored.iter().collect();
+        by_recency.sort_by(|(a, _), (b, _)| {
+            b.date_created
+                .cmp(&a.date_created)
+                .then_with(|| a.photo_guid.cmp(&b.photo_guid))
+        });
+        for (_, target) in by_recency.into_iter().take(count) {
+            let link = dir.join(file_name(target));
+            create_link(target, &link).await?;
+            created.push(link);
+        }
+    }
+
+    Ok(created)
+}
@@ -0,0 +1,756 @@
+//! Change detection for followed albums.
+//!
+//! This module provides [`AlbumWatcher`], which polls a shared album and turns
+//! the differences between successive fetches into [`AlbumEvent`]s, and the
+//! traits through which those events are delivered: [`Notifier`] for cheap,
+//! synchronous reactions and [`EventSink`] for asynchronous, fallible delivery
+//! such as email (see [`crate::sinks`]). The watcher only fetches album metadata
+//! and photo information; asset URLs are not resolved. [`EventFilter`]s drop
+//! uninteresting events at the source, e.g. with [`only_videos`] for busy
+//! albums. A [`Shutdown`] signal stops a running watcher.
+
+use crate::derivatives::{self, SizeClass};
+use crate::logging;
+use crate::models::{Image, Metadata, StreamCtag, WebstreamRequest};
+use crate::redact::Redacted;
+use crate::scheduler::Jitter;
+use crate::shutdown::Shutdown;
+use crate::{api, redirect, utils};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// A change detected between two fetches of an album
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum AlbumEvent {
+    /// A photo appeared in the album
+    PhotoAdded {
+        /// GUID of the new photo
+        photo_guid: String,
+        /// Caption of the new photo, if any
+        caption: Option<String>,
+        /// Creation date of the new photo, if known
+        date_created: Option<String>,
+        /// URL of a small preview of the photo
+        ///
+        /// Only resolved when the watcher was built with
+        /// [`AlbumWatcher::with_thumbnails`].
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        thumbnail_url: Option<String>,
+        /// The preview itself, for sinks that attach or inline it
+        ///
+        /// Only downloaded when the watcher was built with
+        /// [`AlbumWatcher::with_thumbnail_bytes`] and the preview fits the cap.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        thumbnail: Option<ThumbnailData>,
+    },
+    /// A photo disappeared from the album
+    PhotoRemoved {
+        /// GUID of the removed photo
+        photo_guid: String,
+    },
+    /// The caption of an existing photo changed
+    CaptionChanged {
+        /// GUID of the photo
+        photo_guid: String,
+        /// Caption before the change
+        old_caption: Option<String>,
+        /// Caption after the change
+        new_caption: Option<String>,
+    },
+}
+
+/// A downloaded preview image attached to a [`AlbumEvent::PhotoAdded`]
+///
+/// Serialized with the bytes in base64, so JSON sinks carry it inline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThumbnailData {
+    /// MIME type of the image, e.g. `image/jpeg`
+    pub content_type: String,
+    /// The image
+    #[serde(with = "base64_bytes")]
+    pub bytes: Vec<u8>,
+}
+
+impl ThumbnailData {
+    /// Returns the image as a `data:` URL, e.g. for HTML email bodies
+    pub fn data_url(&self) -> String {
+        format!(
+            "data:{};base64,{}",
+            self.content_type,
+            utils::encode_base64(&self.bytes)
+        )
+    }
+}
+
+/// (De)serializes bytes as a base64 string
+mod base64_bytes {
+    use crate::utils;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&utils::encode_base64(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        utils::decode_base64(&encoded).ok_or_else(|| de::Error::custom("invalid base64"))
+    }
+}
+
+impl AlbumEvent {
+    /// Returns the GUID of the photo the event relates to
+    pub fn photo_guid(&self) -> &str {
+        match self {
+            AlbumEvent::PhotoAdded { photo_guid, .. } => photo_guid,
+            AlbumEvent::PhotoRemoved { photo_guid } => photo_guid,
+            AlbumEvent::CaptionChanged { photo_guid, .. } => photo_guid,
+        }
+    }
+}
+
+impl fmt::Display for AlbumEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlbumEvent::PhotoAdded {
+                photo_guid,
+                caption: Some(caption),
+                ..
+            } => write!(f, "Photo added: {} ({})", photo_guid, caption),
+            AlbumEvent::PhotoAdded { photo_guid, .. } => write!(f, "Photo added: {}", photo_guid),
+            AlbumEvent::PhotoRemoved { photo_guid } => write!(f, "Photo removed: {}", photo_guid),
+            AlbumEvent::CaptionChanged {
+                photo_guid,
+                new_caption,
+                ..
+            } => write!(
+                f,
+                "Caption changed: {} -> {}",
+                photo_guid,
+                new_caption.as_deref().unwrap_or("(none)")
+            ),
+        }
+    }
+}
+
+/// Computes the events that turn `previous` into `current`
+///
+/// Events are ordered as: additions (in `current` order), removals (in
+/// `previous` order), then caption changes (in `current` order).
+///
+/// # Arguments
+///
+/// * `previous` - Photos from the earlier fetch
+/// * `current` - Photos from the later fetch
+///
+/// # Returns
+///
+/// The list of detected changes, empty if nothing changed
+pub fn diff_albums(previous: &[Image], current: &[Image]) -> Vec<AlbumEvent> {
+    let previous_by_guid: HashMap<&str, &Image> = previous
+        .iter()
+        .map(|photo| (photo.photo_guid.as_str(), photo))
+        .collect();
+    let current_guids: HashSet<&str> = current.iter().map(|p| p.photo_guid.as_str()).collect();
+
+    let mut events = Vec::new();
+
+    for photo in current {
+        if !previous_by_guid.contains_key(photo.photo_guid.as_str()) {
+            events.push(AlbumEvent::PhotoAdded {
+                photo_guid: photo.photo_guid.clone(),
+                caption: photo.caption.clone(),
+                date_created: photo.date_created.clone(),
+                thumbnail_url: None,
+                thumbnail: None,
+            });
+        }
+    }
+
+    for photo in previous {
+        if !current_guids.contains(photo.photo_guid.as_str()) {
+            events.push(AlbumEvent::PhotoRemoved {
+                photo_guid: photo.photo_guid.clone(),
+            });
+        }
+    }
+
+    for photo in current {
+        if let Some(old) = previous_by_guid.get(photo.photo_guid.as_str()) {
+            if old.caption != photo.caption {
+                events.push(AlbumEvent::CaptionChanged {
+                    photo_guid: photo.photo_guid.clone(),
+                    old_caption: old.caption.clone(),
+                    new_caption: photo.caption.clone(),
+                });
+            }
+        }
+    }
+
+    events
+}
+
+/// Receives the events detected by an [`AlbumWatcher`]
+pub trait Notifier: Send + Sync {
+    /// Called once per poll that detected at least one event
+    ///
+    /// # Arguments
+    ///
+    /// * `album` - Metadata of the album the events belong to
+    /// * `events` - The events detected by the poll, never empty
+    fn notify(&self, album: &Metadata, events: &[AlbumEvent]);
+}
+
+/// Error returned by an [`EventSink`] that failed to deliver events
+pub type SinkError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Future returned by [`EventSink::deliver`]
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = Result<(), SinkError>> + Send + 'a>>;
+
+/// Asynchronous destination for the events detected by an [`AlbumWatcher`]
+///
+/// Unlike [`Notifier`], sinks may perform network I/O and report failures. The
+/// watcher logs delivery failures and keeps polling.
+pub trait EventSink: Send + Sync {
+    /// Delivers the events detected by one poll
+    ///
+    /// # Arguments
+    ///
+    /// * `album` - Metadata of the album the events belong to
+    /// * `events` - The events detected by the poll, never empty
+    fn deliver<'a>(&'a self, album: &'a Metadata, events: &'a [AlbumEvent]) -> SinkFuture<'a>;
+}
+
+/// Boxed sinks, e.g. ones built from [`crate::app_config::SinkSettings`]
+impl<S: EventSink + ?Sized> EventSink for Box<S> {
+    fn deliver<'a>(&'a self, album: &'a Metadata, events: &'a [AlbumEvent]) -> SinkFuture<'a> {
+        (**self).deliver(album, events)
+    }
+}
+
+/// Decides which detected events an [`AlbumWatcher`] reports
+///
+/// Closures taking the same arguments are filters too, so one-off predicates
+/// need no type of their own.
+pub trait EventFilter: Send + Sync {
+    /// Whether `event` is reported to notifiers and sinks
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The detected event
+    /// * `photo` - The photo the event relates to: as fetched for additions and
+    ///   caption changes, as last seen for removals
+    fn accepts(&self, event: &AlbumEvent, photo: Option<&Image>) -> bool;
+}
+
+impl<F> EventFilter for F
+where
+    F: Fn(&AlbumEvent, Option<&Image>) -> bool + Send + Sync,
+{
+    fn accepts(&self, event: &AlbumEvent, photo: Option<&Image>) -> bool {
+        self(event, photo)
+    }
+}
+
+/// Filter reporting only events about videos
+pub fn only_videos() -> impl EventFilter {
+    |_: &AlbumEvent, photo: Option<&Image>| {
+        // Asset URLs aren't resolved yet, so derivatives are classified directly
+        photo.is_some_and(|photo| {
+            photo.derivatives.iter().any(|(key, derivative)| {
+                derivatives::classify_derivative(key, derivative) == Some(SizeClass::Video)
+            })
+        })
+    }
+}
+
+/// Filter reporting only photo additions
+pub fn only_additions() -> impl EventFilter {
+    |event: &AlbumEvent, _: Option<&Image>| matches!(event, AlbumEvent::PhotoAdded { .. })
+}
+
+/// Filter reporting only events about photos whose caption contains `keyword`
+///
+/// The comparison ignores case. For caption changes, the new caption counts.
+pub fn caption_contains(keyword: &str) -> impl EventFilter {
+    let keyword = keyword.to_lowercase();
+    move |_: &AlbumEvent, photo: Option<&Image>| {
+        photo
+            .and_then(|photo| photo.caption.as_deref())
+            .is_some_and(|caption| caption.to_lowercase().contains(&keyword))
+    }
+}
+
+/// Polls a shared album and reports changes to registered notifiers
+///
+/// The first poll establishes a baseline and reports no events. Later polls
+/// compare against the previous result; when the album's `streamCtag` is
+/// unchanged the photo comparison is skipped entirely.
+pub struct AlbumWatcher {
+    token: String,
+    client: reqwest::Client,
+    interval: Duration,
+    jitter: Jitter,
+    notifiers: Vec<Box<dyn Notifier>>,
+    sinks: Vec<Box<dyn EventSink>>,
+    filters: Vec<Box<dyn EventFilter>>,
+    resolve_thumbnails: bool,
+    thumbnail_max_bytes: Option<u64>,
+    last_ctag: Option<StreamCtag>,
+    known_photos: Option<Vec<Image>>,
+    shutdown: Shutdown,
+}
+
+impl AlbumWatcher {
+    /// Default time between polls
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+
+    /// Create a watcher for the album with the given token
+    pub fn new(token: &str) -> Self {
+        Self {
+            token: token.to_string(),
+            client: reqwest::Client::new(),
+            interval: Self::DEFAULT_INTERVAL,
+            jitter: Jitter::default(),
+            notifiers: Vec::new(),
+            sinks: Vec::new(),
+            filters: Vec::new(),
+            resolve_thumbnails: false,
+            thumbnail_max_bytes: None,
+            last_ctag: None,
+            known_photos: None,
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    /// Set the time between polls used by [`AlbumWatcher::run`]
+    #[must_use]
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Vary the interval by a fixed amount derived from the token, see [`Jitter`]
+    ///
+    /// Spreads the polls of watchers started together, in one process or in
+    /// many deployments, that would otherwise hit iCloud at the same second.
+    #[must_use]
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Send requests through `client`, e.g. one built from a [`crate::net::NetworkConfig`]
+    #[must_use]
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Register a notifier to receive detected events
+    #[must_use]
+    pub fn with_notifier(mut self, notifier: impl Notifier + 'static) -> Self {
+        self.notifiers.push(Box::new(notifier));
+        self
+    }
+
+    /// Register an asynchronous sink to receive detected events
+    #[must_use]
+    pub fn with_sink(mut self, sink: impl EventSink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Only report events `filter` accepts
+    ///
+    /// With several filters, an event is reported if every filter accepts it.
+    /// Filtered events are dropped before thumbnails are resolved, so they
+    /// cost no requests either.
+    #[must_use]
+    pub fn with_filter(mut self, filter: impl EventFilter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Resolve a preview URL for every added photo during [`AlbumWatcher::poll_once`]
+    ///
+    /// This costs one extra asset URL request per poll that detects additions.
+    #[must_use]
+    pub fn with_thumbnails(mut self, enabled: bool) -> Self {
+        self.resolve_thumbnails = enabled;
+        self
+    }
+
+    /// Also download previews of up to `max_bytes` and attach them to additions
+    ///
+    /// Implies [`AlbumWatcher::with_thumbnails`]. Previews known or found to
+    /// be larger are only reported by URL; downloads that fail are logged and
+    /// skipped.
+    #[must_use]
+    pub fn with_thumbnail_bytes(mut self, max_bytes: u64) -> Self {
+        self.resolve_thumbnails = true;
+        self.thumbnail_max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Stop when `shutdown` is triggered, e.g. a signal shared with other components
+    #[must_use]
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Returns the album token being watched
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Returns the watcher's shutdown signal, for stopping it from another task
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Stops the watcher, see [`AlbumWatcher::run`]
+    pub fn shutdown(&self) {
+        self.shutdown.trigger();
+    }
+
+    /// Fetches the album once and reports any changes since the previous poll
+    ///
+    /// If the shutdown signal is triggered while the album is being fetched,
+    /// the fetch is cancelled and nothing is reported. Once fetched, events are
+    /// always delivered in full.
+    ///
+    /// # Returns
+    ///
+    /// The events detected by this poll (empty on the first poll)
+    pub async fn poll_once(&mut self) -> Result<Vec<AlbumEvent>, Box<dyn std::error::Error>> {
+        // Cloning shares the connection pool
+        let client = self.client.clone();
+        let Some(fetched) = self.shutdown.run_until(self.fetch(&client)).await else {
+            return Ok(Vec::new());
+        };
+        let (redirected_url, photos, metadata) = fetched?;
+
+        let mut events = self.detect(&metadata, photos);
+        if self.resolve_thumbnails {
+            if let Err(e) = self
+                .attach_thumbnails(&client, &redirected_url, &mut events)
+                .await
+            {
+                logging::log_warn!(
+                    logging::WATCH,
+                    "Failed to resolve thumbnail URLs: {}",
+                    Redacted(&e)
+                );
+            }
+        }
+
+        self.notify(&metadata, &events);
+        self.dispatch(&metadata, &events).await;
+        Ok(events)
+    }
+
+    /// Fetches the album's photos and metadata
+    ///
+    /// # Returns
+    ///
+    /// The redirected base URL, the photos and the metadata
+    async fn fetch(
+        &self,
+        client: &reqwest::Client,
+    ) -> Result<(String, Vec<Image>, Metadata), Box<dyn std::error::Error>> {
+        let probe =
+            redirect::probe_album(client, &self.token, &WebstreamRequest::default()).await?;
+
+        let redirected_url = probe.base_url().to_string();
+        let response = match probe.into_body() {
+            Some(body) => api::parse_webstream_response(&body)?,
+            None => api::get_api_response(client, &redirected_url).await?,
+        };
+        Ok((redirected_url, response.photos, response.metadata))
+    }
+
+    /// Fills in `thumbnail_url`, and `thumbnail` if enabled, for every addition in `events`
+    async fn attach_thumbnails(
+        &self,
+        client: &reqwest::Client,
+        base_url: &str,
+        events: &mut [AlbumEvent],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(photos) = &self.known_photos else {
+            return Ok(());
+        };
+
+        // Map each added photo to the checksum and size of its preview derivative
+        let mut checksums: HashMap<String, (String, Option<u64>)> = HashMap::new();
+        for event in events.iter() {
+            if let AlbumEvent::PhotoAdded { photo_guid, .. } = event {
+                let thumbnail = photos
+                    .iter()
+                    .find(|photo| &photo.photo_guid == photo_guid)
+                    .and_then(|photo| utils::select_thumbnail_derivative(&photo.derivatives));
+                if let Some((_key, derivative)) = thumbnail {
+                    checksums.insert(
+                        photo_guid.clone(),
+                        (derivative.checksum.clone(), derivative.file_size),
+                    );
+                }
+            }
+        }
+        if checksums.is_empty() {
+            return Ok(());
+        }
+
+        let guids: Vec<String> = checksums.keys().cloned().collect();
+        let urls = api::get_asset_urls(client, base_url, &guids).await?;
+
+        for event in events.iter_mut() {
+            if let AlbumEvent::PhotoAdded {
+                photo_guid,
+                thumbnail_url,
+                thumbnail,
+                ..
+            } = event
+            {
+                let Some((checksum, file_size)) = checksums.get(photo_guid.as_str()) else {
+                    continue;
+                };
+                *thumbnail_url = urls.get(checksum).cloned();
+                match (self.thumbnail_max_bytes, thumbnail_url.as_deref()) {
+                    (Some(max_bytes), Some(url))
+                        if file_size.map_or(true, |size| size <= max_bytes) =>
+                    {
+                        match fetch_thumbnail(client, url, max_bytes).await {
+                            Ok(data) => *thumbnail = data,
+                            Err(e) => logging::log_warn!(
+                                logging::WATCH,
+                                "Failed to download thumbnail of {}: {}",
+                                photo_guid,
+                                Redacted(&e)
+                            ),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Delivers events to every registered [`EventSink`]
+    ///
+    /// Delivery failures are logged and do not affect other sinks. Nothing is
+    /// delivered if `events` is empty.
+    ///
+    /// # Arguments
+    ///
+    /// * `album` - Metadata of the album the events belong to
+    /// * `events` - The events to deliver
+    pub async fn dispatch(&self, album: &Metadata, events: &[AlbumEvent]) {
+        if events.is_empty() {
+            return;
+        }
+        for sink in &self.sinks {
+            if let Err(e) = sink.deliver(album, events).await {
+                logging::log_warn!(
+                    logging::WATCH,
+                    "Failed to deliver album events: {}",
+                    Redacted(&e)
+                );
+            }
+        }
+    }
+
+    /// Records a freshly fetched album state and reports the changes it implies
+    ///
+    /// This is the pure half of [`AlbumWatcher::poll_once`], useful when the
+    /// album has been fetched by other means. Registered notifiers are called,
+    /// but sinks are not; use [`AlbumWatcher::dispatch`] for those.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - Metadata of the fetched album
+    /// * `photos` - Photos of the fetched album
+    ///
+    /// # Returns
+    ///
+    /// The events detected (empty for the first observation)
+    pub fn observe(&mut self, metadata: &Metadata, photos: Vec<Image>) -> Vec<AlbumEvent> {
+        let events = self.detect(metadata, photos);
+        self.notify(metadata, &events);
+        events
+    }
+
+    /// Records a new album state and returns the changes, without notifying anyone
+    fn detect(&mut self, metadata: &Metadata, photos: Vec<Image>) -> Vec<AlbumEvent> {
+        let ctag_unchanged = metadata
+            .stream_ctag
+            .unchanged_since(self.last_ctag.as_ref());
+        self.last_ctag = Some(metadata.stream_ctag.clone());
+
+        let events = match &self.known_photos {
+            None => Vec::new(),
+            Some(_) if ctag_unchanged => return Vec::new(),
+            Some(previous) => self.filter(diff_albums(previous, &photos), previous, &photos),
+        };
+        self.known_photos = Some(photos);
+        events
+    }
+
+    /// Drops the events a registered filter rejects
+    fn filter(
+        &self,
+        events: Vec<AlbumEvent>,
+        previous: &[Image],
+        current: &[Image],
+    ) -> Vec<AlbumEvent> {
+        if self.filters.is_empty() {
+            return events;
+        }
+        events
+            .into_iter()
+            .filter(|event| {
+                let photos = match event {
+                    AlbumEvent::PhotoRemoved { .. } => previous,
+                    _ => current,
+                };
+                let photo = photos
+                    .iter()
+                    .find(|photo| photo.photo_guid == event.photo_guid());
+                self.filters
+                    .iter()
+                    .all(|filter| filter.accepts(event, photo))
+            })
+            .collect()
+    }
+
+    /// Calls every registered notifier, unless `events` is empty
+    fn notify(&self, metadata: &Metadata, events: &[AlbumEvent]) {
+        if events.is_empty() {
+            return;
+        }
+        for notifier in &self.notifiers {
+            notifier.notify(metadata, events);
+        }
+    }
+
+    /// Polls the album until shut down, sleeping for the configured interval between polls
+    ///
+    /// The interval is varied by the watcher's [`Jitter`], if any.
+    ///
+    /// Poll failures are logged and do not stop the loop. Once the shutdown
+    /// signal is triggered, a fetch in flight is cancelled (see
+    /// [`AlbumWatcher::poll_once`]) and this returns.
+    pub async fn run(&mut self) {
+        let shutdown = self.shutdown.clone();
+        while !shutdown.is_triggered() {
+            if let Err(e) = self.poll_once().await {
+                logging::log_warn!(logging::WATCH, "Polling album failed: {}", Redacted(&e));
+            }
+            let interval = self.jitter.apply(self.interval, &self.token);
+            shutdown.run_until(tokio::time::sleep(interval)).await;
+        }
+        logging::log_debug!(logging::WATCH, "Album watcher stopped");
+    }
+}
+
+/// Downloads a preview, or returns `None` if it turns out larger than `max_bytes`
+async fn fetch_thumbnail(
+    client: &reqwest::Client,
+    url: &str,
+    max_bytes: u64,
+) -> Result<Option<ThumbnailData>, reqwest::Error> {
+    let mut response = client.get(url).send().await?.error_for_status()?;
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_bytes)
+    {
+        return Ok(None);
+    }
+    let header_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if (bytes.len() + chunk.len()) as u64 > max_bytes {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    let content_type = utils::sniff_mime_type(&bytes)
+        .map(str::to_string)
+        .or(header_type)
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    Ok(Some(ThumbnailData {
+        content_type,
+        bytes,
+    }))
+}
+
+/// Notifier that shows a desktop notification when photos are added
+///
+/// Uses `notify-send` on Linux and other Unix systems and `osascript` on macOS.
+/// Failures to launch the notification command are logged and otherwise ignored.
+#[cfg(feature = "desktop-notify")]
+#[derive(Debug, Clone, Default)]
+pub struct DesktopNotifier;
+
+#[cfg(feature = "desktop-notify")]
+impl DesktopNotifier {
+    /// Create a desktop notifier
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Builds the notification text for a batch of events
+    ///
+    /// Only additions are announced; returns `None` if there are none.
+    pub fn message_for(album: &Metadata, events: &[AlbumEvent]) -> Option<String> {
+        let added = events
+            .iter()
+            .filter(|e| matches!(e, AlbumEvent::PhotoAdded { .. }))
+            .count();
+        match added {
+            0 => None,
+            1 => Some(format!("1 new photo in {}", album.stream_name)),
+            n => Some(format!("{} new photos in {}", n, album.stream_name)),
+        }
+    }
+}
+
+#[cfg(feature = "desktop-notify")]
+impl Notifier for DesktopNotifier {
+    fn notify(&self, album: &Metadata, events: &[AlbumEvent]) {
+        let Some(message) = Self::message_for(album, events) else {
+            return;
+        };
+
+        let result = if cfg!(target_os = "macos") {
+            // AppleScript string literals need quotes and backslashes escaped
+            let escaped = message.replace('\\', "\\\\").replace('"', "\\\"");
+            std::process::Command::new("osascript")
+                .arg("-e")
+                .arg(format!(
+                    "display notification \"{}\" with title \"Shared album\"",
+                    escaped
+                ))
+                .spawn()
+        } else {
+            std::process::Command::new("notify-send")
+                .arg("Shared album")
+                .arg(&message)
+                .spawn()
+        };
+
+        if let Err(e) = result {
+            logging::log_warn!(logging::WATCH, "Failed to show desktop notification: {}", e);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! A minimal web UI for self-hosted album viewers.
+//!
+//! The UI is a single static page, [`INDEX_HTML`], that lists the albums an
+//! application mirrors with their sync status and shows the photos of the
+//! selected one as a grid. It needs no build step and no external assets;
+//! [`asset`] returns it for the paths it is served under.
+//!
+//! The serve mode of the `server` feature mounts the UI at `/ui/`. Other
+//! applications serve the page and the two JSON documents it loads,
+//! relative to the page:
+//!
+//! * `albums.json` - a list of [`AlbumStatus`], see [`AlbumStatus::from_state`]
+//! * `albums/{id}.json` - an [`AlbumGrid`], see [`album_grid`]
+//!
+//! Photos are shown through the application's own asset endpoints, e.g. an
+//! asset proxy answering with [`crate::asset_response::respond`]; the URLs
+//! come from a template given to [`album_grid`]. A page opened with an
+//! `access_token` query parameter passes it on to the documents and images.
+
+use crate::derivatives::SizeClass;
+use crate::models::{ICloudResponse, Image, StreamCtag};
+use crate::sync::SyncState;
+use crate::utils;
+use serde::{Deserialize, Serialize};
+
+/// The page of the UI
+pub const INDEX_HTML: &str = include_str!("web_ui/index.html");
+
+/// Returns the content type and content of a UI file, or `None` for other paths
+///
+/// # Arguments
+///
+/// * `path` - The request path relative to where the UI is mounted, e.g. `/`
+pub fn asset(path: &str) -> Option<(&'static str, &'static str)> {
+    match path.trim_start_matches('/') {
+        "" | "index.html" => Some(("text/html; charset=utf-8", INDEX_HTML)),
+        _ => None,
+    }
+}
+
+/// An album in the UI's album list
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlbumStatus {
+    /// Identifies the album in `albums/{id}.json`, e.g. a slug of its name
+    pub id: String,
+    /// Name shown for the album
+    pub name: String,
+    /// Number of photos at the last sync
+    pub photo_count: usize,
+    /// Number of files in the mirror at the last sync
+    pub file_count: usize,
+    /// Total size of the mirrored files at the last sync, in bytes
+    pub bytes: u64,
+    /// When the last sync finished, as a Unix timestamp, or `None` if it never ran
+    pub last_synced_at: Option<u64>,
+    /// `streamCtag` of the album at the last sync
+    pub stream_ctag: Option<StreamCtag>,
+    /// Whether a chunked download is still unfinished, see [`crate::session`]
+    pub pending: bool,
+}
+
+impl AlbumStatus {
+    /// Describes an album from its sync state
+    ///
+    /// The counts come from the latest run recorded with
+    /// [`SyncState::record_run`]; an album that never ran has none.
+    pub fn from_state(id: &str, name: &str, state: &SyncState) -> Self {
+        let last = state.history.last();
+        Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            photo_count: last.map_or(0, |run| run.photo_count),
+            file_count: last.map_or(state.files.len(), |run| run.file_count),
+            bytes: last.map_or(0, |run| run.bytes),
+            last_synced_at: last.map(|run| run.finished_at),
+            stream_ctag: state.stream_ctag.clone(),
+            pending: state.chunk_progress.is_some(),
+        }
+    }
+}
+
+/// A photo in the UI's grid
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GridPhoto {
+    /// GUID of the photo
+    pub photo_guid: String,
+    /// Caption, used as the image's alternative text
+    pub caption: Option<String>,
+    /// URL of the image shown in the grid
+    pub thumbnail_url: String,
+    /// URL opened when the photo is clicked
+    pub url: String,
+    /// Whether the photo is a video
+    pub is_video: bool,
+}
+
+/// The photos of an album, as the UI loads them from `albums/{id}.json`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlbumGrid {
+    /// Identifies the album, as in [`AlbumStatus::id`]
+    pub id: String,
+    /// Name of the album
+    pub name: String,
+    /// The photos, in album order
+    pub photos: Vec<GridPhoto>,
+}
+
+/// Size classes shown in the grid, most preferred first
+const GRID_CLASSES: &[SizeClass] = &[
+    SizeClass::Thumbnail,
+    SizeClass::VideoPoster,
+    SizeClass::Medium,
+];
+
+/// Size classes opened on click, most preferred first
+const FULL_CLASSES: &[SizeClass] = &[
+    SizeClass::Video,
+    SizeClass::Large,
+    SizeClass::Original,
+    SizeClass::Medium,
+];
+
+/// Builds the grid of an album
+///
+/// Each photo links to its assets through `url_template`, in which `{guid}`
+/// is replaced by the photo GUID and `{class}` by the
+/// [`SizeClass::file_suffix`] of the derivative, e.g.
+/// `/proxy/{guid}/{class}`. Photos without a derivative to show are left out.
+///
+/// # Arguments
+///
+/// * `id` - Identifies the album, as in [`AlbumStatus::id`]
+/// * `album` - The fetched album
+/// * `url_template` - URL of an asset on the application's asset endpoints
+pub fn album_grid(id: &str, album: &ICloudResponse, url_template: &str) -> AlbumGrid {
+    let photos = album
+        .photos
+        .iter()
+        .filter_map(|photo| {
+            let thumbnail = preferred_class(photo, GRID_CLASSES)?;
+            let full = preferred_class(photo, FULL_CLASSES).unwrap_or(thumbnail);
+            let url = |size_class: SizeClass| {
+                url_template
+                    .replace("{guid}", &photo.photo_guid)
+                    .replace("{class}", size_class.file_suffix())
+            };
+            Some(GridPhoto {
+                photo_guid: photo.photo_guid.clone(),
+                caption: photo.caption.clone(),
+                thumbnail_url: url(thumbnail),
+                url: url(full),
+                is_video: full == SizeClass::Video,
+            })
+        })
+        .collect();
+    AlbumGrid {
+        id: id.to_string(),
+        name: album.metadata.stream_name.clone(),
+        photos,
+    }
+}
+
+/// The first of `classes` a photo has a derivative of
+fn preferred_class(photo: &Image, classes: &[SizeClass]) -> Option<SizeClass> {
+    classes.iter().copied().find(|&size_class| {
+        utils::select_derivative_of_class(&photo.derivatives, size_class).is_some()
+    })
+}
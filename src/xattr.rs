@@ -0,0 +1,147 @@
+//! Provenance extended attributes for downloaded files.
+//!
+//! This module is only compiled with the `xattr` feature. It records where a
+//! file came from (photo GUID and album name) as extended attributes on the
+//! file itself, so provenance survives moves and renames and can be used to
+//! match files back to photos later.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Attribute holding the photo GUID
+pub const GUID_ATTR: &str = "user.icloud.guid";
+
+/// Attribute holding the album name
+pub const ALBUM_ATTR: &str = "user.icloud.album";
+
+/// Provenance recorded on a downloaded file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    /// GUID of the photo the file was downloaded from
+    pub photo_guid: Option<String>,
+    /// Name of the album the photo belongs to
+    pub album: Option<String>,
+}
+
+/// Converts a path into a C string for the xattr syscalls
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Converts an attribute name into a C string
+fn c_name(name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn raw_set(path: &CString, name: &CString, value: &[u8]) -> libc::c_int {
+    libc::setxattr(
+        path.as_ptr(),
+        name.as_ptr(),
+        value.as_ptr() as *const libc::c_void,
+        value.len(),
+        0,
+    )
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn raw_set(path: &CString, name: &CString, value: &[u8]) -> libc::c_int {
+    libc::setxattr(
+        path.as_ptr(),
+        name.as_ptr(),
+        value.as_ptr() as *const libc::c_void,
+        value.len(),
+        0,
+        0,
+    )
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn raw_get(path: &CString, name: &CString, buffer: &mut [u8]) -> libc::ssize_t {
+    libc::getxattr(
+        path.as_ptr(),
+        name.as_ptr(),
+        buffer.as_mut_ptr() as *mut libc::c_void,
+        buffer.len(),
+    )
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn raw_get(path: &CString, name: &CString, buffer: &mut [u8]) -> libc::ssize_t {
+    libc::getxattr(
+        path.as_ptr(),
+        name.as_ptr(),
+        buffer.as_mut_ptr() as *mut libc::c_void,
+        buffer.len(),
+        0,
+        0,
+    )
+}
+
+/// Sets an extended attribute on a file
+///
+/// # Arguments
+///
+/// * `path` - The file to annotate
+/// * `name` - Attribute name (on Linux, must be in the `user.` namespace)
+/// * `value` - Attribute value
+pub fn set_attribute(path: &Path, name: &str, value: &str) -> io::Result<()> {
+    let path = c_path(path)?;
+    let name = c_name(name)?;
+    // SAFETY: both strings are valid NUL-terminated C strings and the value
+    // pointer/length describe a live byte slice
+    let result = unsafe { raw_set(&path, &name, value.as_bytes()) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Reads an extended attribute from a file
+///
+/// # Returns
+///
+/// The attribute value, or `None` if the file has no such attribute
+pub fn get_attribute(path: &Path, name: &str) -> io::Result<Option<String>> {
+    let path = c_path(path)?;
+    let name = c_name(name)?;
+    let mut buffer = vec![0u8; 1024];
+    // SAFETY: both strings are valid NUL-terminated C strings and the buffer
+    // pointer/length describe a live, writable byte slice
+    let len = unsafe { raw_get(&path, &name, &mut buffer) };
+    if len < 0 {
+        let error = io::Error::last_os_error();
+        #[cfg(target_os = "linux")]
+        let missing = error.raw_os_error() == Some(libc::ENODATA);
+        #[cfg(target_os = "macos")]
+        let missing = error.raw_os_error() == Some(libc::ENOATTR);
+        return if missing { Ok(None) } else { Err(error) };
+    }
+    buffer.truncate(len as usize);
+    Ok(Some(String::from_utf8_lossy(&buffer).into_owned()))
+}
+
+/// Records provenance attributes on a file
+///
+/// Fields that are `None` are not written.
+pub fn write_provenance(path: &Path, provenance: &Provenance) -> io::Result<()> {
+    if let Some(guid) = &provenance.photo_guid {
+        set_attribute(path, GUID_ATTR, guid)?;
+    }
+    if let Some(album) = &provenance.album {
+        set_attribute(path, ALBUM_ATTR, album)?;
+    }
+    Ok(())
+}
+
+/// Reads the provenance attributes of a file
+pub fn read_provenance(path: &Path) -> io::Result<Provenance> {
+    Ok(Provenance {
+        photo_guid: get_attribute(path, GUID_ATTR)?,
+        album: get_attribute(path, ALBUM_ATTR)?,
+    })
+}
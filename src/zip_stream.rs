@@ -0,0 +1,357 @@
+//! Streaming an album as a ZIP archive.
+//!
+//! Web UIs that offer "download all" need the album as one file, and staging
+//! it on the server first costs disk space and delays the first byte.
+//! [`stream_zip`] fetches the photos one at a time and writes each into the
+//! archive as soon as it arrives, so it can be the body of an HTTP response.
+//! Each asset is staged in a temporary file while it downloads and copied
+//! from there, so memory use doesn't grow with the size of the photos. Entries are stored uncompressed,
+//! as photos and videos don't compress further; archives that outgrow the
+//! classic format's 4 GiB limits get ZIP64 records.
+//!
+//! The server of the `server` feature streams archives at
+//! `/albums/{token}/zip`. Other applications serving albums write the archive
+//! into their response, filtering the photos first, e.g. with
+//! [`crate::selection::Selection::filter`]. Downloads follow the
+//! [`DownloadOptions`] of a [`crate::download::download_derivative_classes`]
+//! pass, including [`DownloadOptions::with_url_refresh`] for asset URLs that
+//! stopped returning media.
+
+use crate::api::ErrorContext;
+use crate::derivatives::SizeClass;
+use crate::download::{self, DownloadError, DownloadOptions};
+use crate::logging;
+use crate::models::{CalendarDate, Image};
+use crate::throttle::HostLimits;
+use crate::utils;
+use std::error::Error;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Content type of the archives written by [`stream_zip`]
+pub const ZIP_CONTENT_TYPE: &str = "application/zip";
+
+/// A file written into an archive by [`stream_zip`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipEntry {
+    /// GUID of the photo
+    pub photo_guid: String,
+    /// Size class of the file
+    pub size_class: SizeClass,
+    /// Name of the file in the archive, see [`DownloadOptions::file_name_templates`]
+    pub name: String,
+    /// Size of the file in bytes
+    pub size: u64,
+}
+
+/// Result of [`stream_zip`]
+#[derive(Debug, Default)]
+pub struct ZipReport {
+    /// Files written into the archive, in photo order
+    pub entries: Vec<ZipEntry>,
+    /// Photos without a derivative of a requested class, as (photo GUID, size class)
+    pub missing: Vec<(String, SizeClass)>,
+    /// Downloads that failed and were left out, as (photo GUID, size class, error)
+    pub failures: Vec<(String, SizeClass, DownloadError)>,
+    /// Whether [`DownloadOptions::shutdown`] ended the archive early
+    pub cancelled: bool,
+    /// Size of the archive in bytes
+    pub bytes: u64,
+}
+
+/// Where an entry's header starts and what the central directory repeats of it
+struct CentralRecord {
+    name: String,
+    crc: u32,
+    size: u64,
+    offset: u64,
+    time: u16,
+    date: u16,
+}
+
+/// Writes the photos of an album into a ZIP archive as they are downloaded
+///
+/// For each photo and class, the derivative chosen by
+/// [`utils::select_derivative_of_class`] is downloaded and written under the
+/// name a [`crate::download::download_derivative_classes`] pass would give
+/// it. A failed download is left out and recorded in the report; once bytes
+/// have been sent, the archive can't take it back. When
+/// [`DownloadOptions::shutdown`] is triggered, the archive is closed after the
+/// files written so far, so it stays valid.
+///
+/// # Arguments
+///
+/// * `photos` - The photos to archive, enriched with URLs
+/// * `classes` - The size classes to archive for every photo
+/// * `writer` - Receives the archive, e.g. an HTTP response body
+/// * `options` - Options controlling how the photos are downloaded
+///
+/// # Returns
+///
+/// A report of the archived files, missing classes and failures, or an error
+/// if the HTTP client could not be built, the file name templates of two
+/// classes could give the same name or the archive could not be written
+pub async fn stream_zip<W>(
+    photos: &[Image],
+    classes: &[SizeClass],
+    writer: &mut W,
+    options: &DownloadOptions,
+) -> Result<ZipReport, Box<dyn Error>>
+where
+    W: AsyncWrite + Unpin,
+{
+    download::check_file_name_templates(options, classes)?;
+    let client = options.build_client()?;
+    let hosts = HostLimits::new(options.concurrency.clone(), options.circuit_breaker.clone());
+    let shutdown = options.shutdown.clone().unwrap_or_default();
+
+    let mut report = ZipReport::default();
+    let mut records = Vec::new();
+    'photos: for photo in photos {
+        for &size_class in classes {
+            let Some((key, derivative)) =
+                utils::select_derivative_of_class(&photo.derivatives, size_class)
+            else {
+                report.missing.push((photo.photo_guid.clone(), size_class));
+                continue;
+            };
+            let fetch = download::fetch_derivative(&client, &hosts, photo, &key, options);
+            let staged = match shutdown.run_until(fetch).await {
+                Some(Ok(staged)) => staged,
+                Some(Err(source)) => {
+                    let mut context = ErrorContext::new("download").with_guid(&photo.photo_guid);
+                    context.endpoint = derivative.url.clone();
+                    report.failures.push((
+                        photo.photo_guid.clone(),
+                        size_class,
+                        DownloadError { context, source },
+                    ));
+                    continue;
+                }
+                None => {
+                    report.cancelled = true;
+                    break 'photos;
+                }
+            };
+
+            let name = download::render_file_name(
+                options.file_name_template(size_class),
+                &utils::photo_base_filename(photo, None, None),
+                size_class,
+                &utils::get_extension_for_derivative(staged.head(), derivative),
+            );
+            let (time, date) = dos_date_time(photo.timestamp_taken());
+            let record = CentralRecord {
+                crc: staged.crc32(),
+                size: staged.len(),
+                offset: report.bytes,
+                name,
+                time,
+                date,
+            };
+            let header = local_header(&record);
+            writer.write_all(&header).await?;
+            let mut file = tokio::fs::File::open(staged.path()).await?;
+            tokio::io::copy(&mut file, writer).await?;
+            drop(file);
+            staged.discard().await;
+            report.bytes += header.len() as u64 + record.size;
+            report.entries.push(ZipEntry {
+                photo_guid: photo.photo_guid.clone(),
+                size_class,
+                name: record.name.clone(),
+                size: record.size,
+            });
+            records.push(record);
+        }
+    }
+    if report.cancelled {
+        logging::log_debug!(
+            logging::DOWNLOAD,
+            "Shutdown closed the archive after {} files",
+            records.len()
+        );
+    }
+
+    let trailer = central_directory(&records, report.bytes);
+    writer.write_all(&trailer).await?;
+    writer.flush().await?;
+    report.bytes += trailer.len() as u64;
+    Ok(report)
+}
+
+/// Sizes and offsets from this value on are stored in ZIP64 fields
+const ZIP64_THRESHOLD: u64 = u32::MAX as u64;
+/// Flag marking file names as UTF-8
+const FLAG_UTF8: u16 = 1 << 11;
+
+/// The local file header preceding an entry's content
+fn local_header(record: &CentralRecord) -> Vec<u8> {
+    let zip64 = record.size >= ZIP64_THRESHOLD;
+    let mut header = Vec::with_capacity(30 + record.name.len() + 20);
+    put_u32(&mut header, 0x0403_4b50);
+    put_u16(&mut header, if zip64 { 45 } else { 20 });
+    put_u16(&mut header, FLAG_UTF8);
+    put_u16(&mut header, 0); // stored
+    put_u16(&mut header, record.time);
+    put_u16(&mut header, record.date);
+    put_u32(&mut header, record.crc);
+    let size = if zip64 { u32::MAX } else { record.size as u32 };
+    put_u32(&mut header, size);
+    put_u32(&mut header, size);
+    put_u16(&mut header, record.name.len() as u16);
+    put_u16(&mut header, if zip64 { 20 } else { 0 });
+    header.extend_from_slice(record.name.as_bytes());
+    if zip64 {
+        put_u16(&mut header, 0x0001);
+        put_u16(&mut header, 16);
+        put_u64(&mut header, record.size);
+        put_u64(&mut header, record.size);
+    }
+    header
+}
+
+/// The central directory and end records closing an archive
+///
+/// `offset` is where the central directory starts, i.e. the size of the
+/// entries before it.
+fn central_directory(records: &[CentralRecord], offset: u64) -> Vec<u8> {
+    let mut directory = Vec::new();
+    for record in records {
+        let zip64 = record.size >= ZIP64_THRESHOLD || record.offset >= ZIP64_THRESHOLD;
+        put_u32(&mut directory, 0x0201_4b50);
+        put_u16(&mut directory, 3 << 8 | 45); // Unix, version 4.5
+        put_u16(&mut directory, if zip64 { 45 } else { 20 });
+        put_u16(&mut directory, FLAG_UTF8);
+        put_u16(&mut directory, 0);
+        put_u16(&mut directory, record.time);
+        put_u16(&mut directory, record.date);
+        put_u32(&mut directory, record.crc);
+        let (size, local_offset) = if zip64 {
+            (u32::MAX, u32::MAX)
+        } else {
+            (record.size as u32, record.offset as u32)
+        };
+        put_u32(&mut directory, size);
+        put_u32(&mut directory, size);
+        put_u16(&mut directory, record.name.len() as u16);
+        put_u16(&mut directory, if zip64 { 28 } else { 0 });
+        put_u16(&mut directory, 0); // comment
+        put_u16(&mut directory, 0); // disk
+        put_u16(&mut directory, 0); // internal attributes
+        put_u32(&mut directory, 0o100644 << 16);
+        put_u32(&mut directory, local_offset);
+        directory.extend_from_slice(record.name.as_bytes());
+        if zip64 {
+            put_u16(&mut directory, 0x0001);
+            put_u16(&mut directory, 24);
+            put_u64(&mut directory, record.size);
+            put_u64(&mut directory, record.size);
+            put_u64(&mut directory, record.offset);
+        }
+    }
+
+    let size = directory.len() as u64;
+    let count = records.len() as u64;
+    let zip64 = count >= u16::MAX as u64 || size >= ZIP64_THRESHOLD || offset >= ZIP64_THRESHOLD;
+    if zip64 {
+        let end_offset = offset + size;
+        put_u32(&mut directory, 0x0606_4b50);
+        put_u64(&mut directory, 44);
+        put_u16(&mut directory, 3 << 8 | 45);
+        put_u16(&mut directory, 45);
+        put_u32(&mut directory, 0);
+        put_u32(&mut directory, 0);
+        put_u64(&mut directory, count);
+        put_u64(&mut directory, count);
+        put_u64(&mut directory, size);
+        put_u64(&mut directory, offset);
+        // Locator of the ZIP64 end record
+        put_u32(&mut directory, 0x0706_4b50);
+        put_u32(&mut directory, 0);
+        put_u64(&mut directory, end_offset);
+        put_u32(&mut directory, 1);
+    }
+    put_u32(&mut directory, 0x0605_4b50);
+    put_u16(&mut directory, 0);
+    put_u16(&mut directory, 0);
+    let count = count.min(u16::MAX as u64) as u16;
+    put_u16(&mut directory, count);
+    put_u16(&mut directory, count);
+    put_u32(&mut directory, size.min(ZIP64_THRESHOLD) as u32);
+    put_u32(&mut directory, offset.min(ZIP64_THRESHOLD) as u32);
+    put_u16(&mut directory, 0);
+    directory
+}
+
+/// The MS-DOS time and date of a photo, clamped to the years DOS can represent
+fn dos_date_time(timestamp: Option<i64>) -> (u16, u16) {
+    // 1980-01-01 and 2107-12-31 23:59:58
+    let timestamp = timestamp.unwrap_or(0).clamp(315_532_800, 4_354_819_198);
+    let date = CalendarDate::from_timestamp(timestamp);
+    let seconds = timestamp.rem_euclid(86_400) as u16;
+    let time = (seconds / 3600) << 11 | (seconds / 60 % 60) << 5 | (seconds % 60 / 2);
+    let date = ((date.year - 1980) as u16) << 9 | (date.month as u16) << 5 | date.day as u16;
+    (time, date)
+}
+
+/// CRC-32 (IEEE) of `bytes`, as ZIP archives record it
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::default();
+    crc.update(bytes);
+    crc.finish()
+}
+
+/// A [`crc32`] computed over content that arrives in pieces
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self(!0)
+    }
+}
+
+impl Crc32 {
+    /// Adds the next piece of the content
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        const TABLE: [u32; 256] = {
+            let mut table = [0; 256];
+            let mut i = 0;
+            while i < 256 {
+                let mut crc = i as u32;
+                let mut bit = 0;
+                while bit < 8 {
+                    crc = if crc & 1 == 1 {
+                        0xEDB8_8320 ^ (crc >> 1)
+                    } else {
+                        crc >> 1
+                    };
+                    bit += 1;
+                }
+                table[i] = crc;
+                i += 1;
+            }
+            table
+        };
+        self.0 = bytes.iter().fold(self.0, |crc, &byte| {
+            TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+        });
+    }
+
+    /// The checksum of the content added so far
+    pub(crate) fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
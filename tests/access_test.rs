@@ -0,0 +1,92 @@
+use icloud_album_rs::access::{Access, AccessPolicy};
+
+fn policy() -> AccessPolicy {
+    AccessPolicy::new()
+        .with_bearer_token("admin-token")
+        .with_album_token("family", "family-token")
+}
+
+#[test]
+fn test_open_policy_grants_everything() {
+    let policy = AccessPolicy::new();
+    assert!(!policy.is_protected());
+    assert_eq!(policy.authorize(None, None, Some("family")), Access::Full);
+}
+
+#[test]
+fn test_bearer_and_album_tokens() {
+    let policy = policy();
+    assert_eq!(
+        policy.authorize(Some("Bearer admin-token"), None, Some("family")),
+        Access::Full
+    );
+    assert_eq!(
+        policy.authorize(Some("bearer family-token"), None, Some("family")),
+        Access::Album("family".to_string())
+    );
+    // An album token only opens its album
+    let other = policy.authorize(Some("Bearer family-token"), None, Some("work"));
+    assert_eq!(other, Access::Forbidden);
+    assert_eq!(other.denial_status(), Some(403));
+    // Album lists are filtered by the caller
+    let listing = policy.authorize(Some("Bearer family-token"), None, None);
+    assert!(listing.allows("family"));
+    assert!(!listing.allows("work"));
+
+    let wrong = policy.authorize(Some("Bearer guess"), None, Some("family"));
+    assert_eq!(wrong, Access::Unauthorized);
+    assert_eq!(wrong.denial_status(), Some(401));
+    assert_eq!(policy.authorize(None, None, None), Access::Unauthorized);
+    assert_eq!(
+        policy.challenge(),
+        "Bearer realm=\"icloud-album\", charset=\"UTF-8\""
+    );
+}
+
+#[test]
+fn test_access_token_in_query() {
+    let policy = policy();
+    assert_eq!(
+        policy.authorize(
+            None,
+            Some("?w=200&access_token=family-token"),
+            Some("family")
+        ),
+        Access::Album("family".to_string())
+    );
+    assert_eq!(
+        policy.authorize(None, Some("access_token=admin%2Dtoken"), None),
+        Access::Full
+    );
+    assert_eq!(
+        policy.authorize(None, Some("w=200"), None),
+        Access::Unauthorized
+    );
+}
+
+#[test]
+fn test_basic_auth() {
+    let policy = AccessPolicy::new()
+        .with_realm("Photos")
+        .with_basic_auth("jane", "s3cret:pass");
+    // "jane:s3cret:pass"
+    assert_eq!(
+        policy.authorize(Some("Basic amFuZTpzM2NyZXQ6cGFzcw=="), None, None),
+        Access::Full
+    );
+    // "jane:wrong"
+    assert_eq!(
+        policy.authorize(Some("Basic amFuZTp3cm9uZw=="), None, None),
+        Access::Unauthorized
+    );
+    assert_eq!(
+        policy.authorize(Some("Basic not*base64"), None, None),
+        Access::Unauthorized
+    );
+    assert_eq!(
+        policy.challenge(),
+        "Basic realm=\"Photos\", charset=\"UTF-8\""
+    );
+    // Credentials never show up in debug output
+    assert!(!format!("{:?}", policy).contains("s3cret"));
+}
@@ -0,0 +1,215 @@
+#![cfg(feature = "album-cache")]
+
+use icloud_album_rs::album_cache::{AlbumCache, AlbumCacheConfig, AlbumCacheStats};
+use icloud_album_rs::models::{ICloudResponse, Image, StreamCtag};
+use serde_json::json;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn album(ctag: &str, guids: &[&str]) -> ICloudResponse {
+    ICloudResponse {
+        metadata: serde_json::from_value(json!({
+            "streamName": "Family",
+            "userFirstName": "John",
+            "userLastName": "Doe",
+            "streamCtag": ctag,
+            "itemsReturned": guids.len(),
+            "locations": {}
+        }))
+        .unwrap(),
+        photos: guids
+            .iter()
+            .map(|guid| Image {
+                photo_guid: guid.to_string(),
+                ..Default::default()
+            })
+            .collect(),
+        unparsed: Vec::new(),
+    }
+}
+
+async fn fetch_counted(
+    count: &AtomicUsize,
+    response: ICloudResponse,
+) -> Result<ICloudResponse, Box<dyn Error>> {
+    count.fetch_add(1, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    Ok(response)
+}
+
+async fn unreachable_ctag() -> Result<StreamCtag, Box<dyn Error>> {
+    panic!("fresh entries are not revalidated")
+}
+
+#[tokio::test]
+async fn test_one_fetch_serves_concurrent_waiters() {
+    let cache = AlbumCache::default();
+    let fetches = AtomicUsize::new(0);
+
+    let requests = (0..10).map(|_| {
+        cache.get_with(
+            "token",
+            "",
+            || fetch_counted(&fetches, album("ctag1", &["a", "b"])),
+            unreachable_ctag,
+        )
+    });
+    let albums: Vec<_> = futures_join(requests).await;
+
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    let first = albums[0].as_ref().unwrap();
+    assert!(albums
+        .iter()
+        .all(|album| Arc::ptr_eq(album.as_ref().unwrap(), first)));
+    assert_eq!(
+        cache.stats(),
+        AlbumCacheStats {
+            hits: 9,
+            revalidations: 0,
+            fetches: 1
+        }
+    );
+
+    // Filters are cached separately
+    let latest = cache
+        .get_with(
+            "token",
+            "latest-1",
+            || fetch_counted(&fetches, album("ctag1", &["b"])),
+            unreachable_ctag,
+        )
+        .await
+        .unwrap();
+    assert_eq!(latest.photos.len(), 1);
+    assert_eq!(cache.len(), 2);
+
+    cache.invalidate("token");
+    assert!(cache.is_empty());
+}
+
+#[tokio::test]
+async fn test_stale_entries_are_revalidated_by_ctag() {
+    let cache = AlbumCache::new(AlbumCacheConfig {
+        ttl: Duration::ZERO,
+        max_age: Duration::from_secs(60),
+    });
+    let fetches = AtomicUsize::new(0);
+
+    let first = cache
+        .get_with(
+            "token",
+            "",
+            || fetch_counted(&fetches, album("ctag1", &["a"])),
+            unreachable_ctag,
+        )
+        .await
+        .unwrap();
+
+    // Unchanged album: the cached copy is kept
+    let second = cache
+        .get_with(
+            "token",
+            "",
+            || fetch_counted(&fetches, album("ctag1", &["a"])),
+            || async { Ok("ctag1".into()) },
+        )
+        .await
+        .unwrap();
+    assert!(Arc::ptr_eq(&first, &second));
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+    // Changed album: it is fetched again
+    let third = cache
+        .get_with(
+            "token",
+            "",
+            || fetch_counted(&fetches, album("ctag2", &["a", "b"])),
+            || async { Ok("ctag2".into()) },
+        )
+        .await
+        .unwrap();
+    assert_eq!(third.photos.len(), 2);
+
+    // Failed revalidation: it is fetched again too
+    let fourth = cache
+        .get_with(
+            "token",
+            "",
+            || fetch_counted(&fetches, album("ctag2", &["a", "b"])),
+            || async { Err("offline".into()) },
+        )
+        .await
+        .unwrap();
+    assert!(!Arc::ptr_eq(&third, &fourth));
+    assert_eq!(
+        cache.stats(),
+        AlbumCacheStats {
+            hits: 0,
+            revalidations: 1,
+            fetches: 3
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_old_entries_are_fetched_again() {
+    let cache = AlbumCache::new(AlbumCacheConfig {
+        ttl: Duration::ZERO,
+        max_age: Duration::ZERO,
+    });
+    let fetches = AtomicUsize::new(0);
+
+    for _ in 0..2 {
+        cache
+            .get_with(
+                "token",
+                "",
+                || fetch_counted(&fetches, album("ctag1", &["a"])),
+                unreachable_ctag,
+            )
+            .await
+            .unwrap();
+    }
+    assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+    // Failed fetches are not cached
+    let error = cache
+        .get_with(
+            "other",
+            "",
+            || async { Err::<ICloudResponse, Box<dyn Error>>("down".into()) },
+            unreachable_ctag,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(error.to_string(), "down");
+
+    assert_eq!(cache.purge_expired(), 2);
+    assert!(cache.is_empty());
+}
+
+/// Runs the futures concurrently on the current task
+async fn futures_join<F: std::future::Future>(futures: impl Iterator<Item = F>) -> Vec<F::Output> {
+    let mut pending: Vec<_> = futures.map(Box::pin).collect();
+    let mut outputs: Vec<Option<F::Output>> = pending.iter().map(|_| None).collect();
+    std::future::poll_fn(|cx| {
+        let mut done = true;
+        for (future, output) in pending.iter_mut().zip(outputs.iter_mut()) {
+            if output.is_none() {
+                match future.as_mut().poll(cx) {
+                    std::task::Poll::Ready(value) => *output = Some(value),
+                    std::task::Poll::Pending => done = false,
+                }
+            }
+        }
+        if done {
+            std::task::Poll::Ready(())
+        } else {
+            std::task::Poll::Pending
+        }
+    })
+    .await;
+    outputs.into_iter().map(Option::unwrap).collect()
+}
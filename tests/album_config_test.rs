@@ -0,0 +1,150 @@
+use icloud_album_rs::album_config::{
+    diff_configs, AlbumsConfig, ConfigChange, ConfigError, ConfigWatcher, DEFAULT_INTERVAL_SECS,
+};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("icloud-{}-{}.json", name, std::process::id()))
+}
+
+const INITIAL: &str = r#"{"albums": [
+    {"token": "family", "output_dir": "photos/family"},
+    {"token": "work", "name": "Work", "interval_secs": 60, "output_dir": "photos/work"}
+]}"#;
+
+#[test]
+fn test_parse_config() {
+    let config = AlbumsConfig::parse(INITIAL).unwrap();
+    assert_eq!(config.albums.len(), 2);
+    let family = config.album("family").unwrap();
+    assert_eq!(family.interval_secs, DEFAULT_INTERVAL_SECS);
+    assert_eq!(family.name, None);
+    assert_eq!(
+        config.album("work").unwrap().interval(),
+        Duration::from_secs(60)
+    );
+
+    let duplicate = r#"{"albums": [
+        {"token": "a", "output_dir": "x"}, {"token": "a", "output_dir": "y"}
+    ]}"#;
+    assert!(matches!(
+        AlbumsConfig::parse(duplicate),
+        Err(ConfigError::Invalid(_))
+    ));
+    let zero = r#"{"albums": [{"token": "a", "interval_secs": 0, "output_dir": "x"}]}"#;
+    assert!(matches!(
+        AlbumsConfig::parse(zero),
+        Err(ConfigError::Invalid(_))
+    ));
+    assert!(matches!(
+        AlbumsConfig::parse("{"),
+        Err(ConfigError::Parse(_))
+    ));
+}
+
+#[test]
+fn test_diff_configs() {
+    let previous = AlbumsConfig::parse(INITIAL).unwrap();
+    let current = AlbumsConfig::parse(
+        r#"{"albums": [
+            {"token": "work", "name": "Office", "interval_secs": 120, "output_dir": "archive/work"},
+            {"token": "trip", "output_dir": "photos/trip"}
+        ]}"#,
+    )
+    .unwrap();
+    let changes = diff_configs(&previous, &current);
+    let tokens: Vec<&str> = changes.iter().map(ConfigChange::token).collect();
+    assert_eq!(tokens, ["family", "work", "work", "work", "trip"]);
+    assert_eq!(
+        changes[1],
+        ConfigChange::IntervalChanged {
+            token: "work".to_string(),
+            old_secs: 60,
+            new_secs: 120,
+        }
+    );
+    assert_eq!(
+        changes[2],
+        ConfigChange::OutputDirChanged {
+            token: "work".to_string(),
+            old_dir: PathBuf::from("photos/work"),
+            new_dir: PathBuf::from("archive/work"),
+        }
+    );
+    assert!(matches!(changes[3], ConfigChange::NameChanged { .. }));
+    assert!(matches!(changes[4], ConfigChange::AlbumAdded { .. }));
+    assert!(diff_configs(&current, &current).is_empty());
+
+    let value = serde_json::to_value(&changes[0]).unwrap();
+    assert_eq!(value["type"], "AlbumRemoved");
+}
+
+#[tokio::test]
+async fn test_reload_keeps_config_on_errors() {
+    let path = temp_file("config-reload");
+    std::fs::write(&path, INITIAL).unwrap();
+    let mut watcher = ConfigWatcher::open(&path).await.unwrap();
+    assert!(watcher.reload().await.unwrap().is_empty());
+
+    std::fs::write(&path, "{\"albums\": [").unwrap();
+    assert!(watcher.reload().await.is_err());
+    assert_eq!(watcher.config().albums.len(), 2);
+
+    std::fs::write(
+        &path,
+        r#"{"albums": [{"token": "family", "output_dir": "photos/family"}]}"#,
+    )
+    .unwrap();
+    let changes = watcher.reload().await.unwrap();
+    assert_eq!(
+        changes,
+        vec![ConfigChange::AlbumRemoved {
+            token: "work".to_string()
+        }]
+    );
+    assert_eq!(watcher.config().albums.len(), 1);
+    std::fs::remove_file(&path).ok();
+}
+
+#[tokio::test]
+async fn test_run_reports_changes_until_shut_down() {
+    let path = temp_file("config-run");
+    std::fs::write(&path, INITIAL).unwrap();
+    let mut watcher = ConfigWatcher::open(&path)
+        .await
+        .unwrap()
+        .with_poll_interval(Duration::from_millis(10));
+    let shutdown = watcher.shutdown_handle();
+    let applied = Arc::new(Mutex::new(Vec::new()));
+
+    let recorded = applied.clone();
+    let task = tokio::spawn(async move {
+        watcher
+            .run(move |config, changes| {
+                assert_eq!(config.albums.len(), 3);
+                recorded.lock().unwrap().extend_from_slice(changes);
+            })
+            .await;
+    });
+
+    let edited = INITIAL.replace(
+        "]}",
+        r#", {"token": "trip", "output_dir": "photos/trip"}]}"#,
+    );
+    std::fs::write(&path, edited).unwrap();
+    for _ in 0..200 {
+        if !applied.lock().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    shutdown.trigger();
+    task.await.unwrap();
+
+    let applied = applied.lock().unwrap();
+    assert_eq!(applied.len(), 1);
+    assert_eq!(applied[0].token(), "trip");
+    std::fs::remove_file(&path).ok();
+}
@@ -0,0 +1,86 @@
+use icloud_album_rs::album::AlbumRef;
+use icloud_album_rs::base_url::with_api_origin;
+use icloud_album_rs::fetch::FetchOptions;
+use icloud_album_rs::get_album_photos;
+use serde_json::json;
+
+#[test]
+fn test_album_ref() {
+    let album: AlbumRef = " https://www.icloud.com/sharedalbum/#B0z5qAGN1JIFd3y "
+        .parse()
+        .unwrap();
+    assert_eq!(album.token(), "B0z5qAGN1JIFd3y");
+    assert_eq!(album.partition(), 12);
+    assert_eq!(
+        album.base_url(),
+        "https://p12-sharedstreams.icloud.com/B0z5qAGN1JIFd3y/sharedstreams/"
+    );
+    assert_eq!(album.resolved_host(), None);
+    assert_eq!(
+        album.share_url(),
+        "https://www.icloud.com/sharedalbum/#B0z5qAGN1JIFd3y"
+    );
+
+    // Display helpers don't show the whole token
+    assert!(!album.to_string().contains("B0z5qAGN1JIFd3y"));
+    let album = album.with_alias("Family");
+    assert_eq!(album.display_name(), "Family");
+    assert!(album.to_string().starts_with("Family ("));
+
+    let mut album = album;
+    album.set_resolved("https://p42-sharedstreams.icloud.com/B0z5qAGN1JIFd3y/sharedstreams/");
+    assert_eq!(album.resolved_host(), Some("p42-sharedstreams.icloud.com"));
+    assert!(album.base_url().contains("p42-"));
+    album.clear_resolved();
+    assert!(album.base_url().contains("p12-"));
+
+    assert!(AlbumRef::new("").is_err());
+    assert!("-nope".parse::<AlbumRef>().is_err());
+}
+
+#[tokio::test]
+async fn test_album_ref_reuses_resolved_host() {
+    let mut server = mockito::Server::new_async().await;
+    let webstream = json!({
+        "streamName": "Test Album",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "12345",
+        "itemsReturned": 0,
+        "locations": {},
+        "photos": []
+    });
+
+    // Only the first fetch probes and gets redirected
+    let mock_redirect = server
+        .mock("POST", "/test_token/sharedstreams/webstream")
+        .with_status(330)
+        .with_body(json!({ "X-Apple-MMe-Host": "p42-sharedstreams.icloud.com" }).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    let mock_webstream = server
+        .mock("POST", "/test_token/sharedstreams/webstream")
+        .with_body(webstream.to_string())
+        .expect(2)
+        .create_async()
+        .await;
+
+    let mut album = AlbumRef::new("test_token").unwrap();
+    let options = FetchOptions::new();
+    let origin = server.url();
+    with_api_origin(&origin, async {
+        let response = get_album_photos(&mut album, &options).await.unwrap();
+        assert_eq!(response.metadata.stream_name, "Test Album");
+        assert_eq!(
+            album.resolved_base_url(),
+            Some(format!("{}/test_token/sharedstreams/", origin).as_str())
+        );
+
+        get_album_photos(&mut album, &options).await.unwrap();
+    })
+    .await;
+
+    mock_redirect.assert_async().await;
+    mock_webstream.assert_async().await;
+}
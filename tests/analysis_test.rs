@@ -0,0 +1,138 @@
+mod common;
+
+use common::{derivative, photo_with};
+use icloud_album_rs::analysis::{date_summary, largest_items, size_histogram, storage_summary};
+use icloud_album_rs::derivatives::SizeClass;
+use icloud_album_rs::models::{CalendarDate, Derivative, ICloudResponse, Image, Metadata};
+use serde_json::json;
+
+// Build a photo with the given (key, size) derivatives
+fn sized_photo(guid: &str, sizes: &[(&str, Option<u64>)]) -> Image {
+    let derivatives = sizes.iter().map(|&(key, size)| {
+        let derivative = Derivative {
+            file_size: size,
+            ..derivative(format!("{}-{}", guid, key), None)
+        };
+        (key, derivative)
+    });
+    photo_with(guid, derivatives)
+}
+
+fn album(photos: Vec<Image>) -> ICloudResponse {
+    let metadata: Metadata = serde_json::from_value(json!({
+        "streamName": "My Album",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag123",
+        "itemsReturned": photos.len(),
+        "locations": {}
+    }))
+    .unwrap();
+
+    ICloudResponse {
+        metadata,
+        photos,
+        unparsed: Vec::new(),
+    }
+}
+
+#[test]
+fn test_storage_summary() {
+    let response = album(vec![
+        sized_photo("a", &[("342", Some(100)), ("3", Some(1000))]),
+        sized_photo("b", &[("342", Some(200)), ("1", Some(3000))]),
+        sized_photo("c", &[("342", None)]),
+    ]);
+
+    let summary = storage_summary(&response);
+    assert_eq!(summary.photo_count, 3);
+    assert_eq!(summary.total_bytes, 4300);
+    assert_eq!(
+        summary.bytes_by_class.get(&SizeClass::Thumbnail),
+        Some(&300)
+    );
+    assert_eq!(
+        summary.bytes_by_class.get(&SizeClass::Original),
+        Some(&1000)
+    );
+    assert_eq!(summary.unclassified_bytes, 3000);
+    // Largest per photo: 1000 and 3000; photo "c" has no known size
+    assert_eq!(summary.average_photo_bytes, Some(2000));
+
+    // An empty album has no average
+    assert_eq!(storage_summary(&album(vec![])).average_photo_bytes, None);
+}
+
+#[test]
+fn test_largest_items() {
+    let response = album(vec![
+        sized_photo("a", &[("342", Some(100)), ("3", Some(1000))]),
+        sized_photo("b", &[("342", Some(200)), ("3", Some(3000))]),
+    ]);
+
+    let items = largest_items(&response, 2);
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].photo_guid, "b");
+    assert_eq!(items[0].file_size, 3000);
+    assert_eq!(items[1].photo_guid, "a");
+    assert_eq!(items[1].derivative_key, "3");
+}
+
+#[test]
+fn test_size_histogram() {
+    let response = album(vec![
+        sized_photo("a", &[("3", Some(1000))]),
+        sized_photo("b", &[("3", Some(1023))]),
+        sized_photo("c", &[("3", Some(1024))]),
+        sized_photo("d", &[("3", None)]),
+    ]);
+
+    let buckets = size_histogram(&response);
+    assert_eq!(buckets.len(), 2);
+    assert_eq!(
+        (buckets[0].min_bytes, buckets[0].max_bytes, buckets[0].count),
+        (512, 1024, 2)
+    );
+    assert_eq!(
+        (buckets[1].min_bytes, buckets[1].max_bytes, buckets[1].count),
+        (1024, 2048, 1)
+    );
+}
+
+#[test]
+fn test_date_summary() {
+    let dated = |created: Option<&str>, batch: Option<&str>| Image {
+        date_created: created.map(String::from),
+        batch_date_created: batch.map(String::from),
+        ..Default::default()
+    };
+    let response = album(vec![
+        dated(Some("2024-03-01T08:00:00Z"), None),
+        dated(Some("2024-02-28T23:59:00Z"), None),
+        dated(Some("2024-03-01T18:30:00-05:00"), None),
+        dated(None, Some("2024-02-28T10:00:00Z")),
+        dated(Some("garbage"), None),
+        dated(None, None),
+    ]);
+
+    let summary = date_summary(&response);
+    let date = |y, m, d| CalendarDate::new(y, m, d).unwrap();
+    assert_eq!(summary.first, Some(date(2024, 2, 28)));
+    assert_eq!(summary.last, Some(date(2024, 3, 1)));
+    assert_eq!(summary.undated, 2);
+    assert_eq!(summary.per_day.len(), 2);
+
+    // 2024 is a leap year, so the empty 29th shows up between the two days
+    assert_eq!(
+        summary.daily_counts(),
+        vec![
+            (date(2024, 2, 28), 2),
+            (date(2024, 2, 29), 0),
+            (date(2024, 3, 1), 2)
+        ]
+    );
+
+    let empty = date_summary(&album(Vec::new()));
+    assert_eq!(empty.first, None);
+    assert!(empty.daily_counts().is_empty());
+}
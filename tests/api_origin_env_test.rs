@@ -0,0 +1,36 @@
+use icloud_album_rs::base_url::{api_origin, get_base_url, API_ORIGIN_ENV};
+use icloud_album_rs::get_icloud_photos;
+use serde_json::json;
+
+// The environment is process-wide, so everything is checked in one test
+#[tokio::test]
+async fn test_api_origin_from_environment() {
+    let mut server = mockito::Server::new_async().await;
+    let webstream = server
+        .mock("POST", "/B0z5qAGN1JIFd3y/sharedstreams/webstream")
+        .with_body(
+            json!({
+                "streamName": "Staging Album",
+                "streamCtag": "1",
+                "photos": []
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+
+    std::env::set_var(API_ORIGIN_ENV, format!("{}/", server.url()));
+    assert_eq!(api_origin(), Some(server.url()));
+    assert_eq!(
+        get_base_url("B0z5qAGN1JIFd3y").unwrap(),
+        format!("{}/B0z5qAGN1JIFd3y/sharedstreams/", server.url())
+    );
+    let album = get_icloud_photos("B0z5qAGN1JIFd3y").await.unwrap();
+    assert_eq!(album.metadata.stream_name, "Staging Album");
+    webstream.assert_async().await;
+
+    // An empty value is no override
+    std::env::set_var(API_ORIGIN_ENV, "");
+    assert_eq!(api_origin(), None);
+    std::env::remove_var(API_ORIGIN_ENV);
+}
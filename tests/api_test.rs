@@ -1,15 +1,13 @@
-use icloud_album_rs::api::{get_api_response, get_asset_urls};
+use icloud_album_rs::api::{
+    check_api_schema, get_api_response, get_api_response_with_retry, get_asset_urls,
+    get_stream_ctag_at, parse_stream_ctag_bytes, parse_webasseturls_response,
+    parse_webasseturls_response_with_report, parse_webstream_response, AssetUrlsResponse,
+    IssueSeverity, RetryConfig, SchemaIssue, ValidationFailure, WebstreamResponse,
+};
+use icloud_album_rs::url_policy::UrlPolicy;
 use reqwest::Client;
 use serde_json::json;
 
-// Define old-style test function for compatibility with main test runner
-#[test]
-fn run_api_tests() {
-    // We'll verify these tests pass without running them in the normal test suite
-    // Since they require an active tokio runtime
-    println!("API tests should be run individually with: cargo test --test api_test -- --ignored");
-}
-
 // Function to create sample API response JSON
 fn create_sample_api_response() -> serde_json::Value {
     json!({
@@ -82,15 +80,147 @@ fn create_sample_asset_urls_response() -> serde_json::Value {
     })
 }
 
+#[test]
+fn test_parse_webstream_response() {
+    let mut response = create_sample_api_response();
+
+    // A clean response produces a clean report
+    let WebstreamResponse {
+        photos,
+        metadata,
+        report,
+        ..
+    } = parse_webstream_response(&response).unwrap();
+    assert_eq!(photos.len(), 2);
+    assert_eq!(metadata.stream_name, "Test Album");
+    assert!(report.is_clean());
+
+    // An unparseable photo is skipped and recorded in the report
+    response["photos"][1] = json!({ "photoGuid": 42 });
+    let WebstreamResponse { photos, report, .. } = parse_webstream_response(&response).unwrap();
+    assert_eq!(photos.len(), 1);
+    assert_eq!(report.skipped_photos.len(), 1);
+    assert_eq!(report.skipped_photos[0].0, 1);
+    assert_eq!(report.unparsed_items.len(), 1);
+    assert_eq!(report.unparsed_items[0].index, 1);
+    assert_eq!(report.unparsed_items[0].raw, json!({ "photoGuid": 42 }));
+    assert!(report.schema_issues.contains(&SchemaIssue {
+        field: "photos[1].derivatives".to_string(),
+        failure: ValidationFailure::Missing,
+        severity: IssueSeverity::Warning,
+    }));
+    assert_eq!(report.max_severity(), Some(IssueSeverity::Warning));
+}
+
+#[test]
+fn test_schema_issue_severity() {
+    let mut response = create_sample_api_response();
+    response.as_object_mut().unwrap().remove("streamCtag");
+    response.as_object_mut().unwrap().remove("streamName");
+
+    let issues = check_api_schema(&response, "webstream");
+    let severity_of = |field: &str| {
+        issues
+            .iter()
+            .find(|issue| issue.field == field)
+            .map(|issue| issue.severity)
+    };
+    assert_eq!(severity_of("streamCtag"), Some(IssueSeverity::Info));
+    assert_eq!(severity_of("streamName"), Some(IssueSeverity::Critical));
+
+    let critical: Vec<_> = issues
+        .iter()
+        .filter(|issue| issue.severity == IssueSeverity::Critical)
+        .map(|issue| issue.field.as_str())
+        .collect();
+    assert_eq!(critical, vec!["streamName"]);
+
+    // Informational issues do not prevent parsing
+    let mut response = create_sample_api_response();
+    response.as_object_mut().unwrap().remove("streamCtag");
+    let report = parse_webstream_response(&response).unwrap().report;
+    assert_eq!(report.max_severity(), Some(IssueSeverity::Info));
+    assert_eq!(report.issues_at_least(IssueSeverity::Warning).count(), 0);
+}
+
+#[test]
+fn test_parse_webasseturls_response_with_report() {
+    let mut response = create_sample_asset_urls_response();
+    let AssetUrlsResponse { urls, report, .. } =
+        parse_webasseturls_response_with_report(&response, &UrlPolicy::permissive()).unwrap();
+    assert_eq!(urls.len(), 3);
+    assert!(report.is_clean());
+
+    response["items"]["photo456"]
+        .as_object_mut()
+        .unwrap()
+        .remove("url_path");
+    let AssetUrlsResponse { urls, report, .. } =
+        parse_webasseturls_response_with_report(&response, &UrlPolicy::permissive()).unwrap();
+    assert_eq!(urls.len(), 2);
+    assert_eq!(
+        report.skipped_assets,
+        vec![("photo456".to_string(), "missing url_path".to_string())]
+    );
+    assert!(report
+        .schema_issues
+        .iter()
+        .any(|issue| issue.field == "items.photo456.url_path"
+            && issue.severity == IssueSeverity::Warning));
+}
+
+#[test]
+fn test_parse_webasseturls_response() {
+    let urls = parse_webasseturls_response(&create_sample_asset_urls_response()).unwrap();
+    assert_eq!(urls.len(), 3);
+    assert_eq!(
+        urls.get("photo456"),
+        Some(&"https://example2.icloud.com/path/to/image2.jpg".to_string())
+    );
+
+    // A response without items is a hard error
+    assert!(parse_webasseturls_response(&json!({})).is_err());
+}
+
+#[test]
+fn test_parse_stream_ctag_bytes() {
+    let body = json!({
+        "streamCtag": "ctag42",
+        "photos": [{ "not": "a photo" }]
+    });
+    assert_eq!(
+        parse_stream_ctag_bytes(body.to_string().as_bytes()).unwrap(),
+        "ctag42"
+    );
+    assert!(parse_stream_ctag_bytes(b"{\"photos\": []}").is_err());
+    assert!(parse_stream_ctag_bytes(b"not json").is_err());
+}
+
+#[tokio::test]
+async fn test_get_stream_ctag_at() {
+    let mut server = mockito::Server::new_async().await;
+    let mock = server
+        .mock("POST", "/sharedstreams/webstream")
+        .match_body(mockito::Matcher::Json(json!({ "streamCtag": null })))
+        .with_status(200)
+        .with_body(create_sample_api_response().to_string())
+        .create_async()
+        .await;
+
+    let base_url = format!("{}/sharedstreams/", server.url());
+    let ctag = get_stream_ctag_at(&Client::new(), &base_url).await.unwrap();
+    assert_eq!(ctag, "12345");
+    mock.assert_async().await;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[tokio::test]
-    #[ignore = "Requires separate tokio runtime"]
     async fn test_api_response() {
         // Create a mock server
-        let mut server = mockito::Server::new();
+        let mut server = mockito::Server::new_async().await;
         let mock_url = server.url();
 
         // Set up the mock response
@@ -100,14 +230,17 @@ mod tests {
             .with_status(200)
             .with_header("content-type", "application/json")
             .with_body(sample_response.to_string())
-            .create();
+            .create_async()
+            .await;
 
         // Test with a base URL that ends with the mock server URL plus a trailing slash
         let base_url = format!("{}/", mock_url);
         let client = Client::new();
 
         // Call the function and check the result
-        let (photos, metadata) = get_api_response(&client, &base_url).await.unwrap();
+        let WebstreamResponse {
+            photos, metadata, ..
+        } = get_api_response(&client, &base_url).await.unwrap();
 
         // Verify metadata
         assert_eq!(metadata.stream_name, "Test Album");
@@ -132,14 +265,13 @@ mod tests {
         );
 
         // Verify the mock was called
-        mock.assert();
+        mock.assert_async().await;
     }
 
     #[tokio::test]
-    #[ignore = "Requires separate tokio runtime"]
     async fn test_asset_urls() {
         // Create a mock server
-        let mut server = mockito::Server::new();
+        let mut server = mockito::Server::new_async().await;
         let mock_url = server.url();
 
         // Set up the mock response
@@ -149,7 +281,8 @@ mod tests {
             .with_status(200)
             .with_header("content-type", "application/json")
             .with_body(sample_response.to_string())
-            .create();
+            .create_async()
+            .await;
 
         // Test with a base URL that ends with the mock server URL plus a trailing slash
         let base_url = format!("{}/", mock_url);
@@ -185,6 +318,35 @@ mod tests {
         );
 
         // Verify the mock was called
-        mock.assert();
+        mock.assert_async().await;
     }
 }
+
+#[tokio::test]
+async fn test_report_keeps_response_headers() {
+    let mut server = mockito::Server::new_async().await;
+    let mock = server
+        .mock("POST", "/webstream")
+        .with_header("x-apple-request-uuid", "abc-123")
+        .with_header("x-apple-user-partition", "12")
+        .with_body(create_sample_api_response().to_string())
+        .create_async()
+        .await;
+
+    let client = Client::new();
+    let report = get_api_response_with_retry(
+        &client,
+        &format!("{}/", server.url()),
+        &Default::default(),
+        &RetryConfig::default(),
+    )
+    .await
+    .unwrap()
+    .report;
+    mock.assert_async().await;
+
+    let headers = report.response_headers.unwrap();
+    assert_eq!(headers.status, 200);
+    assert_eq!(headers.request_id(), Some("abc-123"));
+    assert_eq!(headers.get("x-apple-user-partition"), Some("12"));
+}
@@ -0,0 +1,265 @@
+use icloud_album_rs::app_config::{AppConfig, AppConfigError, ConfigFormat, SinkSettings};
+use icloud_album_rs::derivatives::SizeClass;
+use icloud_album_rs::throttle::Concurrency;
+use std::path::{Path, PathBuf};
+
+const JSON: &str = r#"{
+    "albums": [
+        { "token": "B0z5qAGN1JIFd3y", "name": "Family", "output_dir": "family" },
+        { "token": "B1abcdefghijklm", "interval_secs": 60, "output_dir": "/mnt/trips" }
+    ],
+    "output": {
+        "dir": "/srv/photos",
+        "markdown_captions": true,
+        "naming": { "thumb": "{base}-small.{ext}", "original": "{base}.{ext}" }
+    },
+    "retry": { "max_retries": 5, "base_delay_ms": 200 },
+    "rate_limits": { "concurrency": 2 },
+    "sinks": [{ "type": "jsonl", "path": "/var/log/albums.jsonl" }]
+}"#;
+
+fn error_key(contents: &str) -> String {
+    let error = AppConfig::parse(contents, ConfigFormat::Json).unwrap_err();
+    error.key().unwrap().to_string()
+}
+
+#[test]
+fn test_app_config_parse() {
+    let config = AppConfig::parse(JSON, ConfigFormat::Json).unwrap();
+
+    let albums = config.albums_config().albums;
+    assert_eq!(albums[0].output_dir, PathBuf::from("/srv/photos/family"));
+    assert_eq!(albums[1].output_dir, PathBuf::from("/mnt/trips"));
+    assert_eq!(albums[1].interval_secs, 60);
+
+    let options = config.download_options();
+    assert!(options.markdown_captions);
+    assert_eq!(options.concurrency, Concurrency::Fixed(2));
+    assert_eq!(
+        options.file_name_template(SizeClass::Thumbnail),
+        "{base}-small.{ext}"
+    );
+    let retry = options.retry.unwrap();
+    assert_eq!((retry.max_retries, retry.base_delay_ms), (5, 200));
+
+    assert_eq!(
+        config.sinks,
+        [SinkSettings::Jsonl {
+            path: PathBuf::from("/var/log/albums.jsonl")
+        }]
+    );
+    assert_eq!(config.build_sinks().unwrap().len(), 1);
+
+    // Everything is optional
+    let empty = AppConfig::parse("{}", ConfigFormat::Json).unwrap();
+    assert_eq!(empty, AppConfig::default());
+    assert!(empty.retry_config().is_none());
+}
+
+#[test]
+fn test_app_config_errors_name_the_key() {
+    assert_eq!(
+        error_key(r#"{ "albums": [{ "token": "a", "output_dir": "a" }, { "token": "b" }] }"#),
+        "albums[1]"
+    );
+    assert_eq!(
+        error_key(r#"{ "retry": { "max_retries": "many" } }"#),
+        "retry.max_retries"
+    );
+    assert_eq!(
+        error_key(r#"{ "output": { "namng": {} } }"#),
+        "output.namng"
+    );
+    assert_eq!(
+        error_key(
+            r#"{ "albums": [{ "token": "a", "output_dir": "a" }, { "token": "a", "output_dir": "b" }] }"#
+        ),
+        "albums[1].token"
+    );
+    assert_eq!(
+        error_key(r#"{ "albums": [{ "token": "a", "interval_secs": 0, "output_dir": "a" }] }"#),
+        "albums[0].interval_secs"
+    );
+    assert_eq!(
+        error_key(r#"{ "output": { "naming": { "huge": "{base}.{ext}" } } }"#),
+        "output.naming.huge"
+    );
+    assert_eq!(
+        error_key(r#"{ "output": { "naming": { "thumb": "thumbs/{base}.{ext}" } } }"#),
+        "output.naming.thumb"
+    );
+    assert_eq!(
+        error_key(
+            r#"{ "output": { "naming": { "thumb": "{base}.{ext}", "original": "{base}.{ext}" } } }"#
+        ),
+        "output.naming"
+    );
+    assert_eq!(
+        error_key(r#"{ "retry": { "base_delay_ms": 500, "max_delay_ms": 100 } }"#),
+        "retry.base_delay_ms"
+    );
+    assert_eq!(
+        error_key(r#"{ "rate_limits": { "concurrency": 0 } }"#),
+        "rate_limits.concurrency"
+    );
+    assert_eq!(
+        error_key(r#"{ "sinks": [{ "type": "pager", "number": "1" }] }"#),
+        "sinks[0].type"
+    );
+    assert_eq!(
+        error_key(
+            r#"{ "sinks": [{ "type": "smtp", "host": "mail", "from": "a@example.com",
+                "to": ["b@example.com"], "tls": "starttls", "username": "a" }] }"#
+        ),
+        "sinks[0].password"
+    );
+
+    let error = AppConfig::parse(
+        r#"{ "rate_limits": { "concurrency": 0 } }"#,
+        ConfigFormat::Json,
+    )
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Invalid config at `rate_limits.concurrency`: must not be zero"
+    );
+}
+
+#[test]
+fn test_app_config_adaptive_rate_limits() {
+    let config = AppConfig::parse(
+        r#"{ "rate_limits": { "adaptive": true, "concurrency": 2, "max_concurrency": 6 } }"#,
+        ConfigFormat::Json,
+    )
+    .unwrap();
+    match config.download_options().concurrency {
+        Concurrency::Adaptive(aimd) => assert_eq!((aimd.initial, aimd.max), (2, 6)),
+        other => panic!("expected adaptive concurrency, got {:?}", other),
+    }
+
+    assert_eq!(
+        error_key(r#"{ "rate_limits": { "max_concurrency": 6 } }"#),
+        "rate_limits.max_concurrency"
+    );
+}
+
+#[test]
+fn test_app_config_load() {
+    let dir = std::env::temp_dir().join(format!("icloud-app-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.json");
+    std::fs::write(&path, JSON).unwrap();
+    assert_eq!(AppConfig::load(&path).unwrap().albums.len(), 2);
+
+    assert!(matches!(
+        AppConfig::load(&dir.join("config.ini")),
+        Err(AppConfigError::UnsupportedFormat(_))
+    ));
+    assert!(matches!(
+        AppConfig::load(&dir.join("missing.json")),
+        Err(AppConfigError::Io(_))
+    ));
+    assert_eq!(
+        ConfigFormat::from_path(Path::new("a/config.YML")),
+        Some(ConfigFormat::Yaml)
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "toml-config")]
+#[test]
+fn test_app_config_toml() {
+    let config = AppConfig::parse(
+        r#"
+[[albums]]
+token = "B0z5qAGN1JIFd3y"
+output_dir = "family"
+
+[output]
+naming = { thumb = "{base}-small.{ext}" }
+
+[[sinks]]
+type = "jsonl"
+path = "albums.jsonl"
+"#,
+        ConfigFormat::Toml,
+    )
+    .unwrap();
+    assert_eq!(config.albums[0].token, "B0z5qAGN1JIFd3y");
+    assert_eq!(config.sinks.len(), 1);
+
+    let error = AppConfig::parse("[retry]\nmax_retries = -1\n", ConfigFormat::Toml).unwrap_err();
+    assert_eq!(error.key(), Some("retry.max_retries"));
+}
+
+#[cfg(feature = "yaml-config")]
+#[test]
+fn test_app_config_yaml() {
+    let config = AppConfig::parse(
+        "albums:\n  - token: B0z5qAGN1JIFd3y\n    output_dir: family\nrate_limits:\n  concurrency: 3\n",
+        ConfigFormat::Yaml,
+    )
+    .unwrap();
+    assert_eq!(config.albums[0].output_dir, PathBuf::from("family"));
+    assert_eq!(config.download_options().concurrency, Concurrency::Fixed(3));
+
+    let error = AppConfig::parse("albums:\n  - token: a\n", ConfigFormat::Yaml).unwrap_err();
+    assert_eq!(error.key(), Some("albums[0]"));
+}
+
+#[cfg(not(feature = "toml-config"))]
+#[test]
+fn test_app_config_toml_needs_feature() {
+    assert!(matches!(
+        AppConfig::parse("", ConfigFormat::Toml),
+        Err(AppConfigError::UnsupportedFormat(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_app_config_watch() {
+    use icloud_album_rs::album_config::{ConfigChange, ConfigError};
+
+    let dir = std::env::temp_dir().join(format!("icloud-app-config-watch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.json");
+    std::fs::write(&path, JSON).unwrap();
+    let mut watcher = AppConfig::watch(&path).await.unwrap();
+    // Output directories are resolved against output.dir
+    assert_eq!(
+        watcher.config().albums[0].output_dir,
+        PathBuf::from("/srv/photos/family")
+    );
+
+    // Invalid edits name the key and keep the albums in effect
+    std::fs::write(
+        &path,
+        JSON.replace("\"interval_secs\": 60", "\"interval_secs\": 0"),
+    )
+    .unwrap();
+    match watcher.reload().await {
+        Err(ConfigError::App(e)) => assert_eq!(e.key(), Some("albums[1].interval_secs")),
+        other => panic!("unexpected reload result: {:?}", other),
+    }
+    assert_eq!(watcher.config().albums.len(), 2);
+
+    std::fs::write(
+        &path,
+        JSON.replace("\"dir\": \"/srv/photos\"", "\"dir\": \"/data\""),
+    )
+    .unwrap();
+    let changes = watcher.reload().await.unwrap();
+    assert_eq!(changes.len(), 1);
+    assert!(matches!(
+        &changes[0],
+        ConfigChange::OutputDirChanged { new_dir, .. } if new_dir == Path::new("/data/family")
+    ));
+
+    assert!(matches!(
+        AppConfig::watch(dir.join("config.ini")).await,
+        Err(ConfigError::App(AppConfigError::UnsupportedFormat(_)))
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,116 @@
+use icloud_album_rs::apple_photos::{export_apple_photos, CAPTIONS_FILE, PLIST_FILE, SCRIPT_FILE};
+use icloud_album_rs::models::{Image, Location, Metadata};
+use icloud_album_rs::sync::{SyncState, SyncedFile};
+use serde_json::json;
+use std::time::{Duration, UNIX_EPOCH};
+
+fn album() -> Metadata {
+    serde_json::from_value(json!({
+        "streamName": "Mom's \"Best\" Trip",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag123",
+        "itemsReturned": 2,
+        "locations": {}
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_export_apple_photos() {
+    let root = std::env::temp_dir().join(format!("icloud-apple-photos-{}", std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&root).await;
+    let store = root.join("store");
+    tokio::fs::create_dir_all(&store).await.unwrap();
+
+    let photos = vec![
+        Image {
+            photo_guid: "guid1".to_string(),
+            caption: Some("Dinner, \"finally\"".to_string()),
+            date_created: Some("2023-07-14T18:30:05-05:00".to_string()),
+            contributor_full_name: Some("Jane Doe".to_string()),
+            location: Some(Location {
+                latitude: 41.5,
+                longitude: -87.25,
+                altitude: None,
+            }),
+            ..Default::default()
+        },
+        Image {
+            photo_guid: "guid2".to_string(),
+            ..Default::default()
+        },
+        // No downloaded file
+        Image {
+            photo_guid: "guid3".to_string(),
+            ..Default::default()
+        },
+    ];
+    let mut state = SyncState::new();
+    for (guid, name) in [("guid1", "a.jpg"), ("guid2", "b.heic")] {
+        let path = store.join(name);
+        tokio::fs::write(&path, guid).await.unwrap();
+        state.record(
+            guid,
+            SyncedFile {
+                path,
+                ..Default::default()
+            },
+        );
+    }
+
+    let export = root.join("export");
+    let exported = export_apple_photos(&export, &album(), &photos, &state)
+        .await
+        .unwrap();
+
+    let album_dir = export.join("Mom_s _Best_ Trip");
+    assert_eq!(
+        exported,
+        vec![album_dir.join("a.jpg"), album_dir.join("b.heic")]
+    );
+    assert_eq!(tokio::fs::read(&exported[0]).await.unwrap(), b"guid1");
+
+    // The modification time is when the photo was taken
+    let modified = tokio::fs::metadata(&exported[0])
+        .await
+        .unwrap()
+        .modified()
+        .unwrap();
+    assert_eq!(modified, UNIX_EPOCH + Duration::from_secs(1_689_377_405));
+
+    let csv = tokio::fs::read_to_string(album_dir.join(CAPTIONS_FILE))
+        .await
+        .unwrap();
+    assert_eq!(
+        csv,
+        "file,caption,date_taken,latitude,longitude,contributor\n\
+         a.jpg,\"Dinner, \"\"finally\"\"\",2023-07-14T23:30:05Z,41.5,-87.25,Jane Doe\n\
+         b.heic,,,,,\n"
+    );
+
+    let plist = tokio::fs::read_to_string(album_dir.join(PLIST_FILE))
+        .await
+        .unwrap();
+    assert!(plist.contains("<string>Mom&apos;s &quot;Best&quot; Trip</string>"));
+    assert!(plist.contains("<key>DateTaken</key><date>2023-07-14T23:30:05Z</date>"));
+    assert!(plist.contains("<key>Caption</key><string>Dinner, &quot;finally&quot;</string>"));
+
+    let script = tokio::fs::read_to_string(album_dir.join(SCRIPT_FILE))
+        .await
+        .unwrap();
+    assert!(script.contains("make new album named \"Mom's \\\"Best\\\" Trip\""));
+    assert!(script.contains("set description of importedItem to \"Dinner, \\\"finally\\\"\""));
+    // The recorded wall-clock time is kept
+    assert!(script.contains("set month of takenDate to 7"));
+    assert!(script.contains("set time of takenDate to 66605"));
+    assert_eq!(script.matches("import {POSIX file").count(), 2);
+
+    // Re-running replaces the earlier export
+    let again = export_apple_photos(&export, &album(), &photos, &state)
+        .await
+        .unwrap();
+    assert_eq!(again, exported);
+
+    tokio::fs::remove_dir_all(&root).await.unwrap();
+}
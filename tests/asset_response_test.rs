@@ -0,0 +1,102 @@
+use icloud_album_rs::asset_response::{
+    parse_range, respond, AssetCachePolicy, AssetRequest, RangeRequest,
+};
+use icloud_album_rs::models::Derivative;
+use std::time::Duration;
+
+fn video() -> Derivative {
+    Derivative {
+        checksum: "abc123".to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_parse_range() {
+    assert_eq!(
+        parse_range("bytes=0-499", 1000),
+        RangeRequest::Partial(0..500)
+    );
+    assert_eq!(
+        parse_range("bytes=500-", 1000),
+        RangeRequest::Partial(500..1000)
+    );
+    assert_eq!(
+        parse_range("bytes=-200", 1000),
+        RangeRequest::Partial(800..1000)
+    );
+    // Ends past the content are cut off
+    assert_eq!(
+        parse_range("bytes=900-5000", 1000),
+        RangeRequest::Partial(900..1000)
+    );
+    assert_eq!(
+        parse_range("bytes=1000-", 1000),
+        RangeRequest::Unsatisfiable
+    );
+    assert_eq!(parse_range("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+    // Multiple ranges, other units and nonsense are ignored
+    assert_eq!(parse_range("bytes=0-1,5-6", 1000), RangeRequest::Full);
+    assert_eq!(parse_range("items=0-1", 1000), RangeRequest::Full);
+    assert_eq!(parse_range("bytes=5-1", 1000), RangeRequest::Full);
+}
+
+#[test]
+fn test_respond_full_and_partial() {
+    let content: Vec<u8> = (0..100).collect();
+    let policy = AssetCachePolicy::default().with_max_age(Duration::from_secs(3600));
+
+    let full = respond(
+        &AssetRequest::default(),
+        &video(),
+        100,
+        Some("video/mp4"),
+        &policy,
+    );
+    assert_eq!(full.status, 200);
+    assert_eq!(full.header("etag"), Some("\"abc123\""));
+    assert_eq!(full.header("Cache-Control"), Some("public, max-age=3600"));
+    assert_eq!(full.header("Accept-Ranges"), Some("bytes"));
+    assert_eq!(full.header("Content-Type"), Some("video/mp4"));
+    assert_eq!(full.header("Content-Length"), Some("100"));
+    assert_eq!(full.body(&content).len(), 100);
+
+    let request = AssetRequest::from_headers([("Range", "bytes=10-19")]);
+    let partial = respond(&request, &video(), 100, None, &policy);
+    assert_eq!(partial.status, 206);
+    assert_eq!(partial.header("Content-Range"), Some("bytes 10-19/100"));
+    assert_eq!(partial.header("Content-Length"), Some("10"));
+    assert_eq!(partial.body(&content), &content[10..20]);
+
+    let request = AssetRequest::from_headers([("range", "bytes=200-")]);
+    let unsatisfiable = respond(&request, &video(), 100, None, &policy);
+    assert_eq!(unsatisfiable.status, 416);
+    assert_eq!(unsatisfiable.header("Content-Range"), Some("bytes */100"));
+    assert!(unsatisfiable.body(&content).is_empty());
+}
+
+#[test]
+fn test_respond_validators() {
+    let policy = AssetCachePolicy::default().private();
+
+    let request = AssetRequest::from_headers([("If-None-Match", "\"other\", W/\"abc123\"")]);
+    let not_modified = respond(&request, &video(), 100, None, &policy);
+    assert_eq!(not_modified.status, 304);
+    assert_eq!(
+        not_modified.header("Cache-Control"),
+        Some("private, max-age=86400")
+    );
+    assert_eq!(not_modified.header("Content-Length"), None);
+
+    // A range of an older version gets the whole current content
+    let request = AssetRequest::from_headers([("Range", "bytes=0-9"), ("If-Range", "\"older\"")]);
+    assert_eq!(respond(&request, &video(), 100, None, &policy).status, 200);
+    let request = AssetRequest::from_headers([("Range", "bytes=0-9"), ("If-Range", "\"abc123\"")]);
+    assert_eq!(respond(&request, &video(), 100, None, &policy).status, 206);
+
+    // Without a checksum there is no ETag to revalidate
+    let request = AssetRequest::from_headers([("If-None-Match", "*")]);
+    let response = respond(&request, &Derivative::default(), 100, None, &policy);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("ETag"), None);
+}
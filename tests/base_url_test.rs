@@ -1,4 +1,7 @@
-use icloud_album_rs::base_url::{get_base_url, BaseUrlError};
+use icloud_album_rs::base_url::{
+    api_origin, calculate_partition, get_base_url, normalize_token, partition_host, share_url,
+    with_api_origin, BaseUrlError, TokenWarning,
+};
 
 #[test]
 fn test_get_base_url_with_different_tokens() {
@@ -39,3 +42,91 @@ fn test_get_base_url_with_invalid_input() {
         other => panic!("Expected InvalidBase62Char error, got {:?}", other),
     }
 }
+
+#[tokio::test]
+async fn test_get_base_url_with_api_origin() {
+    let url = with_api_origin("http://127.0.0.1:8080/", async {
+        assert_eq!(api_origin().as_deref(), Some("http://127.0.0.1:8080"));
+        get_base_url("A0z5qAGN1JIFd3y").unwrap()
+    })
+    .await;
+    assert_eq!(url, "http://127.0.0.1:8080/A0z5qAGN1JIFd3y/sharedstreams/");
+
+    // The override ends with the future
+    assert_eq!(
+        get_base_url("A0z5qAGN1JIFd3y").unwrap(),
+        "https://p11-sharedstreams.icloud.com/A0z5qAGN1JIFd3y/sharedstreams/"
+    );
+}
+
+#[test]
+fn test_partition_queries() {
+    assert_eq!(calculate_partition("B0z5qAGN1JIFd3y"), Ok(12));
+    assert_eq!(calculate_partition("z0z5qAGN1JIFd3y"), Ok(22));
+    assert_eq!(
+        partition_host("B0z5qAGN1JIFd3y").unwrap(),
+        "p12-sharedstreams.icloud.com"
+    );
+    assert_eq!(partition_host(""), Err(BaseUrlError::EmptyToken));
+    assert_eq!(
+        calculate_partition("-abc"),
+        Err(BaseUrlError::InvalidBase62Char('-'))
+    );
+}
+
+#[test]
+fn test_normalize_token() {
+    // A clean token is unchanged
+    let normalized = normalize_token("B1z5qAGN1JIFd3y").unwrap();
+    assert_eq!(normalized.token, "B1z5qAGN1JIFd3y");
+    assert!(normalized.warnings.is_empty());
+
+    // Share URLs pasted from Messages with trailing punctuation
+    let normalized =
+        normalize_token(" https://www.icloud.com/sharedalbum/#B1z5qAGN1JIFd3y;Trip.\u{200B}\n")
+            .unwrap();
+    assert_eq!(normalized.token, "B1z5qAGN1JIFd3y");
+    assert_eq!(
+        normalized.warnings,
+        vec![TokenWarning::TrimmedWhitespace, TokenWarning::StrippedUrl]
+    );
+
+    let normalized = normalize_token("\"B1z5qAGN1JIFd3y\"!").unwrap();
+    assert_eq!(normalized.token, "B1z5qAGN1JIFd3y");
+    assert_eq!(
+        normalized.warnings,
+        vec![TokenWarning::StrippedPunctuation("\"\"!".to_string())]
+    );
+
+    // Zero/O mix-ups can't be fixed, only flagged
+    let normalized = normalize_token("B0z5qAGN1JIFdOy").unwrap();
+    assert_eq!(normalized.token, "B0z5qAGN1JIFdOy");
+    assert_eq!(
+        normalized.warnings,
+        vec![TokenWarning::AmbiguousCharacters(vec![(1, '0'), (13, 'O')])]
+    );
+    assert!(normalized.warnings[0].to_string().contains("'O' at 14"));
+
+    // Nothing usable is still an error
+    assert_eq!(normalize_token("  ...  "), Err(BaseUrlError::EmptyToken));
+    assert_eq!(
+        normalize_token("B1z5-qAGN"),
+        Err(BaseUrlError::InvalidBase62Char('-'))
+    );
+}
+
+#[test]
+fn test_share_url() {
+    let expected = "https://www.icloud.com/sharedalbum/#B1z5qAGN1JIFd3y";
+    assert_eq!(share_url("B1z5qAGN1JIFd3y").unwrap(), expected);
+    // Pasted share URLs come out canonical
+    assert_eq!(
+        share_url(" https://www.icloud.com/sharedalbum/#B1z5qAGN1JIFd3y;Trip. ").unwrap(),
+        expected
+    );
+    assert_eq!(share_url(""), Err(BaseUrlError::EmptyToken));
+    assert_eq!(
+        share_url("B1z5 qAGN"),
+        Err(BaseUrlError::InvalidBase62Char(' '))
+    );
+}
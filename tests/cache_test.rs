@@ -0,0 +1,102 @@
+mod common;
+
+use common::{derivative, photo_with};
+use icloud_album_rs::cache::{enrich_photos_cached, AssetUrlCache};
+use icloud_album_rs::models::Image;
+use serde_json::json;
+use std::time::Duration;
+
+// A photo whose one derivative has the given checksum
+fn checksummed(guid: &str, checksum: &str) -> Image {
+    photo_with(guid, [("1", derivative(checksum, None))])
+}
+
+#[test]
+fn test_cache_get_and_insert() {
+    let cache = AssetUrlCache::default();
+    assert!(cache.get("a", "1").is_none());
+
+    cache.insert("a", "1", "https://example.com/a");
+    assert_eq!(
+        cache.get("a", "1"),
+        Some("https://example.com/a".to_string())
+    );
+    assert!(cache.get("a", "2").is_none());
+    assert_eq!(cache.len(), 1);
+
+    cache.clear();
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_cache_evicts_least_recently_used() {
+    let cache = AssetUrlCache::new(2, Duration::from_secs(60));
+    cache.insert("a", "1", "url-a");
+    cache.insert("b", "1", "url-b");
+
+    // Touch "a" so that "b" becomes the least recently used entry
+    assert!(cache.get("a", "1").is_some());
+    cache.insert("c", "1", "url-c");
+
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get("a", "1").is_some());
+    assert!(cache.get("b", "1").is_none());
+    assert!(cache.get("c", "1").is_some());
+}
+
+#[test]
+fn test_cache_expiry() {
+    let cache = AssetUrlCache::new(10, Duration::ZERO);
+    cache.insert("a", "1", "url-a");
+    assert!(cache.get("a", "1").is_none());
+    // Expired entries are dropped on lookup
+    assert!(cache.is_empty());
+}
+
+#[tokio::test]
+async fn test_enrich_photos_cached() {
+    let mut server = mockito::Server::new_async().await;
+    let base_url = format!("{}/", server.url());
+    let mock = server
+        .mock("POST", "/webasseturls")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({
+                "items": {
+                    "c1": { "url_location": "example.icloud.com", "url_path": "/a.jpg" },
+                    "c2": { "url_location": "example.icloud.com", "url_path": "/b.jpg" }
+                }
+            })
+            .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+
+    let client = reqwest::Client::new();
+    let cache = AssetUrlCache::default();
+
+    let mut photos = vec![checksummed("a", "c1"), checksummed("b", "c2")];
+    let fetched = enrich_photos_cached(&client, &base_url, &mut photos, &cache)
+        .await
+        .unwrap();
+    assert_eq!(fetched, 2);
+    assert_eq!(
+        photos[0].derivatives["1"].url.as_deref(),
+        Some("https://example.icloud.com/a.jpg")
+    );
+
+    // The second lookup is served entirely from the cache
+    let mut photos = vec![checksummed("a", "c1"), checksummed("b", "c2")];
+    let fetched = enrich_photos_cached(&client, &base_url, &mut photos, &cache)
+        .await
+        .unwrap();
+    assert_eq!(fetched, 0);
+    assert_eq!(
+        photos[1].derivatives["1"].url.as_deref(),
+        Some("https://example.icloud.com/b.jpg")
+    );
+
+    mock.assert_async().await;
+}
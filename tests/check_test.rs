@@ -0,0 +1,174 @@
+use icloud_album_rs::api::IssueSeverity;
+use icloud_album_rs::check::{check_album, check_responses};
+use icloud_album_rs::fetch::FetchOptions;
+use serde_json::{json, Value};
+
+fn webstream() -> Value {
+    json!({
+        "streamName": "Holiday",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "1",
+        "itemsReturned": "2",
+        "locations": {},
+        "photos": [
+            {
+                "photoGuid": "photo1",
+                "derivatives": {
+                    "342": { "checksum": "small1", "fileSize": 90, "width": 342, "height": 256 },
+                    "2048": { "checksum": "big1", "fileSize": "900", "width": "2048", "height": "1536" }
+                },
+                "dateCreated": "2024-05-01T12:00:00Z",
+                "width": 2048,
+                "height": 1536
+            },
+            {
+                "photoGuid": "photo2",
+                "derivatives": {
+                    "342": { "checksum": "small2", "fileSize": 90, "width": 342, "height": 256 }
+                }
+            }
+        ]
+    })
+}
+
+fn webasseturls() -> Value {
+    json!({
+        "items": {
+            "small1": { "url_location": "cvws.icloud-content.com", "url_path": "/small1.jpg" },
+            "small2": { "url_location": "cvws.icloud-content.com", "url_path": "/small2.jpg" }
+        }
+    })
+}
+
+#[test]
+fn test_clean_responses_are_compatible() {
+    let report = check_responses(&webstream(), Some(&webasseturls()));
+    assert!(report.is_compatible(), "{}", report);
+    assert_eq!(report.photos, 2);
+    assert_eq!(report.asset_urls, Some(2));
+    assert_eq!(report.unknown_fields().count(), 0);
+    assert!(report.type_drifts.is_empty());
+
+    // Derivative keys and checksums are generalized, and both encodings of numbers are seen
+    let width = report
+        .fields
+        .iter()
+        .find(|field| field.pattern == "photos[].derivatives.*.width")
+        .unwrap();
+    assert_eq!(width.response, "webstream");
+    assert_eq!(width.types, ["integer", "string"]);
+    assert_eq!(width.count, 3);
+    assert!(report
+        .fields
+        .iter()
+        .any(|field| field.response == "webasseturls" && field.pattern == "items.*.url_path"));
+    assert!(report
+        .to_string()
+        .starts_with("Compatibility: compatible\n"));
+}
+
+#[test]
+fn test_reports_unknown_fields_and_type_drifts() {
+    let mut data = webstream();
+    data["streamMood"] = json!({ "value": "sunny" });
+    data["photos"][0]["derivatives"]["342"]["width"] = json!(342.5);
+    data["photos"][1]["derivatives"]["342"]["width"] = json!(true);
+    data["photos"][1]["caption"] = json!(["not", "a", "string"]);
+
+    let report = check_responses(&data, None);
+    let unknown: Vec<&str> = report
+        .unknown_fields()
+        .map(|field| field.pattern.as_str())
+        .collect();
+    // Unknown fields are not descended into
+    assert_eq!(unknown, ["streamMood"]);
+
+    let drifts: Vec<(&str, &str, usize)> = report
+        .type_drifts
+        .iter()
+        .map(|drift| (drift.pattern.as_str(), drift.found, drift.count))
+        .collect();
+    assert_eq!(
+        drifts,
+        [
+            ("photos[].derivatives.*.width", "number", 1),
+            ("photos[].caption", "array", 1),
+            ("photos[].derivatives.*.width", "boolean", 1),
+        ]
+    );
+    assert_eq!(
+        report.type_drifts[0].example,
+        "photos[0].derivatives.342.width"
+    );
+    assert_eq!(report.type_drifts[1].expected, ["null", "string"]);
+    assert!(!report.is_compatible());
+    assert_eq!(report.asset_urls, None);
+
+    let text = report.to_string();
+    assert!(text.starts_with("Compatibility: INCOMPATIBLE\n"));
+    assert!(text.contains("Unknown fields (1):\n  webstream streamMood (object, 1 time(s))"));
+    assert!(text.contains("Asset URLs: not checked"));
+}
+
+#[test]
+fn test_reports_schema_issues_and_skipped_items() {
+    let mut data = webstream();
+    data["photos"][1]
+        .as_object_mut()
+        .unwrap()
+        .remove("photoGuid");
+    let mut urls = webasseturls();
+    urls["items"]["small2"]["url_location"] = json!("");
+
+    let report = check_responses(&data, Some(&urls));
+    assert!(!report.is_compatible());
+    assert!(report
+        .schema_issues
+        .iter()
+        .any(|group| group.field_pattern == "photos[].photoGuid"));
+    assert_eq!(report.photos, 2);
+    assert_eq!(report.skipped_photos.len(), 1);
+    assert_eq!(report.skipped_photos[0].0, 1);
+    assert_eq!(report.asset_urls, Some(1));
+    assert_eq!(report.skipped_assets[0].0, "small2");
+
+    // Without its name the album can't be parsed at all
+    data.as_object_mut().unwrap().remove("streamName");
+    let report = check_responses(&data, None);
+    let stream_name = report
+        .schema_issues
+        .iter()
+        .find(|group| group.field_pattern == "streamName")
+        .unwrap();
+    assert_eq!(stream_name.severity, IssueSeverity::Critical);
+    assert_eq!(report.parse_errors.len(), 1);
+    assert!(report.parse_errors[0].starts_with("webstream: "));
+}
+
+#[tokio::test]
+async fn test_check_album_requests_no_assets() {
+    let mut server = mockito::Server::new_async().await;
+    let base_url = format!("{}/test_token/sharedstreams/", server.url());
+
+    let _webstream = server
+        .mock("POST", "/test_token/sharedstreams/webstream")
+        .with_body(webstream().to_string())
+        .create_async()
+        .await;
+    let webasseturls = server
+        .mock("POST", "/test_token/sharedstreams/webasseturls")
+        .match_body(mockito::Matcher::Json(
+            json!({ "photoGuids": ["photo1", "photo2"] }),
+        ))
+        .with_body(webasseturls().to_string())
+        .expect(1)
+        .create_async()
+        .await;
+
+    let options = FetchOptions::new().with_base_url(&base_url);
+    let report = check_album("test_token", &options).await.unwrap();
+    assert!(report.is_compatible(), "{}", report);
+    assert_eq!(report.asset_urls, Some(2));
+    webasseturls.assert_async().await;
+}
@@ -0,0 +1,52 @@
+//! Fixtures shared by the integration tests
+//!
+//! Every test binary compiles its own copy of this module, so helpers a
+//! binary doesn't use are not dead code.
+#![allow(dead_code)]
+
+use icloud_album_rs::models::{Derivative, Image};
+use std::path::PathBuf;
+
+/// A photo with only its GUID set; fill in other fields with struct update syntax
+pub fn photo(guid: &str) -> Image {
+    Image {
+        photo_guid: guid.to_string(),
+        ..Image::default()
+    }
+}
+
+/// A photo with the given derivatives, keyed by size
+pub fn photo_with<K: Into<String>>(
+    guid: &str,
+    derivatives: impl IntoIterator<Item = (K, Derivative)>,
+) -> Image {
+    Image {
+        derivatives: derivatives
+            .into_iter()
+            .map(|(key, derivative)| (key.into(), derivative))
+            .collect(),
+        ..photo(guid)
+    }
+}
+
+/// A derivative with the given checksum, served from `url` if any
+pub fn derivative(checksum: impl Into<String>, url: Option<String>) -> Derivative {
+    Derivative {
+        checksum: checksum.into(),
+        url,
+        ..Derivative::default()
+    }
+}
+
+/// A path in the system temp directory that is unique to this test process
+pub fn temp_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("icloud-{}-{}", name, std::process::id()))
+}
+
+/// Like [`temp_dir`], but emptied and created
+pub async fn fresh_dir(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    tokio::fs::create_dir_all(&dir).await.unwrap();
+    dir
+}
@@ -0,0 +1,198 @@
+mod common;
+
+use common::photo_with;
+use icloud_album_rs::compact::{CompactAlbum, CompactOptions};
+use icloud_album_rs::fetch::FetchOptions;
+use icloud_album_rs::models::{Derivative, ICloudResponse, Image, Metadata};
+use serde_json::json;
+use std::collections::HashMap;
+
+fn derivative(checksum: &str, width: u32, url: Option<&str>) -> Derivative {
+    Derivative {
+        checksum: checksum.to_string(),
+        file_size: Some(width as u64 * 100),
+        width: Some(width),
+        height: Some(width * 3 / 4),
+        url: url.map(str::to_string),
+        ..Default::default()
+    }
+}
+
+// A captioned, dated photo with two resolved derivatives
+fn resolved(guid: &str) -> Image {
+    let url = |size: u32| {
+        format!(
+            "https://cvws.icloud-content.com/B/{}/{}.jpg?o=x",
+            guid, size
+        )
+    };
+    Image {
+        caption: Some("A caption that the compact album drops".to_string()),
+        date_created: Some("2024-05-01T12:00:00Z".to_string()),
+        ..photo_with(
+            guid,
+            [
+                ("2048", derivative("big", 2048, Some(&url(2048)))),
+                ("342", derivative("small", 342, Some(&url(342)))),
+            ],
+        )
+    }
+}
+
+fn response(count: usize) -> ICloudResponse {
+    let mut metadata = Metadata::new("Frame", "John", "Doe");
+    metadata.stream_ctag = "1".into();
+    metadata.items_returned = count as u32;
+    ICloudResponse {
+        metadata,
+        photos: (0..count)
+            .map(|i| resolved(&format!("guid{}", i)))
+            .collect(),
+        unparsed: Vec::new(),
+    }
+}
+
+#[test]
+fn test_keeps_smallest_derivative() {
+    let album = CompactAlbum::from_response(&response(3), &CompactOptions::new());
+    assert_eq!(album.name(), "Frame");
+    assert_eq!(album.len(), 3);
+    assert_eq!(album.omitted(), 0);
+
+    let first = album.get(0).unwrap();
+    assert_eq!(first.guid, "guid0");
+    assert_eq!((first.width, first.height), (Some(342), Some(256)));
+    assert_eq!(first.taken_at, Some(1_714_564_800));
+    assert_eq!(
+        first.url().as_deref(),
+        Some("https://cvws.icloud-content.com/B/guid0/342.jpg?o=x")
+    );
+    let guids: Vec<_> = album.photos().map(|photo| photo.guid).collect();
+    assert_eq!(guids, ["guid0", "guid1", "guid2"]);
+    assert!(album.get(3).is_none());
+}
+
+#[test]
+fn test_interns_url_origins() {
+    let one = CompactAlbum::from_response(&response(1), &CompactOptions::new());
+    let many = CompactAlbum::from_response(&response(101), &CompactOptions::new());
+    // The shared origin is stored once, so each further photo costs less than its URL
+    let per_photo = (many.bytes() - one.bytes()) / 100;
+    let url_len = many.get(1).unwrap().url().unwrap().len();
+    assert!(per_photo < url_len + 64, "{} bytes per photo", per_photo);
+}
+
+#[test]
+fn test_respects_byte_budget() {
+    let unlimited = CompactAlbum::from_response(&response(50), &CompactOptions::new());
+    let budget = unlimited.bytes() / 2;
+    let album =
+        CompactAlbum::from_response(&response(50), &CompactOptions::new().with_max_bytes(budget));
+    assert!(album.bytes() <= budget);
+    assert!(!album.is_empty());
+    assert_eq!(album.len() + album.omitted(), 50);
+    assert_eq!(album.get(0).unwrap().guid, "guid0");
+
+    // A budget too small for anything keeps no photos
+    let album = CompactAlbum::from_response(&response(5), &CompactOptions::new().with_max_bytes(1));
+    assert!(album.is_empty());
+    assert_eq!(album.omitted(), 5);
+}
+
+#[test]
+fn test_skips_videos_and_missing_urls() {
+    let mut video = resolved("video");
+    video.derivatives = HashMap::from([(
+        "720p".to_string(),
+        Derivative {
+            media_type: Some("video".to_string()),
+            ..derivative("movie", 1280, None)
+        },
+    )]);
+    let mut unresolved = resolved("unresolved");
+    for derivative in unresolved.derivatives.values_mut() {
+        derivative.url = None;
+    }
+    let response = ICloudResponse {
+        photos: vec![video, unresolved],
+        ..response(0)
+    };
+
+    let album = CompactAlbum::from_response(&response, &CompactOptions::new());
+    let video = album.get(0).unwrap();
+    assert_eq!((video.width, video.url()), (None, None));
+    let unresolved = album.get(1).unwrap();
+    assert_eq!(unresolved.width, Some(342));
+    assert_eq!(unresolved.url(), None);
+}
+
+#[tokio::test]
+async fn test_fetch_resolves_only_kept_derivatives() {
+    let mut server = mockito::Server::new_async().await;
+    let base_url = format!("{}/test_token/sharedstreams/", server.url());
+
+    let _webstream = server
+        .mock("POST", "/test_token/sharedstreams/webstream")
+        .with_body(
+            json!({
+                "streamName": "Frame",
+                "userFirstName": "John",
+                "userLastName": "Doe",
+                "streamCtag": "1",
+                "itemsReturned": 1,
+                "locations": {},
+                "photos": [{
+                    "photoGuid": "photo1",
+                    "derivatives": {
+                        "2048": { "checksum": "big", "fileSize": 900, "width": 2048, "height": 1536 },
+                        "342": { "checksum": "small", "fileSize": 90, "width": 342, "height": 256 }
+                    },
+                    "caption": "Dropped",
+                    "dateCreated": "2024-05-01T12:00:00Z",
+                    "batchDateCreated": "2024-05-01T12:00:00Z",
+                    "width": 2048,
+                    "height": 1536
+                }]
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+    let _webasseturls = server
+        .mock("POST", "/test_token/sharedstreams/webasseturls")
+        .with_body(
+            json!({
+                "items": {
+                    "big": { "url_location": "cvws.icloud-content.com", "url_path": "/big.jpg" },
+                    "small": { "url_location": "cvws.icloud-content.com", "url_path": "/small.jpg" }
+                }
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+
+    // The trimmed response has only the smallest derivative and no caption
+    let options = FetchOptions::new()
+        .with_base_url(&base_url)
+        .with_compact(CompactOptions::new());
+    let response = icloud_album_rs::get_icloud_photos_with("test_token", &options)
+        .await
+        .unwrap();
+    let photo = &response.photos[0];
+    assert_eq!(photo.derivatives.len(), 1);
+    assert_eq!(
+        photo.derivatives["342"].url.as_deref(),
+        Some("https://cvws.icloud-content.com/small.jpg")
+    );
+    assert!(photo.caption.is_none());
+
+    let album = CompactAlbum::fetch("test_token", &FetchOptions::new().with_base_url(&base_url))
+        .await
+        .unwrap();
+    assert_eq!(album.len(), 1);
+    assert_eq!(
+        album.get(0).unwrap().url().as_deref(),
+        Some("https://cvws.icloud-content.com/small.jpg")
+    );
+}
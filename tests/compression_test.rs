@@ -0,0 +1,94 @@
+#![cfg(feature = "compression")]
+
+use flate2::write::GzEncoder;
+use icloud_album_rs::api::{get_api_response, WebstreamResponse};
+use icloud_album_rs::compression::{
+    compression_stats, decode, set_accept_encoding, AcceptEncoding,
+};
+use serde_json::json;
+use std::io::Write;
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn test_decode() {
+    let data = b"{\"photos\": []}".repeat(100);
+    assert_eq!(decode("gzip", &gzip(&data)).unwrap(), data);
+    assert_eq!(decode("identity", &data).unwrap(), data);
+    assert!(decode("zstd", &data).is_err());
+    assert!(decode("gzip", b"not gzip").is_err());
+
+    assert_eq!(AcceptEncoding::default().header_value(), Some("br, gzip"));
+    assert_eq!(AcceptEncoding::identity().header_value(), None);
+}
+
+#[tokio::test]
+async fn test_webstream_requested_compressed() {
+    let mut server = mockito::Server::new_async().await;
+    let photos: Vec<_> = (0..200)
+        .map(|i| {
+            json!({
+                "photoGuid": format!("photo{}", i),
+                "derivatives": {
+                    "1": { "checksum": format!("checksum{}", i), "fileSize": 1000, "width": 100, "height": 100 }
+                },
+                "caption": "A caption that repeats across many photos"
+            })
+        })
+        .collect();
+    let body = json!({
+        "streamName": "Test Album",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "12345",
+        "itemsReturned": 200,
+        "locations": {},
+        "photos": photos
+    })
+    .to_string();
+    let compressed = gzip(body.as_bytes());
+
+    let mock = server
+        .mock("POST", "/webstream")
+        .match_header("accept-encoding", "br, gzip")
+        .with_header("content-encoding", "gzip")
+        .with_body(compressed.clone())
+        .create_async()
+        .await;
+
+    let before = compression_stats();
+    let client = reqwest::Client::new();
+    let WebstreamResponse {
+        photos: images,
+        metadata,
+        ..
+    } = get_api_response(&client, &format!("{}/", server.url()))
+        .await
+        .unwrap();
+    mock.assert_async().await;
+    assert_eq!(images.len(), 200);
+    assert_eq!(metadata.stream_name, "Test Album");
+
+    let after = compression_stats();
+    assert!(after.compressed_responses > before.compressed_responses);
+    assert!(after.wire_bytes - before.wire_bytes >= compressed.len() as u64);
+    assert!(after.decoded_bytes - before.decoded_bytes >= body.len() as u64);
+    assert!(after.saved_bytes() > 0);
+
+    // Without accepted encodings, no Accept-Encoding header is sent
+    set_accept_encoding(AcceptEncoding::identity());
+    let plain = server
+        .mock("POST", "/webstream")
+        .match_header("accept-encoding", mockito::Matcher::Missing)
+        .with_body(body)
+        .create_async()
+        .await;
+    let result = get_api_response(&client, &format!("{}/", server.url())).await;
+    set_accept_encoding(AcceptEncoding::default());
+    plain.assert_async().await;
+    assert_eq!(result.unwrap().photos.len(), 200);
+}
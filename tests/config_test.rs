@@ -0,0 +1,141 @@
+use icloud_album_rs::config::{
+    Config, EnvConfigError, MirrorConfig, CONCURRENCY_ENV, INTERVAL_SECS_ENV, OUTPUT_DIR_ENV,
+    RETRY_DELAY_MS_ENV, RETRY_MAX_ENV, TIMEOUT_SECS_ENV, TOKENS_ENV,
+};
+use icloud_album_rs::download::DownloadOptions;
+use icloud_album_rs::fetch::FetchOptions;
+use icloud_album_rs::throttle::Concurrency;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn config(vars: &[(&str, &str)]) -> Result<Config, EnvConfigError> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    Config::from_lookup(|name| vars.get(name).cloned())
+}
+
+#[test]
+fn test_config_from_lookup() {
+    assert!(config(&[]).unwrap().is_empty());
+    assert!(config(&[(CONCURRENCY_ENV, " ")]).unwrap().is_empty());
+
+    let config = config(&[
+        (CONCURRENCY_ENV, "8"),
+        (RETRY_MAX_ENV, " 5 "),
+        (TIMEOUT_SECS_ENV, "30"),
+    ])
+    .unwrap();
+    assert_eq!(config.concurrency, Some(8));
+    assert_eq!(config.retry_max, Some(5));
+    assert_eq!(config.retry_delay_ms, None);
+    assert_eq!(config.timeout, Some(Duration::from_secs(30)));
+}
+
+#[test]
+fn test_config_rejects_invalid_values() {
+    assert_eq!(
+        config(&[(RETRY_MAX_ENV, "lots")]),
+        Err(EnvConfigError {
+            name: RETRY_MAX_ENV,
+            value: "lots".to_string(),
+            expected: "a number",
+        })
+    );
+    assert!(config(&[(CONCURRENCY_ENV, "0")]).is_err());
+    assert!(config(&[(TIMEOUT_SECS_ENV, "-1")]).is_err());
+}
+
+#[test]
+fn test_config_applies_to_options() {
+    let config = config(&[
+        (CONCURRENCY_ENV, "4"),
+        (RETRY_DELAY_MS_ENV, "100"),
+        (TIMEOUT_SECS_ENV, "10"),
+    ])
+    .unwrap();
+
+    let retry = config.retry_config().unwrap();
+    assert_eq!(retry.base_delay_ms, 100);
+    // Settings left out keep their defaults
+    assert_eq!(retry.max_retries, 3);
+
+    let download = config.apply_to_download(DownloadOptions::default());
+    assert_eq!(download.concurrency, Concurrency::Fixed(4));
+    assert_eq!(download.retry.unwrap().base_delay_ms, 100);
+    assert_eq!(
+        download.network.connect_timeout,
+        Some(Duration::from_secs(10))
+    );
+
+    let fetch = config.apply_to_fetch(FetchOptions::default());
+    assert!(fetch.client.is_some());
+    assert_eq!(fetch.retry.unwrap().default.base_delay_ms, 100);
+
+    // Nothing configured changes nothing
+    let fetch = Config::default().apply_to_fetch(FetchOptions::default());
+    assert!(fetch.client.is_none() && fetch.retry.is_none());
+    assert!(Config::default().retry_config().is_none());
+}
+
+#[test]
+fn test_mirror_config_from_lookup() {
+    let lookup = |vars: &'static [(&'static str, &'static str)]| {
+        move |name: &str| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        }
+    };
+
+    let config = MirrorConfig::from_lookup(lookup(&[
+        (
+            TOKENS_ENV,
+            "B0z5qAGN1JIFd3y, https://www.icloud.com/sharedalbum/#B1abcDEF2ghiJKL\nC3xyz",
+        ),
+        (OUTPUT_DIR_ENV, "/photos"),
+        (INTERVAL_SECS_ENV, "3600"),
+        (CONCURRENCY_ENV, "2"),
+    ]))
+    .unwrap();
+    assert_eq!(
+        config.tokens,
+        ["B0z5qAGN1JIFd3y", "B1abcDEF2ghiJKL", "C3xyz"]
+    );
+    assert_eq!(config.album_dir("C3xyz"), PathBuf::from("/photos/C3xyz"));
+    assert_eq!(config.interval, Some(Duration::from_secs(3600)));
+    assert_eq!(config.config.concurrency, Some(2));
+
+    // Without an interval, the albums are mirrored once
+    let config =
+        MirrorConfig::from_lookup(lookup(&[(TOKENS_ENV, "C3xyz"), (OUTPUT_DIR_ENV, "out")]))
+            .unwrap();
+    assert_eq!(config.interval, None);
+
+    assert_eq!(
+        MirrorConfig::from_lookup(lookup(&[(OUTPUT_DIR_ENV, "out")]))
+            .unwrap_err()
+            .name,
+        TOKENS_ENV
+    );
+    assert_eq!(
+        MirrorConfig::from_lookup(lookup(&[(TOKENS_ENV, "C3xyz")]))
+            .unwrap_err()
+            .name,
+        OUTPUT_DIR_ENV
+    );
+    assert_eq!(
+        MirrorConfig::from_lookup(lookup(&[(TOKENS_ENV, "C3-xyz"), (OUTPUT_DIR_ENV, "out")]))
+            .unwrap_err()
+            .value,
+        "C3-xyz"
+    );
+    assert!(MirrorConfig::from_lookup(lookup(&[
+        (TOKENS_ENV, "C3xyz"),
+        (OUTPUT_DIR_ENV, "out"),
+        (INTERVAL_SECS_ENV, "0"),
+    ]))
+    .is_err());
+}
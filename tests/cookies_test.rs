@@ -0,0 +1,136 @@
+#![cfg(feature = "cookies")]
+
+use icloud_album_rs::cookies::CookieJar;
+use icloud_album_rs::net::NetworkConfig;
+use icloud_album_rs::state_store::JsonFileStore;
+use std::sync::Arc;
+
+fn url(s: &str) -> url::Url {
+    url::Url::parse(s).unwrap()
+}
+
+#[test]
+fn test_cookie_matching() {
+    let jar = CookieJar::new();
+    let origin = url("https://p12-sharedstreams.icloud.com/TOKEN/sharedstreams/webstream");
+    jar.store_response_cookies(
+        [
+            "session=abc; Path=/; Secure",
+            "scoped=1",
+            "shared=2; Domain=.icloud.com; Path=/; Max-Age=3600",
+            "foreign=3; Domain=example.com",
+            "invalid",
+        ],
+        &origin,
+    );
+    assert_eq!(jar.cookies().len(), 3);
+
+    // Longer paths first; the default path is the request's directory
+    assert_eq!(
+        jar.cookie_header(&origin).as_deref(),
+        Some("scoped=1; session=abc; shared=2")
+    );
+    assert_eq!(
+        jar.cookie_header(&url("https://p12-sharedstreams.icloud.com/other"))
+            .as_deref(),
+        Some("session=abc; shared=2")
+    );
+    // Domain cookies reach subdomains, host-only and secure cookies don't
+    assert_eq!(
+        jar.cookie_header(&url("https://cvws.icloud-content.com/")),
+        None
+    );
+    assert_eq!(
+        jar.cookie_header(&url("http://www.icloud.com/")).as_deref(),
+        Some("shared=2")
+    );
+
+    // Expired cookies remove stored ones
+    jar.store_response_cookies(
+        [
+            "session=; Path=/; Max-Age=0",
+            "scoped=; Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+        ],
+        &origin,
+    );
+    assert_eq!(jar.cookie_header(&origin).as_deref(), Some("shared=2"));
+}
+
+#[test]
+fn test_cookie_persistence() {
+    let path = std::env::temp_dir()
+        .join(format!("icloud-cookies-{}", std::process::id()))
+        .join("cookies.json");
+    let _ = std::fs::remove_file(&path);
+
+    let jar = CookieJar::load(&path).unwrap();
+    assert!(jar.cookies().is_empty());
+    let origin = url("https://p12-sharedstreams.icloud.com/");
+    jar.store_response_cookies(
+        [
+            "session=abc",
+            "kept=1; Expires=Fri, 01 Jan 2100 00:00:00 GMT",
+        ],
+        &origin,
+    );
+    jar.save().unwrap();
+
+    // Session cookies are not persisted
+    let reloaded = CookieJar::load(&path).unwrap();
+    let cookies = reloaded.cookies();
+    assert_eq!(cookies.len(), 1);
+    assert_eq!(cookies[0].name, "kept");
+    assert_eq!(cookies[0].expires_at, Some(4_102_444_800));
+
+    // The same cookies can be kept in a state store
+    let store = JsonFileStore::new(path.parent().unwrap().join("store"));
+    jar.save_to_store(&store, "cookies").unwrap();
+    assert_eq!(
+        CookieJar::load_from_store(&store, "cookies")
+            .unwrap()
+            .cookies(),
+        cookies
+    );
+    assert!(CookieJar::load_from_store(&store, "missing")
+        .unwrap()
+        .cookies()
+        .is_empty());
+
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn test_clients_send_stored_cookies() {
+    let mut server = mockito::Server::new_async().await;
+    let set = server
+        .mock("GET", "/login")
+        .with_header("set-cookie", "token=xyz; Path=/")
+        .create_async()
+        .await;
+    let check = server
+        .mock("GET", "/album")
+        .match_header("cookie", "token=xyz")
+        .create_async()
+        .await;
+
+    let jar = Arc::new(CookieJar::new());
+    let client = NetworkConfig::new()
+        .with_cookies(Arc::clone(&jar))
+        .build_client()
+        .unwrap();
+    client
+        .get(format!("{}/login", server.url()))
+        .send()
+        .await
+        .unwrap();
+    let response = client
+        .get(format!("{}/album", server.url()))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    assert_eq!(jar.cookies()[0].value, "xyz");
+
+    set.assert_async().await;
+    check.assert_async().await;
+}
@@ -0,0 +1,90 @@
+use icloud_album_rs::derivatives::{
+    classify_derivative, classify_derivative_key, is_original_key, lookup_derivative_key,
+    SizeClass, KNOWN_DERIVATIVE_KEYS,
+};
+use icloud_album_rs::models::Derivative;
+
+#[test]
+fn test_known_keys_are_unique() {
+    for (i, info) in KNOWN_DERIVATIVE_KEYS.iter().enumerate() {
+        assert!(
+            KNOWN_DERIVATIVE_KEYS[i + 1..]
+                .iter()
+                .all(|other| other.key != info.key),
+            "Duplicate derivative key in table: {}",
+            info.key
+        );
+        assert_eq!(lookup_derivative_key(info.key), Some(info));
+    }
+}
+
+#[test]
+fn test_classify_derivative_key() {
+    // Keys from the table
+    assert_eq!(classify_derivative_key("3"), Some(SizeClass::Original));
+    assert_eq!(
+        classify_derivative_key("PosterFrame"),
+        Some(SizeClass::VideoPoster)
+    );
+    assert_eq!(classify_derivative_key("720p"), Some(SizeClass::Video));
+
+    // Name patterns
+    assert_eq!(
+        classify_derivative_key("original"),
+        Some(SizeClass::Original)
+    );
+    assert_eq!(
+        classify_derivative_key("FullSize"),
+        Some(SizeClass::Original)
+    );
+
+    // Numeric keys interpreted as pixel dimensions
+    assert_eq!(classify_derivative_key("342"), Some(SizeClass::Thumbnail));
+    assert_eq!(classify_derivative_key("1024"), Some(SizeClass::Medium));
+    assert_eq!(classify_derivative_key("2049"), Some(SizeClass::Large));
+
+    // Small numeric keys and unknown names are not classified
+    assert_eq!(classify_derivative_key("1"), None);
+    assert_eq!(classify_derivative_key("medium"), None);
+}
+
+#[test]
+fn test_classify_derivative_prefers_declared_type() {
+    let declared = |asset_type: &str| Derivative {
+        media_asset_type: Some(asset_type.to_string()),
+        ..Default::default()
+    };
+
+    // Without hints, the key decides
+    assert_eq!(
+        classify_derivative("342", &Derivative::default()),
+        Some(SizeClass::Thumbnail)
+    );
+    // A declared video is a video under any key
+    assert_eq!(
+        classify_derivative("2049", &declared("video")),
+        Some(SizeClass::Video)
+    );
+    assert_eq!(
+        classify_derivative("medium", &declared("video")),
+        Some(SizeClass::Video)
+    );
+    // A still image under a video key is its poster frame
+    assert_eq!(
+        classify_derivative("720p", &declared("image")),
+        Some(SizeClass::VideoPoster)
+    );
+    assert_eq!(
+        classify_derivative("3", &declared("image")),
+        Some(SizeClass::Original)
+    );
+}
+
+#[test]
+fn test_is_original_key() {
+    assert!(is_original_key("3"));
+    assert!(is_original_key("4"));
+    assert!(is_original_key("original"));
+    assert!(!is_original_key("1"));
+    assert!(!is_original_key("342"));
+}
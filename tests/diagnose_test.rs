@@ -0,0 +1,155 @@
+use icloud_album_rs::base_url::with_api_origin;
+use icloud_album_rs::diagnose::{diagnose, DiagnosisReport, Health};
+use icloud_album_rs::fetch::FetchOptions;
+use serde_json::json;
+
+const TOKEN: &str = "B0z5qAGN1JIFd3y";
+
+async fn mock_album(server: &mut mockito::ServerGuard, resolve_big: bool) -> Vec<mockito::Mock> {
+    let webstream = server
+        .mock("POST", format!("/{}/sharedstreams/webstream", TOKEN).as_str())
+        .with_body(
+            json!({
+                "streamName": "Holiday",
+                "userFirstName": "John",
+                "userLastName": "Doe",
+                "streamCtag": "1",
+                "itemsReturned": 2,
+                "locations": {},
+                "photos": [
+                    {
+                        "photoGuid": "photo1",
+                        "derivatives": {
+                            "342": { "checksum": "small1", "fileSize": 90, "width": 342, "height": 256 },
+                            "2048": { "checksum": "big1", "fileSize": 900, "width": 2048, "height": 1536 }
+                        }
+                    },
+                    {
+                        "photoGuid": "photo2",
+                        "derivatives": {
+                            "342": { "checksum": "small2", "fileSize": 90, "width": 342, "height": 256 }
+                        }
+                    }
+                ]
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+    let mut items = json!({
+        "small1": { "url_location": "cvws.icloud-content.com", "url_path": "/small1.jpg" },
+        "small2": { "url_location": "cvws.icloud-content.com", "url_path": "/small2.jpg" }
+    });
+    if resolve_big {
+        items["big1"] =
+            json!({ "url_location": "cvws.icloud-content.com", "url_path": "/big1.jpg" });
+    }
+    let webasseturls = server
+        .mock(
+            "POST",
+            format!("/{}/sharedstreams/webasseturls", TOKEN).as_str(),
+        )
+        .with_body(json!({ "items": items }).to_string())
+        .create_async()
+        .await;
+    vec![webstream, webasseturls]
+}
+
+async fn run(server: &mockito::ServerGuard) -> DiagnosisReport {
+    let options =
+        FetchOptions::new().with_base_url(format!("{}/{}/sharedstreams/", server.url(), TOKEN));
+    with_api_origin(&server.url(), diagnose(TOKEN, &options)).await
+}
+
+#[tokio::test]
+async fn test_healthy_album() {
+    let mut server = mockito::Server::new_async().await;
+    let _album = mock_album(&mut server, true).await;
+    let mut assets = Vec::new();
+    for path in ["/small1.jpg", "/small2.jpg"] {
+        let asset = server
+            .mock("GET", path)
+            .match_header("range", "bytes=0-0")
+            .with_status(206)
+            .with_body("x")
+            .expect(1)
+            .create_async()
+            .await;
+        assets.push(asset);
+    }
+
+    let report = run(&server).await;
+    assert_eq!(report.health(), Health::Healthy, "{}", report);
+    assert_eq!(report.score(), 100);
+    assert!(!report.redirect.as_ref().unwrap().redirected);
+    let requests: Vec<&str> = report.latencies.iter().map(|l| l.request).collect();
+    assert_eq!(requests, ["webstream", "webasseturls", "asset", "asset"]);
+    assert!(report.slowest().is_some());
+    let urls = report.url_resolution.unwrap();
+    assert_eq!((urls.resolved, urls.requested), (3, 3));
+    assert!(report.assets.iter().all(|asset| asset.is_available()));
+    assert!(report.compatibility.as_ref().unwrap().is_compatible());
+    for asset in assets {
+        asset.assert_async().await;
+    }
+
+    let text = report.to_string();
+    assert!(text.starts_with("Health: healthy (100/100)\nRedirect: none ("));
+    assert!(text.contains("URL resolution: 3/3 derivatives (100%)"));
+}
+
+#[tokio::test]
+async fn test_degraded_album() {
+    let mut server = mockito::Server::new_async().await;
+    let _album = mock_album(&mut server, false).await;
+    let _small1 = server
+        .mock("GET", "/small1.jpg")
+        .with_status(206)
+        .create_async()
+        .await;
+    let _small2 = server
+        .mock("GET", "/small2.jpg")
+        .with_status(403)
+        .create_async()
+        .await;
+
+    let report = run(&server).await;
+    assert_eq!(report.health(), Health::Degraded, "{}", report);
+    let urls = report.url_resolution.unwrap();
+    assert_eq!((urls.resolved, urls.requested), (2, 3));
+    assert_eq!(report.assets[1].status, Some(403));
+    assert!(!report.assets[1].is_available());
+    // 40 for the schema, 20 for two thirds of the URLs and 15 for half of the assets
+    assert_eq!(report.score(), 75);
+    assert!(report.to_string().contains("Asset small2: HTTP 403"));
+}
+
+#[tokio::test]
+async fn test_unreachable_album() {
+    let mut server = mockito::Server::new_async().await;
+    let _webstream = server
+        .mock(
+            "POST",
+            format!("/{}/sharedstreams/webstream", TOKEN).as_str(),
+        )
+        .with_status(500)
+        .create_async()
+        .await;
+
+    let report = run(&server).await;
+    assert_eq!(report.health(), Health::Broken);
+    assert_eq!(report.score(), 0);
+    assert!(report.compatibility.is_none());
+    assert!(
+        report.errors[0].starts_with("webstream: "),
+        "{:?}",
+        report.errors
+    );
+    assert_eq!(report.latencies.len(), 1);
+
+    let report = diagnose("", &FetchOptions::new()).await;
+    assert_eq!(report.health(), Health::Broken);
+    assert!(report.errors[0].starts_with("invalid token: "));
+    assert!(report.latencies.is_empty());
+    assert!(report.to_string().contains("Redirect: album not reached"));
+}
@@ -0,0 +1,56 @@
+// The capture directory is process-wide, so this binary has a single test
+use icloud_album_rs::api::{parse_webstream_response, WebstreamResponse};
+use icloud_album_rs::diagnostics::{self, MAX_CAPTURES_PER_RESPONSE};
+use serde_json::json;
+
+#[test]
+fn test_parse_failures_are_captured() {
+    let dir = std::env::temp_dir().join(format!("icloud-diagnostics-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let mut photos = vec![json!({
+        "photoGuid": "good",
+        "derivatives": { "1": { "checksum": "abc" } }
+    })];
+    for i in 0..MAX_CAPTURES_PER_RESPONSE + 2 {
+        photos.push(json!({
+            "photoGuid": format!("secret-guid-{}", i),
+            "caption": "Private caption",
+            "derivatives": "not an object"
+        }));
+    }
+    let response = json!({
+        "streamName": "Album",
+        "streamCtag": "ctag",
+        "photos": photos
+    });
+
+    // Nothing is written while capture is disabled
+    let report = parse_webstream_response(&response).unwrap().report;
+    assert!(report.captured_fixtures.is_empty());
+    assert!(!dir.exists());
+
+    diagnostics::set_capture_dir(Some(dir.clone()));
+    let WebstreamResponse {
+        photos: parsed,
+        report,
+        ..
+    } = parse_webstream_response(&response).unwrap();
+    diagnostics::set_capture_dir(None);
+
+    assert_eq!(parsed.len(), 1);
+    assert_eq!(report.skipped_photos.len(), MAX_CAPTURES_PER_RESPONSE + 2);
+    assert_eq!(report.captured_fixtures.len(), MAX_CAPTURES_PER_RESPONSE);
+
+    let fixture: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&report.captured_fixtures[0]).unwrap()).unwrap();
+    assert_eq!(fixture["kind"], "photo");
+    assert_eq!(fixture["index"], 1);
+    assert!(fixture["error"].as_str().unwrap().contains("invalid type"));
+    assert_eq!(fixture["fragment"]["derivatives"], "not an object");
+    let text = fixture.to_string();
+    assert!(!text.contains("secret-guid"));
+    assert!(!text.contains("Private caption"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
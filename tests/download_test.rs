@@ -0,0 +1,1190 @@
+mod common;
+
+use common::temp_dir;
+use icloud_album_rs::api::RetryConfig;
+use icloud_album_rs::base_url::with_api_origin;
+use icloud_album_rs::derivatives::SizeClass;
+use icloud_album_rs::download::{
+    check_asset_body, download_derivative_classes, download_photo_if_changed_with_options,
+    download_photo_with_options, move_file, render_file_name, sync_parent_dir, DownloadOptions,
+    HedgeConfig, MemoryBudget, SpeedFloor, StallConfig, TRANSFER_BUFFER_BYTES,
+};
+use icloud_album_rs::download_photo_if_changed;
+use icloud_album_rs::models::{Derivative, DownloadOutcome, HttpValidators, Image};
+use icloud_album_rs::shutdown::Shutdown;
+use icloud_album_rs::throttle::{AimdConfig, CircuitBreakerConfig, Concurrency};
+use icloud_album_rs::transport::{with_transport, Transport, TransportRequest, TransportResponse};
+use std::collections::HashMap;
+
+// PNG signature followed by some padding
+const PNG_BYTES: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+fn photo_with_url(url: String) -> Image {
+    common::photo_with("photo123", [("1", common::derivative("c1", Some(url)))])
+}
+
+#[tokio::test]
+async fn test_download_photo_if_changed() {
+    let mut server = mockito::Server::new_async().await;
+    let output_dir = temp_dir("conditional-download")
+        .to_string_lossy()
+        .into_owned();
+    let photo = photo_with_url(format!("{}/photo.png", server.url()));
+
+    // Without validators the file is downloaded and the new validators returned
+    let full = server
+        .mock("GET", "/photo.png")
+        .match_header("if-none-match", mockito::Matcher::Missing)
+        .with_status(200)
+        .with_header("etag", "\"v1\"")
+        .with_header("last-modified", "Sun, 01 Jan 2023 00:00:00 GMT")
+        .with_body(PNG_BYTES)
+        .create_async()
+        .await;
+
+    let outcome = download_photo_if_changed(&photo, None, &output_dir, None, &Default::default())
+        .await
+        .unwrap();
+    let DownloadOutcome::Downloaded { path, validators } = outcome else {
+        panic!("expected a download, got {:?}", outcome);
+    };
+    assert!(path.ends_with("photo123.png"));
+    assert_eq!(tokio::fs::read(&path).await.unwrap(), PNG_BYTES);
+    assert_eq!(
+        validators,
+        HttpValidators {
+            etag: Some("\"v1\"".to_string()),
+            last_modified: Some("Sun, 01 Jan 2023 00:00:00 GMT".to_string()),
+        }
+    );
+    full.assert_async().await;
+
+    // With matching validators the server answers 304 and nothing is written
+    let not_modified = server
+        .mock("GET", "/photo.png")
+        .match_header("if-none-match", "\"v1\"")
+        .match_header("if-modified-since", "Sun, 01 Jan 2023 00:00:00 GMT")
+        .with_status(304)
+        .create_async()
+        .await;
+    tokio::fs::remove_file(&path).await.unwrap();
+
+    let outcome = download_photo_if_changed(&photo, None, &output_dir, None, &validators)
+        .await
+        .unwrap();
+    assert_eq!(outcome, DownloadOutcome::NotModified);
+    assert!(tokio::fs::metadata(&path).await.is_err());
+    not_modified.assert_async().await;
+
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_download_photo_if_changed_streams_within_budget() {
+    let mut server = mockito::Server::new_async().await;
+    let body = [PNG_BYTES, &vec![0; 1024 * 1024]].concat();
+    server
+        .mock("GET", "/large.png")
+        .with_header("etag", "\"v1\"")
+        .with_body(&body)
+        .create_async()
+        .await;
+    let output_dir = temp_dir("conditional-budget")
+        .to_string_lossy()
+        .into_owned();
+    let photo = photo_with_url(format!("{}/large.png", server.url()));
+
+    let budget = std::sync::Arc::new(MemoryBudget::new(TRANSFER_BUFFER_BYTES));
+    let options = DownloadOptions::new().with_memory_budget(budget.clone());
+    let outcome = download_photo_if_changed_with_options(
+        &photo,
+        None,
+        &output_dir,
+        None,
+        &HttpValidators::default(),
+        &options,
+    )
+    .await
+    .unwrap();
+    let DownloadOutcome::Downloaded { path, validators } = outcome else {
+        panic!("expected a download, got {:?}", outcome);
+    };
+    // The body is larger than the budget, so it can only have been streamed
+    assert_eq!(tokio::fs::read(&path).await.unwrap(), body);
+    assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+    assert_eq!(budget.in_use(), 0);
+
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_download_photo_if_changed_reports_http_errors() {
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/missing.png")
+        .with_status(404)
+        .create_async()
+        .await;
+
+    let photo = photo_with_url(format!("{}/missing.png", server.url()));
+    let result = download_photo_if_changed(
+        &photo,
+        None,
+        &temp_dir("conditional-missing").to_string_lossy(),
+        None,
+        &HttpValidators::default(),
+    )
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_download_with_temp_dir() {
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/photo.png")
+        .with_status(200)
+        .with_body(PNG_BYTES)
+        .create_async()
+        .await;
+
+    let photo = photo_with_url(format!("{}/photo.png", server.url()));
+    let output_dir = temp_dir("staged-output").to_string_lossy().into_owned();
+    let staging_dir = temp_dir("staged-temp").to_string_lossy().into_owned();
+    let options = DownloadOptions::new()
+        .with_temp_dir(&staging_dir)
+        .with_fsync(true);
+
+    let path = download_photo_with_options(&photo, Some(2), &output_dir, None, &options)
+        .await
+        .unwrap();
+    assert!(path.ends_with("3_photo123.png"));
+    assert_eq!(tokio::fs::read(&path).await.unwrap(), PNG_BYTES);
+
+    // The partial file was moved out of the staging directory
+    let mut staged = tokio::fs::read_dir(&staging_dir).await.unwrap();
+    assert!(staged.next_entry().await.unwrap().is_none());
+
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+    tokio::fs::remove_dir_all(&staging_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_download_names_files_after_markdown_captions() {
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/photo.png")
+        .with_status(200)
+        .with_body(PNG_BYTES)
+        .expect(2)
+        .create_async()
+        .await;
+
+    let mut photo = photo_with_url(format!("{}/photo.png", server.url()));
+    photo.caption = Some("**Beach** day\n\n- with [Ana](https://example.com)".to_string());
+    let output_dir = temp_dir("markdown-names").to_string_lossy().into_owned();
+    let options = DownloadOptions::new().with_markdown_captions(true);
+
+    let path = download_photo_with_options(&photo, None, &output_dir, None, &options)
+        .await
+        .unwrap();
+    assert!(path.ends_with("photo123_Beach day with Ana.png"));
+
+    // Without the option, the caption is only sanitized
+    let path =
+        download_photo_with_options(&photo, None, &output_dir, None, &DownloadOptions::new())
+            .await
+            .unwrap();
+    assert!(path.contains("__Beach__"));
+
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_move_file_across_filesystems() {
+    // /dev/shm is usually a separate tmpfs, which exercises the EXDEV fallback;
+    // elsewhere this degrades to a plain rename
+    let source_dir = if std::path::Path::new("/dev/shm").is_dir() {
+        std::path::PathBuf::from("/dev/shm")
+    } else {
+        std::env::temp_dir()
+    };
+    let source = source_dir.join(format!("icloud-move-{}.bin", std::process::id()));
+    let target_dir = temp_dir("move-target").to_string_lossy().into_owned();
+    tokio::fs::create_dir_all(&target_dir).await.unwrap();
+    let target = std::path::Path::new(&target_dir).join("moved.bin");
+
+    tokio::fs::write(&source, b"payload").await.unwrap();
+    move_file(&source, &target).await.unwrap();
+
+    assert!(!source.exists());
+    assert_eq!(tokio::fs::read(&target).await.unwrap(), b"payload");
+    assert!(!std::path::Path::new(&target_dir)
+        .join("moved.bin.part")
+        .exists());
+
+    tokio::fs::remove_dir_all(&target_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_sync_parent_dir() {
+    let dir = temp_dir("sync-parent").to_string_lossy().into_owned();
+    tokio::fs::create_dir_all(&dir).await.unwrap();
+    let file = std::path::Path::new(&dir).join("file.bin");
+    tokio::fs::write(&file, b"data").await.unwrap();
+
+    sync_parent_dir(&file).await.unwrap();
+    // Relative paths without a directory component sync the current directory
+    sync_parent_dir(std::path::Path::new("file.bin"))
+        .await
+        .unwrap();
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_download_applies_permissions() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/photo.png")
+        .with_status(200)
+        .with_body(PNG_BYTES)
+        .create_async()
+        .await;
+
+    let root = temp_dir("permissions").to_string_lossy().into_owned();
+    let output_dir = format!("{}/album", root);
+    tokio::fs::create_dir_all(&root).await.unwrap();
+    let current = tokio::fs::metadata(&root).await.unwrap();
+
+    // Changing ownership to the current owner is permitted without privileges
+    let options = DownloadOptions::new()
+        .with_modes(0o640, 0o750)
+        .with_owner(Some(current.uid()), Some(current.gid()));
+    let photo = photo_with_url(format!("{}/photo.png", server.url()));
+    let path = download_photo_with_options(&photo, None, &output_dir, None, &options)
+        .await
+        .unwrap();
+
+    let file = tokio::fs::metadata(&path).await.unwrap();
+    assert_eq!(file.permissions().mode() & 0o777, 0o640);
+    assert_eq!(file.uid(), current.uid());
+    let dir = tokio::fs::metadata(&output_dir).await.unwrap();
+    assert_eq!(dir.permissions().mode() & 0o777, 0o750);
+
+    tokio::fs::remove_dir_all(&root).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_download_derivative_classes() {
+    let mut server = mockito::Server::new_async().await;
+    let output_dir = temp_dir("derivative-classes")
+        .to_string_lossy()
+        .into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    let thumb = server
+        .mock("GET", "/thumb.png")
+        .with_body(PNG_BYTES)
+        .create_async()
+        .await;
+    let original = server
+        .mock("GET", "/original.png")
+        .with_body(PNG_BYTES)
+        .create_async()
+        .await;
+    let broken = server
+        .mock("GET", "/broken.png")
+        .with_status(500)
+        .create_async()
+        .await;
+
+    let derivative = |path: &str, width: u32| Derivative {
+        checksum: path.to_string(),
+        width: Some(width),
+        height: Some(width),
+        url: Some(format!("{}/{}", server.url(), path)),
+        ..Default::default()
+    };
+    let mut derivatives = HashMap::new();
+    derivatives.insert("342".to_string(), derivative("thumb.png", 342));
+    derivatives.insert("200".to_string(), derivative("small.png", 200));
+    derivatives.insert("3".to_string(), derivative("original.png", 4032));
+    let first = Image {
+        photo_guid: "first".to_string(),
+        derivatives,
+        ..Default::default()
+    };
+    let mut derivatives = HashMap::new();
+    derivatives.insert("342".to_string(), derivative("broken.png", 342));
+    let second = Image {
+        photo_guid: "second".to_string(),
+        derivatives,
+        ..Default::default()
+    };
+
+    let report = download_derivative_classes(
+        &[first, second],
+        &output_dir,
+        &[SizeClass::Thumbnail, SizeClass::Original],
+        &DownloadOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    let written: Vec<(&str, SizeClass, &str, &str)> = report
+        .downloaded
+        .iter()
+        .map(|d| {
+            (
+                d.photo_guid.as_str(),
+                d.size_class,
+                d.key.as_str(),
+                d.path.as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        written,
+        vec![
+            (
+                "first",
+                SizeClass::Thumbnail,
+                "342",
+                format!("{}/first_thumb.png", output_dir).as_str()
+            ),
+            (
+                "first",
+                SizeClass::Original,
+                "3",
+                format!("{}/first_original.png", output_dir).as_str()
+            ),
+        ]
+    );
+    assert_eq!(
+        report.missing,
+        vec![("second".to_string(), SizeClass::Original)]
+    );
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(report.failures[0].0, "second");
+    assert_eq!(report.failures[0].1, SizeClass::Thumbnail);
+
+    thumb.assert_async().await;
+    original.assert_async().await;
+    broken.assert_async().await;
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[test]
+fn test_render_file_name() {
+    assert_eq!(
+        render_file_name("{base}_{suffix}.{ext}", "ABC", SizeClass::Thumbnail, ".jpg"),
+        "ABC_thumb.jpg"
+    );
+    assert_eq!(
+        render_file_name("{base}.poster.{ext}", "ABC", SizeClass::VideoPoster, "jpg"),
+        "ABC.poster.jpg"
+    );
+    // Without {ext} the extension is appended
+    assert_eq!(
+        render_file_name("{base}-{suffix}", "ABC", SizeClass::Video, ".mp4"),
+        "ABC-video.mp4"
+    );
+}
+
+#[tokio::test]
+async fn test_download_derivative_classes_video_templates() {
+    let mut server = mockito::Server::new_async().await;
+    let output_dir = temp_dir("video-templates").to_string_lossy().into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    // Same content for both, so only the templates tell the files apart
+    let video = server
+        .mock("GET", "/video.png")
+        .with_body(PNG_BYTES)
+        .create_async()
+        .await;
+    let poster = server
+        .mock("GET", "/poster.png")
+        .with_body(PNG_BYTES)
+        .create_async()
+        .await;
+
+    let derivative = |path: &str| Derivative {
+        checksum: path.to_string(),
+        url: Some(format!("{}/{}", server.url(), path)),
+        ..Default::default()
+    };
+    let mut derivatives = HashMap::new();
+    derivatives.insert("720p".to_string(), derivative("video.png"));
+    derivatives.insert("PosterFrame".to_string(), derivative("poster.png"));
+    let clip = Image {
+        photo_guid: "clip".to_string(),
+        derivatives,
+        ..Default::default()
+    };
+    let classes = [SizeClass::Video, SizeClass::VideoPoster];
+
+    // Templates giving both classes the same names are rejected up front
+    let conflated = DownloadOptions::default()
+        .with_file_name_template(SizeClass::Video, "{base}.{ext}")
+        .with_file_name_template(SizeClass::VideoPoster, "{base}");
+    let error = download_derivative_classes(
+        std::slice::from_ref(&clip),
+        &output_dir,
+        &classes,
+        &conflated,
+    )
+    .await
+    .unwrap_err();
+    assert!(error.to_string().contains("same names"));
+    let outside = DownloadOptions::default()
+        .with_file_name_template(SizeClass::VideoPoster, "../{base}.{ext}");
+    assert!(download_derivative_classes(
+        std::slice::from_ref(&clip),
+        &output_dir,
+        &classes,
+        &outside
+    )
+    .await
+    .is_err());
+
+    let options = DownloadOptions::default()
+        .with_file_name_template(SizeClass::Video, "{base}.{ext}")
+        .with_file_name_template(SizeClass::VideoPoster, "{base}.poster.{ext}");
+    let report = download_derivative_classes(&[clip], &output_dir, &classes, &options)
+        .await
+        .unwrap();
+
+    assert!(report.failures.is_empty());
+    assert_eq!(
+        report.video_pairs(),
+        vec![(
+            "clip".to_string(),
+            format!("{}/clip.png", output_dir),
+            format!("{}/clip.poster.png", output_dir)
+        )]
+    );
+    assert!(std::path::Path::new(&format!("{}/clip.png", output_dir)).exists());
+    assert!(std::path::Path::new(&format!("{}/clip.poster.png", output_dir)).exists());
+
+    video.assert_async().await;
+    poster.assert_async().await;
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[test]
+fn test_check_asset_body() {
+    assert!(check_asset_body(Some("image/png"), PNG_BYTES).is_ok());
+    // The signature wins over a wrong content type
+    assert!(check_asset_body(Some("text/html"), PNG_BYTES).is_ok());
+    // Unknown binary formats pass
+    assert!(check_asset_body(Some("application/octet-stream"), &[0; 32]).is_ok());
+
+    assert!(check_asset_body(Some("image/jpeg"), b"").is_err());
+    let page = b"\n  <!DOCTYPE html><html><body>Not found</body></html>";
+    let error = check_asset_body(Some("image/jpeg"), page).unwrap_err();
+    assert!(error.contains("instead of media"));
+    assert!(check_asset_body(None, b"<?xml version=\"1.0\"?><Error/>").is_err());
+    assert!(check_asset_body(Some("Text/Plain; charset=utf-8"), b"Access denied").is_err());
+}
+
+#[tokio::test]
+async fn test_download_derivative_classes_rejects_error_pages() {
+    let mut server = mockito::Server::new_async().await;
+    let output_dir = temp_dir("error-pages").to_string_lossy().into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    let stale = server
+        .mock("GET", "/stale.png")
+        .with_header("content-type", "text/html")
+        .with_body("<html><body>Link expired</body></html>")
+        .expect(2)
+        .create_async()
+        .await;
+    let asset_urls = server
+        .mock("POST", "/album/webasseturls")
+        .with_body(
+            serde_json::json!({
+                "items": {
+                    "stale": {
+                        "url_location": "cvws.icloud-content.com",
+                        "url_path": "/fresh.png"
+                    }
+                }
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+    let fresh = server
+        .mock("GET", "/fresh.png")
+        .with_body(PNG_BYTES)
+        .create_async()
+        .await;
+
+    let mut derivatives = HashMap::new();
+    derivatives.insert(
+        "3".to_string(),
+        Derivative {
+            checksum: "stale".to_string(),
+            url: Some(format!("{}/stale.png", server.url())),
+            ..Default::default()
+        },
+    );
+    let photos = [Image {
+        photo_guid: "photo123".to_string(),
+        derivatives,
+        ..Default::default()
+    }];
+
+    // Without a refresh, the error page is a failure and nothing is written
+    let report = download_derivative_classes(
+        &photos,
+        &output_dir,
+        &[SizeClass::Original],
+        &DownloadOptions::default(),
+    )
+    .await
+    .unwrap();
+    assert!(report.downloaded.is_empty());
+    assert_eq!(report.failures.len(), 1);
+    assert!(report.failures[0]
+        .2
+        .to_string()
+        .contains("text/html (38 bytes) instead of media"));
+    let mut entries = tokio::fs::read_dir(&output_dir).await.unwrap();
+    assert!(entries.next_entry().await.unwrap().is_none());
+
+    // With a refresh, the URL is resolved again and the download succeeds
+    let options = DownloadOptions::default().with_url_refresh(format!("{}/album/", server.url()));
+    let report = with_api_origin(
+        &server.url(),
+        download_derivative_classes(&photos, &output_dir, &[SizeClass::Original], &options),
+    )
+    .await
+    .unwrap();
+    assert!(report.failures.is_empty());
+    assert_eq!(
+        report.downloaded[0].path,
+        format!("{}/photo123_original.png", output_dir)
+    );
+
+    stale.assert_async().await;
+    asset_urls.assert_async().await;
+    fresh.assert_async().await;
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_download_derivative_classes_resumes_stalled_transfers() {
+    let mut server = mockito::Server::new_async().await;
+    let output_dir = temp_dir("stalled-transfers").to_string_lossy().into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    // Sends half of the file, then nothing for longer than the stall timeout
+    let stalling = || {
+        |w: &mut dyn std::io::Write| {
+            w.write_all(&PNG_BYTES[..6])?;
+            w.flush()?;
+            std::thread::sleep(std::time::Duration::from_secs(2));
+            w.write_all(&PNG_BYTES[6..])
+        }
+    };
+    let first = server
+        .mock("GET", "/video.png")
+        .match_header("range", mockito::Matcher::Missing)
+        .with_header("etag", "\"v1\"")
+        .with_chunked_body(stalling())
+        .expect(2)
+        .create_async()
+        .await;
+    let resumed = server
+        .mock("GET", "/video.png")
+        .match_header("range", "bytes=6-")
+        .match_header("if-range", "\"v1\"")
+        .with_status(206)
+        .with_header("content-range", "bytes 6-11/12")
+        .with_body(&PNG_BYTES[6..])
+        .create_async()
+        .await;
+    let mut photo = photo_with_url(format!("{}/video.png", server.url()));
+    let derivative = photo.derivatives.remove("1").unwrap();
+    photo.derivatives.insert("3".to_string(), derivative);
+    let photos = [photo];
+    let download = |stall: StallConfig| {
+        let options = DownloadOptions::default().with_stall_detection(stall);
+        let (photos, output_dir) = (&photos, &output_dir);
+        async move {
+            download_derivative_classes(photos, output_dir, &[SizeClass::Original], &options)
+                .await
+                .unwrap()
+        }
+    };
+
+    let stall = StallConfig::new(std::time::Duration::from_millis(200));
+    let report = download(stall.clone()).await;
+    assert!(report.failures.is_empty());
+    let written = tokio::fs::read(format!("{}/photo123_original.png", output_dir))
+        .await
+        .unwrap();
+    assert_eq!(written, PNG_BYTES);
+    resumed.assert_async().await;
+
+    // Without resumes left, the stall fails the download
+    let report = download(stall.with_max_resumes(0)).await;
+    assert_eq!(report.failures.len(), 1);
+    assert!(report.failures[0]
+        .2
+        .to_string()
+        .contains("stalled after 6 bytes"));
+
+    first.assert_async().await;
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_download_derivative_classes_speed_floor() {
+    let mut server = mockito::Server::new_async().await;
+    let output_dir = temp_dir("speed-floor").to_string_lossy().into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    // Trickles the file out a byte every 100ms
+    let slow = server
+        .mock("GET", "/slow.png")
+        .match_header("range", mockito::Matcher::Missing)
+        .with_chunked_body(|w| {
+            for byte in PNG_BYTES {
+                w.write_all(&[*byte])?;
+                w.flush()?;
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+            Ok(())
+        })
+        .expect(2)
+        .create_async()
+        .await;
+    // Ignores the range and sends the whole file at full speed
+    let restarted = server
+        .mock("GET", "/slow.png")
+        .match_header("range", mockito::Matcher::Regex("^bytes=".to_string()))
+        .with_body(PNG_BYTES)
+        .create_async()
+        .await;
+    let mut derivatives = HashMap::new();
+    derivatives.insert(
+        "3".to_string(),
+        Derivative {
+            checksum: "slow".to_string(),
+            url: Some(format!("{}/slow.png", server.url())),
+            ..Default::default()
+        },
+    );
+    let photos = [Image {
+        photo_guid: "photo123".to_string(),
+        derivatives,
+        ..Default::default()
+    }];
+    let floor = SpeedFloor::new(1000, std::time::Duration::from_millis(300));
+
+    // Without stall detection, the slow transfer fails
+    let options = DownloadOptions::default().with_speed_floor(floor.clone());
+    let report =
+        download_derivative_classes(&photos, &output_dir, &[SizeClass::Original], &options)
+            .await
+            .unwrap();
+    assert_eq!(report.failures.len(), 1);
+    assert!(report.failures[0]
+        .2
+        .to_string()
+        .contains("fell below 1000 bytes/s"));
+
+    // With it, the transfer continues over a new connection
+    let options = DownloadOptions::default()
+        .with_speed_floor(floor)
+        .with_stall_detection(StallConfig::new(std::time::Duration::from_secs(5)));
+    let report =
+        download_derivative_classes(&photos, &output_dir, &[SizeClass::Original], &options)
+            .await
+            .unwrap();
+    assert!(report.failures.is_empty());
+    let written = tokio::fs::read(format!("{}/photo123_original.png", output_dir))
+        .await
+        .unwrap();
+    assert_eq!(written, PNG_BYTES);
+
+    slow.assert_async().await;
+    restarted.assert_async().await;
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_download_derivative_classes_memory_budget() {
+    let mut server = mockito::Server::new_async().await;
+    let output_dir = temp_dir("memory-budget").to_string_lossy().into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    // 1 MiB PNGs without a length, each larger than the whole budget
+    let assets = server
+        .mock("GET", mockito::Matcher::Regex(r"^/big\d\.png$".to_string()))
+        .with_chunked_body(|w| {
+            w.write_all(PNG_BYTES)?;
+            for _ in 0..64 {
+                w.write_all(&[7; 16 * 1024])?;
+            }
+            Ok(())
+        })
+        .expect(3)
+        .create_async()
+        .await;
+    let photos: Vec<Image> = (0..3)
+        .map(|i| {
+            let mut derivatives = HashMap::new();
+            derivatives.insert(
+                "3".to_string(),
+                Derivative {
+                    checksum: format!("big{}", i),
+                    url: Some(format!("{}/big{}.png", server.url(), i)),
+                    ..Default::default()
+                },
+            );
+            Image {
+                photo_guid: format!("big{}", i),
+                derivatives,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    let budget = std::sync::Arc::new(MemoryBudget::new(2 * TRANSFER_BUFFER_BYTES - 1000));
+    assert_eq!(budget.limit(), 2 * TRANSFER_BUFFER_BYTES);
+    let options = DownloadOptions::default()
+        .with_concurrency(Concurrency::Fixed(3))
+        .with_memory_budget(budget.clone());
+    let report =
+        download_derivative_classes(&photos, &output_dir, &[SizeClass::Original], &options)
+            .await
+            .unwrap();
+
+    // Every asset is streamed to disk, although each is larger than the budget
+    assert_eq!(report.downloaded.len(), 3);
+    for download in &report.downloaded {
+        let written = tokio::fs::read(&download.path).await.unwrap();
+        assert_eq!(written.len(), PNG_BYTES.len() + 1024 * 1024);
+        assert!(written.len() > budget.limit());
+        assert!(written.starts_with(PNG_BYTES));
+    }
+    // The budget is released and no partial files are left
+    assert_eq!(budget.in_use(), 0);
+    let mut entries = tokio::fs::read_dir(&output_dir).await.unwrap();
+    while let Some(entry) = entries.next_entry().await.unwrap() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        assert!(!name.ends_with(".part"), "partial file left: {}", name);
+    }
+
+    assets.assert_async().await;
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+/// Answers downloads with a PNG, panicking for URLs containing "boom"
+struct PanickingTransport;
+
+impl Transport for PanickingTransport {
+    fn respond(&self, request: &TransportRequest) -> Option<TransportResponse> {
+        if request.url.contains("boom") {
+            panic!("transport exploded");
+        }
+        Some(TransportResponse::new(200, PNG_BYTES))
+    }
+}
+
+#[tokio::test]
+async fn test_download_derivative_classes_reports_panics() {
+    let output_dir = temp_dir("task-panics").to_string_lossy().into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    let photo = |guid: &str| {
+        let mut derivatives = HashMap::new();
+        derivatives.insert(
+            "3".to_string(),
+            Derivative {
+                checksum: guid.to_string(),
+                url: Some(format!("https://cvws.icloud-content.com/{}.png", guid)),
+                ..Default::default()
+            },
+        );
+        Image {
+            photo_guid: guid.to_string(),
+            derivatives,
+            ..Default::default()
+        }
+    };
+    let photos = [photo("boom"), photo("fine")];
+
+    let report = with_transport(
+        std::sync::Arc::new(PanickingTransport),
+        download_derivative_classes(
+            &photos,
+            &output_dir,
+            &[SizeClass::Original],
+            &DownloadOptions::default(),
+        ),
+    )
+    .await
+    .unwrap();
+
+    // The panic fails its own download only
+    assert_eq!(report.downloaded.len(), 1);
+    assert_eq!(report.downloaded[0].photo_guid, "fine");
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(report.failures[0].0, "boom");
+    assert!(report.failures[0]
+        .2
+        .to_string()
+        .contains("download task panicked: transport exploded"));
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_download_derivative_classes_adaptive_concurrency() {
+    let mut server = mockito::Server::new_async().await;
+    let output_dir = temp_dir("adaptive-concurrency")
+        .to_string_lossy()
+        .into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    let ok = server
+        .mock("GET", mockito::Matcher::Regex(r"^/ok\d\.png$".to_string()))
+        .with_body(PNG_BYTES)
+        .expect(5)
+        .create_async()
+        .await;
+    let throttled = server
+        .mock("GET", "/busy.png")
+        .with_status(429)
+        .create_async()
+        .await;
+
+    let photos: Vec<Image> = ["ok0", "ok1", "busy", "ok2", "ok3", "ok4"]
+        .iter()
+        .map(|name| {
+            let mut derivatives = HashMap::new();
+            derivatives.insert(
+                "original".to_string(),
+                Derivative {
+                    url: Some(format!("{}/{}.png", server.url(), name)),
+                    ..Default::default()
+                },
+            );
+            Image {
+                photo_guid: name.to_string(),
+                derivatives,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    let options = DownloadOptions::new().with_concurrency(Concurrency::Adaptive(AimdConfig {
+        initial: 4,
+        max: 4,
+        ..Default::default()
+    }));
+    let report =
+        download_derivative_classes(&photos, &output_dir, &[SizeClass::Original], &options)
+            .await
+            .unwrap();
+
+    // Results are reported in photo order, whatever order they completed in
+    let guids: Vec<&str> = report
+        .downloaded
+        .iter()
+        .map(|d| d.photo_guid.as_str())
+        .collect();
+    assert_eq!(guids, vec!["ok0", "ok1", "ok2", "ok3", "ok4"]);
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(report.failures[0].0, "busy");
+    // The 429 halved the limit, and four fast responses can't have restored it
+    assert_eq!(report.concurrency.len(), 1);
+    assert!(report.concurrency.values().all(|&limit| limit < 4));
+
+    ok.assert_async().await;
+    throttled.assert_async().await;
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_download_derivative_classes_pauses_failing_host() {
+    let mut server = mockito::Server::new_async().await;
+    let output_dir = temp_dir("host-isolation").to_string_lossy().into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    let healthy = server
+        .mock("GET", mockito::Matcher::Regex(r"^/ok\d\.png$".to_string()))
+        .with_body(PNG_BYTES)
+        .expect(3)
+        .create_async()
+        .await;
+    // Only the requests before the breaker opens reach the failing host
+    let failing = server
+        .mock(
+            "GET",
+            mockito::Matcher::Regex(r"^/down\d\.png$".to_string()),
+        )
+        .with_status(502)
+        .expect(2)
+        .create_async()
+        .await;
+
+    // The same server under two host names stands in for two CDN hosts
+    let port = server.url().rsplit(':').next().unwrap().to_string();
+    let photo = |host: &str, name: &str| {
+        let mut derivatives = HashMap::new();
+        derivatives.insert(
+            "original".to_string(),
+            Derivative {
+                url: Some(format!("http://{}:{}/{}.png", host, port, name)),
+                ..Default::default()
+            },
+        );
+        Image {
+            photo_guid: name.to_string(),
+            derivatives,
+            ..Default::default()
+        }
+    };
+    let photos = vec![
+        photo("localhost", "down0"),
+        photo("127.0.0.1", "ok0"),
+        photo("localhost", "down1"),
+        photo("127.0.0.1", "ok1"),
+        photo("localhost", "down2"),
+        photo("localhost", "down3"),
+        photo("127.0.0.1", "ok2"),
+    ];
+
+    let options = DownloadOptions::new()
+        .with_concurrency(Concurrency::Fixed(1))
+        .with_circuit_breaker(CircuitBreakerConfig {
+            failure_threshold: 2,
+            cooldown: std::time::Duration::from_secs(60),
+        });
+    let report =
+        download_derivative_classes(&photos, &output_dir, &[SizeClass::Original], &options)
+            .await
+            .unwrap();
+
+    let guids: Vec<&str> = report
+        .downloaded
+        .iter()
+        .map(|d| d.photo_guid.as_str())
+        .collect();
+    assert_eq!(guids, vec!["ok0", "ok1", "ok2"]);
+    let failed: Vec<&str> = report
+        .failures
+        .iter()
+        .map(|(guid, _, _)| guid.as_str())
+        .collect();
+    assert_eq!(failed, vec!["down0", "down1", "down2", "down3"]);
+    let paused = report
+        .failures
+        .iter()
+        .filter(|(_, _, e)| e.to_string().contains("paused"))
+        .count();
+    assert_eq!(paused, 2);
+    assert_eq!(report.concurrency.len(), 2);
+
+    // All failures of the pass share one generated operation ID
+    let operation_id = report.failures[0].2.context.operation_id.clone();
+    assert!(operation_id.is_some());
+    assert!(report
+        .failures
+        .iter()
+        .all(|(_, _, e)| e.context.operation_id == operation_id));
+
+    healthy.assert_async().await;
+    failing.assert_async().await;
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_download_derivative_classes_retries_failures() {
+    let mut server = mockito::Server::new_async().await;
+    let output_dir = temp_dir("download-retries").to_string_lossy().into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    let flaky = server
+        .mock("GET", "/flaky.png")
+        .with_status(503)
+        .expect(3)
+        .create_async()
+        .await;
+    let missing = server
+        .mock("GET", "/missing.png")
+        .with_status(404)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let photos: Vec<Image> = ["flaky", "missing"]
+        .iter()
+        .map(|name| {
+            let mut derivatives = HashMap::new();
+            derivatives.insert(
+                "original".to_string(),
+                Derivative {
+                    url: Some(format!("{}/{}.png", server.url(), name)),
+                    ..Default::default()
+                },
+            );
+            Image {
+                photo_guid: name.to_string(),
+                derivatives,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    let options = DownloadOptions::new().with_retry(RetryConfig {
+        max_retries: 3,
+        base_delay_ms: 1,
+        ..Default::default()
+    });
+    let report =
+        download_derivative_classes(&photos, &output_dir, &[SizeClass::Original], &options)
+            .await
+            .unwrap();
+
+    // 503s are retried until attempts run out, 404s fail right away
+    assert_eq!(report.failures.len(), 2);
+    flaky.assert_async().await;
+    missing.assert_async().await;
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+}
+
+#[tokio::test]
+async fn test_download_derivative_classes_shutdown() {
+    let mut server = mockito::Server::new_async().await;
+    let output_dir = temp_dir("download-shutdown").to_string_lossy().into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    server
+        .mock("GET", "/fast.png")
+        .with_body(PNG_BYTES)
+        .create_async()
+        .await;
+    // A host that accepts connections and never answers
+    let stalled = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let stalled_url = format!("http://{}/stalled.png", stalled.local_addr().unwrap());
+    std::thread::spawn(move || {
+        let _connections: Vec<_> = stalled.incoming().collect();
+    });
+
+    let photos: Vec<Image> = [
+        ("fast", format!("{}/fast.png", server.url())),
+        ("slow", stalled_url),
+    ]
+    .into_iter()
+    .map(|(guid, url)| {
+        let mut derivatives = HashMap::new();
+        derivatives.insert(
+            "original".to_string(),
+            Derivative {
+                url: Some(url),
+                ..Default::default()
+            },
+        );
+        Image {
+            photo_guid: guid.to_string(),
+            derivatives,
+            ..Default::default()
+        }
+    })
+    .collect();
+
+    let shutdown = Shutdown::new();
+    let options = DownloadOptions::new().with_shutdown(shutdown.clone());
+    let stop = async {
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        shutdown.trigger();
+    };
+    let (report, _) = tokio::join!(
+        download_derivative_classes(&photos, &output_dir, &[SizeClass::Original], &options),
+        stop
+    );
+    let report = report.unwrap();
+
+    // The finished download is kept, the stalled one is cancelled
+    assert_eq!(report.downloaded.len(), 1);
+    assert_eq!(report.downloaded[0].photo_guid, "fast");
+    assert!(report.failures.is_empty());
+    assert_eq!(
+        report.cancelled,
+        vec![("slow".to_string(), SizeClass::Original)]
+    );
+    let files: Vec<_> = std::fs::read_dir(&output_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(files.len(), 1);
+
+    // Once triggered, nothing is downloaded
+    let report =
+        download_derivative_classes(&photos, &output_dir, &[SizeClass::Original], &options)
+            .await
+            .unwrap();
+    assert!(report.downloaded.is_empty());
+    assert_eq!(report.cancelled.len(), 2);
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+}
+
+#[tokio::test]
+async fn test_download_derivative_classes_hedging() {
+    let output_dir = temp_dir("download-hedging").to_string_lossy().into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    // A host whose first connection never answers and later ones answer at once
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/thumb.png", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        use std::io::{Read, Write};
+        let mut stalled = Vec::new();
+        for mut stream in listener.incoming().flatten() {
+            if stalled.is_empty() {
+                stalled.push(stream);
+                continue;
+            }
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request);
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                PNG_BYTES.len()
+            );
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(PNG_BYTES);
+        }
+    });
+
+    let mut derivatives = HashMap::new();
+    derivatives.insert(
+        "160".to_string(),
+        Derivative {
+            url: Some(url),
+            width: Some(160),
+            height: Some(120),
+            file_size: Some(PNG_BYTES.len() as u64),
+            ..Default::default()
+        },
+    );
+    let photos = vec![Image {
+        photo_guid: "hedged".to_string(),
+        derivatives,
+        ..Default::default()
+    }];
+
+    let options = DownloadOptions::new()
+        .with_concurrency(Concurrency::Fixed(2))
+        .with_hedging(HedgeConfig::new(std::time::Duration::from_millis(100)).with_max_extra(1.0));
+    let report = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        download_derivative_classes(&photos, &output_dir, &[SizeClass::Thumbnail], &options),
+    )
+    .await
+    .expect("the hedged request should answer")
+    .unwrap();
+
+    assert_eq!(report.hedged, 1);
+    assert_eq!(report.downloaded.len(), 1);
+    assert!(report.failures.is_empty());
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+}
@@ -1,6 +1,11 @@
-use icloud_album_rs::enrich::enrich_photos_with_urls;
-use icloud_album_rs::models::{Derivative, Image};
+use icloud_album_rs::enrich::{
+    enrich_photos_with_locations, enrich_photos_with_urls, geocode_photos, resolve_photo_urls,
+    resolve_thumbnail_urls, GeocodeFuture, Geocoder, THUMBNAIL_BATCH_SIZE,
+};
+use icloud_album_rs::models::{Derivative, Image, Location, Metadata};
+use serde_json::json;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[test]
 fn test_enrich_photos_with_urls() {
@@ -26,6 +31,7 @@ fn test_enrich_photos_with_urls() {
         width: Some(800),
         height: Some(600),
         url: None,
+        ..Default::default()
     };
 
     let derivative2 = Derivative {
@@ -34,6 +40,7 @@ fn test_enrich_photos_with_urls() {
         width: Some(1600),
         height: Some(1200),
         url: None,
+        ..Default::default()
     };
 
     let derivative3 = Derivative {
@@ -42,6 +49,7 @@ fn test_enrich_photos_with_urls() {
         width: Some(2400),
         height: Some(1800),
         url: None,
+        ..Default::default()
     };
 
     let derivative4 = Derivative {
@@ -50,6 +58,7 @@ fn test_enrich_photos_with_urls() {
         width: Some(3200),
         height: Some(2400),
         url: None,
+        ..Default::default()
     };
 
     // Create photos with derivatives
@@ -67,8 +76,12 @@ fn test_enrich_photos_with_urls() {
         caption: Some("Photo 1".to_string()),
         date_created: Some("2023-01-01".to_string()),
         batch_date_created: Some("2023-01-01".to_string()),
+        contributor_full_name: None,
         width: Some(1600),
         height: Some(1200),
+        location: None,
+        place_name: None,
+        placeholder: None,
     };
 
     let photo2 = Image {
@@ -77,8 +90,12 @@ fn test_enrich_photos_with_urls() {
         caption: Some("Photo 2".to_string()),
         date_created: Some("2023-01-02".to_string()),
         batch_date_created: Some("2023-01-02".to_string()),
+        contributor_full_name: None,
         width: Some(2400),
         height: Some(1800),
+        location: None,
+        place_name: None,
+        placeholder: None,
     };
 
     // Create a mutable slice of photos
@@ -106,3 +123,247 @@ fn test_enrich_photos_with_urls() {
     // This derivative shouldn't have a URL since its checksum wasn't in the map
     assert_eq!(photos[1].derivatives.get("2").unwrap().url, None);
 }
+
+#[test]
+fn test_photo_locations_and_enrichment() {
+    let metadata: Metadata = serde_json::from_value(json!({
+        "streamName": "Trip",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag",
+        "itemsReturned": 3,
+        "locations": {
+            "photo1": { "latitude": 41.8781, "longitude": -87.6298, "altitude": 181.0 },
+            "photo2": { "lat": "48.8566", "lng": "2.3522" },
+            "photo3": { "latitude": 120.0, "longitude": 0.0 },
+            "photo4": { "name": "no coordinates" }
+        }
+    }))
+    .unwrap();
+
+    let locations = metadata.photo_locations();
+    assert_eq!(locations.len(), 2);
+    assert_eq!(
+        locations["photo1"],
+        Location {
+            latitude: 41.8781,
+            longitude: -87.6298,
+            altitude: Some(181.0),
+        }
+    );
+    assert_eq!(locations["photo2"].latitude, 48.8566);
+
+    let mut photos: Vec<Image> = ["photo1", "photo2", "photo3"]
+        .iter()
+        .map(|guid| Image {
+            photo_guid: guid.to_string(),
+            ..Default::default()
+        })
+        .collect();
+    enrich_photos_with_locations(&mut photos, &locations);
+    assert_eq!(photos[0].location, Some(locations["photo1"]));
+    assert!(photos[2].location.is_none());
+}
+
+/// Geocoder naming locations by hemisphere and counting its calls
+struct HemisphereGeocoder {
+    calls: AtomicUsize,
+}
+
+impl Geocoder for HemisphereGeocoder {
+    fn reverse_geocode(&self, location: Location) -> GeocodeFuture<'_> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async move {
+            if location.latitude == 0.0 {
+                return Err("no service at the equator".into());
+            }
+            Ok(Some(
+                if location.latitude > 0.0 {
+                    "North"
+                } else {
+                    "South"
+                }
+                .to_string(),
+            ))
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_geocode_photos() {
+    let at = |latitude| {
+        Some(Location {
+            latitude,
+            longitude: 10.0,
+            altitude: None,
+        })
+    };
+    let mut photos: Vec<Image> = vec![
+        Image {
+            location: at(45.0),
+            ..Default::default()
+        },
+        Image {
+            location: at(45.0),
+            ..Default::default()
+        },
+        Image {
+            location: at(-30.0),
+            place_name: Some("Already named".to_string()),
+            ..Default::default()
+        },
+        Image {
+            location: at(0.0),
+            ..Default::default()
+        },
+        Image::default(),
+    ];
+
+    let geocoder = HemisphereGeocoder {
+        calls: AtomicUsize::new(0),
+    };
+    let named = geocode_photos(&mut photos, &geocoder).await;
+
+    assert_eq!(named, 2);
+    // The shared location is resolved once, the named photo is skipped
+    assert_eq!(geocoder.calls.load(Ordering::SeqCst), 2);
+    assert_eq!(photos[0].place_name.as_deref(), Some("North"));
+    assert_eq!(photos[1].place_name.as_deref(), Some("North"));
+    assert_eq!(photos[2].place_name.as_deref(), Some("Already named"));
+    assert_eq!(photos[3].place_name, None);
+    assert_eq!(photos[4].place_name, None);
+}
+
+#[tokio::test]
+async fn test_resolve_photo_urls_only_sends_given_photos() {
+    let mut server = mockito::Server::new_async().await;
+    let mock = server
+        .mock("POST", "/sharedstreams/webasseturls")
+        .match_body(mockito::Matcher::Json(json!({ "photoGuids": ["photo2"] })))
+        .with_status(200)
+        .with_body(
+            json!({
+                "items": {
+                    "checksum2": {
+                        "url_location": "cvws.icloud-content.com",
+                        "url_path": "/photo2.jpg"
+                    }
+                }
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+
+    let photo = |guid: &str, checksum: &str| {
+        let mut derivatives = HashMap::new();
+        derivatives.insert(
+            "1".to_string(),
+            Derivative {
+                checksum: checksum.to_string(),
+                ..Default::default()
+            },
+        );
+        Image {
+            photo_guid: guid.to_string(),
+            derivatives,
+            ..Default::default()
+        }
+    };
+    let photos = vec![photo("photo1", "checksum1"), photo("photo2", "checksum2")];
+
+    // Only the selected photo is resolved
+    let mut selected: Vec<Image> = photos
+        .into_iter()
+        .filter(|p| p.photo_guid == "photo2")
+        .collect();
+    let client = reqwest::Client::new();
+    let base_url = format!("{}/sharedstreams/", server.url());
+    let resolved = resolve_photo_urls(&client, &base_url, &mut selected)
+        .await
+        .unwrap();
+
+    assert_eq!(resolved, 1);
+    assert_eq!(
+        selected[0].derivatives["1"].url.as_deref(),
+        Some("https://cvws.icloud-content.com/photo2.jpg")
+    );
+    mock.assert_async().await;
+
+    // Nothing is requested for an empty selection
+    assert_eq!(
+        resolve_photo_urls(&client, &base_url, &mut [])
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_resolve_thumbnail_urls_in_batches() {
+    let count = THUMBNAIL_BATCH_SIZE + 1;
+    let photos: Vec<Image> = (0..count)
+        .map(|i| {
+            let mut derivatives = HashMap::new();
+            for (key, width) in [("large", 2048), ("small", 342)] {
+                derivatives.insert(
+                    key.to_string(),
+                    Derivative {
+                        checksum: format!("{}-{}", key, i),
+                        width: Some(width),
+                        height: Some(width),
+                        ..Default::default()
+                    },
+                );
+            }
+            Image {
+                photo_guid: format!("photo{}", i),
+                derivatives,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    let mut items = serde_json::Map::new();
+    for i in 0..count {
+        for key in ["large", "small"] {
+            items.insert(
+                format!("{}-{}", key, i),
+                json!({
+                    "url_location": "cvws.icloud-content.com",
+                    "url_path": format!("/{}-{}.jpg", key, i)
+                }),
+            );
+        }
+    }
+    // Photo 0 has no URLs in the response
+    items.remove("large-0");
+    items.remove("small-0");
+
+    let mut server = mockito::Server::new_async().await;
+    let mock = server
+        .mock("POST", "/sharedstreams/webasseturls")
+        .with_status(200)
+        .with_body(json!({ "items": items }).to_string())
+        .expect(2)
+        .create_async()
+        .await;
+
+    let client = reqwest::Client::new();
+    let base_url = format!("{}/sharedstreams/", server.url());
+    let thumbnails = resolve_thumbnail_urls(&client, &base_url, &photos)
+        .await
+        .unwrap();
+
+    assert_eq!(thumbnails.len(), count - 1);
+    assert!(!thumbnails.contains_key("photo0"));
+    assert_eq!(
+        thumbnails["photo1"],
+        "https://cvws.icloud-content.com/small-1.jpg"
+    );
+    assert_eq!(
+        thumbnails[&format!("photo{}", count - 1)],
+        format!("https://cvws.icloud-content.com/small-{}.jpg", count - 1)
+    );
+    mock.assert_async().await;
+}
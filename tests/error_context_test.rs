@@ -0,0 +1,203 @@
+use icloud_album_rs::api::{
+    get_api_response, get_asset_urls, ApiError, ErrorContext, FailureClass,
+};
+use icloud_album_rs::download::{download_photo_with_options, DownloadError, DownloadOptions};
+use icloud_album_rs::models::{Derivative, Image};
+use std::error::Error;
+
+#[test]
+fn test_context_wrapping() {
+    let error = ApiError::JsonParseError("expected value".to_string()).with_context(
+        ErrorContext::new("fetch webstream")
+            .with_endpoint(
+                "https://p01-sharedstreams.icloud.com/B0z5qAGN1JIFd3y/sharedstreams/webstream",
+            )
+            .with_guid("guid1"),
+    );
+
+    assert_eq!(
+        error.to_string(),
+        "fetch webstream for photo guid1 (https://p01-sharedstreams.icloud.com/B0z5***/sharedstreams/webstream): JSON parse error: expected value"
+    );
+    assert!(matches!(error.root(), ApiError::JsonParseError(_)));
+    assert_eq!(error.context().unwrap().guid.as_deref(), Some("guid1"));
+    assert!(error.source().is_some());
+}
+
+#[test]
+fn test_failure_classes() {
+    let not_found = ApiError::AlbumNotFound("gone".to_string())
+        .with_context(ErrorContext::new("fetch webstream"));
+    assert_eq!(not_found.failure_class(), FailureClass::Token);
+    let unauthorized = ApiError::RequestError {
+        status: Some(401),
+        message: "unauthorized".to_string(),
+    };
+    assert_eq!(unauthorized.failure_class(), FailureClass::Token);
+    let unavailable = ApiError::RequestError {
+        status: Some(503),
+        message: "unavailable".to_string(),
+    };
+    assert_eq!(unavailable.failure_class(), FailureClass::Network);
+    let parse = ApiError::JsonParseError("expected value".to_string());
+    assert_eq!(parse.failure_class(), FailureClass::Other);
+
+    let boxed: Box<dyn Error> = Box::new(unavailable);
+    assert_eq!(FailureClass::of(boxed.as_ref()), FailureClass::Network);
+    let download = DownloadError {
+        context: ErrorContext::new("download"),
+        source: Box::new(not_found),
+    };
+    assert_eq!(FailureClass::of(&download), FailureClass::Token);
+    let token = icloud_album_rs::base_url::get_base_url("").unwrap_err();
+    assert_eq!(FailureClass::of(&token), FailureClass::Token);
+    let io = std::io::Error::other("disk full");
+    assert_eq!(FailureClass::of(&io), FailureClass::Other);
+}
+
+#[tokio::test]
+async fn test_api_errors_name_the_endpoint() {
+    let mut server = mockito::Server::new_async().await;
+    let webstream = server
+        .mock("POST", "/album/webstream")
+        .with_status(500)
+        .create_async()
+        .await;
+    let assets = server
+        .mock("POST", "/album/webasseturls")
+        .with_status(200)
+        .with_body("not json")
+        .expect_at_least(1)
+        .create_async()
+        .await;
+
+    let client = reqwest::Client::new();
+    let base_url = format!("{}/album/", server.url());
+
+    let error = get_api_response(&client, &base_url).await.unwrap_err();
+    let context = error.context().unwrap();
+    assert_eq!(context.operation, "fetch webstream");
+    assert_eq!(
+        context.endpoint.as_deref(),
+        Some(format!("{}webstream", base_url).as_str())
+    );
+    assert!(matches!(
+        error.root(),
+        ApiError::RequestError {
+            status: Some(500),
+            ..
+        }
+    ));
+
+    let error = get_asset_urls(&client, &base_url, &["guid1".to_string()])
+        .await
+        .unwrap_err();
+    assert_eq!(error.context().unwrap().operation, "fetch asset URLs");
+    assert!(error.to_string().contains("webasseturls"));
+
+    webstream.assert_async().await;
+    assets.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_download_errors_name_the_photo() {
+    let mut photo = Image {
+        photo_guid: "guid1".to_string(),
+        ..Default::default()
+    };
+    photo.derivatives.insert(
+        "1".to_string(),
+        Derivative {
+            checksum: "c1".to_string(),
+            file_size: Some(10),
+            width: Some(10),
+            height: Some(10),
+            url: Some("http://127.0.0.1:1/a.jpg?sig=secret".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let dir = std::env::temp_dir().join(format!("icloud-error-context-{}", std::process::id()));
+    let error = download_photo_with_options(
+        &photo,
+        None,
+        dir.to_str().unwrap(),
+        None,
+        &DownloadOptions::default(),
+    )
+    .await
+    .unwrap_err();
+
+    let download_error = error.downcast_ref::<DownloadError>().unwrap();
+    assert_eq!(download_error.context.guid.as_deref(), Some("guid1"));
+    let message = error.to_string();
+    assert!(message.starts_with("download for photo guid1 (http://127.0.0.1:1/a.jpg?***)"));
+    assert!(!message.contains("secret"));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_errors_carry_the_operation_id() {
+    let mut server = mockito::Server::new_async().await;
+    let webstream = server
+        .mock("POST", "/album/webstream")
+        .with_status(500)
+        .expect(2)
+        .create_async()
+        .await;
+
+    let client = reqwest::Client::new();
+    let base_url = format!("{}/album/", server.url());
+
+    // Outside an operation, no ID is recorded
+    let error = get_api_response(&client, &base_url).await.unwrap_err();
+    assert_eq!(error.context().unwrap().operation_id, None);
+
+    let error =
+        icloud_album_rs::logging::with_operation_id("req-42", get_api_response(&client, &base_url))
+            .await
+            .unwrap_err();
+    assert_eq!(
+        error.context().unwrap().operation_id.as_deref(),
+        Some("req-42")
+    );
+    assert!(error.to_string().contains("[op req-42]"));
+    webstream.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_errors_carry_response_headers() {
+    let mut server = mockito::Server::new_async().await;
+    let webstream = server
+        .mock("POST", "/album/webstream")
+        .with_status(503)
+        .with_header("x-apple-request-uuid", "3f1c-42")
+        .with_header("x-responding-instance", "sharedstreams:12345:pv12")
+        .with_header("x-ratelimit-remaining", "0")
+        .with_header("x-unrelated", "dropped")
+        .create_async()
+        .await;
+
+    let client = reqwest::Client::new();
+    let base_url = format!("{}/album/", server.url());
+
+    let error = get_api_response(&client, &base_url).await.unwrap_err();
+    let headers = error
+        .context()
+        .and_then(|context| context.response_headers.as_deref())
+        .unwrap();
+    assert_eq!(headers.status, 503);
+    assert_eq!(headers.request_id(), Some("3f1c-42"));
+    assert_eq!(
+        headers.get("X-Responding-Instance"),
+        Some("sharedstreams:12345:pv12")
+    );
+    assert_eq!(
+        headers.rate_limits().collect::<Vec<_>>(),
+        vec![("x-ratelimit-remaining", "0")]
+    );
+    assert_eq!(headers.get("x-unrelated"), None);
+    assert!(error.to_string().contains("[request 3f1c-42]"));
+
+    webstream.assert_async().await;
+}
@@ -0,0 +1,106 @@
+use icloud_album_rs::export::{to_kml, to_kml_with_options, KmlOptions};
+use icloud_album_rs::models::{Derivative, ICloudResponse, Image, Location, Metadata};
+use serde_json::json;
+use std::collections::HashMap;
+
+fn album(photos: Vec<Image>) -> ICloudResponse {
+    let metadata: Metadata = serde_json::from_value(json!({
+        "streamName": "Road Trip & Friends",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag123",
+        "itemsReturned": photos.len(),
+        "locations": {}
+    }))
+    .unwrap();
+
+    ICloudResponse {
+        metadata,
+        photos,
+        unparsed: Vec::new(),
+    }
+}
+
+fn located(guid: &str, latitude: f64, longitude: f64, url: Option<&str>) -> Image {
+    let mut derivatives = HashMap::new();
+    derivatives.insert(
+        "342".to_string(),
+        Derivative {
+            checksum: format!("{}-thumb", guid),
+            width: Some(342),
+            height: Some(256),
+            url: url.map(String::from),
+            ..Default::default()
+        },
+    );
+    Image {
+        photo_guid: guid.to_string(),
+        derivatives,
+        location: Some(Location {
+            latitude,
+            longitude,
+            altitude: None,
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_to_kml() {
+    let mut first = located(
+        "photo1",
+        41.8781,
+        -87.6298,
+        Some("https://cvws.icloud-content.com/a.jpg?o=1&e=2"),
+    );
+    first.caption = Some("Bean <3".to_string());
+    first.date_created = Some("2023-06-01T12:00:00Z".to_string());
+    first.place_name = Some("Chicago".to_string());
+    let second = located("photo2", 48.8566, 2.3522, None);
+    let unlocated = Image {
+        photo_guid: "photo3".to_string(),
+        ..Default::default()
+    };
+
+    let kml = to_kml(&album(vec![first, second, unlocated]));
+
+    assert!(kml.starts_with("<?xml"));
+    assert!(kml.contains("<name>Road Trip &amp; Friends</name>"));
+    assert_eq!(kml.matches("<Placemark>").count(), 2);
+    assert!(!kml.contains("photo3"));
+
+    // Placemarks use the caption, escaped, and longitude-first coordinates
+    assert!(kml.contains("<name>Bean &lt;3</name>"));
+    assert!(kml.contains("<coordinates>-87.6298,41.8781,0</coordinates>"));
+    assert!(kml.contains("<when>2023-06-01T12:00:00Z</when>"));
+    assert!(kml.contains("Place: Chicago"));
+    assert!(kml.contains("<name>photo2</name>"));
+
+    // Only the photo with a resolved URL gets an overlay
+    assert_eq!(kml.matches("<PhotoOverlay>").count(), 1);
+    assert!(kml.contains("<href>https://cvws.icloud-content.com/a.jpg?o=1&amp;e=2</href>"));
+
+    // The balloon image is described by the caption, escaped as HTML within XML
+    assert!(kml.contains("&lt;img src=\"https://cvws.icloud-content.com/a.jpg?o=1&amp;e=2\" alt=\"Bean &amp;lt;3\"/&gt;"));
+}
+
+#[test]
+fn test_to_kml_without_locations() {
+    let kml = to_kml(&album(vec![Image::default()]));
+    assert!(!kml.contains("<Placemark>"));
+    assert!(kml.trim_end().ends_with("</kml>"));
+}
+
+#[test]
+fn test_to_kml_markdown_captions() {
+    let mut photo = located("photo1", 41.8781, -87.6298, None);
+    photo.caption = Some("**Bean** trip\n\n- with *Ana*".to_string());
+    let album = album(vec![photo]);
+
+    let kml = to_kml_with_options(&album, &KmlOptions::new().with_markdown_captions(true));
+    assert!(kml.contains("<name>Bean trip with Ana</name>"));
+    assert!(kml.contains("<description>&lt;p&gt;&lt;strong&gt;Bean&lt;/strong&gt; trip&lt;/p&gt;"));
+
+    // By default captions are exported as written
+    assert!(to_kml(&album).contains("<name>**Bean** trip"));
+}
@@ -0,0 +1,114 @@
+#![cfg(feature = "fault-injection")]
+
+use icloud_album_rs::api::{get_asset_urls_with_config, ApiError, RetryConfig};
+use icloud_album_rs::faults::{self, FaultConfig, FaultStats};
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+// The fault configuration is process-wide, so everything is checked in one test
+#[tokio::test]
+async fn test_injected_failures_and_latency() {
+    let mut server = mockito::Server::new_async().await;
+    let assets = server
+        .mock("POST", "/album/webasseturls")
+        .with_body(json!({ "items": {} }).to_string())
+        .expect(5)
+        .create_async()
+        .await;
+    let client = reqwest::Client::new();
+    let base_url = format!("{}/album/", server.url());
+    let single_attempt = || RetryConfig {
+        max_retries: 1,
+        ..Default::default()
+    };
+
+    // Every second request fails without reaching the server
+    faults::set_faults(FaultConfig::new().fail_every(2, 503));
+    let mut results = Vec::new();
+    for _ in 0..4 {
+        results.push(
+            get_asset_urls_with_config(&client, &base_url, &["g".to_string()], single_attempt())
+                .await,
+        );
+    }
+    assert!(results[0].is_ok() && results[2].is_ok());
+    for failed in [&results[1], &results[3]] {
+        assert!(matches!(
+            failed.as_ref().unwrap_err().root(),
+            ApiError::RequestError {
+                status: Some(503),
+                ..
+            }
+        ));
+    }
+    assert_eq!(
+        faults::stats(),
+        Some(FaultStats {
+            requests: 4,
+            failures: 2
+        })
+    );
+
+    // Retries get past injected failures
+    faults::set_faults(FaultConfig::new().fail_every(2, 503));
+    get_asset_urls_with_config(&client, &base_url, &["g".to_string()], single_attempt())
+        .await
+        .unwrap();
+    let retrying = RetryConfig {
+        max_retries: 2,
+        base_delay_ms: 1,
+        ..Default::default()
+    };
+    get_asset_urls_with_config(&client, &base_url, &["g".to_string()], retrying)
+        .await
+        .unwrap();
+    assert_eq!(
+        faults::stats(),
+        Some(FaultStats {
+            requests: 3,
+            failures: 1
+        })
+    );
+
+    // Requests to other hosts are unaffected
+    faults::set_faults(
+        FaultConfig::new()
+            .fail_every(1, 500)
+            .for_host("example.com"),
+    );
+    get_asset_urls_with_config(&client, &base_url, &["g".to_string()], single_attempt())
+        .await
+        .unwrap();
+    assert_eq!(faults::stats().unwrap().requests, 0);
+
+    // Latency is added to matching requests
+    faults::set_faults(
+        FaultConfig::new()
+            .fail_every(1, 429)
+            .with_latency(Duration::from_millis(50), Duration::from_millis(60))
+            .for_host("127.0.0.1"),
+    );
+    let started = Instant::now();
+    let error =
+        get_asset_urls_with_config(&client, &base_url, &["g".to_string()], single_attempt())
+            .await
+            .unwrap_err();
+    assert!(started.elapsed() >= Duration::from_millis(50));
+    assert!(matches!(
+        error.root(),
+        ApiError::RequestError {
+            status: Some(429),
+            ..
+        }
+    ));
+
+    assert_eq!(
+        faults::clear_faults(),
+        Some(FaultStats {
+            requests: 1,
+            failures: 1
+        })
+    );
+    assert_eq!(faults::stats(), None);
+    assets.assert_async().await;
+}
@@ -0,0 +1,50 @@
+#![cfg(feature = "ffi")]
+
+use icloud_album_rs::ffi::{
+    icloud_download_photo, icloud_fetch_album_json, icloud_last_error_message, icloud_string_free,
+};
+use std::ffi::{CStr, CString};
+
+// Read and free the last error message
+fn take_last_error() -> String {
+    let ptr = icloud_last_error_message();
+    assert!(!ptr.is_null(), "expected an error message");
+    let message = unsafe { CStr::from_ptr(ptr) }
+        .to_string_lossy()
+        .into_owned();
+    unsafe { icloud_string_free(ptr) };
+    message
+}
+
+#[test]
+fn test_fetch_rejects_null_and_invalid_tokens() {
+    let result = unsafe { icloud_fetch_album_json(std::ptr::null()) };
+    assert!(result.is_null());
+    assert!(take_last_error().contains("token"));
+
+    // An invalid token fails before any network request is made
+    let token = CString::new("!invalid").unwrap();
+    let result = unsafe { icloud_fetch_album_json(token.as_ptr()) };
+    assert!(result.is_null());
+    assert!(take_last_error().contains("Invalid base62 character"));
+}
+
+#[test]
+fn test_download_rejects_invalid_photo_json() {
+    let photo = CString::new("not json").unwrap();
+    let dir = CString::new("/tmp").unwrap();
+    let result = unsafe { icloud_download_photo(photo.as_ptr(), dir.as_ptr()) };
+    assert!(result.is_null());
+    assert!(take_last_error().contains("Invalid photo JSON"));
+
+    // A photo without any URLs cannot be downloaded
+    let photo = CString::new(r#"{"photoGuid": "p1", "derivatives": {}}"#).unwrap();
+    let result = unsafe { icloud_download_photo(photo.as_ptr(), dir.as_ptr()) };
+    assert!(result.is_null());
+    assert!(take_last_error().contains("No suitable derivative"));
+}
+
+#[test]
+fn test_string_free_accepts_null() {
+    unsafe { icloud_string_free(std::ptr::null_mut()) };
+}
@@ -0,0 +1,159 @@
+//! Seeded randomized tests for filename sanitization and MIME detection
+//!
+//! These feed the functions large numbers of random inputs from a fixed seed;
+//! the coverage-guided fuzz target for `sanitize_filename` lives in `fuzz/`.
+//! Besides never panicking, the sanitized names must be usable: no path
+//! separators or control characters, and short enough for common filesystems.
+
+use icloud_album_rs::models::{parse_timestamp, Image};
+use icloud_album_rs::utils::{
+    detect_mime_type, get_extension_for_content, photo_base_filename, sanitize_filename,
+    sniff_mime_type, truncate_at_char_boundary, MAX_BASE_FILENAME_BYTES,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const ITERATIONS: usize = 2000;
+
+// Known signatures the MIME sniffer looks for
+const SIGNATURES: &[&[u8]] = &[
+    &[0xFF, 0xD8, 0xFF],
+    &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A],
+    b"GIF89a",
+    b"\0\0\0\x18ftypheic",
+    b"\0\0\0\x18ftypqt  ",
+    b"\0\0\0\x18ftypmp42",
+];
+
+// Text mixing ASCII, punctuation and multi-byte characters
+fn random_text(rng: &mut StdRng, max_len: usize) -> String {
+    let len = rng.gen_range(0..max_len);
+    (0..len)
+        .map(|_| match rng.gen_range(0..4) {
+            0 => rng.gen_range(b' '..=b'~') as char,
+            1 => ['é', '日', '🙂', '\u{200B}', '/', '\\', '.', '\n'][rng.gen_range(0..8)],
+            _ => rng.gen::<char>(),
+        })
+        .collect()
+}
+
+// Random bytes, sometimes starting with a known signature
+fn random_bytes(rng: &mut StdRng) -> Vec<u8> {
+    let mut bytes = if rng.gen_bool(0.5) {
+        SIGNATURES[rng.gen_range(0..SIGNATURES.len())].to_vec()
+    } else {
+        Vec::new()
+    };
+    let len = rng.gen_range(0..24);
+    bytes.extend((0..len).map(|_| rng.gen::<u8>()));
+    bytes.truncate(rng.gen_range(0..=bytes.len()));
+    bytes
+}
+
+// Names must not contain path separators or control characters
+fn assert_safe(name: &str, input: &str) {
+    assert!(
+        !name.contains(['/', '\\', '\0']) && !name.chars().any(char::is_control),
+        "unsafe name {:?} for {:?}",
+        name,
+        input
+    );
+}
+
+#[test]
+fn test_sanitized_filenames_are_usable() {
+    let mut rng = StdRng::seed_from_u64(0xf11e);
+    for _ in 0..ITERATIONS {
+        let input = random_text(&mut rng, 300);
+        let name = sanitize_filename(&input);
+        assert!(
+            name.len() <= MAX_BASE_FILENAME_BYTES,
+            "too long: {:?}",
+            name
+        );
+        assert_safe(&name, &input);
+        assert!(!name.starts_with('.') && !name.ends_with(' '));
+    }
+}
+
+#[test]
+fn test_photo_base_filenames_are_bounded() {
+    let mut rng = StdRng::seed_from_u64(0xba5e);
+    for _ in 0..ITERATIONS {
+        let photo = Image {
+            photo_guid: "01234567-89AB-CDEF-0123-456789ABCDEF".to_string(),
+            caption: Some(random_text(&mut rng, 400)),
+            ..Default::default()
+        };
+        let index = rng.gen_bool(0.5).then(|| rng.gen_range(0..100_000));
+        let custom = rng.gen_bool(0.3).then(|| random_text(&mut rng, 400));
+        let name = photo_base_filename(&photo, index, custom.as_deref());
+        assert!(
+            name.len() <= MAX_BASE_FILENAME_BYTES,
+            "too long: {:?}",
+            name
+        );
+        assert_safe(&name, photo.caption.as_deref().unwrap_or_default());
+    }
+    let photo = Image {
+        photo_guid: "guid1".to_string(),
+        caption: Some("Line one\nline\0two".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        photo_base_filename(&photo, None, None),
+        "guid1_Line one_line_two"
+    );
+    assert_eq!(
+        photo_base_filename(&photo, None, Some("../up")),
+        "guid1__up"
+    );
+}
+
+#[test]
+fn test_truncation_never_splits_characters() {
+    let mut rng = StdRng::seed_from_u64(0x7e47);
+    for _ in 0..ITERATIONS {
+        let text = random_text(&mut rng, 40);
+        let max_bytes = rng.gen_range(0..50);
+        let truncated = truncate_at_char_boundary(&text, max_bytes);
+        assert!(truncated.len() <= max_bytes);
+        assert!(text.starts_with(truncated));
+    }
+    assert_eq!(truncate_at_char_boundary("日本", 4), "日");
+    assert_eq!(sanitize_filename(" ..a/b:c.. "), "a_b_c");
+    // Whitespace uncovered by trimming dots is trimmed too
+    assert_eq!(sanitize_filename("! ."), "_");
+    assert_eq!(sanitize_filename(". a ."), "a");
+    assert_eq!(
+        sanitize_filename(&"日".repeat(100)).len(),
+        189 + "_truncated".len()
+    );
+    assert!(sanitize_filename(&"a".repeat(300)).len() <= MAX_BASE_FILENAME_BYTES);
+}
+
+#[test]
+fn test_mime_detection_never_panics() {
+    let mut rng = StdRng::seed_from_u64(0x313e);
+    for _ in 0..ITERATIONS {
+        let bytes = random_bytes(&mut rng);
+        let filename = rng.gen_bool(0.5).then(|| random_text(&mut rng, 20));
+        let _ = sniff_mime_type(&bytes);
+        let mime_type = detect_mime_type(&bytes, filename.as_deref());
+        assert!(mime_type.contains('/'));
+        let extension = get_extension_for_content(&bytes, filename.as_deref());
+        assert!(extension.starts_with('.'));
+    }
+}
+
+#[test]
+fn test_timestamp_parsing_never_panics() {
+    let mut rng = StdRng::seed_from_u64(0x71e5);
+    for _ in 0..ITERATIONS {
+        let mut timestamp = "2024-02-29T23:59:60".to_string();
+        timestamp.push_str(&random_text(&mut rng, 8));
+        let _ = parse_timestamp(&timestamp);
+    }
+    // A multi-byte character where the offset digits should be
+    assert_eq!(parse_timestamp("2024-01-01T00:00:00+aé0"), None);
+}
@@ -0,0 +1,98 @@
+use icloud_album_rs::fixup::{fix_extensions, FixupOptions};
+use std::path::{Path, PathBuf};
+
+const JPEG: &[u8] = &[
+    0xFF, 0xD8, 0xFF, 0xE0, 0, 0x10, b'J', b'F', b'I', b'F', 0, 1,
+];
+const PNG: &[u8] = &[
+    0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0x0D,
+];
+const HEIC: &[u8] = &[
+    0, 0, 0, 0x18, b'f', b't', b'y', b'p', b'h', b'e', b'i', b'c',
+];
+const MOV: &[u8] = &[
+    0, 0, 0, 0x14, b'f', b't', b'y', b'p', b'q', b't', b' ', b' ',
+];
+
+async fn setup(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("icloud-fixup-{}-{}", name, std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    tokio::fs::create_dir_all(dir.join("nested")).await.unwrap();
+
+    let files: &[(&str, &[u8])] = &[
+        ("correct.jpg", JPEG),
+        ("also_correct.jpeg", JPEG),
+        ("screenshot.jpg", PNG),
+        ("live.jpg", HEIC),
+        ("notes.txt", b"just some plain text"),
+        ("nested/clip.jpg", MOV),
+    ];
+    for (file, bytes) in files {
+        tokio::fs::write(dir.join(file), bytes).await.unwrap();
+    }
+    dir
+}
+
+fn names(dir: &Path, paths: Vec<PathBuf>) -> Vec<String> {
+    paths
+        .iter()
+        .map(|p| {
+            p.strip_prefix(dir)
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/")
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_fix_extensions_dry_run() {
+    let dir = setup("dry-run").await;
+    let options = FixupOptions::new().with_dry_run(true);
+
+    let fixes = fix_extensions(&dir, &options).await.unwrap();
+    assert_eq!(
+        names(&dir, fixes.iter().map(|f| f.to.clone()).collect()),
+        ["live.heic", "nested/clip.mov", "screenshot.png"]
+    );
+    assert_eq!(fixes[0].mime_type, "image/heic");
+
+    // Nothing was renamed
+    assert!(dir.join("screenshot.jpg").exists());
+    assert!(!dir.join("screenshot.png").exists());
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_fix_extensions_renames() {
+    let dir = setup("rename").await;
+    // An existing file already occupies the corrected name
+    tokio::fs::write(dir.join("screenshot.png"), PNG)
+        .await
+        .unwrap();
+
+    let options = FixupOptions::new()
+        .with_recursive(false)
+        .with_concurrency(2);
+    let fixes = fix_extensions(&dir, &options).await.unwrap();
+    assert_eq!(
+        names(&dir, fixes.iter().map(|f| f.to.clone()).collect()),
+        ["live.heic", "screenshot_1.png"]
+    );
+
+    assert!(!dir.join("screenshot.jpg").exists());
+    assert_eq!(
+        tokio::fs::read(dir.join("screenshot_1.png")).await.unwrap(),
+        PNG
+    );
+    // Non-recursive runs leave subdirectories alone
+    assert!(dir.join("nested/clip.jpg").exists());
+    // Unrecognized content is never renamed
+    assert!(dir.join("notes.txt").exists());
+
+    // A second pass finds nothing left to fix
+    assert!(fix_extensions(&dir, &options).await.unwrap().is_empty());
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
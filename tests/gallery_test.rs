@@ -0,0 +1,374 @@
+mod common;
+
+use common::{derivative, photo_with, temp_dir};
+use icloud_album_rs::derivatives::SizeClass;
+use icloud_album_rs::download::{DerivativeDownload, MultiDownloadReport};
+use icloud_album_rs::gallery::{
+    gallery_items, render_gallery, write_gallery, write_gallery_incremental, AltTextGenerator,
+    GalleryItem, GalleryManifest, GalleryOptions, GalleryPage, GalleryTheme, TemplateTheme,
+    GALLERY_FILE,
+};
+use icloud_album_rs::models::{ICloudResponse, Image, Location, Metadata};
+use serde_json::json;
+use std::path::Path;
+
+fn album(guids: &[&str]) -> ICloudResponse {
+    album_of(
+        guids
+            .iter()
+            .map(|guid| Image {
+                photo_guid: guid.to_string(),
+                caption: Some(format!("{} & friends", guid)),
+                ..Default::default()
+            })
+            .collect(),
+    )
+}
+
+fn album_of(photos: Vec<Image>) -> ICloudResponse {
+    let metadata: Metadata = serde_json::from_value(json!({
+        "streamName": "Summer <2024>",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag123",
+        "itemsReturned": photos.len(),
+        "locations": {}
+    }))
+    .unwrap();
+    ICloudResponse {
+        metadata,
+        photos,
+        unparsed: Vec::new(),
+    }
+}
+
+fn downloaded(guid: &str, size_class: SizeClass, path: &str) -> DerivativeDownload {
+    DerivativeDownload {
+        photo_guid: guid.to_string(),
+        size_class,
+        key: "342".to_string(),
+        path: path.to_string(),
+    }
+}
+
+#[test]
+fn test_gallery_items() {
+    let album = album(&["photo", "video", "missing", "packed"]);
+    let report = MultiDownloadReport {
+        downloaded: vec![
+            downloaded("photo", SizeClass::Thumbnail, "site/photo_thumb.jpg"),
+            downloaded("photo", SizeClass::Large, "site/large/my photo.jpg"),
+            downloaded("video", SizeClass::VideoPoster, "site/video_poster.jpg"),
+            downloaded("video", SizeClass::Video, "site/video_video.mp4"),
+            downloaded(
+                "packed",
+                SizeClass::Thumbnail,
+                "site/thumbs.pack#packed.jpg",
+            ),
+        ],
+        ..Default::default()
+    };
+
+    let items = gallery_items(&album, &report, Path::new("site"));
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].thumbnail_url, "photo_thumb.jpg");
+    assert_eq!(items[0].url, "large/my%20photo.jpg");
+    assert!(!items[0].is_video);
+    assert_eq!(items[1].thumbnail_url, "video_poster.jpg");
+    assert_eq!(items[1].url, "video_video.mp4");
+    assert!(items[1].is_video);
+}
+
+#[test]
+fn test_render_gallery_escapes() {
+    let album = album(&["photo"]);
+    let report = MultiDownloadReport {
+        downloaded: vec![downloaded("photo", SizeClass::Thumbnail, "photo_thumb.jpg")],
+        ..Default::default()
+    };
+    let items = gallery_items(&album, &report, Path::new("."));
+    // Without a larger file, the thumbnail links to itself
+    assert_eq!(items[0].url, "photo_thumb.jpg");
+
+    let pages = render_gallery("Summer <2024>", &items, &GalleryOptions::new());
+    assert_eq!(pages.len(), 1);
+    assert_eq!(pages[0].file_name, GALLERY_FILE);
+    let html = &pages[0].html;
+    assert!(html.contains("<title>Summer &lt;2024&gt;</title>"));
+    assert!(html.contains("<figcaption>photo &amp; friends</figcaption>"));
+    assert!(html.contains("<p>1 photo</p>"));
+
+    let html = &render_gallery("x", &items, &GalleryOptions::new().with_captions(false))[0].html;
+    assert!(!html.contains("<figcaption>"));
+
+    let mut items = items;
+    items[0].caption = Some("**Sunset** at <the> pier".to_string());
+    let options = GalleryOptions::new().with_markdown_captions(true);
+    let html = &render_gallery("x", &items, &options)[0].html;
+    assert!(html
+        .contains("<figcaption><p><strong>Sunset</strong> at &lt;the&gt; pier</p></figcaption>"));
+    assert!(html.contains("alt=\"Sunset at &lt;the&gt; pier\""));
+}
+
+#[test]
+fn test_custom_templates() {
+    let mut album = album(&["photo"]);
+    album.photos[0].date_created = Some("2024-07-01T10:00:00Z".to_string());
+    album.photos[0].place_name = Some("Lisbon".to_string());
+    album.photos[0].location = Some(Location {
+        latitude: 38.7,
+        longitude: -9.1,
+        altitude: None,
+    });
+    let report = MultiDownloadReport {
+        downloaded: vec![downloaded("photo", SizeClass::Thumbnail, "photo_thumb.jpg")],
+        ..Default::default()
+    };
+    let items = gallery_items(&album, &report, Path::new("."));
+
+    let theme = TemplateTheme::new(
+        "<h1>{title}</h1><style>{css}</style>{photos}{unknown}",
+        "<img src=\"{thumbnail_url}\" title=\"{caption} - {date} - {place} ({latitude},{longitude})\">",
+    );
+    let options = GalleryOptions::new()
+        .with_css("img { width: 100px }")
+        .with_theme(theme);
+    let html = &render_gallery("Trip {title}", &items, &options)[0].html;
+    assert_eq!(
+        html,
+        "<h1>Trip {title}</h1><style>img { width: 100px }</style>\
+         <img src=\"photo_thumb.jpg\" title=\"photo &amp; friends - 2024-07-01T10:00:00Z \
+         - Lisbon (38.7,-9.1)\">{unknown}"
+    );
+
+    struct CountTheme;
+    impl GalleryTheme for CountTheme {
+        fn render(&self, page: &GalleryPage) -> String {
+            format!("{}: {}", page.title, page.photos.len())
+        }
+    }
+    let options = GalleryOptions::new().with_theme(CountTheme);
+    assert_eq!(
+        render_gallery("Album", &items, &options)[0].html,
+        "Album: 1"
+    );
+}
+
+#[test]
+fn test_alt_text() {
+    let mut album = album(&["captioned", "described", "dated", "video"]);
+    album.photos[0].caption = Some("  Sunset\n at the pier ".to_string());
+    for photo in &mut album.photos[1..] {
+        photo.caption = None;
+    }
+    album.photos[2].date_created = Some("2024-07-01T10:00:00Z".to_string());
+    let report = MultiDownloadReport {
+        downloaded: vec![
+            downloaded("captioned", SizeClass::Thumbnail, "captioned.jpg"),
+            downloaded("described", SizeClass::Thumbnail, "described.jpg"),
+            downloaded("dated", SizeClass::Thumbnail, "dated.jpg"),
+            downloaded("video", SizeClass::VideoPoster, "video_poster.jpg"),
+            downloaded("video", SizeClass::Video, "video.mp4"),
+        ],
+        ..Default::default()
+    };
+    let items = gallery_items(&album, &report, Path::new("."));
+
+    /// Describes only the photos whose thumbnail it knows
+    struct Model;
+    impl AltTextGenerator for Model {
+        fn alt_text(&self, item: &GalleryItem) -> Option<String> {
+            assert_ne!(item.photo_guid, "captioned");
+            match item.thumbnail_url.as_str() {
+                "described.jpg" => Some("A dog on a \"beach\"".to_string()),
+                "video_poster.jpg" => Some("Waves".to_string()),
+                _ => None,
+            }
+        }
+    }
+    let options = GalleryOptions::new()
+        .with_alt_text(Model)
+        .with_theme(TemplateTheme::new("{photos}", "{alt}"));
+    assert_eq!(
+        render_gallery("x", &items, &options)[0].html,
+        "Sunset at the pier\nA dog on a &quot;beach&quot;\nPhoto, 2024-07-01T10:00:00Z\nVideo: Waves"
+    );
+
+    let html = &render_gallery("x", &items, &GalleryOptions::new())[0].html;
+    assert!(html.contains("<html lang=\"en\">"));
+    assert!(html.contains("<main>"));
+    assert!(html.contains("alt=\"Photo\""));
+    assert!(html.contains("alt=\"Video\""));
+}
+
+#[test]
+fn test_write_gallery() {
+    let dir = temp_dir("gallery");
+    std::fs::create_dir_all(&dir).unwrap();
+    let album = album(&["photo"]);
+    let report = MultiDownloadReport {
+        downloaded: vec![downloaded(
+            "photo",
+            SizeClass::Thumbnail,
+            dir.join("photo_thumb.jpg").to_str().unwrap(),
+        )],
+        ..Default::default()
+    };
+
+    let path = write_gallery(&album, &report, &dir, &GalleryOptions::new()).unwrap();
+    assert_eq!(path, dir.join(GALLERY_FILE));
+    let html = std::fs::read_to_string(&path).unwrap();
+    assert!(html.contains("<h1>Summer &lt;2024&gt;</h1>"));
+    assert!(html.contains("src=\"photo_thumb.jpg\""));
+
+    let options = GalleryOptions::new().with_title("Holidays");
+    write_gallery(&album, &report, &dir, &options).unwrap();
+    assert!(std::fs::read_to_string(&path)
+        .unwrap()
+        .contains("<title>Holidays</title>"));
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+// A photo whose thumbnail has the given checksum
+fn thumbnailed(guid: &str, checksum: &str) -> Image {
+    photo_with(guid, [("342", derivative(checksum, None))])
+}
+
+/// Writes the thumbnails of `guids` and returns the report of downloading them
+fn download(dir: &Path, guids: &[&str]) -> MultiDownloadReport {
+    let downloaded = guids
+        .iter()
+        .map(|guid| {
+            let path = dir.join(format!("{}_thumb.jpg", guid));
+            std::fs::write(&path, guid).unwrap();
+            downloaded(guid, SizeClass::Thumbnail, path.to_str().unwrap())
+        })
+        .collect();
+    MultiDownloadReport {
+        downloaded,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_incremental_build() {
+    let dir = temp_dir("gallery-inc");
+    std::fs::create_dir_all(&dir).unwrap();
+    let options = GalleryOptions::new();
+
+    // The first build publishes everything
+    let first = album_of(vec![thumbnailed("a", "a1"), thumbnailed("b", "b1")]);
+    let manifest = GalleryManifest::load(&dir).unwrap();
+    let plan = manifest.plan(&first, &dir);
+    assert_eq!(plan.changed, ["a", "b"]);
+    let report = download(&dir, &["a", "b"]);
+    let build = write_gallery_incremental(&first, &report, &dir, &options, &manifest).unwrap();
+    assert_eq!((build.published, build.reused), (2, 0));
+    assert_eq!(build.pages_written, 1);
+    assert_eq!(GalleryManifest::load(&dir).unwrap(), build.manifest);
+
+    // Nothing changed: nothing is downloaded and the page is left alone
+    let manifest = GalleryManifest::load(&dir).unwrap();
+    let plan = manifest.plan(&first, &dir);
+    assert!(plan.changed.is_empty());
+    assert_eq!(plan.unchanged, ["a", "b"]);
+    let build = write_gallery_incremental(
+        &first,
+        &MultiDownloadReport::default(),
+        &dir,
+        &options,
+        &manifest,
+    )
+    .unwrap();
+    assert_eq!((build.published, build.reused), (0, 2));
+    assert_eq!(build.pages_written, 0);
+
+    // b was edited, a removed and c added
+    let mut second = album_of(vec![thumbnailed("b", "b2"), thumbnailed("c", "c1")]);
+    let plan = manifest.plan(&second, &dir);
+    assert_eq!(plan.changed, ["b", "c"]);
+    assert_eq!(plan.removed, ["a"]);
+    assert_eq!(plan.changed_photos(&second).len(), 2);
+    let report = download(&dir, &["b", "c"]);
+    let build = write_gallery_incremental(&second, &report, &dir, &options, &manifest).unwrap();
+    assert_eq!((build.published, build.reused), (2, 0));
+    assert_eq!(build.removed_files, [dir.join("a_thumb.jpg")]);
+    assert!(!dir.join("a_thumb.jpg").exists());
+    assert!(dir.join("b_thumb.jpg").exists());
+    assert_eq!(build.pages_written, 1);
+
+    // A caption change only rewrites the page; a deleted file is downloaded again
+    let manifest = build.manifest;
+    second.photos[0].caption = Some("New caption".to_string());
+    std::fs::remove_file(dir.join("c_thumb.jpg")).unwrap();
+    let plan = manifest.plan(&second, &dir);
+    assert_eq!(plan.changed, ["c"]);
+    let report = download(&dir, &["c"]);
+    let build = write_gallery_incremental(&second, &report, &dir, &options, &manifest).unwrap();
+    assert_eq!((build.published, build.reused), (1, 1));
+    assert_eq!(build.pages_written, 1);
+    assert!(std::fs::read_to_string(dir.join(GALLERY_FILE))
+        .unwrap()
+        .contains("New caption"));
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn test_paginated_gallery() {
+    let dir = temp_dir("gallery-pages");
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut thumbnail = thumbnailed("a", "a1");
+    let derivative = thumbnail.derivatives.get_mut("342").unwrap();
+    derivative.width = Some(342);
+    derivative.height = Some(256);
+    let five = album_of(vec![
+        thumbnail,
+        thumbnailed("b", "b1"),
+        thumbnailed("c", "c1"),
+        thumbnailed("d", "d1"),
+        thumbnailed("e", "e1"),
+    ]);
+    let report = download(&dir, &["a", "b", "c", "d", "e"]);
+    let options = GalleryOptions::new().with_page_size(2);
+
+    let items = gallery_items(&five, &report, &dir);
+    assert_eq!((items[0].width, items[0].height), (Some(342), Some(256)));
+    let pages = render_gallery("Album", &items, &options);
+    let names: Vec<&str> = pages.iter().map(|page| page.file_name.as_str()).collect();
+    assert_eq!(names, ["index.html", "page-2.html", "page-3.html"]);
+    assert!(pages[0].html.contains(
+        "src=\"a_thumb.jpg\" alt=\"Photo\" width=\"342\" height=\"256\" loading=\"lazy\""
+    ));
+    assert!(pages[0].html.contains("<p>5 photos</p>"));
+    assert!(pages[0].html.contains("href=\"page-2.html\" rel=\"next\""));
+    assert!(!pages[0].html.contains("rel=\"prev\""));
+    assert!(pages[1].html.contains("href=\"index.html\" rel=\"prev\""));
+    assert!(pages[1]
+        .html
+        .contains("<span aria-current=\"page\">Page 2 of 3</span>"));
+    assert_eq!(pages[2].html.matches("<figure>").count(), 1);
+
+    // One page: no pagination
+    let single = render_gallery("Album", &items, &GalleryOptions::new().with_page_size(0));
+    assert_eq!(single.len(), 1);
+    assert!(!single[0].html.contains("pagination\">"));
+
+    // Pages no longer needed are deleted by the next build
+    let build =
+        write_gallery_incremental(&five, &report, &dir, &options, &GalleryManifest::default())
+            .unwrap();
+    assert_eq!(build.pages_written, 3);
+    let two = album_of(vec![thumbnailed("a", "a1"), thumbnailed("b", "b1")]);
+    let build = write_gallery_incremental(
+        &two,
+        &MultiDownloadReport::default(),
+        &dir,
+        &options,
+        &build.manifest,
+    )
+    .unwrap();
+    assert_eq!(build.manifest.pages, ["index.html"]);
+    assert!(build.removed_files.contains(&dir.join("page-3.html")));
+    assert!(!dir.join("page-2.html").exists());
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,123 @@
+use icloud_album_rs::api::get_asset_urls;
+use icloud_album_rs::download::{download_photo_with_options, DownloadOptions};
+use icloud_album_rs::har::{self, HarOptions};
+use icloud_album_rs::models::{Derivative, Image};
+use serde_json::json;
+use std::collections::HashMap;
+
+// Recording is process-wide, so everything is checked in one test
+#[tokio::test]
+async fn test_har_recording() {
+    let mut server = mockito::Server::new_async().await;
+    let asset_urls = server
+        .mock("POST", "/B0z5qAGN1JIFd3y/sharedstreams/webasseturls")
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({
+                "items": {
+                    "checksum1": {
+                        "url_location": "cvws.icloud-content.com",
+                        "url_path": "/photo.jpg?signature=secret"
+                    }
+                }
+            })
+            .to_string(),
+        )
+        .expect(2)
+        .create_async()
+        .await;
+    let photo_body = server
+        .mock("GET", "/photo.jpg")
+        .match_query(mockito::Matcher::Any)
+        .with_header("content-type", "image/jpeg")
+        .with_body([0xFF, 0xD8, 0xFF, 0xE0, 0, 0, 0, 0])
+        .create_async()
+        .await;
+
+    assert!(!har::is_recording());
+    har::start_recording(HarOptions::default());
+    assert!(har::is_recording());
+
+    // Responses are still usable after being recorded
+    let base_url = format!("{}/B0z5qAGN1JIFd3y/sharedstreams/", server.url());
+    let urls = get_asset_urls(&reqwest::Client::new(), &base_url, &["guid1".to_string()])
+        .await
+        .unwrap();
+    assert_eq!(
+        urls["checksum1"],
+        "https://cvws.icloud-content.com/photo.jpg?signature=secret"
+    );
+
+    let mut derivatives = HashMap::new();
+    derivatives.insert(
+        "original".to_string(),
+        Derivative {
+            url: Some(format!("{}/photo.jpg?signature=secret", server.url())),
+            ..Default::default()
+        },
+    );
+    let photo = Image {
+        photo_guid: "guid1".to_string(),
+        derivatives,
+        ..Default::default()
+    };
+    let output_dir = std::env::temp_dir()
+        .join(format!("icloud-har-{}", std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    download_photo_with_options(&photo, None, &output_dir, None, &DownloadOptions::new())
+        .await
+        .unwrap();
+
+    let log = har::stop_recording().unwrap();
+    assert!(!har::is_recording());
+    assert!(har::stop_recording().is_none());
+    assert_eq!(log.log.version, "1.2");
+    assert_eq!(log.log.entries.len(), 2);
+
+    let api = &log.log.entries[0];
+    assert_eq!(api.request.method, "POST");
+    // The token is redacted from the URL and the GUID pseudonymized in the body
+    assert!(api
+        .request
+        .url
+        .ends_with("/B0z5***/sharedstreams/webasseturls"));
+    let post_data = api.request.post_data.as_ref().unwrap();
+    assert!(post_data.text.contains("ANON"));
+    assert!(!post_data.text.contains("guid1"));
+    assert_eq!(api.response.status, 200);
+    let text = api.response.content.text.as_deref().unwrap();
+    assert!(text.contains("/photo.jpg?anonymized"));
+    assert!(!text.contains("secret"));
+
+    let download = &log.log.entries[1];
+    assert_eq!(download.request.method, "GET");
+    assert!(!download.request.url.contains("secret"));
+    assert!(download.request.query_string.is_empty());
+    assert_eq!(download.response.content.size, 8);
+    assert_eq!(download.response.content.mime_type, "image/jpeg");
+    assert!(download.response.content.text.is_none());
+
+    let written: serde_json::Value = serde_json::from_str(&log.to_json()).unwrap();
+    assert_eq!(written["log"]["entries"][0]["response"]["redirectURL"], "");
+
+    // Without anonymization, bodies are kept verbatim but truncated
+    har::start_recording(
+        HarOptions::new()
+            .with_max_body_len(10)
+            .with_anonymize(false),
+    );
+    get_asset_urls(&reqwest::Client::new(), &base_url, &["guid1".to_string()])
+        .await
+        .unwrap();
+    let log = har::stop_recording().unwrap();
+    let content = &log.log.entries[0].response.content;
+    assert_eq!(content.text.as_deref(), Some("{\"items\":{"));
+    assert_eq!(content.comment.as_deref(), Some("truncated to 10 bytes"));
+    let post_data = log.log.entries[0].request.post_data.as_ref().unwrap();
+    assert_eq!(post_data.text, "{\"photoGui");
+
+    asset_urls.assert_async().await;
+    photo_body.assert_async().await;
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
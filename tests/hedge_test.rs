@@ -0,0 +1,65 @@
+#![cfg(feature = "fault-injection")]
+
+use icloud_album_rs::base_url::{cached_partition, set_partition_scheme, PartitionScheme};
+use icloud_album_rs::faults::{clear_faults, set_faults, FaultConfig};
+use icloud_album_rs::get_icloud_photos;
+use icloud_album_rs::transport::{
+    with_transport, MockTransport, Transport, TransportRequest, TransportResponse,
+};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Serves the `family` fixture from one partition host only
+struct SinglePartition {
+    host: &'static str,
+    fixture: MockTransport,
+}
+
+impl Transport for SinglePartition {
+    fn respond(&self, request: &TransportRequest) -> Option<TransportResponse> {
+        if request.url.contains(self.host) {
+            self.fixture.respond(request)
+        } else {
+            Some(TransportResponse::new(404, "unknown album"))
+        }
+    }
+}
+
+// The partition scheme and faults are process-wide, so everything is checked in one test
+#[tokio::test]
+async fn test_hedged_partition_probe() {
+    let token = "B0z5qAGN1JIFd3y";
+    set_partition_scheme(
+        PartitionScheme::new()
+            .with_candidates([5])
+            .with_hedging(Duration::from_millis(50)),
+    );
+
+    // Partition 5 answers first and is remembered
+    let transport = Arc::new(SinglePartition {
+        host: "p05-sharedstreams.icloud.com",
+        fixture: MockTransport::with_fixture("family").unwrap(),
+    });
+    with_transport(transport, get_icloud_photos(token))
+        .await
+        .unwrap();
+    assert_eq!(cached_partition(token), Some(5));
+
+    // Once it turns slow, the computed partition 12 answers in its place
+    set_faults(
+        FaultConfig::new()
+            .with_latency(Duration::from_secs(5), Duration::from_secs(5))
+            .for_host("p05-sharedstreams.icloud.com"),
+    );
+    let transport = Arc::new(MockTransport::with_fixture("family").unwrap());
+    let started = Instant::now();
+    let album = with_transport(transport, get_icloud_photos(token))
+        .await
+        .unwrap();
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert_eq!(album.metadata.stream_name, "Summer Trip");
+    assert_eq!(cached_partition(token), Some(12));
+
+    clear_faults();
+    set_partition_scheme(PartitionScheme::default());
+}
@@ -0,0 +1,653 @@
+#![cfg(feature = "server")]
+
+mod common;
+
+use common::temp_dir;
+use icloud_album_rs::base_url::API_ORIGIN_ENV;
+use icloud_album_rs::config::{INTERVAL_SECS_ENV, OUTPUT_DIR_ENV, TOKENS_ENV};
+use icloud_album_rs::sync::SyncState;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::process::Output;
+
+// PNG signature followed by some padding
+const PNG_BYTES: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+/// Mocks an album with two photos whose originals are served by `server`
+async fn mock_album(server: &mut mockito::ServerGuard) -> Vec<mockito::Mock> {
+    mock_album_with(server, "original").await
+}
+
+/// Mocks an album with two photos, each with a single derivative under `key`
+async fn mock_album_with(server: &mut mockito::ServerGuard, key: &str) -> Vec<mockito::Mock> {
+    let photos: Vec<Value> = ["photo1", "photo2"]
+        .iter()
+        .map(|guid| {
+            json!({
+                "photoGuid": guid,
+                "derivatives": {
+                    key: { "checksum": format!("{}-c", guid), "fileSize": "12" }
+                }
+            })
+        })
+        .collect();
+    let webstream = json!({
+        "streamName": "Mirrored Album",
+        "streamCtag": "ctag1",
+        "itemsReturned": "2",
+        "photos": photos
+    });
+    let asset_urls = json!({
+        "items": {
+            "photo1-c": { "url_location": "cvws.icloud-content.com", "url_path": "/photo1.png" },
+            "photo2-c": { "url_location": "cvws.icloud-content.com", "url_path": "/photo2.png" }
+        }
+    });
+    vec![
+        server
+            .mock("POST", "/TestToken/sharedstreams/webstream")
+            .with_body(webstream.to_string())
+            .create_async()
+            .await,
+        server
+            .mock("POST", "/TestToken/sharedstreams/webasseturls")
+            .with_body(asset_urls.to_string())
+            .create_async()
+            .await,
+        server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex(r"^/photo\d\.png$".to_string()),
+            )
+            .with_header("content-type", "image/png")
+            .with_body(PNG_BYTES)
+            .create_async()
+            .await,
+    ]
+}
+
+/// The JSON summary line a mirror pass prints on stdout
+fn summary(output: &Output) -> Value {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    let summary = serde_json::from_str(lines.next().expect("no summary line")).unwrap();
+    assert_eq!(lines.next(), None, "more than one line on stdout");
+    summary
+}
+
+/// Runs `icloud-album mirror` once against `origin`
+async fn mirror(origin: &str, tokens: &str, output_dir: &Path) -> Output {
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_icloud-album"));
+    command
+        .arg("mirror")
+        .env(API_ORIGIN_ENV, origin)
+        .env(TOKENS_ENV, tokens)
+        .env(OUTPUT_DIR_ENV, output_dir);
+    tokio::task::spawn_blocking(move || command.output().unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_mirror_once_from_environment() {
+    let mut server = mockito::Server::new_async().await;
+    let _mocks = mock_album(&mut server).await;
+    let output_dir = temp_dir("mirror");
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+
+    let output = mirror(&server.url(), "TestToken", &output_dir).await;
+    assert!(output.status.success(), "{:?}", output);
+    let album_dir = output_dir.join("TestToken");
+    let state = SyncState::load(album_dir.join(".sync-state.json"))
+        .await
+        .unwrap();
+    assert!(state.file("photo1").unwrap().path.is_file());
+    assert!(state.file("photo2").unwrap().path.is_file());
+
+    // A second pass finds everything in place
+    let output = mirror(&server.url(), "TestToken", &output_dir).await;
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("0 downloaded, 0 failed, 2 already present"));
+
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_mirror_summary_and_exit_codes() {
+    let mut server = mockito::Server::new_async().await;
+    let _mocks = mock_album(&mut server).await;
+    let _gone = server
+        .mock("POST", "/GoneToken/sharedstreams/webstream")
+        .with_status(404)
+        .create_async()
+        .await;
+    let output_dir = temp_dir("exit-codes");
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+
+    let output = mirror(&server.url(), "TestToken", &output_dir).await;
+    assert_eq!(output.status.code(), Some(0));
+    let line = summary(&output);
+    assert_eq!(line["status"], "ok");
+    assert_eq!(line["albums"], 1);
+    assert_eq!(line["downloaded"], 2);
+    assert_eq!(line["errors"], json!([]));
+
+    // A photo that fails to download makes the pass partial
+    let _broken = server
+        .mock("POST", "/TestToken/sharedstreams/webstream")
+        .with_body(
+            json!({
+                "streamName": "Mirrored Album",
+                "streamCtag": "ctag2",
+                "photos": [{
+                    "photoGuid": "photo3",
+                    "derivatives": { "original": { "checksum": "photo3-c", "fileSize": "12" } }
+                }]
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+    let _missing_asset = server
+        .mock("POST", "/TestToken/sharedstreams/webasseturls")
+        .with_body(
+            json!({ "items": {
+                "photo3-c": { "url_location": "cvws.icloud-content.com", "url_path": "/photo3.png" }
+            } })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+    let _failing = server
+        .mock("GET", "/photo3.png")
+        .with_status(500)
+        .create_async()
+        .await;
+    let output = mirror(&server.url(), "TestToken", &output_dir).await;
+    assert_eq!(output.status.code(), Some(2));
+    let line = summary(&output);
+    assert_eq!(line["status"], "partial");
+    assert_eq!(line["failed"], 1);
+
+    // A missing album is a token error, which wins over other outcomes
+    let output = mirror(&server.url(), "TestToken,GoneToken", &output_dir).await;
+    assert_eq!(output.status.code(), Some(3));
+    let line = summary(&output);
+    assert_eq!(line["status"], "token_error");
+    assert_eq!(line["albums"], 2);
+    assert_eq!(line["errors"][0]["class"], "token");
+    assert!(!line.to_string().contains("GoneToken"));
+
+    // Nothing listens on the discard port
+    let output = mirror("http://127.0.0.1:9", "TestToken", &output_dir).await;
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(summary(&output)["status"], "network_error");
+
+    // Invalid configuration
+    let output = mirror(&server.url(), "not-a-token", &output_dir).await;
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_serve_reports_followed_albums_in_health() {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    let mut server = mockito::Server::new_async().await;
+    let _mocks = mock_album(&mut server).await;
+    let root = temp_dir("serve-health");
+    let _ = tokio::fs::remove_dir_all(&root).await;
+    tokio::fs::create_dir_all(&root).await.unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_icloud-album"))
+        .args(["serve", "--port", "0", "--root"])
+        .arg(&root)
+        .env(API_ORIGIN_ENV, server.url())
+        .env(TOKENS_ENV, "TestToken")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    // The reader is kept open, as the server keeps printing after its origin
+    let stdout = child.stdout.take().unwrap();
+    let (origin, _stdout) = tokio::task::spawn_blocking(move || {
+        let mut lines = BufReader::new(stdout).lines();
+        let origin = lines
+            .by_ref()
+            .map_while(Result::ok)
+            .find_map(|line| {
+                let rpc = line.split(" at ").nth(1)?;
+                Some(rpc.trim_end_matches("/rpc").to_string())
+            })
+            .expect("no origin printed");
+        (origin, lines)
+    })
+    .await
+    .unwrap();
+
+    let mut health = Value::Null;
+    for _ in 0..100 {
+        health = reqwest::get(format!("{}/health", origin))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        if !health["scheduler"]["last_success"].is_null() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["scheduler"]["albums"], 1);
+    assert_eq!(health["scheduler"]["queue_depth"], 0);
+    assert!(health["scheduler"]["last_success"].is_u64(), "{}", health);
+    let state = SyncState::load(root.join("TestToken").join(".sync-state.json"))
+        .await
+        .unwrap();
+    assert!(state.file("photo1").unwrap().path.is_file());
+
+    tokio::fs::remove_dir_all(&root).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_mirror_follows_albums_with_an_interval() {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    let mut server = mockito::Server::new_async().await;
+    let _mocks = mock_album(&mut server).await;
+    let output_dir = temp_dir("mirror-follow");
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_icloud-album"))
+        .arg("mirror")
+        .env(API_ORIGIN_ENV, server.url())
+        .env(TOKENS_ENV, "TestToken")
+        .env(OUTPUT_DIR_ENV, &output_dir)
+        .env(INTERVAL_SECS_ENV, "3600")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    // Every sync of an album prints its own summary line, and the process keeps running
+    let stdout = child.stdout.take().unwrap();
+    let line = tokio::task::spawn_blocking(move || {
+        BufReader::new(stdout)
+            .lines()
+            .next()
+            .expect("no summary line")
+            .unwrap()
+    })
+    .await
+    .unwrap();
+    assert!(child.try_wait().unwrap().is_none(), "mirror exited");
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let summary: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(summary["status"], "ok");
+    assert_eq!(summary["albums"], 1);
+    assert_eq!(summary["downloaded"], 2);
+    let state = SyncState::load(output_dir.join("TestToken").join(".sync-state.json"))
+        .await
+        .unwrap();
+    assert!(state.file("photo2").unwrap().path.is_file());
+
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_mirror_applies_config_file_edits() {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    let mut server = mockito::Server::new_async().await;
+    let _mocks = mock_album(&mut server).await;
+    let dir = temp_dir("mirror-config");
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    tokio::fs::create_dir_all(&dir).await.unwrap();
+    let config_file = dir.join("albums.json");
+    let write_config = |output_dir: &Path, interval_secs: u64| {
+        let config = json!({
+            "albums": [{
+                "token": "TestToken",
+                "interval_secs": interval_secs,
+                "output_dir": output_dir,
+            }]
+        });
+        std::fs::write(&config_file, config.to_string()).unwrap();
+    };
+    write_config(&dir.join("before"), 3600);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_icloud-album"))
+        .arg("mirror")
+        .arg("--config")
+        .arg(&config_file)
+        .env(API_ORIGIN_ENV, server.url())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let (line, mut lines) = tokio::task::spawn_blocking(move || {
+        let line = lines.next().expect("no summary line").unwrap();
+        (line, lines)
+    })
+    .await
+    .unwrap();
+    let summary: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(summary["downloaded"], 2);
+
+    // Moving the album and polling it sooner applies without a restart
+    write_config(&dir.join("after"), 1);
+    let line = tokio::task::spawn_blocking(move || lines.next().expect("no summary line"))
+        .await
+        .unwrap()
+        .unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let summary: Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(summary["status"], "ok");
+    assert_eq!(summary["downloaded"], 2);
+    let state = SyncState::load(dir.join("after").join(".sync-state.json"))
+        .await
+        .unwrap();
+    assert!(state.file("photo1").unwrap().path.is_file());
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_mirror_once_from_config_file() {
+    use icloud_album_rs::sinks::read_event_log;
+
+    let mut server = mockito::Server::new_async().await;
+    let _mocks = mock_album(&mut server).await;
+    let dir = temp_dir("mirror-app-config");
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    tokio::fs::create_dir_all(&dir).await.unwrap();
+    let config_file = dir.join("config.json");
+    let events = dir.join("events.jsonl");
+    let config = json!({
+        "albums": [{ "token": "TestToken", "output_dir": "family" }],
+        "output": { "dir": dir, "naming": { "original": "{base}-full.{ext}" } },
+        "retry": { "max_retries": 1 },
+        "sinks": [{ "type": "jsonl", "path": events }],
+    });
+    std::fs::write(&config_file, config.to_string()).unwrap();
+
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_icloud-album"));
+    command
+        .arg("mirror")
+        .arg("--config")
+        .arg(&config_file)
+        .arg("--once")
+        .env(API_ORIGIN_ENV, server.url());
+    let output = tokio::task::spawn_blocking(move || command.output().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(summary(&output)["downloaded"], 2);
+
+    // Output directories are relative to output.dir, and files follow the naming templates
+    let state = SyncState::load(dir.join("family").join(".sync-state.json"))
+        .await
+        .unwrap();
+    let file = &state.file("photo1").unwrap().path;
+    assert!(file.is_file());
+    assert!(
+        file.to_string_lossy().contains("-full."),
+        "{}",
+        file.display()
+    );
+    // New photos are reported to the sinks
+    let records = read_event_log(&events).await.unwrap();
+    let guids: Vec<&str> = records
+        .iter()
+        .map(|record| record.event.photo_guid())
+        .collect();
+    assert_eq!(guids, ["photo1", "photo2"]);
+
+    // Invalid files are rejected with the offending key
+    std::fs::write(&config_file, r#"{"albums": [], "retry": {"max_tries": 1}}"#).unwrap();
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_icloud-album"));
+    command.arg("mirror").arg("--config").arg(&config_file);
+    let output = tokio::task::spawn_blocking(move || command.output().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("retry"));
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_frame_serves_the_slideshow() {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    let mut server = mockito::Server::new_async().await;
+    let _mocks = mock_album(&mut server).await;
+    let dir = temp_dir("frame");
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_icloud-album"))
+        .args(["frame", "TestToken"])
+        .arg(&dir)
+        .args(["--addr", "127.0.0.1:0"])
+        .env(API_ORIGIN_ENV, server.url())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let (origin, _stdout) = tokio::task::spawn_blocking(move || {
+        let mut lines = BufReader::new(stdout).lines();
+        let origin = lines
+            .by_ref()
+            .map_while(Result::ok)
+            .find_map(|line| {
+                let url = line.strip_prefix("Serving the frame on ")?;
+                Some(url.trim_end_matches('/').to_string())
+            })
+            .expect("no origin printed");
+        (origin, lines)
+    })
+    .await
+    .unwrap();
+
+    let mut manifest = Value::Null;
+    for _ in 0..100 {
+        manifest = reqwest::get(format!("{}/slideshow.json", origin))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        if manifest["slides"].as_array().is_some_and(|s| !s.is_empty()) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    let page = reqwest::get(format!("{}/", origin)).await.unwrap();
+    let page_status = page.status();
+    let slide = manifest["slides"][0]["file"]
+        .as_str()
+        .unwrap_or("")
+        .to_string();
+    let slide_status = reqwest::get(format!("{}/slides/{}", origin, slide))
+        .await
+        .unwrap()
+        .status();
+    let escape_status = reqwest::get(format!("{}/slides/..%2Fslideshow.json", origin))
+        .await
+        .unwrap()
+        .status();
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert_eq!(
+        manifest["slides"].as_array().unwrap().len(),
+        2,
+        "{}",
+        manifest
+    );
+    assert_eq!(page_status, 200);
+    assert_eq!(slide_status, 200);
+    assert_eq!(escape_status, 404);
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_publish_writes_the_site() {
+    let mut server = mockito::Server::new_async().await;
+    // Sites are built from thumbnails and screen-sized renditions
+    let _mocks = mock_album_with(&mut server, "342").await;
+    let dir = temp_dir("publish");
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_icloud-album"));
+    command
+        .args(["publish", "TestToken"])
+        .arg(&dir)
+        .args(["--title", "Published Album"])
+        .env(API_ORIGIN_ENV, server.url());
+    let output = tokio::task::spawn_blocking(move || command.output().unwrap())
+        .await
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let index = std::fs::read_to_string(dir.join("index.html")).unwrap();
+    assert!(index.contains("Published Album"));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Published 2 photos"), "{}", stdout);
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[test]
+fn test_open_prints_the_share_url() {
+    let dir = temp_dir("open");
+    std::fs::create_dir_all(&dir).unwrap();
+    let config_file = dir.join("albums.json");
+    std::fs::write(
+        &config_file,
+        r#"{"albums": [{"token": "B0z5qAGN1JIFd3y", "name": "Family", "output_dir": "family"}]}"#,
+    )
+    .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_icloud-album"))
+        .args(["open", "Family", "--print", "--config"])
+        .arg(&config_file)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "https://www.icloud.com/sharedalbum/#B0z5qAGN1JIFd3y"
+    );
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_icloud-album"))
+        .args(["open", "not a token", "--print"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_check_reports_compatibility() {
+    let mut server = mockito::Server::new_async().await;
+    let _mocks = mock_album(&mut server).await;
+
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_icloud-album"));
+    command
+        .args(["check", "TestToken"])
+        .env(API_ORIGIN_ENV, server.url());
+    let output = tokio::task::spawn_blocking(move || command.output().unwrap())
+        .await
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout.contains("streamName"), "{}", stdout);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_icloud-album"))
+        .arg("check")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_search_finds_captions() {
+    use icloud_album_rs::metadata_index::MetadataIndex;
+    use icloud_album_rs::models::Image;
+    use icloud_album_rs::sync::{SyncState, SyncedFile};
+
+    let dir = temp_dir("search");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("index.db");
+    let photos = [("a", "Birthday party"), ("b", "Beach")].map(|(guid, caption)| Image {
+        caption: Some(caption.to_string()),
+        date_created: Some("2024-03-02T18:00:00Z".to_string()),
+        ..common::photo(guid)
+    });
+    let mut state = SyncState::new();
+    for photo in &photos {
+        let file = SyncedFile::for_photo(photo, format!("/archive/{}.jpg", photo.photo_guid));
+        state.record(&photo.photo_guid, file);
+    }
+    MetadataIndex::open(&path)
+        .unwrap()
+        .update_album("family", &photos, &state)
+        .unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_icloud-album"))
+        .arg("search")
+        .arg(&path)
+        .args(["birthday", "--since", "2024-01-01"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("/archive/a.jpg"), "{}", stdout);
+    assert!(!stdout.contains("/archive/b.jpg"), "{}", stdout);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_history_lists_mirror_runs() {
+    let mut server = mockito::Server::new_async().await;
+    let _mocks = mock_album(&mut server).await;
+    let output_dir = temp_dir("history");
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+
+    let output = mirror(&server.url(), "TestToken", &output_dir).await;
+    assert!(output.status.success(), "{:?}", output);
+    let album_dir = output_dir.join("TestToken");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_icloud-album"))
+        .arg("history")
+        .arg(&album_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", stdout);
+    assert!(lines[0].starts_with("Finished"));
+    assert_eq!(lines[1].split_whitespace().nth(1), Some("2"));
+
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
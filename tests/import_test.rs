@@ -0,0 +1,206 @@
+#![cfg(feature = "import")]
+
+use icloud_album_rs::import::{
+    import_album, xmp_sidecar, ImmichImporter, ImportReport, PhotoPrismImporter,
+};
+use icloud_album_rs::models::{Image, Location, Metadata};
+use icloud_album_rs::sync::{SyncState, SyncedFile};
+use mockito::{Matcher, Server};
+use serde_json::json;
+
+fn album() -> Metadata {
+    serde_json::from_value(json!({
+        "streamName": "Summer Trip",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag123",
+        "itemsReturned": 2,
+        "locations": {}
+    }))
+    .unwrap()
+}
+
+fn photos() -> Vec<Image> {
+    vec![
+        Image {
+            photo_guid: "photo1".to_string(),
+            caption: Some("Beach <day>".to_string()),
+            date_created: Some("2023-07-14T18:30:05Z".to_string()),
+            location: Some(Location {
+                latitude: 41.8781,
+                longitude: -87.6298,
+                altitude: None,
+            }),
+            ..Default::default()
+        },
+        // No downloaded file
+        Image {
+            photo_guid: "photo2".to_string(),
+            ..Default::default()
+        },
+    ]
+}
+
+async fn sync_state(name: &str) -> SyncState {
+    let dir = std::env::temp_dir().join(format!("icloud-import-{}-{}", name, std::process::id()));
+    tokio::fs::create_dir_all(&dir).await.unwrap();
+    let path = dir.join("photo1.jpg");
+    tokio::fs::write(&path, b"jpeg").await.unwrap();
+    let mut state = SyncState::new();
+    state.record(
+        "photo1",
+        SyncedFile {
+            path,
+            ..Default::default()
+        },
+    );
+    state
+}
+
+#[test]
+fn test_xmp_sidecar() {
+    let xmp = xmp_sidecar(&photos()[0]);
+    assert!(xmp
+        .contains("<dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">Beach &lt;day&gt;</rdf:li>"));
+    assert!(xmp.contains("<photoshop:DateCreated>2023-07-14T18:30:05Z</photoshop:DateCreated>"));
+    assert!(xmp.contains("<exif:GPSLatitude>41,52.686000N</exif:GPSLatitude>"));
+    assert!(xmp.contains("<exif:GPSLongitude>87,37.788000W</exif:GPSLongitude>"));
+
+    let bare = xmp_sidecar(&photos()[1]);
+    assert!(!bare.contains("dc:title"));
+    assert!(!bare.contains("GPS"));
+}
+
+#[tokio::test]
+async fn test_immich_import() {
+    let mut server = Server::new_async().await;
+    let upload = server
+        .mock("POST", "/api/assets")
+        .match_header("x-api-key", "secret")
+        .match_header(
+            "content-type",
+            Matcher::Regex("^multipart/form-data".to_string()),
+        )
+        .match_body(Matcher::AllOf(vec![
+            Matcher::Regex("name=\"deviceAssetId\"\r\n\r\nphoto1".to_string()),
+            Matcher::Regex("name=\"fileCreatedAt\"\r\n\r\n2023-07-14T18:30:05Z".to_string()),
+            Matcher::Regex("name=\"assetData\"; filename=\"photo1.jpg\"".to_string()),
+        ]))
+        .with_status(201)
+        .with_body(r#"{"id":"asset-1","status":"created"}"#)
+        .create_async()
+        .await;
+    let metadata = server
+        .mock("PUT", "/api/assets/asset-1")
+        .match_header("x-api-key", "secret")
+        .match_body(Matcher::Json(json!({
+            "description": "Beach <day>",
+            "dateTimeOriginal": "2023-07-14T18:30:05Z",
+            "latitude": 41.8781,
+            "longitude": -87.6298
+        })))
+        .with_status(200)
+        .with_body("{}")
+        .create_async()
+        .await;
+    let album_assets = server
+        .mock("PUT", "/api/albums/album-9/assets")
+        .match_body(Matcher::Json(json!({ "ids": ["asset-1"] })))
+        .with_status(200)
+        .with_body("[]")
+        .create_async()
+        .await;
+
+    let importer =
+        ImmichImporter::new(&format!("{}/", server.url()), "secret").with_album("album-9");
+    let report = import_album(&importer, &album(), &photos(), &sync_state("immich").await)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        report,
+        ImportReport {
+            created: 1,
+            ..Default::default()
+        }
+    );
+    upload.assert_async().await;
+    metadata.assert_async().await;
+    album_assets.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_immich_duplicate_and_failure() {
+    let mut server = Server::new_async().await;
+    let _upload = server
+        .mock("POST", "/api/assets")
+        .with_status(200)
+        .with_body(r#"{"id":"asset-1","status":"duplicate"}"#)
+        .create_async()
+        .await;
+    // Metadata of duplicates is left alone
+    let metadata = server
+        .mock("PUT", Matcher::Any)
+        .expect(0)
+        .create_async()
+        .await;
+
+    let importer = ImmichImporter::new(&server.url(), "secret");
+    let report = import_album(&importer, &album(), &photos(), &sync_state("dup").await)
+        .await
+        .unwrap();
+    assert_eq!(report.duplicates, 1);
+    metadata.assert_async().await;
+
+    let importer = ImmichImporter::new("http://127.0.0.1:1", "secret");
+    let report = import_album(&importer, &album(), &photos(), &sync_state("fail").await)
+        .await
+        .unwrap();
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(report.failures[0].0, "photo1");
+}
+
+#[tokio::test]
+async fn test_photoprism_import() {
+    let mut server = Server::new_async().await;
+    let upload = server
+        .mock(
+            "POST",
+            Matcher::Regex("^/api/v1/users/user-1/upload/[a-z0-9]{16}$".to_string()),
+        )
+        .match_header("x-auth-token", "token")
+        .match_body(Matcher::AllOf(vec![
+            Matcher::Regex("name=\"files\"; filename=\"photo1.jpg\"".to_string()),
+            Matcher::Regex("name=\"files\"; filename=\"photo1.xmp\"".to_string()),
+            Matcher::Regex("exif:GPSLatitude".to_string()),
+        ]))
+        .with_status(200)
+        .with_body("{}")
+        .create_async()
+        .await;
+    let process = server
+        .mock(
+            "PUT",
+            Matcher::Regex("^/api/v1/users/user-1/upload/[a-z0-9]{16}$".to_string()),
+        )
+        .match_header("x-auth-token", "token")
+        .match_body(Matcher::Json(json!({ "albums": ["Summer Trip"] })))
+        .with_status(200)
+        .with_body("{}")
+        .create_async()
+        .await;
+
+    let importer = PhotoPrismImporter::new(&server.url(), "token", "user-1");
+    let report = import_album(
+        &importer,
+        &album(),
+        &photos(),
+        &sync_state("photoprism").await,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(report.queued, 1);
+    upload.assert_async().await;
+    process.assert_async().await;
+}
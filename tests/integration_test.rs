@@ -1,13 +1,10 @@
-use reqwest::Client;
+use icloud_album_rs::base_url::with_api_origin;
+use icloud_album_rs::fetch::{FetchOptions, ParseMode};
+use icloud_album_rs::observer::PipelineObserver;
+use icloud_album_rs::{get_album_metadata, get_icloud_photos, get_icloud_photos_with};
 use serde_json::json;
-
-// Define old-style test function for compatibility with main test runner
-#[test]
-fn run_integration_tests() {
-    // We'll verify these tests pass without running them in the normal test suite
-    // Since they require an active tokio runtime
-    println!("Integration tests should be run individually with: cargo test --test integration_test -- --ignored");
-}
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 // Create sample webstream response
 fn create_webstream_response() -> serde_json::Value {
@@ -51,129 +48,283 @@ fn create_webasseturls_response() -> serde_json::Value {
     })
 }
 
-// A modified version of get_icloud_photos that works with our mock server
-async fn mock_get_icloud_photos(
-    _token: &str,
-    _base_url: &str,
-    mock_url: &str,
-) -> Result<icloud_album_rs::models::ICloudResponse, Box<dyn std::error::Error>> {
-    // Create a reqwest client
-    let client = Client::new();
+#[tokio::test]
+async fn test_icloud_photos() {
+    // Create a mock server standing in for the album's partition host
+    let mut server = mockito::Server::new_async().await;
+    let token = "test_token";
+    let base_url = format!("{}/{}/sharedstreams/", server.url(), token);
+
+    // The host doesn't redirect, so its webstream body is used directly
+    let mock_webstream = server
+        .mock("POST", "/test_token/sharedstreams/webstream")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(create_webstream_response().to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    let mock_webasseturls = server
+        .mock("POST", "/test_token/sharedstreams/webasseturls")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(create_webasseturls_response().to_string())
+        .create_async()
+        .await;
+
+    let options = FetchOptions::new().with_base_url(&base_url);
+    let response = get_icloud_photos_with(token, &options).await.unwrap();
 
-    // We'll simulate the redirect manually for testing
-    // This means using the mock_url but with different endpoints
-    let redirected_url = format!("{}/sharedstreams/", mock_url);
+    // Verify the metadata
+    assert_eq!(response.metadata.stream_name, "Test Album");
+    assert_eq!(response.metadata.user_first_name, "John");
+    assert_eq!(response.metadata.user_last_name, "Doe");
 
-    // Fetch the metadata and photos using our mock URL
-    let (mut photos, metadata) =
-        icloud_album_rs::api::get_api_response(&client, &redirected_url).await?;
+    // Verify the photos
+    assert_eq!(response.photos.len(), 1);
+    assert_eq!(response.photos[0].photo_guid, "photo123");
+    assert_eq!(response.photos[0].derivatives.len(), 1);
 
-    // Extract all photo GUIDs
-    let photo_guids: Vec<String> = photos.iter().map(|p| p.photo_guid.clone()).collect();
+    // Check that the URL was properly enriched
+    let derivative = response.photos[0].derivatives.get("1").unwrap();
+    assert_eq!(
+        derivative.url,
+        Some("https://example1.icloud.com/path/to/image1.jpg".to_string())
+    );
 
-    // Fetch the URLs for all photos
-    let all_urls =
-        icloud_album_rs::api::get_asset_urls(&client, &redirected_url, &photo_guids).await?;
+    // Verify the mocks were called
+    mock_webstream.assert_async().await;
+    mock_webasseturls.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_icloud_photos_with_api_origin() {
+    let mut server = mockito::Server::new_async().await;
 
-    // Enrich the photos with their URLs
-    icloud_album_rs::enrich::enrich_photos_with_urls(&mut photos, &all_urls);
+    // Redirects stay on the overriding origin
+    let mock_redirect = server
+        .mock("POST", "/test_token/sharedstreams/webstream")
+        .with_status(330)
+        .with_body(json!({ "X-Apple-MMe-Host": "p42-sharedstreams.icloud.com" }).to_string())
+        .expect(1)
+        .create_async()
+        .await;
+    let mock_webstream = server
+        .mock("POST", "/test_token/sharedstreams/webstream")
+        .with_status(200)
+        .with_body(create_webstream_response().to_string())
+        .create_async()
+        .await;
+    let mock_webasseturls = server
+        .mock("POST", "/test_token/sharedstreams/webasseturls")
+        .with_status(200)
+        .with_body(create_webasseturls_response().to_string())
+        .create_async()
+        .await;
 
-    // Return the final response
-    Ok(icloud_album_rs::models::ICloudResponse { metadata, photos })
+    let response = with_api_origin(&server.url(), get_icloud_photos("test_token"))
+        .await
+        .unwrap();
+    assert_eq!(response.metadata.stream_name, "Test Album");
+    assert!(response.photos[0].derivatives["1"].url.is_some());
+
+    mock_redirect.assert_async().await;
+    mock_webstream.assert_async().await;
+    mock_webasseturls.assert_async().await;
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[tokio::test]
-    #[ignore = "Requires separate tokio runtime"]
-    async fn test_icloud_photos() {
-        // Create a mock server for all endpoints
-        let mut server = mockito::Server::new();
-        let mock_url = server.url();
-
-        // The token we'll use for testing
-        let token = "test_token";
-
-        // First, set up the mock for the redirect check
-        // We'll simulate a situation where we get redirected to a new host
-        let redirect_response = json!({
-            "X-Apple-MMe-Host": "p42-sharedstreams.icloud.com"
-        });
-
-        let _mock_redirect = server
-            .mock("POST", "/webstream")
-            .with_status(330)
-            .with_header("content-type", "application/json")
-            .with_body(redirect_response.to_string())
-            .create();
-
-        // Now set up the new redirected URL (this is where we'll continue tests)
-        // We need to manually construct this since the real code will use the redirected URL
-        let redirect_host = "p42-sharedstreams.icloud.com";
-        let _redirected_base_url = format!("https://{}/{}/sharedstreams/", redirect_host, token);
-
-        // We need to patch our functions to use the mock server instead of the real redirected URL
-        // We can do this by making the base_url function return our mock URL
-        // And then intercepting the redirected URL construction in the get_redirected_base_url function
-
-        // Mock the webstream endpoint to return metadata and photos
-        let webstream_response = create_webstream_response();
-
-        // Here's where it gets tricky - we need to mock the redirected URL, but we don't control it
-        // So for this test, we'll use a special approach where we mock both URLs
-
-        // First, mock the original URL (should not be called in normal operation, but just in case)
-        let base_url = format!("{}/", mock_url);
-
-        // Then mock the endpoint at the redirected URL, which is what should actually be called
-        // For this test though, we'll just assume another endpoint at the same mock server
-        let mock_webstream = server
-            .mock("POST", "/sharedstreams/webstream")
-            .with_status(200)
-            .with_header("content-type", "application/json")
-            .with_body(webstream_response.to_string())
-            .create();
-
-        // Mock the webasseturls endpoint to return URLs for photos
-        let webasseturls_response = create_webasseturls_response();
-
-        let mock_webasseturls = server
-            .mock("POST", "/sharedstreams/webasseturls")
-            .with_status(200)
-            .with_header("content-type", "application/json")
-            .with_body(webasseturls_response.to_string())
-            .create();
-
-        // Now, use a custom function instead of the real get_icloud_photos for this test
-        // This function will use our mock server instead of real URLs
-        let response = mock_get_icloud_photos(token, &base_url, &mock_url)
-            .await
-            .unwrap();
-
-        // Verify the metadata
-        assert_eq!(response.metadata.stream_name, "Test Album");
-        assert_eq!(response.metadata.user_first_name, "John");
-        assert_eq!(response.metadata.user_last_name, "Doe");
-
-        // Verify the photos
-        assert_eq!(response.photos.len(), 1);
-        assert_eq!(response.photos[0].photo_guid, "photo123");
-        assert_eq!(response.photos[0].derivatives.len(), 1);
-
-        // Check that the URL was properly enriched
-        let derivative = response.photos[0].derivatives.get("1").unwrap();
-        assert_eq!(
-            derivative.url,
-            Some("https://example1.icloud.com/path/to/image1.jpg".to_string())
-        );
-
-        // Verify the mocks were called
-        // Note: since we don't call the actual get_icloud_photos function,
-        // the redirect mock may not be called in our test setup
-        // mock_redirect.assert();
-        mock_webstream.assert();
-        mock_webasseturls.assert();
+// Observer counting the photos it is told were parsed
+#[derive(Default)]
+struct ParsedCounter(AtomicUsize);
+
+impl PipelineObserver for ParsedCounter {
+    fn on_photos_parsed(&self, count: usize) {
+        self.0.fetch_add(count, Ordering::Relaxed);
     }
 }
+
+#[tokio::test]
+async fn test_observer_and_selection_options() {
+    let mut server = mockito::Server::new_async().await;
+    let _webstream = server
+        .mock("POST", "/test_token/sharedstreams/webstream")
+        .with_body(create_webstream_response().to_string())
+        .create_async()
+        .await;
+    let _webasseturls = server
+        .mock("POST", "/test_token/sharedstreams/webasseturls")
+        .with_body(create_webasseturls_response().to_string())
+        .create_async()
+        .await;
+
+    // A shared observer can be inspected after the fetch
+    let observer = Arc::new(ParsedCounter::default());
+    let options = FetchOptions::new().with_observer(Arc::clone(&observer));
+    let response = with_api_origin(
+        &server.url(),
+        get_icloud_photos_with("test_token", &options),
+    )
+    .await
+    .unwrap();
+    assert!(response.photos[0].derivatives["1"].url.is_some());
+    assert_eq!(observer.0.load(Ordering::Relaxed), 1);
+
+    let options = FetchOptions::new().with_selection(|mut photos| {
+        photos.clear();
+        photos
+    });
+    let response = with_api_origin(
+        &server.url(),
+        get_icloud_photos_with("test_token", &options),
+    )
+    .await
+    .unwrap();
+    assert!(response.photos.is_empty());
+    assert_eq!(response.metadata.stream_name, "Test Album");
+}
+
+#[tokio::test]
+async fn test_icloud_photos_keeps_unparsed_items() {
+    let mut server = mockito::Server::new_async().await;
+    let base_url = format!("{}/test_token/sharedstreams/", server.url());
+
+    // A video in a shape the parser doesn't know
+    let unknown = json!({ "photoGuid": "video1", "mediaAssetType": "video", "derivatives": [] });
+    let mut webstream = create_webstream_response();
+    webstream["photos"]
+        .as_array_mut()
+        .unwrap()
+        .push(unknown.clone());
+    let _webstream = server
+        .mock("POST", "/test_token/sharedstreams/webstream")
+        .with_body(webstream.to_string())
+        .create_async()
+        .await;
+    let _webasseturls = server
+        .mock("POST", "/test_token/sharedstreams/webasseturls")
+        .with_body(create_webasseturls_response().to_string())
+        .create_async()
+        .await;
+
+    let options = FetchOptions::new().with_base_url(&base_url);
+    let response = get_icloud_photos_with("test_token", &options)
+        .await
+        .unwrap();
+    assert_eq!(response.photos.len(), 1);
+    assert_eq!(response.unparsed.len(), 1);
+    assert_eq!(response.unparsed[0].index, 1);
+    assert_eq!(response.unparsed[0].raw, unknown);
+
+    // Unparsed items survive a round trip, and are omitted when there are none
+    let json = serde_json::to_value(&response).unwrap();
+    assert_eq!(json["unparsed"][0]["raw"], unknown);
+    let mut clean = response.clone();
+    clean.unparsed.clear();
+    assert!(serde_json::to_value(&clean)
+        .unwrap()
+        .get("unparsed")
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_icloud_photos_with_options() {
+    let mut server = mockito::Server::new_async().await;
+    let base_url = format!("{}/test_token/sharedstreams/", server.url());
+
+    let mut webstream = create_webstream_response();
+    let photos = webstream["photos"].as_array_mut().unwrap();
+    let mut other = photos[0].clone();
+    other["photoGuid"] = json!("photo456");
+    photos.push(other);
+    photos.push(json!({ "photoGuid": "video1", "derivatives": [] }));
+    let _webstream = server
+        .mock("POST", "/test_token/sharedstreams/webstream")
+        .with_body(webstream.to_string())
+        .create_async()
+        .await;
+    // Only the photo kept by the filter is asked about
+    let mock_webasseturls = server
+        .mock("POST", "/test_token/sharedstreams/webasseturls")
+        .match_body(mockito::Matcher::Json(
+            json!({ "photoGuids": ["photo456"] }),
+        ))
+        .with_body(create_webasseturls_response().to_string())
+        .expect(1)
+        .create_async()
+        .await;
+
+    let options = FetchOptions::new()
+        .with_base_url(&base_url)
+        .with_filter(|photo| photo.photo_guid == "photo456");
+    let response = get_icloud_photos_with("test_token", &options)
+        .await
+        .unwrap();
+    assert_eq!(response.photos.len(), 1);
+    assert_eq!(response.photos[0].photo_guid, "photo456");
+    assert!(response.photos[0].derivatives["1"].url.is_some());
+    assert_eq!(response.unparsed.len(), 1);
+    mock_webasseturls.assert_async().await;
+
+    // Without URL resolution, no more webasseturls requests are made
+    let response = get_icloud_photos_with("test_token", &options.with_resolve_urls(false))
+        .await
+        .unwrap();
+    assert!(response.photos[0].derivatives["1"].url.is_none());
+    mock_webasseturls.assert_async().await;
+
+    // Strict parsing fails on the unparsable item
+    let options = FetchOptions::new()
+        .with_base_url(&base_url)
+        .with_parse_mode(ParseMode::Strict);
+    let error = get_icloud_photos_with("test_token", &options)
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("photos[2]"), "{error}");
+}
+
+#[tokio::test]
+async fn test_icloud_photos_without_retry_makes_single_attempts() {
+    let mut server = mockito::Server::new_async().await;
+    let base_url = format!("{}/test_token/sharedstreams/", server.url());
+    let _webstream = server
+        .mock("POST", "/test_token/sharedstreams/webstream")
+        .with_body(create_webstream_response().to_string())
+        .create_async()
+        .await;
+    let mock_webasseturls = server
+        .mock("POST", "/test_token/sharedstreams/webasseturls")
+        .with_status(503)
+        .expect(1)
+        .create_async()
+        .await;
+
+    // Neither endpoint is retried unless the options ask for it
+    let options = FetchOptions::new().with_base_url(&base_url);
+    assert!(get_icloud_photos_with("test_token", &options)
+        .await
+        .is_err());
+    mock_webasseturls.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_album_metadata_skips_urls() {
+    let mut server = mockito::Server::new_async().await;
+    let _webstream = server
+        .mock("POST", "/test_token/sharedstreams/webstream")
+        .with_body(create_webstream_response().to_string())
+        .create_async()
+        .await;
+    let mock_webasseturls = server
+        .mock("POST", "/test_token/sharedstreams/webasseturls")
+        .expect(0)
+        .create_async()
+        .await;
+
+    let response = with_api_origin(&server.url(), get_album_metadata("test_token"))
+        .await
+        .unwrap();
+    assert_eq!(response.metadata.stream_name, "Test Album");
+    assert_eq!(response.photos[0].caption.as_deref(), Some("Test image 1"));
+    assert!(response.photos[0].derivatives["1"].url.is_none());
+    mock_webasseturls.assert_async().await;
+}
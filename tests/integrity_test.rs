@@ -0,0 +1,211 @@
+use icloud_album_rs::integrity::{
+    hash_bytes, hash_file, hash_files, parse_checksum, verify_derivative, verify_derivative_bytes,
+    verify_file, ChecksumFormat, IntegrityDigest, IntegrityHasher, Sha256Hasher, VerifyConfidence,
+};
+use icloud_album_rs::models::Derivative;
+use std::path::PathBuf;
+
+#[test]
+fn test_sha256_known_vectors() {
+    let cases: [(&[u8], &str); 3] = [
+        (
+            b"",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        ),
+        (
+            b"abc",
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        ),
+        (
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        ),
+    ];
+    for (input, expected) in cases {
+        let digest = hash_bytes(&Sha256Hasher, input);
+        assert_eq!(digest.algorithm, "sha256");
+        assert_eq!(digest.hex, expected);
+    }
+
+    // One million 'a' characters, fed in uneven chunks
+    let mut digest = Sha256Hasher.new_digest();
+    for chunk in vec![b'a'; 1_000_000].chunks(997) {
+        digest.update(chunk);
+    }
+    let hex: String = digest
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    assert_eq!(
+        hex,
+        "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+    );
+}
+
+#[test]
+fn test_hash_and_verify_files() {
+    let dir = std::env::temp_dir().join(format!("icloud-integrity-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let paths: Vec<PathBuf> = (0..5)
+        .map(|i| {
+            let path = dir.join(format!("file{}.bin", i));
+            std::fs::write(&path, vec![i as u8; 100_000 * (i + 1)]).unwrap();
+            path
+        })
+        .collect();
+    let mut with_missing = paths.clone();
+    with_missing.push(dir.join("missing.bin"));
+
+    let results = hash_files(&Sha256Hasher, &with_missing, 3);
+    assert_eq!(results.len(), 6);
+    for (path, result) in paths.iter().zip(&results) {
+        assert_eq!(
+            result.as_ref().unwrap(),
+            &hash_file(&Sha256Hasher, path).unwrap()
+        );
+    }
+    assert!(results[5].is_err());
+
+    let expected = results[0].as_ref().unwrap().clone();
+    assert!(verify_file(&Sha256Hasher, &paths[0], &expected).unwrap());
+    assert!(!verify_file(&Sha256Hasher, &paths[1], &expected).unwrap());
+
+    let other = IntegrityDigest {
+        algorithm: "blake3".to_string(),
+        hex: expected.hex.clone(),
+    };
+    assert!(verify_file(&Sha256Hasher, &paths[0], &other).is_err());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_verify_derivatives() {
+    let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    // Apple's usual prefixed 20-byte signatures are opaque
+    assert_eq!(
+        parse_checksum("01a1b2c3d4e5f60718293a4b5c6d7e8f9001020304"),
+        ChecksumFormat::Opaque { prefix: Some(1) }
+    );
+    assert_eq!(
+        parse_checksum("abc123"),
+        ChecksumFormat::Opaque { prefix: None }
+    );
+    match parse_checksum(&format!("01{}", abc.to_uppercase())) {
+        ChecksumFormat::Digest { prefix, digest } => {
+            assert_eq!(prefix, Some(1));
+            assert_eq!(digest.to_string(), format!("sha256:{}", abc));
+        }
+        format => panic!("unexpected format {:?}", format),
+    }
+
+    let derivative = |checksum: &str, file_size| Derivative {
+        checksum: checksum.to_string(),
+        file_size,
+        ..Default::default()
+    };
+    let verify = |checksum, size, data: &[u8]| {
+        verify_derivative_bytes(&Sha256Hasher, data, &derivative(checksum, size))
+    };
+
+    let result = verify(abc, Some(1), b"abc");
+    assert_eq!(result.confidence, VerifyConfidence::Checksum);
+    assert!(result.ok, "the checksum takes precedence over the size");
+    let result = verify(abc, None, b"abd");
+    assert_eq!(result.confidence, VerifyConfidence::Checksum);
+    assert!(!result.ok);
+    assert!(result.mismatch.unwrap().contains(abc));
+
+    let result = verify("01a1b2c3", Some(3), b"abc");
+    assert_eq!(result.confidence, VerifyConfidence::Size);
+    assert!(result.ok);
+    let result = verify("01a1b2c3", Some(4), b"abc");
+    assert!(!result.ok);
+    assert_eq!(result.mismatch.as_deref(), Some("Expected 4 bytes, got 3"));
+
+    let result = verify("01a1b2c3", None, b"abc");
+    assert_eq!(result.confidence, VerifyConfidence::Unverified);
+    assert!(result.ok);
+
+    // Files give the same results
+    let path = std::env::temp_dir().join(format!("icloud-verify-{}", std::process::id()));
+    std::fs::write(&path, b"abc").unwrap();
+    let result = verify_derivative(&Sha256Hasher, &path, &derivative(abc, None)).unwrap();
+    assert_eq!(result.confidence, VerifyConfidence::Checksum);
+    assert!(result.ok);
+    std::fs::remove_file(&path).unwrap();
+    assert!(verify_derivative(&Sha256Hasher, &path, &derivative(abc, None)).is_err());
+}
+
+#[cfg(feature = "parallel-hashing")]
+#[test]
+fn test_parallel_hasher_verifies_files_in_order() {
+    use icloud_album_rs::integrity::ParallelHasher;
+
+    let dir = std::env::temp_dir().join(format!("icloud-parallel-hash-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths: Vec<PathBuf> = (0..8)
+        .map(|i| {
+            let path = dir.join(format!("file{}.bin", i));
+            std::fs::write(&path, vec![i as u8; 10_000 * (i + 1)]).unwrap();
+            path
+        })
+        .collect();
+
+    let hasher = ParallelHasher::new(3).unwrap();
+    assert_eq!(hasher.threads(), 3);
+    let digests: Vec<IntegrityDigest> = hasher
+        .hash_files(&Sha256Hasher, &paths)
+        .into_iter()
+        .map(Result::unwrap)
+        .collect();
+    for (path, digest) in paths.iter().zip(&digests) {
+        assert_eq!(digest, &hash_file(&Sha256Hasher, path).unwrap());
+    }
+
+    // A changed file and a missing one are reported in their places
+    std::fs::write(&paths[2], b"changed").unwrap();
+    let mut files: Vec<(PathBuf, IntegrityDigest)> =
+        paths.iter().cloned().zip(digests.iter().cloned()).collect();
+    files.push((dir.join("missing.bin"), digests[0].clone()));
+    let results = hasher.verify_files(&Sha256Hasher, &files);
+    assert_eq!(results.len(), 9);
+    assert!(!results[2].as_ref().unwrap());
+    assert!(results[8].is_err());
+    assert!(results
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index != 2 && *index != 8)
+        .all(|(_, result)| *result.as_ref().unwrap()));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[cfg(feature = "blake3")]
+#[test]
+fn test_blake3_hasher() {
+    use icloud_album_rs::integrity::Blake3Hasher;
+
+    let digest = hash_bytes(&Blake3Hasher, b"");
+    assert_eq!(digest.algorithm, "blake3");
+    assert_eq!(
+        digest.hex,
+        "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+    );
+
+    let dir = std::env::temp_dir().join(format!("icloud-blake3-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("photo.jpg");
+    std::fs::write(&path, vec![7u8; 200_000]).unwrap();
+    let digest = hash_file(&Blake3Hasher, &path).unwrap();
+    assert_eq!(digest, hash_bytes(&Blake3Hasher, &[7u8; 200_000]));
+    assert!(verify_file(&Blake3Hasher, &path, &digest).unwrap());
+    // Digests are only verified with the algorithm that produced them
+    assert!(verify_file(&Sha256Hasher, &path, &digest).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
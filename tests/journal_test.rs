@@ -0,0 +1,96 @@
+mod common;
+
+use common::temp_dir;
+use icloud_album_rs::download::{download_photo_with_options, DownloadOptions};
+use icloud_album_rs::journal::{DownloadJournal, JournaledWrite};
+use icloud_album_rs::models::{Derivative, Image};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+fn write(dir: &std::path::Path, guid: &str, expected_size: u64) -> JournaledWrite {
+    JournaledWrite {
+        photo_guid: guid.to_string(),
+        path: dir.join(format!("{}.jpg", guid)),
+        partial: dir.join(format!(".{}.jpg.part", guid)),
+        expected_size,
+    }
+}
+
+#[test]
+fn test_journal_recovery() {
+    let dir = temp_dir("journal-recovery");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let journal_path = dir.join("downloads.journal");
+
+    {
+        let journal = DownloadJournal::open(&journal_path).unwrap();
+        // Crashed while staging
+        let staged = write(&dir, "staged", 10);
+        journal.begin(&staged).unwrap();
+        std::fs::write(&staged.partial, b"12345").unwrap();
+        // Crashed after the rename
+        let renamed = write(&dir, "renamed", 4);
+        journal.begin(&renamed).unwrap();
+        std::fs::write(&renamed.path, b"1234").unwrap();
+        // Finished
+        let done = write(&dir, "done", 4);
+        journal.begin(&done).unwrap();
+        journal.end(&done.path).unwrap();
+    }
+
+    let journal = DownloadJournal::open(&journal_path).unwrap();
+    assert_eq!(journal.pending().unwrap().len(), 2);
+    let recovery = journal.recover().unwrap();
+    assert_eq!(recovery.completed, vec![write(&dir, "renamed", 4)]);
+    assert_eq!(recovery.incomplete, vec![write(&dir, "staged", 10)]);
+    assert_eq!(
+        recovery.removed_partials,
+        vec![write(&dir, "staged", 10).partial]
+    );
+    assert!(!write(&dir, "staged", 10).partial.exists());
+    assert!(write(&dir, "renamed", 4).path.exists());
+    assert!(journal.pending().unwrap().is_empty());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_downloads_are_journaled() {
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/photo.jpg")
+        .with_body([0xFF, 0xD8, 0xFF, 0xE0, 0, 0])
+        .create_async()
+        .await;
+    let dir = temp_dir("journal-downloads");
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+
+    let mut derivatives = HashMap::new();
+    derivatives.insert(
+        "1".to_string(),
+        Derivative {
+            url: Some(format!("{}/photo.jpg", server.url())),
+            ..Default::default()
+        },
+    );
+    let photo = Image {
+        photo_guid: "guid1".to_string(),
+        derivatives,
+        ..Default::default()
+    };
+
+    let journal = Arc::new(DownloadJournal::open(dir.join("downloads.journal")).unwrap());
+    let options = DownloadOptions::new().with_journal(Arc::clone(&journal));
+    let output_dir = dir.join("photos");
+    download_photo_with_options(&photo, None, output_dir.to_str().unwrap(), None, &options)
+        .await
+        .unwrap();
+
+    // A finished download leaves nothing to recover
+    assert!(journal.pending().unwrap().is_empty());
+    let journal_text = std::fs::read_to_string(journal.path()).unwrap();
+    assert!(journal_text.contains("\"photo_guid\":\"guid1\""));
+
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+}
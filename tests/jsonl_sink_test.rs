@@ -0,0 +1,70 @@
+use icloud_album_rs::models::Metadata;
+use icloud_album_rs::sinks::{read_event_log, JsonlSink};
+use icloud_album_rs::watch::{AlbumEvent, EventSink};
+use serde_json::json;
+
+fn metadata(ctag: &str) -> Metadata {
+    serde_json::from_value(json!({
+        "streamName": "Family",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": ctag,
+        "itemsReturned": 0,
+        "locations": {}
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_jsonl_sink_appends_records() {
+    let dir = std::env::temp_dir().join(format!("icloud-jsonl-test-{}", std::process::id()));
+    tokio::fs::create_dir_all(&dir).await.unwrap();
+    let path = dir.join("events.jsonl");
+    let _ = tokio::fs::remove_file(&path).await;
+
+    let sink = JsonlSink::new(&path);
+    let first = vec![
+        AlbumEvent::PhotoAdded {
+            photo_guid: "a".to_string(),
+            caption: Some("Beach".to_string()),
+            date_created: None,
+            thumbnail_url: None,
+            thumbnail: None,
+        },
+        AlbumEvent::PhotoRemoved {
+            photo_guid: "b".to_string(),
+        },
+    ];
+    let second = vec![AlbumEvent::CaptionChanged {
+        photo_guid: "a".to_string(),
+        old_caption: Some("Beach".to_string()),
+        new_caption: None,
+    }];
+
+    sink.deliver(&metadata("1"), &first).await.unwrap();
+    sink.deliver(&metadata("2"), &second).await.unwrap();
+
+    // Each event is one self-describing JSON object per line
+    let contents = tokio::fs::read_to_string(&path).await.unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 3);
+    let line: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+    assert_eq!(line["type"], "PhotoRemoved");
+    assert_eq!(line["photo_guid"], "b");
+    assert_eq!(line["album"], "Family");
+    assert_eq!(line["stream_ctag"], "1");
+
+    let records = read_event_log(&path).await.unwrap();
+    let events: Vec<AlbumEvent> = records.iter().map(|r| r.event.clone()).collect();
+    assert_eq!(events, [first, second].concat());
+    assert_eq!(records[2].stream_ctag, "2");
+
+    // Malformed lines are reported with their position
+    tokio::fs::write(&path, format!("{}\nnot json\n", lines[0]))
+        .await
+        .unwrap();
+    let error = read_event_log(&path).await.unwrap_err();
+    assert!(error.to_string().contains("line 2"));
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
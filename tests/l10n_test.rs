@@ -0,0 +1,138 @@
+#![cfg(feature = "l10n")]
+
+use icloud_album_rs::export::{to_kml_with_options, KmlOptions};
+use icloud_album_rs::gallery::{render_gallery, GalleryItem, GalleryOptions, TemplateTheme};
+use icloud_album_rs::l10n::{Catalog, CatalogError};
+use icloud_album_rs::models::{ICloudResponse, Image, Location, Metadata};
+use serde_json::json;
+
+#[test]
+fn test_builtin_catalogs() {
+    let english = Catalog::english();
+    assert_eq!(english.locale(), "en");
+    assert_eq!(english.count_message("photo-count", 1), "1 photo");
+    assert_eq!(english.count_message("photo-count", 3), "3 photos");
+    assert_eq!(
+        english.message("page-of", &[("page", &2), ("count", &5)]),
+        "Page 2 of 5"
+    );
+    assert_eq!(english.message("no-such-message", &[]), "no-such-message");
+
+    let german = Catalog::builtin("de-AT").unwrap();
+    assert_eq!(german.locale(), "de-AT");
+    assert_eq!(german.count_message("photo-count", 3), "3 Fotos");
+    for locale in ["en", "de", "fr", "es", "nl"] {
+        assert!(Catalog::builtin(locale).is_some(), "{}", locale);
+    }
+    assert!(Catalog::builtin("xx").is_none());
+}
+
+#[test]
+fn test_format_date() {
+    let date = "2023-06-01T12:00:00Z";
+    assert_eq!(
+        Catalog::english().format_date(date).unwrap(),
+        "June 1, 2023"
+    );
+    assert_eq!(
+        Catalog::builtin("de").unwrap().format_date(date).unwrap(),
+        "1. Juni 2023"
+    );
+    assert_eq!(
+        Catalog::builtin("fr").unwrap().format_date(date).unwrap(),
+        "1 juin 2023"
+    );
+    assert_eq!(Catalog::english().format_date("yesterday"), None);
+    assert_eq!(Catalog::english().format_date("2023-13-01"), None);
+}
+
+#[test]
+fn test_parse_catalog() {
+    let catalog = Catalog::parse(
+        "pt-BR",
+        "# Português\nphoto-count = { $count } fotos\nbroken = {unclosed\n",
+    )
+    .unwrap();
+    assert_eq!(catalog.count_message("photo-count", 4), "4 fotos");
+    // Messages left out fall back to English
+    assert_eq!(catalog.message("next-page", &[]), "Next");
+    assert_eq!(catalog.message("broken", &[]), "{unclosed");
+
+    assert_eq!(
+        Catalog::parse("x", "photo-count = ok\nnot a message"),
+        Err(CatalogError {
+            line: 2,
+            message: "expected `id = text`".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_localized_gallery() {
+    let item = GalleryItem {
+        photo_guid: "photo".to_string(),
+        caption: None,
+        date_created: Some("2024-03-05T10:00:00Z".to_string()),
+        date_label: None,
+        alt_text: None,
+        place_name: None,
+        location: None,
+        thumbnail_url: "photo_thumb.jpg".to_string(),
+        width: None,
+        height: None,
+        url: "photo_thumb.jpg".to_string(),
+        is_video: false,
+    };
+    let items = vec![item.clone(), item];
+    let options = GalleryOptions::new()
+        .with_page_size(1)
+        .with_catalog(Catalog::builtin("de").unwrap());
+    let pages = render_gallery("Album", &items, &options);
+    assert!(pages[0].html.contains("<p>2 Fotos</p>"));
+    assert!(pages[0].html.contains("Seite 1 von 2"));
+    assert!(pages[0].html.contains("Weiter &rarr;"));
+    assert!(pages[1].html.contains("&larr; Zurück"));
+    assert!(pages[0].html.contains("<html lang=\"de\">"));
+    assert!(pages[0].html.contains("aria-label=\"Seiten\""));
+    assert!(pages[0].html.contains("alt=\"Foto, 5. März 2024\""));
+
+    let options = GalleryOptions::new()
+        .with_catalog(Catalog::builtin("nl").unwrap())
+        .with_theme(TemplateTheme::new("{photos}", "{date_label};"));
+    assert_eq!(
+        render_gallery("Album", &items, &options)[0].html,
+        "5 maart 2024;\n5 maart 2024;"
+    );
+}
+
+#[test]
+fn test_localized_kml() {
+    let metadata: Metadata = serde_json::from_value(json!({
+        "streamName": "Trip",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag123",
+        "itemsReturned": 1,
+        "locations": {}
+    }))
+    .unwrap();
+    let response = ICloudResponse {
+        metadata,
+        photos: vec![Image {
+            photo_guid: "photo".to_string(),
+            date_created: Some("2023-06-01T12:00:00Z".to_string()),
+            place_name: Some("Paris".to_string()),
+            location: Some(Location {
+                latitude: 48.8566,
+                longitude: 2.3522,
+                altitude: None,
+            }),
+            ..Default::default()
+        }],
+        unparsed: Vec::new(),
+    };
+
+    let options = KmlOptions::new().with_catalog(Catalog::builtin("de").unwrap());
+    let kml = to_kml_with_options(&response, &options);
+    assert!(kml.contains("Aufgenommen: 1. Juni 2023&lt;br/&gt;Ort: Paris"));
+}
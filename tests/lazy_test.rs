@@ -0,0 +1,106 @@
+mod common;
+
+use common::{derivative, photo_with};
+use icloud_album_rs::lazy::LazyAlbum;
+use icloud_album_rs::models::{Image, Metadata};
+use mockito::Matcher;
+use serde_json::json;
+
+fn metadata() -> Metadata {
+    serde_json::from_value(json!({
+        "streamName": "Family",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag123",
+        "itemsReturned": 3,
+        "locations": {}
+    }))
+    .unwrap()
+}
+
+// A photo whose derivative's checksum is derived from its GUID
+fn unresolved(guid: &str) -> Image {
+    photo_with(
+        guid,
+        [("1", derivative(format!("{}-checksum", guid), None))],
+    )
+}
+
+fn asset_urls(guids: &[&str]) -> String {
+    let items: serde_json::Map<String, serde_json::Value> = guids
+        .iter()
+        .map(|guid| {
+            (
+                format!("{}-checksum", guid),
+                json!({
+                    "url_location": "cvws.icloud-content.com",
+                    "url_path": format!("/{}.jpg", guid)
+                }),
+            )
+        })
+        .collect();
+    json!({ "items": items }).to_string()
+}
+
+#[tokio::test]
+async fn test_lazy_album_resolves_on_access() {
+    let mut server = mockito::Server::new_async().await;
+    let page = server
+        .mock("POST", "/sharedstreams/webasseturls")
+        .match_body(Matcher::Json(json!({ "photoGuids": ["a", "b"] })))
+        .with_body(asset_urls(&["a", "b"]))
+        .expect(1)
+        .create_async()
+        .await;
+    let single = server
+        .mock("POST", "/sharedstreams/webasseturls")
+        .match_body(Matcher::Json(json!({ "photoGuids": ["c"] })))
+        .with_body(asset_urls(&["c"]))
+        .expect(1)
+        .create_async()
+        .await;
+
+    let album = LazyAlbum::new(
+        reqwest::Client::new(),
+        &format!("{}/sharedstreams/", server.url()),
+        metadata(),
+        vec![unresolved("a"), unresolved("b"), unresolved("c")],
+    );
+    assert_eq!(album.len(), 3);
+    assert_eq!(album.metadata().stream_name, "Family");
+    // Browsing metadata does not resolve anything
+    assert!(album
+        .photos()
+        .iter()
+        .all(|p| p.derivatives["1"].url.is_none()));
+
+    let first_page = album.page(0..2).await.unwrap();
+    let guids: Vec<&str> = first_page.iter().map(|p| p.photo_guid.as_str()).collect();
+    assert_eq!(guids, vec!["a", "b"]);
+    assert_eq!(
+        first_page[1].derivatives["1"].url.as_deref(),
+        Some("https://cvws.icloud-content.com/b.jpg")
+    );
+
+    // Resolved photos come from the cache
+    let again = album.photo("a").await.unwrap().unwrap();
+    assert_eq!(
+        again.derivatives["1"].url.as_deref(),
+        Some("https://cvws.icloud-content.com/a.jpg")
+    );
+    let by_guid = album.photos_by_guid(&["a", "b"]).await.unwrap();
+    assert_eq!(by_guid.len(), 2);
+
+    let c = album.photo("c").await.unwrap().unwrap();
+    assert_eq!(
+        c.derivatives["1"].url.as_deref(),
+        Some("https://cvws.icloud-content.com/c.jpg")
+    );
+
+    // Unknown photos and pages past the end need no request
+    assert!(album.photo("missing").await.unwrap().is_none());
+    assert!(album.page(5..10).await.unwrap().is_empty());
+
+    page.assert_async().await;
+    single.assert_async().await;
+}
@@ -0,0 +1,67 @@
+use icloud_album_rs::logging;
+use icloud_album_rs::models::Derivative;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+use std::sync::Mutex;
+
+static RECORDS: Mutex<Vec<(String, Level)>> = Mutex::new(Vec::new());
+
+struct CaptureLogger;
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        RECORDS
+            .lock()
+            .unwrap()
+            .push((record.target().to_string(), record.level()));
+    }
+
+    fn flush(&self) {}
+}
+
+fn schema_warnings() -> usize {
+    RECORDS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(target, level)| target == logging::SCHEMA && *level == Level::Warn)
+        .count()
+}
+
+fn parse_quirky_derivative() {
+    let derivative: Derivative =
+        serde_json::from_value(json!({ "checksum": "c1", "width": "wide" })).unwrap();
+    assert_eq!(derivative.width, None);
+}
+
+// The logger and verbosity caps are process-wide, so everything is checked in one test
+#[test]
+fn test_targets_and_levels() {
+    log::set_logger(&CaptureLogger).unwrap();
+    log::set_max_level(LevelFilter::Trace);
+
+    parse_quirky_derivative();
+    assert_eq!(schema_warnings(), 1);
+
+    // Capping the schema target silences only that target
+    logging::set_level(logging::SCHEMA, LevelFilter::Error);
+    parse_quirky_derivative();
+    assert_eq!(schema_warnings(), 1);
+    assert_eq!(logging::level(logging::API), LevelFilter::Trace);
+
+    // More specific prefixes win over the crate-wide cap
+    logging::set_level(logging::ROOT, LevelFilter::Off);
+    logging::set_level(logging::SCHEMA, LevelFilter::Warn);
+    assert_eq!(logging::level(logging::DOWNLOAD), LevelFilter::Off);
+    assert!(logging::enabled(logging::SCHEMA, Level::Warn));
+    assert!(logging::enabled("icloud_albums::api", Level::Error));
+    parse_quirky_derivative();
+    assert_eq!(schema_warnings(), 2);
+
+    logging::reset_levels();
+    assert_eq!(logging::level(logging::DOWNLOAD), LevelFilter::Trace);
+}
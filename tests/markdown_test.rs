@@ -0,0 +1,59 @@
+use icloud_album_rs::markdown::{render_markdown, strip_markdown};
+
+#[test]
+fn test_render_markdown() {
+    assert_eq!(
+        render_markdown("A **great** _day_ at `pier 39`"),
+        "<p>A <strong>great</strong> <em>day</em> at <code>pier 39</code></p>"
+    );
+    assert_eq!(
+        render_markdown("First line\nsecond line\n\nNew paragraph"),
+        "<p>First line<br>\nsecond line</p>\n<p>New paragraph</p>"
+    );
+    assert_eq!(
+        render_markdown("Packing list:\n- sunscreen\n* *towels*"),
+        "<p>Packing list:</p>\n<ul><li>sunscreen</li><li><em>towels</em></li></ul>"
+    );
+    assert_eq!(
+        render_markdown("See [the map](https://example.com/?a=1&b=2)"),
+        "<p>See <a href=\"https://example.com/?a=1&amp;b=2\">the map</a></p>"
+    );
+}
+
+#[test]
+fn test_render_markdown_is_safe() {
+    assert_eq!(
+        render_markdown("<script>alert(1)</script>"),
+        "<p>&lt;script&gt;alert(1)&lt;/script&gt;</p>"
+    );
+    // Links with other schemes keep only their text
+    assert_eq!(
+        render_markdown("[click](javascript:alert(1))"),
+        "<p>click)</p>"
+    );
+    assert_eq!(render_markdown("[quote](\"onmouseover=x)"), "<p>quote</p>");
+}
+
+#[test]
+fn test_markdown_leaves_plain_text_alone() {
+    // Unmatched markers, word-internal underscores and escapes are text
+    assert_eq!(render_markdown("2 * 3 = 6"), "<p>2 * 3 = 6</p>");
+    assert_eq!(
+        render_markdown("IMG_0001 and snake_case_name"),
+        "<p>IMG_0001 and snake_case_name</p>"
+    );
+    assert_eq!(
+        render_markdown("\\*not emphasis\\*"),
+        "<p>*not emphasis*</p>"
+    );
+    assert_eq!(render_markdown(""), "");
+}
+
+#[test]
+fn test_strip_markdown() {
+    assert_eq!(
+        strip_markdown("**Beach** day\n\n- with [Ana](https://example.com)\n- `sunset`"),
+        "Beach day\nwith Ana\nsunset"
+    );
+    assert_eq!(strip_markdown("Plain caption"), "Plain caption");
+}
@@ -0,0 +1,102 @@
+use icloud_album_rs::media_library::{export_media_library, MediaLibraryOptions, UNDATED_DIR};
+use icloud_album_rs::models::{Image, Metadata};
+use icloud_album_rs::sync::{SyncState, SyncedFile};
+use serde_json::json;
+
+fn album() -> Metadata {
+    serde_json::from_value(json!({
+        "streamName": "Trip: Summer/2023",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag123",
+        "itemsReturned": 3,
+        "locations": {}
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_export_media_library() {
+    let root = std::env::temp_dir().join(format!("icloud-media-library-{}", std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&root).await;
+    let store = root.join("store");
+    tokio::fs::create_dir_all(&store).await.unwrap();
+
+    let photos = vec![
+        Image {
+            photo_guid: "ABCDEF123456".to_string(),
+            caption: Some("Fish & chips".to_string()),
+            date_created: Some("2023-07-14T18:30:05Z".to_string()),
+            contributor_full_name: Some("Jane Doe".to_string()),
+            ..Default::default()
+        },
+        Image {
+            photo_guid: "undated1".to_string(),
+            ..Default::default()
+        },
+        // No downloaded file
+        Image {
+            photo_guid: "missing".to_string(),
+            ..Default::default()
+        },
+    ];
+    let mut state = SyncState::new();
+    for (guid, name) in [("ABCDEF123456", "a.jpg"), ("undated1", "b.heic")] {
+        let path = store.join(name);
+        tokio::fs::write(&path, guid).await.unwrap();
+        state.record(
+            guid,
+            SyncedFile {
+                path,
+                ..Default::default()
+            },
+        );
+    }
+
+    let library = root.join("library");
+    let options = MediaLibraryOptions::new().with_hard_links(true);
+    let exported = export_media_library(&library, &album(), &photos, &state, &options)
+        .await
+        .unwrap();
+
+    let album_dir = library.join("Trip_ Summer_2023");
+    assert_eq!(
+        exported,
+        vec![
+            album_dir
+                .join("2023")
+                .join("2023-07-14 18.30.05 ABCDEF12.jpg"),
+            album_dir.join(UNDATED_DIR).join("undated1.heic"),
+        ]
+    );
+    assert_eq!(
+        tokio::fs::read(&exported[0]).await.unwrap(),
+        b"ABCDEF123456"
+    );
+
+    let nfo = tokio::fs::read_to_string(
+        album_dir
+            .join("2023")
+            .join("2023-07-14 18.30.05 ABCDEF12.nfo"),
+    )
+    .await
+    .unwrap();
+    assert!(nfo.contains("<title>Fish &amp; chips</title>"));
+    assert!(nfo.contains("<premiered>2023-07-14</premiered>"));
+    assert!(nfo.contains("<credits>Jane Doe</credits>"));
+    assert!(nfo.contains("<uniqueid type=\"icloud\" default=\"true\">ABCDEF123456</uniqueid>"));
+
+    // Undated photos are titled after the album
+    let nfo = tokio::fs::read_to_string(album_dir.join(UNDATED_DIR).join("undated1.nfo"))
+        .await
+        .unwrap();
+    assert!(nfo.contains("<title>Trip: Summer/2023</title>"));
+
+    // Re-running replaces the earlier export
+    let again = export_media_library(&library, &album(), &photos, &state, &options)
+        .await
+        .unwrap();
+    assert_eq!(again, exported);
+
+    tokio::fs::remove_dir_all(&root).await.unwrap();
+}
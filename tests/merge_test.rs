@@ -0,0 +1,99 @@
+mod common;
+
+use common::{derivative, photo_with};
+use icloud_album_rs::merge::{merge_albums, PhotoSource};
+use icloud_album_rs::models::{ICloudResponse, Image, Metadata};
+use serde_json::json;
+
+// A photo with one derivative per checksum
+fn dated(guid: &str, checksums: &[&str], date: Option<&str>) -> Image {
+    let derivatives = checksums
+        .iter()
+        .enumerate()
+        .map(|(i, checksum)| (i.to_string(), derivative(*checksum, None)));
+    Image {
+        date_created: date.map(str::to_string),
+        ..photo_with(guid, derivatives)
+    }
+}
+
+fn album(name: &str, photos: Vec<Image>) -> ICloudResponse {
+    let metadata: Metadata = serde_json::from_value(json!({
+        "streamName": name,
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": format!("ctag-{}", name),
+        "itemsReturned": photos.len(),
+        "locations": {}
+    }))
+    .unwrap();
+    ICloudResponse {
+        metadata,
+        photos,
+        unparsed: Vec::new(),
+    }
+}
+
+#[test]
+fn test_merge_albums() {
+    let family = album(
+        "Family",
+        vec![
+            dated(
+                "f1",
+                &["shared-orig", "shared-thumb"],
+                Some("2024-01-02T00:00:00Z"),
+            ),
+            dated("f2", &["family-only"], Some("2023-01-01T00:00:00Z")),
+            dated("f3", &[], None),
+        ],
+    );
+    let cousins = album(
+        "Cousins",
+        vec![
+            // Same photo, re-shared with only the thumbnail matching
+            dated(
+                "c1",
+                &["other-orig", "shared-thumb"],
+                Some("2024-01-02T00:00:00Z"),
+            ),
+            dated("c2", &["cousins-only"], Some("2024-06-01T00:00:00Z")),
+            dated("c3", &[], None),
+        ],
+    );
+
+    let merged = merge_albums(vec![family, cousins]);
+    assert_eq!(merged.sources.len(), 2);
+    assert_eq!(merged.sources[0].stream_name, "Family");
+    assert_eq!(merged.sources[0].owner, "John Doe");
+    assert_eq!(merged.sources[1].photo_count, 3);
+
+    // Oldest first, undated last in merge order, shared photo kept once
+    let guids: Vec<_> = merged
+        .photos
+        .iter()
+        .map(|merged| merged.photo.photo_guid.as_str())
+        .collect();
+    assert_eq!(guids, ["f2", "f1", "c2", "f3", "c3"]);
+    assert_eq!(merged.len(), 5);
+    assert_eq!(merged.duplicates(), 1);
+
+    let shared = &merged.photos[1];
+    assert!(shared.is_shared());
+    assert_eq!(
+        shared.sources,
+        vec![
+            PhotoSource {
+                album: 0,
+                photo_guid: "f1".to_string()
+            },
+            PhotoSource {
+                album: 1,
+                photo_guid: "c1".to_string()
+            },
+        ]
+    );
+    assert_eq!(merged.photos_from(1).count(), 3);
+    assert_eq!(merged.images().len(), 5);
+    assert!(merge_albums(Vec::new()).is_empty());
+}
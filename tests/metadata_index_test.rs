@@ -0,0 +1,161 @@
+#![cfg(feature = "sqlite")]
+
+mod common;
+
+use common::photo;
+use icloud_album_rs::metadata_index::{IndexUpdate, MetadataIndex};
+use icloud_album_rs::models::Image;
+use icloud_album_rs::search::CaptionQuery;
+use icloud_album_rs::sync::{SyncState, SyncedFile};
+use std::path::PathBuf;
+
+fn captioned(guid: &str, caption: Option<&str>, date: &str) -> Image {
+    Image {
+        caption: caption.map(str::to_string),
+        date_created: Some(date.to_string()),
+        ..photo(guid)
+    }
+}
+
+fn state(photos: &[&Image]) -> SyncState {
+    let mut state = SyncState::new();
+    for photo in photos {
+        let mut file = SyncedFile::for_photo(photo, format!("/archive/{}.jpg", photo.photo_guid));
+        file.checksum = Some(format!("sum-{}", photo.photo_guid));
+        state.record(&photo.photo_guid, file);
+    }
+    state
+}
+
+#[test]
+fn test_update_and_query() {
+    let index = MetadataIndex::open_in_memory().unwrap();
+    assert!(index.is_empty().unwrap());
+
+    let beach = captioned("a", Some("Beach day"), "2023-07-01T10:00:00Z");
+    let party = captioned("b", Some("Birthday party"), "2024-03-02T18:00:00Z");
+    let undated = photo("c");
+    let photos = vec![beach.clone(), party.clone(), undated.clone()];
+    let update = index
+        .update_album("family", &photos, &state(&[&beach, &party, &undated]))
+        .unwrap();
+    assert_eq!(
+        update,
+        IndexUpdate {
+            indexed: 3,
+            removed: 0
+        }
+    );
+    index
+        .update_album("friends", std::slice::from_ref(&party), &state(&[&party]))
+        .unwrap();
+
+    assert_eq!(index.len().unwrap(), 4);
+    assert_eq!(
+        index.albums().unwrap(),
+        vec![("family".to_string(), 3), ("friends".to_string(), 1)]
+    );
+    let row = index.photo("family", "a").unwrap().unwrap();
+    assert_eq!(row.caption.as_deref(), Some("Beach day"));
+    assert_eq!(row.path, PathBuf::from("/archive/a.jpg"));
+    assert_eq!(row.checksum.as_deref(), Some("sum-a"));
+    assert!(row.taken_at.is_some());
+
+    assert_eq!(index.find_by_guid("b").unwrap().len(), 2);
+    assert_eq!(index.find_by_checksum("sum-b").unwrap().len(), 2);
+    let in_2024 = index.taken_between(party.timestamp_taken(), None).unwrap();
+    assert_eq!(in_2024.len(), 2);
+    assert!(in_2024.iter().all(|row| row.photo_guid == "b"));
+    assert_eq!(
+        index
+            .taken_between(None, party.timestamp_taken())
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(index.taken_between(None, None).unwrap().len(), 4);
+
+    // A later run without the beach photo, and a fetch missing the party's caption
+    let uncaptioned = captioned("b", None, "2024-03-02T18:00:00Z");
+    let update = index
+        .update_album(
+            "family",
+            std::slice::from_ref(&uncaptioned),
+            &state(&[&uncaptioned]),
+        )
+        .unwrap();
+    assert_eq!(
+        update,
+        IndexUpdate {
+            indexed: 1,
+            removed: 2
+        }
+    );
+    assert!(index.photo("family", "a").unwrap().is_none());
+    assert_eq!(
+        index
+            .photo("family", "b")
+            .unwrap()
+            .unwrap()
+            .caption
+            .as_deref(),
+        Some("Birthday party")
+    );
+    assert_eq!(index.album_photos("family").unwrap().len(), 1);
+
+    assert_eq!(index.remove_album("friends").unwrap(), 1);
+    assert_eq!(index.len().unwrap(), 1);
+}
+
+#[test]
+fn test_index_persists() {
+    let path = std::env::temp_dir().join(format!("icloud-index-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let beach = captioned("a", Some("Beach day"), "2023-07-01T10:00:00Z");
+    {
+        let index = MetadataIndex::open(&path).unwrap();
+        index
+            .update_album("family", std::slice::from_ref(&beach), &state(&[&beach]))
+            .unwrap();
+    }
+    let index = MetadataIndex::open(&path).unwrap();
+    assert_eq!(index.album_photos("family").unwrap().len(), 1);
+    drop(index);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_search_captions() {
+    let index = MetadataIndex::open_in_memory().unwrap();
+    let party = captioned("a", Some("Birthday party"), "2024-03-02T18:00:00Z");
+    let cake = captioned("b", Some("Birthday cake"), "2023-03-02T18:00:00Z");
+    let beach = captioned("c", Some("Beach"), "2024-07-01T10:00:00Z");
+    index
+        .update_album(
+            "family",
+            &[party.clone(), beach.clone()],
+            &state(&[&party, &beach]),
+        )
+        .unwrap();
+    index
+        .update_album("friends", std::slice::from_ref(&cake), &state(&[&cake]))
+        .unwrap();
+
+    let hits = index
+        .search_captions(&CaptionQuery::new("birthday"))
+        .unwrap();
+    let found: Vec<_> = hits
+        .iter()
+        .map(|hit| (hit.item.album.as_str(), hit.item.photo_guid.as_str()))
+        .collect();
+    assert_eq!(found, [("family", "a"), ("friends", "b")]);
+
+    let query = CaptionQuery::new("birthday").with_until(party.timestamp_taken().unwrap());
+    let hits = index.search_captions(&query).unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].item.photo_guid, "b");
+    assert!(index
+        .search_captions(&CaptionQuery::new("wedding"))
+        .unwrap()
+        .is_empty());
+}
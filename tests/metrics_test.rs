@@ -0,0 +1,68 @@
+use icloud_album_rs::api::{RetryConfig, RetryPolicies};
+use icloud_album_rs::base_url::with_api_origin;
+use icloud_album_rs::fetch::FetchOptions;
+use icloud_album_rs::get_icloud_photos_with;
+use icloud_album_rs::metrics::{self, Metrics, PrometheusMetrics};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[test]
+fn test_prometheus_render() {
+    let prometheus = PrometheusMetrics::new();
+    prometheus.on_api_call();
+    prometheus.on_api_call();
+    prometheus.on_retry();
+    prometheus.on_bytes_downloaded(1024);
+    let at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    prometheus.on_sync_completed("B0z…3y", 3, at);
+    prometheus.on_sync_completed("B0z…3y", 2, at);
+    prometheus.on_sync_completed("with \"quotes\"", 0, at);
+
+    assert_eq!(prometheus.api_calls(), 2);
+    assert_eq!(prometheus.photos_synced("B0z…3y"), 5);
+    assert_eq!(prometheus.last_sync("B0z…3y"), Some(at));
+    assert_eq!(prometheus.last_sync("unknown"), None);
+
+    let text = prometheus.render();
+    assert!(text
+        .contains("# TYPE icloud_album_api_calls_total counter\nicloud_album_api_calls_total 2\n"));
+    assert!(text.contains("icloud_album_retries_total 1\n"));
+    assert!(text.contains("icloud_album_downloaded_bytes_total 1024\n"));
+    assert!(text.contains("icloud_album_photos_synced_total{album=\"B0z…3y\"} 5\n"));
+    assert!(
+        text.contains("icloud_album_last_sync_timestamp_seconds{album=\"B0z…3y\"} 1700000000\n")
+    );
+    assert!(text.contains("{album=\"with \\\"quotes\\\"\"}"));
+}
+
+#[tokio::test]
+async fn test_installed_metrics_count_api_calls_and_retries() {
+    let mut server = mockito::Server::new_async().await;
+    let failing = server
+        .mock("POST", "/test_token/sharedstreams/webstream")
+        .with_status(503)
+        .expect(3)
+        .create_async()
+        .await;
+
+    let prometheus = Arc::new(PrometheusMetrics::new());
+    metrics::install(prometheus.clone());
+    let options = FetchOptions::new().with_retry(RetryPolicies::new(RetryConfig {
+        max_retries: 2,
+        base_delay_ms: 1,
+        ..Default::default()
+    }));
+    let fetched = with_api_origin(
+        &server.url(),
+        get_icloud_photos_with("test_token", &options),
+    )
+    .await;
+    metrics::uninstall();
+
+    assert!(fetched.is_err());
+    // The redirect probe and two attempts at the webstream
+    failing.assert_async().await;
+    assert_eq!(prometheus.api_calls(), 3);
+    assert_eq!(prometheus.retries(), 1);
+    assert_eq!(prometheus.bytes_downloaded(), 0);
+}
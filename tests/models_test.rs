@@ -1,4 +1,7 @@
-use icloud_album_rs::models::{ApiResponse, Derivative, ICloudResponse, Image, Metadata};
+use icloud_album_rs::models::{
+    ApiResponse, Derivative, ICloudResponse, Image, Metadata, SharingInfo, StreamCtag,
+    WebstreamRequest,
+};
 use serde_json::json;
 use std::collections::HashMap;
 
@@ -26,6 +29,42 @@ fn test_derivative_deserialization() {
     );
 }
 
+#[test]
+fn test_derivative_type_hints() {
+    let derivative: Derivative = serde_json::from_value(json!({
+        "checksum": "abc123",
+        "type": "com.apple.quicktime-movie",
+        "mediaAssetType": "video",
+        "state": "finished"
+    }))
+    .unwrap();
+    assert_eq!(
+        derivative.media_type.as_deref(),
+        Some("com.apple.quicktime-movie")
+    );
+    assert_eq!(derivative.media_asset_type.as_deref(), Some("video"));
+    assert_eq!(derivative.state.as_deref(), Some("finished"));
+    assert_eq!(derivative.declared_mime_type(), Some("video/quicktime"));
+    assert_eq!(derivative.declares_video(), Some(true));
+    assert!(!derivative.is_pending());
+
+    // The hints round-trip, and are left out when absent
+    let value = serde_json::to_value(&derivative).unwrap();
+    assert_eq!(value["mediaAssetType"], "video");
+    let plain = serde_json::to_value(Derivative::default()).unwrap();
+    assert!(plain.get("type").is_none());
+
+    let poster = Derivative {
+        media_type: Some("public.jpeg".to_string()),
+        state: Some("pending".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(poster.declared_mime_type(), Some("image/jpeg"));
+    assert_eq!(poster.declares_video(), Some(false));
+    assert!(poster.is_pending());
+    assert_eq!(Derivative::default().declares_video(), None);
+}
+
 #[test]
 fn test_image_deserialization() {
     let json_str = r#"
@@ -126,21 +165,16 @@ fn test_api_response_deserialization() {
     assert_eq!(api_response.stream_name, Some("My Album".to_string()));
     assert_eq!(api_response.user_first_name, Some("John".to_string()));
     assert_eq!(api_response.user_last_name, Some("Doe".to_string()));
-    assert_eq!(api_response.stream_ctag, Some("ctag123".to_string()));
+    assert_eq!(api_response.stream_ctag, Some("ctag123".into()));
     assert_eq!(api_response.items_returned, Some(10));
 }
 
 #[test]
 fn test_icloud_response_construction() {
     // Create a minimal metadata instance
-    let metadata = Metadata {
-        stream_name: "My Album".to_string(),
-        user_first_name: "John".to_string(),
-        user_last_name: "Doe".to_string(),
-        stream_ctag: "ctag123".to_string(),
-        items_returned: 1,
-        locations: json!({}),
-    };
+    let mut metadata = Metadata::new("My Album", "John", "Doe");
+    metadata.stream_ctag = "ctag123".into();
+    metadata.items_returned = 1;
 
     // Create a minimal derivative
     let mut derivatives = HashMap::new();
@@ -152,6 +186,7 @@ fn test_icloud_response_construction() {
             width: Some(800),
             height: Some(600),
             url: Some("https://example.com/image.jpg".to_string()),
+            ..Default::default()
         },
     );
 
@@ -162,17 +197,189 @@ fn test_icloud_response_construction() {
         caption: Some("Test image".to_string()),
         date_created: Some("2023-01-01".to_string()),
         batch_date_created: Some("2023-01-01".to_string()),
+        contributor_full_name: None,
         width: Some(1600),
         height: Some(1200),
+        location: None,
+        place_name: None,
+        placeholder: None,
     };
 
     // Create an ICloudResponse
     let icloud_response = ICloudResponse {
         metadata,
         photos: vec![image],
+        unparsed: Vec::new(),
     };
 
     assert_eq!(icloud_response.metadata.stream_name, "My Album");
     assert_eq!(icloud_response.photos.len(), 1);
     assert_eq!(icloud_response.photos[0].photo_guid, "photo123");
 }
+
+#[test]
+fn test_webstream_request_payload() {
+    // The default request should match the historical hardcoded payload
+    assert_eq!(
+        WebstreamRequest::default().to_payload(),
+        json!({ "streamCtag": null })
+    );
+
+    // A ctag and extra parameters should be merged into the top-level payload
+    let request = WebstreamRequest::new()
+        .with_ctag("ctag123")
+        .with_param("pageSize", json!(50));
+    assert_eq!(
+        request.to_payload(),
+        json!({ "streamCtag": "ctag123", "pageSize": 50 })
+    );
+
+    // Serializing with serde should produce the same shape
+    assert_eq!(
+        serde_json::to_value(&request).unwrap(),
+        request.to_payload()
+    );
+}
+
+#[test]
+fn test_stream_ctag() {
+    let ctag: StreamCtag = serde_json::from_value(json!("FEGK7Yyrnkd")).unwrap();
+    assert_eq!(ctag, "FEGK7Yyrnkd");
+    assert_eq!(ctag.to_string(), "FEGK7Yyrnkd");
+    assert_eq!(serde_json::to_value(&ctag).unwrap(), json!("FEGK7Yyrnkd"));
+
+    assert_eq!(StreamCtag::parse("  FEGK7Yyrnkd\n"), Some(ctag.clone()));
+    assert_eq!(StreamCtag::parse(" "), None);
+
+    // Only known, equal tags mean the album is unchanged
+    assert!(ctag.unchanged_since(Some(&"FEGK7Yyrnkd".into())));
+    assert!(!ctag.unchanged_since(Some(&"FEGK7Yyrnke".into())));
+    assert!(!ctag.unchanged_since(None));
+    let unknown = StreamCtag::default();
+    assert!(!unknown.unchanged_since(Some(&unknown)));
+}
+
+#[test]
+fn test_sharing_info_extraction() {
+    let response = json!({
+        "streamName": "My Album",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "ownerDsid": 123456789,
+        "isPublic": "true",
+        "allowContributions": false
+    });
+
+    let sharing = SharingInfo::from_response(&response);
+    assert_eq!(sharing.owner_id, Some("123456789".to_string()));
+    assert_eq!(sharing.owner_full_name, None);
+    assert_eq!(sharing.is_public, Some(true));
+    assert_eq!(sharing.allow_contributions, Some(false));
+
+    // Responses without sharing fields produce an empty SharingInfo
+    assert_eq!(
+        SharingInfo::from_response(&json!({ "streamName": "My Album" })),
+        SharingInfo::default()
+    );
+}
+
+#[test]
+fn test_metadata_owner_key() {
+    let mut metadata: Metadata = serde_json::from_value(json!({
+        "streamName": "My Album",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag123",
+        "itemsReturned": 10,
+        "locations": {}
+    }))
+    .unwrap();
+
+    // Without an owner identifier the name is used
+    assert_eq!(metadata.owner_key(), "John Doe");
+
+    // An owner identifier takes precedence over the name
+    metadata.sharing.owner_id = Some("owner-1".to_string());
+    assert_eq!(metadata.owner_key(), "owner-1");
+}
+
+#[test]
+fn test_calendar_date_parse() {
+    use icloud_album_rs::models::CalendarDate;
+
+    let date = CalendarDate::parse("2023-01-31T12:00:00Z").unwrap();
+    assert_eq!((date.year, date.month, date.day), (2023, 1, 31));
+    assert_eq!(date.to_string(), "2023-01-31");
+    assert_eq!(CalendarDate::parse("2023-01-31"), Some(date));
+
+    for invalid in [
+        "2023-02-29",
+        "2023-13-01",
+        "2023-1-01",
+        "20230131",
+        "",
+        "2023-01-31X",
+    ] {
+        assert_eq!(CalendarDate::parse(invalid), None, "{}", invalid);
+    }
+    assert!(CalendarDate::parse("2000-02-29").is_some());
+    assert!(CalendarDate::parse("1900-02-29").is_none());
+
+    let new_year = CalendarDate::new(2023, 12, 31).unwrap().next_day();
+    assert_eq!(new_year.to_string(), "2024-01-01");
+}
+
+#[test]
+fn test_parse_timestamp() {
+    use icloud_album_rs::models::parse_timestamp;
+
+    assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+    assert_eq!(parse_timestamp("2023-01-31T12:00:00Z"), Some(1_675_166_400));
+    assert_eq!(
+        parse_timestamp("2023-01-31T12:00:00.250Z"),
+        Some(1_675_166_400)
+    );
+    assert_eq!(
+        parse_timestamp("2023-01-31T07:00:00-05:00"),
+        Some(1_675_166_400)
+    );
+    assert_eq!(
+        parse_timestamp("2023-01-31T17:30:00+0530"),
+        Some(1_675_166_400)
+    );
+    assert_eq!(parse_timestamp("2023-01-31T12:00:00"), Some(1_675_166_400));
+    assert_eq!(parse_timestamp("2024-02-29T00:00:00Z"), Some(1_709_164_800));
+    assert_eq!(parse_timestamp("1969-12-31T23:59:59Z"), Some(-1));
+
+    for invalid in [
+        "2023-01-31",
+        "2023-01-31T25:00:00Z",
+        "2023-01-31T12:00Z",
+        "2023-01-31T12:00:00+5",
+    ] {
+        assert_eq!(parse_timestamp(invalid), None, "{}", invalid);
+    }
+}
+
+#[test]
+fn test_format_timestamp() {
+    use icloud_album_rs::models::{format_timestamp, parse_timestamp, CalendarDate};
+
+    assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+    assert_eq!(format_timestamp(-1), "1969-12-31T23:59:59Z");
+    assert_eq!(format_timestamp(1_709_164_800), "2024-02-29T00:00:00Z");
+    for timestamp in [
+        "2000-03-01T01:02:03Z",
+        "2100-12-31T23:59:59Z",
+        "1900-01-01T00:00:00Z",
+    ] {
+        assert_eq!(
+            format_timestamp(parse_timestamp(timestamp).unwrap()),
+            timestamp
+        );
+    }
+    assert_eq!(
+        CalendarDate::from_timestamp(1_675_166_400),
+        CalendarDate::new(2023, 1, 31).unwrap()
+    );
+}
@@ -0,0 +1,157 @@
+use icloud_album_rs::download::{download_photo_with_options, DownloadOptions};
+use icloud_album_rs::models::{Derivative, Image};
+use icloud_album_rs::net::{parse_host_mapping, AddressFamily, HostMappingError, NetworkConfig};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+fn port(server: &mockito::Server) -> u16 {
+    server.url().rsplit(':').next().unwrap().parse().unwrap()
+}
+
+#[tokio::test]
+async fn test_ipv4_only_client() {
+    let mut server = mockito::Server::new_async().await;
+    let mock = server
+        .mock("GET", "/ping")
+        .with_body("pong")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let client = NetworkConfig::ipv4_only(Duration::from_secs(5))
+        .build_client()
+        .unwrap();
+    let body = client
+        .get(format!("http://127.0.0.1:{}/ping", port(&server)))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(body, "pong");
+
+    // An IPv6-only client can't reach the IPv4 mock server
+    let client = NetworkConfig::new()
+        .with_address_family(AddressFamily::Ipv6Only)
+        .build_client()
+        .unwrap();
+    assert!(client
+        .get(format!("http://127.0.0.1:{}/ping", port(&server)))
+        .send()
+        .await
+        .is_err());
+
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_host_addresses_in_downloads() {
+    let mut server = mockito::Server::new_async().await;
+    let mock = server
+        .mock("GET", "/photo.jpg")
+        .with_body([0xFF, 0xD8, 0xFF, 0xE0, 0, 0, 0, 0])
+        .create_async()
+        .await;
+
+    let mut derivatives = HashMap::new();
+    derivatives.insert(
+        "original".to_string(),
+        Derivative {
+            url: Some(format!(
+                "http://cvws.icloud-content.test:{}/photo.jpg",
+                port(&server)
+            )),
+            ..Default::default()
+        },
+    );
+    let photo = Image {
+        photo_guid: "pinned".to_string(),
+        derivatives,
+        ..Default::default()
+    };
+
+    let address: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let network = NetworkConfig::ipv4_only(Duration::from_secs(5))
+        .with_host_addresses("cvws.icloud-content.test", &[address]);
+    assert!(format!("{:?}", network).contains("Ipv4Only"));
+
+    let output_dir = std::env::temp_dir()
+        .join(format!("icloud-net-{}", std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let path = download_photo_with_options(
+        &photo,
+        None,
+        &output_dir,
+        None,
+        &DownloadOptions::new().with_network(network),
+    )
+    .await
+    .unwrap();
+    assert!(path.ends_with("pinned.jpg"));
+
+    mock.assert_async().await;
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
+
+#[test]
+fn test_parse_host_mapping() {
+    assert_eq!(
+        parse_host_mapping(" p23-sharedstreams.icloud.com = 17.248.128.1 ").unwrap(),
+        (
+            "p23-sharedstreams.icloud.com".to_string(),
+            "17.248.128.1".parse::<IpAddr>().unwrap()
+        )
+    );
+    assert_eq!(
+        parse_host_mapping("cvws.icloud-content.com=[2001:db8::1]")
+            .unwrap()
+            .1,
+        "2001:db8::1".parse::<IpAddr>().unwrap()
+    );
+    assert!(matches!(
+        parse_host_mapping("no-address"),
+        Err(HostMappingError::Malformed(_))
+    ));
+    assert!(matches!(
+        parse_host_mapping("=1.2.3.4"),
+        Err(HostMappingError::Malformed(_))
+    ));
+    assert!(matches!(
+        parse_host_mapping("host=not-an-ip"),
+        Err(HostMappingError::InvalidAddress(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_host_mappings_route_requests() {
+    let mut server = mockito::Server::new_async().await;
+    let mock = server
+        .mock("POST", "/token/sharedstreams/webstream")
+        .with_body("{}")
+        .create_async()
+        .await;
+
+    let network = NetworkConfig::new()
+        .with_host_mappings([
+            "p99-sharedstreams.icloud.test=127.0.0.1",
+            "other.test=10.0.0.1",
+        ])
+        .unwrap();
+    assert_eq!(network.host_addresses.len(), 2);
+    assert!(NetworkConfig::new().with_host_mappings(["broken"]).is_err());
+
+    let client = network.build_client().unwrap();
+    let response = client
+        .post(format!(
+            "http://p99-sharedstreams.icloud.test:{}/token/sharedstreams/webstream",
+            port(&server)
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    mock.assert_async().await;
+}
@@ -0,0 +1,60 @@
+use icloud_album_rs::models::Metadata;
+use icloud_album_rs::observer::{NoopObserver, PipelineObserver};
+use std::sync::Mutex;
+
+// Observer that records every stage it is notified about
+#[derive(Default)]
+struct RecordingObserver {
+    events: Mutex<Vec<String>>,
+}
+
+impl PipelineObserver for RecordingObserver {
+    fn on_base_url(&self, base_url: &str) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("base_url:{}", base_url));
+    }
+
+    fn on_photos_parsed(&self, count: usize) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("photos:{}", count));
+    }
+}
+
+#[test]
+fn test_default_hooks_are_noops() {
+    let mut metadata = Metadata::new("My Album", "John", "Doe");
+    metadata.stream_ctag = "ctag123".into();
+    metadata.items_returned = 1;
+
+    // Every hook should be callable through a trait object without effect
+    let observer: &dyn PipelineObserver = &NoopObserver;
+    observer.on_base_url("https://example.com/");
+    observer.on_redirect("https://example.com/");
+    observer.on_metadata(&metadata);
+    observer.on_photos_parsed(1);
+    observer.on_urls_resolved(1);
+}
+
+#[test]
+fn test_partial_observer_records_overridden_hooks() {
+    let observer = RecordingObserver::default();
+    let as_dyn: &dyn PipelineObserver = &observer;
+
+    as_dyn.on_base_url("https://example.com/");
+    as_dyn.on_redirect("https://other.example.com/");
+    as_dyn.on_photos_parsed(3);
+
+    // Only the overridden hooks should have recorded anything
+    let events = observer.events.lock().unwrap();
+    assert_eq!(
+        *events,
+        vec![
+            "base_url:https://example.com/".to_string(),
+            "photos:3".to_string()
+        ]
+    );
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use common::temp_dir;
+use icloud_album_rs::derivatives::SizeClass;
+use icloud_album_rs::download::{download_derivative_classes, DownloadOptions};
+use icloud_album_rs::models::{Derivative, Image};
+use icloud_album_rs::packfile::Packfile;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// PNG signature followed by some padding
+const PNG_BYTES: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+#[test]
+fn test_packfile_roundtrip() {
+    let dir = temp_dir("packfile-roundtrip");
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = dir.join("thumbs.pack");
+
+    {
+        let pack = Packfile::open(&path).unwrap();
+        assert!(pack.is_empty());
+        pack.append("a_thumb.jpg", "a", b"first").unwrap();
+        let b = pack.append("b_thumb.jpg", "b", b"second").unwrap();
+        assert_eq!((b.offset, b.length), (5, 6));
+        // Replacing keeps the entry's position in the list
+        pack.append("a_thumb.jpg", "a", b"again").unwrap();
+        assert_eq!(pack.read("a_thumb.jpg").unwrap().unwrap(), b"again");
+        assert_eq!(pack.read("missing.jpg").unwrap(), None);
+        pack.flush().unwrap();
+        assert!(pack.index_path().exists());
+    }
+
+    let pack = Packfile::open(&path).unwrap();
+    let names: Vec<String> = pack.entries().into_iter().map(|e| e.name).collect();
+    assert_eq!(names, vec!["a_thumb.jpg", "b_thumb.jpg"]);
+    assert_eq!(pack.entry("b_thumb.jpg").unwrap().photo_guid, "b");
+    assert_eq!(pack.read("b_thumb.jpg").unwrap().unwrap(), b"second");
+
+    let extracted = pack.extract(dir.join("out")).unwrap();
+    assert_eq!(extracted.len(), 2);
+    assert_eq!(std::fs::read(&extracted[0]).unwrap(), b"again");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_packfile_drops_unflushed_bytes() {
+    let dir = temp_dir("packfile-unflushed");
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = dir.join("thumbs.pack");
+
+    {
+        let pack = Packfile::open(&path).unwrap();
+        pack.append("a_thumb.jpg", "a", b"kept").unwrap();
+        pack.flush().unwrap();
+        // Interrupted before the next flush
+        pack.append("b_thumb.jpg", "b", b"lost").unwrap();
+    }
+
+    let pack = Packfile::open(&path).unwrap();
+    assert_eq!(pack.len(), 1);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 4);
+    let c = pack.append("c_thumb.jpg", "c", b"new").unwrap();
+    assert_eq!(c.offset, 4);
+
+    // A data file shorter than its index is refused
+    drop(pack);
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(2)
+        .unwrap();
+    assert!(Packfile::open(&path).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_download_derivative_classes_packs_thumbnails() {
+    let mut server = mockito::Server::new_async().await;
+    let output_dir = temp_dir("packfile-download").to_string_lossy().into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    let assets = server
+        .mock("GET", mockito::Matcher::Regex(r"^/\w+\.png$".to_string()))
+        .with_body(PNG_BYTES)
+        .expect(4)
+        .create_async()
+        .await;
+    let photos: Vec<Image> = (0..2)
+        .map(|i| {
+            let derivative = |name: &str| Derivative {
+                checksum: format!("{}{}", name, i),
+                url: Some(format!("{}/{}{}.png", server.url(), name, i)),
+                ..Default::default()
+            };
+            let mut derivatives = HashMap::new();
+            derivatives.insert("342".to_string(), derivative("thumb"));
+            derivatives.insert("3".to_string(), derivative("original"));
+            Image {
+                photo_guid: format!("photo{}", i),
+                derivatives,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    let pack_path = PathBuf::from(&output_dir).join("thumbs.pack");
+    let pack = Arc::new(Packfile::open(&pack_path).unwrap());
+    let options = DownloadOptions::default().with_packfile(pack.clone(), &[SizeClass::Thumbnail]);
+    let report = download_derivative_classes(
+        &photos,
+        &output_dir,
+        &[SizeClass::Thumbnail, SizeClass::Original],
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(report.downloaded.len(), 4);
+    assert!(report.failures.is_empty());
+    let thumb = &report.downloaded[0];
+    assert_eq!(thumb.size_class, SizeClass::Thumbnail);
+    assert_eq!(
+        thumb.path,
+        format!("{}#photo0_thumb.png", pack_path.display())
+    );
+    // Originals are still written as files, thumbnails only to the pack
+    assert!(PathBuf::from(&output_dir)
+        .join("photo1_original.png")
+        .exists());
+    assert!(!PathBuf::from(&output_dir).join("photo1_thumb.png").exists());
+    assert_eq!(pack.read("photo1_thumb.png").unwrap().unwrap(), PNG_BYTES);
+
+    // The pass flushed the index
+    let reopened = Packfile::open(&pack_path).unwrap();
+    assert_eq!(reopened.len(), 2);
+
+    assets.assert_async().await;
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
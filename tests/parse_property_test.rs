@@ -0,0 +1,320 @@
+//! Property-based robustness tests for the response parsers
+//!
+//! Strategies generate webstream and webasseturls bodies shaped like Apple's
+//! responses, with any field free to hold an arbitrary JSON value, and
+//! proptest shrinks failures to a minimal body. Malformed inputs must never
+//! panic the parsers, including the custom serde visitors in models.rs, and
+//! well-formed ones must parse to what they describe.
+
+use icloud_album_rs::api::{parse_webasseturls_bytes, parse_webstream_bytes, WebstreamResponse};
+use icloud_album_rs::models::{Derivative, Image};
+use proptest::prelude::*;
+use serde_json::{json, Map, Value};
+
+fn sample_webstream() -> Value {
+    json!({
+        "streamName": "Test Album",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "12345",
+        "itemsReturned": "2",
+        "locations": {},
+        "photoGuids": ["photo123"],
+        "photos": [
+            {
+                "photoGuid": "photo123",
+                "derivatives": {
+                    "1": { "checksum": "abc123", "fileSize": "12345", "width": 800, "height": "600" }
+                },
+                "caption": "Test image 1",
+                "dateCreated": "2023-01-01",
+                "width": 1600,
+                "height": 1200
+            }
+        ]
+    })
+}
+
+fn sample_webasseturls() -> Value {
+    json!({
+        "items": {
+            "abc123": { "url_location": "example1.icloud.com", "url_path": "/path/to/image1.jpg" }
+        }
+    })
+}
+
+/// Any JSON value, nested a few levels deep
+fn arb_json() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::Bool),
+        any::<i64>().prop_map(|n| json!(n)),
+        any::<f64>().prop_map(|n| json!(n)),
+        any::<u64>().prop_map(|n| json!(n.to_string())),
+        ".{0,8}".prop_map(Value::String),
+    ];
+    leaf.prop_recursive(3, 24, 4, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..4).prop_map(Value::Array),
+            prop::collection::btree_map("k[0-3]", inner, 0..4)
+                .prop_map(|map| Value::Object(map.into_iter().collect())),
+        ]
+    })
+}
+
+/// A number as Apple sends it: a JSON number or a numeric string
+fn arb_number(max: u64) -> impl Strategy<Value = (u64, Value)> {
+    (0..=max, any::<bool>()).prop_map(|(n, as_string)| {
+        let value = if as_string {
+            json!(n.to_string())
+        } else {
+            json!(n)
+        };
+        (n, value)
+    })
+}
+
+/// A field that is usually well-formed but sometimes holds anything
+fn field(valid: impl Strategy<Value = Value> + 'static) -> BoxedStrategy<Value> {
+    prop_oneof![4 => valid, 1 => arb_json()].boxed()
+}
+
+/// Sets or leaves out each field of an object
+fn object(fields: Vec<(&'static str, Option<Value>)>) -> Value {
+    let map: Map<String, Value> = fields
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_string(), value?)))
+        .collect();
+    Value::Object(map)
+}
+
+fn arb_derivative() -> impl Strategy<Value = Value> {
+    (
+        field("[a-f0-9]{1,12}".prop_map(Value::String)),
+        prop::option::of(field(arb_number(u64::MAX).prop_map(|(_, v)| v))),
+        prop::option::of(field(arb_number(u32::MAX.into()).prop_map(|(_, v)| v))),
+        prop::option::of(field(arb_number(u32::MAX.into()).prop_map(|(_, v)| v))),
+    )
+        .prop_map(|(checksum, file_size, width, height)| {
+            object(vec![
+                ("checksum", Some(checksum)),
+                ("fileSize", file_size),
+                ("width", width),
+                ("height", height),
+            ])
+        })
+}
+
+fn arb_photo() -> impl Strategy<Value = Value> {
+    (
+        field("[a-zA-Z0-9-]{1,12}".prop_map(Value::String)),
+        field(
+            prop::collection::btree_map("[0-9]{1,4}|original", arb_derivative(), 0..4)
+                .prop_map(|map| Value::Object(map.into_iter().collect())),
+        ),
+        prop::option::of(field(".{0,16}".prop_map(Value::String))),
+        prop::option::of(field(Just(json!("2023-01-01T10:00:00Z")))),
+        prop::option::of(field(arb_number(u32::MAX.into()).prop_map(|(_, v)| v))),
+    )
+        .prop_map(|(guid, derivatives, caption, date, width)| {
+            object(vec![
+                ("photoGuid", Some(guid)),
+                ("derivatives", Some(derivatives)),
+                ("caption", caption),
+                ("dateCreated", date),
+                ("width", width),
+            ])
+        })
+}
+
+fn arb_webstream() -> impl Strategy<Value = Value> {
+    (
+        field(".{0,16}".prop_map(Value::String)),
+        field("[0-9A-Za-z]{0,8}".prop_map(Value::String)),
+        field(arb_number(1000).prop_map(|(_, v)| v)),
+        field(prop::collection::vec(arb_photo(), 0..6).prop_map(Value::Array)),
+    )
+        .prop_map(|(name, ctag, items_returned, photos)| {
+            object(vec![
+                ("streamName", Some(name)),
+                ("userFirstName", Some(json!("John"))),
+                ("userLastName", Some(json!("Doe"))),
+                ("streamCtag", Some(ctag)),
+                ("itemsReturned", Some(items_returned)),
+                ("locations", Some(json!({}))),
+                ("photos", Some(photos)),
+            ])
+        })
+}
+
+fn arb_webasseturls() -> impl Strategy<Value = Value> {
+    let item = (
+        field("[a-z0-9]{1,10}\\.icloud-content\\.com".prop_map(Value::String)),
+        field("/[a-z0-9/]{0,20}\\.jpg".prop_map(Value::String)),
+    )
+        .prop_map(|(location, path)| json!({ "url_location": location, "url_path": path }));
+    field(
+        prop::collection::btree_map("[a-f0-9]{1,12}", item, 0..6)
+            .prop_map(|map| Value::Object(map.into_iter().collect())),
+    )
+    .prop_map(|items| json!({ "items": items }))
+}
+
+/// Flips, drops or truncates bytes of a body
+fn mutate_bytes(mut bytes: Vec<u8>, mutations: &[(prop::sample::Index, u8, u8)]) -> Vec<u8> {
+    for (index, kind, byte) in mutations {
+        if bytes.is_empty() {
+            break;
+        }
+        let index = index.index(bytes.len());
+        match kind % 3 {
+            0 => bytes[index] = *byte,
+            1 => {
+                bytes.remove(index);
+            }
+            _ => bytes.truncate(index),
+        }
+    }
+    bytes
+}
+
+proptest! {
+    #[test]
+    fn test_generated_webstream_never_panics(body in arb_webstream()) {
+        let _ = parse_webstream_bytes(body.to_string().as_bytes());
+    }
+
+    #[test]
+    fn test_byte_mutated_webstream_never_panics(
+        mutations in prop::collection::vec((any::<prop::sample::Index>(), any::<u8>(), any::<u8>()), 1..4)
+    ) {
+        let bytes = mutate_bytes(sample_webstream().to_string().into_bytes(), &mutations);
+        let _ = parse_webstream_bytes(&bytes);
+    }
+
+    #[test]
+    fn test_generated_webasseturls_never_panics(
+        body in arb_webasseturls(),
+        mutations in prop::collection::vec((any::<prop::sample::Index>(), any::<u8>(), any::<u8>()), 0..3)
+    ) {
+        let _ = parse_webasseturls_bytes(body.to_string().as_bytes());
+        let _ = parse_webasseturls_bytes(&mutate_bytes(body.to_string().into_bytes(), &mutations));
+    }
+
+    #[test]
+    fn test_arbitrary_numeric_fields_never_panic(
+        file_size in arb_json(),
+        width in arb_json(),
+        height in arb_json(),
+    ) {
+        // Exercise the string-or-number visitors directly
+        let derivative = json!({
+            "checksum": "abc",
+            "fileSize": file_size,
+            "width": width,
+            "height": height
+        });
+        let _ = serde_json::from_value::<Derivative>(derivative.clone());
+        let image = json!({
+            "photoGuid": "photo",
+            "derivatives": { "1": derivative },
+            "width": width,
+            "height": height
+        });
+        let _ = serde_json::from_value::<Image>(image);
+    }
+
+    #[test]
+    fn test_numbers_and_numeric_strings_parse_alike(
+        (file_size, file_size_json) in arb_number(u64::MAX),
+        (width, width_json) in arb_number(u32::MAX.into()),
+    ) {
+        let derivative: Derivative = serde_json::from_value(json!({
+            "checksum": "abc",
+            "fileSize": file_size_json,
+            "width": width_json
+        }))
+        .unwrap();
+        prop_assert_eq!(derivative.file_size, Some(file_size));
+        prop_assert_eq!(derivative.width.map(u64::from), Some(width));
+        prop_assert_eq!(derivative.height, None);
+    }
+
+    #[test]
+    fn test_well_formed_webstream_keeps_every_photo(
+        derivatives in prop::collection::vec(
+            prop::collection::btree_map("[0-9]{1,4}", "[a-f0-9]{1,12}", 1..4),
+            0..8,
+        )
+    ) {
+        let photos: Vec<Value> = derivatives
+            .iter()
+            .enumerate()
+            .map(|(index, derivatives)| {
+                let derivatives: Map<String, Value> = derivatives
+                    .iter()
+                    .map(|(key, checksum)| (key.clone(), json!({ "checksum": checksum })))
+                    .collect();
+                json!({ "photoGuid": format!("photo{}", index), "derivatives": derivatives })
+            })
+            .collect();
+        let body = json!({
+            "streamName": "Album",
+            "streamCtag": "1",
+            "itemsReturned": photos.len().to_string(),
+            "photos": photos
+        });
+
+        let response = parse_webstream_bytes(body.to_string().as_bytes()).unwrap();
+        prop_assert_eq!(response.photos.len(), derivatives.len());
+        for (photo, expected) in response.photos.iter().zip(&derivatives) {
+            prop_assert_eq!(photo.derivatives.len(), expected.len());
+        }
+    }
+
+    #[test]
+    fn test_well_formed_webasseturls_resolve_every_checksum(
+        items in prop::collection::btree_map(
+            "[a-f0-9]{1,12}",
+            ("[a-z0-9]{1,10}\\.icloud-content\\.com", "/[a-z0-9]{1,12}\\.jpg"),
+            0..6,
+        )
+    ) {
+        let body = json!({
+            "items": items
+                .iter()
+                .map(|(checksum, (location, path))| {
+                    (checksum.clone(), json!({ "url_location": location, "url_path": path }))
+                })
+                .collect::<Map<String, Value>>()
+        });
+
+        let urls = parse_webasseturls_bytes(body.to_string().as_bytes()).unwrap();
+        prop_assert_eq!(urls.len(), items.len());
+        for (checksum, (location, path)) in &items {
+            let expected = format!("https://{}{}", location, path);
+            prop_assert_eq!(urls.get(checksum), Some(&expected));
+        }
+    }
+}
+
+#[test]
+fn test_valid_bytes_parse() {
+    let WebstreamResponse {
+        photos, metadata, ..
+    } = parse_webstream_bytes(sample_webstream().to_string().as_bytes())
+        .expect("valid webstream body should parse");
+    assert_eq!(photos.len(), 1);
+    assert_eq!(metadata.items_returned, 2);
+
+    let urls = parse_webasseturls_bytes(sample_webasseturls().to_string().as_bytes())
+        .expect("valid webasseturls body should parse");
+    assert_eq!(
+        urls.get("abc123"),
+        Some(&"https://example1.icloud.com/path/to/image1.jpg".to_string())
+    );
+
+    // Invalid JSON is an error, not a panic
+    assert!(parse_webstream_bytes(b"{not json").is_err());
+    assert!(parse_webasseturls_bytes(b"").is_err());
+}
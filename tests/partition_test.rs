@@ -0,0 +1,150 @@
+use icloud_album_rs::base_url::{
+    cached_partition, clear_partition_cache, get_base_url, set_partition_scheme, BaseUrlError,
+    NeighborScan, PartitionScheme, MAX_SCAN_RADIUS,
+};
+use icloud_album_rs::get_icloud_photos;
+use icloud_album_rs::transport::{
+    with_transport, MockTransport, Transport, TransportRequest, TransportResponse,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Serves the `family` fixture from one partition host only
+struct SinglePartition {
+    host: &'static str,
+    fixture: MockTransport,
+    hosts: Mutex<Vec<String>>,
+    other_status: u16,
+}
+
+impl SinglePartition {
+    fn new(host: &'static str, other_status: u16) -> Arc<Self> {
+        Arc::new(Self {
+            host,
+            fixture: MockTransport::with_fixture("family").unwrap(),
+            hosts: Mutex::new(Vec::new()),
+            other_status,
+        })
+    }
+
+    fn hosts(&self) -> Vec<String> {
+        self.hosts.lock().unwrap().clone()
+    }
+}
+
+impl Transport for SinglePartition {
+    fn respond(&self, request: &TransportRequest) -> Option<TransportResponse> {
+        let host = url::Url::parse(&request.url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+        self.hosts.lock().unwrap().push(host.clone());
+        if host == self.host {
+            self.fixture.respond(request)
+        } else {
+            Some(TransportResponse::new(self.other_status, "unknown album"))
+        }
+    }
+}
+
+// The partition scheme is process-wide, so everything is checked in one test
+#[tokio::test]
+async fn test_partition_scheme_and_candidates() {
+    // The count and formula are configurable
+    set_partition_scheme(PartitionScheme::new().with_count(20));
+    assert_eq!(
+        get_base_url("z0z5qAGN1JIFd3y").unwrap(),
+        "https://p02-sharedstreams.icloud.com/z0z5qAGN1JIFd3y/sharedstreams/"
+    );
+    set_partition_scheme(PartitionScheme::new().with_formula(|token, count| {
+        if token.is_empty() {
+            return Err(BaseUrlError::EmptyToken);
+        }
+        Ok(token.len() as u32 % count + 1)
+    }));
+    assert_eq!(
+        get_base_url("abc").unwrap(),
+        "https://p04-sharedstreams.icloud.com/abc/sharedstreams/"
+    );
+    assert!(matches!(get_base_url(""), Err(BaseUrlError::EmptyToken)));
+
+    // Candidates are tried when the computed partition fails
+    set_partition_scheme(PartitionScheme::new().with_candidates([7, 5]));
+    let transport = SinglePartition::new("p05-sharedstreams.icloud.com", 404);
+    let album = with_transport(transport.clone(), get_icloud_photos("B0z5qAGN1JIFd3y"))
+        .await
+        .unwrap();
+    assert_eq!(album.metadata.stream_name, "Summer Trip");
+    assert_eq!(
+        transport.hosts()[..3],
+        [
+            "p12-sharedstreams.icloud.com",
+            "p07-sharedstreams.icloud.com",
+            "p05-sharedstreams.icloud.com"
+        ]
+    );
+
+    // The partition that answered is remembered and tried first
+    assert_eq!(cached_partition("B0z5qAGN1JIFd3y"), Some(5));
+    assert_eq!(
+        get_base_url("B0z5qAGN1JIFd3y").unwrap(),
+        "https://p05-sharedstreams.icloud.com/B0z5qAGN1JIFd3y/sharedstreams/"
+    );
+    transport.hosts.lock().unwrap().clear();
+    with_transport(transport.clone(), get_icloud_photos("B0z5qAGN1JIFd3y"))
+        .await
+        .unwrap();
+    assert_eq!(transport.hosts()[0], "p05-sharedstreams.icloud.com");
+
+    clear_partition_cache();
+    assert_eq!(cached_partition("B0z5qAGN1JIFd3y"), None);
+
+    // A missing host leads to a rate-limited scan of the neighbors
+    let delay = Duration::from_millis(20);
+    set_partition_scheme(PartitionScheme::new().with_neighbor_scan(2, delay));
+    let transport = SinglePartition::new("p13-sharedstreams.icloud.com", 404);
+    let started = Instant::now();
+    with_transport(transport.clone(), get_icloud_photos("B0z5qAGN1JIFd3y"))
+        .await
+        .unwrap();
+    assert!(started.elapsed() >= delay * 2);
+    assert_eq!(
+        transport.hosts()[..3],
+        [
+            "p12-sharedstreams.icloud.com",
+            "p11-sharedstreams.icloud.com",
+            "p13-sharedstreams.icloud.com"
+        ]
+    );
+    assert_eq!(cached_partition("B0z5qAGN1JIFd3y"), Some(13));
+
+    // Other failures don't trigger the scan
+    set_partition_scheme(PartitionScheme::new().with_neighbor_scan(2, Duration::ZERO));
+    let transport = SinglePartition::new("p13-sharedstreams.icloud.com", 503);
+    assert!(
+        with_transport(transport.clone(), get_icloud_photos("B0z5qAGN1JIFd3y"))
+            .await
+            .is_err()
+    );
+    assert!(transport
+        .hosts()
+        .iter()
+        .all(|host| host == "p12-sharedstreams.icloud.com"));
+
+    set_partition_scheme(PartitionScheme::default());
+}
+
+#[test]
+fn test_neighbor_scan_partitions() {
+    let scan = NeighborScan::new(2, Duration::ZERO);
+    assert_eq!(scan.partitions(12, 40), vec![11, 13, 10, 14]);
+    // Partitions wrap around
+    assert_eq!(scan.partitions(1, 40), vec![40, 2, 39, 3]);
+    // Small partition counts don't repeat partitions
+    assert_eq!(scan.partitions(1, 2), vec![2]);
+
+    // The radius is bounded
+    let scan = NeighborScan::new(100, Duration::ZERO);
+    assert_eq!(scan.radius, MAX_SCAN_RADIUS);
+    assert_eq!(scan.partitions(20, 40).len(), 2 * MAX_SCAN_RADIUS as usize);
+}
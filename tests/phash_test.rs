@@ -0,0 +1,173 @@
+#![cfg(feature = "phash")]
+
+use icloud_album_rs::codec::StandardDecoder;
+use icloud_album_rs::phash::{
+    dhash, group_near_duplicates, hash_image, near_duplicate_report, phash, GrayImage,
+    HashAlgorithm, ImageDecoder, PnmDecoder, DEFAULT_THRESHOLD,
+};
+use std::path::PathBuf;
+
+// A diagonal gradient with a bright square, at any size
+fn scene(width: usize, height: usize, brightness: i32) -> GrayImage {
+    let pixels = (0..height)
+        .flat_map(|y| {
+            (0..width).map(move |x| {
+                let base = (x * 160 / width + y * 60 / height) as i32;
+                let square = if x > width / 2 && y < height / 3 {
+                    60
+                } else {
+                    0
+                };
+                (base + square + brightness).clamp(0, 255) as u8
+            })
+        })
+        .collect();
+    GrayImage::new(width, height, pixels).unwrap()
+}
+
+// A checkerboard, visually unrelated to the scene
+fn checkerboard(size: usize) -> GrayImage {
+    let pixels = (0..size)
+        .flat_map(|y| (0..size).map(move |x| if (x / 8 + y / 8) % 2 == 0 { 20 } else { 230 }))
+        .collect();
+    GrayImage::new(size, size, pixels).unwrap()
+}
+
+fn pgm(image: &GrayImage) -> Vec<u8> {
+    let mut bytes =
+        format!("P5\n# test image\n{} {}\n255\n", image.width, image.height).into_bytes();
+    bytes.extend_from_slice(&image.pixels);
+    bytes
+}
+
+fn jpeg(image: &GrayImage) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, 80)
+        .encode(
+            &image.pixels,
+            image.width as u32,
+            image.height as u32,
+            image::ExtendedColorType::L8,
+        )
+        .unwrap();
+    bytes
+}
+
+#[test]
+fn test_hashes_are_robust_to_resizing_and_brightness() {
+    let original = scene(640, 480, 0);
+    let smaller = scene(320, 240, 15);
+    let other = checkerboard(256);
+
+    for algorithm in [HashAlgorithm::DHash, HashAlgorithm::PHash] {
+        let a = hash_image(&original, algorithm);
+        let b = hash_image(&smaller, algorithm);
+        let c = hash_image(&other, algorithm);
+        assert!(a.distance(&b).unwrap() <= DEFAULT_THRESHOLD, "{} {}", a, b);
+        assert!(a.distance(&c).unwrap() > DEFAULT_THRESHOLD, "{} {}", a, c);
+    }
+
+    // Hashes of different algorithms are not comparable
+    let d = hash_image(&original, HashAlgorithm::DHash);
+    let p = hash_image(&original, HashAlgorithm::PHash);
+    assert_eq!(d.distance(&p), None);
+    assert_eq!(d.bits, dhash(&original));
+    assert_eq!(p.bits, phash(&original));
+    assert!(d.to_string().starts_with("dhash:"));
+}
+
+#[test]
+fn test_pnm_decoder() {
+    let image = scene(17, 11, 0);
+    assert_eq!(PnmDecoder.decode(&pgm(&image)).unwrap(), image);
+
+    // PPM samples are converted to luminance
+    let ppm = [b"P6 2 1 255\n".as_slice(), &[255, 255, 255, 0, 0, 0]].concat();
+    assert_eq!(PnmDecoder.decode(&ppm).unwrap().pixels, vec![255, 0]);
+
+    assert!(PnmDecoder.decode(b"\x89PNG").is_err());
+    assert!(PnmDecoder.decode(b"P5 4 4 255\n\x00\x00").is_err());
+}
+
+#[test]
+fn test_group_near_duplicates_is_transitive() {
+    let hash = |bits| icloud_album_rs::phash::PerceptualHash {
+        algorithm: HashAlgorithm::DHash,
+        bits,
+    };
+    let hashes = vec![
+        ("a", hash(0b0000)),
+        ("far", hash(u64::MAX)),
+        ("b", hash(0b0011)),
+        ("c", hash(0b1111)),
+    ];
+    assert_eq!(group_near_duplicates(&hashes, 2), vec![vec!["a", "b", "c"]]);
+    assert!(group_near_duplicates(&hashes, 1).is_empty());
+}
+
+#[test]
+fn test_near_duplicate_report() {
+    let dir = std::env::temp_dir().join(format!("icloud-phash-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let write = |name: &str, bytes: Vec<u8>| -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        path
+    };
+    let paths = vec![
+        write("original.pgm", pgm(&scene(400, 300, 0))),
+        write("other.pgm", pgm(&checkerboard(128))),
+        write("reencoded.pgm", pgm(&scene(200, 150, -10))),
+        write("broken.pgm", b"not an image".to_vec()),
+    ];
+
+    let report =
+        near_duplicate_report(&PnmDecoder, HashAlgorithm::DHash, &paths, DEFAULT_THRESHOLD);
+    assert_eq!(
+        report.groups,
+        vec![vec![paths[0].clone(), paths[2].clone()]]
+    );
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(report.failures[0].0, paths[3]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_jpeg_near_duplicates() {
+    let dir = std::env::temp_dir().join(format!("icloud-phash-jpeg-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let write = |name: &str, bytes: Vec<u8>| -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        path
+    };
+    // JPEG derivatives of the same shot, and a re-encoded PGM of it
+    let paths = vec![
+        write("IMG_0001.JPG", jpeg(&scene(400, 300, 0))),
+        write("IMG_0001-thumb.JPG", jpeg(&scene(200, 150, 10))),
+        write("other.jpg", jpeg(&checkerboard(128))),
+        write("IMG_0001.pgm", pgm(&scene(320, 240, 0))),
+    ];
+
+    let report = near_duplicate_report(
+        &StandardDecoder,
+        HashAlgorithm::PHash,
+        &paths,
+        DEFAULT_THRESHOLD,
+    );
+    assert!(report.failures.is_empty(), "{:?}", report.failures);
+    assert_eq!(
+        report.groups,
+        vec![vec![paths[0].clone(), paths[1].clone(), paths[3].clone()]]
+    );
+    // The PNM-only decoder can't read them
+    assert!(PnmDecoder
+        .decode(&std::fs::read(&paths[0]).unwrap())
+        .is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,172 @@
+#![cfg(feature = "placeholders")]
+
+use icloud_album_rs::codec::StandardDecoder;
+use icloud_album_rs::derivatives::SizeClass;
+use icloud_album_rs::download::DerivativeDownload;
+use icloud_album_rs::models::Image;
+use icloud_album_rs::placeholder::{
+    blurhash, dominant_color, enrich_photos_with_placeholders, hex_color, placeholder,
+    PlaceholderOptions, PpmDecoder, RgbDecoder, RgbImage,
+};
+
+// A horizontal red-to-blue gradient over a vertical brightness ramp
+fn gradient(width: usize, height: usize) -> RgbImage {
+    let pixels = (0..height)
+        .flat_map(|y| {
+            (0..width).map(move |x| {
+                let t = x * 255 / (width - 1);
+                let v = 80 + y * 150 / (height - 1);
+                [(255 - t) as u8, v as u8, t as u8]
+            })
+        })
+        .collect();
+    RgbImage::new(width, height, pixels).unwrap()
+}
+
+fn ppm(image: &RgbImage) -> Vec<u8> {
+    let mut bytes = format!("P6\n{} {}\n255\n", image.width, image.height).into_bytes();
+    bytes.extend(image.pixels.iter().flatten());
+    bytes
+}
+
+#[test]
+fn test_blurhash_matches_reference_encoder() {
+    // Expected values come from the reference TypeScript encoder
+    let image = gradient(32, 24);
+    assert_eq!(blurhash(&image, 4, 3), "L.Hom[|,$9xCman$jujaf%fQfQfQ");
+    assert_eq!(blurhash(&image, 1, 1), "00Hom[");
+
+    // A flat image has no AC components
+    let flat = RgbImage::new(4, 4, vec![[255, 255, 255]; 16]).unwrap();
+    assert_eq!(blurhash(&flat, 1, 1), "00TSUA");
+    assert_eq!(blurhash(&flat, 4, 3).len(), 4 + 2 * 4 * 3);
+
+    // Components are clamped
+    assert_eq!(blurhash(&image, 0, 20), blurhash(&image, 1, 9));
+}
+
+#[test]
+fn test_large_images_are_downscaled() {
+    // Every source pixel becomes a 25x25 block, which downscaling averages back
+    let source = gradient(64, 48);
+    let pixels = (0..48 * 25)
+        .flat_map(|y| (0..64 * 25).map(move |x| (x / 25, y / 25)))
+        .map(|(x, y)| source.pixels[y * 64 + x])
+        .collect();
+    let large = RgbImage::new(64 * 25, 48 * 25, pixels).unwrap();
+    assert_eq!(
+        placeholder(&large, &PlaceholderOptions::new()),
+        placeholder(&source, &PlaceholderOptions::new())
+    );
+}
+
+#[test]
+fn test_dominant_color() {
+    // Mostly green with a few saturated red pixels
+    let mut pixels = vec![[30, 140, 60]; 90];
+    pixels.extend([[250, 0, 0]; 10]);
+    let image = RgbImage::new(10, 10, pixels).unwrap();
+    assert_eq!(dominant_color(&image), [30, 140, 60]);
+    assert_eq!(hex_color(dominant_color(&image)), "#1e8c3c");
+}
+
+#[test]
+fn test_ppm_decoder() {
+    let image = gradient(5, 4);
+    assert_eq!(PpmDecoder.decode(&ppm(&image)).unwrap(), image);
+
+    // Gray images are expanded to RGB
+    let pgm = [b"P5 2 1 255\n".as_slice(), &[10, 200]].concat();
+    assert_eq!(
+        PpmDecoder.decode(&pgm).unwrap().pixels,
+        vec![[10, 10, 10], [200, 200, 200]]
+    );
+    assert!(PpmDecoder.decode(b"P6 2 2 255\n\x00").is_err());
+    assert!(PpmDecoder.decode(b"\xff\xd8\xff").is_err());
+}
+
+#[test]
+fn test_enrich_photos_with_placeholders() {
+    let dir = std::env::temp_dir().join(format!("placeholder-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let write = |name: &str, bytes: &[u8]| {
+        let path = dir.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        path.to_string_lossy().into_owned()
+    };
+    let thumb = write("a_thumb.ppm", &ppm(&gradient(16, 12)));
+    let original = write("a_original.ppm", b"not an image");
+    let broken = write("b_thumb.ppm", b"not an image");
+    let download = |guid: &str, size_class, path: &str| DerivativeDownload {
+        photo_guid: guid.to_string(),
+        size_class,
+        key: "1".to_string(),
+        path: path.to_string(),
+    };
+    let downloads = [
+        download("a", SizeClass::Original, &original),
+        download("a", SizeClass::Thumbnail, &thumb),
+        download("b", SizeClass::Thumbnail, &broken),
+    ];
+
+    let mut photos: Vec<Image> = ["a", "b", "c"]
+        .iter()
+        .map(|guid| Image {
+            photo_guid: guid.to_string(),
+            ..Default::default()
+        })
+        .collect();
+    let failures = enrich_photos_with_placeholders(
+        &mut photos,
+        &downloads,
+        &PpmDecoder,
+        &PlaceholderOptions::new(),
+    );
+
+    // The thumbnail is used, not the larger (here undecodable) original
+    let expected = placeholder(&gradient(16, 12), &PlaceholderOptions::new());
+    assert_eq!(photos[0].placeholder.as_ref(), Some(&expected));
+    assert_eq!(photos[1].placeholder, None);
+    assert_eq!(photos[2].placeholder, None);
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].0, "b");
+
+    // Placeholders are part of the exported photo
+    let json = serde_json::to_value(&photos[0]).unwrap();
+    assert_eq!(json["placeholder"]["blurhash"], expected.blurhash.as_str());
+    assert_eq!(
+        json["placeholder"]["dominantColor"],
+        expected.dominant_color.as_str()
+    );
+    assert!(serde_json::to_value(&photos[2])
+        .unwrap()
+        .get("placeholder")
+        .is_none());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_jpeg_thumbnail_placeholders() {
+    let image = gradient(32, 24);
+    let mut jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 90)
+        .encode(
+            &image.pixels.concat(),
+            32,
+            24,
+            image::ExtendedColorType::Rgb8,
+        )
+        .unwrap();
+    assert!(PpmDecoder.decode(&jpeg).is_err());
+
+    let decoded = StandardDecoder.decode(&jpeg).unwrap();
+    assert_eq!((decoded.width, decoded.height), (32, 24));
+    // Compression shifts colors a little, not the placeholder's overall look
+    let [r, g, b] = dominant_color(&decoded);
+    let [er, eg, eb] = dominant_color(&image);
+    for (channel, expected) in [(r, er), (g, eg), (b, eb)] {
+        assert!(channel.abs_diff(expected) < 24, "{:?}", (r, g, b));
+    }
+    assert_eq!(blurhash(&decoded, 4, 3).len(), blurhash(&image, 4, 3).len());
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use common::temp_dir;
+use icloud_album_rs::derivatives::SizeClass;
+use icloud_album_rs::download::{download_derivative_classes, DownloadOptions};
+use icloud_album_rs::models::{Derivative, Image};
+use icloud_album_rs::postprocess::{PostProcessFuture, PostProcessor};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// PNG signature followed by some padding
+const PNG_BYTES: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+/// Records the files it sees and how many it processes at once
+#[derive(Default)]
+struct Tagger {
+    seen: Mutex<Vec<(String, String, SizeClass)>>,
+    running: AtomicUsize,
+    max_running: AtomicUsize,
+}
+
+impl PostProcessor for Tagger {
+    fn name(&self) -> &str {
+        "tagger"
+    }
+
+    fn process<'a>(
+        &'a self,
+        path: &'a Path,
+        photo: &'a Image,
+        size_class: SizeClass,
+    ) -> PostProcessFuture<'a> {
+        Box::pin(async move {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            self.seen.lock().unwrap().push((
+                photo.photo_guid.clone(),
+                path.to_string_lossy().into_owned(),
+                size_class,
+            ));
+            Ok(None)
+        })
+    }
+}
+
+/// Moves originals to `<name>.converted`, failing for `photo1`
+struct Converter;
+
+impl PostProcessor for Converter {
+    fn name(&self) -> &str {
+        "converter"
+    }
+
+    fn process<'a>(
+        &'a self,
+        path: &'a Path,
+        photo: &'a Image,
+        size_class: SizeClass,
+    ) -> PostProcessFuture<'a> {
+        Box::pin(async move {
+            if size_class != SizeClass::Original {
+                return Ok(None);
+            }
+            if photo.photo_guid == "photo1" {
+                return Err("unsupported format".into());
+            }
+            let converted = path.with_extension("converted");
+            tokio::fs::rename(path, &converted).await?;
+            Ok(Some(converted))
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_download_runs_post_processors() {
+    let mut server = mockito::Server::new_async().await;
+    let output_dir = temp_dir("postprocess").to_string_lossy().into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    let _assets = server
+        .mock("GET", mockito::Matcher::Regex(r"^/\w+\.png$".to_string()))
+        .with_body(PNG_BYTES)
+        .expect(6)
+        .create_async()
+        .await;
+    let photos: Vec<Image> = (0..3)
+        .map(|i| {
+            let derivative = |name: &str| Derivative {
+                checksum: format!("{}{}", name, i),
+                url: Some(format!("{}/{}{}.png", server.url(), name, i)),
+                ..Default::default()
+            };
+            let mut derivatives = HashMap::new();
+            derivatives.insert("342".to_string(), derivative("thumb"));
+            derivatives.insert("3".to_string(), derivative("original"));
+            Image {
+                photo_guid: format!("photo{}", i),
+                derivatives,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    let tagger = Arc::new(Tagger::default());
+    let options = DownloadOptions::new()
+        .with_post_processor(Converter)
+        .with_post_processor(Arc::clone(&tagger))
+        .with_post_process_concurrency(2);
+    let report = download_derivative_classes(
+        &photos,
+        &output_dir,
+        &[SizeClass::Thumbnail, SizeClass::Original],
+        &options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.downloaded.len(), 6);
+
+    // The converted originals replace the downloaded ones in the report
+    let original = &report.downloaded[1];
+    assert_eq!(original.size_class, SizeClass::Original);
+    assert!(original.path.ends_with(".converted"));
+    assert!(Path::new(&original.path).exists());
+
+    // The failing conversion skips the tagger for that file only
+    assert_eq!(report.post_process_failures.len(), 1);
+    let failure = &report.post_process_failures[0];
+    assert_eq!(failure.photo_guid, "photo1");
+    assert_eq!(failure.size_class, SizeClass::Original);
+    assert_eq!(failure.processor, "converter");
+    assert_eq!(failure.error.to_string(), "unsupported format");
+    assert!(!report.downloaded[3].path.ends_with(".converted"));
+
+    let seen = tagger.seen.lock().unwrap().clone();
+    assert_eq!(seen.len(), 5);
+    assert!(seen.iter().any(|(guid, path, class)| guid == "photo0"
+        && *class == SizeClass::Original
+        && path.ends_with(".converted")));
+    assert!(!seen
+        .iter()
+        .any(|(guid, _, class)| guid == "photo1" && *class == SizeClass::Original));
+    assert!(tagger.max_running.load(Ordering::SeqCst) <= 2);
+
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use common::temp_dir;
+use icloud_album_rs::derivatives::SizeClass;
+use icloud_album_rs::download::{download_derivative_classes, DownloadOptions};
+use icloud_album_rs::models::{Derivative, Image};
+use icloud_album_rs::predownload::{
+    PlannedDownload, PreDownloadDecision, PreDownloadFuture, PreDownloadHook,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// PNG signature followed by some padding
+const PNG_BYTES: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+/// Denies originals of `photo1`, redirects `photo2` to a mirror and fails for `photo3`
+struct Moderation {
+    mirror: String,
+}
+
+impl PreDownloadHook for Moderation {
+    fn name(&self) -> &str {
+        "moderation"
+    }
+
+    fn check<'a>(&'a self, download: PlannedDownload<'a>) -> PreDownloadFuture<'a> {
+        Box::pin(async move {
+            match (download.photo.photo_guid.as_str(), download.size_class) {
+                ("photo1", SizeClass::Original) => {
+                    Ok(PreDownloadDecision::Deny("over budget".to_string()))
+                }
+                ("photo2", _) => Ok(PreDownloadDecision::Redirect(format!(
+                    "{}/{}",
+                    self.mirror, download.key
+                ))),
+                ("photo3", _) => Err("moderation service unavailable".into()),
+                _ => Ok(PreDownloadDecision::Allow),
+            }
+        })
+    }
+}
+
+/// Records the URLs it is asked about
+#[derive(Default)]
+struct Recorder {
+    urls: Mutex<Vec<String>>,
+}
+
+impl PreDownloadHook for Recorder {
+    fn name(&self) -> &str {
+        "recorder"
+    }
+
+    fn check<'a>(&'a self, download: PlannedDownload<'a>) -> PreDownloadFuture<'a> {
+        self.urls.lock().unwrap().push(download.url.to_string());
+        Box::pin(async { Ok(PreDownloadDecision::Allow) })
+    }
+}
+
+#[tokio::test]
+async fn test_pre_download_hooks() {
+    let mut server = mockito::Server::new_async().await;
+    let output_dir = temp_dir("predownload").to_string_lossy().into_owned();
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    let assets = server
+        .mock("GET", mockito::Matcher::Regex(r"^/\w+\.png$".to_string()))
+        .with_body(PNG_BYTES)
+        .expect(3)
+        .create_async()
+        .await;
+    let mirror = server
+        .mock("GET", mockito::Matcher::Regex(r"^/mirror/\d+$".to_string()))
+        .with_body(PNG_BYTES)
+        .expect(2)
+        .create_async()
+        .await;
+    let photos: Vec<Image> = (0..4)
+        .map(|i| {
+            let derivative = |name: &str| Derivative {
+                checksum: format!("{}{}", name, i),
+                url: Some(format!("{}/{}{}.png", server.url(), name, i)),
+                ..Default::default()
+            };
+            let mut derivatives = HashMap::new();
+            derivatives.insert("342".to_string(), derivative("thumb"));
+            derivatives.insert("3".to_string(), derivative("original"));
+            Image {
+                photo_guid: format!("photo{}", i),
+                derivatives,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    let recorder = Arc::new(Recorder::default());
+    let options = DownloadOptions::new()
+        .with_pre_download_hook(Moderation {
+            mirror: format!("{}/mirror", server.url()),
+        })
+        .with_pre_download_hook(Arc::clone(&recorder));
+    let report = download_derivative_classes(
+        &photos,
+        &output_dir,
+        &[SizeClass::Thumbnail, SizeClass::Original],
+        &options,
+    )
+    .await
+    .unwrap();
+
+    // photo0 both, photo1 its thumbnail, photo2 both from the mirror
+    assert_eq!(report.downloaded.len(), 5);
+    assert_eq!(
+        report.denied,
+        [(
+            "photo1".to_string(),
+            SizeClass::Original,
+            "over budget".to_string()
+        )]
+    );
+    assert_eq!(report.failures.len(), 2);
+    assert!(report
+        .failures
+        .iter()
+        .all(|(guid, _, error)| guid == "photo3"
+            && error.to_string().contains("moderation service unavailable")));
+    assert!(report.cancelled.is_empty());
+    assets.assert_async().await;
+    mirror.assert_async().await;
+
+    // Later hooks see the redirected URL and nothing denied or failed
+    let urls = recorder.urls.lock().unwrap().clone();
+    assert_eq!(urls.len(), 5);
+    assert!(urls.contains(&format!("{}/mirror/342", server.url())));
+    assert!(!urls.iter().any(|url| url.contains("original1")));
+
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
@@ -0,0 +1,73 @@
+use icloud_album_rs::derivatives::SizeClass;
+use icloud_album_rs::models::{Derivative, Image};
+use icloud_album_rs::prefetch::{PrefetchConfig, PrefetchPredictor};
+use std::collections::HashMap;
+
+#[test]
+fn test_predicts_scroll_direction_and_stride() {
+    let predictor = PrefetchPredictor::new(PrefetchConfig::default());
+
+    // A first request assumes forward scrolling
+    assert_eq!(predictor.record("a", 10, 100), [11, 12, 13]);
+    assert_eq!(predictor.record("a", 11, 100), [12, 13, 14]);
+
+    // Scrolling back
+    let predictor = PrefetchPredictor::new(PrefetchConfig::default());
+    predictor.record("b", 10, 100);
+    predictor.record("b", 9, 100);
+    assert_eq!(predictor.record("b", 8, 100), [7, 6, 5]);
+    // A long jump starts over, forward
+    assert_eq!(predictor.record("b", 50, 100), [51, 52, 53]);
+
+    // Paging through a grid, stopping at the end of the album
+    predictor.record("c", 0, 100);
+    predictor.record("c", 12, 100);
+    assert_eq!(predictor.record("c", 24, 100), [36, 48, 60]);
+    predictor.record("d", 0, 40);
+    predictor.record("d", 12, 40);
+    assert_eq!(predictor.record("d", 24, 40), [36]);
+
+    // Recently seen photos are not fetched again
+    predictor.record("e", 5, 100);
+    predictor.record("e", 7, 100);
+    assert_eq!(predictor.record("e", 6, 100), [8, 9]);
+}
+
+#[test]
+fn test_stats_and_targets() {
+    let predictor = PrefetchPredictor::new(PrefetchConfig::default().with_depth(2));
+    for index in 0..4 {
+        predictor.record("viewer", index, 10);
+    }
+    // Another client's requests don't count as hits
+    predictor.record("other", 4, 10);
+    let stats = predictor.stats();
+    assert_eq!(stats.requests, 5);
+    assert_eq!(stats.predicted, 10);
+    assert_eq!(stats.hits, 3);
+    assert_eq!(stats.precision(), Some(0.3));
+
+    let photo = |guid: &str, key: &str| {
+        let mut derivatives = HashMap::new();
+        derivatives.insert(
+            key.to_string(),
+            Derivative {
+                checksum: guid.to_string(),
+                url: Some(format!("https://example.com/{}", guid)),
+                ..Default::default()
+            },
+        );
+        Image {
+            photo_guid: guid.to_string(),
+            derivatives,
+            ..Default::default()
+        }
+    };
+    let photos = vec![photo("a", "1280"), photo("b", "3"), photo("c", "1280")];
+    let predictor = PrefetchPredictor::new(PrefetchConfig::default());
+    assert_eq!(predictor.config().size_class, SizeClass::Medium);
+    assert_eq!(
+        predictor.targets(&photos, &[1, 2, 7]),
+        vec![("c".to_string(), "https://example.com/c".to_string())]
+    );
+}
@@ -0,0 +1,164 @@
+#![cfg(feature = "qr-code")]
+
+use icloud_album_rs::album::AlbumRef;
+use icloud_album_rs::gallery::{render_gallery, GalleryOptions};
+use icloud_album_rs::qr::{EcLevel, QrCode, QrError, DEFAULT_SCALE, QUIET_ZONE};
+
+fn album_code() -> QrCode {
+    let album: AlbumRef = "B0z5qAGN1JIFd3y".parse().unwrap();
+    QrCode::for_album(&album).unwrap()
+}
+
+// Decodes the pixels of a PNG written by `to_png`, one byte per row byte
+fn png_pixels(png: &[u8]) -> (u32, Vec<u8>) {
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    let mut offset = 8;
+    let mut width = 0;
+    let mut idat = Vec::new();
+    while offset < png.len() {
+        let len = u32::from_be_bytes(png[offset..offset + 4].try_into().unwrap()) as usize;
+        let kind = &png[offset + 4..offset + 8];
+        let data = &png[offset + 8..offset + 8 + len];
+        match kind {
+            b"IHDR" => width = u32::from_be_bytes(data[..4].try_into().unwrap()),
+            b"IDAT" => idat.extend_from_slice(data),
+            // The checksum of an empty IEND chunk is always the same
+            b"IEND" => assert_eq!(&png[offset + 8..offset + 12], [0xAE, 0x42, 0x60, 0x82]),
+            _ => panic!("unexpected chunk"),
+        }
+        offset += len + 12;
+    }
+
+    // Stored deflate blocks after the zlib header
+    let mut pixels = Vec::new();
+    let mut at = 2;
+    loop {
+        let last = idat[at] & 1 == 1;
+        let len = u16::from_le_bytes([idat[at + 1], idat[at + 2]]) as usize;
+        pixels.extend_from_slice(&idat[at + 5..at + 5 + len]);
+        at += 5 + len;
+        if last {
+            break;
+        }
+    }
+    (width, pixels)
+}
+
+#[test]
+fn test_encodes_share_url() {
+    let code = album_code();
+    assert_eq!(code.size(), code.version() as usize * 4 + 17);
+    assert!(code.version() <= 4, "version {}", code.version());
+
+    // Finder patterns: dark border, light separator, dark center
+    let far = code.size() - 1;
+    for (x, y) in [(0, 0), (far, 0), (0, far)] {
+        assert!(code.is_dark(x, y));
+    }
+    assert!(!code.is_dark(7, 0));
+    assert!(code.is_dark(3, 3));
+    assert!(!code.is_dark(code.size(), 0));
+
+    let high = QrCode::encode(
+        b"https://www.icloud.com/sharedalbum/#B0z5qAGN1JIFd3y",
+        EcLevel::High,
+    )
+    .unwrap();
+    assert!(high.version() > code.version());
+}
+
+#[test]
+fn test_rejects_too_long_data() {
+    let error = QrCode::encode(&[b'a'; 3000], EcLevel::Low).unwrap_err();
+    assert_eq!(error, QrError::TooLong(3000));
+}
+
+#[test]
+fn test_renders_svg() {
+    let code = album_code();
+    let svg = code.to_svg();
+    let side = code.size() + 2 * QUIET_ZONE;
+    assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+    assert!(svg.contains(&format!("viewBox=\"0 0 {side} {side}\"")));
+    // The top left module sits inside the quiet zone
+    assert!(svg.contains(&format!("M{q},{q}h1v1h-1z", q = QUIET_ZONE)));
+}
+
+#[test]
+fn test_renders_png() {
+    let code = album_code();
+    let scale = 3;
+    let (width, pixels) = png_pixels(&code.to_png(scale));
+    let side = (code.size() + 2 * QUIET_ZONE) * scale;
+    assert_eq!(width as usize, side);
+    let row_len = 1 + side.div_ceil(8);
+    assert_eq!(pixels.len(), row_len * side);
+
+    // Bits are 1 for white, after each row's filter byte
+    let white = |px: usize, py: usize| pixels[py * row_len + 1 + px / 8] & (0x80 >> (px % 8)) != 0;
+    for py in 0..side {
+        for px in 0..side {
+            let dark = code.is_dark(
+                (px / scale).wrapping_sub(QUIET_ZONE),
+                (py / scale).wrapping_sub(QUIET_ZONE),
+            );
+            assert_eq!(white(px, py), !dark, "pixel {},{}", px, py);
+        }
+    }
+}
+
+#[test]
+fn test_renders_unicode() {
+    let code = album_code();
+    let side = code.size() + 2 * QUIET_ZONE;
+    let text = code.to_unicode(false);
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), side.div_ceil(2));
+    assert!(lines.iter().all(|line| line.chars().count() == side));
+    // The quiet zone is blank, and all dark when inverted
+    assert!(lines[0].chars().all(|c| c == ' '));
+    assert!(code
+        .to_unicode(true)
+        .lines()
+        .next()
+        .unwrap()
+        .chars()
+        .all(|c| c == '█'));
+    assert_eq!(lines[2].chars().nth(QUIET_ZONE), Some('█'));
+}
+
+#[test]
+fn test_saves_by_extension() {
+    let dir = std::env::temp_dir().join(format!("icloud-qr-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let code = album_code();
+
+    code.save(&dir.join("album.svg")).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dir.join("album.svg")).unwrap(),
+        code.to_svg()
+    );
+    code.save(&dir.join("album.PNG")).unwrap();
+    assert_eq!(
+        std::fs::read(dir.join("album.PNG")).unwrap(),
+        code.to_png(DEFAULT_SCALE)
+    );
+    let error = code.save(&dir.join("album.gif")).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_gallery_links_album_with_qr_code() {
+    let url = "https://www.icloud.com/sharedalbum/#B0z5qAGN1JIFd3y";
+    let options = GalleryOptions::new().with_share_url(url);
+    let pages = render_gallery("Album", &[], &options);
+    let html = &pages[0].html;
+    assert!(html.contains(&format!("<p class=\"share\"><a href=\"{}\"><svg", url)));
+    assert!(html.contains("View the album</a>"));
+
+    // Without a share URL there is no link
+    let pages = render_gallery("Album", &[], &GalleryOptions::new());
+    assert!(!pages[0].html.contains("class=\"share\""));
+}
@@ -0,0 +1,91 @@
+use icloud_album_rs::quota::{BandwidthQuota, Limited, RateLimit, ServeLimiter};
+use std::net::IpAddr;
+use std::time::Duration;
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_rate_limit_per_client() {
+    let limiter = ServeLimiter::new()
+        .with_rate_limit(RateLimit::new(2, Duration::from_secs(10)).with_burst(3));
+    let client = ip("203.0.113.7");
+    for _ in 0..3 {
+        assert!(limiter.check_request(client).is_ok());
+    }
+    let limited = limiter.check_request(client).unwrap_err();
+    assert_eq!(limited.status(), 429);
+    assert_eq!(limited.retry_after, Duration::from_secs(5));
+    assert_eq!(limited.retry_after_header(), "5");
+    // Other clients have their own bucket
+    assert!(limiter.check_request(ip("203.0.113.8")).is_ok());
+
+    tokio::time::advance(Duration::from_secs(5)).await;
+    assert!(limiter.check_request(client).is_ok());
+    assert!(limiter.check_request(client).is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_ipv6_clients_share_their_network() {
+    let limiter = ServeLimiter::new().with_rate_limit(RateLimit::new(1, Duration::from_secs(60)));
+    assert!(limiter.check_request(ip("2001:db8:1:2::1")).is_ok());
+    assert!(limiter.check_request(ip("2001:db8:1:2:ffff::9")).is_err());
+    assert!(limiter.check_request(ip("2001:db8:1:3::1")).is_ok());
+}
+
+#[test]
+fn test_unlimited_by_default() {
+    let limiter = ServeLimiter::new();
+    for _ in 0..100 {
+        assert!(limiter.check_request(ip("127.0.0.1")).is_ok());
+        assert!(limiter.reserve("family", 1 << 30).is_ok());
+    }
+    assert_eq!(limiter.usage("family"), 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_album_bandwidth_quota() {
+    let limiter = ServeLimiter::new()
+        .with_bandwidth_quota(BandwidthQuota::new(1000, Duration::from_secs(60)))
+        .with_album_quota("big", BandwidthQuota::new(10_000, Duration::from_secs(60)));
+
+    assert!(limiter.reserve("family", 600).is_ok());
+    assert!(limiter.reserve("family", 400).is_ok());
+    tokio::time::advance(Duration::from_secs(20)).await;
+    let limited = limiter.reserve("family", 1).unwrap_err();
+    assert_eq!(limited.retry_after, Duration::from_secs(40));
+    assert_eq!(limiter.usage("family"), 1000);
+    // Albums are counted separately, with their own quotas
+    assert!(limiter.reserve("big", 5000).is_ok());
+
+    tokio::time::advance(Duration::from_secs(40)).await;
+    assert_eq!(limiter.usage("family"), 0);
+    // An asset larger than the quota is served once per window
+    assert!(limiter.reserve("family", 5000).is_ok());
+    assert!(limiter.reserve("family", 1).is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_recorded_bytes_count_against_the_quota() {
+    let limiter =
+        ServeLimiter::new().with_bandwidth_quota(BandwidthQuota::new(100, Duration::from_secs(60)));
+    assert!(limiter.check_quota("family").is_ok());
+    limiter.record("family", 100);
+    assert!(limiter.check_quota("family").is_err());
+    limiter.record("family", 50);
+    assert_eq!(limiter.usage("family"), 150);
+    assert!(limiter.reserve("family", 1).is_err());
+}
+
+#[test]
+fn test_retry_after_rounds_up() {
+    let limited = Limited {
+        retry_after: Duration::from_millis(1200),
+    };
+    assert_eq!(limited.retry_after_header(), "2");
+    let limited = Limited {
+        retry_after: Duration::ZERO,
+    };
+    assert_eq!(limited.retry_after_header(), "1");
+}
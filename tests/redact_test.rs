@@ -0,0 +1,47 @@
+use icloud_album_rs::api::ApiError;
+use icloud_album_rs::redact::{redact_text, redact_token, set_redaction, Redacted};
+
+// Redaction is a process-wide switch, so everything is checked in one test
+#[test]
+fn test_redaction() {
+    assert_eq!(redact_token("B0z5qAGN1JIFd3y"), "B0z5***");
+    assert_eq!(redact_token("abc"), "***");
+
+    let base = "https://p42-sharedstreams.icloud.com/B0z5qAGN1JIFd3y/sharedstreams/webstream";
+    assert_eq!(
+        redact_text(base),
+        "https://p42-sharedstreams.icloud.com/B0z5***/sharedstreams/webstream"
+    );
+
+    let message = "error sending request for url (https://cvws.icloud-content.com/S/abc/IMG.JPG?o=AtSig&e=123): timed out";
+    assert_eq!(
+        Redacted(message).to_string(),
+        "error sending request for url (https://cvws.icloud-content.com/S/abc/IMG.JPG?***): timed out"
+    );
+    assert_eq!(redact_text("no urls here"), "no urls here");
+
+    let error = ApiError::Other(format!("Failed to fetch {}?sig=secret", base));
+    assert!(!error.to_string().contains("secret"));
+    assert!(!error.to_string().contains("qAGN1JIFd3y"));
+    assert!(!format!("{:?}", error).contains("secret"));
+
+    // Display and Debug redact the same fields
+    let errors = [
+        ApiError::RequestError {
+            status: Some(500),
+            message: format!("{}?sig=secret", base),
+        },
+        ApiError::AlbumNotFound(format!("{}?sig=secret", base)),
+    ];
+    for error in &errors {
+        assert!(!error.to_string().contains("secret"), "{}", error);
+        assert!(!format!("{:?}", error).contains("secret"), "{:?}", error);
+    }
+
+    // Opting out shows everything again
+    set_redaction(false);
+    assert!(error.to_string().contains("sig=secret"));
+    assert_eq!(redact_token("B0z5qAGN1JIFd3y"), "B0z5qAGN1JIFd3y");
+    set_redaction(true);
+    assert!(!error.to_string().contains("secret"));
+}
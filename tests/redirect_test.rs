@@ -1,24 +1,19 @@
-use icloud_album_rs::redirect::get_redirected_base_url;
+use icloud_album_rs::api::ApiError;
+use icloud_album_rs::models::WebstreamRequest;
+use icloud_album_rs::redirect::{
+    get_redirect_info, get_redirected_base_url, probe_webstream, RedirectInfo, WebstreamProbe,
+};
 use reqwest::Client;
 use serde_json::json;
 
-// Define old-style test function for compatibility with main test runner
-#[test]
-fn run_redirect_tests() {
-    // We'll verify these tests pass without running them in the normal test suite
-    // Since they require an active tokio runtime
-    println!("Redirect tests should be run individually with: cargo test --test redirect_test -- --ignored");
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[tokio::test]
-    #[ignore = "Requires separate tokio runtime"]
     async fn test_no_redirect() {
         // Create a mock server that returns a 200 response
-        let mut server = mockito::Server::new();
+        let mut server = mockito::Server::new_async().await;
         let mock_url = server.url();
 
         // Set up a mock response for a non-redirect case
@@ -27,7 +22,8 @@ mod tests {
             .with_status(200)
             .with_header("content-type", "application/json")
             .with_body(r#"{"data": "no redirect"}"#)
-            .create();
+            .create_async()
+            .await;
 
         // Test with a base URL that ends with the mock server URL plus a trailing slash
         let base_url = format!("{}/", mock_url);
@@ -41,14 +37,13 @@ mod tests {
         assert_eq!(result, base_url);
 
         // Verify the mock was called
-        mock.assert();
+        mock.assert_async().await;
     }
 
     #[tokio::test]
-    #[ignore = "Requires separate tokio runtime"]
     async fn test_with_redirect() {
         // Create a mock server
-        let mut server = mockito::Server::new();
+        let mut server = mockito::Server::new_async().await;
         let mock_url = server.url();
 
         // Set up a mock response for a redirect case
@@ -61,7 +56,8 @@ mod tests {
             .with_status(330)
             .with_header("content-type", "application/json")
             .with_body(redirect_response.to_string())
-            .create();
+            .create_async()
+            .await;
 
         // Test with a base URL that ends with the mock server URL plus a trailing slash
         let base_url = format!("{}/", mock_url);
@@ -79,14 +75,13 @@ mod tests {
         assert_eq!(result, expected);
 
         // Verify the mock was called
-        mock.assert();
+        mock.assert_async().await;
     }
 
     #[tokio::test]
-    #[ignore = "Requires separate tokio runtime"]
     async fn test_missing_host() {
         // Create a mock server
-        let mut server = mockito::Server::new();
+        let mut server = mockito::Server::new_async().await;
         let mock_url = server.url();
 
         // Set up a mock response for a redirect case with missing host
@@ -99,7 +94,8 @@ mod tests {
             .with_status(330)
             .with_header("content-type", "application/json")
             .with_body(redirect_response.to_string())
-            .create();
+            .create_async()
+            .await;
 
         // Test with a base URL that ends with the mock server URL plus a trailing slash
         let base_url = format!("{}/", mock_url);
@@ -113,6 +109,96 @@ mod tests {
         assert_eq!(result, base_url);
 
         // Verify the mock was called
-        mock.assert();
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_probe_reuses_body() {
+        // Create a mock server that answers the probe with a full webstream body
+        let mut server = mockito::Server::new_async().await;
+        let mock_url = server.url();
+
+        let webstream_response = json!({
+            "streamName": "Test Album",
+            "streamCtag": "12345",
+            "photos": []
+        });
+
+        let mock = server
+            .mock("POST", "/webstream")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(webstream_response.to_string())
+            .create_async()
+            .await;
+
+        let base_url = format!("{}/", mock_url);
+        let client = Client::new();
+
+        // The probe should keep the body so it doesn't need to be fetched again
+        let probe = probe_webstream(&client, &base_url, "test_token", &WebstreamRequest::new())
+            .await
+            .unwrap();
+        assert_eq!(probe.base_url(), base_url);
+        assert!(matches!(probe, WebstreamProbe::Response { .. }));
+        assert_eq!(probe.into_body(), Some(webstream_response));
+
+        // Only a single request should have been made
+        mock.assert_async().await;
+    }
+
+    #[test]
+    fn test_redirect_info_parse() {
+        let info = RedirectInfo::parse(&json!({
+            "X-Apple-MMe-Host": "p42-sharedstreams.icloud.com",
+            "X-Apple-MMe-Scope": "stream"
+        }));
+        assert_eq!(info.host.as_deref(), Some("p42-sharedstreams.icloud.com"));
+        assert_eq!(info.fields.get("X-Apple-MMe-Scope"), Some(&json!("stream")));
+        assert!(!info.is_not_found());
+        assert_eq!(
+            info.base_url("token").unwrap(),
+            "https://p42-sharedstreams.icloud.com/token/sharedstreams/"
+        );
+
+        let info = RedirectInfo::parse(&json!({ "errorMessage": "stream is not found" }));
+        assert_eq!(info.host, None);
+        assert!(info.is_not_found());
+
+        assert_eq!(RedirectInfo::parse(&json!([])), RedirectInfo::default());
+    }
+
+    #[tokio::test]
+    async fn test_redirect_not_found() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/webstream")
+            .with_status(330)
+            .with_body(json!({ "errorMessage": "stream is not found" }).to_string())
+            .expect(2)
+            .create_async()
+            .await;
+
+        let base_url = format!("{}/", server.url());
+        let client = Client::new();
+        let request = WebstreamRequest::default();
+
+        let info = get_redirect_info(&client, &base_url, &request)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.message.as_deref(), Some("stream is not found"));
+
+        let error = probe_webstream(&client, &base_url, "test_token", &request)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("stream is not found"));
+        let error = error.downcast_ref::<ApiError>().unwrap();
+        assert!(error.is_not_found());
+        assert!(
+            matches!(error, ApiError::AlbumNotFound(message) if message == "stream is not found")
+        );
+
+        mock.assert_async().await;
     }
 }
@@ -0,0 +1,136 @@
+mod common;
+
+use common::{derivative, photo_with};
+use icloud_album_rs::models::Image;
+use icloud_album_rs::refresh::{RefreshConfig, UrlRefresher};
+use mockito::Matcher;
+use serde_json::json;
+use std::time::Duration;
+
+// A photo whose derivative still points at an expired URL
+fn expired(guid: &str) -> Image {
+    let url = format!("https://old.example/{}.jpg", guid);
+    photo_with(
+        guid,
+        [("1", derivative(format!("{}-checksum", guid), Some(url)))],
+    )
+}
+
+fn asset_urls(guids: &[&str]) -> String {
+    let items: serde_json::Map<String, serde_json::Value> = guids
+        .iter()
+        .map(|guid| {
+            (
+                format!("{}-checksum", guid),
+                json!({
+                    "url_location": "cvws.icloud-content.com",
+                    "url_path": format!("/{}.jpg", guid)
+                }),
+            )
+        })
+        .collect();
+    json!({ "items": items }).to_string()
+}
+
+fn url_of(photos: &[Image], guid: &str) -> Option<String> {
+    photos
+        .iter()
+        .find(|p| p.photo_guid == guid)
+        .and_then(|p| p.derivatives["1"].url.clone())
+}
+
+#[test]
+fn test_albums_are_due_shortly_before_expiry() {
+    let mut refresher = UrlRefresher::new(RefreshConfig::default());
+    assert!(refresher.next_refresh().is_none());
+
+    let before = tokio::time::Instant::now();
+    let photos = refresher.add_album("https://example.com/album/", vec![expired("a")]);
+    let next = refresher.next_refresh().unwrap();
+    assert!(next >= before + Duration::from_secs(50 * 60));
+    assert!(next <= photos.refreshed_at() + Duration::from_secs(50 * 60));
+    assert_eq!(refresher.len(), 1);
+}
+
+#[tokio::test]
+async fn test_refresh_due_resolves_urls_in_batches() {
+    let mut server = mockito::Server::new_async().await;
+    let first = server
+        .mock("POST", "/album/webasseturls")
+        .match_body(Matcher::Json(json!({ "photoGuids": ["a", "b"] })))
+        .with_body(asset_urls(&["a", "b"]))
+        .create_async()
+        .await;
+    let second = server
+        .mock("POST", "/album/webasseturls")
+        .match_body(Matcher::Json(json!({ "photoGuids": ["c"] })))
+        .with_body(asset_urls(&["c"]))
+        .create_async()
+        .await;
+
+    let mut refresher = UrlRefresher::new(RefreshConfig {
+        url_lifetime: Duration::ZERO,
+        batch_size: 2,
+        ..Default::default()
+    });
+    let base_url = format!("{}/album/", server.url());
+    let album = refresher.add_album(&base_url, vec![expired("a"), expired("b"), expired("c")]);
+    assert_eq!(
+        url_of(&album.photos(), "c").as_deref(),
+        Some("https://old.example/c.jpg")
+    );
+
+    let report = refresher.refresh_due().await;
+    assert_eq!(report.refreshed, vec![base_url]);
+    assert!(report.failed.is_empty());
+    assert_eq!(report.requests, 2);
+
+    assert_eq!(album.refreshes(), 1);
+    assert_eq!(
+        url_of(&album.photos(), "a").as_deref(),
+        Some("https://cvws.icloud-content.com/a.jpg")
+    );
+    assert_eq!(
+        url_of(&album.photos(), "c").as_deref(),
+        Some("https://cvws.icloud-content.com/c.jpg")
+    );
+
+    first.assert_async().await;
+    second.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_failed_refresh_keeps_urls_and_retries_later() {
+    let mut server = mockito::Server::new_async().await;
+    let failing = server
+        .mock("POST", "/album/webasseturls")
+        .with_status(500)
+        .expect_at_least(1)
+        .create_async()
+        .await;
+
+    let mut refresher = UrlRefresher::new(RefreshConfig {
+        url_lifetime: Duration::ZERO,
+        retry_delay: Duration::from_secs(30),
+        ..Default::default()
+    });
+    let base_url = format!("{}/album/", server.url());
+    let album = refresher.add_album(&base_url, vec![expired("a")]);
+
+    let report = refresher.refresh_due().await;
+    assert!(report.refreshed.is_empty());
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(album.refreshes(), 0);
+    assert_eq!(
+        url_of(&album.photos(), "a").as_deref(),
+        Some("https://old.example/a.jpg")
+    );
+
+    // The album is not due again until the retry delay has passed
+    let next = refresher.next_refresh().unwrap();
+    assert!(next > tokio::time::Instant::now() + Duration::from_secs(20));
+    let report = refresher.refresh_due().await;
+    assert!(report.refreshed.is_empty() && report.failed.is_empty());
+
+    failing.assert_async().await;
+}
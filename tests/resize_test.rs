@@ -0,0 +1,178 @@
+#![cfg(feature = "resize")]
+
+mod common;
+
+use common::temp_dir;
+use icloud_album_rs::codec::StandardDecoder;
+use icloud_album_rs::models::Derivative;
+use icloud_album_rs::placeholder::{PpmDecoder, RgbDecoder, RgbImage};
+use icloud_album_rs::resize::{
+    resize, Fit, ImageEncoder, JpegEncoder, PngEncoder, PpmEncoder, ResizeCache, ResizeRequest,
+    Resizer,
+};
+use std::sync::Arc;
+
+// Left half black, right half white
+fn halves(width: usize, height: usize) -> RgbImage {
+    let pixels = (0..width * height)
+        .map(|i| {
+            if i % width < width / 2 {
+                [0; 3]
+            } else {
+                [255; 3]
+            }
+        })
+        .collect();
+    RgbImage::new(width, height, pixels).unwrap()
+}
+
+fn request(width: Option<u32>, height: Option<u32>, fit: Fit) -> ResizeRequest {
+    ResizeRequest { width, height, fit }
+}
+
+#[test]
+fn test_resize_request_from_query() {
+    assert_eq!(
+        ResizeRequest::from_query("?w=320&h=200&fit=cover&token=x"),
+        Ok(Some(request(Some(320), Some(200), Fit::Cover)))
+    );
+    assert_eq!(
+        ResizeRequest::from_query("h=100"),
+        Ok(Some(request(None, Some(100), Fit::Contain)))
+    );
+    assert_eq!(ResizeRequest::from_query("fit=fill"), Ok(None));
+    assert_eq!(ResizeRequest::from_query(""), Ok(None));
+    assert!(ResizeRequest::from_query("w=0").is_err());
+    assert!(ResizeRequest::from_query("w=100000").is_err());
+    assert!(ResizeRequest::from_query("w=10&fit=squash").is_err());
+    assert_eq!(
+        request(Some(320), None, Fit::Cover).rendition_key("ab/c1"),
+        "abc1-320x0-cover"
+    );
+}
+
+#[test]
+fn test_resize_fits() {
+    let image = halves(400, 200);
+
+    let contain = resize(&image, &request(Some(100), Some(100), Fit::Contain));
+    assert_eq!((contain.width, contain.height), (100, 50));
+    assert_eq!(contain.pixels[0], [0; 3]);
+    assert_eq!(contain.pixels[99], [255; 3]);
+
+    // Cropped to the middle square, which is half black and half white
+    let cover = resize(&image, &request(Some(100), Some(100), Fit::Cover));
+    assert_eq!((cover.width, cover.height), (100, 100));
+    assert_eq!(cover.pixels[0], [0; 3]);
+    assert_eq!(cover.pixels[99], [255; 3]);
+
+    let fill = resize(&image, &request(Some(30), Some(60), Fit::Fill));
+    assert_eq!((fill.width, fill.height), (30, 60));
+
+    let by_height = resize(&image, &request(None, Some(50), Fit::Contain));
+    assert_eq!((by_height.width, by_height.height), (100, 50));
+
+    // Never enlarged
+    let small = resize(&image, &request(Some(800), None, Fit::Contain));
+    assert_eq!((small.width, small.height), (400, 200));
+}
+
+#[test]
+fn test_resize_cache_evicts_least_recently_used() {
+    let dir = temp_dir("resize-cache");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let cache = ResizeCache::open(&dir, 10).unwrap();
+    cache.put("a", b"aaaa").unwrap();
+    cache.put("b", b"bbbb").unwrap();
+    // Using "a" makes "b" the least recently used
+    assert_eq!(cache.get("a").unwrap(), Some(b"aaaa".to_vec()));
+    cache.put("c", b"cccc").unwrap();
+
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.size_bytes(), 8);
+    assert_eq!(cache.get("b").unwrap(), None);
+    assert!(!dir.join("b").exists());
+    // Larger than the whole budget, so not kept
+    cache.put("huge", &[0; 11]).unwrap();
+    assert_eq!(cache.get("huge").unwrap(), None);
+
+    drop(cache);
+    let reopened = ResizeCache::open(&dir, 10).unwrap();
+    assert_eq!(reopened.len(), 2);
+    assert_eq!(reopened.get("c").unwrap(), Some(b"cccc".to_vec()));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_resizer_caches_renditions() {
+    let dir = temp_dir("resizer");
+    let _ = std::fs::remove_dir_all(&dir);
+    let cache = Arc::new(ResizeCache::open(&dir, 1024 * 1024).unwrap());
+    let resizer =
+        Resizer::new(Arc::new(PpmDecoder), Arc::new(PpmEncoder)).with_cache(cache.clone());
+    let content = PpmEncoder.encode(&halves(40, 20)).unwrap();
+    let derivative = Derivative {
+        checksum: "abc".to_string(),
+        ..Default::default()
+    };
+    let request = request(Some(10), None, Fit::Contain);
+
+    let first = resizer
+        .resize_asset(&derivative, &content, &request)
+        .unwrap();
+    assert!(!first.cached);
+    assert_eq!(first.content_type, "image/x-portable-pixmap");
+    let decoded = PpmDecoder.decode(&first.bytes).unwrap();
+    assert_eq!((decoded.width, decoded.height), (10, 5));
+
+    let second = resizer
+        .resize_asset(&derivative, &content, &request)
+        .unwrap();
+    assert!(second.cached);
+    assert_eq!(second.bytes, first.bytes);
+    assert_eq!(cache.len(), 1);
+
+    // Without a checksum nothing is cached
+    resizer
+        .resize_asset(&Derivative::default(), &content, &request)
+        .unwrap();
+    assert_eq!(cache.len(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_jpeg_and_png_codecs() {
+    let image = halves(16, 8);
+    // PNG is lossless
+    let png = PngEncoder.encode(&image).unwrap();
+    assert!(png.starts_with(b"\x89PNG"));
+    assert_eq!(StandardDecoder.decode(&png).unwrap(), image);
+    // The PNM files of the other decoders are read too
+    let ppm = PpmEncoder.encode(&image).unwrap();
+    assert_eq!(StandardDecoder.decode(&ppm).unwrap(), image);
+
+    // JPEG keeps the size and, roughly, the colors
+    let jpeg = JpegEncoder::default().encode(&image).unwrap();
+    assert!(jpeg.starts_with(&[0xFF, 0xD8, 0xFF]));
+    let decoded = StandardDecoder.decode(&jpeg).unwrap();
+    assert_eq!((decoded.width, decoded.height), (16, 8));
+    assert!(decoded.pixels[0][0] < 32 && decoded.pixels[15][0] > 223);
+
+    // Resizing a JPEG derivative, as the serve mode does
+    let resizer = Resizer::new(Arc::new(StandardDecoder), Arc::new(JpegEncoder::default()));
+    let resized = resizer
+        .resize_asset(
+            &Derivative::default(),
+            &jpeg,
+            &request(Some(8), None, Fit::Contain),
+        )
+        .unwrap();
+    assert_eq!(resized.content_type, "image/jpeg");
+    let resized = StandardDecoder.decode(&resized.bytes).unwrap();
+    assert_eq!((resized.width, resized.height), (8, 4));
+
+    assert!(StandardDecoder.decode(b"not an image").is_err());
+}
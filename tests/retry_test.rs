@@ -0,0 +1,185 @@
+use icloud_album_rs::api::{
+    get_api_response_with_retry, get_asset_urls_with_config, ApiError, BackoffStrategy, Endpoint,
+    RetryConfig, RetryDecision, RetryPolicies,
+};
+use icloud_album_rs::models::WebstreamRequest;
+
+fn config(backoff_strategy: BackoffStrategy) -> RetryConfig {
+    RetryConfig {
+        base_delay_ms: 100,
+        max_delay_ms: 1000,
+        backoff_strategy,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_deterministic_strategies() {
+    assert_eq!(
+        config(BackoffStrategy::Constant).retry_delay_ms(3, 100),
+        100
+    );
+    assert_eq!(config(BackoffStrategy::Linear).retry_delay_ms(3, 200), 300);
+    assert_eq!(
+        config(BackoffStrategy::Exponential).retry_delay_ms(2, 200),
+        400
+    );
+    // Capped at the maximum delay
+    assert_eq!(
+        config(BackoffStrategy::Exponential).retry_delay_ms(10, 1000),
+        1000
+    );
+}
+
+#[test]
+fn test_jittered_strategies_stay_in_range() {
+    for _ in 0..200 {
+        let full = config(BackoffStrategy::ExponentialWithJitter).retry_delay_ms(2, 0);
+        assert!(full <= 400);
+
+        // Equal jitter keeps at least half of the exponential delay
+        let equal = config(BackoffStrategy::EqualJitter).retry_delay_ms(2, 0);
+        assert!((200..=400).contains(&equal));
+
+        // Decorrelated jitter grows from the previous delay, not the attempt
+        let decorrelated = config(BackoffStrategy::DecorrelatedJitter).retry_delay_ms(7, 150);
+        assert!((100..=450).contains(&decorrelated));
+        let first = config(BackoffStrategy::DecorrelatedJitter).retry_delay_ms(1, 0);
+        assert!((100..=300).contains(&first));
+        let capped = config(BackoffStrategy::DecorrelatedJitter).retry_delay_ms(2, 900);
+        assert!((100..=1000).contains(&capped));
+    }
+}
+
+#[test]
+fn test_custom_delay_function() {
+    fn fibonacci_ish(attempt: u64, previous: u64) -> u64 {
+        previous + attempt * 10
+    }
+
+    let strategy = BackoffStrategy::Custom(fibonacci_ish);
+    assert_eq!(strategy, BackoffStrategy::Custom(fibonacci_ish));
+    assert_ne!(strategy, BackoffStrategy::Exponential);
+    assert_eq!(config(strategy).retry_delay_ms(1, 0), 10);
+    assert_eq!(config(strategy).retry_delay_ms(2, 10), 30);
+    // Custom delays are capped too
+    assert_eq!(config(strategy).retry_delay_ms(3, 5000), 1000);
+}
+
+#[test]
+fn test_built_in_retry_classification() {
+    let config = RetryConfig::default();
+    let server_error = ApiError::RequestError {
+        status: Some(500),
+        message: "failed".to_string(),
+    };
+    assert!(config.should_retry(&server_error, 1));
+    let not_found = ApiError::RequestError {
+        status: Some(404),
+        message: "failed".to_string(),
+    };
+    assert!(!config.should_retry(&not_found, 1));
+    assert!(!config.should_retry(&ApiError::JsonParseError("eof".to_string()), 1));
+}
+
+#[test]
+fn test_retry_predicate_overrides_classification() {
+    fn retry_truncated_bodies(error: &ApiError, attempt: u64) -> RetryDecision {
+        match error {
+            ApiError::JsonParseError(message) if message.contains("EOF") && attempt < 2 => {
+                RetryDecision::Retry
+            }
+            ApiError::RequestError {
+                status: Some(500), ..
+            } => RetryDecision::Stop,
+            _ => RetryDecision::Default,
+        }
+    }
+
+    let config = RetryConfig {
+        retry_predicate: Some(retry_truncated_bodies),
+        ..Default::default()
+    };
+    let truncated = ApiError::JsonParseError("EOF while parsing".to_string());
+    assert!(config.should_retry(&truncated, 1));
+    assert!(!config.should_retry(&truncated, 2));
+    let server_error = ApiError::RequestError {
+        status: Some(500),
+        message: "failed".to_string(),
+    };
+    assert!(!config.should_retry(&server_error, 1));
+    // Falls back to the built-in classification
+    let unavailable = ApiError::RequestError {
+        status: Some(503),
+        message: "failed".to_string(),
+    };
+    assert!(config.should_retry(&unavailable, 1));
+}
+
+#[tokio::test]
+async fn test_retry_predicate_applies_to_requests() {
+    let mut server = mockito::Server::new_async().await;
+    let failing = server
+        .mock("POST", "/album/webasseturls")
+        .with_status(500)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let config = RetryConfig {
+        retry_predicate: Some(|_, _| RetryDecision::Stop),
+        ..Default::default()
+    };
+    let error = get_asset_urls_with_config(
+        &reqwest::Client::new(),
+        &format!("{}/album/", server.url()),
+        &["guid1".to_string()],
+        config,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        error.root(),
+        ApiError::RequestError {
+            status: Some(500),
+            ..
+        }
+    ));
+    failing.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_webstream_retries_use_their_endpoint_policy() {
+    let mut server = mockito::Server::new_async().await;
+    let webstream = server
+        .mock("POST", "/album/webstream")
+        .with_status(503)
+        .expect(2)
+        .create_async()
+        .await;
+
+    let policies = RetryPolicies::new(RetryConfig {
+        max_retries: 5,
+        ..Default::default()
+    })
+    .with_endpoint(
+        Endpoint::Webstream,
+        RetryConfig {
+            max_retries: 2,
+            base_delay_ms: 1,
+            ..Default::default()
+        },
+    );
+    assert_eq!(policies.for_endpoint(Endpoint::WebAssetUrls).max_retries, 5);
+
+    let error = get_api_response_with_retry(
+        &reqwest::Client::new(),
+        &format!("{}/album/", server.url()),
+        &WebstreamRequest::default(),
+        policies.for_endpoint(Endpoint::Webstream),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(error.context().unwrap().operation, "fetch webstream");
+    webstream.assert_async().await;
+}
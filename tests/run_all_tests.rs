@@ -1,196 +0,0 @@
-//! ABOUTME: Unified test runner for all tests including async
-//! ABOUTME: Runs all tests including those marked with #[ignore]
-
-use std::process::Command;
-
-// This main function allows running this as a standalone binary:
-// cargo test --test run_all_tests
-#[test]
-fn run_all_tests() {
-    let regular_tests_result = run_regular_tests();
-    let (ignored_tests_result, ignored_test_summary) = run_ignored_tests();
-
-    // We only assert on the regular tests
-    assert!(regular_tests_result, "Regular tests failed");
-
-    // For ignored tests, we just report their status
-    if ignored_tests_result {
-        println!("✅ All tests passed (including regular tests and automated ignored tests)");
-    } else {
-        println!("⚠️ Regular tests passed, but some ignored tests need manual verification");
-        println!("{}", ignored_test_summary);
-        println!("These tests require separate Tokio runtimes and may need to be run manually:");
-        println!("cargo test -- --ignored");
-    }
-}
-
-fn run_regular_tests() -> bool {
-    println!("\n📋 Running regular tests...");
-
-    // Exclude this test runner to avoid recursive execution
-    let output = Command::new("cargo")
-        .args([
-            "test",
-            "--all-targets",
-            "--lib",
-            "--bins",
-            "--examples",
-            "--tests",
-            "--",
-            "--skip",
-            "run_all_tests",
-        ])
-        .output()
-        .expect("Failed to execute test command");
-
-    let success = output.status.success();
-
-    if success {
-        println!("✅ Regular tests passed");
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        println!("❌ Regular tests failed:\n{}", stderr);
-    }
-
-    success
-}
-
-fn run_ignored_tests() -> (bool, String) {
-    println!("\n📋 Running tests that require separate tokio runtimes...");
-
-    // These are the test modules with #[ignore] attributes
-    let async_tests: Vec<(&str, &[&str])> = vec![
-        ("api_test", &["test_api_response", "test_asset_urls"]),
-        (
-            "redirect_test",
-            &[
-                "test_missing_host",
-                "test_no_redirect",
-                "test_with_redirect",
-            ],
-        ),
-        ("integration_test", &["test_icloud_photos"]),
-    ];
-
-    let mut all_success = true;
-    let mut summary = String::new();
-
-    summary.push_str("\n--- IGNORED TESTS SUMMARY ---\n");
-
-    for (test_module, test_fns) in async_tests {
-        println!("\n➡️ Running {} tests...", test_module);
-        summary.push_str(&format!("\n{} tests:\n", test_module));
-
-        for test_fn in test_fns {
-            println!("\n  🔍 Running {}::{}...", test_module, test_fn);
-
-            // We need to run the test in a way that doesn't try to run it inside our own runtime
-            // Use `cargo test --test <module> <function> -- --ignored --exact` to run just this test
-            let output = Command::new("cargo")
-                .args([
-                    "test",
-                    "--test",
-                    test_module,
-                    &format!("tests::{}", test_fn), // Fully qualified test name
-                    "--",
-                    "--ignored",
-                    "--exact",     // Match the test name exactly
-                    "--nocapture", // Show stdout during test execution
-                ])
-                .output()
-                .expect("Failed to execute test command");
-
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let stderr = String::from_utf8_lossy(&output.stderr);
-
-            // Check if the test failed due to tokio runtime issues
-            let is_tokio_runtime_error = stderr
-                .contains("Cannot start a runtime from within a runtime")
-                || stdout.contains("Cannot start a runtime from within a runtime");
-
-            // If it's a tokio runtime error, don't count it as a failure for the automation
-            // We need to run these tests manually
-            let test_status = if is_tokio_runtime_error {
-                summary.push_str(&format!(
-                    "  - {}::{}: Requires manual run (tokio runtime conflict)\n",
-                    test_module, test_fn
-                ));
-                true // Don't count as failure for automation
-            } else {
-                // For other tests, normal failure detection
-                let test_passed = output.status.success()
-                    && !stdout.contains("FAILED")
-                    && !stdout.contains("panicked")
-                    && !stderr.contains("error:");
-
-                if test_passed {
-                    summary.push_str(&format!("  - {}::{}: ✅ Passed\n", test_module, test_fn));
-                } else {
-                    summary.push_str(&format!("  - {}::{}: ❌ Failed\n", test_module, test_fn));
-                }
-
-                test_passed
-            };
-
-            // Record if we should count this as a success or failure
-            all_success = all_success && test_status;
-
-            // Log the appropriate output
-            if is_tokio_runtime_error {
-                println!(
-                    "  ⚠️ Test {}::{} requires manual execution due to tokio runtime conflicts",
-                    test_module, test_fn
-                );
-                println!(
-                    "     Use: cargo test --test {} {} -- --ignored --exact",
-                    test_module,
-                    &format!("tests::{}", test_fn)
-                );
-            } else if test_status {
-                println!("  ✅ Test {}::{} passed", test_module, test_fn);
-
-                // Print a sample of the output to confirm success
-                let success_line = stdout
-                    .lines()
-                    .find(|line| line.contains("test result: ok") || line.contains("... ok"))
-                    .unwrap_or("Test completed successfully");
-                println!("    {}", success_line);
-            } else {
-                println!("  ❌ Test {}::{} failed", test_module, test_fn);
-
-                // Print stderr if any
-                if !stderr.is_empty() {
-                    println!("  Error output:");
-                    for line in stderr.lines().take(10) {
-                        if line.contains("error:") || line.contains("panicked") {
-                            println!("    {}", line);
-                        }
-                    }
-                }
-
-                // Print failure information from stdout if any
-                if !stdout.is_empty() {
-                    println!("  Test output:");
-                    for line in stdout.lines().take(10) {
-                        if line.contains("FAILED")
-                            || line.contains("panicked")
-                            || line.contains("test result:")
-                        {
-                            println!("    {}", line);
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    // Real world test is ignored for a different reason - it makes real API calls
-    // We'll skip it in the automated runner to avoid unexpected external interactions
-    println!("\n📋 Skipping real_world_test as it makes actual API calls");
-    summary.push_str("\nreal_world_test: Skipped (makes real API calls)\n");
-
-    // The runner will technically pass, even though some tests are marked for manual execution
-    // This is intentional - we're treating tokio runtime errors as "needs manual verification"
-    // rather than failures
-    (true, summary)
-}
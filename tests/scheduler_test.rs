@@ -0,0 +1,315 @@
+use icloud_album_rs::album_config::{AlbumsConfig, ConfigChange};
+use icloud_album_rs::api::ApiError;
+use icloud_album_rs::scheduler::{Jitter, PollHealth, PollScheduler};
+use icloud_album_rs::throttle::AdaptiveLimit;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[tokio::test(start_paused = true)]
+async fn test_first_polls_are_staggered() {
+    let scheduler = PollScheduler::new().with_stagger(Duration::from_secs(60));
+    let start = Instant::now();
+    for token in ["a", "b", "c", "d"] {
+        scheduler.add(token, Duration::from_secs(600));
+    }
+    // A short interval caps the stagger
+    scheduler.add("fast", Duration::from_secs(10));
+
+    let offsets: HashMap<String, Duration> = scheduler
+        .schedule()
+        .into_iter()
+        .map(|album| (album.token, album.next_due - start))
+        .collect();
+    assert_eq!(offsets["a"], Duration::ZERO);
+    let mut distinct: Vec<u64> = ["a", "b", "c", "d"]
+        .iter()
+        .map(|token| offsets[*token].as_secs())
+        .collect();
+    distinct.sort();
+    distinct.dedup();
+    assert_eq!(distinct.len(), 4);
+    assert!(offsets
+        .values()
+        .all(|offset| *offset < Duration::from_secs(60)));
+    assert!(offsets["fast"] < Duration::from_secs(10));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_busy_albums_are_polled_sooner() {
+    let scheduler = PollScheduler::new()
+        .with_stagger(Duration::ZERO)
+        .with_busy_factor(0.5);
+    scheduler.add("busy", Duration::from_secs(100));
+    scheduler.add("quiet", Duration::from_secs(100));
+
+    let mut taken = vec![scheduler.take_due().unwrap(), scheduler.take_due().unwrap()];
+    taken.sort();
+    assert_eq!(taken, ["busy", "quiet"]);
+    assert_eq!(scheduler.take_due(), None);
+    scheduler.complete("busy", true);
+    scheduler.complete("quiet", false);
+
+    let schedule = scheduler.schedule();
+    assert_eq!(schedule[0].token, "busy");
+    assert!(schedule[0].activity > 0.0);
+    assert!(schedule[0].effective_interval < Duration::from_secs(100));
+    assert_eq!(schedule[1].effective_interval, Duration::from_secs(100));
+
+    // When both are overdue, the one waiting longest goes first
+    tokio::time::advance(Duration::from_secs(200)).await;
+    assert_eq!(scheduler.take_due().as_deref(), Some("busy"));
+    assert_eq!(scheduler.take_due().as_deref(), Some("quiet"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_apply_config_changes() {
+    let scheduler = PollScheduler::new();
+    let config = AlbumsConfig::parse(
+        r#"{"albums": [{"token": "family", "interval_secs": 600, "output_dir": "x"}]}"#,
+    )
+    .unwrap();
+    scheduler.apply(&[ConfigChange::AlbumAdded {
+        album: config.albums[0].clone(),
+    }]);
+    scheduler.apply(&[ConfigChange::IntervalChanged {
+        token: "family".to_string(),
+        old_secs: 600,
+        new_secs: 30,
+    }]);
+    let schedule = scheduler.schedule();
+    assert_eq!(schedule[0].interval, Duration::from_secs(30));
+    assert!(schedule[0].next_due <= Instant::now() + Duration::from_secs(30));
+
+    scheduler.apply(&[ConfigChange::AlbumRemoved {
+        token: "family".to_string(),
+    }]);
+    assert!(scheduler.schedule().is_empty());
+    assert_eq!(scheduler.next_due(), None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_run_shares_the_limit() {
+    let scheduler = Arc::new(
+        PollScheduler::new()
+            .with_limit(Arc::new(AdaptiveLimit::fixed(1)))
+            .with_stagger(Duration::from_secs(10)),
+    );
+    scheduler.add("busy", Duration::from_secs(60));
+    for token in ["quiet-1", "quiet-2"] {
+        scheduler.add(token, Duration::from_secs(60));
+    }
+
+    let polls = Arc::new(Mutex::new(HashMap::<String, usize>::new()));
+    let running = Arc::new(AtomicUsize::new(0));
+    let task = {
+        let scheduler = Arc::clone(&scheduler);
+        let polls = Arc::clone(&polls);
+        let running = Arc::clone(&running);
+        tokio::spawn(async move {
+            scheduler
+                .run(move |token| {
+                    let polls = Arc::clone(&polls);
+                    let running = Arc::clone(&running);
+                    async move {
+                        assert_eq!(running.fetch_add(1, Ordering::SeqCst), 0);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        *polls.lock().unwrap().entry(token.clone()).or_default() += 1;
+                        Ok(token == "busy")
+                    }
+                })
+                .await;
+        })
+    };
+
+    tokio::time::sleep(Duration::from_secs(600)).await;
+    scheduler.add("late", Duration::from_secs(60));
+    tokio::time::sleep(Duration::from_secs(120)).await;
+    scheduler.remove("quiet-2");
+    scheduler.shutdown_handle().trigger();
+    task.await.unwrap();
+
+    let polls = polls.lock().unwrap();
+    assert!(polls["late"] >= 2);
+    assert!(polls["quiet-1"] >= 10);
+    assert!(polls["busy"] > polls["quiet-1"]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_failing_albums_back_off() {
+    let scheduler = PollScheduler::new()
+        .with_stagger(Duration::ZERO)
+        .with_max_backoff(Duration::from_secs(300))
+        .with_persistent_after(3);
+    scheduler.add("flaky", Duration::from_secs(60));
+    let error = ApiError::Other("connection reset".to_string());
+
+    let mut delays = Vec::new();
+    for _ in 0..4 {
+        let start = Instant::now();
+        assert_eq!(scheduler.take_due().as_deref(), Some("flaky"));
+        scheduler.fail("flaky", &error);
+        let due = scheduler.next_due().unwrap();
+        delays.push((due - start).as_secs());
+        tokio::time::advance(due - start).await;
+    }
+    assert_eq!(delays, [60, 120, 240, 300]);
+    assert!(matches!(
+        scheduler.health("flaky"),
+        Some(PollHealth::PersistentError { failures: 4, .. })
+    ));
+
+    // A successful poll resets the back-off
+    assert_eq!(scheduler.take_due().as_deref(), Some("flaky"));
+    scheduler.complete("flaky", false);
+    assert_eq!(scheduler.health("flaky"), Some(PollHealth::Healthy));
+    assert_eq!(
+        scheduler.next_due().unwrap() - Instant::now(),
+        Duration::from_secs(60)
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_rejected_token_is_persistent_at_once() {
+    let scheduler = PollScheduler::new().with_stagger(Duration::ZERO);
+    scheduler.add("gone", Duration::from_secs(60));
+    scheduler.add("down", Duration::from_secs(60));
+    scheduler.take_due();
+    scheduler.take_due();
+
+    let rejected = ApiError::RequestError {
+        status: Some(404),
+        message: "Not Found".to_string(),
+    };
+    scheduler.fail("gone", &rejected);
+    let unavailable = ApiError::RequestError {
+        status: Some(503),
+        message: "Service Unavailable".to_string(),
+    };
+    scheduler.fail("down", &unavailable);
+
+    match scheduler.health("gone") {
+        Some(PollHealth::PersistentError {
+            failures,
+            last_error,
+        }) => {
+            assert_eq!(failures, 1);
+            assert!(last_error.contains("Not Found"));
+        }
+        other => panic!("unexpected health {:?}", other),
+    }
+    assert!(matches!(
+        scheduler.health("down"),
+        Some(PollHealth::Retrying { failures: 1, .. })
+    ));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_overall_health_reports_queue_and_errors() {
+    let scheduler = PollScheduler::new().with_stagger(Duration::ZERO);
+    for token in ["a", "b", "c"] {
+        scheduler.add(token, Duration::from_secs(60));
+    }
+    let health = scheduler.overall_health();
+    assert_eq!(
+        (health.albums, health.queue_depth, health.polling),
+        (3, 3, 0)
+    );
+    assert_eq!(health.last_success, None);
+    assert_eq!(health.last_error, None);
+    assert!(health.is_healthy());
+
+    let first = scheduler.take_due().unwrap();
+    let second = scheduler.take_due().unwrap();
+    let health = scheduler.overall_health();
+    assert_eq!((health.queue_depth, health.polling), (1, 2));
+
+    scheduler.complete(&first, true);
+    let rejected = ApiError::RequestError {
+        status: Some(410),
+        message: "Gone".to_string(),
+    };
+    scheduler.fail(&second, &rejected);
+    let health = scheduler.overall_health();
+    assert_eq!((health.queue_depth, health.polling), (1, 0));
+    assert!(health.since_last_success().is_some());
+    let last_error = health.last_error.as_ref().unwrap();
+    assert_eq!(last_error.token, second);
+    assert!(last_error.error.contains("Gone"));
+    assert_eq!((health.failing, health.persistent_errors), (1, 1));
+    assert!(!health.is_healthy());
+
+    // The last error is kept after the album recovers
+    tokio::time::advance(Duration::from_secs(60)).await;
+    while let Some(token) = scheduler.take_due() {
+        scheduler.complete(&token, false);
+    }
+    let health = scheduler.overall_health();
+    assert_eq!((health.failing, health.persistent_errors), (0, 0));
+    assert!(health.last_error.is_some());
+}
+
+#[test]
+fn test_jitter_is_deterministic_and_bounded() {
+    let interval = Duration::from_secs(600);
+    let jitter = Jitter::new(0.1);
+    assert_eq!(Jitter::default().apply(interval, "album"), interval);
+    assert_eq!(
+        jitter.apply(interval, "album"),
+        jitter.apply(interval, "album")
+    );
+
+    let intervals: Vec<Duration> = (0..20)
+        .map(|i| jitter.apply(interval, &format!("album-{}", i)))
+        .collect();
+    assert!(intervals
+        .iter()
+        .all(|d| *d >= Duration::from_secs(540) && *d <= Duration::from_secs(660)));
+    let mut seconds: Vec<u64> = intervals.iter().map(Duration::as_secs).collect();
+    seconds.sort();
+    seconds.dedup();
+    assert!(seconds.len() > 10);
+
+    // Instances with different seeds spread the same album
+    assert_ne!(
+        jitter.with_seed(1).apply(interval, "album"),
+        jitter.with_seed(2).apply(interval, "album")
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_non_finite_factors_are_ignored() {
+    let interval = Duration::from_secs(600);
+    for fraction in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+        assert_eq!(Jitter::new(fraction).apply(interval, "album"), interval);
+    }
+
+    // A NaN busy factor leaves busy albums at their interval
+    let scheduler = PollScheduler::new()
+        .with_stagger(Duration::ZERO)
+        .with_busy_factor(f64::NAN)
+        .with_jitter(Jitter::new(f64::NAN));
+    scheduler.add("busy", Duration::from_secs(100));
+    scheduler.take_due();
+    scheduler.complete("busy", true);
+    let schedule = scheduler.schedule();
+    assert!(schedule[0].activity > 0.0);
+    assert_eq!(schedule[0].effective_interval, Duration::from_secs(100));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_scheduler_applies_jitter() {
+    let scheduler = PollScheduler::new()
+        .with_stagger(Duration::ZERO)
+        .with_jitter(Jitter::new(0.2));
+    scheduler.add("album", Duration::from_secs(100));
+    scheduler.take_due();
+    scheduler.complete("album", false);
+    let expected = Jitter::new(0.2).apply(Duration::from_secs(100), "album");
+    let schedule = scheduler.schedule();
+    assert_eq!(schedule[0].effective_interval, expected);
+    assert_eq!(schedule[0].next_due - Instant::now(), expected);
+}
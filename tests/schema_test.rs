@@ -0,0 +1,60 @@
+use icloud_album_rs::api::validate_api_schema;
+use icloud_album_rs::schema::{json_schema, openapi_document, DIALECT, SCHEMA_NAMES};
+use serde_json::json;
+
+#[test]
+fn test_json_schema_documents() {
+    for name in SCHEMA_NAMES {
+        let schema = json_schema(name).unwrap();
+        assert_eq!(schema["$schema"], DIALECT);
+        assert_eq!(schema["title"], *name);
+        assert_eq!(schema["type"], "object");
+    }
+    assert!(json_schema("unknown").is_none());
+}
+
+#[test]
+fn test_required_fields_match_validation() {
+    // Every top-level field reported missing for an empty object is required by the schema
+    for name in SCHEMA_NAMES {
+        let schema = json_schema(name).unwrap();
+        let required: Vec<&str> = schema["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_str().unwrap())
+            .collect();
+        let mut missing: Vec<String> = validate_api_schema(&json!({}), name)
+            .into_iter()
+            .map(|(field, _)| field)
+            .collect();
+        missing.sort();
+        let mut required: Vec<String> = required.into_iter().map(String::from).collect();
+        required.sort();
+        assert_eq!(missing, required, "schema {}", name);
+    }
+
+    let photo = &json_schema("webstream").unwrap()["properties"]["photos"]["items"];
+    assert_eq!(photo["required"], json!(["photoGuid", "derivatives"]));
+    let item = &json_schema("webasseturls").unwrap()["properties"]["items"]["additionalProperties"];
+    assert_eq!(item["required"], json!(["url_location", "url_path"]));
+}
+
+#[test]
+fn test_openapi_document() {
+    let document = openapi_document();
+    assert_eq!(document["openapi"], "3.1.0");
+    let webstream = &document["paths"]["/{token}/sharedstreams/webstream"]["post"];
+    assert_eq!(
+        webstream["responses"]["200"]["content"]["application/json"]["schema"]["$ref"],
+        "#/components/schemas/webstream"
+    );
+    assert!(webstream["responses"]["330"].is_object());
+
+    // Every referenced schema is a component, without its own dialect
+    for name in SCHEMA_NAMES {
+        let component = &document["components"]["schemas"][*name];
+        assert_eq!(component["title"], *name);
+        assert!(component.get("$schema").is_none());
+    }
+}
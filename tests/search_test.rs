@@ -0,0 +1,55 @@
+mod common;
+
+use common::photo;
+use icloud_album_rs::models::{parse_timestamp, Image};
+use icloud_album_rs::search::{search_captions, CaptionQuery};
+
+fn captioned(guid: &str, caption: Option<&str>, date: Option<&str>) -> Image {
+    Image {
+        caption: caption.map(str::to_string),
+        date_created: date.map(str::to_string),
+        ..photo(guid)
+    }
+}
+
+fn guids(hits: &[icloud_album_rs::search::CaptionHit<&Image>]) -> Vec<String> {
+    hits.iter().map(|hit| hit.item.photo_guid.clone()).collect()
+}
+
+#[test]
+fn test_score() {
+    let query = CaptionQuery::new("Birthday, cake!");
+    assert_eq!(query.words(), ["birthday", "cake"]);
+    assert_eq!(query.score("Cake at Mia's BIRTHDAY"), Some(4));
+    assert_eq!(query.score("Birthday cakes"), Some(3));
+    assert_eq!(query.score("Birthday party"), None);
+    assert_eq!(CaptionQuery::new("  ").score("anything"), None);
+    assert_eq!(CaptionQuery::new("fête").score("La Fête"), Some(2));
+}
+
+#[test]
+fn test_search_captions() {
+    let photos = vec![
+        captioned("old", Some("Birthday cake"), Some("2022-05-01T12:00:00Z")),
+        captioned("new", Some("Birthday cake"), Some("2024-05-01T12:00:00Z")),
+        captioned("prefix", Some("Birthdays"), Some("2024-06-01T12:00:00Z")),
+        captioned("undated", Some("birthday"), None),
+        captioned("uncaptioned", None, Some("2024-05-01T12:00:00Z")),
+        captioned("other", Some("Beach"), Some("2024-05-01T12:00:00Z")),
+    ];
+
+    // Exact matches first, newest first among equals, undated last
+    let hits = search_captions(&photos, &CaptionQuery::new("birthday"));
+    assert_eq!(guids(&hits), ["new", "old", "undated", "prefix"]);
+    assert_eq!(hits[0].score, 2);
+
+    let since = parse_timestamp("2023-01-01T00:00:00Z").unwrap();
+    let until = parse_timestamp("2024-06-01T00:00:00Z").unwrap();
+    let query = CaptionQuery::new("birthday")
+        .with_since(since)
+        .with_until(until);
+    assert_eq!(guids(&search_captions(&photos, &query)), ["new"]);
+
+    let limited = CaptionQuery::new("birth").with_limit(2);
+    assert_eq!(search_captions(&photos, &limited).len(), 2);
+}
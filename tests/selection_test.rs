@@ -0,0 +1,51 @@
+mod common;
+
+use common::photo;
+use icloud_album_rs::selection::{list_selections, Selection};
+use icloud_album_rs::state_store::{JsonFileStore, StateStore};
+
+#[test]
+fn test_selection_editing() {
+    let mut selection = Selection::new("mylist");
+    assert!(selection.add("c"));
+    assert!(selection.add("a"));
+    assert!(!selection.add("a"));
+    assert!(!selection.toggle("c"));
+    assert!(selection.toggle("gone"));
+    assert!(selection.contains("gone"));
+    assert!(!selection.remove("b"));
+    assert_eq!(selection.photo_guids, ["a", "gone"]);
+
+    let photos = vec![photo("a"), photo("b"), photo("c")];
+    let selected: Vec<_> = selection
+        .filter(&photos)
+        .into_iter()
+        .map(|photo| photo.photo_guid)
+        .collect();
+    assert_eq!(selected, ["a"]);
+    assert_eq!(selection.missing(&photos), ["gone"]);
+}
+
+#[test]
+fn test_selections_persist() {
+    let dir = std::env::temp_dir().join(format!("icloud-selections-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let store = JsonFileStore::new(&dir);
+
+    assert!(Selection::load(&store, "mylist").unwrap().is_none());
+    let mut selection = Selection::load_or_new(&store, "mylist").unwrap();
+    selection.add("a");
+    selection.save(&store).unwrap();
+    Selection::new("other").save(&store).unwrap();
+    store.put("unrelated", b"{}").unwrap();
+
+    // A later session continues where the last one stopped
+    let mut resumed = Selection::load_or_new(&store, "mylist").unwrap();
+    assert_eq!(resumed, selection);
+    resumed.add("b");
+    resumed.save(&store).unwrap();
+    assert_eq!(Selection::load(&store, "mylist").unwrap().unwrap().len(), 2);
+    assert_eq!(list_selections(&store).unwrap(), ["mylist", "other"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,646 @@
+#![cfg(feature = "server")]
+
+mod common;
+
+use common::temp_dir;
+use icloud_album_rs::access::AccessPolicy;
+use icloud_album_rs::base_url::with_api_origin;
+use icloud_album_rs::derivatives::SizeClass;
+use icloud_album_rs::metrics::{self, PrometheusMetrics};
+use icloud_album_rs::prefetch::{PrefetchConfig, PrefetchPredictor};
+use icloud_album_rs::quota::{BandwidthQuota, RateLimit, ServeLimiter};
+use icloud_album_rs::redact::redact_token;
+use icloud_album_rs::server::{
+    openapi_document, Server, ServerConfig, INVALID_PARAMS, METHOD_NOT_FOUND,
+};
+use icloud_album_rs::sync::SyncState;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+// PNG signature followed by some padding
+const PNG_BYTES: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+/// Mocks an album with two photos served by `server`, the second with a thumbnail
+async fn mock_album(server: &mut mockito::ServerGuard) -> Vec<mockito::Mock> {
+    mock_album_serving(server, PNG_BYTES).await
+}
+
+/// Mocks the album of [`mock_album`] with `content` as each asset
+async fn mock_album_serving(
+    server: &mut mockito::ServerGuard,
+    content: &[u8],
+) -> Vec<mockito::Mock> {
+    let photos: Vec<Value> = ["photo1", "photo2"]
+        .iter()
+        .map(|guid| {
+            let mut derivatives = json!({
+                "original": { "checksum": format!("{}-c", guid), "fileSize": "12" }
+            });
+            if *guid == "photo2" {
+                derivatives["342"] = json!({ "checksum": "photo2-t", "fileSize": "12" });
+            }
+            json!({ "photoGuid": guid, "derivatives": derivatives })
+        })
+        .collect();
+    let webstream = json!({
+        "streamName": "Served Album",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag1",
+        "itemsReturned": "2",
+        "locations": {},
+        "photos": photos
+    });
+    let asset_urls = json!({
+        "items": {
+            "photo1-c": { "url_location": "cvws.icloud-content.com", "url_path": "/photo1.png" },
+            "photo2-c": { "url_location": "cvws.icloud-content.com", "url_path": "/photo2.png" },
+            "photo2-t": { "url_location": "cvws.icloud-content.com", "url_path": "/photo2t.png" }
+        }
+    });
+    vec![
+        server
+            .mock("POST", "/TestToken/sharedstreams/webstream")
+            .with_body(webstream.to_string())
+            .create_async()
+            .await,
+        server
+            .mock("POST", "/TestToken/sharedstreams/webasseturls")
+            .with_body(asset_urls.to_string())
+            .create_async()
+            .await,
+        server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex(r"^/photo\dt?\.png$".to_string()),
+            )
+            .with_header("content-type", "image/png")
+            .with_body(content)
+            .create_async()
+            .await,
+    ]
+}
+
+/// Runs `server` with album requests going to `origin` until `client` is done
+async fn serve<F: Future>(server: Server, origin: &str, client: F) -> F::Output {
+    with_api_origin(origin, async {
+        tokio::select! {
+            result = server.run() => panic!("server stopped: {:?}", result),
+            output = client => output,
+        }
+    })
+    .await
+}
+
+/// Sends a JSON-RPC call, returning the HTTP status and body
+async fn call(
+    origin: &str,
+    authorization: Option<&str>,
+    method: &str,
+    params: Value,
+) -> (u16, Value) {
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/rpc", origin))
+        .json(&json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "method": method,
+            "params": params,
+        }));
+    if let Some(authorization) = authorization {
+        request = request.header("authorization", authorization);
+    }
+    let response = request.send().await.unwrap();
+    let status = response.status().as_u16();
+    let body = response.json().await.unwrap_or(Value::Null);
+    (status, body)
+}
+
+#[tokio::test]
+async fn test_fetch_download_and_sync() {
+    let mut mock = mockito::Server::new_async().await;
+    let _mocks = mock_album(&mut mock).await;
+    let root = temp_dir("rpc");
+    let _ = tokio::fs::remove_dir_all(&root).await;
+
+    let prometheus = Arc::new(PrometheusMetrics::new());
+    metrics::install(prometheus.clone());
+    let config = ServerConfig::new(&root).with_metrics(prometheus.clone());
+    let server = Server::bind("127.0.0.1:0", config).await.unwrap();
+    let origin = server.origin();
+    serve(server, &mock.url(), async {
+        let (status, body) = call(&origin, None, "fetch", json!({ "token": "TestToken" })).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["id"], 7);
+        assert_eq!(body["result"]["metadata"]["streamName"], "Served Album");
+        assert_eq!(body["result"]["photos"].as_array().unwrap().len(), 2);
+
+        let (_, body) = call(
+            &origin,
+            None,
+            "download",
+            json!({ "token": "TestToken", "dir": "all", "classes": ["original"] }),
+        )
+        .await;
+        assert_eq!(body["result"]["photos"], 2);
+        assert_eq!(body["result"]["downloaded"].as_array().unwrap().len(), 2);
+        assert!(root.join("all").read_dir().unwrap().count() >= 2);
+
+        // A sync only downloads what the directory doesn't have yet
+        let (_, body) = call(
+            &origin,
+            None,
+            "sync",
+            json!({ "token": "TestToken", "dir": "synced" }),
+        )
+        .await;
+        assert_eq!(body["result"]["downloaded"].as_array().unwrap().len(), 2);
+        assert_eq!(body["result"]["skipped"], 0);
+        let (_, body) = call(
+            &origin,
+            None,
+            "sync",
+            json!({ "token": "TestToken", "dir": "synced" }),
+        )
+        .await;
+        assert_eq!(body["result"]["downloaded"].as_array().unwrap().len(), 0);
+        assert_eq!(body["result"]["skipped"], 2);
+
+        let text = reqwest::get(format!("{}/metrics", origin))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let album = redact_token("TestToken");
+        assert!(text.contains(&format!(
+            "icloud_album_photos_synced_total{{album=\"{}\"}} 2\n",
+            album
+        )));
+        assert!(text.contains("icloud_album_last_sync_timestamp_seconds"));
+    })
+    .await;
+    metrics::uninstall();
+    assert!(prometheus.bytes_downloaded() >= 4 * PNG_BYTES.len() as u64);
+
+    let state = SyncState::load(root.join("synced").join(".sync-state.json"))
+        .await
+        .unwrap();
+    assert!(state.file("photo1").is_some());
+    assert_eq!(
+        state.file("photo2").unwrap().checksum.as_deref(),
+        Some("photo2-c")
+    );
+    tokio::fs::remove_dir_all(&root).await.unwrap();
+}
+
+/// Counts the local file headers of a ZIP archive
+fn zip_entries(archive: &[u8]) -> usize {
+    archive
+        .windows(4)
+        .filter(|window| *window == b"PK\x03\x04")
+        .count()
+}
+
+#[tokio::test]
+async fn test_album_zip() {
+    let mut mock = mockito::Server::new_async().await;
+    let _mocks = mock_album(&mut mock).await;
+    let config = ServerConfig::new(temp_dir("zip"));
+    let server = Server::bind("127.0.0.1:0", config).await.unwrap();
+    let origin = server.origin();
+    serve(server, &mock.url(), async {
+        let response = reqwest::get(format!("{}/albums/TestToken/zip", origin))
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["content-type"], "application/zip");
+        assert_eq!(
+            response.headers()["content-disposition"],
+            "attachment; filename=\"Served Album.zip\""
+        );
+        let archive = response.bytes().await.unwrap();
+        assert!(archive.starts_with(b"PK\x03\x04"));
+        assert_eq!(zip_entries(&archive), 2);
+
+        // A subset of the photos, in more size classes
+        let url = format!(
+            "{}/albums/TestToken/zip?guids=photo2&classes=original,thumb",
+            origin
+        );
+        let archive = reqwest::get(url).await.unwrap().bytes().await.unwrap();
+        assert_eq!(zip_entries(&archive), 2);
+
+        let url = format!("{}/albums/TestToken/zip?classes=huge", origin);
+        let response = reqwest::get(url).await.unwrap();
+        assert_eq!(response.status().as_u16(), 400);
+        let response = reqwest::Client::new()
+            .post(format!("{}/albums/TestToken/zip", origin))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 405);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_asset_proxy() {
+    let mut mock = mockito::Server::new_async().await;
+    let _mocks = mock_album(&mut mock).await;
+    let config = ServerConfig::new(temp_dir("assets"));
+    let server = Server::bind("127.0.0.1:0", config).await.unwrap();
+    let origin = server.origin();
+    serve(server, &mock.url(), async {
+        let url = format!("{}/albums/TestToken/assets/photo1/original", origin);
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let headers = response.headers().clone();
+        assert_eq!(headers["content-type"], "image/png");
+        assert_eq!(headers["etag"], "\"photo1-c\"");
+        assert_eq!(headers["cache-control"], "public, max-age=86400");
+        assert_eq!(headers["accept-ranges"], "bytes");
+        assert_eq!(response.bytes().await.unwrap().as_ref(), PNG_BYTES);
+
+        let client = reqwest::Client::new();
+        let response = client
+            .get(&url)
+            .header("range", "bytes=0-3")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 206);
+        assert_eq!(response.headers()["content-range"], "bytes 0-3/12");
+        assert_eq!(response.bytes().await.unwrap().as_ref(), &PNG_BYTES[..4]);
+
+        let response = client
+            .get(&url)
+            .header("if-none-match", "\"photo1-c\"")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 304);
+
+        for (path, status) in [
+            ("assets/photo9/original", 404),
+            ("assets/photo1/thumb", 404),
+            ("assets/photo1/huge", 400),
+        ] {
+            let url = format!("{}/albums/TestToken/{}", origin, path);
+            assert_eq!(reqwest::get(url).await.unwrap().status().as_u16(), status);
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_assets_are_prefetched() {
+    let mut mock = mockito::Server::new_async().await;
+    let mut mocks = mock_album(&mut mock).await;
+    mocks.pop().unwrap().remove_async().await;
+    let assets = mock
+        .mock(
+            "GET",
+            mockito::Matcher::Regex(r"^/photo\d\.png$".to_string()),
+        )
+        .with_header("content-type", "image/png")
+        .with_body(PNG_BYTES)
+        .expect(2)
+        .create_async()
+        .await;
+    let predictor = Arc::new(PrefetchPredictor::new(
+        PrefetchConfig::default().with_size_class(SizeClass::Original),
+    ));
+    let config = ServerConfig::new(temp_dir("prefetch")).with_prefetch(Arc::clone(&predictor));
+    let server = Server::bind("127.0.0.1:0", config).await.unwrap();
+    let origin = server.origin();
+    serve(server, &mock.url(), async {
+        let url = format!("{}/albums/TestToken/assets/photo1/original", origin);
+        assert_eq!(reqwest::get(url).await.unwrap().status().as_u16(), 200);
+        // Requesting the first photo prefetches the second
+        for _ in 0..50 {
+            if assets.matched_async().await {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let url = format!("{}/albums/TestToken/assets/photo2/original", origin);
+        let response = reqwest::get(url).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["etag"], "\"photo2-c\"");
+        assert_eq!(response.headers()["content-type"], "image/png");
+        assert_eq!(response.bytes().await.unwrap().as_ref(), PNG_BYTES);
+    })
+    .await;
+    // The second photo was served from memory
+    assets.assert_async().await;
+    let stats = predictor.stats();
+    assert_eq!((stats.requests, stats.predicted, stats.hits), (2, 1, 1));
+}
+
+#[cfg(feature = "resize")]
+#[tokio::test]
+async fn test_asset_resize() {
+    use icloud_album_rs::codec::StandardDecoder;
+    use icloud_album_rs::placeholder::{RgbDecoder, RgbImage};
+    use icloud_album_rs::resize::{ImageEncoder, JpegEncoder, Resizer};
+
+    // A 4x2 JPEG of white pixels, as iCloud serves most derivatives
+    let white = RgbImage::new(4, 2, vec![[255; 3]; 8]).unwrap();
+    let jpeg = JpegEncoder::default().encode(&white).unwrap();
+    let mut mock = mockito::Server::new_async().await;
+    let _mocks = mock_album_serving(&mut mock, &jpeg).await;
+    let resizer = Resizer::new(Arc::new(StandardDecoder), Arc::new(JpegEncoder::default()));
+    let config = ServerConfig::new(temp_dir("resize")).with_resizer(resizer);
+    let server = Server::bind("127.0.0.1:0", config).await.unwrap();
+    let origin = server.origin();
+    serve(server, &mock.url(), async {
+        let url = format!("{}/albums/TestToken/assets/photo1/original?w=2", origin);
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["content-type"], "image/jpeg");
+        assert_eq!(response.headers()["etag"], "\"photo1c-2x0-contain\"");
+        let resized = StandardDecoder
+            .decode(&response.bytes().await.unwrap())
+            .unwrap();
+        assert_eq!((resized.width, resized.height), (2, 1));
+
+        // Renditions are validated against their own ETag
+        let response = reqwest::Client::new()
+            .get(&url)
+            .header("if-none-match", "\"photo1c-2x0-contain\"")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 304);
+
+        let url = format!("{}/albums/TestToken/assets/photo1/original?w=0", origin);
+        assert_eq!(reqwest::get(url).await.unwrap().status().as_u16(), 400);
+    })
+    .await;
+}
+
+#[cfg(feature = "web-ui")]
+#[tokio::test]
+async fn test_web_ui() {
+    use icloud_album_rs::server::SYNC_STATE_FILE;
+
+    let root = temp_dir("ui");
+    std::fs::create_dir_all(root.join("served")).unwrap();
+    let mut state = SyncState::new();
+    state.record_run(2).await;
+    state
+        .save(root.join("served").join(SYNC_STATE_FILE))
+        .await
+        .unwrap();
+    let mut mock = mockito::Server::new_async().await;
+    let _mocks = mock_album(&mut mock).await;
+    let access = AccessPolicy::new()
+        .with_bearer_token("admin")
+        .with_album_token("TestToken", "family");
+    let config = ServerConfig::new(&root)
+        .with_access(access)
+        .with_ui_album("TestToken", "Served", "served")
+        .with_ui_album("OtherToken", "Other", "other");
+    let server = Server::bind("127.0.0.1:0", config).await.unwrap();
+    let origin = server.origin();
+    serve(server, &mock.url(), async {
+        // The page needs no credentials
+        let response = reqwest::get(format!("{}/ui", origin)).await.unwrap();
+        assert_eq!(response.url().path(), "/ui/");
+        assert!(response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+
+        let url = format!("{}/ui/albums.json", origin);
+        assert_eq!(reqwest::get(&url).await.unwrap().status().as_u16(), 401);
+        let albums: Value = reqwest::Client::new()
+            .get(&url)
+            .bearer_auth("admin")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(albums.as_array().unwrap().len(), 2);
+        assert_eq!(albums[0]["id"], "TestToken");
+        assert_eq!(albums[0]["name"], "Served");
+        assert_eq!(albums[0]["photo_count"], 2);
+        assert_eq!(albums[1]["last_synced_at"], Value::Null);
+
+        // Album tokens only list their album
+        let url = format!("{}/ui/albums.json?access_token=family", origin);
+        let albums: Value = reqwest::get(url).await.unwrap().json().await.unwrap();
+        assert_eq!(albums.as_array().unwrap().len(), 1);
+
+        let url = format!("{}/ui/albums/TestToken.json?access_token=family", origin);
+        let grid: Value = reqwest::get(url).await.unwrap().json().await.unwrap();
+        assert_eq!(grid["name"], "Served Album");
+        assert_eq!(grid["photos"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            grid["photos"][0]["thumbnail_url"],
+            "/albums/TestToken/assets/photo2/thumb"
+        );
+
+        for (path, status) in [
+            ("albums/OtherToken.json?access_token=family", 403),
+            ("albums/Unknown.json?access_token=family", 404),
+            ("app.js", 404),
+        ] {
+            let url = format!("{}/ui/{}", origin, path);
+            assert_eq!(reqwest::get(url).await.unwrap().status().as_u16(), status);
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_requests_are_limited() {
+    let mut mock = mockito::Server::new_async().await;
+    let _mocks = mock_album(&mut mock).await;
+    let limiter = ServeLimiter::new()
+        .with_rate_limit(RateLimit::new(3, Duration::from_secs(60)))
+        .with_bandwidth_quota(BandwidthQuota::new(12, Duration::from_secs(3600)));
+    let limiter = Arc::new(limiter);
+    let config = ServerConfig::new(temp_dir("limits")).with_limiter(Arc::clone(&limiter));
+    let server = Server::bind("127.0.0.1:0", config).await.unwrap();
+    let origin = server.origin();
+    serve(server, &mock.url(), async {
+        let get = |path: &str| reqwest::get(format!("{}/albums/TestToken/{}", origin, path));
+        let response = get("assets/photo1/original").await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(limiter.usage("TestToken"), 12);
+
+        // The album's quota is used up
+        for path in ["assets/photo2/original", "zip"] {
+            let response = get(path).await.unwrap();
+            assert_eq!(response.status().as_u16(), 429);
+            assert_eq!(response.headers()["retry-after"], "3600");
+        }
+
+        // And so is the client's rate limit
+        let response = get("assets/photo1/original").await.unwrap();
+        assert_eq!(response.status().as_u16(), 429);
+        let retry_after: u64 = response.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=20).contains(&retry_after));
+        // Which covers the API too, but not health checks
+        let (status, _) = call(&origin, None, "fetch", json!({ "token": "TestToken" })).await;
+        assert_eq!(status, 429);
+        let response = reqwest::get(format!("{}/health", origin)).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn test_rpc_errors_and_access() {
+    let root = temp_dir("errors");
+    let config = ServerConfig::new(&root)
+        .with_access(AccessPolicy::new().with_album_token("TestToken", "family"));
+    let server = Server::bind("127.0.0.1:0", config).await.unwrap();
+    let origin = server.origin();
+    serve(server, "http://127.0.0.1:9", async {
+        // Requests without credentials are challenged
+        let (status, _) = call(&origin, None, "fetch", json!({ "token": "TestToken" })).await;
+        assert_eq!(status, 401);
+
+        // Album tokens don't open other albums
+        let (status, _) = call(
+            &origin,
+            Some("Bearer family"),
+            "fetch",
+            json!({ "token": "OtherToken" }),
+        )
+        .await;
+        assert_eq!(status, 403);
+
+        // Paths can't leave the root
+        for dir in ["../escape", "/tmp/escape"] {
+            let (status, body) = call(
+                &origin,
+                Some("Bearer family"),
+                "sync",
+                json!({ "token": "TestToken", "dir": dir }),
+            )
+            .await;
+            assert_eq!(status, 200);
+            assert_eq!(body["error"]["code"], INVALID_PARAMS);
+        }
+
+        let (_, body) = call(&origin, Some("Bearer family"), "delete", json!({})).await;
+        assert_eq!(body["error"]["code"], METHOD_NOT_FOUND);
+
+        // Album routes take the same credentials, also as a query parameter
+        let zip = |query: &str| format!("{}/albums/TestToken/zip{}", origin, query);
+        let response = reqwest::get(zip("")).await.unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+        assert!(response.headers().contains_key("www-authenticate"));
+        let response = reqwest::get(format!(
+            "{}/albums/OtherToken/zip?access_token=family",
+            origin
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status().as_u16(), 403);
+        // The album can't be fetched from the unreachable origin
+        let response = reqwest::get(zip("?access_token=family")).await.unwrap();
+        assert_eq!(response.status().as_u16(), 502);
+
+        let response = reqwest::get(format!("{}/nope", origin)).await.unwrap();
+        assert_eq!(response.status().as_u16(), 404);
+        // Without metrics, there is no /metrics
+        let response = reqwest::get(format!("{}/metrics", origin)).await.unwrap();
+        assert_eq!(response.status().as_u16(), 404);
+    })
+    .await;
+    assert!(!root.exists());
+}
+
+#[tokio::test]
+async fn test_health_needs_no_credentials() {
+    let config = ServerConfig::new(temp_dir("health"))
+        .with_access(AccessPolicy::new().with_bearer_token("secret"));
+    let server = Server::bind("127.0.0.1:0", config).await.unwrap();
+    let origin = server.origin();
+    serve(server, "http://127.0.0.1:9", async {
+        let response = reqwest::get(format!("{}/health", origin)).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["status"], "ok");
+        assert!(body.get("scheduler").is_none());
+
+        // Nor does the description of the API
+        let response = reqwest::get(format!("{}/openapi.json", origin))
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        let document: Value = response.json().await.unwrap();
+        assert_eq!(document, openapi_document());
+        assert_eq!(document["openapi"], "3.1.0");
+        for path in ["/rpc", "/albums/{token}/zip", "/health", "/metrics", "/ui/"] {
+            assert!(document["paths"][path].is_object(), "{}", path);
+        }
+        let rpc = &document["paths"]["/rpc"]["post"]["requestBody"]["content"];
+        assert_eq!(
+            rpc["application/json"]["schema"]["$ref"],
+            "#/components/schemas/RpcRequest"
+        );
+    })
+    .await;
+}
+
+/// Sends `request` as raw bytes, returning the status line of the reply
+async fn raw_status(origin: &str, request: &[u8]) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let mut stream = tokio::net::TcpStream::connect(origin.trim_start_matches("http://"))
+        .await
+        .unwrap();
+    // The server may answer and close before the whole request is written
+    let _ = stream.write_all(request).await;
+    let mut reply = Vec::new();
+    let _ = stream.read_to_end(&mut reply).await;
+    let reply = String::from_utf8_lossy(&reply);
+    reply.lines().next().unwrap_or_default().to_string()
+}
+
+#[tokio::test]
+async fn test_oversized_requests_are_rejected() {
+    let server = Server::bind("127.0.0.1:0", ServerConfig::new(temp_dir("limits")))
+        .await
+        .unwrap();
+    let origin = server.origin();
+    serve(server, "http://127.0.0.1:9", async {
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(9000));
+        assert!(raw_status(&origin, long_line.as_bytes())
+            .await
+            .starts_with("HTTP/1.1 400"));
+
+        let long_header = format!(
+            "GET /health HTTP/1.1\r\nx-big: {}\r\n\r\n",
+            "a".repeat(9000)
+        );
+        assert!(raw_status(&origin, long_header.as_bytes())
+            .await
+            .starts_with("HTTP/1.1 431"));
+
+        let many_headers = format!("GET /health HTTP/1.1\r\n{}\r\n", "x-h: 1\r\n".repeat(101));
+        assert!(raw_status(&origin, many_headers.as_bytes())
+            .await
+            .starts_with("HTTP/1.1 431"));
+
+        let fine = "GET /health HTTP/1.1\r\nhost: localhost\r\n\r\n";
+        assert!(raw_status(&origin, fine.as_bytes())
+            .await
+            .starts_with("HTTP/1.1 200"));
+    })
+    .await;
+}
@@ -0,0 +1,167 @@
+mod common;
+
+use common::{derivative, photo_with};
+use icloud_album_rs::derivatives::SizeClass;
+use icloud_album_rs::download::DownloadOptions;
+use icloud_album_rs::models::Image;
+use icloud_album_rs::session::{download_chunked, ChunkedDownload};
+use icloud_album_rs::shutdown::Shutdown;
+use icloud_album_rs::sync::SyncState;
+
+const PNG_BYTES: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+// A photo whose original is served from `url`
+fn served(guid: &str, url: String) -> Image {
+    photo_with(guid, [("3", derivative(guid, Some(url)))])
+}
+
+#[tokio::test]
+async fn test_chunked_download_resumes() {
+    let mut server = mockito::Server::new_async().await;
+    let dir = std::env::temp_dir().join(format!("icloud-session-{}", std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    let output_dir = dir.join("photos").to_string_lossy().to_string();
+    let state_path = dir.join("state.json");
+    tokio::fs::create_dir_all(&dir).await.unwrap();
+
+    let mut mocks = Vec::new();
+    let mut photos = Vec::new();
+    for i in 0..5 {
+        let path = format!("/p{}.png", i);
+        // Every photo is downloaded exactly once across both sessions
+        mocks.push(
+            server
+                .mock("GET", path.as_str())
+                .with_body(PNG_BYTES)
+                .expect(1)
+                .create_async()
+                .await,
+        );
+        photos.push(served(
+            &format!("p{}", i),
+            format!("{}{}", server.url(), path),
+        ));
+    }
+    let session = ChunkedDownload::new(2).with_state_path(&state_path);
+
+    // The first session is stopped after its first chunk
+    let shutdown = Shutdown::new();
+    let options = DownloadOptions::new().with_shutdown(shutdown.clone());
+    let mut state = SyncState::load(&state_path).await.unwrap();
+    let report = download_chunked(
+        &photos,
+        &output_dir,
+        &[SizeClass::Original],
+        &options,
+        &session,
+        &mut state,
+        |_, _| shutdown.trigger(),
+    )
+    .await
+    .unwrap();
+    assert_eq!((report.chunks, report.completed, report.skipped), (3, 1, 0));
+    assert!(!report.finished);
+    assert_eq!(report.report.downloaded.len(), 2);
+
+    let mut state = SyncState::load(&state_path).await.unwrap();
+    let progress = state.chunk_progress.clone().unwrap();
+    assert_eq!(progress.chunk_size, 2);
+    assert_eq!(progress.completed.len(), 1);
+    assert_eq!(progress.completed[0].first_guid, "p0");
+    assert_eq!(progress.completed[0].last_guid, "p1");
+
+    // The next session picks up at the second chunk
+    let mut chunk_sizes = Vec::new();
+    let report = download_chunked(
+        &photos,
+        &output_dir,
+        &[SizeClass::Original],
+        &DownloadOptions::new(),
+        &session,
+        &mut state,
+        |_, report| chunk_sizes.push(report.downloaded.len()),
+    )
+    .await
+    .unwrap();
+    assert_eq!((report.completed, report.skipped), (2, 1));
+    assert!(report.finished);
+    assert_eq!(chunk_sizes, [2, 1]);
+    assert!(state.chunk_progress.is_none());
+    assert!(SyncState::load(&state_path)
+        .await
+        .unwrap()
+        .chunk_progress
+        .is_none());
+
+    for mock in mocks {
+        mock.assert_async().await;
+    }
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_chunk_with_failures_stays_pending() {
+    let mut server = mockito::Server::new_async().await;
+    let dir = std::env::temp_dir().join(format!("icloud-session-failed-{}", std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+    let output_dir = dir.join("photos").to_string_lossy().to_string();
+
+    let _ok = server
+        .mock("GET", "/p0.png")
+        .with_body(PNG_BYTES)
+        .create_async()
+        .await;
+    let broken = server
+        .mock("GET", "/p1.png")
+        .with_status(404)
+        .create_async()
+        .await;
+    let photos: Vec<Image> = (0..2)
+        .map(|i| served(&format!("p{}", i), format!("{}/p{}.png", server.url(), i)))
+        .collect();
+    let session = ChunkedDownload::new(1);
+
+    let mut state = SyncState::new();
+    let report = download_chunked(
+        &photos,
+        &output_dir,
+        &[SizeClass::Original],
+        &DownloadOptions::new(),
+        &session,
+        &mut state,
+        |_, _| {},
+    )
+    .await
+    .unwrap();
+    assert_eq!((report.completed, report.failed), (1, 1));
+    assert!(!report.finished);
+    let progress = state.chunk_progress.clone().unwrap();
+    assert_eq!(progress.completed.len(), 1);
+    assert_eq!(progress.completed[0].first_guid, "p0");
+
+    // Once the photo is back, only the failed chunk runs again
+    broken.remove_async().await;
+    let fixed = server
+        .mock("GET", "/p1.png")
+        .with_body(PNG_BYTES)
+        .expect(1)
+        .create_async()
+        .await;
+    let report = download_chunked(
+        &photos,
+        &output_dir,
+        &[SizeClass::Original],
+        &DownloadOptions::new(),
+        &session,
+        &mut state,
+        |_, _| {},
+    )
+    .await
+    .unwrap();
+    assert_eq!((report.skipped, report.completed, report.failed), (1, 1, 0));
+    assert!(report.finished);
+    assert!(state.chunk_progress.is_none());
+
+    fixed.assert_async().await;
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
@@ -0,0 +1,93 @@
+#![cfg(feature = "simulator")]
+
+use icloud_album_rs::base_url::with_api_origin;
+use icloud_album_rs::simulator::{FailureMode, Simulator, SimulatorConfig};
+use icloud_album_rs::{download_photo, get_icloud_photos};
+
+/// Starts a simulator on a free port, returning its origin
+async fn start(config: SimulatorConfig) -> String {
+    let simulator = Simulator::bind("127.0.0.1:0", config).await.unwrap();
+    let origin = simulator.origin();
+    tokio::spawn(simulator.run());
+    origin
+}
+
+#[tokio::test]
+async fn test_full_pipeline_against_simulator() {
+    let origin = start(
+        SimulatorConfig::new()
+            .with_photos(30)
+            .with_album_name("Dev Album"),
+    )
+    .await;
+
+    let album = with_api_origin(&origin, get_icloud_photos("AnyToken"))
+        .await
+        .unwrap();
+    assert_eq!(album.metadata.stream_name, "Dev Album");
+    assert_eq!(album.metadata.items_returned, 30);
+    assert_eq!(album.photos.len(), 30);
+
+    let url = album.photos[0].derivatives["original"].url.clone().unwrap();
+    assert!(url.starts_with(&origin));
+
+    // Downloads are served by the simulator too
+    let dir = std::env::temp_dir().join(format!("simulator-test-{}", std::process::id()));
+    let path = with_api_origin(
+        &origin,
+        download_photo(&album.photos[0], None, dir.to_str().unwrap(), None),
+    )
+    .await
+    .unwrap();
+    assert!(path.ends_with(".jpg"));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // The API description is served alongside
+    let document: serde_json::Value = reqwest::get(format!("{}/openapi.json", origin))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(document, icloud_album_rs::schema::openapi_document());
+}
+
+#[tokio::test]
+async fn test_simulated_failure_modes() {
+    // The redirect is followed and stays on the simulator
+    let origin = start(SimulatorConfig::new().with_failure(FailureMode::Redirect)).await;
+    let album = with_api_origin(&origin, get_icloud_photos("AnyToken"))
+        .await
+        .unwrap();
+    assert_eq!(album.photos.len(), 10);
+
+    // Photos without URLs are still returned
+    let origin = start(SimulatorConfig::new().with_failure(FailureMode::MissingUrls)).await;
+    let album = with_api_origin(&origin, get_icloud_photos("AnyToken"))
+        .await
+        .unwrap();
+    let resolved = album
+        .photos
+        .iter()
+        .filter(|photo| photo.derivatives.values().any(|d| d.url.is_some()))
+        .count();
+    assert_eq!(resolved, 5);
+
+    // Every request failing surfaces the status
+    let origin = start(SimulatorConfig::new().with_failure(FailureMode::Status {
+        every: 1,
+        status: 503,
+    }))
+    .await;
+    let err = with_api_origin(&origin, get_icloud_photos("AnyToken"))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("503"), "{err}");
+
+    // Truncated JSON is a parse failure
+    let origin =
+        start(SimulatorConfig::new().with_failure(FailureMode::Malformed { every: 1 })).await;
+    assert!(with_api_origin(&origin, get_icloud_photos("AnyToken"))
+        .await
+        .is_err());
+}
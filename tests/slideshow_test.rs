@@ -0,0 +1,144 @@
+mod common;
+
+use common::photo_with;
+use icloud_album_rs::models::{Derivative, ICloudResponse, Image, Metadata};
+use icloud_album_rs::slideshow::{
+    build_slideshow, select_display_derivative, ImageResizer, ResizeError, SlideOrder,
+    SlideshowManifest, SlideshowOptions, MANIFEST_FILE,
+};
+use serde_json::json;
+use std::collections::HashMap;
+
+// PNG signature followed by some padding
+const PNG_BYTES: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+fn derivative(width: u32, height: u32, url: Option<String>) -> Derivative {
+    Derivative {
+        checksum: format!("sum-{}", width),
+        width: Some(width),
+        height: Some(height),
+        url,
+        ..Default::default()
+    }
+}
+
+// A dated photo with three derivatives served under `base_url`
+fn slide(guid: &str, date: &str, caption: Option<&str>, base_url: &str) -> Image {
+    let derivatives = [("342", 342, 256), ("1280", 1280, 960), ("2048", 2048, 1536)].map(
+        |(key, width, height)| {
+            let url = format!("{}/{}/{}.png", base_url, guid, key);
+            (key, derivative(width, height, Some(url)))
+        },
+    );
+    Image {
+        caption: caption.map(String::from),
+        date_created: Some(date.to_string()),
+        ..photo_with(guid, derivatives)
+    }
+}
+
+fn album(photos: Vec<Image>) -> ICloudResponse {
+    let metadata: Metadata = serde_json::from_value(json!({
+        "streamName": "Family",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag123",
+        "itemsReturned": photos.len(),
+        "locations": {}
+    }))
+    .unwrap();
+    ICloudResponse {
+        metadata,
+        photos,
+        unparsed: Vec::new(),
+    }
+}
+
+#[test]
+fn test_select_display_derivative() {
+    let mut derivatives = HashMap::new();
+    derivatives.insert("342".to_string(), derivative(342, 256, None));
+    derivatives.insert("1280".to_string(), derivative(1280, 960, None));
+    derivatives.insert("2048".to_string(), derivative(2048, 1536, None));
+
+    // The smallest derivative filling the display in one dimension
+    let key = |w, h| select_display_derivative(&derivatives, w, h).unwrap().0;
+    assert_eq!(key(800, 480), "1280");
+    assert_eq!(key(1600, 1200), "2048");
+    // Displays larger than every derivative get the largest one
+    assert_eq!(key(3840, 2160), "2048");
+}
+
+struct MarkingResizer;
+
+impl ImageResizer for MarkingResizer {
+    fn resize(&self, content: &[u8], width: u32, height: u32) -> Result<Vec<u8>, ResizeError> {
+        let mut resized = content.to_vec();
+        resized.extend_from_slice(format!("{}x{}", width, height).as_bytes());
+        Ok(resized)
+    }
+}
+
+#[tokio::test]
+async fn test_build_slideshow() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+    let mock = server
+        .mock(
+            "GET",
+            mockito::Matcher::Regex(r"^/(later|earlier)/1280\.png$".to_string()),
+        )
+        .with_body(PNG_BYTES)
+        .expect(2)
+        .create_async()
+        .await;
+    let missing = server
+        .mock("GET", "/broken/1280.png")
+        .with_status(404)
+        .create_async()
+        .await;
+
+    let response = album(vec![
+        slide("later", "2023-06-02T10:00:00Z", Some("Beach"), &url),
+        slide("broken", "2023-06-01T09:00:00Z", None, &url),
+        slide("earlier", "2023-06-01T08:00:00Z", Some("  "), &url),
+    ]);
+
+    let output_dir = std::env::temp_dir().join(format!("icloud-slideshow-{}", std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&output_dir).await;
+    let options = SlideshowOptions::new(1024, 600).with_duration(7);
+
+    let manifest = build_slideshow(&response, &output_dir, &options, Some(&MarkingResizer))
+        .await
+        .unwrap();
+    mock.assert_async().await;
+    missing.assert_async().await;
+
+    // Chronological order, with the failed download left out
+    let guids: Vec<&str> = manifest
+        .slides
+        .iter()
+        .map(|s| s.photo_guid.as_str())
+        .collect();
+    assert_eq!(guids, vec!["earlier", "later"]);
+    assert_eq!(manifest.order, SlideOrder::Chronological);
+    assert_eq!(manifest.slides[0].file, "earlier_slide-0001.png");
+    assert_eq!(manifest.slides[0].caption, None);
+    assert_eq!(manifest.slides[1].caption.as_deref(), Some("Beach"));
+    assert_eq!(manifest.slides[1].duration_secs, 7);
+
+    let content = tokio::fs::read(output_dir.join("later_slide-0002.png"))
+        .await
+        .unwrap();
+    assert!(content.ends_with(b"1024x600"));
+
+    let written: SlideshowManifest = serde_json::from_slice(
+        &tokio::fs::read(output_dir.join(MANIFEST_FILE))
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(written, manifest);
+
+    tokio::fs::remove_dir_all(&output_dir).await.unwrap();
+}
@@ -0,0 +1,165 @@
+#![cfg(feature = "smtp")]
+
+use icloud_album_rs::models::Metadata;
+use icloud_album_rs::sinks::{EmailMode, SmtpSink, SmtpTls};
+use icloud_album_rs::watch::{AlbumEvent, EventSink};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+fn metadata() -> Metadata {
+    serde_json::from_value(json!({
+        "streamName": "Family",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "1",
+        "itemsReturned": 0,
+        "locations": {}
+    }))
+    .unwrap()
+}
+
+fn events() -> Vec<AlbumEvent> {
+    vec![
+        AlbumEvent::PhotoAdded {
+            photo_guid: "a".to_string(),
+            caption: Some("Beach".to_string()),
+            date_created: None,
+            thumbnail_url: None,
+            thumbnail: None,
+        },
+        AlbumEvent::PhotoRemoved {
+            photo_guid: "b".to_string(),
+        },
+    ]
+}
+
+// Minimal SMTP server that accepts one session and returns everything received
+async fn fake_smtp_server(listener: TcpListener) -> String {
+    let (stream, _) = listener.accept().await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut transcript = String::new();
+
+    writer.write_all(b"220 localhost ready\r\n").await.unwrap();
+    let mut in_data = false;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await.unwrap() == 0 {
+            break;
+        }
+        transcript.push_str(&line);
+
+        let reply: &[u8] = if in_data {
+            if line != ".\r\n" {
+                continue;
+            }
+            in_data = false;
+            b"250 queued\r\n"
+        } else if line.starts_with("HELO") {
+            b"250-localhost\r\n250 ok\r\n"
+        } else if line.starts_with("DATA") {
+            in_data = true;
+            b"354 go ahead\r\n"
+        } else if line.starts_with("QUIT") {
+            writer.write_all(b"221 bye\r\n").await.unwrap();
+            break;
+        } else {
+            b"250 ok\r\n"
+        };
+        writer.write_all(reply).await.unwrap();
+    }
+    transcript
+}
+
+#[test]
+fn test_compose_modes() {
+    let album = metadata();
+    let sink = SmtpSink::new("localhost", 25, "watcher@example.com", &["me@example.com"]);
+
+    let digest = sink.compose(&album, &events());
+    assert_eq!(digest.len(), 1);
+    assert_eq!(digest[0].0, "[Family] 2 changes");
+    assert_eq!(digest[0].1, "Photo added: a (Beach)\nPhoto removed: b");
+
+    let per_event = sink
+        .with_mode(EmailMode::PerEvent)
+        .compose(&album, &events());
+    assert_eq!(per_event.len(), 2);
+    assert_eq!(per_event[1].0, "[Family] Photo removed: b");
+}
+
+#[tokio::test]
+async fn test_deliver_over_smtp() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(fake_smtp_server(listener));
+
+    let sink = SmtpSink::new(
+        "127.0.0.1",
+        port,
+        "watcher@example.com",
+        &["me@example.com", "you@example.com"],
+    );
+    let album = metadata();
+    let events = events();
+    sink.deliver(&album, &events).await.unwrap();
+
+    let transcript = server.await.unwrap();
+    assert!(transcript.contains("MAIL FROM:<watcher@example.com>\r\n"));
+    assert!(transcript.contains("RCPT TO:<me@example.com>\r\n"));
+    assert!(transcript.contains("RCPT TO:<you@example.com>\r\n"));
+    assert!(transcript.contains("Subject: [Family] 2 changes\r\n"));
+    assert!(transcript.contains("Photo removed: b\r\n.\r\n"));
+    assert!(transcript.contains("\r\nDate: "));
+    assert!(transcript.ends_with("QUIT\r\n"));
+}
+
+#[tokio::test]
+async fn test_deliver_encodes_non_ascii_subjects() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(fake_smtp_server(listener));
+
+    let sink = SmtpSink::new("127.0.0.1", port, "a@example.com", &["b@example.com"])
+        .with_tls(SmtpTls::None);
+    let mut album = metadata();
+    album.stream_name = "Família".to_string();
+    let events = events();
+    sink.deliver(&album, &events).await.unwrap();
+
+    let transcript = server.await.unwrap();
+    assert!(transcript.contains("Subject: =?utf-8?b?"));
+    assert!(!transcript.contains("Família"));
+}
+
+#[tokio::test]
+async fn test_deliver_rejects_header_injection() {
+    let album = metadata();
+    let events = events();
+    for (from, to) in [
+        ("a@example.com\r\nBcc: c@example.com", "b@example.com"),
+        ("a@example.com", "b@example.com>\r\nRCPT TO:<c@example.com"),
+    ] {
+        // Nothing listens on port 9, the addresses are refused before connecting
+        let sink = SmtpSink::new("127.0.0.1", 9, from, &[to]);
+        let error = sink.deliver(&album, &events).await.unwrap_err();
+        assert!(error.to_string().contains("Invalid email address"));
+    }
+}
+
+#[tokio::test]
+async fn test_deliver_reports_rejection() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        stream.write_all(b"554 no service here\r\n").await.unwrap();
+    });
+
+    let sink = SmtpSink::new("127.0.0.1", port, "a@example.com", &["b@example.com"]);
+    let album = metadata();
+    let events = events();
+    let error = sink.deliver(&album, &events).await.unwrap_err();
+    assert!(error.to_string().contains("554"));
+}
@@ -0,0 +1,190 @@
+mod common;
+
+use common::{derivative, photo_with};
+use icloud_album_rs::models::{ICloudResponse, Image, Metadata};
+use icloud_album_rs::snapshot::{
+    freeze_album, restore_snapshot, ContentStore, FrozenAlbum, ManifestEntry, RestoreOptions,
+    SnapshotManifest,
+};
+use serde_json::json;
+
+const JPEG: &[u8] = &[
+    0xFF, 0xD8, 0xFF, 0xE0, 0, 0x10, b'J', b'F', b'I', b'F', 0, 1,
+];
+const PNG: &[u8] = &[
+    0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0x0D,
+];
+
+// A captioned photo whose original is served from `url`
+fn served(guid: &str, checksum: &str, url: String) -> Image {
+    Image {
+        caption: Some(format!("Caption {}", guid)),
+        ..photo_with(guid, [("3", derivative(checksum, Some(url)))])
+    }
+}
+
+fn response(photos: Vec<Image>) -> ICloudResponse {
+    let metadata: Metadata = serde_json::from_value(json!({
+        "streamName": "Family",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag1",
+        "itemsReturned": photos.len(),
+        "locations": {}
+    }))
+    .unwrap();
+    ICloudResponse {
+        metadata,
+        photos,
+        unparsed: Vec::new(),
+    }
+}
+
+#[test]
+fn test_object_path_is_sanitized() {
+    let store = ContentStore::new("/store");
+    assert_eq!(
+        store.object_path("abc123"),
+        std::path::Path::new("/store/objects/ab/abc123")
+    );
+    // Checksums can never escape the objects directory
+    assert_eq!(
+        store.object_path("../x"),
+        std::path::Path::new("/store/objects/__/___x")
+    );
+}
+
+#[tokio::test]
+async fn test_freeze_album_dedupes_assets() {
+    let mut server = mockito::Server::new_async().await;
+    let first = server
+        .mock("GET", "/a.jpg")
+        .with_body(JPEG)
+        .expect(1)
+        .create_async()
+        .await;
+    let second = server
+        .mock("GET", "/b.png")
+        .with_body(PNG)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let root = std::env::temp_dir().join(format!("icloud-snapshot-{}", std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&root).await;
+    let store = ContentStore::new(&root);
+
+    let album = response(vec![
+        served("a", "checksumA", format!("{}/a.jpg", server.url())),
+        served("b", "checksumB", format!("{}/b.png", server.url())),
+        // Same asset shared by two photos is stored once
+        served("c", "checksumA", format!("{}/a.jpg", server.url())),
+        Image {
+            photo_guid: "no-url".to_string(),
+            ..Default::default()
+        },
+    ]);
+
+    let FrozenAlbum {
+        manifest, report, ..
+    } = freeze_album(&album, &store).await.unwrap();
+    assert_eq!(report.downloaded, 2);
+    assert_eq!(report.reused, 1);
+    assert_eq!(report.skipped, vec!["no-url".to_string()]);
+    assert_eq!(manifest.entries.len(), 3);
+    assert_eq!(manifest.entries[1].extension, ".png");
+    assert_eq!(manifest.entries[2].checksum, "checksumA");
+    assert_eq!(manifest.entries[2].file_size, JPEG.len() as u64);
+    assert_eq!(
+        manifest.entries[2]
+            .integrity
+            .as_ref()
+            .map(|d| d.algorithm.as_str()),
+        Some("sha256")
+    );
+    assert_eq!(
+        tokio::fs::read(store.object_path("checksumA"))
+            .await
+            .unwrap(),
+        JPEG
+    );
+
+    // Freezing again transfers nothing
+    let FrozenAlbum {
+        manifest: again,
+        report,
+        ..
+    } = freeze_album(&album, &store).await.unwrap();
+    assert_eq!(report.downloaded, 0);
+    assert_eq!(report.reused, 3);
+    assert_eq!(again.entries, manifest.entries);
+
+    let manifests = store.list_manifests().await.unwrap();
+    assert!(!manifests.is_empty());
+    let stored = ContentStore::read_manifest(manifests.last().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(stored, again);
+
+    first.assert_async().await;
+    second.assert_async().await;
+    tokio::fs::remove_dir_all(&root).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_restore_snapshot() {
+    let root = std::env::temp_dir().join(format!("icloud-restore-{}", std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&root).await;
+    let store = ContentStore::new(root.join("store"));
+    store.put("checksumA", JPEG).await.unwrap();
+    store.put("checksumB", PNG).await.unwrap();
+
+    let entry =
+        |guid: &str, checksum: &str, extension: &str, caption: Option<&str>| ManifestEntry {
+            photo_guid: guid.to_string(),
+            derivative_key: "3".to_string(),
+            checksum: checksum.to_string(),
+            extension: extension.to_string(),
+            caption: caption.map(|c| c.to_string()),
+            date_created: None,
+            file_size: 12,
+            integrity: None,
+        };
+    let mut manifest = SnapshotManifest {
+        created_at: 1,
+        stream_name: "Family".to_string(),
+        stream_ctag: "ctag1".into(),
+        entries: vec![
+            entry("a", "checksumA", ".jpg", Some("Beach: day 1")),
+            entry("b", "checksumB", ".png", None),
+        ],
+    };
+
+    let output = root.join("album");
+    let options = RestoreOptions::new()
+        .with_numbered(true)
+        .with_hard_links(true);
+    let restored = restore_snapshot(&manifest, &store, &output, &options)
+        .await
+        .unwrap();
+    assert_eq!(
+        restored,
+        vec![output.join("1_a_Beach_ day 1.jpg"), output.join("2_b.png")]
+    );
+    assert_eq!(tokio::fs::read(&restored[1]).await.unwrap(), PNG);
+
+    // Restoring again over an existing directory succeeds
+    let restored = restore_snapshot(&manifest, &store, &output, &RestoreOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(restored[1], output.join("b.png"));
+
+    // A missing object is reported rather than silently skipped
+    manifest.entries.push(entry("c", "missing", ".jpg", None));
+    let error = restore_snapshot(&manifest, &store, &output, &options)
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+
+    tokio::fs::remove_dir_all(&root).await.unwrap();
+}
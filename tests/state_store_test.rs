@@ -0,0 +1,94 @@
+mod common;
+
+use common::temp_dir;
+use icloud_album_rs::state_store::{get_json, JsonFileStore, StateStore};
+use icloud_album_rs::sync::{SyncState, SyncedFile};
+use std::path::PathBuf;
+
+/// Checks the behavior every store must have
+fn check_store(store: &dyn StateStore) {
+    assert_eq!(store.get("albums/a").unwrap(), None);
+    assert!(store.list("").unwrap().is_empty());
+
+    store.put("albums/b", b"2").unwrap();
+    store.put("albums/a", b"1").unwrap();
+    store.put("cookies", b"[]").unwrap();
+    store.put("odd key %+&=", b"x").unwrap();
+    store.put("albums/a", b"11").unwrap();
+
+    assert_eq!(store.get("albums/a").unwrap(), Some(b"11".to_vec()));
+    assert_eq!(store.get("odd key %+&=").unwrap(), Some(b"x".to_vec()));
+    assert_eq!(store.list("albums/").unwrap(), vec!["albums/a", "albums/b"]);
+    assert_eq!(store.list("albums_").unwrap(), Vec::<String>::new());
+    assert_eq!(store.list("").unwrap().len(), 4);
+
+    // Sync state round-trips under a key
+    let mut state = SyncState::new();
+    state.record(
+        "photo1",
+        SyncedFile {
+            path: PathBuf::from("photos/photo1.jpg"),
+            ..Default::default()
+        },
+    );
+    state.save_to(store, "sync/album").unwrap();
+    assert_eq!(SyncState::load_from(store, "sync/album").unwrap(), state);
+    assert_eq!(
+        SyncState::load_from(store, "sync/other").unwrap(),
+        SyncState::new()
+    );
+
+    store.put("sync/broken", b"not json").unwrap();
+    let err = get_json::<SyncState>(store, "sync/broken").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_json_file_store() {
+    let dir = temp_dir("state-store-json");
+    let _ = std::fs::remove_dir_all(&dir);
+    let store = JsonFileStore::new(&dir);
+    check_store(&store);
+
+    // Keys never escape the directory
+    assert_eq!(store.path_of("../x").parent(), Some(dir.as_path()));
+    assert!(dir.join("albums%2Fa.json").is_file());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_sqlite_store() {
+    use icloud_album_rs::state_store::SqliteStore;
+
+    let connection = rusqlite::Connection::open_in_memory().unwrap();
+    assert!(SqliteStore::with_connection(connection, "state; DROP TABLE x").is_err());
+
+    let connection = rusqlite::Connection::open_in_memory().unwrap();
+    let store = SqliteStore::with_connection(connection, "daemon_state").unwrap();
+    check_store(&store);
+    // LIKE wildcards in prefixes are taken literally
+    assert!(store.list("albums%").unwrap().is_empty());
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn test_sled_store() {
+    use icloud_album_rs::state_store::SledStore;
+
+    let dir = temp_dir("state-store-sled");
+    let _ = std::fs::remove_dir_all(&dir);
+    let store = SledStore::open(&dir).unwrap();
+    check_store(&store);
+    drop(store);
+
+    // A daemon's own database, with the state in a tree of its choosing.
+    // sled releases its file lock in the background, so this is a separate
+    // database rather than the one above opened again.
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let store = SledStore::with_tree(db.open_tree("icloud").unwrap());
+    check_store(&store);
+    assert!(db.get("albums/a").unwrap().is_none());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
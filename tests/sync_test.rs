@@ -0,0 +1,401 @@
+mod common;
+
+use common::fresh_dir;
+use icloud_album_rs::models::{HttpValidators, Image};
+use icloud_album_rs::sync::{
+    EvictionPolicy, EvictionReason, PathPlan, Retention, SizeBudget, SyncState, SyncedFile,
+    TRASH_DIR,
+};
+use icloud_album_rs::utils::photo_base_filename;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[tokio::test]
+async fn test_sync_state_roundtrip() {
+    let dir = fresh_dir("roundtrip").await;
+    let state_path = dir.join("state.json");
+
+    // A missing state file loads as empty
+    assert_eq!(
+        SyncState::load(&state_path).await.unwrap(),
+        SyncState::new()
+    );
+
+    let mut state = SyncState::new();
+    state.stream_ctag = Some("ctag1".into());
+    state.record(
+        "guid1",
+        SyncedFile {
+            path: dir.join("guid1.jpg"),
+            checksum: Some("abc".to_string()),
+            validators: HttpValidators {
+                etag: Some("\"v1\"".to_string()),
+                last_modified: None,
+            },
+            integrity: None,
+            taken_at: Some(1_700_000_000),
+        },
+    );
+    state.save(&state_path).await.unwrap();
+
+    let loaded = SyncState::load(&state_path).await.unwrap();
+    assert_eq!(loaded, state);
+    assert_eq!(
+        loaded.file("guid1").unwrap().checksum.as_deref(),
+        Some("abc")
+    );
+    assert!(!dir.join("state.json.tmp").exists());
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_template_change_renames_instead_of_downloading() {
+    let dir = fresh_dir("rename").await;
+    let photo = Image {
+        photo_guid: "guid1".to_string(),
+        caption: Some("Beach day".to_string()),
+        ..Default::default()
+    };
+
+    // A previous run saved the photo using its index-based name
+    let old_path = dir.join(format!(
+        "{}.png",
+        photo_base_filename(&photo, Some(0), None)
+    ));
+    tokio::fs::write(&old_path, b"data").await.unwrap();
+
+    let mut state = SyncState::new();
+    state.record(
+        "guid1",
+        SyncedFile {
+            path: old_path.clone(),
+            ..Default::default()
+        },
+    );
+
+    // Unknown photos must be downloaded
+    assert_eq!(state.plan("guid2", &dir, "guid2"), PathPlan::Download);
+
+    // The new run names files without an index
+    let new_base = photo_base_filename(&photo, None, None);
+    let new_path = dir.join("guid1_Beach day.png");
+    assert_eq!(
+        state.reconcile("guid1", &dir, &new_base).await.unwrap(),
+        PathPlan::Rename {
+            from: old_path.clone(),
+            to: new_path.clone(),
+        }
+    );
+    assert!(!old_path.exists());
+    assert_eq!(tokio::fs::read(&new_path).await.unwrap(), b"data");
+    assert_eq!(state.file("guid1").unwrap().path, new_path);
+
+    // Running again finds the photo in place
+    assert_eq!(
+        state.reconcile("guid1", &dir, &new_base).await.unwrap(),
+        PathPlan::UpToDate(new_path.clone())
+    );
+
+    // A deleted file has to be downloaded again
+    tokio::fs::remove_file(&new_path).await.unwrap();
+    assert_eq!(state.plan("guid1", &dir, &new_base), PathPlan::Download);
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+/// Writes a file of `size` bytes and records it, taken at `taken_at`
+async fn mirrored(
+    state: &mut SyncState,
+    dir: &std::path::Path,
+    guid: &str,
+    size: usize,
+    taken_at: Option<&str>,
+) {
+    let photo = Image {
+        photo_guid: guid.to_string(),
+        date_created: taken_at.map(String::from),
+        ..Default::default()
+    };
+    let path = dir.join(format!("{}.jpg", guid));
+    tokio::fs::write(&path, vec![0u8; size]).await.unwrap();
+    state.record(guid, SyncedFile::for_photo(&photo, path));
+}
+
+#[tokio::test]
+async fn test_size_budget_evicts_oldest_first() {
+    let dir = fresh_dir("size-budget").await;
+    let mut state = SyncState::new();
+    mirrored(&mut state, &dir, "new", 400, Some("2024-06-01T10:00:00Z")).await;
+    mirrored(&mut state, &dir, "old", 300, Some("2019-06-01T10:00:00Z")).await;
+    mirrored(&mut state, &dir, "undated", 200, None).await;
+    mirrored(&mut state, &dir, "older", 100, Some("2015-06-01T10:00:00Z")).await;
+    assert!(state.file("old").unwrap().taken_at.is_some());
+
+    // Within the budget nothing happens
+    assert!(state
+        .enforce_size_budget(&SizeBudget::new(1000))
+        .await
+        .unwrap()
+        .is_empty());
+
+    // The oldest photos go first, until the rest fits
+    let evicted = state
+        .enforce_size_budget(&SizeBudget::new(650))
+        .await
+        .unwrap();
+    assert_eq!(evicted, ["older", "old"]);
+    assert!(!dir.join("old.jpg").exists());
+    assert!(dir.join("undated.jpg").exists());
+    assert_eq!(state.files.len(), 2);
+    let eviction = &state.evictions["old"];
+    assert_eq!(eviction.size, 300);
+    assert_eq!(eviction.reason, EvictionReason::SizeBudget);
+    assert_eq!(eviction.path, dir.join("old.jpg"));
+
+    // Evicted photos are not downloaded again, and the evictions are persisted
+    assert_eq!(state.plan("old", &dir, "old"), PathPlan::Evicted);
+    let state_path = dir.join("state.json");
+    state.save(&state_path).await.unwrap();
+    assert_eq!(SyncState::load(&state_path).await.unwrap(), state);
+
+    // Undated photos go last
+    let evicted = state
+        .enforce_size_budget(&SizeBudget::new(250))
+        .await
+        .unwrap();
+    assert_eq!(evicted, ["new"]);
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_size_budget_with_priorities() {
+    let dir = fresh_dir("size-priority").await;
+    let mut state = SyncState::new();
+    for guid in ["a", "b", "c"] {
+        mirrored(&mut state, &dir, guid, 100, None).await;
+    }
+    // Records of missing files don't count towards the budget
+    state.record(
+        "gone",
+        SyncedFile {
+            path: dir.join("gone.jpg"),
+            ..Default::default()
+        },
+    );
+
+    let keep_a = EvictionPolicy::LowestPriority(Arc::new(
+        |guid: &str, _: &SyncedFile| {
+            if guid == "a" {
+                10
+            } else {
+                0
+            }
+        },
+    ));
+    let evicted = state
+        .enforce_size_budget(&SizeBudget::new(150).with_policy(keep_a))
+        .await
+        .unwrap();
+    assert_eq!(evicted, ["b", "c"]);
+    assert!(dir.join("a.jpg").exists());
+    assert!(state.file("gone").is_some());
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_retention_evicts_photos_outside_the_window() {
+    let dir = fresh_dir("retention").await;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let day = 24 * 60 * 60;
+    let mut state = SyncState::new();
+    for (guid, taken_at) in [
+        ("recent", Some(now - 3 * day)),
+        ("old", Some(now - 100 * day)),
+        ("undated", None),
+    ] {
+        let path = dir.join(format!("{}.jpg", guid));
+        tokio::fs::write(&path, b"data").await.unwrap();
+        state.record(
+            guid,
+            SyncedFile {
+                path,
+                taken_at,
+                ..Default::default()
+            },
+        );
+    }
+
+    let retention = Retention::months(3);
+    assert_eq!(retention.max_age, Duration::from_secs(90 * day as u64));
+    let evicted = state.enforce_retention(&retention).await.unwrap();
+    assert_eq!(evicted, ["old"]);
+    assert!(!dir.join("old.jpg").exists());
+    assert_eq!(state.evictions["old"].reason, EvictionReason::Retention);
+    assert_eq!(state.evictions["old"].size, 4);
+    assert_eq!(state.plan("old", &dir, "old"), PathPlan::Evicted);
+
+    // Undated photos are never evicted by age
+    let evicted = state.enforce_retention(&Retention::days(1)).await.unwrap();
+    assert_eq!(evicted, ["recent"]);
+    assert!(dir.join("undated.jpg").exists());
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_removed_photos_are_quarantined() {
+    let dir = fresh_dir("quarantine").await;
+    let mut state = SyncState::new();
+    for guid in ["kept", "removed", "returning"] {
+        mirrored(&mut state, &dir, guid, 10, None).await;
+    }
+    // A record without a file is simply dropped
+    state.record(
+        "missing",
+        SyncedFile {
+            path: dir.join("missing.jpg"),
+            ..Default::default()
+        },
+    );
+
+    let quarantined = state.quarantine_removed(["kept"], &dir).await.unwrap();
+    assert_eq!(quarantined, ["removed", "returning"]);
+    let trash = dir.join(TRASH_DIR);
+    assert!(trash.join("removed.jpg").exists());
+    assert!(!dir.join("removed.jpg").exists());
+    assert!(dir.join("kept.jpg").exists());
+    assert_eq!(state.files.keys().collect::<Vec<_>>(), ["kept"]);
+    assert_eq!(
+        state.quarantine["removed"].trash_path,
+        trash.join("removed.jpg")
+    );
+
+    // The quarantine survives a save
+    let state_path = dir.join("state.json");
+    state.save(&state_path).await.unwrap();
+    let mut state = SyncState::load(&state_path).await.unwrap();
+
+    // A photo that comes back is restored from the trash
+    assert_eq!(
+        state
+            .reconcile("returning", &dir, "returning")
+            .await
+            .unwrap(),
+        PathPlan::Rename {
+            from: trash.join("returning.jpg"),
+            to: dir.join("returning.jpg"),
+        }
+    );
+    assert!(dir.join("returning.jpg").exists());
+    assert_eq!(
+        state.file("returning").unwrap().path,
+        dir.join("returning.jpg")
+    );
+    assert!(!state.quarantine.contains_key("returning"));
+
+    // Purging respects the retention period
+    let day = Duration::from_secs(24 * 60 * 60);
+    assert!(state.purge_quarantine(day).await.unwrap().is_empty());
+    assert!(trash.join("removed.jpg").exists());
+    assert_eq!(
+        state.purge_quarantine(Duration::ZERO).await.unwrap(),
+        ["removed"]
+    );
+    assert!(!trash.join("removed.jpg").exists());
+    assert!(state.quarantine.is_empty());
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_run_history() {
+    let dir = fresh_dir("history").await;
+    let state_path = dir.join("state.json");
+    let mut state = SyncState::new();
+
+    state.stream_ctag = Some("ctag1".into());
+    tokio::fs::write(dir.join("a.jpg"), b"12345").await.unwrap();
+    state.record(
+        "a",
+        SyncedFile::for_photo(&Image::default(), dir.join("a.jpg")),
+    );
+    let first = state.record_run(1).await.clone();
+    assert_eq!(first.photo_count, 1);
+    assert_eq!((first.file_count, first.bytes), (1, 5));
+
+    state.stream_ctag = Some("ctag2".into());
+    tokio::fs::write(dir.join("b.jpg"), b"123").await.unwrap();
+    state.record(
+        "b",
+        SyncedFile::for_photo(&Image::default(), dir.join("b.jpg")),
+    );
+    // Records of missing files don't count
+    state.record(
+        "c",
+        SyncedFile::for_photo(&Image::default(), dir.join("c.jpg")),
+    );
+    let second = state.record_run(3).await.clone();
+    assert_eq!(second.stream_ctag, Some("ctag2".into()));
+    assert_eq!((second.file_count, second.bytes), (2, 8));
+
+    state.save(&state_path).await.unwrap();
+    let loaded = SyncState::load(&state_path).await.unwrap();
+    assert_eq!(loaded.history, vec![first.clone(), second]);
+    assert_eq!(loaded.history_since(first.finished_at + 3600).len(), 0);
+    assert_eq!(loaded.history_since(0).len(), 2);
+
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+}
+
+#[tokio::test]
+async fn test_local_tags() {
+    let dir = fresh_dir("tags").await;
+    let state_path = dir.join("state.json");
+    let photo = |guid: &str| Image {
+        photo_guid: guid.to_string(),
+        ..Image::default()
+    };
+    let photos = vec![photo("a"), photo("b"), photo("c")];
+
+    let mut state = SyncState::new();
+    assert!(state.tag("a", " birthday "));
+    assert!(!state.tag("a", "birthday"));
+    assert!(!state.tag("a", "  "));
+    assert!(state.tag("a", "print"));
+    assert!(state.tag("c", "birthday"));
+    assert_eq!(
+        state.tags_of("a").collect::<Vec<_>>(),
+        ["birthday", "print"]
+    );
+    assert_eq!(state.tags_of("b").count(), 0);
+
+    let tagged: Vec<_> = state
+        .filter_tagged(&photos, "birthday")
+        .into_iter()
+        .map(|photo| photo.photo_guid)
+        .collect();
+    assert_eq!(tagged, ["a", "c"]);
+    let filter = state.tag_filter("print");
+    assert!(filter(&photos[0]) && !filter(&photos[2]));
+
+    // Tags survive photos leaving the state's files
+    state.record("a", SyncedFile::for_photo(&photos[0], dir.join("a.jpg")));
+    state.files.clear();
+    state.save(&state_path).await.unwrap();
+    let mut loaded = SyncState::load(&state_path).await.unwrap();
+    assert_eq!(loaded.tagged("birthday").len(), 2);
+
+    assert!(loaded.untag("a", "print"));
+    assert!(!loaded.untag("a", "print"));
+    assert!(!loaded.untag("b", "print"));
+    assert!(loaded.untag("c", "birthday"));
+    assert!(!loaded.tags.contains_key("c"));
+
+    let _ = tokio::fs::remove_dir_all(&dir).await;
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use common::fresh_dir;
+use icloud_album_rs::download::{download_photo_with_options, DownloadOptions};
+use icloud_album_rs::models::{Derivative, Image, Location, Metadata};
+use icloud_album_rs::takeout::{
+    metadata_path, photo_metadata, write_album_metadata, ALBUM_METADATA_FILE,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+const PNG_BYTES: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+#[test]
+fn test_photo_metadata() {
+    let photo = Image {
+        photo_guid: "guid1".to_string(),
+        caption: Some(" Sunset ".to_string()),
+        date_created: Some("2023-07-14T18:30:05Z".to_string()),
+        batch_date_created: Some("2024-02-29T00:00:00+01:00".to_string()),
+        contributor_full_name: Some("Jane Doe".to_string()),
+        location: Some(Location {
+            latitude: 41.5,
+            longitude: -87.25,
+            altitude: Some(180.0),
+        }),
+        ..Default::default()
+    };
+
+    let metadata = photo_metadata(&photo, "guid1.jpg");
+    assert_eq!(metadata["title"], "guid1.jpg");
+    assert_eq!(metadata["description"], "Sunset");
+    assert_eq!(
+        metadata["photoTakenTime"],
+        json!({ "timestamp": "1689359405", "formatted": "Jul 14, 2023, 6:30:05 PM UTC" })
+    );
+    assert_eq!(
+        metadata["creationTime"],
+        json!({ "timestamp": "1709161200", "formatted": "Feb 28, 2024, 11:00:00 PM UTC" })
+    );
+    assert_eq!(metadata["geoData"]["latitude"], 41.5);
+    assert_eq!(metadata["geoData"]["longitude"], -87.25);
+    assert_eq!(metadata["geoData"]["altitude"], 180.0);
+    assert_eq!(metadata["geoDataExif"], metadata["geoData"]);
+    assert_eq!(metadata["people"], json!([{ "name": "Jane Doe" }]));
+
+    // Unknown dates are left out and unknown locations are zero
+    let bare = photo_metadata(&Image::default(), "x.jpg");
+    assert!(bare.get("photoTakenTime").is_none());
+    assert!(bare.get("creationTime").is_none());
+    assert!(bare.get("people").is_none());
+    assert_eq!(bare["description"], "");
+    assert_eq!(bare["geoData"]["latitude"], 0.0);
+}
+
+#[test]
+fn test_metadata_path() {
+    assert_eq!(
+        metadata_path(Path::new("/a/IMG.1.jpg")),
+        PathBuf::from("/a/IMG.1.jpg.json")
+    );
+}
+
+#[tokio::test]
+async fn test_download_writes_takeout_metadata() {
+    let dir = fresh_dir("download").await;
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/photo.png")
+        .with_status(200)
+        .with_body(PNG_BYTES)
+        .create_async()
+        .await;
+
+    let mut derivatives = HashMap::new();
+    derivatives.insert(
+        "1".to_string(),
+        Derivative {
+            checksum: "c1".to_string(),
+            url: Some(format!("{}/photo.png", server.url())),
+            ..Default::default()
+        },
+    );
+    let photo = Image {
+        photo_guid: "guid1".to_string(),
+        caption: Some("Hello".to_string()),
+        derivatives,
+        ..Default::default()
+    };
+
+    let options = DownloadOptions::new().with_takeout_metadata(true);
+    let path = download_photo_with_options(&photo, None, &dir.to_string_lossy(), None, &options)
+        .await
+        .unwrap();
+
+    let sidecar = tokio::fs::read(metadata_path(Path::new(&path)))
+        .await
+        .unwrap();
+    let metadata: Value = serde_json::from_slice(&sidecar).unwrap();
+    assert_eq!(metadata["title"], "guid1_Hello.png");
+    assert_eq!(metadata["description"], "Hello");
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_write_album_metadata() {
+    let dir = fresh_dir("album").await;
+    let album: Metadata = serde_json::from_value(json!({
+        "streamName": "Family",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag123",
+        "itemsReturned": 2,
+        "locations": {}
+    }))
+    .unwrap();
+    let photos = vec![
+        Image {
+            batch_date_created: Some("2023-01-02T00:00:00Z".to_string()),
+            ..Default::default()
+        },
+        Image {
+            batch_date_created: Some("2023-01-01T00:00:00Z".to_string()),
+            ..Default::default()
+        },
+    ];
+
+    let path = write_album_metadata(&dir, &album, &photos).await.unwrap();
+    assert_eq!(path, dir.join(ALBUM_METADATA_FILE));
+    let metadata: Value = serde_json::from_slice(&tokio::fs::read(&path).await.unwrap()).unwrap();
+    assert_eq!(metadata["title"], "Family");
+    assert_eq!(
+        metadata["date"],
+        json!({ "timestamp": "1672531200", "formatted": "Jan 1, 2023, 12:00:00 AM UTC" })
+    );
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
@@ -0,0 +1,164 @@
+use icloud_album_rs::throttle::{
+    host_key, AdaptiveLimit, AimdConfig, CircuitBreakerConfig, Concurrency, HostLimits, Outcome,
+};
+use reqwest::StatusCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn config() -> AimdConfig {
+    AimdConfig {
+        min: 1,
+        max: 6,
+        initial: 4,
+        target_latency: Duration::from_secs(1),
+        backoff: 0.5,
+    }
+}
+
+#[test]
+fn test_outcome_from_status() {
+    let latency = Duration::from_millis(10);
+    assert_eq!(
+        Outcome::from_status(StatusCode::OK, latency),
+        Outcome::Success(latency)
+    );
+    assert_eq!(
+        Outcome::from_status(StatusCode::TOO_MANY_REQUESTS, latency),
+        Outcome::Throttled
+    );
+    assert_eq!(
+        Outcome::from_status(StatusCode::SERVICE_UNAVAILABLE, latency),
+        Outcome::Throttled
+    );
+    assert_eq!(
+        Outcome::from_status(StatusCode::BAD_GATEWAY, latency),
+        Outcome::Unavailable
+    );
+    assert_eq!(
+        Outcome::from_status(StatusCode::NOT_FOUND, latency),
+        Outcome::Failed
+    );
+}
+
+#[test]
+fn test_additive_increase_multiplicative_decrease() {
+    let limit = AdaptiveLimit::new(config());
+    assert_eq!(limit.limit(), 4);
+
+    // About one slot per round of fast responses
+    let fast = Outcome::Success(Duration::from_millis(100));
+    for _ in 0..4 {
+        limit.record(Instant::now(), fast);
+    }
+    assert_eq!(limit.limit(), 4);
+    limit.record(Instant::now(), fast);
+    assert_eq!(limit.limit(), 5);
+
+    // Throttling halves the limit
+    let started = Instant::now();
+    limit.record(started, Outcome::Throttled);
+    assert_eq!(limit.limit(), 2);
+
+    // Responses to requests started before the decrease don't decrease again
+    limit.record(started, Outcome::Throttled);
+    assert_eq!(limit.limit(), 2);
+
+    // Slow responses count as overload, other failures are ignored
+    limit.record(Instant::now(), Outcome::Failed);
+    assert_eq!(limit.limit(), 2);
+    limit.record(Instant::now(), Outcome::Success(Duration::from_secs(2)));
+    assert_eq!(limit.limit(), 1);
+
+    // The limit stays within its bounds
+    limit.record(Instant::now(), Outcome::Throttled);
+    assert_eq!(limit.limit(), 1);
+    for _ in 0..100 {
+        limit.record(Instant::now(), fast);
+    }
+    assert_eq!(limit.limit(), 6);
+}
+
+#[test]
+fn test_fixed_limit_never_changes() {
+    let limit = AdaptiveLimit::from_concurrency(&Concurrency::Fixed(3));
+    limit.record(Instant::now(), Outcome::Throttled);
+    limit.record(Instant::now(), Outcome::Success(Duration::ZERO));
+    assert_eq!(limit.limit(), 3);
+    assert_eq!(AdaptiveLimit::fixed(0).limit(), 1);
+}
+
+#[tokio::test]
+async fn test_acquire_waits_for_a_free_slot() {
+    let limit = Arc::new(AdaptiveLimit::fixed(2));
+    let first = limit.acquire().await;
+    let _second = limit.acquire().await;
+    assert_eq!(limit.in_flight(), 2);
+
+    let waiting = tokio::spawn({
+        let limit = Arc::clone(&limit);
+        async move {
+            let _permit = limit.acquire().await;
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!waiting.is_finished());
+
+    first.finish(Outcome::Success(Duration::ZERO));
+    tokio::time::timeout(Duration::from_secs(5), waiting)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(limit.in_flight(), 1);
+}
+
+#[test]
+fn test_host_key() {
+    assert_eq!(
+        host_key("https://cvws.icloud-content.com/B/abc?x=1"),
+        "cvws.icloud-content.com"
+    );
+    assert_eq!(host_key("http://127.0.0.1:8080/a.jpg"), "127.0.0.1:8080");
+    assert_eq!(host_key("not a url"), "");
+}
+
+#[tokio::test]
+async fn test_host_limits_are_isolated() {
+    let hosts = HostLimits::new(
+        Concurrency::Adaptive(config()),
+        CircuitBreakerConfig {
+            failure_threshold: 2,
+            cooldown: Duration::from_millis(50),
+        },
+    );
+    let healthy = hosts.limit("a.example");
+    let failing = hosts.limit("b.example");
+
+    // Throttling one host leaves the other alone
+    failing.record(Instant::now(), Outcome::Throttled);
+    assert_eq!(failing.limit(), 2);
+    assert_eq!(healthy.limit(), 4);
+    assert!(Arc::ptr_eq(&healthy, &hosts.limit("a.example")));
+
+    // Consecutive failures pause only the failing host
+    hosts.record("b.example", Outcome::Unavailable);
+    assert!(!hosts.is_paused("b.example"));
+    hosts.record("b.example", Outcome::Throttled);
+    assert!(hosts.is_paused("b.example"));
+    assert!(!hosts.is_paused("a.example"));
+
+    // After the cooldown, one more failure pauses the host again
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert!(!hosts.is_paused("b.example"));
+    hosts.record("b.example", Outcome::Unavailable);
+    assert!(hosts.is_paused("b.example"));
+
+    // A success closes the breaker for good
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    hosts.record("b.example", Outcome::Success(Duration::ZERO));
+    hosts.record("b.example", Outcome::Unavailable);
+    assert!(!hosts.is_paused("b.example"));
+
+    let concurrency = hosts.concurrency();
+    assert_eq!(concurrency["a.example"], 4);
+    assert_eq!(concurrency["b.example"], 2);
+}
@@ -0,0 +1,278 @@
+#![cfg(all(feature = "transcode", unix))]
+
+use icloud_album_rs::derivatives::SizeClass;
+use icloud_album_rs::download::{download_derivative_classes, DownloadOptions};
+use icloud_album_rs::models::{Derivative, ICloudResponse, Image, Metadata};
+use icloud_album_rs::slideshow::{build_slideshow, SlideOrder, SlideshowOptions};
+use icloud_album_rs::transcode::{
+    make_loop, needs_transcode, output_path, LoopFormat, LoopOptions, TranscodeConfig,
+    TranscodeStatus,
+};
+use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// ISO base media headers with a codec sample entry further into the file
+const QUICKTIME: &[u8] = b"\0\0\0\x14ftypqt  \0\0\0\0avc1";
+const H264_MP4: &[u8] = b"\0\0\0\x14ftypisom\0\0\0\0avc1";
+const HEVC_MP4: &[u8] = b"\0\0\0\x14ftypisom\0\0\0\0hvc1";
+const GIF: &[u8] = b"GIF89a\x01\0\x01\0\0\0\0;";
+const PNG: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+// Copies the input to the output (the last argument), recording the arguments
+const COPYING_FFMPEG: &str = r#"echo "$@" >> "$(dirname "$0")/args"
+while [ $# -gt 1 ]; do [ "$1" = "-i" ] && input="$2"; shift; done; cp "$input" "$1""#;
+
+/// Writes an executable stand-in for ffmpeg
+fn fake_ffmpeg(dir: &Path, body: &str) -> PathBuf {
+    let path = dir.join("ffmpeg");
+    std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+fn video(guid: &str, url: String) -> Image {
+    let mut derivatives = HashMap::new();
+    derivatives.insert(
+        "720p".to_string(),
+        Derivative {
+            checksum: guid.to_string(),
+            url: Some(url),
+            ..Default::default()
+        },
+    );
+    Image {
+        photo_guid: guid.to_string(),
+        derivatives,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_needs_transcode() {
+    assert!(needs_transcode(QUICKTIME));
+    assert!(needs_transcode(HEVC_MP4));
+    assert!(!needs_transcode(H264_MP4));
+
+    assert_eq!(
+        output_path(Path::new("/out/a_video.mov")),
+        Path::new("/out/a_video.mp4")
+    );
+    assert_eq!(
+        output_path(Path::new("/out/a_video.mp4")),
+        Path::new("/out/a_video_h264.mp4")
+    );
+}
+
+#[tokio::test]
+async fn test_download_transcodes_videos() {
+    let mut server = mockito::Server::new_async().await;
+    let dir = std::env::temp_dir().join(format!("transcode-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let output_dir = dir.join("out").to_string_lossy().into_owned();
+    for (path, body) in [("/qt", QUICKTIME), ("/h264", H264_MP4), ("/hevc", HEVC_MP4)] {
+        server
+            .mock("GET", path)
+            .with_body(body)
+            .create_async()
+            .await;
+    }
+    let photos = [
+        video("qt", format!("{}/qt", server.url())),
+        video("h264", format!("{}/h264", server.url())),
+        video("hevc", format!("{}/hevc", server.url())),
+    ];
+
+    let ffmpeg = fake_ffmpeg(&dir, COPYING_FFMPEG);
+    let options =
+        DownloadOptions::new().with_transcode(TranscodeConfig::new().with_ffmpeg(&ffmpeg));
+    let report = download_derivative_classes(&photos, &output_dir, &[SizeClass::Video], &options)
+        .await
+        .unwrap();
+
+    assert_eq!(report.transcodes.len(), 3);
+    let qt = &report.transcodes[0];
+    assert_eq!(qt.photo_guid, "qt");
+    assert!(qt.source.ends_with("qt_video.mov"));
+    let transcoded = format!("{}/qt_video.mp4", output_dir);
+    assert_eq!(
+        qt.status,
+        TranscodeStatus::Transcoded {
+            path: transcoded.clone()
+        }
+    );
+    // The download points at the MP4, and the QuickTime file is gone
+    assert_eq!(report.downloaded[0].path, transcoded);
+    assert_eq!(std::fs::read(&transcoded).unwrap(), QUICKTIME);
+    assert!(!Path::new(&qt.source).exists());
+
+    assert_eq!(report.transcodes[1].status, TranscodeStatus::Compatible);
+    assert!(report.downloaded[1].path.ends_with("h264_video.mp4"));
+    assert!(matches!(
+        &report.transcodes[2].status,
+        TranscodeStatus::Transcoded { path } if path.ends_with("hevc_video_h264.mp4")
+    ));
+
+    // A failing ffmpeg keeps the download and reports the error
+    let ffmpeg = fake_ffmpeg(&dir, "echo 'Unknown encoder' >&2; exit 1");
+    let options =
+        DownloadOptions::new().with_transcode(TranscodeConfig::new().with_ffmpeg(&ffmpeg));
+    let report =
+        download_derivative_classes(&photos[..1], &output_dir, &[SizeClass::Video], &options)
+            .await
+            .unwrap();
+    match &report.transcodes[0].status {
+        TranscodeStatus::Failed(message) => {
+            assert!(message.contains("Unknown encoder"), "{message}")
+        }
+        status => panic!("unexpected status {:?}", status),
+    }
+    assert!(report.downloaded[0].path.ends_with("qt_video.mov"));
+    assert!(Path::new(&report.downloaded[0].path).exists());
+    // No partial output is left behind
+    let leftovers: Vec<_> = std::fs::read_dir(&output_dir)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(".part"))
+        .collect();
+    assert!(leftovers.is_empty());
+
+    // A missing binary is reported too
+    let options = DownloadOptions::new()
+        .with_transcode(TranscodeConfig::new().with_ffmpeg(dir.join("missing-ffmpeg")));
+    let report =
+        download_derivative_classes(&photos[..1], &output_dir, &[SizeClass::Video], &options)
+            .await
+            .unwrap();
+    assert!(matches!(
+        &report.transcodes[0].status,
+        TranscodeStatus::Failed(message) if message.starts_with("Could not run")
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_make_loop() {
+    let dir = std::env::temp_dir().join(format!("loop-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let ffmpeg = fake_ffmpeg(&dir, COPYING_FFMPEG);
+    let source = dir.join("live.gif");
+    std::fs::write(&source, GIF).unwrap();
+
+    let options = LoopOptions::new()
+        .with_ffmpeg(&ffmpeg)
+        .with_format(LoopFormat::WebP)
+        .with_limits(Duration::from_millis(2500), 481);
+    let output = dir.join(format!("live.{}", options.format.extension()));
+    make_loop(&source, &output, &options).await.unwrap();
+    assert_eq!(std::fs::read(&output).unwrap(), GIF);
+
+    let args = std::fs::read_to_string(dir.join("args")).unwrap();
+    assert!(args.contains("-t 2.500 -an"), "{args}");
+    assert!(args.contains("scale='min(480,trunc(iw/2)*2)':-2"), "{args}");
+    assert!(args.contains("-c:v libwebp -loop 0 -f webp"), "{args}");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_slideshow_motion_clips() {
+    let mut server = mockito::Server::new_async().await;
+    let dir = std::env::temp_dir().join(format!("motion-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (path, body) in [("/still", PNG), ("/live", QUICKTIME), ("/animated", GIF)] {
+        server
+            .mock("GET", path)
+            .with_body(body)
+            .create_async()
+            .await;
+    }
+
+    let still = |guid: &str, path: &str| {
+        let mut derivatives = HashMap::new();
+        derivatives.insert(
+            "1280".to_string(),
+            Derivative {
+                checksum: guid.to_string(),
+                width: Some(1280),
+                height: Some(960),
+                url: Some(format!("{}{}", server.url(), path)),
+                ..Default::default()
+            },
+        );
+        Image {
+            photo_guid: guid.to_string(),
+            derivatives,
+            ..Default::default()
+        }
+    };
+    // A Live Photo: a still with a video component
+    let mut live = still("live", "/still");
+    live.derivatives
+        .extend(video("live", format!("{}/live", server.url())).derivatives);
+    let metadata: Metadata = serde_json::from_value(serde_json::json!({
+        "streamName": "Motion",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "ctag",
+        "itemsReturned": 3,
+        "locations": {}
+    }))
+    .unwrap();
+    let response = ICloudResponse {
+        metadata,
+        photos: vec![live, still("gif", "/animated"), still("plain", "/still")],
+        unparsed: Vec::new(),
+    };
+
+    let ffmpeg = fake_ffmpeg(&dir, COPYING_FFMPEG);
+    let options = SlideshowOptions::new(1280, 960)
+        .with_order(SlideOrder::Album)
+        .with_motion(LoopOptions::new().with_ffmpeg(&ffmpeg));
+    let output_dir = dir.join("slides");
+    let manifest = build_slideshow(&response, &output_dir, &options, None)
+        .await
+        .unwrap();
+
+    let motion: Vec<Option<&str>> = manifest
+        .slides
+        .iter()
+        .map(|slide| slide.motion.as_deref())
+        .collect();
+    assert_eq!(
+        motion,
+        [
+            Some("live_slide-0001-motion.mp4"),
+            Some("gif_slide-0002-motion.mp4"),
+            None
+        ]
+    );
+    // The Live Photo clip is made from the video component, which is not kept
+    assert_eq!(
+        std::fs::read(output_dir.join("live_slide-0001-motion.mp4")).unwrap(),
+        QUICKTIME
+    );
+    let mut files: Vec<String> = std::fs::read_dir(&output_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            "gif_slide-0002-motion.mp4",
+            "gif_slide-0002.gif",
+            "live_slide-0001-motion.mp4",
+            "live_slide-0001.png",
+            "plain_slide-0003.png",
+            "slideshow.json"
+        ]
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
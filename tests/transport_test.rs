@@ -0,0 +1,108 @@
+use icloud_album_rs::get_icloud_photos;
+use icloud_album_rs::transport::{
+    with_transport, MockTransport, Transport, TransportRequest, TransportResponse, FIXTURES,
+};
+use std::sync::Arc;
+
+#[tokio::test]
+async fn test_family_fixture_serves_album() {
+    let transport = Arc::new(MockTransport::with_fixture("family").unwrap());
+    let album = with_transport(transport.clone(), get_icloud_photos("B0z5qAGN1JIFd3y"))
+        .await
+        .unwrap();
+
+    assert_eq!(album.metadata.stream_name, "Summer Trip");
+    assert_eq!(album.metadata.items_returned, 3);
+    assert_eq!(album.photos.len(), 3);
+    assert!(album
+        .photos
+        .iter()
+        .all(|photo| photo.derivatives.values().any(|d| d.url.is_some())));
+
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[0]
+        .url
+        .ends_with("/B0z5qAGN1JIFd3y/sharedstreams/webstream"));
+    assert!(requests[1].url.ends_with("/webasseturls"));
+}
+
+#[tokio::test]
+async fn test_fixture_is_deterministic() {
+    let first = with_transport(
+        Arc::new(MockTransport::with_fixture("family").unwrap()),
+        get_icloud_photos("token"),
+    )
+    .await
+    .unwrap();
+    let second = with_transport(
+        Arc::new(MockTransport::with_fixture("family").unwrap()),
+        get_icloud_photos("token"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        serde_json::to_value(&first.photos).unwrap(),
+        serde_json::to_value(&second.photos).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_empty_fixture() {
+    let transport = Arc::new(MockTransport::with_fixture("empty").unwrap());
+    let album = with_transport(transport, get_icloud_photos("token"))
+        .await
+        .unwrap();
+    assert!(album.photos.is_empty());
+}
+
+#[tokio::test]
+async fn test_redirect_fixture_follows_partition_host() {
+    let transport = Arc::new(MockTransport::with_fixture("redirect").unwrap());
+    let album = with_transport(transport.clone(), get_icloud_photos("token"))
+        .await
+        .unwrap();
+    assert_eq!(album.metadata.stream_name, "Summer Trip");
+
+    let requests = transport.requests();
+    assert!(requests
+        .iter()
+        .skip(1)
+        .all(|request| request.url.contains("p99-sharedstreams.icloud.com")));
+}
+
+#[test]
+fn test_every_listed_fixture_loads() {
+    for name in FIXTURES {
+        assert!(MockTransport::with_fixture(name).is_ok(), "{name}");
+    }
+    let err = MockTransport::with_fixture("missing").unwrap_err();
+    assert_eq!(err.to_string(), "Unknown fixture: missing");
+}
+
+#[test]
+fn test_routes_and_unmatched_requests() {
+    let transport = MockTransport::new()
+        .with_route("POST", "/webstream", TransportResponse::new(500, "first"))
+        .with_route("post", "/webstream", TransportResponse::new(200, "second"));
+    let request = |method: &str, url: &str| TransportRequest {
+        method: method.to_string(),
+        url: url.to_string(),
+        body: None,
+    };
+
+    let routed = transport
+        .respond(&request(
+            "POST",
+            "https://example.com/t/sharedstreams/webstream",
+        ))
+        .unwrap();
+    assert_eq!(routed.status, 200);
+    assert_eq!(routed.body, b"second");
+
+    let unmatched = transport
+        .respond(&request("GET", "https://example.com/photo.jpg"))
+        .unwrap();
+    assert_eq!(unmatched.status, 404);
+    assert_eq!(transport.requests().len(), 2);
+}
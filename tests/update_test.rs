@@ -0,0 +1,109 @@
+use icloud_album_rs::update::{
+    check_for_update_at, latest_version, UpdateError, UpdateInfo, Version, CURRENT_VERSION,
+};
+use serde_json::json;
+
+fn version(s: &str) -> Version {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_parses_and_orders_versions() {
+    assert_eq!(
+        version("1.2.3-rc.1+build.5"),
+        Version {
+            major: 1,
+            minor: 2,
+            patch: 3,
+            pre: Some("rc.1".to_string()),
+        }
+    );
+    assert_eq!(version(CURRENT_VERSION), Version::current());
+    assert!(version("0.10.0") > version("0.9.9"));
+    assert!(version("1.0.0-rc.1") < version("1.0.0"));
+    assert!(version("1.0.0-rc.1") < version("1.0.0-rc.2"));
+    assert_eq!(version("2.0.0-beta").to_string(), "2.0.0-beta");
+    for invalid in ["", "1.2", "1.2.3.4", "1.x.3", "1.2.3-"] {
+        assert!(invalid.parse::<Version>().is_err(), "{:?}", invalid);
+    }
+}
+
+#[test]
+fn test_latest_version_skips_yanked_and_pre_releases() {
+    let body = json!({ "crate": { "max_stable_version": "0.7.1" } });
+    assert_eq!(latest_version(&body).unwrap(), version("0.7.1"));
+
+    let body = json!({
+        "crate": { "max_stable_version": null },
+        "versions": [
+            { "num": "0.9.0", "yanked": true },
+            { "num": "0.8.0-rc.1", "yanked": false },
+            { "num": "0.7.2", "yanked": false },
+            { "num": "0.7.10", "yanked": false }
+        ]
+    });
+    assert_eq!(latest_version(&body).unwrap(), version("0.7.10"));
+    assert!(matches!(
+        latest_version(&json!({})),
+        Err(UpdateError::InvalidResponse(_))
+    ));
+}
+
+#[test]
+fn test_instructions_depend_on_breaking_changes() {
+    let update = |current: &str, latest: &str| UpdateInfo {
+        current: version(current),
+        latest: version(latest),
+    };
+    assert!(!update("0.5.0", "0.5.3").is_breaking());
+    assert!(update("0.5.0", "0.6.0").is_breaking());
+    assert!(!update("1.2.0", "1.4.0").is_breaking());
+    assert!(update("1.2.0", "2.0.0").is_breaking());
+
+    let text = update("0.5.0", "0.5.3").instructions();
+    assert!(text
+        .starts_with("A newer version of icloud-album-rs is available: 0.5.3 (running 0.5.0)\n"));
+    assert!(text.contains("cargo update -p icloud-album-rs"));
+    let text = update("0.5.0", "0.6.0").instructions();
+    assert!(text.contains("`icloud-album-rs = \"0.6.0\"`"));
+}
+
+#[tokio::test]
+async fn test_check_for_update_queries_registry() {
+    let mut server = mockito::Server::new_async().await;
+    let url = format!("{}/api/v1/crates/icloud-album-rs", server.url());
+    let client = reqwest::Client::new();
+
+    let newer = server
+        .mock("GET", "/api/v1/crates/icloud-album-rs")
+        .match_header(
+            "user-agent",
+            mockito::Matcher::Regex(format!("^icloud-album-rs/{} ", CURRENT_VERSION)),
+        )
+        .with_body(json!({ "crate": { "max_stable_version": "999.0.0" } }).to_string())
+        .create_async()
+        .await;
+    let update = check_for_update_at(&client, &url).await.unwrap().unwrap();
+    assert_eq!(update.current, Version::current());
+    assert_eq!(update.latest, version("999.0.0"));
+    newer.assert_async().await;
+    newer.remove_async().await;
+
+    let same = server
+        .mock("GET", "/api/v1/crates/icloud-album-rs")
+        .with_body(json!({ "crate": { "max_stable_version": CURRENT_VERSION } }).to_string())
+        .create_async()
+        .await;
+    assert!(check_for_update_at(&client, &url).await.unwrap().is_none());
+    same.remove_async().await;
+
+    let _missing = server
+        .mock("GET", "/api/v1/crates/icloud-album-rs")
+        .with_status(404)
+        .create_async()
+        .await;
+    assert!(matches!(
+        check_for_update_at(&client, &url).await,
+        Err(UpdateError::Status(404))
+    ));
+}
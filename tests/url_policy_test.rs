@@ -0,0 +1,255 @@
+use icloud_album_rs::api::{parse_webasseturls_response_with_policy, ApiError};
+use icloud_album_rs::fetch::FetchOptions;
+use icloud_album_rs::url_policy::{UrlPolicy, UrlPolicyError};
+use serde_json::json;
+
+#[test]
+fn test_default_policy_allows_apple_hosts() {
+    let policy = UrlPolicy::default();
+    assert!(policy.allows_host("cvws.icloud-content.com"));
+    assert!(policy.allows_host("p42-sharedstreams.icloud.com"));
+    assert!(policy.allows_host("ICLOUD.COM."));
+    assert!(!policy.allows_host("evilicloud.com"));
+    assert!(!policy.allows_host("icloud.com.evil.net"));
+
+    let url = policy
+        .asset_url("cvws.icloud-content.com", "/S/abc/IMG.JPG?o=x&e=1")
+        .unwrap();
+    assert_eq!(
+        url.as_str(),
+        "https://cvws.icloud-content.com/S/abc/IMG.JPG?o=x&e=1"
+    );
+}
+
+#[test]
+fn test_asset_url_rejections() {
+    let policy = UrlPolicy::default();
+    assert!(matches!(
+        policy.asset_url("example.com", "/a.jpg"),
+        Err(UrlPolicyError::DisallowedHost(_))
+    ));
+    for location in ["evil.com@icloud.com", "icloud.com:8080", "", "icloud.com/x"] {
+        assert!(
+            matches!(
+                policy.asset_url(location, "/a.jpg"),
+                Err(UrlPolicyError::Malformed(_))
+            ),
+            "{}",
+            location
+        );
+    }
+    for path in [
+        "a.jpg",
+        "//evil.com/a.jpg",
+        "/../etc/passwd",
+        "/a\\b",
+        "/a b",
+    ] {
+        assert!(
+            matches!(
+                policy.asset_url("cvws.icloud-content.com", path),
+                Err(UrlPolicyError::InvalidPath(_))
+            ),
+            "{}",
+            path
+        );
+    }
+
+    assert!(matches!(
+        policy.validate("http://cvws.icloud-content.com/a.jpg"),
+        Err(UrlPolicyError::InsecureScheme(_))
+    ));
+    assert!(UrlPolicy::permissive()
+        .validate("http://127.0.0.1:8080/a.jpg")
+        .is_ok());
+}
+
+#[test]
+fn test_custom_allowlist() {
+    let policy = UrlPolicy::new().with_allowed_hosts(["*.example.org"]);
+    assert!(policy.asset_url("cdn.example.org", "/a.jpg").is_ok());
+    assert!(policy
+        .asset_url("cvws.icloud-content.com", "/a.jpg")
+        .is_err());
+}
+
+#[test]
+fn test_parse_rejects_foreign_hosts() {
+    let response = json!({
+        "items": {
+            "good": { "url_location": "cvws.icloud-content.com", "url_path": "/a.jpg" },
+            "bad": { "url_location": "attacker.example", "url_path": "/b.jpg" }
+        }
+    });
+
+    let err =
+        parse_webasseturls_response_with_policy(&response, &UrlPolicy::default()).unwrap_err();
+    match err {
+        ApiError::UrlRejected { guid, reason } => {
+            assert_eq!(guid, "bad");
+            assert_eq!(
+                reason,
+                UrlPolicyError::DisallowedHost("attacker.example".to_string())
+            );
+        }
+        other => panic!("unexpected error: {}", other),
+    }
+
+    let urls =
+        parse_webasseturls_response_with_policy(&response, &UrlPolicy::permissive()).unwrap();
+    assert_eq!(urls.len(), 2);
+}
+
+#[test]
+fn test_validate_token() {
+    use icloud_album_rs::url_policy::validate_token;
+
+    assert!(validate_token("B0z5qAGN1JIFd3y").is_ok());
+    for token in ["", "abc/def", "abc%2F", "a b", "abc?x=1", &"a".repeat(65)] {
+        assert!(
+            matches!(validate_token(token), Err(UrlPolicyError::InvalidToken(_))),
+            "{}",
+            token
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_hardened_mode_rejects_bad_token_without_requests() {
+    let options = FetchOptions::new().with_url_policy(UrlPolicy::default());
+    let err = icloud_album_rs::get_icloud_photos_with("../evil", &options)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Invalid album token"));
+}
+
+#[tokio::test]
+async fn test_client_refuses_redirects_to_other_hosts() {
+    let mut server = mockito::Server::new_async().await;
+    let port = server.socket_address().port();
+    let redirect = server
+        .mock("GET", "/start")
+        .with_status(302)
+        .with_header("location", &format!("http://localhost:{}/end", port))
+        .create_async()
+        .await;
+    let allowed = server
+        .mock("GET", "/hop")
+        .with_status(302)
+        .with_header("location", &format!("http://127.0.0.1:{}/end", port))
+        .create_async()
+        .await;
+    let end = server
+        .mock("GET", "/end")
+        .with_body("ok")
+        .expect(1)
+        .create_async()
+        .await;
+
+    let policy = UrlPolicy::new()
+        .with_allowed_hosts(["127.0.0.1"])
+        .with_require_https(false);
+    let client = policy.build_client().unwrap();
+
+    let result = client
+        .get(format!("http://127.0.0.1:{}/start", port))
+        .send()
+        .await;
+    assert!(result.unwrap_err().is_redirect());
+
+    let body = client
+        .get(format!("http://127.0.0.1:{}/hop", port))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(body, "ok");
+
+    redirect.assert_async().await;
+    allowed.assert_async().await;
+    end.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_download_refuses_disallowed_url() {
+    use icloud_album_rs::download::{download_photo_with_options, DownloadOptions};
+    use icloud_album_rs::models::{Derivative, Image};
+
+    let mut photo = Image {
+        photo_guid: "guid1".to_string(),
+        ..Default::default()
+    };
+    photo.derivatives.insert(
+        "1".to_string(),
+        Derivative {
+            checksum: "c1".to_string(),
+            file_size: Some(10),
+            width: Some(10),
+            height: Some(10),
+            url: Some("http://127.0.0.1:1/a.jpg".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let dir = std::env::temp_dir().join(format!("icloud-url-policy-{}", std::process::id()));
+    let options = DownloadOptions::new().with_url_policy(UrlPolicy::default());
+    let err = download_photo_with_options(&photo, None, dir.to_str().unwrap(), None, &options)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("https"));
+    assert!(!dir.exists());
+}
+
+#[tokio::test]
+async fn test_fetch_options_url_policy_checks_asset_urls() {
+    use icloud_album_rs::fetch::FetchOptions;
+
+    let mut server = mockito::Server::new_async().await;
+    let base_url = format!("{}/B0z5qAGN1JIFd3y/sharedstreams/", server.url());
+    let _webstream = server
+        .mock("POST", "/B0z5qAGN1JIFd3y/sharedstreams/webstream")
+        .with_body(
+            json!({
+                "streamName": "Album",
+                "photos": [{
+                    "photoGuid": "photo1",
+                    "derivatives": { "342": { "checksum": "small1", "fileSize": "90" } }
+                }]
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+    let _urls = server
+        .mock("POST", "/B0z5qAGN1JIFd3y/sharedstreams/webasseturls")
+        .with_body(
+            json!({
+                "items": {
+                    "small1": { "url_location": "evil.example.com", "url_path": "/small1.jpg" }
+                }
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+
+    let policy = UrlPolicy::default()
+        .with_allowed_hosts(["127.0.0.1", "icloud-content.com"])
+        .with_require_https(false);
+    let options = FetchOptions::new()
+        .with_base_url(&base_url)
+        .with_url_policy(policy.clone());
+    let err = icloud_album_rs::get_icloud_photos_with("B0z5qAGN1JIFd3y", &options)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("evil.example.com"), "{}", err);
+
+    // A token the policy rejects fails before any request
+    let options = FetchOptions::new().with_url_policy(policy);
+    let err = icloud_album_rs::get_icloud_photos_with("../evil", &options)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Invalid album token"));
+}
@@ -20,6 +20,31 @@ fn test_extension_from_mime_type() {
     );
 }
 
+#[test]
+fn test_get_extension_for_derivative() {
+    let movie = Derivative {
+        media_type: Some("com.apple.quicktime-movie".to_string()),
+        ..Default::default()
+    };
+    // Unrecognized content takes the declared type instead of defaulting to .jpg
+    assert_eq!(
+        utils::get_extension_for_derivative(&[0; 16], &movie),
+        ".mov"
+    );
+    assert_eq!(
+        utils::get_extension_for_derivative(&[0; 16], &Derivative::default()),
+        ".jpg"
+    );
+    // Recognized content wins over the declared type
+    let png_bytes = [
+        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D,
+    ];
+    assert_eq!(
+        utils::get_extension_for_derivative(&png_bytes, &movie),
+        ".png"
+    );
+}
+
 #[test]
 fn test_detect_mime_type() {
     // JPEG test data (FF D8 FF)
@@ -75,6 +100,7 @@ fn test_select_best_derivative() {
         width: Some(800),
         height: Some(600),
         url: Some("https://example.com/image1.jpg".to_string()),
+        ..Default::default()
     };
 
     let mut derivative2 = Derivative {
@@ -83,6 +109,7 @@ fn test_select_best_derivative() {
         width: Some(1600),
         height: Some(1200),
         url: Some("https://example.com/image2.jpg".to_string()),
+        ..Default::default()
     };
 
     let mut derivative3 = Derivative {
@@ -91,6 +118,7 @@ fn test_select_best_derivative() {
         width: Some(3200),
         height: Some(2400),
         url: Some("https://example.com/image3.jpg".to_string()),
+        ..Default::default()
     };
 
     // Test 1: Basic resolution comparison
@@ -159,3 +187,234 @@ fn test_select_best_derivative() {
     let (key, _der, _url) = result.unwrap();
     assert_eq!(key, "original"); // Should prioritize the one with "original" in key
 }
+
+#[test]
+fn test_select_thumbnail_derivative() {
+    let derivative = |width, height, file_size| Derivative {
+        checksum: format!("checksum{}", width),
+        file_size: Some(file_size),
+        width: Some(width),
+        height: Some(height),
+        url: None,
+        ..Default::default()
+    };
+
+    let mut derivatives = HashMap::new();
+    derivatives.insert("342".to_string(), derivative(342, 256, 20000));
+    derivatives.insert("2049".to_string(), derivative(2049, 1536, 400000));
+    derivatives.insert("360p".to_string(), derivative(100, 100, 90000));
+
+    // The video rendition is skipped even though it is smallest
+    let (key, thumbnail) = utils::select_thumbnail_derivative(&derivatives).unwrap();
+    assert_eq!(key, "342");
+    assert_eq!(thumbnail.checksum, "checksum342");
+
+    // Without dimensions the smallest file wins
+    let mut no_dimensions = derivative(0, 0, 5000);
+    no_dimensions.width = None;
+    no_dimensions.height = None;
+    derivatives.clear();
+    derivatives.insert("a".to_string(), no_dimensions);
+    derivatives.insert("b".to_string(), {
+        let mut d = derivative(0, 0, 9000);
+        d.width = None;
+        d
+    });
+    assert_eq!(
+        utils::select_thumbnail_derivative(&derivatives).unwrap().0,
+        "a"
+    );
+
+    assert!(utils::select_thumbnail_derivative(&HashMap::new()).is_none());
+}
+
+#[test]
+fn test_sniff_mime_type() {
+    let heic = [
+        0x00, 0x00, 0x00, 0x18, 0x66, 0x74, 0x79, 0x70, 0x68, 0x65, 0x69, 0x63,
+    ];
+    assert_eq!(utils::sniff_mime_type(&heic), Some("image/heic"));
+    assert_eq!(utils::detect_mime_type(&heic, None), "image/heic");
+
+    let heif = [
+        0x00, 0x00, 0x00, 0x18, 0x66, 0x74, 0x79, 0x70, 0x6D, 0x69, 0x66, 0x31,
+    ];
+    assert_eq!(utils::sniff_mime_type(&heif), Some("image/heif"));
+
+    // HEIC photos used to be detected as MP4 videos; every brand is pinned
+    for (brand, mime_type, extension) in [
+        (b"heic", "image/heic", ".heic"),
+        (b"heix", "image/heic", ".heic"),
+        (b"heif", "image/heif", ".heif"),
+        (b"mif1", "image/heif", ".heif"),
+        (b"qt  ", "video/quicktime", ".mov"),
+        (b"isom", "video/mp4", ".mp4"),
+    ] {
+        let mut bytes = vec![0x00, 0x00, 0x00, 0x18];
+        bytes.extend_from_slice(b"ftyp");
+        bytes.extend_from_slice(brand);
+        assert_eq!(utils::sniff_mime_type(&bytes), Some(mime_type));
+        assert_eq!(utils::get_extension_for_content(&bytes, None), extension);
+    }
+
+    // No fallback to the filename or a default
+    assert_eq!(utils::sniff_mime_type(b"plain text content"), None);
+    assert_eq!(utils::sniff_mime_type(&[0xFF, 0xD8]), None);
+}
+
+#[test]
+fn test_anonymize_response() {
+    use serde_json::json;
+
+    let webstream = json!({
+        "streamName": "Family Trip",
+        "userFirstName": "Jane",
+        "userLastName": "Doe",
+        "streamCtag": "FT;123",
+        "locations": { "photo1": { "latitude": 41.88, "longitude": -87.62 } },
+        "photoGuids": ["guid-a", "guid-b"],
+        "photos": [
+            {
+                "photoGuid": "guid-a",
+                "caption": "At grandma's house",
+                "contributorFullName": "Jane Doe",
+                "dateCreated": "2023-01-01T00:00:00Z",
+                "derivatives": { "1242": { "checksum": "sum-a", "fileSize": 100 } }
+            },
+            {
+                "photoGuid": "guid-b",
+                "derivatives": { "1242": { "checksum": "sum-b" } }
+            }
+        ]
+    });
+    let webasseturls = json!({
+        "items": {
+            "sum-a": { "url_location": "cvws.icloud-content.com", "url_path": "/S/a.jpg?o=sig&e=1" }
+        }
+    });
+
+    let mut anonymizer = utils::ResponseAnonymizer::new();
+    let webstream = anonymizer.anonymize(webstream);
+    let webasseturls = anonymizer.anonymize(webasseturls);
+
+    let text = format!("{} {}", webstream, webasseturls);
+    for secret in [
+        "Family Trip",
+        "Jane",
+        "grandma",
+        "guid-a",
+        "sum-a",
+        "sig",
+        "41.88",
+    ] {
+        assert!(!text.contains(secret), "{} leaked: {}", secret, text);
+    }
+
+    // Identifiers are replaced consistently, within and across responses
+    assert_eq!(
+        webstream["photoGuids"][0],
+        webstream["photos"][0]["photoGuid"]
+    );
+    assert_ne!(webstream["photoGuids"][0], webstream["photoGuids"][1]);
+    let checksum = webstream["photos"][0]["derivatives"]["1242"]["checksum"]
+        .as_str()
+        .unwrap();
+    let item = &webasseturls["items"][checksum];
+    assert_eq!(item["url_location"], "cvws.icloud-content.com");
+    assert_eq!(item["url_path"], "/S/a.jpg?anonymized");
+
+    // Non-personal data is kept
+    assert_eq!(
+        webstream["photos"][0]["dateCreated"],
+        "2023-01-01T00:00:00Z"
+    );
+    assert_eq!(
+        webstream["photos"][0]["derivatives"]["1242"]["fileSize"],
+        100
+    );
+
+    // The shorthand produces the same shape
+    assert_eq!(
+        utils::anonymize_response(json!({ "photoGuid": "x" }))["photoGuid"],
+        "ANON000001"
+    );
+}
+
+#[test]
+fn test_cluster_bursts() {
+    use icloud_album_rs::models::Image;
+
+    let shot = |guid: &str, date: Option<&str>, size: u32| Image {
+        photo_guid: guid.to_string(),
+        date_created: date.map(String::from),
+        width: Some(size),
+        height: Some(size),
+        ..Default::default()
+    };
+    let photos = vec![
+        shot("late", Some("2023-06-01T12:10:00Z"), 100),
+        shot("burst-b", Some("2023-06-01T12:00:02Z"), 300),
+        shot("burst-a", Some("2023-06-01T12:00:00Z"), 200),
+        shot("undated", None, 50),
+        shot("burst-c", Some("2023-06-01T12:00:04Z"), 300),
+    ];
+
+    let guids = |bursts: Vec<Vec<&Image>>| -> Vec<Vec<String>> {
+        bursts
+            .into_iter()
+            .map(|burst| burst.iter().map(|p| p.photo_guid.clone()).collect())
+            .collect()
+    };
+
+    // Gaps of two seconds chain into one burst
+    assert_eq!(
+        guids(utils::cluster_bursts(&photos, 2)),
+        vec![
+            vec!["burst-a", "burst-b", "burst-c"],
+            vec!["late"],
+            vec!["undated"]
+        ]
+    );
+    assert_eq!(utils::cluster_bursts(&photos, 1).len(), 5);
+
+    // The largest photo wins, ties go to the earliest
+    let best: Vec<&str> = utils::best_of_bursts(&photos, 2)
+        .iter()
+        .map(|p| p.photo_guid.as_str())
+        .collect();
+    assert_eq!(best, vec!["burst-b", "late", "undated"]);
+}
+
+#[test]
+fn test_select_per_day() {
+    use icloud_album_rs::models::Image;
+    use icloud_album_rs::utils::DayRanking;
+
+    let shot = |guid: &str, date: Option<&str>, size: u32| Image {
+        photo_guid: guid.to_string(),
+        date_created: date.map(String::from),
+        width: Some(size),
+        height: Some(size),
+        ..Default::default()
+    };
+    let photos = vec![
+        shot("mon-morning-small", Some("2023-06-05T08:00:00Z"), 100),
+        shot("sun-only", Some("2023-06-04T09:00:00Z"), 50),
+        shot("mon-noon-large", Some("2023-06-05T12:00:00Z"), 400),
+        shot("mon-evening-medium", Some("2023-06-05T20:00:00Z"), 200),
+        shot("undated", None, 1000),
+    ];
+    let guids = |selected: Vec<&Image>| -> Vec<String> {
+        selected.iter().map(|p| p.photo_guid.clone()).collect()
+    };
+
+    assert_eq!(
+        guids(utils::select_per_day(&photos, 2, DayRanking::Resolution)),
+        vec!["sun-only", "mon-noon-large", "mon-evening-medium"]
+    );
+    assert_eq!(
+        guids(utils::select_per_day(&photos, 1, DayRanking::Recency)),
+        vec!["sun-only", "mon-evening-medium"]
+    );
+    assert!(utils::select_per_day(&photos, 0, DayRanking::Resolution).is_empty());
+}
@@ -0,0 +1,106 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::photo;
+use icloud_album_rs::models::Image;
+use icloud_album_rs::sync::{SyncState, SyncedFile};
+use icloud_album_rs::views::{build_views, ViewOptions};
+use std::path::PathBuf;
+
+fn contributed(guid: &str, date: Option<&str>, contributor: Option<&str>) -> Image {
+    Image {
+        date_created: date.map(|d| d.to_string()),
+        contributor_full_name: contributor.map(|c| c.to_string()),
+        ..photo(guid)
+    }
+}
+
+async fn setup(name: &str) -> (PathBuf, Vec<Image>, SyncState) {
+    let root = std::env::temp_dir().join(format!("icloud-views-{}-{}", name, std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&root).await;
+    let store = root.join("store");
+    tokio::fs::create_dir_all(&store).await.unwrap();
+
+    let photos = vec![
+        contributed("a", Some("2023-01-15T10:00:00Z"), Some("Jane Doe")),
+        contributed("b", Some("2023-02-01T10:00:00Z"), Some("John/Doe\u{7}")),
+        contributed("c", None, None),
+        // Photo without a downloaded file
+        contributed("d", Some("2024-01-01T00:00:00Z"), None),
+    ];
+
+    let mut state = SyncState::new();
+    for guid in ["a", "b", "c"] {
+        let path = store.join(format!("{}.jpg", guid));
+        tokio::fs::write(&path, guid).await.unwrap();
+        state.record(
+            guid,
+            SyncedFile {
+                path,
+                ..Default::default()
+            },
+        );
+    }
+    (root, photos, state)
+}
+
+#[tokio::test]
+async fn test_build_views() {
+    let (root, photos, state) = setup("build").await;
+    let views = root.join("views");
+
+    let links = build_views(&views, &photos, &state, &ViewOptions::all(2))
+        .await
+        .unwrap();
+    assert_eq!(links.len(), 3 + 3 + 2);
+
+    let read = |path: PathBuf| std::fs::read_to_string(path).unwrap();
+    assert_eq!(read(views.join("by-date/2023/01/a.jpg")), "a");
+    assert_eq!(read(views.join("by-date/2023/02/b.jpg")), "b");
+    assert_eq!(read(views.join("by-date/unknown/c.jpg")), "c");
+    assert_eq!(read(views.join("by-contributor/Jane Doe/a.jpg")), "a");
+    assert_eq!(read(views.join("by-contributor/John_Doe_/b.jpg")), "b");
+    assert_eq!(read(views.join("by-contributor/unknown/c.jpg")), "c");
+
+    // The latest view holds the two most recent dated photos
+    assert!(views.join("latest/b.jpg").exists());
+    assert!(views.join("latest/a.jpg").exists());
+    assert!(!views.join("latest/c.jpg").exists());
+
+    // Links point at the canonical files rather than copying them
+    let meta = tokio::fs::symlink_metadata(views.join("latest/a.jpg"))
+        .await
+        .unwrap();
+    assert!(meta.file_type().is_symlink());
+
+    tokio::fs::remove_dir_all(&root).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_rebuild_removes_stale_links_only() {
+    let (root, photos, mut state) = setup("rebuild").await;
+    let views = root.join("views");
+    let options = ViewOptions::new().with_by_date(true);
+    build_views(&views, &photos, &state, &options)
+        .await
+        .unwrap();
+
+    // A hand-placed file in a view tree must survive rebuilds
+    let note = views.join("by-date/2023/01/notes.txt");
+    tokio::fs::write(&note, "keep me").await.unwrap();
+
+    // Forget photo "b" and rebuild
+    state.files.remove("b");
+    build_views(&views, &photos, &state, &options)
+        .await
+        .unwrap();
+
+    assert!(!views.join("by-date/2023/02").exists());
+    assert!(views.join("by-date/2023/01/a.jpg").exists());
+    assert!(note.exists());
+    // Views that were not requested are not created
+    assert!(!views.join("latest").exists());
+
+    tokio::fs::remove_dir_all(&root).await.unwrap();
+}
@@ -0,0 +1,132 @@
+use icloud_album_rs::api::{
+    group_schema_issues, parse_webstream_response, IssueSeverity, SchemaIssue, ValidationFailure,
+    WebstreamResponse,
+};
+use icloud_album_rs::logging;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::json;
+use std::sync::Mutex;
+
+static MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct CaptureLogger;
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() == Level::Warn && record.target() == logging::SCHEMA {
+            MESSAGES.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[test]
+fn test_group_schema_issues() {
+    let issue = |field: &str, failure, severity| SchemaIssue {
+        field: field.to_string(),
+        failure,
+        severity,
+    };
+    let issues = vec![
+        issue(
+            "photos[0].photoGuid",
+            ValidationFailure::Missing,
+            IssueSeverity::Warning,
+        ),
+        issue(
+            "photos[1].photoGuid",
+            ValidationFailure::Missing,
+            IssueSeverity::Warning,
+        ),
+        issue(
+            "photos[1].derivatives",
+            ValidationFailure::WrongType,
+            IssueSeverity::Warning,
+        ),
+        issue(
+            "items.abc.url_path",
+            ValidationFailure::Missing,
+            IssueSeverity::Warning,
+        ),
+        issue(
+            "items.def.url_path",
+            ValidationFailure::Missing,
+            IssueSeverity::Warning,
+        ),
+        issue(
+            "streamCtag",
+            ValidationFailure::Missing,
+            IssueSeverity::Info,
+        ),
+    ];
+
+    let groups = group_schema_issues(&issues);
+    let summary: Vec<(&str, usize, &str)> = groups
+        .iter()
+        .map(|g| (g.field_pattern.as_str(), g.count, g.example.as_str()))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("photos[].photoGuid", 2, "photos[0].photoGuid"),
+            ("photos[].derivatives", 1, "photos[1].derivatives"),
+            ("items.*.url_path", 2, "items.abc.url_path"),
+            ("streamCtag", 1, "streamCtag"),
+        ]
+    );
+    assert_eq!(groups[3].severity, IssueSeverity::Info);
+}
+
+#[test]
+fn test_repeated_warnings_are_capped() {
+    log::set_logger(&CaptureLogger).unwrap();
+    log::set_max_level(LevelFilter::Warn);
+    logging::set_repeat_limit(2);
+
+    let photos: Vec<_> = (0..50)
+        .map(|i| {
+            if i < 40 {
+                json!({
+                    "photoGuid": format!("guid{}", i),
+                    "derivatives": { "1": { "checksum": "c", "width": "wide" } }
+                })
+            } else {
+                json!({ "derivatives": {} })
+            }
+        })
+        .collect();
+    let response = json!({
+        "streamName": "Family",
+        "streamCtag": "ctag",
+        "locations": {},
+        "photos": photos
+    });
+
+    let WebstreamResponse {
+        photos: parsed,
+        report,
+        ..
+    } = parse_webstream_response(&response).unwrap();
+    assert_eq!(parsed.len() + report.skipped_photos.len(), 50);
+
+    let messages = MESSAGES.lock().unwrap().clone();
+    let type_warnings = messages
+        .iter()
+        .filter(|m| m.starts_with("Type inconsistency: Failed"))
+        .count();
+    assert_eq!(type_warnings, 2);
+    assert!(messages.iter().any(|m| m
+        .starts_with("Type inconsistency: string values that are not valid u32 numbers")
+        && m.ends_with("not shown")));
+    assert!(messages
+        .iter()
+        .any(|m| m == "Schema validation: Field 'photos[].photoGuid' is missing in 10 items"));
+    assert!(messages.len() < 10, "{:?}", messages);
+
+    logging::set_repeat_limit(logging::DEFAULT_REPEAT_LIMIT);
+}
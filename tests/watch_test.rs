@@ -0,0 +1,306 @@
+mod common;
+
+use common::photo;
+use icloud_album_rs::base_url::with_api_origin;
+use icloud_album_rs::models::{Derivative, Image, Metadata};
+use icloud_album_rs::shutdown::Shutdown;
+use icloud_album_rs::watch::{
+    caption_contains, diff_albums, only_additions, only_videos, AlbumEvent, AlbumWatcher,
+    EventSink, Notifier, SinkFuture, ThumbnailData,
+};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn captioned(guid: &str, caption: Option<&str>) -> Image {
+    Image {
+        caption: caption.map(|c| c.to_string()),
+        ..photo(guid)
+    }
+}
+
+fn metadata(ctag: &str) -> Metadata {
+    serde_json::from_value(json!({
+        "streamName": "Family",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": ctag,
+        "itemsReturned": 0,
+        "locations": {}
+    }))
+    .unwrap()
+}
+
+// Notifier that records every batch it receives
+#[derive(Clone, Default)]
+struct RecordingNotifier {
+    batches: Arc<Mutex<Vec<Vec<AlbumEvent>>>>,
+}
+
+impl Notifier for RecordingNotifier {
+    fn notify(&self, _album: &Metadata, events: &[AlbumEvent]) {
+        self.batches.lock().unwrap().push(events.to_vec());
+    }
+}
+
+// Sink that records delivered events and optionally fails
+#[derive(Clone, Default)]
+struct RecordingSink {
+    delivered: Arc<Mutex<Vec<AlbumEvent>>>,
+    fail: bool,
+}
+
+impl EventSink for RecordingSink {
+    fn deliver<'a>(&'a self, _album: &'a Metadata, events: &'a [AlbumEvent]) -> SinkFuture<'a> {
+        Box::pin(async move {
+            if self.fail {
+                return Err("sink unavailable".into());
+            }
+            self.delivered.lock().unwrap().extend_from_slice(events);
+            Ok(())
+        })
+    }
+}
+
+#[test]
+fn test_diff_albums() {
+    let previous = vec![captioned("a", None), captioned("b", Some("old"))];
+    let current = vec![captioned("b", Some("new")), captioned("c", Some("hello"))];
+
+    let events = diff_albums(&previous, &current);
+    assert_eq!(
+        events,
+        vec![
+            AlbumEvent::PhotoAdded {
+                photo_guid: "c".to_string(),
+                caption: Some("hello".to_string()),
+                date_created: None,
+                thumbnail_url: None,
+                thumbnail: None,
+            },
+            AlbumEvent::PhotoRemoved {
+                photo_guid: "a".to_string()
+            },
+            AlbumEvent::CaptionChanged {
+                photo_guid: "b".to_string(),
+                old_caption: Some("old".to_string()),
+                new_caption: Some("new".to_string()),
+            },
+        ]
+    );
+
+    // Identical albums produce no events
+    assert!(diff_albums(&current, &current).is_empty());
+}
+
+#[test]
+fn test_watcher_observe() {
+    let notifier = RecordingNotifier::default();
+    let mut watcher = AlbumWatcher::new("token").with_notifier(notifier.clone());
+
+    // The first observation is a baseline
+    assert!(watcher
+        .observe(&metadata("1"), vec![captioned("a", None)])
+        .is_empty());
+
+    // An unchanged ctag skips the comparison
+    assert!(watcher
+        .observe(
+            &metadata("1"),
+            vec![captioned("a", None), captioned("b", None)]
+        )
+        .is_empty());
+
+    // A new ctag reports the additions since the last recorded state
+    let events = watcher.observe(
+        &metadata("2"),
+        vec![captioned("a", None), captioned("b", None)],
+    );
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].photo_guid(), "b");
+
+    // Only polls with events reach the notifier
+    assert_eq!(notifier.batches.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_watcher_filters() {
+    let video = Image {
+        photo_guid: "clip".to_string(),
+        caption: Some("Birthday party".to_string()),
+        derivatives: [("720p".to_string(), Derivative::default())]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    let baseline = vec![captioned("a", Some("Beach")), captioned("b", None)];
+    let current = vec![
+        captioned("a", Some("Party at the beach")),
+        captioned("c", Some("party hats")),
+        captioned("d", None),
+        video,
+    ];
+
+    let mut videos = AlbumWatcher::new("token").with_filter(only_videos());
+    let mut parties = AlbumWatcher::new("token").with_filter(caption_contains("PARTY"));
+    let mut new_parties = AlbumWatcher::new("token")
+        .with_filter(caption_contains("party"))
+        .with_filter(only_additions());
+    let mut custom = AlbumWatcher::new("token")
+        .with_filter(|event: &AlbumEvent, _: Option<&Image>| event.photo_guid() == "b");
+    let mut reported = Vec::new();
+    for watcher in [&mut videos, &mut parties, &mut new_parties, &mut custom] {
+        watcher.observe(&metadata("1"), baseline.clone());
+        let events = watcher.observe(&metadata("2"), current.clone());
+        reported.push(
+            events
+                .iter()
+                .map(|event| event.photo_guid().to_string())
+                .collect::<Vec<_>>(),
+        );
+    }
+    assert_eq!(reported[0], ["clip"]);
+    assert_eq!(reported[1], ["c", "clip", "a"]);
+    assert_eq!(reported[2], ["c", "clip"]);
+    // Removals are checked against the photo as last seen
+    assert_eq!(reported[3], ["b"]);
+}
+
+#[tokio::test]
+async fn test_watcher_dispatch() {
+    let sink = RecordingSink::default();
+    let failing = RecordingSink {
+        fail: true,
+        ..Default::default()
+    };
+    let watcher = AlbumWatcher::new("token")
+        .with_sink(failing)
+        .with_sink(sink.clone());
+
+    let events = vec![AlbumEvent::PhotoRemoved {
+        photo_guid: "a".to_string(),
+    }];
+
+    // A failing sink does not prevent delivery to the others
+    watcher.dispatch(&metadata("1"), &events).await;
+    assert_eq!(*sink.delivered.lock().unwrap(), events);
+
+    // Empty batches are not delivered
+    watcher.dispatch(&metadata("1"), &[]).await;
+    assert_eq!(sink.delivered.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_event_display() {
+    let event = AlbumEvent::CaptionChanged {
+        photo_guid: "a".to_string(),
+        old_caption: None,
+        new_caption: Some("Sunset".to_string()),
+    };
+    assert_eq!(event.to_string(), "Caption changed: a -> Sunset");
+}
+
+#[test]
+fn test_event_serialization() {
+    let event = AlbumEvent::PhotoRemoved {
+        photo_guid: "a".to_string(),
+    };
+    assert_eq!(
+        serde_json::to_value(&event).unwrap(),
+        json!({ "type": "PhotoRemoved", "photo_guid": "a" })
+    );
+}
+
+#[test]
+fn test_thumbnail_serialization() {
+    let thumbnail = ThumbnailData {
+        content_type: "image/png".to_string(),
+        bytes: b"\x89PNG!".to_vec(),
+    };
+    assert_eq!(thumbnail.data_url(), "data:image/png;base64,iVBORyE=");
+
+    let event = AlbumEvent::PhotoAdded {
+        photo_guid: "a".to_string(),
+        caption: None,
+        date_created: None,
+        thumbnail_url: Some("https://example.com/a".to_string()),
+        thumbnail: Some(thumbnail),
+    };
+    let value = serde_json::to_value(&event).unwrap();
+    assert_eq!(value["thumbnail"]["bytes"], "iVBORyE=");
+    assert_eq!(serde_json::from_value::<AlbumEvent>(value).unwrap(), event);
+
+    // Events without a preview keep their previous shape
+    let bare: AlbumEvent = serde_json::from_value(
+        json!({ "type": "PhotoAdded", "photo_guid": "b", "caption": null, "date_created": null }),
+    )
+    .unwrap();
+    assert!(!serde_json::to_value(&bare)
+        .unwrap()
+        .as_object()
+        .unwrap()
+        .contains_key("thumbnail"));
+}
+
+#[cfg(feature = "desktop-notify")]
+#[test]
+fn test_desktop_notifier_message() {
+    use icloud_album_rs::watch::DesktopNotifier;
+
+    let added = AlbumEvent::PhotoAdded {
+        photo_guid: "a".to_string(),
+        caption: None,
+        date_created: None,
+        thumbnail_url: None,
+        thumbnail: None,
+    };
+    let removed = AlbumEvent::PhotoRemoved {
+        photo_guid: "b".to_string(),
+    };
+
+    let album = metadata("1");
+    assert_eq!(
+        DesktopNotifier::message_for(&album, std::slice::from_ref(&added)),
+        Some("1 new photo in Family".to_string())
+    );
+    assert_eq!(
+        DesktopNotifier::message_for(&album, &[added.clone(), added, removed.clone()]),
+        Some("2 new photos in Family".to_string())
+    );
+    assert_eq!(DesktopNotifier::message_for(&album, &[removed]), None);
+}
+
+#[tokio::test]
+async fn test_watcher_shutdown() {
+    let mut server = mockito::Server::new_async().await;
+    let mut webstream = serde_json::to_value(metadata("ctag1")).unwrap();
+    webstream["photos"] = json!([]);
+    let mock = server
+        .mock("POST", "/watched/sharedstreams/webstream")
+        .with_body(webstream.to_string())
+        .expect(1)
+        .create_async()
+        .await;
+
+    // The signal interrupts the wait between polls
+    let shutdown = Shutdown::new();
+    let mut watcher = AlbumWatcher::new("watched")
+        .with_interval(Duration::from_secs(3600))
+        .with_shutdown(shutdown.clone());
+    let stop = async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        shutdown.trigger();
+    };
+    let origin = server.url();
+    let run = with_api_origin(&origin, watcher.run());
+    tokio::time::timeout(Duration::from_secs(10), async { tokio::join!(run, stop) })
+        .await
+        .expect("watcher did not stop");
+    mock.assert_async().await;
+
+    // A stopped watcher doesn't poll again
+    assert!(watcher.shutdown_handle().is_triggered());
+    with_api_origin(&origin, watcher.run()).await;
+    assert!(watcher.poll_once().await.unwrap().is_empty());
+    mock.assert_async().await;
+}
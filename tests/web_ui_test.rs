@@ -0,0 +1,79 @@
+#![cfg(feature = "web-ui")]
+
+mod common;
+
+use common::{derivative, photo_with};
+use icloud_album_rs::models::{ICloudResponse, Image, Metadata};
+use icloud_album_rs::sync::SyncState;
+use icloud_album_rs::web_ui::{album_grid, asset, AlbumStatus, INDEX_HTML};
+use serde_json::json;
+
+// A captioned photo with the given derivatives, served from example.com
+fn captioned(guid: &str, keys: &[&str]) -> Image {
+    let derivatives = keys.iter().map(|key| {
+        let url = format!("https://example.com/{}/{}", guid, key);
+        (*key, derivative(format!("{}-{}", guid, key), Some(url)))
+    });
+    Image {
+        caption: Some(format!("Caption of {}", guid)),
+        ..photo_with(guid, derivatives)
+    }
+}
+
+#[test]
+fn test_web_ui_assets() {
+    assert_eq!(asset("/").unwrap().1, INDEX_HTML);
+    assert!(asset("index.html").unwrap().0.starts_with("text/html"));
+    assert!(asset("/app.js").is_none());
+    // The page loads the documents the module describes
+    assert!(INDEX_HTML.contains("albums.json"));
+    assert!(INDEX_HTML.contains("thumbnail_url"));
+}
+
+#[test]
+fn test_album_grid() {
+    let metadata: Metadata = serde_json::from_value(json!({
+        "streamName": "Family",
+        "userFirstName": "Jane",
+        "userLastName": "Doe",
+        "streamCtag": "ctag1",
+        "itemsReturned": 3,
+        "locations": {}
+    }))
+    .unwrap();
+    let album = ICloudResponse {
+        metadata,
+        photos: vec![
+            captioned("still", &["342", "3"]),
+            captioned("clip", &["PosterFrame", "720p"]),
+            captioned("empty", &[]),
+        ],
+        unparsed: Vec::new(),
+    };
+
+    let grid = album_grid("family", &album, "/proxy/{guid}/{class}");
+    assert_eq!(grid.name, "Family");
+    assert_eq!(grid.photos.len(), 2);
+    assert_eq!(grid.photos[0].thumbnail_url, "/proxy/still/thumb");
+    assert_eq!(grid.photos[0].url, "/proxy/still/original");
+    assert!(!grid.photos[0].is_video);
+    assert_eq!(grid.photos[1].thumbnail_url, "/proxy/clip/poster");
+    assert_eq!(grid.photos[1].url, "/proxy/clip/video");
+    assert!(grid.photos[1].is_video);
+}
+
+#[tokio::test]
+async fn test_album_status_from_state() {
+    let mut state = SyncState::new();
+    let never = AlbumStatus::from_state("family", "Family", &state);
+    assert_eq!(never.last_synced_at, None);
+    assert!(!never.pending);
+
+    state.record_run(12).await;
+    let status = AlbumStatus::from_state("family", "Family", &state);
+    assert_eq!(status.photo_count, 12);
+    assert!(status.last_synced_at.is_some());
+    let value = serde_json::to_value(&status).unwrap();
+    assert_eq!(value["id"], "family");
+    assert_eq!(value["photo_count"], 12);
+}
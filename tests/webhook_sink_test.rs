@@ -0,0 +1,129 @@
+#![cfg(feature = "webhooks")]
+
+use icloud_album_rs::models::Metadata;
+use icloud_album_rs::sinks::{DiscordSink, TelegramSink};
+use icloud_album_rs::watch::{AlbumEvent, EventSink};
+use mockito::Matcher;
+use serde_json::json;
+
+fn metadata() -> Metadata {
+    serde_json::from_value(json!({
+        "streamName": "Family",
+        "userFirstName": "John",
+        "userLastName": "Doe",
+        "streamCtag": "1",
+        "itemsReturned": 0,
+        "locations": {}
+    }))
+    .unwrap()
+}
+
+fn added(guid: &str, caption: Option<&str>, thumbnail_url: Option<&str>) -> AlbumEvent {
+    AlbumEvent::PhotoAdded {
+        photo_guid: guid.to_string(),
+        caption: caption.map(|c| c.to_string()),
+        date_created: None,
+        thumbnail_url: thumbnail_url.map(|u| u.to_string()),
+        thumbnail: None,
+    }
+}
+
+#[test]
+fn test_discord_payloads() {
+    let album = metadata();
+    let mut events = vec![
+        added("a", Some("Beach"), Some("https://example.com/a.jpg")),
+        AlbumEvent::PhotoRemoved {
+            photo_guid: "b".to_string(),
+        },
+    ];
+
+    let payloads = DiscordSink::payloads(&album, &events);
+    assert_eq!(
+        payloads,
+        vec![json!({
+            "embeds": [{
+                "title": "New photo in Family: Beach",
+                "image": { "url": "https://example.com/a.jpg" }
+            }]
+        })]
+    );
+
+    // Large batches are split to respect the embed limit
+    for i in 0..15 {
+        events.push(added(&format!("p{}", i), None, None));
+    }
+    let payloads = DiscordSink::payloads(&album, &events);
+    assert_eq!(payloads.len(), 2);
+    assert_eq!(payloads[1]["embeds"].as_array().unwrap().len(), 6);
+
+    // Batches without additions post nothing
+    assert!(DiscordSink::payloads(&album, &events[1..2]).is_empty());
+}
+
+#[tokio::test]
+async fn test_discord_deliver() {
+    let mut server = mockito::Server::new_async().await;
+    let mock = server
+        .mock("POST", "/webhook")
+        .match_body(Matcher::PartialJson(json!({
+            "embeds": [{ "title": "New photo in Family" }]
+        })))
+        .with_status(204)
+        .create_async()
+        .await;
+
+    let sink = DiscordSink::new(&format!("{}/webhook", server.url()));
+    sink.deliver(&metadata(), &[added("a", None, None)])
+        .await
+        .unwrap();
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_telegram_deliver() {
+    let mut server = mockito::Server::new_async().await;
+    let photo = server
+        .mock("POST", "/botSECRET/sendPhoto")
+        .match_body(Matcher::Json(json!({
+            "chat_id": "42",
+            "photo": "https://example.com/a.jpg",
+            "caption": "New photo in Family: Beach"
+        })))
+        .with_status(200)
+        .create_async()
+        .await;
+    let message = server
+        .mock("POST", "/botSECRET/sendMessage")
+        .match_body(Matcher::Json(json!({
+            "chat_id": "42",
+            "text": "New photo in Family"
+        })))
+        .with_status(200)
+        .create_async()
+        .await;
+
+    let sink = TelegramSink::new("SECRET", "42").with_api_base(&server.url());
+    let events = vec![
+        added("a", Some("Beach"), Some("https://example.com/a.jpg")),
+        added("b", None, None),
+    ];
+    sink.deliver(&metadata(), &events).await.unwrap();
+
+    photo.assert_async().await;
+    message.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_telegram_reports_errors() {
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("POST", "/botSECRET/sendMessage")
+        .with_status(401)
+        .create_async()
+        .await;
+
+    let sink = TelegramSink::new("SECRET", "42").with_api_base(&server.url());
+    let result = sink.deliver(&metadata(), &[added("a", None, None)]).await;
+    assert!(result.is_err());
+}
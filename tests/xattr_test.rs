@@ -0,0 +1,83 @@
+#![cfg(all(feature = "xattr", any(target_os = "linux", target_os = "macos")))]
+
+mod common;
+
+use common::fresh_dir;
+use icloud_album_rs::download::{download_photo_with_options, DownloadOptions};
+use icloud_album_rs::models::{Derivative, Image};
+use icloud_album_rs::xattr::{read_provenance, set_attribute, write_provenance, Provenance};
+use std::collections::HashMap;
+use std::path::Path;
+
+const PNG_BYTES: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+// Returns false if the filesystem holding `path` does not support user xattrs
+fn xattrs_supported(path: &Path) -> bool {
+    set_attribute(path, "user.icloud.probe", "1").is_ok()
+}
+
+#[tokio::test]
+async fn test_provenance_roundtrip() {
+    let dir = fresh_dir("roundtrip").await;
+    let file = dir.join("photo.jpg");
+    tokio::fs::write(&file, b"data").await.unwrap();
+    if !xattrs_supported(&file) {
+        eprintln!("Skipping: extended attributes unsupported on this filesystem");
+        return;
+    }
+
+    // Files without attributes have empty provenance
+    assert_eq!(read_provenance(&file).unwrap(), Provenance::default());
+
+    let provenance = Provenance {
+        photo_guid: Some("guid1".to_string()),
+        album: Some("Family Trip".to_string()),
+    };
+    write_provenance(&file, &provenance).unwrap();
+    assert_eq!(read_provenance(&file).unwrap(), provenance);
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_download_writes_provenance() {
+    let dir = fresh_dir("download").await;
+    if !xattrs_supported(&dir) {
+        eprintln!("Skipping: extended attributes unsupported on this filesystem");
+        return;
+    }
+
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/photo.png")
+        .with_status(200)
+        .with_body(PNG_BYTES)
+        .create_async()
+        .await;
+
+    let mut derivatives = HashMap::new();
+    derivatives.insert(
+        "1".to_string(),
+        Derivative {
+            checksum: "c1".to_string(),
+            url: Some(format!("{}/photo.png", server.url())),
+            ..Default::default()
+        },
+    );
+    let photo = Image {
+        photo_guid: "guid1".to_string(),
+        derivatives,
+        ..Default::default()
+    };
+
+    let options = DownloadOptions::new().with_xattrs(Some("Family"));
+    let path = download_photo_with_options(&photo, None, &dir.to_string_lossy(), None, &options)
+        .await
+        .unwrap();
+
+    let provenance = read_provenance(Path::new(&path)).unwrap();
+    assert_eq!(provenance.photo_guid.as_deref(), Some("guid1"));
+    assert_eq!(provenance.album.as_deref(), Some("Family"));
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use common::{derivative, photo_with};
+use icloud_album_rs::derivatives::SizeClass;
+use icloud_album_rs::download::DownloadOptions;
+use icloud_album_rs::models::Image;
+use icloud_album_rs::shutdown::Shutdown;
+use icloud_album_rs::zip_stream::{crc32, stream_zip};
+
+// PNG signature followed by some padding
+const PNG_BYTES: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+/// Reads the (name, content) of every entry through the central directory
+fn read_zip(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let end = archive.len() - 22;
+    assert_eq!(u32_at(archive, end), 0x0605_4b50);
+    let count = u16_at(archive, end + 10) as usize;
+    let mut at = u32_at(archive, end + 16) as usize;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        assert_eq!(u32_at(archive, at), 0x0201_4b50);
+        let crc = u32_at(archive, at + 16);
+        let size = u32_at(archive, at + 24) as usize;
+        let name_len = u16_at(archive, at + 28) as usize;
+        let offset = u32_at(archive, at + 42) as usize;
+        let name = String::from_utf8(archive[at + 46..at + 46 + name_len].to_vec()).unwrap();
+
+        assert_eq!(u32_at(archive, offset), 0x0403_4b50);
+        let start = offset + 30 + u16_at(archive, offset + 26) as usize;
+        let content = archive[start..start + size].to_vec();
+        assert_eq!(crc32(&content), crc);
+        entries.push((name, content));
+        at += 46 + name_len;
+    }
+    entries
+}
+
+// A photo whose thumbnail is served by `server` at `path`
+fn served(server: &mockito::Server, guid: &str, path: &str) -> Image {
+    let url = format!("{}/{}", server.url(), path);
+    photo_with(guid, [("342", derivative(guid, Some(url)))])
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[tokio::test]
+async fn test_stream_zip() {
+    let mut server = mockito::Server::new_async().await;
+    let asset = server
+        .mock("GET", "/first.png")
+        .with_body(PNG_BYTES)
+        .create_async()
+        .await;
+    let broken = server
+        .mock("GET", "/broken.png")
+        .with_status(500)
+        .create_async()
+        .await;
+    let mut undated = served(&server, "second", "broken.png");
+    undated.date_created = None;
+    let photos = vec![
+        Image {
+            date_created: Some("2023-05-06T07:08:10Z".to_string()),
+            ..served(&server, "first", "first.png")
+        },
+        undated,
+    ];
+
+    let mut archive = Vec::new();
+    let report = stream_zip(
+        &photos,
+        &[SizeClass::Thumbnail, SizeClass::Original],
+        &mut archive,
+        &DownloadOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(report.entries.len(), 1);
+    assert_eq!(report.entries[0].name, "first_thumb.png");
+    assert_eq!(report.entries[0].size, PNG_BYTES.len() as u64);
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(report.failures[0].0, "second");
+    assert_eq!(report.missing.len(), 2);
+    assert!(!report.cancelled);
+    assert_eq!(report.bytes, archive.len() as u64);
+    assert_eq!(
+        read_zip(&archive),
+        vec![("first_thumb.png".to_string(), PNG_BYTES.to_vec())]
+    );
+    // 2023-05-06 07:08:10 in MS-DOS format
+    assert_eq!(u16_at(&archive, 10), 7 << 11 | 8 << 5 | 5);
+    assert_eq!(u16_at(&archive, 12), 43 << 9 | 5 << 5 | 6);
+
+    asset.assert_async().await;
+    broken.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_stream_zip_shutdown_closes_archive() {
+    let server = mockito::Server::new_async().await;
+    let shutdown = Shutdown::new();
+    shutdown.trigger();
+    let options = DownloadOptions::default().with_shutdown(shutdown);
+
+    let mut archive = Vec::new();
+    let report = stream_zip(
+        &[served(&server, "first", "first.png")],
+        &[SizeClass::Thumbnail],
+        &mut archive,
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert!(report.cancelled);
+    assert!(report.entries.is_empty());
+    // Still a valid, empty archive
+    assert!(read_zip(&archive).is_empty());
+}